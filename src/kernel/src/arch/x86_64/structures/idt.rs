@@ -228,6 +228,100 @@ unsafe extern "sysv64" fn irq_handoff(irq_number: u64, isf: &mut InterruptStackF
     });
 }
 
+/// Entry point for the `syscall` instruction.
+///
+/// The stub switches to the core-local system call stack, and lays the user context out identically to an
+/// interrupt stack frame, so the same handling path can be used for both `syscall` and `int 0x80`.
+///
+/// ### Safety
+///
+/// This function should not be called from software.
+#[naked]
+pub unsafe extern "sysv64" fn syscall_entry() {
+    core::arch::asm!(
+        "
+        # Switch to the core-local state to retrieve the kernel stack.
+        swapgs
+        mov gs:[{user_sp}], rsp
+        mov rsp, gs:[{kernel_sp}]
+
+        # Build an interrupt stack frame, so the context can be returned via `iretq` if need be.
+        push 0                      # stack segment (written by handoff)
+        push qword ptr gs:[{user_sp}]
+        push r11                    # rflags
+        push 0                      # code segment (written by handoff)
+        push rcx                    # instruction pointer
+
+        swapgs
+
+        # `syscall` clobbers `rcx`, so the fourth argument is passed in `r10` instead.
+        mov rcx, r10
+        ",
+        "cld",
+        push_gprs!(),
+        push_ret_frame!(15),
+        "
+        # Move stack frame into first parameter.
+        lea rdi, [rsp + (17 * 8)]
+        # Move cached gprs pointer into second parameter.
+        lea rsi, [rsp + (2 * 8)]
+
+        call {}
+
+        add rsp, 0x10   # 'pop' stack frame
+        test rax, rax   # can the context be returned via `sysret`?
+        ", pop_gprs!(), "
+        jz 3f
+
+        mov rcx, [rsp + (0 * 8)]    # instruction pointer
+        mov r11, [rsp + (2 * 8)]    # rflags
+        mov rsp, [rsp + (3 * 8)]    # stack pointer
+
+        sysretq
+
+        3:
+        iretq
+        ",
+        sym syscall_handoff,
+        user_sp = const crate::cpu::state::SYSCALL_USER_SP_OFFSET,
+        kernel_sp = const crate::cpu::state::SYSCALL_STACK_PTR_OFFSET,
+        options(noreturn)
+    )
+}
+
+/// Returns whether the (possibly new) context can be returned to with `sysret`.
+///
+/// ### Safety
+///
+/// This function should not be called from software.
+#[allow(clippy::cast_possible_truncation)]
+unsafe extern "sysv64" fn syscall_handoff(isf: &mut InterruptStackFrame, regs: &mut Registers) -> u64 {
+    use crate::arch::x86_64::registers::RFlags;
+    use ia32utils::VirtAddr;
+
+    let mut state = State::user(
+        Address::from_ptr(isf.instruction_pointer.as_mut_ptr::<()>()),
+        Address::from_ptr(isf.stack_pointer.as_mut_ptr::<()>()),
+    );
+    state.rfl = RFlags::from_bits_retain(isf.cpu_flags as usize);
+    let entry_state = state;
+
+    crate::interrupts::traps::handle_syscall(&mut state, regs);
+
+    isf.as_mut().write(InterruptStackFrameValue {
+        instruction_pointer: VirtAddr::from_ptr(state.ip.as_ptr()),
+        code_segment: u64::try_from(state.cs).unwrap(),
+        cpu_flags: u64::try_from(state.rfl.bits()).unwrap(),
+        stack_pointer: VirtAddr::from_ptr(state.sp.as_ptr()),
+        stack_segment: u64::try_from(state.ss).unwrap(),
+    });
+
+    // `sysret` clobbers `rcx` and `r11`, so it can only be used to return to the task which made the syscall. Any
+    // other context (i.e. after a task switch) must be restored in full with `iretq`. Additionally, `sysret` faults in
+    // kernel mode on a non-canonical instruction pointer, so those are left for `iretq` to fault in user mode.
+    u64::from(state == entry_state && libsys::checked_virt_canonical(state.ip.get()))
+}
+
 exception_handler!(de, ());
 extern "sysv64" fn de_handler_inner(stack_frame: &InterruptStackFrame, gprs: &Registers) {
     ex_handler(&ArchException::DivideError(stack_frame, gprs));
@@ -1,6 +1,6 @@
 use crate::interrupts;
-use acpi::platform::interrupt::{Polarity, TriggerMode};
-// use alloc::vec::Vec;
+use acpi::platform::interrupt::{InterruptModel, InterruptSourceOverride, Polarity, TriggerMode};
+use alloc::vec::Vec;
 use bit_field::BitField;
 use libkernel::mem::VolatileCell;
 use spin::Mutex;
@@ -31,9 +31,9 @@ impl RedirectionEntry {
 
     pub fn get_destination_mode(&self) -> interrupts::DestinationMode {
         if self.0.get_bit(11) {
-            interrupts::DestinationMode::Physical
-        } else {
             interrupts::DestinationMode::Logical
+        } else {
+            interrupts::DestinationMode::Physical
         }
     }
 
@@ -131,7 +131,7 @@ impl IoApic<'_> {
     pub fn get_redirection(&self, global_irq_num: u32) -> RedirectionEntry {
         assert!(self.handled_irqs().contains(&global_irq_num), "I/O APIC does not handle the provided redirection");
 
-        let reg_base_index = 0x10 + (global_irq_num * 2);
+        let reg_base_index = 0x10 + ((global_irq_num - self.handled_irqs.start()) * 2);
 
         let ioregs = self.ioregs.lock();
 
@@ -150,7 +150,7 @@ impl IoApic<'_> {
         {
             let redirection_low = redirection.0 as u32;
             let redirection_high = (redirection.0 >> 32) as u32;
-            let reg_base_index = 0x10 + (global_irq_num * 2);
+            let reg_base_index = 0x10 + ((global_irq_num - self.handled_irqs.start()) * 2);
 
             let ioregs = self.ioregs.lock();
 
@@ -166,49 +166,122 @@ impl IoApic<'_> {
     }
 }
 
-// TODO We don't need to store this probably, find some way to init architecture-specifically.
-//      Maybe just iterate them once, processing redirections within the same context.
-// static IOAPICS: Once<Vec<IoApic>> = Once::new();
-// /// Queries the platform for I/O APICs, and returns them in a collection.
-// pub fn get_io_apics() -> &'static Vec<IoApic<'static>> {
-//     IOAPICS.call_once(|| {
-//         todo!()
-
-//          let platform_info = libsys::acpi::get_platform_info();
-
-//          if let acpi::platform::interrupt::InterruptModel::Apic(apic) = &platform_info.interrupt_model {
-//              apic.io_apics
-//                  .iter()
-//                  // TODO unsafety comment
-//                  .map(|ioapic_info| unsafe {
-
-//                      let (ioregsel, ioregwin) = {
-//                          let Ok(ioapic_regs) = libsys::memory::get().allocate_to(Address::<Frame>::new_truncate(ioapic_info.address as u64), 1)
-//                              else { panic!("failed to initialize I/O APIC") };
-
-//                          (
-//                              &*ioapic_regs.as_ptr::<VolatileCell<u32, libsys::WriteOnly>>(),
-//                              &*ioapic_regs.as_ptr::<VolatileCell<u32, libsys::ReadWrite>>().add(1)
-//                          )
-//                      };
-
-//                      let id = {
-//                          ioregsel.write(0x0);
-//                          ioregwin.read().get_bits(24..28) as u8
-//                      };
-//                      let (version, irq_count) = {
-//                          ioregsel.write(0x1);
-//                          let value = ioregwin.read();
-//                          (value.get_bits(0..8) as u8, value.get_bits(16..24) as u32)
-//                      };
-//                      let irq_base = ioapic_info.global_system_interrupt_base;
-//                      let handled_irqs = irq_base..=(irq_base + irq_count);
-
-//                      IoApic { id, version, handled_irqs, ioregs: Mutex::new((ioregsel, ioregwin)) }
-//                  })
-//                  .collect()
-//          } else {
-//              alloc::vec::Vec::new()
-//          }
-//     })
-// }
+static IOAPICS: spin::Once<Vec<IoApic<'static>>> = spin::Once::new();
+
+/// Queries the platform's MADT for I/O APICs, and returns them in a collection.
+pub fn get_io_apics() -> &'static [IoApic<'static>] {
+    IOAPICS.call_once(|| {
+        let Some(platform_info) = crate::acpi::PLATFORM_INFO.as_ref() else { return Vec::new() };
+        let platform_info = platform_info.lock();
+        let InterruptModel::Apic(apic) = &platform_info.interrupt_model else { return Vec::new() };
+
+        apic.io_apics
+            .iter()
+            .map(|ioapic_info| {
+                // Safety: The MADT provides the I/O APIC's register address, which is covered by the HHDM.
+                let (ioregsel, ioregwin) = unsafe {
+                    let ioapic_regs = crate::mem::HHDM.ptr().add(usize::try_from(ioapic_info.address).unwrap());
+
+                    (
+                        &*ioapic_regs.cast::<VolatileCell<u32, libkernel::WriteOnly>>(),
+                        &*ioapic_regs.add(0x10).cast::<VolatileCell<u32, libkernel::ReadWrite>>(),
+                    )
+                };
+
+                ioregsel.write(0x0);
+                let id = u8::try_from(ioregwin.read().get_bits(24..28)).unwrap();
+
+                ioregsel.write(0x1);
+                let version_reg = ioregwin.read();
+                let version = u8::try_from(version_reg.get_bits(0..8)).unwrap();
+                let max_redirection = version_reg.get_bits(16..24);
+
+                let irq_base = ioapic_info.global_system_interrupt_base;
+                let handled_irqs = irq_base..=(irq_base + max_redirection);
+
+                debug!("I/O APIC {} (version {:#X}) handles GSIs {:?}", id, version, handled_irqs);
+
+                IoApic { id, version, handled_irqs, ioregs: Mutex::new((ioregsel, ioregwin)) }
+            })
+            .collect()
+    })
+}
+
+/// Calls `func` with the interrupt source overrides from the MADT, if it has any.
+fn with_source_overrides<T>(func: impl FnOnce(&[InterruptSourceOverride]) -> Option<T>) -> Option<T> {
+    let platform_info = crate::acpi::PLATFORM_INFO.as_ref()?.lock();
+    let InterruptModel::Apic(apic) = &platform_info.interrupt_model else { return None };
+
+    func(&apic.interrupt_source_overrides)
+}
+
+/// Finds the global system interrupt an ISA interrupt is connected to. ISA interrupts are identity-mapped to GSIs,
+/// unless the MADT provides an interrupt source override for them.
+pub fn isa_irq_gsi(isa_irq: u8) -> u32 {
+    with_source_overrides(|source_overrides| {
+        source_overrides
+            .iter()
+            .find(|source_override| source_override.isa_source == isa_irq)
+            .map(|source_override| source_override.global_system_interrupt)
+    })
+    .unwrap_or(u32::from(isa_irq))
+}
+
+/// Finds the polarity and trigger mode of a global system interrupt.
+///
+/// GSIs with an interrupt source override use its flags. Otherwise, the legacy GSIs are connected to the ISA bus,
+/// and the rest to PCI.
+pub fn gsi_mode(gsi: u32) -> (Polarity, TriggerMode) {
+    const LEGACY_GSIS: u32 = 16;
+
+    let overridden = with_source_overrides(|source_overrides| {
+        source_overrides
+            .iter()
+            .find(|source_override| source_override.global_system_interrupt == gsi)
+            .map(|source_override| (source_override.polarity, source_override.trigger_mode))
+    });
+
+    // The ISA bus is active-high and edge-triggered, which `SameAsBus` is programmed as.
+    overridden.unwrap_or(if gsi < LEGACY_GSIS {
+        (Polarity::SameAsBus, TriggerMode::SameAsBus)
+    } else {
+        (Polarity::ActiveLow, TriggerMode::Level)
+    })
+}
+
+fn find_io_apic(gsi: u32) -> Option<&'static IoApic<'static>> {
+    get_io_apics().iter().find(|ioapic| ioapic.handled_irqs().contains(&gsi))
+}
+
+/// Whether any I/O APIC handles the global system interrupt.
+pub fn handles_gsi(gsi: u32) -> bool {
+    find_io_apic(gsi).is_some()
+}
+
+/// Routes the global system interrupt `gsi` to `vector` on the core with `apic_id`. The redirection is left masked.
+///
+/// Returns whether an I/O APIC handles the GSI.
+pub fn route_gsi(gsi: u32, vector: u8, apic_id: u8, polarity: Polarity, trigger_mode: TriggerMode) -> bool {
+    let Some(ioapic) = find_io_apic(gsi) else { return false };
+
+    let mut redirection = ioapic.get_redirection(gsi);
+    redirection.set_masked(true);
+    redirection.set_vector(vector);
+    redirection.set_delivery_mode(interrupts::DeliveryMode::Fixed);
+    redirection.set_destination_mode(interrupts::DestinationMode::Physical);
+    redirection.set_pin_polarity(polarity);
+    redirection.set_trigger_mode(trigger_mode);
+    redirection.set_destination_id(apic_id);
+    ioapic.set_redirection(gsi, &redirection);
+
+    true
+}
+
+/// Masks or unmasks the redirection of the global system interrupt `gsi`, if an I/O APIC handles it.
+pub fn set_gsi_masked(gsi: u32, masked: bool) {
+    if let Some(ioapic) = find_io_apic(gsi) {
+        let mut redirection = ioapic.get_redirection(gsi);
+        redirection.set_masked(masked);
+        ioapic.set_redirection(gsi, &redirection);
+    }
+}
@@ -0,0 +1,282 @@
+use super::{check_transfer, BlockDevice, Error, Request, Result};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use core::{fmt, ops::Range};
+use spin::Mutex;
+
+struct Entry {
+    data: Box<[u8]>,
+    /// Clock value of the entry's last write, so a flush can tell whether it changed while being written back.
+    version: u64,
+    dirty: bool,
+    last_used: u64,
+}
+
+struct State {
+    entries: BTreeMap<u64, Entry>,
+    /// Dirty entries which have been evicted, but not yet written back. They're still read from here until their
+    /// write-back succeeds, so the device's stale sectors are never read in their place.
+    evicted: BTreeMap<u64, Entry>,
+    /// Incremented by each access, to order entries by recency.
+    clock: u64,
+}
+
+impl State {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Data of the sector at `lba`, if it's cached or awaiting write-back. Marks a cached sector as used.
+    fn get(&mut self, lba: u64) -> Option<&[u8]> {
+        let last_used = self.tick();
+
+        match self.entries.get_mut(&lba) {
+            Some(entry) => {
+                entry.last_used = last_used;
+                Some(&*entry.data)
+            }
+
+            None => self.evicted.get(&lba).map(|entry| &*entry.data),
+        }
+    }
+
+    /// Inserts an entry for `lba`, evicting least recently used entries beyond `capacity`. Returns the evicted
+    /// entries which are dirty (and their versions), so they can be written back.
+    fn insert(&mut self, capacity: usize, lba: u64, entry: Entry) -> Vec<(u64, u64, Box<[u8]>)> {
+        self.entries.insert(lba, entry);
+
+        let mut evicted = Vec::new();
+        while self.entries.len() > capacity {
+            let oldest_lba = self.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(lba, _)| *lba).unwrap();
+            let oldest = self.entries.remove(&oldest_lba).unwrap();
+
+            if oldest.dirty {
+                evicted.push((oldest_lba, oldest.version, oldest.data.clone()));
+                self.evicted.insert(oldest_lba, oldest);
+            }
+        }
+
+        evicted
+    }
+}
+
+/// A write-back cache of a block device's sectors, which evicts the least recently used sectors once full.
+pub struct Cache {
+    device: Arc<dyn BlockDevice>,
+    /// Maximum number of sectors held.
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+impl Cache {
+    pub fn new(device: Arc<dyn BlockDevice>, capacity: usize) -> Self {
+        Self {
+            device,
+            capacity: capacity.max(1),
+            state: Mutex::new(State { entries: BTreeMap::new(), evicted: BTreeMap::new(), clock: 0 }),
+        }
+    }
+
+    #[inline]
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+
+    /// Writes `sectors` to the device, coalescing runs of contiguous sectors into single writes.
+    async fn write_sectors(&self, mut sectors: Vec<(u64, u64, Box<[u8]>)>) -> Result<()> {
+        sectors.sort_unstable_by_key(|(lba, _, _)| *lba);
+
+        let mut index = 0;
+        while index < sectors.len() {
+            let first_lba = sectors[index].0;
+            let run_len = sectors[index..]
+                .iter()
+                .zip(first_lba..)
+                .take_while(|((lba, _, _), expected_lba)| lba == expected_lba)
+                .count();
+
+            let run = sectors[index..(index + run_len)]
+                .iter()
+                .flat_map(|(_, _, data)| data.iter().copied())
+                .collect::<Vec<_>>();
+            self.device.write(first_lba, &run).await?;
+
+            index += run_len;
+        }
+
+        Ok(())
+    }
+
+    /// Writes back sectors evicted from the cache, then forgets them. If the write-back fails, they're returned to
+    /// the cache (unless written again since), still dirty, so their data isn't lost.
+    async fn write_back(&self, evicted: Vec<(u64, u64, Box<[u8]>)>) -> Result<()> {
+        if evicted.is_empty() {
+            return Ok(());
+        }
+
+        let versions = evicted.iter().map(|(lba, version, _)| (*lba, *version)).collect::<Vec<_>>();
+        let result = self.write_sectors(evicted).await;
+
+        let mut state = self.state.lock();
+        for (lba, version) in versions {
+            // A sector evicted again while being written back is left to its newer write-back.
+            if state.evicted.get(&lba).is_some_and(|entry| entry.version == version) {
+                let entry = state.evicted.remove(&lba).unwrap();
+
+                if result.is_err() && !state.entries.contains_key(&lba) {
+                    state.entries.insert(lba, entry);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Reads the sectors of `range` from the device, caching them and copying them into `buffer` (which covers
+    /// the sectors starting at `base_lba`). Sectors written to the cache in the meantime take precedence.
+    async fn fill(&self, base_lba: u64, range: Range<u64>, buffer: &mut [u8]) -> Result<()> {
+        let sector_size = self.device.sector_size();
+        let mut data = vec![0u8; usize::try_from(range.end - range.start).unwrap() * sector_size];
+        self.device.read(range.start, &mut data).await?;
+
+        let evicted = {
+            let mut state = self.state.lock();
+            let mut evicted = Vec::new();
+
+            for (lba, sector) in range.zip(data.chunks_exact(sector_size)) {
+                let offset = usize::try_from(lba - base_lba).unwrap() * sector_size;
+
+                if let Some(data) = state.get(lba) {
+                    buffer[offset..(offset + sector_size)].copy_from_slice(data);
+                } else {
+                    buffer[offset..(offset + sector_size)].copy_from_slice(sector);
+
+                    let last_used = state.tick();
+                    let entry = Entry { data: Box::from(sector), version: 0, dirty: false, last_used };
+                    evicted.extend(state.insert(self.capacity, lba, entry));
+                }
+            }
+
+            evicted
+        };
+
+        self.write_back(evicted).await
+    }
+}
+
+impl BlockDevice for Cache {
+    fn sector_size(&self) -> usize {
+        self.device.sector_size()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.device.sector_count()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.device.is_read_only()
+    }
+
+    fn read<'a>(&'a self, lba: u64, buffer: &'a mut [u8]) -> Request<'a> {
+        Box::pin(async move {
+            let count = check_transfer(self, lba, buffer.len())?;
+            let sector_size = self.sector_size();
+
+            // Copy out every cached sector, noting the runs of sectors which must be read from the device.
+            let mut misses = Vec::<Range<u64>>::new();
+            {
+                let mut state = self.state.lock();
+
+                for (sector_lba, sector) in (lba..(lba + count)).zip(buffer.chunks_exact_mut(sector_size)) {
+                    match state.get(sector_lba) {
+                        Some(data) => sector.copy_from_slice(data),
+
+                        None => match misses.last_mut() {
+                            Some(run) if run.end == sector_lba => run.end += 1,
+                            _ => misses.push(sector_lba..(sector_lba + 1)),
+                        },
+                    }
+                }
+            }
+
+            for range in misses {
+                self.fill(lba, range, buffer).await?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn write<'a>(&'a self, lba: u64, buffer: &'a [u8]) -> Request<'a> {
+        Box::pin(async move {
+            if self.is_read_only() {
+                return Err(Error::ReadOnly);
+            }
+
+            let count = check_transfer(self, lba, buffer.len())?;
+            let sector_size = self.sector_size();
+
+            let evicted = {
+                let mut state = self.state.lock();
+                let mut evicted = Vec::new();
+
+                for (sector_lba, sector) in (lba..(lba + count)).zip(buffer.chunks_exact(sector_size)) {
+                    let last_used = state.tick();
+
+                    if let Some(entry) = state.entries.get_mut(&sector_lba) {
+                        entry.data.copy_from_slice(sector);
+                        entry.version = last_used;
+                        entry.dirty = true;
+                        entry.last_used = last_used;
+                    } else {
+                        let entry = Entry { data: Box::from(sector), version: last_used, dirty: true, last_used };
+                        evicted.extend(state.insert(self.capacity, sector_lba, entry));
+                    }
+                }
+
+                evicted
+            };
+
+            self.write_back(evicted).await
+        })
+    }
+
+    fn flush(&self) -> Request<'_> {
+        Box::pin(async move {
+            let dirty = self
+                .state
+                .lock()
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.dirty)
+                .map(|(lba, entry)| (*lba, entry.version, entry.data.clone()))
+                .collect::<Vec<_>>();
+
+            let versions = dirty.iter().map(|(lba, version, _)| (*lba, *version)).collect::<Vec<_>>();
+            self.write_sectors(dirty).await?;
+
+            // Sectors which were written again during the write-back remain dirty.
+            {
+                let mut state = self.state.lock();
+                for (lba, version) in versions {
+                    if let Some(entry) = state.entries.get_mut(&lba).filter(|entry| entry.version == version) {
+                        entry.dirty = false;
+                    }
+                }
+            }
+
+            self.device.flush().await
+        })
+    }
+}
+
+impl fmt::Debug for Cache {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("Cache")
+            .field("Device", &self.device)
+            .field("Capacity", &self.capacity)
+            .field("Cached", &self.state.lock().entries.len())
+            .finish()
+    }
+}
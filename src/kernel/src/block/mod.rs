@@ -0,0 +1,196 @@
+mod cache;
+mod partition;
+
+pub use cache::*;
+pub use partition::*;
+
+use crate::drivers::{ahci, nvme, virtio};
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    task::Wake,
+    vec::Vec,
+};
+use core::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+use spin::RwLock;
+
+crate::error_impl! {
+    #[derive(Debug)]
+    pub enum Error {
+        /// Indicates a transfer would extend past the end of the device.
+        OutOfBounds { lba: u64, count: u64 } => None,
+
+        /// Indicates a buffer's length isn't a multiple of the device's sector size.
+        UnalignedBuffer { len: usize } => None,
+
+        /// Indicates a write was attempted to a read-only device.
+        ReadOnly => None,
+
+        /// Indicates a partition table is present, but malformed.
+        InvalidPartitionTable { reason: &'static str } => None,
+
+        Nvme { err: nvme::Error } => Some(err),
+        Ahci { err: ahci::Error } => Some(err),
+        Virtio { err: virtio::Error } => Some(err)
+    }
+}
+
+impl From<nvme::Error> for Error {
+    fn from(err: nvme::Error) -> Self {
+        Self::Nvme { err }
+    }
+}
+
+impl From<ahci::Error> for Error {
+    fn from(err: ahci::Error) -> Self {
+        Self::Ahci { err }
+    }
+}
+
+impl From<virtio::Error> for Error {
+    fn from(err: virtio::Error) -> Self {
+        Self::Virtio { err }
+    }
+}
+
+/// A request submitted to a block device, which resolves once the device has completed it.
+///
+/// Requests aren't necessarily asynchronous: a driver may wait for its device within the request's first poll. Only
+/// AHCI requests currently yield until their completion interrupt wakes them; NVMe and virtio requests complete
+/// synchronously, polling their device.
+pub type Request<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// A device addressed in fixed-size sectors, which filesystems are built upon.
+pub trait BlockDevice: fmt::Debug + Send + Sync {
+    /// Size of the device's sectors, in bytes.
+    fn sector_size(&self) -> usize;
+
+    fn sector_count(&self) -> u64;
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Reads the sectors starting at `lba` into `buffer`, whose length must be a multiple of the sector size.
+    fn read<'a>(&'a self, lba: u64, buffer: &'a mut [u8]) -> Request<'a>;
+
+    /// Writes `buffer`, whose length must be a multiple of the sector size, to the sectors starting at `lba`.
+    fn write<'a>(&'a self, lba: u64, buffer: &'a [u8]) -> Request<'a>;
+
+    /// Commits any cached writes to non-volatile media.
+    fn flush(&self) -> Request<'_>;
+
+    /// Size of the device, in bytes.
+    fn capacity(&self) -> u64 {
+        u64::try_from(self.sector_size()).unwrap() * self.sector_count()
+    }
+}
+
+/// Checks that a transfer of `len` bytes starting at `lba` is a whole number of sectors within the device. Returns
+/// the number of sectors it covers.
+pub fn check_transfer(device: &(impl BlockDevice + ?Sized), lba: u64, len: usize) -> Result<u64> {
+    if len % device.sector_size() != 0 {
+        return Err(Error::UnalignedBuffer { len });
+    }
+
+    let count = u64::try_from(len / device.sector_size()).unwrap();
+    if lba.checked_add(count).map_or(true, |end_lba| end_lba > device.sector_count()) {
+        return Err(Error::OutOfBounds { lba, count });
+    }
+
+    Ok(count)
+}
+
+/// Wakes [`block_on`] once a pending request can make progress.
+struct Signal(AtomicBool);
+
+impl Wake for Signal {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// Drives `future` to completion, for callers which can't yield (e.g. system calls, which run on the core-local
+/// stack). While the future is pending, the core halts until an interrupt wakes it. Before the core can wait for
+/// interrupts (during early boot), the future is instead polled continuously, and devices poll for completions.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = core::pin::pin!(future);
+    let signal = Arc::new(Signal(AtomicBool::new(false)));
+    let waker = Waker::from(signal.clone());
+    let mut context = Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+
+        crate::interrupts::without(|| {
+            // The signal is checked with interrupts disabled, so a wake can't be missed before the core halts.
+            if !signal.0.swap(false, Ordering::Acquire) {
+                if crate::cpu::state::can_wait_for_interrupt() {
+                    crate::cpu::state::wait_for_interrupt();
+                } else {
+                    core::hint::spin_loop();
+                }
+            }
+        });
+    }
+}
+
+/// Number of sectors cached for each registered disk.
+const CACHE_SECTORS: usize = 1024;
+
+static DEVICES: RwLock<BTreeMap<String, Arc<dyn BlockDevice>>> = RwLock::new(BTreeMap::new());
+static NEXT_DISK_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Registers a disk, placing a sector cache in front of it, and registers each partition found on it. Returns the
+/// name the disk was registered under.
+///
+/// Disks are named `diskN`, and their partitions `diskNpM` (where `M` is the partition's index in the table).
+pub fn register(device: Arc<dyn BlockDevice>) -> String {
+    let name = format!("disk{}", NEXT_DISK_ID.fetch_add(1, Ordering::Relaxed));
+    let disk: Arc<dyn BlockDevice> = Arc::new(Cache::new(device, CACHE_SECTORS));
+    info!("Registered block device {}: {:?}", name, disk);
+
+    match block_on(partition::scan(&disk)) {
+        Ok(partitions) => {
+            let mut devices = DEVICES.write();
+
+            for partition in partitions {
+                let partition_name = format!("{}p{}", name, partition.index());
+                info!("Registered block device {}: {:?}", partition_name, partition);
+
+                devices.insert(partition_name, Arc::new(partition));
+            }
+        }
+
+        Err(err) => warn!("Failed to read partition table of {}: {:?}", name, err),
+    }
+
+    DEVICES.write().insert(name.clone(), disk);
+
+    name
+}
+
+/// Gets the registered block device with the provided name.
+pub fn get(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES.read().get(name).cloned()
+}
+
+/// Every registered block device, and its name.
+pub fn devices() -> Vec<(String, Arc<dyn BlockDevice>)> {
+    DEVICES.read().iter().map(|(name, device)| (name.to_string(), device.clone())).collect()
+}
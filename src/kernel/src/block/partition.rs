@@ -0,0 +1,248 @@
+use super::{check_transfer, BlockDevice, Error, Request, Result};
+use alloc::{boxed::Box, string::String, sync::Arc, vec, vec::Vec};
+use core::fmt;
+use uuid::Uuid;
+
+/// Identifies what a partition holds, as recorded by its partition table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionKind {
+    Mbr { system_id: u8 },
+    Gpt { type_guid: Uuid, unique_guid: Uuid },
+}
+
+/// A contiguous range of a disk's sectors, exposed as a block device of its own.
+pub struct Partition {
+    disk: Arc<dyn BlockDevice>,
+    /// Index of the partition within its table, starting at 1.
+    index: usize,
+    first_lba: u64,
+    sector_count: u64,
+    kind: PartitionKind,
+    label: String,
+}
+
+impl Partition {
+    #[inline]
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+
+    /// LBA of the partition's first sector, on the disk.
+    #[inline]
+    pub const fn first_lba(&self) -> u64 {
+        self.first_lba
+    }
+
+    #[inline]
+    pub const fn kind(&self) -> PartitionKind {
+        self.kind
+    }
+
+    /// Name of the partition, which only GPT records.
+    #[inline]
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    #[inline]
+    pub fn disk(&self) -> &Arc<dyn BlockDevice> {
+        &self.disk
+    }
+}
+
+impl BlockDevice for Partition {
+    fn sector_size(&self) -> usize {
+        self.disk.sector_size()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sector_count
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.disk.is_read_only()
+    }
+
+    fn read<'a>(&'a self, lba: u64, buffer: &'a mut [u8]) -> Request<'a> {
+        Box::pin(async move {
+            check_transfer(self, lba, buffer.len())?;
+            self.disk.read(self.first_lba + lba, buffer).await
+        })
+    }
+
+    fn write<'a>(&'a self, lba: u64, buffer: &'a [u8]) -> Request<'a> {
+        Box::pin(async move {
+            check_transfer(self, lba, buffer.len())?;
+            self.disk.write(self.first_lba + lba, buffer).await
+        })
+    }
+
+    fn flush(&self) -> Request<'_> {
+        self.disk.flush()
+    }
+}
+
+impl fmt::Debug for Partition {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("Partition")
+            .field("Index", &self.index)
+            .field("First LBA", &self.first_lba)
+            .field("Sector Count", &self.sector_count)
+            .field("Kind", &self.kind)
+            .field("Label", &self.label)
+            .finish_non_exhaustive()
+    }
+}
+
+/// MBR partition type which marks a disk as partitioned with GPT.
+const MBR_PROTECTIVE: u8 = 0xEE;
+/// MBR partition types of extended partitions, which hold a chain of logical partitions.
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0F, 0x85];
+const MBR_ENTRIES_OFFSET: usize = 0x1BE;
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// Largest partition entry array which will be read, so a corrupt header can't exhaust memory.
+const GPT_MAX_ENTRIES_LEN: usize = 1 << 20;
+
+/// Reads the partition table of `disk`, if it has one. Disks without an MBR signature have no partitions.
+///
+/// REMARK: Logical partitions within MBR extended partitions, and the backup GPT, aren't yet read.
+pub async fn scan(disk: &Arc<dyn BlockDevice>) -> Result<Vec<Partition>> {
+    if disk.sector_size() < 512 {
+        return Ok(Vec::new());
+    }
+
+    let mut mbr = vec![0u8; disk.sector_size()];
+    disk.read(0, &mut mbr).await?;
+
+    if mbr[510..512] != MBR_SIGNATURE {
+        return Ok(Vec::new());
+    }
+
+    let entries = mbr[MBR_ENTRIES_OFFSET..(MBR_ENTRIES_OFFSET + (4 * 16))].chunks_exact(16);
+    if entries.clone().any(|entry| entry[4] == MBR_PROTECTIVE) {
+        return scan_gpt(disk).await;
+    }
+
+    let mut partitions = Vec::new();
+    for (index, entry) in entries.enumerate() {
+        let system_id = entry[4];
+        let first_lba = u64::from(u32::from_le_bytes(entry[8..12].try_into().unwrap()));
+        let sector_count = u64::from(u32::from_le_bytes(entry[12..16].try_into().unwrap()));
+
+        if system_id == 0 || sector_count == 0 {
+            continue;
+        }
+
+        if MBR_EXTENDED.contains(&system_id) {
+            debug!("Skipping extended MBR partition {}.", index + 1);
+            continue;
+        }
+
+        if first_lba == 0 || (first_lba + sector_count) > disk.sector_count() {
+            return Err(Error::InvalidPartitionTable { reason: "MBR partition is out of bounds of the disk" });
+        }
+
+        partitions.push(Partition {
+            disk: disk.clone(),
+            index: index + 1,
+            first_lba,
+            sector_count,
+            kind: PartitionKind::Mbr { system_id },
+            label: String::new(),
+        });
+    }
+
+    Ok(partitions)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..(offset + 4)].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..(offset + 8)].try_into().unwrap())
+}
+
+async fn scan_gpt(disk: &Arc<dyn BlockDevice>) -> Result<Vec<Partition>> {
+    let sector_size = disk.sector_size();
+
+    let mut header = vec![0u8; sector_size];
+    disk.read(1, &mut header).await?;
+
+    if &header[..8] != GPT_SIGNATURE {
+        return Err(Error::InvalidPartitionTable { reason: "GPT header signature is missing" });
+    }
+
+    let header_len = usize::try_from(read_u32(&header, 12)).unwrap();
+    if !(92..=sector_size).contains(&header_len) {
+        return Err(Error::InvalidPartitionTable { reason: "GPT header size is invalid" });
+    }
+
+    // The header's checksum is calculated with its own field zeroed.
+    let header_crc = read_u32(&header, 16);
+    header[16..20].fill(0);
+    if crc32(&header[..header_len]) != header_crc {
+        return Err(Error::InvalidPartitionTable { reason: "GPT header checksum mismatch" });
+    }
+
+    let entries_lba = read_u64(&header, 72);
+    let entry_count = usize::try_from(read_u32(&header, 80)).unwrap();
+    let entry_len = usize::try_from(read_u32(&header, 84)).unwrap();
+    let entries_crc = read_u32(&header, 88);
+
+    let entries_len = entry_count * entry_len;
+    if entry_len < 128 || entries_len > GPT_MAX_ENTRIES_LEN {
+        return Err(Error::InvalidPartitionTable { reason: "GPT partition entry array is invalid" });
+    }
+
+    let mut entries = vec![0u8; entries_len.next_multiple_of(sector_size)];
+    disk.read(entries_lba, &mut entries).await?;
+    if crc32(&entries[..entries_len]) != entries_crc {
+        return Err(Error::InvalidPartitionTable { reason: "GPT partition entry array checksum mismatch" });
+    }
+
+    let mut partitions = Vec::new();
+    for (index, entry) in entries[..entries_len].chunks_exact(entry_len).enumerate() {
+        let type_guid = Uuid::from_bytes_le(entry[0..16].try_into().unwrap());
+        if type_guid.is_nil() {
+            continue;
+        }
+
+        let first_lba = read_u64(entry, 32);
+        // The last LBA is inclusive.
+        let last_lba = read_u64(entry, 40);
+        if first_lba > last_lba || last_lba >= disk.sector_count() {
+            return Err(Error::InvalidPartitionTable { reason: "GPT partition is out of bounds of the disk" });
+        }
+
+        let label = char::decode_utf16(
+            entry[56..128]
+                .chunks_exact(2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                .take_while(|c| *c != 0),
+        )
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+
+        partitions.push(Partition {
+            disk: disk.clone(),
+            index: index + 1,
+            first_lba,
+            sector_count: (last_lba - first_lba) + 1,
+            kind: PartitionKind::Gpt { type_guid, unique_guid: Uuid::from_bytes_le(entry[16..32].try_into().unwrap()) },
+            label,
+        });
+    }
+
+    Ok(partitions)
+}
+
+/// CRC-32 (IEEE 802.3), as used to checksum GPT structures.
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(u32::MAX, |crc, byte| {
+        (0..8).fold(crc ^ u32::from(*byte), |crc, _| if (crc & 1) > 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 })
+    })
+}
@@ -17,6 +17,11 @@ pub const STACK_SIZE: usize = 0x10000;
 
 #[repr(C)]
 struct State {
+    /// Top of the core-local system call stack. This is read directly by the `syscall` entry stub.
+    syscall_stack_ptr: NonNull<u8>,
+    /// Scratch space for the user stack pointer, used by the `syscall` entry stub while switching stacks.
+    syscall_user_sp: usize,
+
     core_id: u32,
     scheduler: InterruptCell<Scheduler>,
 
@@ -29,12 +34,23 @@ struct State {
     apic: apic::Apic,
 
     timer_interval: Option<NonZeroU64>,
+    timer_frequency: u16,
+    /// Total timer counts elapsed before the current preemption wait was set.
+    timer_counts: u64,
+    /// Timer count at which the current preemption wait was set (TSC-deadline mode), or the count it was set to
+    /// (one-shot mode).
+    timer_armed_count: u64,
+    /// Whether the core is halted within the kernel, waiting for a device's interrupt. The interrupted context is
+    /// mid-operation, so the timer mustn't switch tasks out from under it.
+    waiting_for_interrupt: bool,
 
     catch_exception: AtomicBool,
     exception: UnsafeCell<Option<Exception>>,
 }
 
 pub const SYSCALL_STACK_SIZE: usize = 0x40000;
+pub const SYSCALL_STACK_PTR_OFFSET: usize = core::mem::offset_of!(State, syscall_stack_ptr);
+pub const SYSCALL_USER_SP_OFFSET: usize = core::mem::offset_of!(State, syscall_user_sp);
 
 pub enum ExceptionCatcher {
     Caught(Exception),
@@ -89,7 +105,18 @@ pub unsafe fn init(timer_frequency: u16) {
         tss
     };
 
+    let syscall_stack_ptr = {
+        use crate::mem::Stack;
+
+        // Safety: The stack is only ever written to before it is read from.
+        let stack = unsafe { Box::<Stack<SYSCALL_STACK_SIZE>>::new_zeroed().assume_init() };
+        Box::leak(stack).top()
+    };
+
     let mut state = Box::new(State {
+        syscall_stack_ptr,
+        syscall_user_sp: 0,
+
         core_id: crate::cpu::read_id(),
         scheduler: InterruptCell::new(Scheduler::new(false)),
 
@@ -102,6 +129,10 @@ pub unsafe fn init(timer_frequency: u16) {
         apic: apic::Apic::new(Some(|address: usize| crate::mem::HHDM.ptr().add(address))).unwrap(),
 
         timer_interval: None,
+        timer_frequency,
+        timer_counts: 0,
+        timer_armed_count: 0,
+        waiting_for_interrupt: false,
 
         catch_exception: AtomicBool::new(false),
         exception: UnsafeCell::new(None),
@@ -223,6 +254,36 @@ pub fn with_scheduler<O>(func: impl FnOnce(&mut crate::task::Scheduler) -> O) ->
     state.scheduler.with_mut(func)
 }
 
+/// Whether the core can wait for interrupts within the kernel. This requires the scheduler's timer to be running, so
+/// a wait always ends, even if the interrupt being waited for never arrives.
+pub fn can_wait_for_interrupt() -> bool {
+    get_state().is_ok_and(|state| state.scheduler.with(Scheduler::is_enabled))
+}
+
+/// Halts the core until the next interrupt, from within the kernel. Interrupts must be disabled, and remain so once
+/// this returns; while halted, the timer doesn't switch tasks (see [`is_waiting_for_interrupt`]).
+pub fn wait_for_interrupt() {
+    debug_assert!(!crate::interrupts::are_enabled());
+    debug_assert!(can_wait_for_interrupt());
+
+    let state = get_state_mut().unwrap();
+    state.waiting_for_interrupt = true;
+
+    // Safety: The timer is running and won't switch tasks while the core is waiting, so control always returns here.
+    //         Interrupts are held off until `hlt` has begun (by `sti`'s interrupt shadow), so none can be missed.
+    unsafe {
+        #[cfg(target_arch = "x86_64")]
+        core::arch::asm!("sti", "hlt", "cli", options(nostack, nomem));
+    }
+
+    state.waiting_for_interrupt = false;
+}
+
+/// Whether the core is halted in [`wait_for_interrupt`].
+pub fn is_waiting_for_interrupt() -> bool {
+    get_state().is_ok_and(|state| state.waiting_for_interrupt)
+}
+
 /// Ends the current interrupt context for the interrupt controller.
 ///
 /// On platforms that don't require an EOI, this is a no-op.
@@ -233,6 +294,34 @@ pub unsafe fn end_of_interrupt() -> Result<()> {
     Ok(())
 }
 
+/// Returns the number of timer counts elapsed since the current preemption wait was set.
+fn elapsed_timer_counts(state: &State) -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    match state.apic.get_timer().get_mode() {
+        // A periodic timer counts down from the armed count just as a one-shot timer does, but reloads it on expiry.
+        // Every expiry re-arms the timer through `set_preemption_wait`, so at most one period has elapsed.
+        apic::TimerMode::OneShot | apic::TimerMode::Periodic => {
+            state.timer_armed_count.saturating_sub(u64::from(state.apic.get_timer_current_count()))
+        }
+
+        // Safety: Reading the TSC has no side effects.
+        apic::TimerMode::TscDeadline => unsafe { core::arch::x86_64::_rdtsc() }.saturating_sub(state.timer_armed_count),
+    }
+}
+
+/// Returns the monotonic count of timer ticks on the local core.
+pub fn get_ticks() -> Result<u64> {
+    let state = get_state()?;
+    let timer_interval = state.timer_interval.ok_or(Error::NotInitialized)?;
+
+    Ok((state.timer_counts + elapsed_timer_counts(state)) / timer_interval.get())
+}
+
+/// Converts a duration in milliseconds to local timer ticks, rounding up. Durations too long to count saturate.
+pub fn ms_to_ticks(milliseconds: u64) -> Result<u64> {
+    get_state().map(|state| milliseconds.saturating_mul(u64::from(state.timer_frequency)).div_ceil(1000))
+}
+
 /// ### Safety
 ///
 /// Caller must ensure that setting a new preemption wait will not cause undefined behaviour.
@@ -240,25 +329,33 @@ pub unsafe fn set_preemption_wait(interval_wait: core::num::NonZeroU16) -> Resul
     let state = get_state_mut()?;
     let timer_interval = state.timer_interval.unwrap();
 
+    // Account for the time elapsed in the previous wait, so the tick count stays monotonic.
+    let elapsed_counts = elapsed_timer_counts(state);
+    state.timer_counts += elapsed_counts;
+
     #[cfg(target_arch = "x86_64")]
     {
         let apic = &mut state.apic;
 
         match apic.get_timer().get_mode() {
             // Safety: Control flow expects timer initial count to be set.
-            apic::TimerMode::OneShot => unsafe {
+            apic::TimerMode::OneShot | apic::TimerMode::Periodic => unsafe {
                 let final_count = timer_interval.get() * u64::from(interval_wait.get());
-                apic.set_timer_initial_count(final_count.try_into().unwrap_or(u32::MAX));
+                let final_count = u32::try_from(final_count).unwrap_or(u32::MAX);
+                apic.set_timer_initial_count(final_count);
+
+                state.timer_armed_count = u64::from(final_count);
             },
 
             // Safety: Control flow expects the TSC deadline to be set.
             apic::TimerMode::TscDeadline => unsafe {
+                let now = core::arch::x86_64::_rdtsc();
                 crate::arch::x86_64::registers::msr::IA32_TSC_DEADLINE::set(
-                    core::arch::x86_64::_rdtsc() + (timer_interval.get() * u64::from(interval_wait.get())),
+                    now + (timer_interval.get() * u64::from(interval_wait.get())),
                 );
-            },
 
-            apic::TimerMode::Periodic => unimplemented!(),
+                state.timer_armed_count = now;
+            },
         }
     }
 
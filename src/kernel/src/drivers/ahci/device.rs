@@ -0,0 +1,592 @@
+use super::{
+    hba::{self, fis::Hw2Dev, wait_ms, Class, Command, CommandTable, InterruptStatus, PRDTEntry, Port, TaskFile},
+    Error, Result,
+};
+use crate::{interrupts::InterruptCell, mem::dma::DmaBuffer};
+use alloc::{collections::VecDeque, string::String, vec::Vec};
+use bit_field::BitField;
+use core::{
+    fmt,
+    num::NonZeroUsize,
+    ops::Range,
+    task::{Poll, Waker},
+};
+use libsys::{page_size, Address, Frame};
+use spin::{Mutex, RwLock};
+
+/// Identifying information and geometry reported by ATA IDENTIFY DEVICE.
+#[derive(Debug, Clone, Default)]
+struct Identity {
+    model: String,
+    serial: String,
+    sector_size: usize,
+    sector_count: u64,
+    lba48: bool,
+    /// Number of native queued commands the device may have outstanding, if it supports them.
+    queue_depth: Option<usize>,
+}
+
+impl Identity {
+    fn parse(data: &[u8]) -> Self {
+        let words = data.chunks_exact(2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]])).collect::<Vec<_>>();
+        // Strings hold two characters per word, with the first in the high byte.
+        let string = |range: Range<usize>| {
+            String::from(
+                words[range].iter().flat_map(|word| word.to_be_bytes()).map(char::from).collect::<String>().trim(),
+            )
+        };
+
+        let lba48 = words[83].get_bit(10);
+        let sector_count = if lba48 {
+            words[100..104].iter().rev().fold(0u64, |count, word| (count << 16) | u64::from(*word))
+        } else {
+            u64::from(words[60]) | (u64::from(words[61]) << 16)
+        };
+
+        // Word 106 is only valid when bit 14 is set and bit 15 is clear; bit 12 indicates sectors are larger than
+        // 256 words, with their size (in words) reported in words 117..119.
+        let sector_size = if words[106].get_bits(14..16) == 0b01 && words[106].get_bit(12) {
+            2 * (usize::from(words[117]) | (usize::from(words[118]) << 16))
+        } else {
+            512
+        };
+
+        Self {
+            model: string(27..47),
+            serial: string(10..20),
+            sector_size,
+            sector_count,
+            lba48,
+            queue_depth: words[76].get_bit(8).then(|| usize::from(words[75].get_bits(0..5)) + 1),
+        }
+    }
+}
+
+/// Command slots of a port, tracked as bitmasks indexed by slot.
+struct Slots {
+    /// Slots in use by a command, whether or not it's been issued.
+    allocated: u32,
+    /// Slots whose commands have been issued and not yet completed.
+    issued: u32,
+    /// Task file of the device when each slot's command failed.
+    failed: [Option<TaskFile>; 32],
+    /// Waker of each slot's waiter, woken once its command completes.
+    wakers: [Option<Waker>; 32],
+}
+
+impl Slots {
+    /// Retires the issued commands in `completed`, waking their waiters.
+    fn complete(&mut self, completed: u32) {
+        for slot in (0..32).filter(|slot| completed.get_bit(*slot)) {
+            if let Some(waker) = self.wakers[slot].take() {
+                waker.wake();
+            }
+        }
+
+        self.issued &= !completed;
+    }
+}
+
+/// A SATA disk attached to a port of the HBA.
+pub struct Device {
+    hba: &'static hba::Memory,
+    port_index: usize,
+    port: &'static Port,
+    /// Command list, followed by the received FIS area.
+    command_list: DmaBuffer,
+    /// Command table of each slot.
+    command_tables: DmaBuffer,
+    /// Bounce buffer of each usable slot, which data is transferred through.
+    buffers: DmaBuffer,
+    /// Whether transfers use native command queueing.
+    queued: bool,
+    /// Number of slots which may be in use at once.
+    queue_depth: usize,
+    slots: InterruptCell<Mutex<Slots>>,
+    /// Held shared by queued commands, and exclusively by all others, which can't be outstanding alongside them.
+    issue_mode: RwLock<()>,
+    identity: Identity,
+}
+
+// Safety: Registers are accessed through the global HHDM, and slots are only accessed behind their lock.
+unsafe impl Send for Device {}
+// Safety: See above.
+unsafe impl Sync for Device {}
+
+impl Device {
+    const COMMAND_TIMEOUT_US: u32 = 5_000_000;
+    const POLL_INTERVAL_US: u32 = 10;
+    const RECEIVED_FIS_OFFSET: usize = 0x400;
+    const SLOT_BUFFER_PAGES: usize = 4;
+
+    /// Starts the port, and identifies the disk attached to it.
+    ///
+    /// ### Safety
+    ///
+    /// Caller must ensure `hba` remains valid for the lifetime of the device, and that `port` (which is the port
+    /// `port_index` of `hba`) is used by no other device.
+    pub unsafe fn new(hba: &'static hba::Memory, port_index: usize, port: &'static Port) -> Result<Self> {
+        const READY_TIMEOUT_MS: u32 = 1000;
+
+        if !port.stop() {
+            return Err(Error::Timeout);
+        }
+
+        let capabilities = hba.capabilities();
+        // Without 64-bit addressing, the HBA can only reach memory below 4GiB.
+        let limit = (!capabilities.get_s64a()).then(|| Address::<Frame>::new(1 << 32).unwrap());
+        let tables_size = capabilities.get_ncs() * core::mem::size_of::<CommandTable>();
+
+        let command_list = DmaBuffer::new(NonZeroUsize::MIN, limit)?;
+        let command_tables =
+            DmaBuffer::new(NonZeroUsize::new(libsys::align_up_div(tables_size, libsys::page_shift())).unwrap(), limit)?;
+        let buffers = DmaBuffer::new(NonZeroUsize::new(Self::SLOT_BUFFER_PAGES).unwrap(), limit)?;
+
+        port.set_bases(command_list.physical_address(0), command_list.physical_address(Self::RECEIVED_FIS_OFFSET));
+
+        // From here, dropping the device stops the port, so it's stopped before its memory is freed.
+        let mut device = Self {
+            hba,
+            port_index,
+            port,
+            command_list,
+            command_tables,
+            buffers,
+            queued: false,
+            queue_depth: 1,
+            slots: InterruptCell::new(Mutex::new(Slots {
+                allocated: 0,
+                issued: 0,
+                failed: [None; 32],
+                wakers: [const { None }; 32],
+            })),
+            issue_mode: RwLock::new(()),
+            identity: Identity::default(),
+        };
+
+        port.clear_sata_error();
+        port.clear_interrupt_status(InterruptStatus::all());
+        port.command_status().set_fre(true);
+
+        if !wait_ms(READY_TIMEOUT_MS, || !port.task_file().is_busy()) {
+            return Err(Error::Timeout);
+        }
+
+        match port.class() {
+            Some(Class::SATA) => {}
+            class => return Err(Error::UnsupportedDevice { port: port_index, class }),
+        }
+
+        port.start();
+        port.set_interrupt_enable(
+            InterruptStatus::DHRS | InterruptStatus::PSS | InterruptStatus::SDBS | InterruptStatus::FATAL_ERRORS,
+        );
+
+        // Interrupts aren't yet routed to the controller, so the identify is polled.
+        device.identity = crate::block::block_on(device.identify(false))?;
+        if !device.identity.lba48 {
+            return Err(Error::NoLba48 { port: port_index });
+        }
+
+        // Native command queueing requires support from both the HBA and the device. Queued commands are tagged
+        // with their slot, so the usable slots are limited to the device's queue depth.
+        if let Some(queue_depth) = device.identity.queue_depth.filter(|_| capabilities.get_sncq()) {
+            device.queued = true;
+            device.queue_depth = queue_depth.min(capabilities.get_ncs());
+            device.buffers =
+                DmaBuffer::new(NonZeroUsize::new(device.queue_depth * Self::SLOT_BUFFER_PAGES).unwrap(), limit)?;
+        }
+
+        Ok(device)
+    }
+
+    /// Index of the port the device is attached to.
+    #[inline]
+    pub const fn port(&self) -> usize {
+        self.port_index
+    }
+
+    #[inline]
+    pub fn model(&self) -> &str {
+        &self.identity.model
+    }
+
+    #[inline]
+    pub fn serial(&self) -> &str {
+        &self.identity.serial
+    }
+
+    /// Size of the device's logical sectors, in bytes.
+    #[inline]
+    pub const fn sector_size(&self) -> usize {
+        self.identity.sector_size
+    }
+
+    #[inline]
+    pub const fn sector_count(&self) -> u64 {
+        self.identity.sector_count
+    }
+
+    /// Number of queued commands which may be outstanding at once, or `None` if the device isn't using native
+    /// command queueing.
+    pub fn queue_depth(&self) -> Option<usize> {
+        self.queued.then_some(self.queue_depth)
+    }
+
+    fn slot_buffer_len(&self) -> usize {
+        Self::SLOT_BUFFER_PAGES * page_size()
+    }
+
+    /// ### Safety
+    ///
+    /// Caller must ensure `slot` is allocated to them, and that its command isn't outstanding.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn slot_buffer(&self, slot: usize) -> &mut [u8] {
+        let len = self.slot_buffer_len();
+
+        &mut self.buffers.as_mut_slice()[(slot * len)..((slot + 1) * len)]
+    }
+
+    /// Retires completed commands, failing every outstanding command if the port reported an error.
+    ///
+    /// This is called from the controller's interrupt handler, or by waiters when completions aren't signalled by
+    /// interrupts.
+    pub fn service(&self) {
+        self.slots.with(|slots| {
+            let mut slots = slots.lock();
+
+            // The port's status must be cleared before the HBA's, or the HBA's is immediately set again.
+            let status = self.port.interrupt_status();
+            self.port.clear_interrupt_status(status);
+            self.hba.clear_interrupt_status(1 << self.port_index);
+
+            if status.intersects(InterruptStatus::FATAL_ERRORS) {
+                self.fail_outstanding(&mut slots);
+            } else {
+                // Queued commands are outstanding until the device clears their active bit, and all others until
+                // the HBA clears their issue bit.
+                let outstanding = self.port.sata_active() | self.port.command_issue();
+                let completed = slots.issued & !outstanding;
+                slots.complete(completed);
+            }
+        });
+    }
+
+    /// Fails every outstanding command, and restarts the port so it processes commands again.
+    fn fail_outstanding(&self, slots: &mut Slots) {
+        const CLO_TIMEOUT_MS: u32 = 500;
+
+        let task_file = self.port.task_file();
+        warn!("AHCI port {} failed outstanding commands {:#b}: {:X?}", self.port_index, slots.issued, task_file);
+
+        for slot in (0..32).filter(|slot| slots.issued.get_bit(*slot)) {
+            slots.failed[slot] = Some(task_file);
+        }
+        let issued = slots.issued;
+        slots.complete(issued);
+
+        // Stopping the port clears its issued and active commands.
+        self.port.stop();
+        self.port.clear_sata_error();
+        self.port.clear_interrupt_status(InterruptStatus::all());
+
+        // If the device is still busy, the HBA must be told to ignore it before the port can be started again.
+        if self.port.task_file().is_busy() && self.hba.capabilities().get_sclo() {
+            self.port.command_status().set_clo();
+            wait_ms(CLO_TIMEOUT_MS, || !self.port.command_status().get_clo());
+        }
+
+        self.port.command_status().set_fre(true);
+        self.port.start();
+    }
+
+    /// Allocates a free command slot, waiting for one if every slot is in use.
+    fn acquire_slot(&self, interrupts: bool) -> Result<usize> {
+        let slot_mask = u32::MAX >> (32 - self.queue_depth);
+
+        for _ in 0..(Self::COMMAND_TIMEOUT_US / Self::POLL_INTERVAL_US) {
+            let slot = self.slots.with(|slots| {
+                let mut slots = slots.lock();
+                let free = !slots.allocated & slot_mask;
+
+                (free > 0).then(|| {
+                    let slot = usize::try_from(free.trailing_zeros()).unwrap();
+                    slots.allocated.set_bit(slot, true);
+
+                    slot
+                })
+            });
+
+            match slot {
+                Some(slot) => return Ok(slot),
+                None if !interrupts => self.service(),
+                None => {}
+            }
+
+            crate::time::SYSTEM_CLOCK.spin_wait_us(Self::POLL_INTERVAL_US);
+        }
+
+        Err(Error::Timeout)
+    }
+
+    fn release_slot(&self, slot: usize) {
+        self.slots.with(|slots| {
+            let mut slots = slots.lock();
+            slots.allocated.set_bit(slot, false);
+            slots.failed[slot] = None;
+            slots.wakers[slot] = None;
+        });
+    }
+
+    /// Builds the command for `fis` in `slot`, which transfers the first `len` bytes of the slot's buffer, and
+    /// issues it.
+    fn issue(&self, slot: usize, fis: Hw2Dev, len: usize, write: bool) {
+        let table_address = self.command_tables.physical_address(slot * core::mem::size_of::<CommandTable>());
+        let prdt_entry =
+            (len > 0).then(|| PRDTEntry::new(self.buffers.physical_address(slot * self.slot_buffer_len()), len));
+
+        // Safety: The slot is allocated to this command, which isn't yet issued, so the HBA isn't accessing the
+        //         slot's command header or table.
+        unsafe {
+            self.command_tables
+                .as_ptr()
+                .as_ptr()
+                .cast::<CommandTable>()
+                .add(slot)
+                .write_volatile(CommandTable::new(fis, prdt_entry));
+            self.command_list.as_ptr().as_ptr().cast::<Command>().add(slot).write_volatile(Command::new(
+                table_address,
+                u16::from(prdt_entry.is_some()),
+                write,
+            ));
+        }
+
+        self.slots.with(|slots| {
+            // The command is recorded as issued while holding the lock, so servicing can't miss its completion.
+            slots.lock().issued.set_bit(slot, true);
+            self.port.issue_command_slot(slot, fis.is_queued());
+        });
+    }
+
+    /// Waits for the command in `slot` to complete. If it doesn't complete in time, every outstanding command is
+    /// failed, so none is using its slot's memory once this returns.
+    ///
+    /// With `interrupts`, the waiter is woken by the controller's interrupt handler servicing the port. Otherwise, the
+    /// port is serviced each time the waiter is polled.
+    async fn wait(&self, slot: usize, interrupts: bool) -> Result<()> {
+        let clock = &crate::time::SYSTEM_CLOCK;
+        let timeout_ticks = u64::from(Self::COMMAND_TIMEOUT_US) * (clock.frequency() / 1_000_000);
+        let mut elapsed_ticks = 0;
+        let mut last_tick = clock.get_timestamp();
+
+        let completion = core::future::poll_fn(|context| {
+            if !interrupts {
+                self.service();
+            }
+
+            let completion = self.slots.with(|slots| {
+                let mut slots = slots.lock();
+                let completion = (!slots.issued.get_bit(slot)).then(|| slots.failed[slot]);
+
+                // The waker is stored while holding the lock, so the completion can't be serviced before it is.
+                if completion.is_none() {
+                    slots.wakers[slot] = Some(context.waker().clone());
+                }
+
+                completion
+            });
+
+            // The clock's counter wraps, so the time elapsed is accumulated between each poll.
+            let tick = clock.get_timestamp();
+            elapsed_ticks += tick.wrapping_sub(last_tick) & clock.max_timestamp();
+            last_tick = tick;
+
+            match completion {
+                Some(completion) => Poll::Ready(Some(completion)),
+                None if elapsed_ticks >= timeout_ticks => Poll::Ready(None),
+                None => {
+                    // Without interrupts, nothing else will service the port, so the waiter is polled again.
+                    if !interrupts {
+                        context.waker().wake_by_ref();
+                    }
+
+                    Poll::Pending
+                }
+            }
+        })
+        .await;
+
+        match completion {
+            Some(None) => Ok(()),
+            Some(Some(task_file)) => Err(Error::CommandFailed { task_file }),
+            None => {
+                self.slots.with(|slots| self.fail_outstanding(&mut slots.lock()));
+
+                Err(Error::Timeout)
+            }
+        }
+    }
+
+    /// Executes a non-queued command, which transfers `len` bytes from the device, and calls `func` with them.
+    async fn execute<T>(&self, interrupts: bool, fis: Hw2Dev, len: usize, func: impl FnOnce(&[u8]) -> T) -> Result<T> {
+        let _exclusive = self.issue_mode.write();
+
+        let slot = self.acquire_slot(interrupts)?;
+        self.issue(slot, fis, len, false);
+
+        // Safety: The command has completed, so the HBA is no longer writing to the buffer.
+        let result = self.wait(slot, interrupts).await.map(|()| func(unsafe { &self.slot_buffer(slot)[..len] }));
+        self.release_slot(slot);
+
+        result
+    }
+
+    async fn identify(&self, interrupts: bool) -> Result<Identity> {
+        self.execute(interrupts, Hw2Dev::identify(), 512, Identity::parse).await
+    }
+
+    /// Splits a transfer of `len` bytes starting at `lba` into commands which fit the slots' buffers, issuing as many
+    /// at once as the queue depth allows. `before` is called with each command's buffer and the range of bytes it
+    /// covers before it's issued, and `after` once it has completed.
+    async fn transfer(
+        &self,
+        interrupts: bool,
+        lba: u64,
+        len: usize,
+        write: bool,
+        mut before: impl FnMut(&mut [u8], Range<usize>),
+        mut after: impl FnMut(&[u8], Range<usize>),
+    ) -> Result<()> {
+        let sector_size = self.sector_size();
+        if len % sector_size != 0 {
+            return Err(Error::UnalignedBuffer { len });
+        }
+
+        let count = u64::try_from(len / sector_size).unwrap();
+        if lba.checked_add(count).map_or(true, |end_lba| end_lba > self.sector_count()) {
+            return Err(Error::OutOfBounds { lba, count });
+        }
+
+        // Queued commands may be outstanding alongside each other, but not alongside any other command.
+        let _shared = self.queued.then(|| self.issue_mode.read());
+        let _exclusive = (!self.queued).then(|| self.issue_mode.write());
+
+        let transfer_size = (self.slot_buffer_len() / sector_size) * sector_size;
+        let mut in_flight = VecDeque::with_capacity(self.queue_depth);
+        let mut result = Ok(());
+
+        for start in (0..len).step_by(transfer_size) {
+            if in_flight.len() == self.queue_depth {
+                let (slot, range) = in_flight.pop_front().unwrap();
+                result = self.complete(interrupts, slot, range, &mut after).await;
+                if result.is_err() {
+                    break;
+                }
+            }
+
+            let slot = match self.acquire_slot(interrupts) {
+                Ok(slot) => slot,
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            };
+
+            let range = start..(start + transfer_size).min(len);
+            let sector = lba + u64::try_from(start / sector_size).unwrap();
+            let sector_count = u16::try_from(range.len() / sector_size).unwrap();
+
+            // Safety: The slot was only just allocated, so no command is using its buffer.
+            before(unsafe { &mut self.slot_buffer(slot)[..range.len()] }, range.clone());
+
+            let fis = match (self.queued, write) {
+                (true, false) => Hw2Dev::read_fpdma_queued(sector, sector_count, slot),
+                (true, true) => Hw2Dev::write_fpdma_queued(sector, sector_count, slot),
+                (false, false) => Hw2Dev::read_dma(sector, sector_count),
+                (false, true) => Hw2Dev::write_dma(sector, sector_count),
+            };
+            self.issue(slot, fis, range.len(), write);
+            in_flight.push_back((slot, range));
+        }
+
+        // Every issued command is waited on, even after an error, so none is using its slot once it's released.
+        for (slot, range) in in_flight {
+            result = result.and(self.complete(interrupts, slot, range, &mut after).await);
+        }
+
+        result
+    }
+
+    /// Waits for the transfer command in `slot` to complete, calling `after` with the bytes it covered if it
+    /// succeeded, and releases the slot.
+    async fn complete(
+        &self,
+        interrupts: bool,
+        slot: usize,
+        range: Range<usize>,
+        after: &mut impl FnMut(&[u8], Range<usize>),
+    ) -> Result<()> {
+        let completed = self.wait(slot, interrupts).await;
+        if completed.is_ok() {
+            // Safety: The command has completed, so the HBA is no longer accessing the buffer.
+            after(unsafe { &self.slot_buffer(slot)[..range.len()] }, range);
+        }
+        self.release_slot(slot);
+
+        completed
+    }
+
+    /// Reads the sectors starting at `lba` into `buffer`, whose length must be a multiple of the sector size.
+    pub(super) async fn read(&self, interrupts: bool, lba: u64, buffer: &mut [u8]) -> Result<()> {
+        let len = buffer.len();
+        self.transfer(
+            interrupts,
+            lba,
+            len,
+            false,
+            |_, _| {},
+            |transferred, range| buffer[range].copy_from_slice(transferred),
+        )
+        .await
+    }
+
+    /// Writes `buffer`, whose length must be a multiple of the sector size, to the sectors starting at `lba`.
+    pub(super) async fn write(&self, interrupts: bool, lba: u64, buffer: &[u8]) -> Result<()> {
+        self.transfer(
+            interrupts,
+            lba,
+            buffer.len(),
+            true,
+            |slot_buffer, range| slot_buffer.copy_from_slice(&buffer[range]),
+            |_, _| {},
+        )
+        .await
+    }
+
+    /// Commits any cached writes to non-volatile media.
+    pub(super) async fn flush(&self, interrupts: bool) -> Result<()> {
+        self.execute(interrupts, Hw2Dev::flush_cache(), 0, |_| ()).await
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        // Ensure the HBA stops accessing the command list and tables before they're freed.
+        self.port.set_interrupt_enable(InterruptStatus::empty());
+        self.port.stop();
+    }
+}
+
+impl fmt::Debug for Device {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("AHCI Device")
+            .field("Port", &self.port_index)
+            .field("Model", &self.model())
+            .field("Serial", &self.serial())
+            .field("Sector Size", &self.sector_size())
+            .field("Sector Count", &self.sector_count())
+            .field("Queue Depth", &self.queue_depth())
+            .finish()
+    }
+}
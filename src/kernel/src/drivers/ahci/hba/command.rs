@@ -1,6 +1,9 @@
-use libsys::bitfield_getter;
+use super::fis::Hw2Dev;
+use bit_field::BitField;
 
+/// Physical region descriptor table entry, which describes a region of memory a command transfers data to or from.
 #[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct PRDTEntry {
     db_addr_lower: u32,
     db_addr_upper: u32,
@@ -9,109 +12,87 @@ pub struct PRDTEntry {
 }
 
 impl PRDTEntry {
-    bitfield_getter!(bits, u32, byte_count, 0..22);
-    bitfield_getter!(bits, interrupt_on_completion, 31);
-
-    pub fn set_db_addr(&mut self, addr: libsys::Address<libsys::Virtual>) {
-        let addr_usize = addr.as_usize();
-
-        self.db_addr_lower = addr_usize as u32;
-        self.db_addr_upper = (addr_usize >> 32) as u32;
-    }
-
-    pub fn set_sector_count(&mut self, sector_count: u32) {
-        self.set_byte_count(
-            (sector_count << 9) - 1, /* 512-byte alignment per sector */
-        );
-    }
-
-    pub fn clear(&mut self) {
-        self.db_addr_lower = 0;
-        self.db_addr_upper = 0;
-        self.bits = 0;
+    /// Largest region a single entry can describe.
+    pub const MAX_BYTE_COUNT: usize = 1 << 22;
+
+    /// Describes the `len` bytes at the physical address `address`, which must be word-aligned.
+    pub fn new(address: u64, len: usize) -> Self {
+        assert!(address.trailing_zeros() >= 1, "Data base address must be word-aligned.");
+        assert!(len > 0 && len <= Self::MAX_BYTE_COUNT && (len % 2) == 0, "Byte count must be an even 1..=4MiB.");
+
+        Self {
+            db_addr_lower: u32::try_from(address.get_bits(0..32)).unwrap(),
+            db_addr_upper: u32::try_from(address.get_bits(32..64)).unwrap(),
+            rsvd0: 0,
+            // Byte count is 0-based; interrupts are signalled by the device's FIS instead of on each descriptor.
+            bits: u32::try_from(len - 1).unwrap(),
+        }
     }
 }
 
-pub trait CommandFIS {}
-
+/// An entry in a port's command list, which points the HBA to the command table of its slot.
 #[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Command {
     bits: u16,
     prdt_len: u16,
     prdb_count: u32,
     cmd_tbl_addr_lower: u32,
     cmd_tbl_addr_upper: u32,
-    rsvd0: [u8; 4],
+    rsvd0: [u32; 4],
 }
 
-impl Command {
-    const COMMAND_FIS_OFFSET: usize = 0x0;
-    const ATAPI_COMMAND_OFFSET: usize = 0x40;
-    const PRDT_ENTRIES_OFFSET: usize = 0x80;
-
-    bitfield_getter!(bits, u16, fis_len, 0..5);
-    bitfield_getter!(bits, atapi, 5);
-    bitfield_getter!(bits, write, 6);
-    bitfield_getter!(bits, prefetchable, 7);
-    bitfield_getter!(bits, reset, 8);
-    bitfield_getter!(bits, bist, 9);
-    bitfield_getter!(bits, clear_busy_on_rok, 10);
-    bitfield_getter!(bits, u16, port_multiplier, 12..16);
-
-    const fn total_command_alloc(prdt_len: u16) -> usize {
-        128 /* size of command table in bytes */ + (core::mem::size_of::<PRDTEntry>() * (prdt_len as usize))
-    }
-
-    fn command_table_ptr_mut(&mut self) -> *mut u8 {
-        ((self.cmd_tbl_addr_lower as usize) | ((self.cmd_tbl_addr_upper as usize) << 32)) as *mut u8
-    }
+const _: () = assert!(core::mem::size_of::<Command>() == 0x20);
 
-    pub fn reset<F: CommandFIS + Sized>(&mut self, fis: F) {
-        let cmd_tbl_ptr = unsafe {
-            alloc::alloc::alloc_zeroed(alloc::alloc::Layout::from_size_align_unchecked(
-                Self::total_command_alloc(prdt_len),
-                1,
-            ))
-        };
-
-        self.bits = (core::mem::size_of::<F>() / core::mem::size_of::<u32>()) as u16;
-        self.prdt_len = prdt_len;
-        self.prdb_count = 0;
-        self.cmd_tbl_addr_lower = (cmd_tbl_ptr as usize) as u32;
-        self.cmd_tbl_addr_upper = ((cmd_tbl_ptr as usize) >> 32) as u32;
-
-        *self.command_fis() = fis;
-        self.prdt_entries().iter_mut().for_each(|prdt| prdt.clear());
-    }
-
-    pub fn command_fis<F: CommandFIS + Sized>(&mut self) -> &mut F {
-        unsafe {
-            (self.command_table_ptr_mut().add(Self::COMMAND_FIS_OFFSET) as *mut F)
-                .as_mut()
-                .unwrap()
+impl Command {
+    /// Creates a command header for the command table at the physical address `table` (which must be 128-byte
+    /// aligned), which holds `prdt_len` descriptors. `write` indicates data is transferred to the device.
+    pub fn new(table: u64, prdt_len: u16, write: bool) -> Self {
+        assert!(table.trailing_zeros() >= 7, "Command table must be 128-byte aligned.");
+
+        let mut bits = 0u16;
+        // Length of the command FIS, in dwords.
+        bits.set_bits(0..5, u16::try_from(core::mem::size_of::<Hw2Dev>() / core::mem::size_of::<u32>()).unwrap());
+        bits.set_bit(6, write);
+
+        Self {
+            bits,
+            prdt_len,
+            prdb_count: 0,
+            cmd_tbl_addr_lower: u32::try_from(table.get_bits(0..32)).unwrap(),
+            cmd_tbl_addr_upper: u32::try_from(table.get_bits(32..64)).unwrap(),
+            rsvd0: [0; 4],
         }
     }
 
-    pub fn prdt_entries(&mut self) -> &mut [PRDTEntry] {
-        unsafe {
-            core::slice::from_raw_parts_mut(
-                self.command_table_ptr_mut().add(Self::PRDT_ENTRIES_OFFSET) as *mut PRDTEntry,
-                self.prdt_len as usize,
-            )
-        }
+    /// Number of bytes transferred by the command (only updated by the HBA for non-queued commands).
+    pub fn prdb_count(&self) -> u32 {
+        self.prdb_count
     }
 }
 
-impl Drop for Command {
-    fn drop(&mut self) {
-        unsafe {
-            alloc::alloc::dealloc(
-                self.command_table_ptr_mut(),
-                alloc::alloc::Layout::from_size_align_unchecked(
-                    Self::total_command_alloc(self.prdt_len),
-                    1,
-                ),
-            );
+/// The command FIS, and descriptors of memory to transfer, of a single command slot.
+#[repr(C, align(128))]
+pub struct CommandTable {
+    command_fis: Hw2Dev,
+    _command_fis_rsvd: [u8; 0x40 - core::mem::size_of::<Hw2Dev>()],
+    atapi_command: [u8; 0x10],
+    _rsvd0: [u8; 0x30],
+    prdt_entries: [PRDTEntry; Self::PRDT_LEN],
+}
+
+impl CommandTable {
+    /// Number of descriptors in each table. Transfers are made through physically contiguous buffers, so only one
+    /// descriptor is needed.
+    pub const PRDT_LEN: usize = 1;
+
+    pub fn new(command_fis: Hw2Dev, prdt_entry: Option<PRDTEntry>) -> Self {
+        Self {
+            command_fis,
+            _command_fis_rsvd: [0; 0x40 - core::mem::size_of::<Hw2Dev>()],
+            atapi_command: [0; 0x10],
+            _rsvd0: [0; 0x30],
+            prdt_entries: [prdt_entry.unwrap_or_default(); Self::PRDT_LEN],
         }
     }
 }
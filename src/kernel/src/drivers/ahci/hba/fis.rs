@@ -1,7 +1,9 @@
 use crate::drivers::ahci::CommandType;
+use bit_field::BitField;
 
 #[allow(non_camel_case_types)]
 #[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Type {
     None = 0x0,
     Hw2Dev = 0x27,
@@ -14,7 +16,9 @@ pub enum Type {
     DEV_BITS = 0xA1,
 }
 
+/// Register FIS (host to device), which carries an ATA command.
 #[repr(C)]
+#[derive(Debug, Clone, Copy)]
 pub struct Hw2Dev {
     ty: Type,
     bits1: u8,
@@ -34,45 +38,111 @@ pub struct Hw2Dev {
     rsvd0: [u8; 4],
 }
 
+const _: () = assert!(core::mem::size_of::<Hw2Dev>() == 20);
+
 impl Hw2Dev {
-    libsys::bitfield_getter!(bits1, u8, port_multiplier, 0..4);
-    libsys::bitfield_getter!(bits1, command_control, 7);
+    /// Device register value selecting LBA addressing.
+    const DEVICE_LBA: u8 = 1 << 6;
 
-    pub const fn read(sector_base: usize, sector_count: u16) -> Self {
+    const fn new(command: CommandType) -> Self {
         Self {
             ty: Type::Hw2Dev,
+            // The FIS carries a command, rather than an update to the device control register.
             bits1: 1 << 7,
-            command: CommandType::ReadDMA,
+            command,
             feature_low: 0,
-            lba0: (sector_base >> 0) as u8,
-            lba1: (sector_base >> 8) as u8,
-            lba2: (sector_base >> 16) as u8,
-            device: 1 << 6,
-            lba3: (sector_base >> 24) as u8,
-            lba4: (sector_base >> 32) as u8,
-            lba5: (sector_base >> 40) as u8,
+            lba0: 0,
+            lba1: 0,
+            lba2: 0,
+            device: 0,
+            lba3: 0,
+            lba4: 0,
+            lba5: 0,
             feature_high: 0,
-            count: sector_count,
+            count: 0,
             iso_cmd_compl: 0,
             control: 0,
             rsvd0: [0u8; 4],
         }
     }
 
-    pub fn set_sector_base(&mut self, sector: usize) {
-        assert_eq!(sector & 0xFFFFFFFFFFFF, 0, "`sector` is a 48");
+    pub const fn identify() -> Self {
+        Self::new(CommandType::IdentifyDevice)
+    }
+
+    /// Reads `sector_count` sectors from `sector_base` using 48-bit DMA.
+    pub fn read_dma(sector_base: u64, sector_count: u16) -> Self {
+        let mut fis = Self::new(CommandType::ReadDMAExt);
+        fis.device = Self::DEVICE_LBA;
+        fis.set_sector_base(sector_base);
+        fis.count = sector_count;
 
-        self.lba0 = (sector >> 0) as u8;
-        self.lba1 = (sector >> 8) as u8;
-        self.lba2 = (sector >> 16) as u8;
-        self.lba3 = (sector >> 24) as u8;
-        self.lba4 = (sector >> 32) as u8;
-        self.lba5 = (sector >> 40) as u8;
+        fis
     }
 
-    pub fn set_sector_count(&mut self, sector_count: u16) {
-        self.count = sector_count;
+    /// Writes `sector_count` sectors to `sector_base` using 48-bit DMA.
+    pub fn write_dma(sector_base: u64, sector_count: u16) -> Self {
+        let mut fis = Self::new(CommandType::WriteDMAExt);
+        fis.device = Self::DEVICE_LBA;
+        fis.set_sector_base(sector_base);
+        fis.count = sector_count;
+
+        fis
     }
-}
 
-impl super::super::hba::CommandFIS for Hw2Dev {}
+    /// Native queued read of `sector_count` sectors from `sector_base`, tagged with the command slot `tag`.
+    pub fn read_fpdma_queued(sector_base: u64, sector_count: u16, tag: usize) -> Self {
+        let mut fis = Self::new(CommandType::ReadFPDMAQueued);
+        fis.set_queued(sector_base, sector_count, tag);
+
+        fis
+    }
+
+    /// Native queued write of `sector_count` sectors to `sector_base`, tagged with the command slot `tag`.
+    pub fn write_fpdma_queued(sector_base: u64, sector_count: u16, tag: usize) -> Self {
+        let mut fis = Self::new(CommandType::WriteFPDMAQueued);
+        fis.set_queued(sector_base, sector_count, tag);
+
+        fis
+    }
+
+    pub const fn flush_cache() -> Self {
+        let mut fis = Self::new(CommandType::FlushCacheExt);
+        fis.device = Self::DEVICE_LBA;
+
+        fis
+    }
+
+    fn set_queued(&mut self, sector_base: u64, sector_count: u16, tag: usize) {
+        assert!(tag < 32, "Queued command tag must be between 0..32");
+
+        // Queued commands carry their sector count in the features register, and their tag in the count register.
+        let [feature_low, feature_high] = sector_count.to_le_bytes();
+        self.feature_low = feature_low;
+        self.feature_high = feature_high;
+        self.count = u16::try_from(tag << 3).unwrap();
+        self.device = Self::DEVICE_LBA;
+        self.set_sector_base(sector_base);
+    }
+
+    pub fn set_sector_base(&mut self, sector: u64) {
+        assert_eq!(sector & !0xFFFF_FFFF_FFFF, 0, "`sector` must be a 48-bit address");
+
+        let [lba0, lba1, lba2, lba3, lba4, lba5, _, _] = sector.to_le_bytes();
+        self.lba0 = lba0;
+        self.lba1 = lba1;
+        self.lba2 = lba2;
+        self.lba3 = lba3;
+        self.lba4 = lba4;
+        self.lba5 = lba5;
+    }
+
+    /// Whether the FIS carries a native queued command.
+    pub fn is_queued(&self) -> bool {
+        matches!(self.command, CommandType::ReadFPDMAQueued | CommandType::WriteFPDMAQueued)
+    }
+
+    pub fn port_multiplier(&self) -> u8 {
+        self.bits1.get_bits(0..4)
+    }
+}
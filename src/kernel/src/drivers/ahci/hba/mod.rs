@@ -5,48 +5,173 @@ mod port;
 pub use command::*;
 pub use port::*;
 
-use libsys::{memory::VolatileCell, ReadOnly};
+use bit_field::BitField;
+use core::fmt;
+use libkernel::{mem::VolatileCell, ReadOnly, ReadWrite};
 
+#[repr(transparent)]
+pub struct HostCapabilities(VolatileCell<u32, ReadOnly>);
+
+/// HBA Capabilities (CAP)
+/// An explanation of these values can be found at:
+///     https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/serial-ata-ahci-spec-rev1-3-1.pdf
+///     Section 3.1.1
+impl HostCapabilities {
+    /// Number of ports the HBA supports (which may be more than are implemented).
+    pub fn get_np(&self) -> usize {
+        usize::try_from(self.0.read().get_bits(0..5)).unwrap() + 1
+    }
+
+    /// Number of command slots each port supports.
+    pub fn get_ncs(&self) -> usize {
+        usize::try_from(self.0.read().get_bits(8..13)).unwrap() + 1
+    }
+
+    pub fn get_sclo(&self) -> bool {
+        self.0.read().get_bit(24)
+    }
+
+    pub fn get_sss(&self) -> bool {
+        self.0.read().get_bit(27)
+    }
+
+    pub fn get_sncq(&self) -> bool {
+        self.0.read().get_bit(30)
+    }
+
+    pub fn get_s64a(&self) -> bool {
+        self.0.read().get_bit(31)
+    }
+}
+
+impl fmt::Debug for HostCapabilities {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("HBA Capabilities")
+            .field("NP", &self.get_np())
+            .field("NCS", &self.get_ncs())
+            .field("SCLO", &self.get_sclo())
+            .field("SSS", &self.get_sss())
+            .field("SNCQ", &self.get_sncq())
+            .field("S64A", &self.get_s64a())
+            .finish()
+    }
+}
+
+#[repr(transparent)]
+pub struct GlobalHostControl(VolatileCell<u32, ReadWrite>);
+
+impl GlobalHostControl {
+    fn set_bit(&self, bit: usize, set: bool) {
+        self.0.write(*self.0.read().set_bit(bit, set));
+    }
+
+    pub fn get_hr(&self) -> bool {
+        self.0.read().get_bit(0)
+    }
+
+    /// Resets the HBA. The bit is cleared by the HBA once the reset completes.
+    pub fn set_hr(&self) {
+        self.set_bit(0, true);
+    }
+
+    pub fn get_ie(&self) -> bool {
+        self.0.read().get_bit(1)
+    }
+
+    pub fn set_ie(&self, enable: bool) {
+        self.set_bit(1, enable);
+    }
+
+    pub fn get_ae(&self) -> bool {
+        self.0.read().get_bit(31)
+    }
+
+    pub fn set_ae(&self, enable: bool) {
+        self.set_bit(31, enable);
+    }
+}
+
+/// The HBA's memory registers (ABAR), which are followed by those of each port.
 #[repr(C)]
 pub struct Memory {
-    host_capability: VolatileCell<u32, ReadOnly>,
-    global_host_control: VolatileCell<u32, ReadOnly>,
-    interrupt_status: VolatileCell<u32, ReadOnly>,
+    host_capability: HostCapabilities,
+    global_host_control: GlobalHostControl,
+    interrupt_status: VolatileCell<u32, ReadWrite>,
     ports_implemented: VolatileCell<u32, ReadOnly>,
     version: VolatileCell<u32, ReadOnly>,
-    ccc_control: VolatileCell<u32, ReadOnly>,
-    ccc_ports: VolatileCell<u32, ReadOnly>,
+    ccc_control: VolatileCell<u32, ReadWrite>,
+    ccc_ports: VolatileCell<u32, ReadWrite>,
     enclosure_management_location: VolatileCell<u32, ReadOnly>,
-    enclosure_management_control: VolatileCell<u32, ReadOnly>,
+    enclosure_management_control: VolatileCell<u32, ReadWrite>,
     host_capabilities_extended: VolatileCell<u32, ReadOnly>,
-    bios_handoff_control_status: VolatileCell<u32, ReadOnly>,
+    bios_handoff_control_status: VolatileCell<u32, ReadWrite>,
     _reserved0: [u8; 0x74],
     _vendor0: [u8; 0x60],
     ports: [Port; 32],
 }
 
 impl Memory {
-    fn ports_implemented(&self) -> usize {
-        let mut bits = 0;
-        let mut bit = 1;
+    pub fn capabilities(&self) -> &HostCapabilities {
+        &self.host_capability
+    }
+
+    pub fn global_host_control(&self) -> &GlobalHostControl {
+        &self.global_host_control
+    }
 
-        let ports_impletemented = self.ports_implemented.read();
-        while (ports_impletemented & bit) > 0 {
-            bits += 1;
-            bit <<= 1;
-        }
+    /// Bitmask of the ports with pending interrupts.
+    pub fn interrupt_status(&self) -> u32 {
+        self.interrupt_status.read()
+    }
+
+    /// Clears the pending interrupts of the ports in `ports` (which are write-1-to-clear).
+    pub fn clear_interrupt_status(&self, ports: u32) {
+        self.interrupt_status.write(ports);
+    }
 
-        bits
+    /// Bitmask of the ports the HBA exposes. Implemented ports needn't be contiguous.
+    pub fn ports_implemented(&self) -> u32 {
+        self.ports_implemented.read()
     }
 
-    pub fn ports(&self) -> core::slice::Iter<Port> {
-        self.ports[0..self.ports_implemented()].iter()
+    /// AHCI version, as its major and minor numbers.
+    pub fn version(&self) -> (u16, u16) {
+        let version = self.version.read();
+
+        (u16::try_from(version.get_bits(16..32)).unwrap(), u16::try_from(version.get_bits(0..16)).unwrap())
     }
 
-    pub fn ports_mut(&mut self) -> core::slice::IterMut<Port> {
+    /// Every implemented port, along with its index.
+    pub fn ports(&self) -> impl Iterator<Item = (usize, &Port)> {
         let ports_implemented = self.ports_implemented();
-        self.ports[0..ports_implemented].iter_mut()
+
+        self.ports.iter().enumerate().filter(move |(index, _)| ports_implemented.get_bit(*index))
+    }
+
+    /// Resets the HBA, waiting up to 1 second for the reset to complete. Returns whether the reset completed.
+    pub fn reset(&self) -> bool {
+        const TIMEOUT_MS: u32 = 1000;
+
+        // Software must set AHCI enable before any other register is accessed, including the reset bit.
+        self.global_host_control.set_ae(true);
+        self.global_host_control.set_hr();
+
+        let reset = wait_ms(TIMEOUT_MS, || !self.global_host_control.get_hr());
+        // The reset clears AHCI enable.
+        self.global_host_control.set_ae(true);
+
+        reset
     }
 }
 
-impl libsys::memory::Volatile for Memory {}
+impl fmt::Debug for Memory {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("HBA Memory")
+            .field("Capabilities", &self.host_capability)
+            .field("Version", &self.version())
+            .field("Ports Implemented", &format_args!("{:#b}", self.ports_implemented()))
+            .finish_non_exhaustive()
+    }
+}
@@ -1,135 +1,150 @@
 use bit_field::BitField;
-use core::convert::TryFrom;
-use libsys::{
-    memory::{Volatile, VolatileCell, VolatileSplitPtr},
-    volatile_bitfield_getter, volatile_bitfield_getter_ro, Address, ReadOnly, ReadWrite,
-};
+use core::{convert::TryFrom, fmt, ops::Range};
+use libkernel::{mem::VolatileCell, ReadOnly, ReadWrite};
 use num_enum::TryFromPrimitive;
 
-// INVARIANT: All memory accesses through this struct are volatile.
 #[repr(transparent)]
-pub struct CommandStatus {
-    bits: VolatileCell<u32, ReadWrite>,
-}
+pub struct CommandStatus(VolatileCell<u32, ReadWrite>);
 
+/// Port x Command and Status (PxCMD)
+/// An explanation of these values can be found at:
+///     https://www.intel.com/content/dam/www/public/us/en/documents/technical-specifications/serial-ata-ahci-spec-rev1-3-1.pdf
+///     Section 3.3.7
 impl CommandStatus {
-    volatile_bitfield_getter!(bits, st, 0);
-    // SUD - Check CAP.SSS is 1 or 0 for RW or RO
+    fn set_bit(&self, bit: usize, set: bool) {
+        self.0.write(*self.0.read().set_bit(bit, set));
+    }
 
-    volatile_bitfield_getter_ro!(bits, pod, 2);
-    pub fn set_pod(&mut self, set: bool) -> Result<(), ()> {
-        if self.get_cpd() {
-            self.bits.write(*(self.bits.read().set_bit(2, set)));
+    pub fn get_st(&self) -> bool {
+        self.0.read().get_bit(0)
+    }
+
+    pub fn set_st(&self, start: bool) {
+        self.set_bit(0, start);
+    }
 
-            Ok(())
-        } else {
-            Err(())
+    pub fn get_sud(&self) -> bool {
+        self.0.read().get_bit(1)
+    }
+
+    /// REMARK: Spin-up is only software-controlled when `CAP.SSS` is set.
+    pub fn set_sud(&self, spin_up: bool) {
+        self.set_bit(1, spin_up);
+    }
+
+    pub fn get_pod(&self) -> bool {
+        self.0.read().get_bit(2)
+    }
+
+    /// REMARK: Power is only software-controlled when `PxCMD.CPD` is set.
+    pub fn set_pod(&self, power_on: bool) {
+        if self.get_cpd() {
+            self.set_bit(2, power_on);
         }
     }
 
-    volatile_bitfield_getter!(bits, clo, 3);
-    volatile_bitfield_getter!(bits, fre, 4);
-    volatile_bitfield_getter_ro!(bits, u32, ccs, 8..13);
-    volatile_bitfield_getter_ro!(bits, mpss, 13);
-    volatile_bitfield_getter_ro!(bits, fr, 14);
-    volatile_bitfield_getter_ro!(bits, cr, 15);
-    volatile_bitfield_getter_ro!(bits, cps, 16);
-    // PMA - check CAP.SPM = 1 or 0 for RW or RO
-    volatile_bitfield_getter_ro!(bits, hpcp, 18);
-    volatile_bitfield_getter_ro!(bits, mpsp, 19);
-    volatile_bitfield_getter_ro!(bits, cpd, 20);
-    volatile_bitfield_getter_ro!(bits, esp, 21);
-    volatile_bitfield_getter_ro!(bits, fbscp, 22);
-    volatile_bitfield_getter!(bits, apste, 22);
-    volatile_bitfield_getter!(bits, atapi, 24);
-    volatile_bitfield_getter!(bits, dlae, 25);
-    // ALPE - Check CAP.SALP is 1 or 0 for RW or Reserved
-    // ASP - Check CAP.SALP is 1 or 0 for RW or Reserved
-    volatile_bitfield_getter!(bits, u32, icc, 28..32);
-}
+    pub fn get_clo(&self) -> bool {
+        self.0.read().get_bit(3)
+    }
+
+    pub fn set_clo(&self) {
+        self.set_bit(3, true);
+    }
+
+    pub fn get_fre(&self) -> bool {
+        self.0.read().get_bit(4)
+    }
+
+    pub fn set_fre(&self, enable: bool) {
+        self.set_bit(4, enable);
+    }
 
-impl Volatile for CommandStatus {}
+    pub fn get_ccs(&self) -> u32 {
+        self.0.read().get_bits(8..13)
+    }
+
+    pub fn get_fr(&self) -> bool {
+        self.0.read().get_bit(14)
+    }
 
-impl core::fmt::Debug for CommandStatus {
-    fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    pub fn get_cr(&self) -> bool {
+        self.0.read().get_bit(15)
+    }
+
+    pub fn get_cpd(&self) -> bool {
+        self.0.read().get_bit(20)
+    }
+
+    pub fn get_atapi(&self) -> bool {
+        self.0.read().get_bit(24)
+    }
+
+    pub fn get_icc(&self) -> u32 {
+        self.0.read().get_bits(28..32)
+    }
+}
+
+impl fmt::Debug for CommandStatus {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
         formatter
-            .debug_struct("Command Status Register")
+            .debug_struct("Command Status")
             .field("ST", &self.get_st())
-            // .field("SUD", &self.sud())
+            .field("SUD", &self.get_sud())
+            .field("POD", &self.get_pod())
             .field("CLO", &self.get_clo())
             .field("FRE", &self.get_fre())
-            // .field("CCS", &self.ccs())
-            .field("MPSS", &self.get_mpss())
+            .field("CCS", &self.get_ccs())
             .field("FR", &self.get_fr())
             .field("CR", &self.get_cr())
-            .field("CPS", &self.get_cps())
-            // .field("PMA", &self.pma())
-            .field("HPCP", &self.get_hpcp())
-            .field("MPSP", &self.get_mpsp())
             .field("CPD", &self.get_cpd())
-            .field("ESP", &self.get_esp())
-            .field("APSTE", &self.get_apste())
             .field("ATAPI", &self.get_atapi())
-            .field("DLAE", &self.get_dlae())
-            // .field("ALPE", &self.alpe())
-            // .field("ASP", &self.asp())
-            // .field("ICC", &self.icc())
+            .field("ICC", &self.get_icc())
             .finish()
     }
 }
 
-#[repr(transparent)]
-pub struct InterruptStatus {
-    bits: VolatileCell<u32, ReadWrite>,
-}
-
-impl InterruptStatus {
-    volatile_bitfield_getter!(bits, dhrs, 0);
-    volatile_bitfield_getter!(bits, pss, 1);
-    volatile_bitfield_getter!(bits, dss, 2);
-    volatile_bitfield_getter!(bits, sdbs, 3);
-    volatile_bitfield_getter_ro!(bits, ufs, 4);
-    volatile_bitfield_getter!(bits, dps, 5);
-    volatile_bitfield_getter_ro!(bits, pcs, 6);
-    volatile_bitfield_getter!(bits, dmps, 7);
-    volatile_bitfield_getter_ro!(bits, prcs, 22);
-    volatile_bitfield_getter!(bits, ipms, 23);
-    volatile_bitfield_getter!(bits, ofs, 24);
-    volatile_bitfield_getter!(bits, infs, 26);
-    volatile_bitfield_getter!(bits, ifs, 27);
-    volatile_bitfield_getter!(bits, hbds, 28);
-    volatile_bitfield_getter!(bits, hbfs, 29);
-    volatile_bitfield_getter!(bits, tfes, 30);
-    volatile_bitfield_getter!(bits, cpds, 31);
-
-    pub fn clear(&self) {
-        self.bits.write(0);
-    }
-}
-
-impl Volatile for InterruptStatus {}
-
-impl core::fmt::Debug for InterruptStatus {
-    fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        formatter
-            .debug_struct("Interrupt Status")
-            .field("DHRS", &self.get_dhrs())
-            .field("PSS", &self.get_pss())
-            .field("DSS", &self.get_dss())
-            .field("UFS", &self.get_ufs())
-            .field("DPS", &self.get_dps())
-            .field("PCS", &self.get_pcs())
-            .field("DMPS", &self.get_dmps())
-            .field("PRCS", &self.get_prcs())
-            .field("IPMS", &self.get_ipms())
-            .field("OFS", &self.get_ofs())
-            .field("INFS", &self.get_infs())
-            .field("IFS", &self.get_ifs())
-            .field("HBDS", &self.get_hbds())
-            .field("HBFS", &self.get_hbfs())
-            .field("TFES", &self.get_tfes())
-            .field("CPDS", &self.get_cpds())
-            .finish()
+bitflags::bitflags! {
+    /// Port x Interrupt Status (PxIS), and the matching bits of Port x Interrupt Enable (PxIE).
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct InterruptStatus: u32 {
+        /// Device to host register FIS received.
+        const DHRS = 1 << 0;
+        /// PIO setup FIS received.
+        const PSS = 1 << 1;
+        /// DMA setup FIS received.
+        const DSS = 1 << 2;
+        /// Set device bits FIS received.
+        const SDBS = 1 << 3;
+        /// Unknown FIS received.
+        const UFS = 1 << 4;
+        /// Descriptor processed.
+        const DPS = 1 << 5;
+        /// Port connect change.
+        const PCS = 1 << 6;
+        /// Device mechanical presence change.
+        const DMPS = 1 << 7;
+        /// PhyRdy change.
+        const PRCS = 1 << 22;
+        /// Incorrect port multiplier.
+        const IPMS = 1 << 23;
+        /// Overflow.
+        const OFS = 1 << 24;
+        /// Interface non-fatal error.
+        const INFS = 1 << 26;
+        /// Interface fatal error.
+        const IFS = 1 << 27;
+        /// Host bus data error.
+        const HBDS = 1 << 28;
+        /// Host bus fatal error.
+        const HBFS = 1 << 29;
+        /// Task file error.
+        const TFES = 1 << 30;
+        /// Cold port detect.
+        const CPDS = 1 << 31;
+
+        /// Errors which stop the port from processing commands.
+        const FATAL_ERRORS = Self::TFES.bits() | Self::HBFS.bits() | Self::HBDS.bits() | Self::IFS.bits();
     }
 }
 
@@ -161,30 +176,29 @@ pub enum DeviceDetection {
     PhyOffline = 4,
 }
 
-// INVARIANT: All memory accesses through this struct are volatile.
 #[repr(transparent)]
-pub struct SATAStatus {
-    status: VolatileCell<u32, ReadWrite>,
-}
+pub struct SATAStatus(VolatileCell<u32, ReadOnly>);
 
 impl SATAStatus {
-    pub fn interface_pwm(&self) -> InterfacePowerManagement {
-        InterfacePowerManagement::try_from(self.status.read().get_bits(8..12)).unwrap()
+    fn get_field(&self, range: Range<usize>) -> u32 {
+        self.0.read().get_bits(range)
     }
 
-    pub fn interface_speed(&self) -> InterfaceSpeed {
-        InterfaceSpeed::try_from(self.status.read().get_bits(4..8)).unwrap()
+    pub fn interface_pwm(&self) -> Option<InterfacePowerManagement> {
+        InterfacePowerManagement::try_from(self.get_field(8..12)).ok()
     }
 
-    pub fn device_detection(&self) -> DeviceDetection {
-        DeviceDetection::try_from(self.status.read().get_bits(0..4)).unwrap()
+    pub fn interface_speed(&self) -> Option<InterfaceSpeed> {
+        InterfaceSpeed::try_from(self.get_field(4..8)).ok()
     }
-}
 
-impl Volatile for SATAStatus {}
+    pub fn device_detection(&self) -> Option<DeviceDetection> {
+        DeviceDetection::try_from(self.get_field(0..4)).ok()
+    }
+}
 
-impl core::fmt::Debug for SATAStatus {
-    fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+impl fmt::Debug for SATAStatus {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
         formatter
             .debug_struct("SATA Port Status")
             .field("Interface PWM", &self.interface_pwm())
@@ -195,48 +209,73 @@ impl core::fmt::Debug for SATAStatus {
 }
 
 #[repr(u32)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
 pub enum Class {
-    None = 0x0,
     SATA = 0x00000101,
     SEMB = 0xC33C0101,
     PM = 0x96690101,
     SATAPI = 0xEB140101,
 }
 
-// INVARIANT: All memory accesses through this struct are volatile.
+/// Status (low byte) and error (high byte) of the device's task file, as of the last FIS it sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskFile(u32);
+
+impl TaskFile {
+    pub const STATUS_ERR: u8 = 1 << 0;
+    pub const STATUS_DRQ: u8 = 1 << 3;
+    pub const STATUS_BSY: u8 = 1 << 7;
+
+    pub fn status(self) -> u8 {
+        u8::try_from(self.0.get_bits(0..8)).unwrap()
+    }
+
+    pub fn error(self) -> u8 {
+        u8::try_from(self.0.get_bits(8..16)).unwrap()
+    }
+
+    /// Whether the device is still busy with, or transferring data for, a command.
+    pub fn is_busy(self) -> bool {
+        (self.status() & (Self::STATUS_BSY | Self::STATUS_DRQ)) > 0
+    }
+}
+
+/// A port's registers, within the HBA's memory registers.
 #[repr(C)]
 pub struct Port {
-    cmd_list: VolatileSplitPtr<super::Command>,
-    fis_addr: VolatileSplitPtr<u8>,
-    interrupt_status: InterruptStatus,
-    interrupt_enable: VolatileCell<u32, ReadOnly>,
+    command_list_base: VolatileCell<u32, ReadWrite>,
+    command_list_base_upper: VolatileCell<u32, ReadWrite>,
+    fis_base: VolatileCell<u32, ReadWrite>,
+    fis_base_upper: VolatileCell<u32, ReadWrite>,
+    interrupt_status: VolatileCell<u32, ReadWrite>,
+    interrupt_enable: VolatileCell<u32, ReadWrite>,
     command_status: CommandStatus,
-    _reserved0: [u8; 4],
+    _reserved0: u32,
     task_file_data: VolatileCell<u32, ReadOnly>,
-    signature: VolatileCell<Class, ReadOnly>,
+    signature: VolatileCell<u32, ReadOnly>,
     sata_status: SATAStatus,
-    sata_control: VolatileCell<u32, ReadOnly>,
-    sata_error: VolatileCell<u32, ReadOnly>,
-    sata_active: VolatileCell<u32, ReadOnly>,
+    sata_control: VolatileCell<u32, ReadWrite>,
+    sata_error: VolatileCell<u32, ReadWrite>,
+    sata_active: VolatileCell<u32, ReadWrite>,
     command_issue: VolatileCell<u32, ReadWrite>,
-    sata_notification: VolatileCell<u32, ReadOnly>,
-    fis_switch_control: VolatileCell<u32, ReadOnly>,
-    _reserved1: [u8; 11],
-    _vendor0: [u8; 4],
+    sata_notification: VolatileCell<u32, ReadWrite>,
+    fis_switch_control: VolatileCell<u32, ReadWrite>,
+    device_sleep: VolatileCell<u32, ReadWrite>,
+    _reserved1: [u8; 0x28],
+    _vendor0: [u8; 0x10],
 }
 
+const _: () = assert!(core::mem::size_of::<Port>() == 0x80);
+
 impl Port {
-    pub fn class(&self) -> Class {
-        // Ensures port is in a valid state (deteced & powered).
-        if self.sata_status().device_detection() == DeviceDetection::DetectedAndPhy
-            && self.sata_status().interface_pwm() == InterfacePowerManagement::Active
-        {
-            self.signature.read()
-        } else {
-            // Finally, determine port type from its signature.
-            Class::None
-        }
+    pub fn class(&self) -> Option<Class> {
+        Class::try_from(self.signature.read()).ok()
+    }
+
+    /// Whether a device is present on the port, and communication with it is established.
+    pub fn is_connected(&self) -> bool {
+        self.sata_status.device_detection() == Some(DeviceDetection::DetectedAndPhy)
+            && self.sata_status.interface_pwm() == Some(InterfacePowerManagement::Active)
     }
 
     pub fn sata_status(&self) -> &SATAStatus {
@@ -247,152 +286,112 @@ impl Port {
         &self.command_status
     }
 
-    pub fn task_file_data(&self) -> u32 {
-        self.task_file_data.read()
+    pub fn task_file(&self) -> TaskFile {
+        TaskFile(self.task_file_data.read())
     }
 
-    pub fn command_list(&self) -> &mut [super::Command] {
-        unsafe { core::slice::from_raw_parts_mut(self.cmd_list.get_mut_ptr(), 32) }
+    /// Sets the physical addresses of the command list (1KiB aligned) and received FIS area (256-byte aligned).
+    /// The port must be stopped.
+    pub fn set_bases(&self, command_list: u64, fis: u64) {
+        assert!(!self.command_status.get_cr() && !self.command_status.get_fr(), "Port must be stopped.");
+        assert!(command_list.trailing_zeros() >= 10, "Command list must be 1KiB aligned.");
+        assert!(fis.trailing_zeros() >= 8, "Received FIS area must be 256-byte aligned.");
+
+        self.command_list_base.write(u32::try_from(command_list.get_bits(0..32)).unwrap());
+        self.command_list_base_upper.write(u32::try_from(command_list.get_bits(32..64)).unwrap());
+        self.fis_base.write(u32::try_from(fis.get_bits(0..32)).unwrap());
+        self.fis_base_upper.write(u32::try_from(fis.get_bits(32..64)).unwrap());
     }
 
-    pub fn interrupt_status(&self) -> &InterruptStatus {
-        &self.interrupt_status
+    pub fn interrupt_status(&self) -> InterruptStatus {
+        InterruptStatus::from_bits_retain(self.interrupt_status.read())
     }
 
-    pub fn issue_command_slot(&self, cmd_index: usize) {
-        assert!(cmd_index < 32, "Command index must be between 0..32");
-        assert!(
-            self.command_status().get_st(),
-            "CMD.ST bit must be set for a command to be issued"
-        );
-
-        self.command_issue
-            .write(self.command_issue.read() | (1 << cmd_index));
+    /// Clears the provided interrupt status bits (which are write-1-to-clear).
+    pub fn clear_interrupt_status(&self, status: InterruptStatus) {
+        self.interrupt_status.write(status.bits());
     }
 
-    pub fn check_command_slot(&self, cmd_index: usize) -> bool {
-        assert!(cmd_index < 32, "Command index must be between 0..32");
+    pub fn set_interrupt_enable(&self, enable: InterruptStatus) {
+        self.interrupt_enable.write(enable.bits());
+    }
 
-        self.command_issue.read().get_bit(cmd_index)
+    /// Clears every bit of the SATA error register (which are write-1-to-clear).
+    pub fn clear_sata_error(&self) {
+        self.sata_error.write(u32::MAX);
     }
 
-    pub fn start_cmd(&self) {
-        debug!("AHCI PORT: START CMD");
+    /// Bitmask of the native queued commands which the device hasn't yet completed.
+    pub fn sata_active(&self) -> u32 {
+        self.sata_active.read()
+    }
 
-        let cmd = self.command_status();
+    /// Bitmask of the command slots which the HBA hasn't yet finished processing.
+    pub fn command_issue(&self) -> u32 {
+        self.command_issue.read()
+    }
 
-        while cmd.get_cr() {}
+    /// Issues the command in `slot`. If `queued`, it's marked as a native queued command beforehand.
+    pub fn issue_command_slot(&self, slot: usize, queued: bool) {
+        assert!(slot < 32, "Command slot must be between 0..32");
+        assert!(self.command_status.get_st(), "PxCMD.ST must be set for a command to be issued");
 
-        cmd.set_fre(true);
-        cmd.set_st(true);
+        // Both registers only set the written bits, so other outstanding commands are unaffected.
+        if queued {
+            self.sata_active.write(1 << slot);
+        }
+        self.command_issue.write(1 << slot);
     }
 
-    pub fn stop_cmd(&self) {
-        debug!("AHCI PORT: STOP CMD");
+    /// Stops the port from processing commands and receiving FISes, waiting up to 500ms for each to stop.
+    /// Returns whether the port stopped.
+    pub fn stop(&self) -> bool {
+        const TIMEOUT_MS: u32 = 500;
 
-        let cmd = self.command_status();
+        let cmd = &self.command_status;
 
         cmd.set_st(false);
-        cmd.set_fre(false);
+        let stopped = wait_ms(TIMEOUT_MS, || !cmd.get_cr());
 
-        while cmd.get_fr() | cmd.get_cr() {}
-    }
-
-    pub fn configure(&self) {
-        self.stop_cmd();
-        debug!("AHCI PORT: CONFIGURING");
-
-        debug!("Allocting command and FIS lists.");
-
-        let cmd_list_byte_len = core::mem::size_of::<super::Command>() * 32;
-        let cmd_list_ptr = unsafe {
-            libsys::memory::malloc::get()
-                .alloc(cmd_list_byte_len, core::num::NonZeroUsize::new(128))
-                .unwrap()
-                .into_parts()
-                .0
-        };
-        debug!(
-            "\tCommand list base address: {:?}:{}",
-            cmd_list_ptr, cmd_list_byte_len
-        );
-
-        let fis_byte_len = 1024;
-        let fis_base = unsafe {
-            libsys::memory::malloc::get()
-                .alloc(cmd_list_byte_len, core::num::NonZeroUsize::new(128))
-                .unwrap()
-                .into_parts()
-                .0
-        };
-        debug!("\tFIS base address: {:fis_base?}:{fis_byte_len}");
-
-        unsafe {
-            debug!("Clearing command and FIS lists, and updating port metadata.");
-
-            core::ptr::write_bytes(cmd_list_ptr, 0, cmd_list_byte_len);
-            core::ptr::write_bytes(fis_base, 0, fis_byte_len);
-
-            self.cmd_list.set_ptr(cmd_list_ptr as *mut _);
-            self.fis_addr.set_ptr(fis_base as *mut _);
-        }
-
-        debug!("AHCI PORT: CONFIGURED");
-        self.start_cmd();
+        cmd.set_fre(false);
+        stopped && wait_ms(TIMEOUT_MS, || !cmd.get_fr())
     }
 
-    pub fn read(&self, sector_base: usize, sector_count: u16) -> alloc::vec::Vec<u8> {
-        use crate::drivers::ahci::{hba::fis, ATA_DEV_BUSY, ATA_DEV_DRQ};
+    /// Starts the port processing commands. The FIS receive area must already be enabled.
+    pub fn start(&self) {
+        let cmd = &self.command_status;
+        assert!(cmd.get_fre(), "PxCMD.FRE must be set before the port is started");
 
-        debug!("AHCI PORT: READ: RECEIVED");
-
-        const MAX_SPIN: usize = 1000000;
-
-        debug!("AHCI PORT: READ: BUSY WAIT");
-        let mut spin: usize = 0;
-        while (self.task_file_data() & ((ATA_DEV_BUSY | ATA_DEV_DRQ) as u32)) > 0 && spin < MAX_SPIN
-        {
-            spin += 1
+        while cmd.get_cr() {
+            core::hint::spin_loop();
         }
 
-        if spin >= MAX_SPIN {
-            panic!("failed to read from disk (busy)");
-        }
-
-        debug!("AHCI PORT: READ: CLR INT STATUS");
-        self.interrupt_status().clear(); // clear interrupts
-
-        debug!("AHCI PORT: READ: CFG COMMAND FIS (DIS_REG_H2D)");
-        let mut fis = super::fis::Hw2Dev::read(sector_base, sector_count);
-
-        debug!("AHCI PORT: READ: CFG COMMAND");
-        let command = &mut self.command_list()[0];
-        command.reset(1, fis);
-        command.set_write(false);
-
-        debug!("AHCI PORT: READ: CFG PRDT ENTRY");
-        let prdt_entry = &mut command.prdt_entries()[0];
-        let buffer = alloc::vec![0u8; (sector_count as usize) * 512];
-        prdt_entry.set_db_addr(Address::from_ptr(buffer.as_ptr()));
-        prdt_entry.set_sector_count(sector_count as u32);
-
-        debug!("AHCI PORT: READ: ISSUING COMMAND",);
-        self.issue_command_slot(0);
+        cmd.set_st(true);
+    }
+}
 
-        debug!("AHCI PORT: READ: READ EXECUTING: BUSY WAIT",);
-        while self.check_command_slot(0) {
-            if self.interrupt_status().get_tfes() {
-                panic!("read failed (HBA PxIS TFES");
+impl fmt::Debug for Port {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("AHCI Port")
+            .field("Class", &self.class())
+            .field("Interrupt Status", &self.interrupt_status())
+            .field("Command Status", &self.command_status)
+            .field("Task File", &self.task_file())
+            .field("SATA Status", &self.sata_status)
+            .finish_non_exhaustive()
+    }
+}
 
-                // TODO interrupt status register
-            }
+/// Polls `condition` every millisecond, for up to `timeout_ms`. Returns whether the condition was met.
+pub(in crate::drivers::ahci) fn wait_ms(timeout_ms: u32, mut condition: impl FnMut() -> bool) -> bool {
+    for _ in 0..timeout_ms {
+        if condition() {
+            return true;
         }
 
-        debug!("AHCI PORT: READ: COMPLETE");
-        buffer
+        crate::time::SYSTEM_CLOCK.spin_wait_us(1000);
     }
 
-    // pub fn write(&mut self, sector_base: usize, data: &[u8]) {}
+    condition()
 }
-
-impl libsys::memory::Volatile for Port {}
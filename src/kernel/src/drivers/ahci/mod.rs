@@ -1,86 +1,329 @@
+mod device;
 pub mod hba;
 
-use alloc::vec::Vec;
+pub use device::*;
+
+use crate::{
+    block,
+    interrupts::irq::{self, Interrupt},
+    mem::{alloc::pmm, io::pci, HHDM},
+};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use bit_field::BitField;
-use libsys::io::pci::{standard::StandardRegister, PCIeDevice, Standard};
-
-pub const ATA_DEV_BUSY: u8 = 0x80;
-pub const ATA_DEV_DRQ: u8 = 0x08;
-
-struct FrameInformation {}
-
-impl FrameInformation {
-    // Register FIS - host to device
-    const TYPE_REG_H2D: u16 = 0x27;
-    // Register FIS - device to host
-    const TYPE_REG_D2H: u16 = 0x34;
-    // DMA activate FIS - device to host
-    const TYPE_DMA_ACT: u16 = 0x39;
-    // DMA setup FIS - bidirectional
-    const TYPE_DMA_SETUP: u16 = 0x41;
-    // Data FIS - bidirectional
-    const TYPE_DATA: u16 = 0x46;
-    // BIST activate FIS - bidirectional
-    const TYPE_BIST: u16 = 0x58;
-    // PIO setup FIS - device to host
-    const TYPE_PIO_SETUP: u16 = 0x5F;
-    // Set device bits FIS - device to host
-    const TYPE_DEV_BITS: u16 = 0xA1;
+use core::fmt;
+use spin::{Once, RwLock};
+
+crate::error_impl! {
+    #[derive(Debug)]
+    pub enum Error {
+        /// Indicates the HBA's memory register BAR (ABAR) is missing, or isn't memory space.
+        InvalidBar => None,
+
+        /// Indicates the HBA or a device didn't respond within its timeout.
+        Timeout => None,
+
+        /// Indicates the device attached to a port isn't a SATA disk.
+        UnsupportedDevice { port: usize, class: Option<hba::Class> } => None,
+
+        /// Indicates the device attached to a port doesn't support 48-bit addressing.
+        NoLba48 { port: usize } => None,
+
+        /// Indicates the controller has no device attached to the provided port.
+        UnknownPort { port: usize } => None,
+
+        /// Indicates a command failed, with the device's task file at the time.
+        CommandFailed { task_file: hba::TaskFile } => None,
+
+        /// Indicates a transfer would extend past the end of the device.
+        OutOfBounds { lba: u64, count: u64 } => None,
+
+        /// Indicates a buffer's length isn't a multiple of the device's sector size.
+        UnalignedBuffer { len: usize } => None,
+
+        /// Indicates there wasn't enough physical memory for a port's command lists or buffers.
+        OutOfMemory { err: pmm::Error } => None,
+
+        Interrupt { err: irq::Error } => Some(err),
+        Pci { err: pci::Error } => Some(err)
+    }
 }
 
+impl From<pmm::Error> for Error {
+    fn from(err: pmm::Error) -> Self {
+        Self::OutOfMemory { err }
+    }
+}
 
+/// ATA commands issued by the driver.
 #[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CommandType {
-    ReadDMA = 0x25,
+    ReadDMAExt = 0x25,
+    WriteDMAExt = 0x35,
+    ReadFPDMAQueued = 0x60,
+    WriteFPDMAQueued = 0x61,
+    FlushCacheExt = 0xEA,
+    IdentifyDevice = 0xEC,
 }
 
-#[allow(non_upper_case_globals)]
-pub const HBA_PxIS_TFES: u32 = 1 << 30;
-
-pub struct AHCI<'ahci> {
-    // TODO devise some system of renting out the PCIe devices to drivers
-    device: &'ahci PCIeDevice<Standard>,
-    sata_ports: Vec<&'ahci hba::Port>,
+pub struct Controller {
+    claim: Arc<pci::Claim>,
+    registers: &'static hba::Memory,
+    devices: Vec<Device>,
+    /// Interrupt which signals command completions, if the HBA supports message signalled interrupts.
+    interrupt: Once<Arc<Interrupt>>,
 }
 
-impl<'ahci> AHCI<'ahci> {
-    pub fn from_pcie_device(device: &'ahci PCIeDevice<Standard>) -> Self {
-        assert_eq!(
-            device.class(),
-            libsys::io::pci::DeviceClass::MassStorageController,
-            "Device provided for AHCI driver must be MassStorageController."
-        );
-        assert_eq!(
-            device.subclass(),
-            0x6,
-            "Device provided for AHCI driver must be subclass Serial ATA."
-        );
-
-        debug!("Using PCIe device for AHCI driver:\n{:#?}", device);
-
-        if let Some(hba_mmio) = device.get_register(StandardRegister::Register5) {
-            debug!("Parsing valid SATA ports from HBA memory ports.");
-            let sata_ports = unsafe { hba_mmio.borrow::<hba::Memory>(0x0) }
-                .ports()
-                .filter_map(|port| match port.class() {
-                    hba::Class::SATA | hba::Class::SATAPI => {
-                        debug!("Configuring AHCI port: {:?}", port.class());
-                        port.configure();
-                        Some(port)
+impl Controller {
+    /// Resets the claimed HBA, and starts every port with a SATA disk attached.
+    pub fn new(claim: Arc<pci::Claim>) -> Result<Self> {
+        const LINK_TIMEOUT_MS: u32 = 10;
+
+        let bar = claim.with_device(|device| {
+            // Completions are signalled by message, so the device's pin interrupt is disabled.
+            let mut command = device.get_command();
+            command.insert(pci::Command::MEMORY_SPACE | pci::Command::BUS_MASTER | pci::Command::INTERRUPT_DISABLE);
+            device.set_command(command);
+
+            device.get_bar(5).ok()
+        });
+
+        let registers_address = match bar {
+            Some(bar @ (pci::Bar::MemorySpace32 { .. } | pci::Bar::MemorySpace64 { .. })) if !bar.is_unused() => {
+                bar.get_address().get() & !0xF
+            }
+
+            _ => return Err(Error::InvalidBar),
+        };
+        // Safety: Like PCI configuration space, the HBA's registers are accessed through the HHDM.
+        let registers = unsafe { &*HHDM.ptr().add(registers_address).cast::<hba::Memory>() };
+
+        if !registers.reset() {
+            return Err(Error::Timeout);
+        }
+        debug!("AHCI HBA successfully reset: {:#?}", registers);
+
+        if registers.capabilities().get_sss() {
+            for (_, port) in registers.ports() {
+                port.command_status().set_pod(true);
+                port.command_status().set_sud(true);
+            }
+        }
+
+        // Ports need a moment after the reset for their links to be re-established.
+        hba::wait_ms(LINK_TIMEOUT_MS, || registers.ports().all(|(_, port)| port.is_connected()));
+
+        let devices = registers
+            .ports()
+            .filter(|(_, port)| port.is_connected())
+            .filter_map(|(index, port)| {
+                // Safety: Registers are valid for the lifetime of the controller, which owns the devices, and each
+                //         port is used by only one device.
+                match unsafe { Device::new(registers, index, port) } {
+                    Ok(device) => Some(device),
+                    Err(err) => {
+                        warn!("Skipping AHCI port {}: {:?}", index, err);
+                        None
                     }
-                    _port_type => None,
-                })
-                .collect::<Vec<&hba::Port>>();
+                }
+            })
+            .collect();
+
+        Ok(Self { claim, registers, devices, interrupt: Once::new() })
+    }
+
+    /// Routes the HBA's interrupt to the controller, so completions are signalled rather than polled.
+    fn enable_interrupts(self: &Arc<Self>) -> Result<()> {
+        let controller = Arc::downgrade(self);
+        let interrupt = Interrupt::with_handler(
+            self.claim.clone(),
+            0,
+            Box::new(move || {
+                if let Some(controller) = controller.upgrade() {
+                    controller.handle_interrupt();
+                }
+            }),
+        )
+        .map_err(|err| Error::Interrupt { err })?;
 
-            debug!("Found SATA ports: {}", sata_ports.len());
+        self.interrupt.call_once(|| interrupt);
+        self.registers.clear_interrupt_status(u32::MAX);
+        self.registers.global_host_control().set_ie(true);
 
-            Self { device, sata_ports }
-        } else {
-            panic!("device's host bust adapter is an incorrect register type")
+        Ok(())
+    }
+
+    fn handle_interrupt(&self) {
+        let pending = self.registers.interrupt_status();
+        let mut serviced = 0u32;
+
+        for device in self.devices.iter().filter(|device| pending.get_bit(device.port())) {
+            device.service();
+            serviced.set_bit(device.port(), true);
         }
+
+        // Ports without a device may still raise interrupts (e.g. on hotplug), which are only acknowledged.
+        self.registers.clear_interrupt_status(pending & !serviced);
+    }
+
+    /// Whether completions are currently signalled by interrupts, which wake their waiters. Otherwise (e.g. during
+    /// early boot, before the core can wait for interrupts), waiters service the ports themselves.
+    fn interrupts(&self) -> bool {
+        self.interrupt.get().is_some() && crate::cpu::state::can_wait_for_interrupt()
+    }
+
+    pub fn registers(&self) -> &hba::Memory {
+        self.registers
+    }
+
+    #[inline]
+    pub fn devices(&self) -> &[Device] {
+        &self.devices
+    }
+
+    fn device(&self, port: usize) -> Result<&Device> {
+        self.devices.iter().find(|device| device.port() == port).ok_or(Error::UnknownPort { port })
+    }
+
+    /// Reads the sectors starting at `lba` into `buffer`, whose length must be a multiple of the sector size.
+    pub async fn read(&self, port: usize, lba: u64, buffer: &mut [u8]) -> Result<()> {
+        self.device(port)?.read(self.interrupts(), lba, buffer).await
+    }
+
+    /// Writes `buffer`, whose length must be a multiple of the sector size, to the sectors starting at `lba`.
+    pub async fn write(&self, port: usize, lba: u64, buffer: &[u8]) -> Result<()> {
+        self.device(port)?.write(self.interrupts(), lba, buffer).await
     }
 
-    pub fn sata_ports(&'ahci self) -> core::slice::Iter<&'ahci hba::Port> {
-        self.sata_ports.iter()
+    /// Commits any cached writes of the device on `port` to non-volatile media.
+    pub async fn flush(&self, port: usize) -> Result<()> {
+        self.device(port)?.flush(self.interrupts()).await
+    }
+}
+
+impl Drop for Controller {
+    fn drop(&mut self) {
+        self.registers.global_host_control().set_ie(false);
+    }
+}
+
+impl fmt::Debug for Controller {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("AHCI Controller")
+            .field("Registers", &self.registers)
+            .field("Devices", &self.devices)
+            .field("Interrupts", &self.interrupt.get().map(|interrupt| interrupt.vector()))
+            .finish()
+    }
+}
+
+/// A device attached to a port of a controller, exposed as a block device.
+pub struct Disk {
+    controller: Arc<Controller>,
+    port: usize,
+    sector_size: usize,
+    sector_count: u64,
+}
+
+impl Disk {
+    pub fn new(controller: Arc<Controller>, port: usize) -> Result<Self> {
+        let device = controller.device(port)?;
+        let (sector_size, sector_count) = (device.sector_size(), device.sector_count());
+
+        Ok(Self { controller, port, sector_size, sector_count })
+    }
+}
+
+impl block::BlockDevice for Disk {
+    fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sector_count
+    }
+
+    fn read<'a>(&'a self, lba: u64, buffer: &'a mut [u8]) -> block::Request<'a> {
+        Box::pin(async move { Ok(self.controller.read(self.port, lba, buffer).await?) })
+    }
+
+    fn write<'a>(&'a self, lba: u64, buffer: &'a [u8]) -> block::Request<'a> {
+        Box::pin(async move { Ok(self.controller.write(self.port, lba, buffer).await?) })
+    }
+
+    fn flush(&self) -> block::Request<'_> {
+        Box::pin(async move { Ok(self.controller.flush(self.port).await?) })
+    }
+}
+
+impl fmt::Debug for Disk {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("AHCI Disk")
+            .field("Port", &self.port)
+            .field("Sector Size", &self.sector_size)
+            .field("Sector Count", &self.sector_count)
+            .finish_non_exhaustive()
+    }
+}
+
+static CONTROLLERS: RwLock<Vec<Arc<Controller>>> = RwLock::new(Vec::new());
+
+/// Every AHCI controller which has been brought up.
+pub fn controllers() -> Vec<Arc<Controller>> {
+    CONTROLLERS.read().clone()
+}
+
+/// Claims and brings up every AHCI controller on the PCI bus.
+pub fn init() {
+    let indexes = pci::with_devices(|devices| {
+        devices
+            .iter()
+            .enumerate()
+            // Mass storage controller, serial ATA controller, AHCI.
+            .filter(|(_, device)| device.get_class_codes() == (0x01, 0x06, 0x01))
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>()
+    });
+
+    for index in indexes {
+        let controller = pci::claim(index, *crate::init::KERNEL_HANDLE)
+            .map_err(|err| Error::Pci { err })
+            .and_then(|claim| Controller::new(Arc::new(claim)));
+
+        match controller {
+            Ok(controller) => {
+                let controller = Arc::new(controller);
+                if let Err(err) = controller.enable_interrupts() {
+                    warn!("AHCI controller {} can't signal completions, so they'll be polled: {:?}", index, err);
+                }
+
+                info!("AHCI controller {} is ready, with devices: {:#?}", index, controller.devices());
+
+                // Reading the first sector of each device exercises its command slots.
+                for device in controller.devices() {
+                    let mut sector = alloc::vec![0u8; device.sector_size()];
+                    match block::block_on(controller.read(device.port(), 0, &mut sector)) {
+                        Ok(()) => debug!("Port {} LBA 0: {:02X?}", device.port(), &sector[..16]),
+                        Err(err) => error!("Failed to read port {}: {:?}", device.port(), err),
+                    }
+                }
+
+                for device in controller.devices() {
+                    match Disk::new(controller.clone(), device.port()) {
+                        Ok(disk) => {
+                            block::register(Arc::new(disk));
+                        }
+
+                        Err(err) => error!("Failed to register port {}: {:?}", device.port(), err),
+                    }
+                }
+
+                CONTROLLERS.write().push(controller);
+            }
+
+            Err(err) => error!("Failed to bring up AHCI controller {}: {:?}", index, err),
+        }
     }
 }
@@ -4,16 +4,43 @@ pub struct Color8i {
     pub b: u8,
     pub g: u8,
     pub r: u8,
-    reserved: u8,
+    /// Opacity of the color, from fully transparent (0) to fully opaque (255).
+    pub a: u8,
 }
 
 impl Color8i {
+    pub const TRANSPARENT: Self = Self::with_alpha(0, 0, 0, 0);
+
+    /// Creates an opaque color.
     pub const fn new(r: u8, g: u8, b: u8) -> Self {
-        Color8i {
-            b,
-            g,
-            r,
-            reserved: 0x0,
+        Self::with_alpha(r, g, b, u8::MAX)
+    }
+
+    pub const fn with_alpha(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Color8i { b, g, r, a }
+    }
+
+    /// Composites `self` over `below`, weighting each by the opacity of `self`.
+    ///
+    /// The result has the opacity of `below`, as the bottom-most layer (the framebuffer) is always opaque.
+    #[inline]
+    pub fn blend_over(self, below: Self) -> Self {
+        fn channel(above: u8, below: u8, alpha: u16) -> u8 {
+            let value = (u16::from(above) * alpha) + (u16::from(below) * (255 - alpha));
+
+            // Divides by 255 with rounding, without a division.
+            ((value + 128 + ((value + 128) >> 8)) >> 8) as u8
+        }
+
+        match self.a {
+            u8::MAX => Self { a: below.a, ..self },
+            0 => below,
+            alpha => Self {
+                b: channel(self.b, below.b, u16::from(alpha)),
+                g: channel(self.g, below.g, u16::from(alpha)),
+                r: channel(self.r, below.r, u16::from(alpha)),
+                a: below.a,
+            },
         }
     }
 }
@@ -23,25 +50,19 @@ impl From<u32> for Color8i {
         let r = ((from >> 24) & 0xFF) as u8;
         let g = ((from >> 16) & 0xFF) as u8;
         let b = ((from >> 8) & 0xFF) as u8;
-        let reserved = (from & 0xFF) as u8;
+        let a = (from & 0xFF) as u8;
 
-        Self { r, g, b, reserved }
+        Self { b, g, r, a }
     }
 }
 
 impl core::fmt::Debug for Color8i {
     fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        formatter
-            .debug_tuple("Color8i")
-            .field(&self.r)
-            .field(&self.g)
-            .field(&self.b)
-            .finish()
+        formatter.debug_tuple("Color8i").field(&self.r).field(&self.g).field(&self.b).field(&self.a).finish()
     }
 }
 
 #[repr(u8)]
-
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Colors {
     Black,
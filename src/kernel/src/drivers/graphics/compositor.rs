@@ -0,0 +1,294 @@
+//! Composites rectangular surfaces onto the framebuffer, redrawing only the regions which have changed.
+
+use crate::drivers::graphics::{color::Color8i, framebuffer::FramebufferDriver, rect::Rect};
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+
+/// Most separate damaged regions which are tracked; past this, they're all merged into one.
+const MAX_DAMAGE_REGIONS: usize = 16;
+
+crate::error_impl! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Error {
+        /// Indicates no surface exists with the given ID.
+        NoSurface { id: SurfaceId } => None,
+
+        /// Indicates a surface would be given no pixels.
+        EmptySurface => None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SurfaceId(u64);
+
+impl SurfaceId {
+    #[inline]
+    pub const fn get(self) -> u64 {
+        self.0
+    }
+}
+
+/// A rectangle of pixels placed on the screen, which are blended with whatever lies beneath them.
+pub struct Surface {
+    rect: Rect,
+    pixels: Vec<Color8i>,
+    visible: bool,
+}
+
+impl Surface {
+    /// Position and size of the surface on the screen.
+    #[inline]
+    pub const fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// Pixels of the surface, in rows of the surface's width.
+    #[inline]
+    pub fn pixels(&self) -> &[Color8i] {
+        &self.pixels
+    }
+
+    #[inline]
+    pub const fn is_visible(&self) -> bool {
+        self.visible
+    }
+}
+
+pub struct Compositor {
+    framebuffer: FramebufferDriver,
+    background: Color8i,
+    surfaces: BTreeMap<SurfaceId, Surface>,
+    /// Order in which surfaces are drawn, from the bottom-most to the top-most.
+    stacking: Vec<SurfaceId>,
+    /// Regions of the screen which must be redrawn, which never overlap one another.
+    damage: Vec<Rect>,
+    next_id: u64,
+}
+
+impl Compositor {
+    pub fn new(framebuffer: FramebufferDriver, background: Color8i) -> Self {
+        let mut compositor = Self {
+            framebuffer,
+            background,
+            surfaces: BTreeMap::new(),
+            stacking: Vec::new(),
+            damage: Vec::new(),
+            next_id: 0,
+        };
+        compositor.damage_screen();
+
+        compositor
+    }
+
+    #[inline]
+    pub const fn bounds(&self) -> Rect {
+        self.framebuffer.bounds()
+    }
+
+    /// Gives back the framebuffer, leaving it with whatever was last composed.
+    pub fn into_framebuffer(self) -> FramebufferDriver {
+        self.framebuffer
+    }
+
+    pub fn surface(&self, id: SurfaceId) -> Result<&Surface> {
+        self.surfaces.get(&id).ok_or(Error::NoSurface { id })
+    }
+
+    fn surface_mut(&mut self, id: SurfaceId) -> Result<&mut Surface> {
+        self.surfaces.get_mut(&id).ok_or(Error::NoSurface { id })
+    }
+
+    /// Surfaces which are drawn, from the bottom-most to the top-most.
+    pub fn stacking(&self) -> &[SurfaceId] {
+        &self.stacking
+    }
+
+    /// Creates a transparent surface covering `rect`, above every other surface.
+    pub fn create_surface(&mut self, rect: Rect) -> Result<SurfaceId> {
+        if rect.is_empty() {
+            return Err(Error::EmptySurface);
+        }
+
+        let id = SurfaceId(self.next_id);
+        self.next_id += 1;
+
+        self.surfaces.insert(id, Surface { rect, pixels: vec![Color8i::TRANSPARENT; rect.area()], visible: true });
+        self.stacking.push(id);
+
+        Ok(id)
+    }
+
+    pub fn destroy_surface(&mut self, id: SurfaceId) -> Result<()> {
+        let surface = self.surfaces.remove(&id).ok_or(Error::NoSurface { id })?;
+        self.stacking.retain(|stacked| *stacked != id);
+
+        if surface.visible {
+            self.add_damage(surface.rect);
+        }
+
+        Ok(())
+    }
+
+    /// Pixels of the surface, in rows of the surface's width.
+    ///
+    /// The screen isn't updated with changes to the pixels until they're damaged with [`Self::damage_surface`].
+    pub fn surface_pixels_mut(&mut self, id: SurfaceId) -> Result<&mut [Color8i]> {
+        self.surface_mut(id).map(|surface| surface.pixels.as_mut_slice())
+    }
+
+    /// Marks `local` (relative to the surface's origin), or the whole surface if `None`, to be redrawn.
+    pub fn damage_surface(&mut self, id: SurfaceId, local: Option<Rect>) -> Result<()> {
+        let surface = self.surface(id)?;
+        let surface_rect = surface.rect;
+        let visible = surface.visible;
+
+        let damaged = match local {
+            Some(local) => local.offset(surface_rect.x, surface_rect.y).intersection(&surface_rect),
+            None => Some(surface_rect),
+        };
+
+        if let Some(damaged) = damaged.filter(|_| visible) {
+            self.add_damage(damaged);
+        }
+
+        Ok(())
+    }
+
+    pub fn move_surface(&mut self, id: SurfaceId, x: usize, y: usize) -> Result<()> {
+        let surface = self.surface_mut(id)?;
+        let old_rect = surface.rect;
+        surface.rect.x = x;
+        surface.rect.y = y;
+        let new_rect = surface.rect;
+
+        if surface.visible {
+            self.add_damage(old_rect);
+            self.add_damage(new_rect);
+        }
+
+        Ok(())
+    }
+
+    /// Resizes the surface, keeping the pixels of the area it still covers. Any new area is transparent.
+    pub fn resize_surface(&mut self, id: SurfaceId, width: usize, height: usize) -> Result<()> {
+        if width == 0 || height == 0 {
+            return Err(Error::EmptySurface);
+        }
+
+        let surface = self.surface_mut(id)?;
+        let old_rect = surface.rect;
+
+        let mut pixels = vec![Color8i::TRANSPARENT; width * height];
+        let kept_width = width.min(old_rect.width);
+        for y in 0..height.min(old_rect.height) {
+            let old_row = y * old_rect.width;
+            pixels[(y * width)..((y * width) + kept_width)]
+                .copy_from_slice(&surface.pixels[old_row..(old_row + kept_width)]);
+        }
+
+        surface.pixels = pixels;
+        surface.rect.width = width;
+        surface.rect.height = height;
+        let new_rect = surface.rect;
+
+        if surface.visible {
+            self.add_damage(old_rect.union(&new_rect));
+        }
+
+        Ok(())
+    }
+
+    pub fn set_surface_visible(&mut self, id: SurfaceId, visible: bool) -> Result<()> {
+        let surface = self.surface_mut(id)?;
+        let changed = surface.visible != visible;
+        surface.visible = visible;
+        let rect = surface.rect;
+
+        if changed {
+            self.add_damage(rect);
+        }
+
+        Ok(())
+    }
+
+    /// Moves the surface above every other surface.
+    pub fn raise_surface(&mut self, id: SurfaceId) -> Result<()> {
+        self.surface(id)?;
+
+        self.stacking.retain(|stacked| *stacked != id);
+        self.stacking.push(id);
+        self.damage_surface(id, None)
+    }
+
+    pub fn set_background(&mut self, background: Color8i) {
+        self.background = background;
+        self.damage_screen();
+    }
+
+    /// Marks the whole screen to be redrawn.
+    pub fn damage_screen(&mut self) {
+        self.damage.clear();
+        self.damage.push(self.bounds());
+    }
+
+    /// Marks `rect` of the screen to be redrawn.
+    pub fn add_damage(&mut self, rect: Rect) {
+        let Some(mut rect) = rect.intersection(&self.bounds()) else { return };
+
+        // Absorb every region the new one touches, so regions never overlap, and no pixel is composed twice.
+        while let Some(index) = self.damage.iter().position(|damaged| damaged.touches(&rect)) {
+            rect = rect.union(&self.damage.swap_remove(index));
+        }
+
+        if self.damage.len() >= MAX_DAMAGE_REGIONS {
+            rect = self.damage.drain(..).fold(rect, |rect, damaged| rect.union(&damaged));
+        }
+
+        self.damage.push(rect);
+    }
+
+    /// Whether anything needs to be redrawn.
+    pub fn is_damaged(&self) -> bool {
+        !self.damage.is_empty()
+    }
+
+    /// Redraws every damaged region of the screen, and copies them to the framebuffer.
+    ///
+    /// Returns the regions which were redrawn.
+    pub fn compose(&mut self) -> Vec<Rect> {
+        let damage = core::mem::take(&mut self.damage);
+
+        for rect in &damage {
+            self.compose_rect(*rect);
+            self.framebuffer.flush_rect(*rect);
+        }
+
+        damage
+    }
+
+    fn compose_rect(&mut self, rect: Rect) {
+        let screen_width = self.framebuffer.width();
+        let backbuffer = self.framebuffer.backbuffer_mut();
+
+        for y in rect.y..rect.bottom() {
+            let row = y * screen_width;
+            backbuffer[(row + rect.x)..(row + rect.right())].fill(self.background);
+        }
+
+        let surfaces = self.stacking.iter().filter_map(|id| self.surfaces.get(id)).filter(|surface| surface.visible);
+        for surface in surfaces {
+            let Some(area) = surface.rect.intersection(&rect) else { continue };
+
+            let src_x = area.x - surface.rect.x;
+            for y in area.y..area.bottom() {
+                let src_row = ((y - surface.rect.y) * surface.rect.width) + src_x;
+                let dst_row = (y * screen_width) + area.x;
+
+                let src = &surface.pixels[src_row..(src_row + area.width)];
+                let dst = &mut backbuffer[dst_row..(dst_row + area.width)];
+                for (below, above) in dst.iter_mut().zip(src) {
+                    *below = above.blend_over(*below);
+                }
+            }
+        }
+    }
+}
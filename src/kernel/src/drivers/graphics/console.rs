@@ -0,0 +1,364 @@
+//! Text console drawn to the bootloader's framebuffer, which understands the common ANSI escape sequences.
+
+use crate::drivers::graphics::{
+    color::Color8i,
+    font::{self, GLYPH_HEIGHT, GLYPH_WIDTH, ROW_SCALE},
+    framebuffer::FramebufferDriver,
+};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use spin::Mutex;
+
+/// Colors of the ANSI color indexes, with the bright variants following the normal ones.
+const PALETTE: [Color8i; 16] = [
+    Color8i::new(0, 0, 0),
+    Color8i::new(170, 0, 0),
+    Color8i::new(0, 170, 0),
+    Color8i::new(170, 85, 0),
+    Color8i::new(0, 0, 170),
+    Color8i::new(170, 0, 170),
+    Color8i::new(0, 170, 170),
+    Color8i::new(170, 170, 170),
+    Color8i::new(85, 85, 85),
+    Color8i::new(255, 85, 85),
+    Color8i::new(85, 255, 85),
+    Color8i::new(255, 255, 85),
+    Color8i::new(85, 85, 255),
+    Color8i::new(255, 85, 255),
+    Color8i::new(85, 255, 255),
+    Color8i::new(255, 255, 255),
+];
+
+const DEFAULT_FOREGROUND: usize = 7;
+const DEFAULT_BACKGROUND: usize = 0;
+
+const TAB_WIDTH: usize = 8;
+
+/// Most parameters an escape sequence can have; any more are ignored.
+const MAX_PARAMS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    /// Within a control sequence (`ESC [`), having read the parameters up to and including `params[index]`.
+    Csi {
+        params: [u16; MAX_PARAMS],
+        index: usize,
+    },
+}
+
+pub struct Console {
+    framebuffer: FramebufferDriver,
+    columns: usize,
+    rows: usize,
+    /// Position of the cursor, as a column and row.
+    cursor: (usize, usize),
+    foreground: usize,
+    background: usize,
+    bold: bool,
+    state: State,
+    /// Pixel rows of the backbuffer which haven't been flushed to the framebuffer.
+    dirty: Option<Range<usize>>,
+}
+
+impl Console {
+    pub fn new(mut framebuffer: FramebufferDriver) -> Self {
+        let columns = framebuffer.width() / GLYPH_WIDTH;
+        let rows = framebuffer.height() / GLYPH_HEIGHT;
+
+        framebuffer.clear(PALETTE[DEFAULT_BACKGROUND]);
+        framebuffer.flush_pixels();
+
+        Self {
+            framebuffer,
+            columns,
+            rows,
+            cursor: (0, 0),
+            foreground: DEFAULT_FOREGROUND,
+            background: DEFAULT_BACKGROUND,
+            bold: false,
+            state: State::Ground,
+            dirty: None,
+        }
+    }
+
+    #[inline]
+    pub const fn columns(&self) -> usize {
+        self.columns
+    }
+
+    #[inline]
+    pub const fn rows(&self) -> usize {
+        self.rows
+    }
+
+    fn foreground_color(&self) -> Color8i {
+        // Bold text is drawn with the bright variant of the normal colors.
+        if self.bold && self.foreground < 8 {
+            PALETTE[self.foreground + 8]
+        } else {
+            PALETTE[self.foreground]
+        }
+    }
+
+    fn background_color(&self) -> Color8i {
+        PALETTE[self.background]
+    }
+
+    fn mark_dirty(&mut self, text_rows: Range<usize>) {
+        let pixel_rows = (text_rows.start * GLYPH_HEIGHT)..(text_rows.end * GLYPH_HEIGHT);
+
+        self.dirty = Some(match self.dirty.take() {
+            Some(dirty) => dirty.start.min(pixel_rows.start)..dirty.end.max(pixel_rows.end),
+            None => pixel_rows,
+        });
+    }
+
+    /// Gives back the framebuffer, leaving it with whatever was last flushed.
+    pub fn into_framebuffer(self) -> FramebufferDriver {
+        self.framebuffer
+    }
+
+    /// Copies everything drawn since the last flush to the framebuffer.
+    pub fn flush(&mut self) {
+        if let Some(dirty) = self.dirty.take() {
+            self.framebuffer.flush_rows(dirty);
+        }
+    }
+
+    fn draw_glyph(&mut self, c: char) {
+        let (column, row) = self.cursor;
+        let foreground = self.foreground_color();
+        let background = self.background_color();
+        let origin = (column * GLYPH_WIDTH, row * GLYPH_HEIGHT);
+
+        for (glyph_y, bits) in font::glyph(c).iter().enumerate() {
+            for glyph_x in 0..GLYPH_WIDTH {
+                let color = if (bits >> glyph_x) & 1 > 0 { foreground } else { background };
+
+                for scale_y in 0..ROW_SCALE {
+                    let y = origin.1 + (glyph_y * ROW_SCALE) + scale_y;
+                    self.framebuffer.write_pixel((origin.0 + glyph_x, y), color);
+                }
+            }
+        }
+
+        self.mark_dirty(row..(row + 1));
+    }
+
+    /// Clears the cells of `row` from `columns`, with the current background color.
+    fn clear_cells(&mut self, row: usize, columns: Range<usize>) {
+        let width = columns.end.saturating_sub(columns.start);
+
+        self.framebuffer.fill_rect(
+            (columns.start * GLYPH_WIDTH, row * GLYPH_HEIGHT),
+            (width * GLYPH_WIDTH, GLYPH_HEIGHT),
+            self.background_color(),
+        );
+        self.mark_dirty(row..(row + 1));
+    }
+
+    fn clear_screen(&mut self) {
+        self.framebuffer.clear(self.background_color());
+        self.mark_dirty(0..self.rows);
+    }
+
+    fn new_line(&mut self) {
+        self.cursor.0 = 0;
+
+        if (self.cursor.1 + 1) < self.rows {
+            self.cursor.1 += 1;
+        } else {
+            self.framebuffer.scroll_up(GLYPH_HEIGHT, self.background_color());
+            self.mark_dirty(0..self.rows);
+        }
+    }
+
+    pub fn write_char(&mut self, c: char) {
+        if self.columns == 0 || self.rows == 0 {
+            return;
+        }
+
+        match self.state {
+            State::Ground => self.write_ground(c),
+
+            State::Escape if c == '[' => self.state = State::Csi { params: [0; MAX_PARAMS], index: 0 },
+            State::Escape => self.state = State::Ground,
+
+            State::Csi { mut params, mut index } => match c {
+                '0'..='9' => {
+                    let digit = c.to_digit(10).and_then(|digit| u16::try_from(digit).ok()).unwrap_or(0);
+                    params[index] = params[index].saturating_mul(10).saturating_add(digit);
+                    self.state = State::Csi { params, index };
+                }
+
+                ';' => {
+                    index = (index + 1).min(MAX_PARAMS - 1);
+                    self.state = State::Csi { params, index };
+                }
+
+                // Final bytes end the sequence; anything else (intermediate bytes) is ignored.
+                '\x40'..='\x7E' => {
+                    self.state = State::Ground;
+                    self.execute_csi(c, &params[..=index]);
+                }
+
+                _ => {}
+            },
+        }
+    }
+
+    fn write_ground(&mut self, c: char) {
+        match c {
+            '\x1B' => self.state = State::Escape,
+            '\n' => self.new_line(),
+            '\r' => self.cursor.0 = 0,
+            '\x08' => self.cursor.0 = self.cursor.0.saturating_sub(1),
+
+            '\t' => {
+                self.cursor.0 = ((self.cursor.0 / TAB_WIDTH) + 1) * TAB_WIDTH;
+                if self.cursor.0 >= self.columns {
+                    self.new_line();
+                }
+            }
+
+            c if c.is_control() => {}
+
+            c => {
+                // Wrap only once there's something to draw, so a line which exactly fills the console followed by a
+                // newline doesn't leave an empty line behind.
+                if self.cursor.0 >= self.columns {
+                    self.new_line();
+                }
+
+                self.draw_glyph(c);
+                self.cursor.0 += 1;
+            }
+        }
+    }
+
+    fn execute_csi(&mut self, command: char, params: &[u16]) {
+        let param = |index: usize| params.get(index).copied().map_or(0, usize::from);
+        // Cursor movements treat a missing (zero) count as one.
+        let count = param(0).max(1);
+
+        match command {
+            'm' => params.iter().for_each(|value| self.select_graphic_rendition(*value)),
+
+            'A' => self.cursor.1 = self.cursor.1.saturating_sub(count),
+            'B' => self.cursor.1 = (self.cursor.1 + count).min(self.rows - 1),
+            'C' => self.cursor.0 = (self.cursor.0 + count).min(self.columns - 1),
+            'D' => self.cursor.0 = self.cursor.0.saturating_sub(count),
+
+            'H' | 'f' => {
+                self.cursor.1 = (param(0).max(1) - 1).min(self.rows - 1);
+                self.cursor.0 = (param(1).max(1) - 1).min(self.columns - 1);
+            }
+
+            'J' => match param(0) {
+                0 => {
+                    let (column, row) = self.cursor;
+                    self.clear_cells(row, column..self.columns);
+                    ((row + 1)..self.rows).for_each(|row| self.clear_cells(row, 0..self.columns));
+                }
+                1 => {
+                    let (column, row) = self.cursor;
+                    (0..row).for_each(|row| self.clear_cells(row, 0..self.columns));
+                    self.clear_cells(row, 0..(column + 1));
+                }
+                _ => self.clear_screen(),
+            },
+
+            'K' => {
+                let (column, row) = self.cursor;
+                match param(0) {
+                    0 => self.clear_cells(row, column..self.columns),
+                    1 => self.clear_cells(row, 0..(column + 1)),
+                    _ => self.clear_cells(row, 0..self.columns),
+                }
+            }
+
+            _ => trace!("Unsupported console control sequence: {:?}", command),
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, param: u16) {
+        match param {
+            0 => {
+                self.foreground = DEFAULT_FOREGROUND;
+                self.background = DEFAULT_BACKGROUND;
+                self.bold = false;
+            }
+            1 => self.bold = true,
+            22 => self.bold = false,
+            30..=37 => self.foreground = usize::from(param - 30),
+            39 => self.foreground = DEFAULT_FOREGROUND,
+            40..=47 => self.background = usize::from(param - 40),
+            49 => self.background = DEFAULT_BACKGROUND,
+            90..=97 => self.foreground = usize::from(param - 90) + 8,
+            100..=107 => self.background = usize::from(param - 100) + 8,
+            _ => {}
+        }
+    }
+}
+
+impl core::fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        s.chars().for_each(|c| self.write_char(c));
+
+        Ok(())
+    }
+}
+
+static CONSOLE: Mutex<Option<Console>> = Mutex::new(None);
+static AVAILABLE: AtomicBool = AtomicBool::new(false);
+
+/// Creates the console on the bootloader's framebuffer, if it provided one.
+///
+/// The console's backbuffer is allocated, so this must be called after the kernel's memory is set up.
+pub fn init() {
+    match FramebufferDriver::from_limine() {
+        Some(framebuffer) => {
+            crate::interrupts::without(|| *CONSOLE.lock() = Some(Console::new(framebuffer)));
+            AVAILABLE.store(true, Ordering::Release);
+        }
+
+        None => info!("Bootloader provided no usable framebuffer, so there will be no console."),
+    }
+}
+
+/// Whether the console exists.
+pub fn is_available() -> bool {
+    AVAILABLE.load(Ordering::Acquire)
+}
+
+/// Removes the console, giving back its framebuffer so something else can draw to the screen.
+///
+/// Returns `None` if the console doesn't exist.
+pub fn release() -> Option<FramebufferDriver> {
+    AVAILABLE.store(false, Ordering::Release);
+
+    crate::interrupts::without(|| CONSOLE.lock().take()).map(Console::into_framebuffer)
+}
+
+/// Writes `args` to the console and flushes it.
+///
+/// Nothing is written if the console doesn't exist, or is already being written to (such as when logging from
+/// within the console itself).
+pub fn print(args: core::fmt::Arguments) {
+    use core::fmt::Write;
+
+    if !is_available() {
+        return;
+    }
+
+    crate::interrupts::without(|| {
+        if let Some(Some(console)) = CONSOLE.try_lock().as_deref_mut() {
+            console.write_fmt(args).ok();
+            console.flush();
+        }
+    });
+}
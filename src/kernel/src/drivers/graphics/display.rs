@@ -0,0 +1,476 @@
+//! Display server, which shows the windows of userspace tasks on the framebuffer.
+//!
+//! Each window is a surface of the compositor, which its client draws into through a buffer of shared memory. Drawing
+//! doesn't reach the screen until the client commits it, at which point the damaged part of the buffer is copied into
+//! the compositor and composed. Input is routed to the window with keyboard focus, or the window under the pointer.
+//!
+//! The display server takes the framebuffer from the console when the first window is created, so from then on the
+//! console no longer shows anything.
+
+use crate::{
+    drivers::graphics::{
+        color::Color8i,
+        compositor::{self, Compositor, SurfaceId},
+        console,
+        framebuffer::FramebufferDriver,
+        rect::Rect,
+    },
+    task::{
+        shm::{self, SharedMemory},
+        Registers, Scheduler, State, WaitQueue,
+    },
+};
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    num::NonZeroUsize,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use libsys::{
+    page_size,
+    syscall::display::{Event, EventKind},
+    Address, Page,
+};
+use spin::Mutex;
+
+crate::error_impl! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Error {
+        /// Indicates there's no framebuffer to show windows on.
+        NoDisplay => None,
+
+        /// Indicates a window would be empty, or too large to allocate a buffer for.
+        InvalidSize { width: usize, height: usize } => None,
+
+        /// Indicates the window has been destroyed.
+        Destroyed => None,
+
+        Compositor { err: compositor::Error } => Some(err),
+
+        SharedMemory { err: shm::Error } => Some(err)
+    }
+}
+
+/// Most events which are queued for a window. Past this, the oldest events are dropped.
+const MAX_PENDING_EVENTS: usize = 256;
+/// Largest width or height of a window.
+const MAX_WINDOW_SIZE: usize = 0x4000;
+
+const BACKGROUND: Color8i = Color8i::new(0x20, 0x24, 0x2C);
+
+/// Pointer image, where `#` is drawn black, `-` white, and anything else is transparent.
+const CURSOR: [&[u8; 11]; 16] = [
+    b"#          ",
+    b"##         ",
+    b"#-#        ",
+    b"#--#       ",
+    b"#---#      ",
+    b"#----#     ",
+    b"#-----#    ",
+    b"#------#   ",
+    b"#-------#  ",
+    b"#--------# ",
+    b"#-----#####",
+    b"#--#--#    ",
+    b"#-# #--#   ",
+    b"##  #--#   ",
+    b"#    #--#  ",
+    b"     ####  ",
+];
+
+/// Input from a keyboard or pointing device, to be delivered to a window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    /// A key was pressed or released. `key` is its USB HID usage ID.
+    Key { key: usize, character: Option<char>, pressed: bool },
+    /// The pointer moved by `dx` and `dy` pixels.
+    PointerMotion { dx: isize, dy: isize },
+    /// A pointer button was pressed or released. Buttons are numbered from zero, starting with the primary button.
+    PointerButton { button: usize, pressed: bool },
+}
+
+struct Server {
+    compositor: Compositor,
+    windows: BTreeMap<SurfaceId, Weak<Window>>,
+    focused: Option<SurfaceId>,
+    cursor: SurfaceId,
+    pointer: (usize, usize),
+}
+
+/// Events to be delivered once the server is unlocked, as a delivery may drop the last reference to a window, and
+/// dropping a window locks the server.
+type Deliveries = Vec<(Weak<Window>, Event)>;
+
+impl Server {
+    fn new(framebuffer: FramebufferDriver) -> Self {
+        let mut compositor = Compositor::new(framebuffer, BACKGROUND);
+        let bounds = compositor.bounds();
+        let pointer = (bounds.width / 2, bounds.height / 2);
+
+        let cursor = compositor.create_surface(Rect::new(pointer.0, pointer.1, CURSOR[0].len(), CURSOR.len())).unwrap();
+        let pixels = compositor.surface_pixels_mut(cursor).unwrap();
+        for (pixel, shade) in pixels.iter_mut().zip(CURSOR.iter().flat_map(|row| row.iter())) {
+            *pixel = match shade {
+                b'#' => Color8i::new(0, 0, 0),
+                b'-' => Color8i::new(0xFF, 0xFF, 0xFF),
+                _ => Color8i::TRANSPARENT,
+            };
+        }
+
+        compositor.compose();
+
+        Self { compositor, windows: BTreeMap::new(), focused: None, cursor, pointer }
+    }
+
+    /// The top-most window which contains the pointer.
+    fn window_under_pointer(&self) -> Option<(SurfaceId, Rect)> {
+        let (x, y) = self.pointer;
+
+        self.compositor
+            .stacking()
+            .iter()
+            .rev()
+            .filter(|id| self.windows.contains_key(id))
+            .filter_map(|id| self.compositor.surface(*id).ok().map(|surface| (*id, surface)))
+            .find(|(_, surface)| {
+                let rect = surface.rect();
+                surface.is_visible() && (rect.x..rect.right()).contains(&x) && (rect.y..rect.bottom()).contains(&y)
+            })
+            .map(|(id, surface)| (id, surface.rect()))
+    }
+
+    fn deliver(&self, deliveries: &mut Deliveries, id: SurfaceId, event: Event) {
+        if let Some(window) = self.windows.get(&id) {
+            deliveries.push((window.clone(), event));
+        }
+    }
+
+    /// Gives keyboard focus to the window `id`, raising it.
+    fn focus(&mut self, deliveries: &mut Deliveries, id: Option<SurfaceId>) {
+        if self.focused == id {
+            return;
+        }
+
+        if let Some(old_id) = core::mem::replace(&mut self.focused, id) {
+            self.deliver(deliveries, old_id, Event { kind: EventKind::Focus, ..Event::default() });
+        }
+
+        if let Some(id) = id {
+            self.compositor.raise_surface(id).ok();
+            self.compositor.raise_surface(self.cursor).ok();
+            self.deliver(deliveries, id, Event { kind: EventKind::Focus, pressed: 1, ..Event::default() });
+        }
+    }
+
+    fn handle_input(&mut self, input: Input) -> Deliveries {
+        let mut deliveries = Vec::new();
+
+        match input {
+            Input::Key { key, character, pressed } => {
+                if let Some(id) = self.focused {
+                    let event = Event {
+                        kind: EventKind::Key,
+                        key,
+                        character: character.map_or(0, |character| character as usize),
+                        pressed: usize::from(pressed),
+                        ..Event::default()
+                    };
+                    self.deliver(&mut deliveries, id, event);
+                }
+            }
+
+            Input::PointerMotion { dx, dy } => {
+                let bounds = self.compositor.bounds();
+                let x = self.pointer.0.saturating_add_signed(dx).min(bounds.width.saturating_sub(1));
+                let y = self.pointer.1.saturating_add_signed(dy).min(bounds.height.saturating_sub(1));
+                self.pointer = (x, y);
+                self.compositor.move_surface(self.cursor, x, y).ok();
+
+                if let Some((id, rect)) = self.window_under_pointer() {
+                    let event =
+                        Event { kind: EventKind::PointerMotion, x: x - rect.x, y: y - rect.y, ..Event::default() };
+                    self.deliver(&mut deliveries, id, event);
+                }
+            }
+
+            Input::PointerButton { button, pressed } => {
+                let under_pointer = self.window_under_pointer();
+
+                if pressed {
+                    self.focus(&mut deliveries, under_pointer.map(|(id, _)| id));
+                }
+
+                if let Some((id, rect)) = under_pointer {
+                    let event = Event {
+                        kind: EventKind::PointerButton,
+                        x: self.pointer.0 - rect.x,
+                        y: self.pointer.1 - rect.y,
+                        buttons: button,
+                        pressed: usize::from(pressed),
+                        ..Event::default()
+                    };
+                    self.deliver(&mut deliveries, id, event);
+                }
+            }
+        }
+
+        self.compositor.compose();
+
+        deliveries
+    }
+
+    fn remove_window(&mut self, id: SurfaceId) -> Deliveries {
+        let mut deliveries = Vec::new();
+
+        if self.windows.remove(&id).is_some() {
+            self.compositor.destroy_surface(id).ok();
+
+            if self.focused == Some(id) {
+                self.focused = None;
+                // Focus passes to the top-most remaining window.
+                let next = self.compositor.stacking().iter().rev().find(|id| self.windows.contains_key(id)).copied();
+                self.focus(&mut deliveries, next);
+            }
+
+            self.compositor.compose();
+        }
+
+        deliveries
+    }
+}
+
+static SERVER: spin::Once<Option<Mutex<Server>>> = spin::Once::new();
+
+/// Calls `func` with the display server, starting it if this is the first call.
+fn with_server<T>(func: impl FnOnce(&mut Server) -> T) -> Result<T> {
+    let server = SERVER.call_once(|| {
+        let framebuffer = console::release()?;
+        info!("Display server has taken the framebuffer from the console.");
+
+        Some(Mutex::new(Server::new(framebuffer)))
+    });
+
+    server.as_ref().map(|server| func(&mut server.lock())).ok_or(Error::NoDisplay)
+}
+
+fn deliver(deliveries: Deliveries) {
+    for (window, event) in deliveries {
+        if let Some(window) = window.upgrade() {
+            window.push_event(event);
+        }
+    }
+}
+
+/// Routes `input` to the window it's meant for. Nothing happens if the display server hasn't started.
+pub fn push_input(input: Input) {
+    if let Some(Some(server)) = SERVER.get() {
+        let deliveries = server.lock().handle_input(input);
+        deliver(deliveries);
+    }
+}
+
+/// Memory shared with a window's client, which the client draws the window into.
+struct Buffer {
+    memory: Arc<SharedMemory>,
+    width: usize,
+    height: usize,
+    /// Address the buffer is mapped at in the client's address space.
+    mapped_at: Option<Address<Page>>,
+}
+
+impl Buffer {
+    fn new(width: usize, height: usize) -> Result<Self> {
+        let size = width.checked_mul(height).and_then(|pixels| pixels.checked_mul(core::mem::size_of::<Color8i>()));
+        let page_count = size
+            .filter(|_| (1..=MAX_WINDOW_SIZE).contains(&width) && (1..=MAX_WINDOW_SIZE).contains(&height))
+            .and_then(|size| NonZeroUsize::new(size.div_ceil(page_size())))
+            .ok_or(Error::InvalidSize { width, height })?;
+
+        let memory = SharedMemory::new(page_count).map_err(|err| Error::SharedMemory { err })?;
+
+        Ok(Self { memory, width, height, mapped_at: None })
+    }
+
+    /// Pixels of the buffer, in rows of `width` pixels.
+    ///
+    /// The client may write to the buffer at any time, so the pixels must only be copied, and never referenced.
+    fn pixels(&self) -> *mut Color8i {
+        self.memory.as_ptr().as_ptr().cast::<Color8i>()
+    }
+}
+
+/// A window of a userspace task.
+pub struct Window {
+    surface: SurfaceId,
+    buffer: Mutex<Option<Buffer>>,
+    next_serial: AtomicUsize,
+    events: Mutex<VecDeque<Event>>,
+    waiters: WaitQueue,
+}
+
+impl Window {
+    /// Creates a window covering `rect` of the screen, above every other window, and gives it keyboard focus.
+    pub fn create(rect: Rect) -> Result<Arc<Self>> {
+        let buffer = Buffer::new(rect.width, rect.height)?;
+
+        let (window, deliveries) = with_server(|server| {
+            let surface = server.compositor.create_surface(rect).map_err(|err| Error::Compositor { err })?;
+            server.compositor.raise_surface(server.cursor).ok();
+
+            let window = Arc::new(Self {
+                surface,
+                buffer: Mutex::new(Some(buffer)),
+                next_serial: AtomicUsize::new(1),
+                events: Mutex::new(VecDeque::new()),
+                waiters: WaitQueue::new(),
+            });
+            server.windows.insert(surface, Arc::downgrade(&window));
+
+            let mut deliveries = Vec::new();
+            server.focus(&mut deliveries, Some(surface));
+
+            Ok((window, deliveries))
+        })??;
+
+        deliver(deliveries);
+
+        Ok(window)
+    }
+
+    /// Shared memory the window is drawn into, along with its width and height.
+    pub fn buffer(&self) -> Result<(Arc<SharedMemory>, usize, usize)> {
+        let buffer = self.buffer.lock();
+        let buffer = buffer.as_ref().ok_or(Error::Destroyed)?;
+
+        Ok((buffer.memory.clone(), buffer.width, buffer.height))
+    }
+
+    /// Records where the buffer is mapped in the client's address space, returning where it was mapped before.
+    pub fn set_mapped_at(&self, address: Address<Page>) -> Option<Address<Page>> {
+        self.buffer.lock().as_mut().and_then(|buffer| buffer.mapped_at.replace(address))
+    }
+
+    /// Replaces the window's buffer with one of the new size, copying across the pixels both buffers cover.
+    pub fn resize(&self, width: usize, height: usize) -> Result<()> {
+        let mut buffer = self.buffer.lock();
+        let old_buffer = buffer.as_ref().ok_or(Error::Destroyed)?;
+        let mut new_buffer = Buffer::new(width, height)?;
+
+        let kept_width = width.min(old_buffer.width);
+        for y in 0..height.min(old_buffer.height) {
+            // Safety: Rows lie within both buffers, which are separate allocations.
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    old_buffer.pixels().add(y * old_buffer.width),
+                    new_buffer.pixels().add(y * width),
+                    kept_width,
+                );
+            }
+        }
+
+        with_server(|server| server.compositor.resize_surface(self.surface, width, height))?
+            .map_err(|err| Error::Compositor { err })?;
+
+        new_buffer.mapped_at = old_buffer.mapped_at;
+        *buffer = Some(new_buffer);
+
+        Ok(())
+    }
+
+    /// Copies `damage` (relative to the window's origin, or the whole window if `None`) of the buffer to the screen.
+    /// Returns the commit's serial, which is acknowledged with an event once the damage has been composed.
+    pub fn commit(&self, damage: Option<Rect>) -> Result<usize> {
+        let buffer = self.buffer.lock();
+        let buffer = buffer.as_ref().ok_or(Error::Destroyed)?;
+        let serial = self.next_serial.fetch_add(1, Ordering::Relaxed);
+
+        let window_rect = Rect::new(0, 0, buffer.width, buffer.height);
+        let damage = damage.map_or(Some(window_rect), |damage| damage.intersection(&window_rect));
+
+        let composed = with_server(|server| -> core::result::Result<Rect, compositor::Error> {
+            let Some(damage) = damage else { return Ok(Rect::default()) };
+
+            let pixels = server.compositor.surface_pixels_mut(self.surface)?;
+            for y in damage.y..damage.bottom() {
+                let offset = (y * buffer.width) + damage.x;
+
+                // Safety: The damage lies within the buffer, which is the size of the surface.
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        buffer.pixels().add(offset),
+                        pixels[offset..(offset + damage.width)].as_mut_ptr(),
+                        damage.width,
+                    );
+                }
+            }
+
+            server.compositor.damage_surface(self.surface, Some(damage))?;
+            server.compositor.compose();
+
+            Ok(damage)
+        })?
+        .map_err(|err| Error::Compositor { err })?;
+
+        self.push_event(Event { kind: EventKind::Committed, serial, rect: composed.into(), ..Event::default() });
+
+        Ok(serial)
+    }
+
+    /// Removes the window from the screen. Its buffer is dropped, so it's freed once the client unmaps it.
+    pub fn destroy(&self) -> Option<Address<Page>> {
+        let buffer = self.buffer.lock().take();
+
+        if let Ok(deliveries) = with_server(|server| server.remove_window(self.surface)) {
+            deliver(deliveries);
+        }
+
+        buffer.and_then(|buffer| buffer.mapped_at)
+    }
+
+    fn push_event(&self, event: Event) {
+        let mut events = self.events.lock();
+        if events.len() >= MAX_PENDING_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(event);
+        drop(events);
+
+        self.waiters.wake_all();
+    }
+
+    /// Takes the next event delivered to the window, or, if there are none, blocks the scheduler's current task until
+    /// one is delivered.
+    pub fn wait_event(&self, scheduler: &mut Scheduler, state: &mut State, regs: &mut Registers) -> Option<Event> {
+        let mut events = self.events.lock();
+
+        if let Some(event) = events.pop_front() {
+            Some(event)
+        } else {
+            // Events are pushed while holding the lock, so blocking here can't miss the wake.
+            self.waiters.wait(scheduler, state, regs);
+
+            None
+        }
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        self.destroy();
+    }
+}
+
+impl From<Rect> for libsys::syscall::display::Rect {
+    fn from(rect: Rect) -> Self {
+        Self { x: rect.x, y: rect.y, width: rect.width, height: rect.height }
+    }
+}
+
+impl From<libsys::syscall::display::Rect> for Rect {
+    fn from(rect: libsys::syscall::display::Rect) -> Self {
+        Self::new(rect.x, rect.y, rect.width, rect.height)
+    }
+}
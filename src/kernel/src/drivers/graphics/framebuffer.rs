@@ -1,103 +1,227 @@
-#![allow(dead_code)]
+use crate::drivers::graphics::{
+    color::{Color8i, Colors},
+    rect::Rect,
+};
+use alloc::{vec, vec::Vec};
+use core::{ops::Range, ptr::NonNull};
 
-use crate::drivers::graphics::color::{Color8i, Colors};
-use libsys::{Address, Physical, Size};
-use spin::{Mutex, RwLock};
+#[limine::limine_tag]
+static LIMINE_FRAMEBUFFER: limine::FramebufferRequest = limine::FramebufferRequest::new(crate::init::boot::LIMINE_REV);
 
-#[repr(C)]
+/// Limine's memory model for framebuffers whose pixels are described by RGB bit masks.
+const LIMINE_MEMORY_MODEL_RGB: u8 = 1;
+
+/// Layout of a pixel in the framebuffer's memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelFormat {
+    pub bytes_per_pixel: usize,
+    pub red_shift: u8,
+    pub red_size: u8,
+    pub green_shift: u8,
+    pub green_size: u8,
+    pub blue_shift: u8,
+    pub blue_size: u8,
+}
+
+impl PixelFormat {
+    /// Packs `color` into a pixel of this format, scaling each channel to the width of its mask.
+    #[inline]
+    pub fn encode(&self, color: Color8i) -> u32 {
+        fn channel(value: u8, size: u8, shift: u8) -> u32 {
+            let value = u32::from(value);
+            let scaled = if size <= 8 {
+                value >> (8 - size)
+            } else {
+                // Repeats the high bits in the low bits, so full intensity stays full intensity.
+                (value << (size - 8)) | (value >> (16 - size))
+            };
+
+            scaled << shift
+        }
+
+        channel(color.r, self.red_size, self.red_shift)
+            | channel(color.g, self.green_size, self.green_shift)
+            | channel(color.b, self.blue_size, self.blue_shift)
+    }
+}
+
+/// A linear framebuffer, drawn through a backbuffer which is copied to it when flushed.
 pub struct FramebufferDriver {
-    framebuffer: Mutex<*mut Color8i>,
-    backbuffer: RwLock<*mut Color8i>,
-    dimensions: Size,
-    scanline_width: usize,
+    framebuffer: NonNull<u8>,
+    backbuffer: Vec<Color8i>,
+    width: usize,
+    height: usize,
+    /// Number of bytes between the starts of consecutive rows of the framebuffer.
+    pitch: usize,
+    format: PixelFormat,
 }
 
+// Safety: The framebuffer is only ever accessed through the driver, which requires mutable access to write to it.
+unsafe impl Send for FramebufferDriver {}
+
 impl FramebufferDriver {
-    pub fn new(buffer_addr: Address<Physical>, dimensions: Size, scanline_width: usize) -> Self {
-        let pixel_len = scanline_width * dimensions.height();
-        let byte_len = pixel_len * core::mem::size_of::<Color8i>();
-
-        let framebuffer = unsafe {
-            libsys::memory::malloc::get()
-                .alloc_against(buffer_addr.frame_index(), (byte_len + 0xFFF) / 0x1000)
-                .expect("Allocation error occurred when attempting to create pixelbuffer")
-                .cast()
-                .expect("Allocated region is of invalid alignment for Color8i")
-                .into_parts()
-                .0
-        };
+    /// Creates a driver for the first framebuffer the bootloader provided, if it provided one in a format which can be
+    /// drawn to.
+    pub fn from_limine() -> Option<Self> {
+        let response = LIMINE_FRAMEBUFFER.get_response()?;
+        let framebuffer = response.framebuffers().iter().find(|framebuffer| {
+            framebuffer.memory_model() == LIMINE_MEMORY_MODEL_RGB
+                && matches!(framebuffer.bpp(), 16 | 24 | 32)
+                && [framebuffer.red_mask_size(), framebuffer.green_mask_size(), framebuffer.blue_mask_size()]
+                    .iter()
+                    .all(|size| (1..=16).contains(size))
+        })?;
 
-        let backbuffer = unsafe {
-            libsys::memory::malloc::get()
-                .alloc(
-                    byte_len,
-                    core::num::NonZeroUsize::new(core::mem::align_of::<Color8i>()),
-                )
-                .expect("Allocation error occurred when attempting to create pixelbuffer")
-                .cast()
-                .expect("Allocated region is of invalid alignment for Color8i")
-                .into_parts()
-                .0
+        let format = PixelFormat {
+            bytes_per_pixel: usize::from(framebuffer.bpp() / 8),
+            red_shift: framebuffer.red_mask_shift(),
+            red_size: framebuffer.red_mask_size(),
+            green_shift: framebuffer.green_mask_shift(),
+            green_size: framebuffer.green_mask_size(),
+            blue_shift: framebuffer.blue_mask_shift(),
+            blue_size: framebuffer.blue_mask_size(),
         };
 
-        info!("{:?} {}", dimensions, scanline_width);
+        // Safety: The bootloader maps the framebuffer (in the higher-half direct map) with the dimensions it reports,
+        //         and the kernel's own page tables map framebuffer memory in the same place.
+        Some(unsafe {
+            Self::new(
+                framebuffer.address(),
+                usize::try_from(framebuffer.width()).unwrap(),
+                usize::try_from(framebuffer.height()).unwrap(),
+                usize::try_from(framebuffer.pitch()).unwrap(),
+                format,
+            )
+        })
+    }
 
-        Self {
-            framebuffer: Mutex::new(framebuffer),
-            backbuffer: RwLock::new(backbuffer),
-            dimensions,
-            scanline_width,
-        }
+    /// ### Safety
+    ///
+    /// `framebuffer` must point to `height` rows of `pitch` bytes, which are valid to write for the lifetime of the
+    /// driver, and aren't written by anything else.
+    pub unsafe fn new(
+        framebuffer: NonNull<u8>,
+        width: usize,
+        height: usize,
+        pitch: usize,
+        format: PixelFormat,
+    ) -> Self {
+        assert!(pitch >= width * format.bytes_per_pixel, "framebuffer rows are shorter than its width");
+
+        info!("Framebuffer {}x{} (pitch {}, {:?})", width, height, pitch, format);
+
+        Self { framebuffer, backbuffer: vec![Colors::Black.into(); width * height], width, height, pitch, format }
     }
 
-    pub fn write_pixel(&self, xy: (usize, usize), color: Color8i) {
-        if self.contains_point(xy) {
-            unsafe {
-                self.backbuffer
-                    .write()
-                    .add(self.point_to_offset(xy))
-                    .write_volatile(color)
-            };
-        } else {
-            panic!("point lies without framebuffer");
+    #[inline]
+    pub const fn width(&self) -> usize {
+        self.width
+    }
+
+    #[inline]
+    pub const fn height(&self) -> usize {
+        self.height
+    }
+
+    #[inline]
+    pub const fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    #[inline]
+    pub const fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    /// Pixels of the backbuffer, in rows of `width` pixels.
+    #[inline]
+    pub fn backbuffer_mut(&mut self) -> &mut [Color8i] {
+        &mut self.backbuffer
+    }
+
+    pub fn write_pixel(&mut self, xy: (usize, usize), color: Color8i) {
+        assert!(self.contains_point(xy), "point lies without framebuffer");
+
+        let offset = self.point_to_offset(xy);
+        self.backbuffer[offset] = color;
+    }
+
+    /// Fills the rectangle of `size` at `xy` with `color`, clipped to the framebuffer.
+    pub fn fill_rect(&mut self, xy: (usize, usize), size: (usize, usize), color: Color8i) {
+        let x_end = (xy.0 + size.0).min(self.width);
+        let y_end = (xy.1 + size.1).min(self.height);
+
+        for y in xy.1..y_end {
+            let row = self.point_to_offset((0, y));
+            self.backbuffer[(row + xy.0.min(x_end))..(row + x_end)].fill(color);
         }
     }
 
     pub fn clear(&mut self, color: Color8i) {
-        let backbuffer = self.backbuffer.write();
-        for y in 0..self.dimensions().height() {
-            for x in 0..self.dimensions().width() {
-                unsafe {
-                    backbuffer
-                        .add(self.point_to_offset((x, y)))
-                        .write_volatile(color)
+        self.backbuffer.fill(color);
+    }
+
+    /// Moves the contents of the backbuffer up by `rows`, filling the rows uncovered at the bottom with `color`.
+    pub fn scroll_up(&mut self, rows: usize, color: Color8i) {
+        let rows = rows.min(self.height);
+        let shifted = rows * self.width;
+
+        self.backbuffer.copy_within(shifted.., 0);
+        let len = self.backbuffer.len();
+        self.backbuffer[(len - shifted)..].fill(color);
+    }
+
+    /// Copies the rows `rows` of the backbuffer to the framebuffer.
+    pub fn flush_rows(&mut self, rows: Range<usize>) {
+        let rows = rows.start.min(self.height)..rows.end.min(self.height);
+        self.flush_rect(Rect::new(0, rows.start, self.width, rows.end.saturating_sub(rows.start)));
+    }
+
+    /// Copies the pixels of the backbuffer within `rect` (clipped to the framebuffer) to the framebuffer.
+    pub fn flush_rect(&mut self, rect: Rect) {
+        let Some(rect) = rect.intersection(&self.bounds()) else { return };
+
+        let bytes_per_pixel = self.format.bytes_per_pixel;
+
+        for y in rect.y..rect.bottom() {
+            let row_offset = self.point_to_offset((0, y));
+            let row = &self.backbuffer[(row_offset + rect.x)..(row_offset + rect.right())];
+            // Safety: The row lies within the framebuffer, as `y` is less than its height and rows are at least
+            //         `width * bytes_per_pixel` bytes long.
+            let span_ptr = unsafe { self.framebuffer.as_ptr().add((y * self.pitch) + (rect.x * bytes_per_pixel)) };
+
+            // Framebuffer memory may be write-combined, so it's written volatile. Whole pixels are written at once
+            // when they can be, as that's much faster than writing their bytes one by one.
+            if bytes_per_pixel == 4 && span_ptr.cast::<u32>().is_aligned() {
+                let span_ptr = span_ptr.cast::<u32>();
+
+                for (x, color) in row.iter().enumerate() {
+                    // Safety: See above.
+                    unsafe { span_ptr.add(x).write_volatile(self.format.encode(*color)) };
+                }
+            } else {
+                for (x, color) in row.iter().enumerate() {
+                    let pixel = self.format.encode(*color).to_le_bytes();
+
+                    for (index, byte) in pixel[..bytes_per_pixel].iter().enumerate() {
+                        // Safety: See above.
+                        unsafe { span_ptr.add((x * bytes_per_pixel) + index).write_volatile(*byte) };
+                    }
                 }
             }
         }
     }
 
-    /// Copy backbuffer to frontbuffer and zero backbuffer
+    /// Copies the whole backbuffer to the framebuffer.
     pub fn flush_pixels(&mut self) {
-        unsafe {
-            core::ptr::copy_nonoverlapping(
-                *self.backbuffer.read(),
-                *self.framebuffer.lock(),
-                self.dimensions().len(),
-            )
-        };
-
-        self.clear(Colors::Black.into());
-    }
-
-    pub const fn dimensions(&self) -> Size {
-        self.dimensions
+        self.flush_rows(0..self.height);
     }
 
     const fn point_to_offset(&self, point: (usize, usize)) -> usize {
-        (point.1 * self.scanline_width) + point.0
+        (point.1 * self.width) + point.0
     }
 
     const fn contains_point(&self, point: (usize, usize)) -> bool {
-        point.0 < self.dimensions().width() && point.1 < self.dimensions().height()
+        point.0 < self.width && point.1 < self.height
     }
 }
@@ -1,2 +1,7 @@
 pub mod color;
+pub mod compositor;
+pub mod console;
+pub mod display;
+pub mod font;
 pub mod framebuffer;
+pub mod rect;
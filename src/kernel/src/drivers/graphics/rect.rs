@@ -0,0 +1,69 @@
+/// An axis-aligned rectangle of pixels, with its origin at the top-left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self { x, y, width, height }
+    }
+
+    #[inline]
+    pub const fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    #[inline]
+    pub const fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    #[inline]
+    pub const fn area(&self) -> usize {
+        self.width * self.height
+    }
+
+    /// Moves the rectangle by `x` and `y`.
+    #[inline]
+    pub const fn offset(self, x: usize, y: usize) -> Self {
+        Self { x: self.x + x, y: self.y + y, ..self }
+    }
+
+    /// Returns the area covered by both rectangles, if there is any.
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+
+        (x < right && y < bottom).then(|| Self::new(x, y, right - x, bottom - y))
+    }
+
+    /// Returns the smallest rectangle which covers both rectangles.
+    pub fn union(&self, other: &Self) -> Self {
+        if self.is_empty() {
+            return *other;
+        } else if other.is_empty() {
+            return *self;
+        }
+
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+
+        Self::new(x, y, self.right().max(other.right()) - x, self.bottom().max(other.bottom()) - y)
+    }
+
+    /// Whether the rectangles overlap or share an edge.
+    pub const fn touches(&self, other: &Self) -> bool {
+        self.x <= other.right() && other.x <= self.right() && self.y <= other.bottom() && other.y <= self.bottom()
+    }
+}
@@ -1,6 +1,8 @@
 #![allow(unused)]
 
-// pub mod ahci;
-// pub mod graphics;
-// pub mod nvme;
+pub mod ahci;
+pub mod graphics;
+pub mod nvme;
+pub mod ps2;
 // pub mod sata;
+pub mod virtio;
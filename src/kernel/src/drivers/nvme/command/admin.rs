@@ -1,70 +1,71 @@
-use alloc::boxed::Box;
+use super::{Command, DataPointer};
 use bit_field::BitField;
-use core::{
-    fmt::{Debug, Formatter, Result},
-    marker::PhantomData,
-};
-use libsys::{Address, Physical};
-use num_enum::TryFromPrimitive;
-
-// #[repr(u8)]
-// #[derive(Debug, TryFromPrimitive)]
-// pub enum Opcode {
-//     DeleteIOSubmissionQueue = 0x0,
-//     CreateIOSubmissionQueue = 0x1,
-//     GetLogPage = 0x2,
-//     DeleteIOCompletionQueue = 0x4,
-//     CreateIOCompletionQueue = 0x5,
-//     Identify = 0x6,
-//     Abort = 0x8,
-//     SetFeatures = 0x9,
-//     GetFeatures = 0xA,
-//     AsyncEventRequest = 0xC,
-//     // TODO
-// }
-
-pub enum AdminCommand {
-    Identify { ctrl_id: u16 },
+use core::fmt::{Debug, Formatter, Result};
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+pub enum Opcode {
+    DeleteIOSubmissionQueue = 0x0,
+    CreateIOSubmissionQueue = 0x1,
+    GetLogPage = 0x2,
+    DeleteIOCompletionQueue = 0x4,
+    CreateIOCompletionQueue = 0x5,
+    Identify = 0x6,
+    Abort = 0x8,
+    SetFeatures = 0x9,
+    GetFeatures = 0xA,
+    AsyncEventRequest = 0xC,
 }
 
-impl AdminCommand {
-    pub const fn get_opcode(&self) -> u8 {
-        match self {
-            AdminCommand::Identify { ctrl_id } => 0x6,
-        }
-    }
+/// Controller or Namespace Structure, which selects the data returned by an Identify command.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy)]
+pub enum IdentifyKind {
+    Namespace = 0x0,
+    Controller = 0x1,
+    ActiveNamespaces = 0x2,
+}
+
+/// Identify command, which writes the structure selected by `kind` to the page at `page_address`.
+pub const fn identify(kind: IdentifyKind, ns_id: u32, page_address: u64) -> Command {
+    let mut command = Command::new(Opcode::Identify as u8, ns_id, DataPointer::new_prp(page_address, None));
+    command.cdw10 = kind as u32;
+
+    command
+}
+
+/// Creates I/O completion queue `id`, with `len` entries in the physically contiguous memory at `queue_address`.
+///
+/// If `int_vector` is provided, the queue raises that interrupt vector when completions are posted.
+pub fn create_io_completion_queue(id: u16, len: u16, queue_address: u64, int_vector: Option<u16>) -> Command {
+    let mut command = Command::new(Opcode::CreateIOCompletionQueue as u8, 0, DataPointer::new_prp(queue_address, None));
+    command.cdw10 = (u32::from(len - 1) << 16) | u32::from(id);
+    command.cdw11 = match int_vector {
+        Some(vector) => (u32::from(vector) << 16) | (1 << 1) | 1,
+        None => 1,
+    };
+
+    command
 }
 
-// impl super::Command<Admin> {
-//     pub const fn create_io_completion_queue(
-//         id: u16,
-//         len: u16,
-//         queue_ptr: super::DataPointer,
-//         phys_contiguous: bool,
-//         int_vector: Option<u16>,
-//     ) -> Self {
-//         Self {
-//             opcode: Opcode::CreateIOCompletionQueue as u8,
-//             fuse_psdt: ((super::PSDT::PRP as u8) << 6) | (super::FuseOperation::Normal as u8),
-//             command_id: 0, // TODO support this
-//             ns_id: 0,
-//             cdw2: 0,
-//             cdw3: 0,
-//             mdata_ptr: Address::zero(),
-//             data_ptr: queue_ptr,
-//             cdw10: ((len << 16) as u32) | (id as u32),
-//             cdw11: match int_vector {
-//                 Some(vector) => ((vector as u32) << 16) | (1 << 1) | (phys_contiguous as u32),
-//                 None => phys_contiguous as u32,
-//             },
-//             cdw12: 0,
-//             cdw13: 0,
-//             cdw14: 0,
-//             cdw15: 0,
-//             marker: PhantomData,
-//         }
-//     }
-// }
+/// Creates I/O submission queue `id`, with `len` entries in the physically contiguous memory at `queue_address`,
+/// which posts its completions to completion queue `completion_id`.
+pub fn create_io_submission_queue(id: u16, len: u16, queue_address: u64, completion_id: u16) -> Command {
+    let mut command = Command::new(Opcode::CreateIOSubmissionQueue as u8, 0, DataPointer::new_prp(queue_address, None));
+    command.cdw10 = (u32::from(len - 1) << 16) | u32::from(id);
+    command.cdw11 = (u32::from(completion_id) << 16) | 1;
+
+    command
+}
+
+/// Aborts the command `command_id` of submission queue `submission_id`. Bit 0 of the result is clear if the command
+/// was aborted, though the command still posts its own completion either way.
+pub fn abort(submission_id: u16, command_id: u16) -> Command {
+    let mut command = Command::new(Opcode::Abort as u8, 0, DataPointer::none());
+    command.cdw10 = (u32::from(command_id) << 16) | u32::from(submission_id);
+
+    command
+}
 
 #[repr(C, align(0x1000))]
 pub struct Identify {
@@ -78,8 +79,24 @@ pub struct Identify {
     cmic: u8,
     mdts: u8,
     controller_id: u16,
-    version: [u8; 3],
-    rsvd1: [u8; 4096 - 83],
+    version: u32,
+    rsvd0: [u8; 516 - 84],
+    namespace_count: u32,
+    rsvd1: [u8; 4096 - 520],
+}
+
+impl Identify {
+    /// Maximum data transfer size, as a power-of-two multiple of the minimum memory page size. `None` is unlimited.
+    pub fn max_transfer_shift(&self) -> Option<u32> {
+        match self.mdts {
+            0 => None,
+            mdts => Some(u32::from(mdts)),
+        }
+    }
+
+    pub const fn namespace_count(&self) -> u32 {
+        self.namespace_count
+    }
 }
 
 impl Debug for Identify {
@@ -88,26 +105,66 @@ impl Debug for Identify {
             .debug_struct("NVMe Controller Identify")
             .field("Vendor ID", &format_args!("0x{:X}", self.vendor_id))
             .field("Subsystem Vendor ID", &format_args!("0x{:X}", self.subsys_vendor_id))
-            .field("Serial Number", &core::str::from_utf8(&self.serial_number).unwrap())
-            .field("Model Number", &core::str::from_utf8(&self.model_number).unwrap())
-            .field("Firmware Revision", &core::str::from_utf8(&self.firmware_rev).unwrap())
+            .field("Serial Number", &core::str::from_utf8(&self.serial_number).map(str::trim_end))
+            .field("Model Number", &core::str::from_utf8(&self.model_number).map(str::trim_end))
+            .field("Firmware Revision", &core::str::from_utf8(&self.firmware_rev).map(str::trim_end))
             .field("Recommended Arbitration Burst", &format_args!("2^{}", self.rec_arb_burst))
             .field("IEEE OUI Identifier", &self.ieee)
             .field("Maybe Multiple Subsystem Port", &self.cmic.get_bit(0))
             .field("Maybe Multiple Controllers", &self.cmic.get_bit(1))
             .field("SR-IOV Virtual Function Association", &self.cmic.get_bit(2))
             .field("Asymmetric Namespace Access", &self.cmic.get_bit(3))
+            .field("Maximum Data Transfer Size", &self.max_transfer_shift().map(|shift| 1_u32 << shift))
             .field(
-                "Maximum Data Transfer Size",
+                "Version",
                 &format_args!(
-                    "{:?}",
-                    match self.mdts {
-                        0 => None,
-                        mdts => Some(2_u32.pow(self.mdts as u32)),
-                    }
+                    "{}.{}.{}",
+                    self.version.get_bits(16..32),
+                    self.version.get_bits(8..16),
+                    self.version.get_bits(0..8)
                 ),
             )
-            .field("Version", &format_args!("{}.{}.{}", self.version[0], self.version[1], self.version[2]))
+            .field("Namespaces", &self.namespace_count)
+            .finish()
+    }
+}
+
+#[repr(C, align(0x1000))]
+pub struct IdentifyNamespace {
+    size: u64,
+    capacity: u64,
+    utilization: u64,
+    features: u8,
+    lba_format_count: u8,
+    formatted_lba_size: u8,
+    rsvd0: [u8; 128 - 27],
+    lba_formats: [u32; 64],
+    rsvd1: [u8; 4096 - 384],
+}
+
+impl IdentifyNamespace {
+    /// Total size of the namespace, in logical blocks. Inactive namespaces have a size of `0`.
+    pub const fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Size of the namespace's logical blocks, in bytes.
+    pub fn block_size(&self) -> usize {
+        let format_index = usize::from(self.formatted_lba_size.get_bits(0..4));
+        let lba_data_shift = self.lba_formats[format_index].get_bits(16..24);
+
+        1 << lba_data_shift
+    }
+}
+
+impl Debug for IdentifyNamespace {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> Result {
+        formatter
+            .debug_struct("NVMe Namespace Identify")
+            .field("Size", &self.size)
+            .field("Capacity", &self.capacity)
+            .field("Utilization", &self.utilization)
+            .field("Block Size", &self.block_size())
             .finish()
     }
 }
@@ -1 +1,34 @@
+use super::{Command, DataPointer};
 
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+pub enum Opcode {
+    Flush = 0x0,
+    Write = 0x1,
+    Read = 0x2,
+}
+
+fn transfer(opcode: Opcode, ns_id: u32, lba: u64, block_count: u16, data_ptr: DataPointer) -> Command {
+    let mut command = Command::new(opcode as u8, ns_id, data_ptr);
+    command.cdw10 = u32::try_from(lba & u64::from(u32::MAX)).unwrap();
+    command.cdw11 = u32::try_from(lba >> 32).unwrap();
+    // Number of logical blocks is 0-based.
+    command.cdw12 = u32::from(block_count - 1);
+
+    command
+}
+
+/// Reads `block_count` logical blocks, starting at `lba`, into the memory described by `data_ptr`.
+pub fn read(ns_id: u32, lba: u64, block_count: u16, data_ptr: DataPointer) -> Command {
+    transfer(Opcode::Read, ns_id, lba, block_count, data_ptr)
+}
+
+/// Writes `block_count` logical blocks, starting at `lba`, from the memory described by `data_ptr`.
+pub fn write(ns_id: u32, lba: u64, block_count: u16, data_ptr: DataPointer) -> Command {
+    transfer(Opcode::Write, ns_id, lba, block_count, data_ptr)
+}
+
+/// Commits any volatile write cache contents for the namespace to non-volatile media.
+pub const fn flush(ns_id: u32) -> Command {
+    Command::new(Opcode::Flush as u8, ns_id, DataPointer::none())
+}
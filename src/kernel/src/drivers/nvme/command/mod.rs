@@ -2,10 +2,7 @@ pub mod admin;
 pub mod io;
 
 use bit_field::BitField;
-use core::{convert::TryFrom, fmt, marker::PhantomData, ops::Range};
-use libsys::{
-    memory::VolatileCell, volatile_bitfield_getter, volatile_bitfield_getter_as, Address, Physical, ReadWrite,
-};
+use core::fmt;
 use num_enum::TryFromPrimitive;
 
 pub trait QueueEntry: Copy {}
@@ -34,6 +31,7 @@ pub enum DataTransfer {
     Bidirectional = 0b11,
 }
 
+/// Physical Region Page entries describing the memory a command transfers to or from.
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct DataPointer(u64, u64);
@@ -43,16 +41,20 @@ impl DataPointer {
         Self(0, 0)
     }
 
-    pub const fn new_prp(addr0: Address<Physical>, addr1: Option<Address<Physical>>) -> Self {
-        Self(addr0.as_u64(), addr1.unwrap_or(Address::zero()).as_u64())
+    pub const fn new_prp(addr0: u64, addr1: Option<u64>) -> Self {
+        Self(
+            addr0,
+            match addr1 {
+                Some(addr1) => addr1,
+                None => 0,
+            },
+        )
     }
 }
 
+/// A submission queue entry.
 #[repr(C)]
-// TODO make this pub(super) and remove Q
-//      ^^^^^^^^^^^^^^^^^^^^^^^^^
-//      what does this even mean?
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy)]
 pub struct Command {
     pub opcode: u8,
     pub fuse_psdt: u8,
@@ -60,7 +62,7 @@ pub struct Command {
     pub ns_id: u32,
     pub cdw2: u32,
     pub cdw3: u32,
-    pub mdata_ptr: Address<Physical>,
+    pub mdata_ptr: u64,
     pub data_ptr: DataPointer,
     pub cdw10: u32,
     pub cdw11: u32,
@@ -71,7 +73,29 @@ pub struct Command {
 }
 
 impl QueueEntry for Command {}
+
 impl Command {
+    /// Creates a normal (unfused) command, which uses PRPs to describe its data. The command ID is assigned when the
+    /// command is submitted.
+    pub const fn new(opcode: u8, ns_id: u32, data_ptr: DataPointer) -> Self {
+        Self {
+            opcode,
+            fuse_psdt: ((PSDT::PRP as u8) << 6) | (FuseOperation::Normal as u8),
+            command_id: 0,
+            ns_id,
+            cdw2: 0,
+            cdw3: 0,
+            mdata_ptr: 0,
+            data_ptr,
+            cdw10: 0,
+            cdw11: 0,
+            cdw12: 0,
+            cdw13: 0,
+            cdw14: 0,
+            cdw15: 0,
+        }
+    }
+
     pub const fn opcode(&self) -> u8 {
         self.opcode
     }
@@ -101,39 +125,58 @@ pub enum GenericStatus {
     InvalidControllerMemoryBufferUsage = 0x12,
     PRPOffsetInvalid = 0x13,
     AtomicWriteUnitExceeded = 0x14,
+    LbaOutOfRange = 0x80,
+    CapacityExceeded = 0x81,
+    NamespaceNotReady = 0x82,
 }
 
-#[repr(u32)]
 #[derive(Debug)]
 pub enum StatusCode {
     Generic(GenericStatus),
     CommandSpecific(u8),
-    MediaAndDataIntegrityErrors, // 0x2
-    PathRelatedStatus,           // 0x3
-    VendorSpecific,              // 0x7
+    MediaAndDataIntegrityErrors(u8),
+    PathRelatedStatus(u8),
+    VendorSpecific(u8),
+    Unknown { ty: u8, code: u8 },
 }
 
+/// Status field of a completion queue entry, including the phase tag.
 #[repr(transparent)]
-pub struct CompletionStatus(u32);
+#[derive(Clone, Copy)]
+pub struct CompletionStatus(u16);
 
 impl CompletionStatus {
     // TODO implement CRD
 
-    pub fn dnr(&self) -> bool {
-        self.0.get_bit(31)
+    pub fn phase_tag(self) -> bool {
+        self.0.get_bit(0)
     }
 
-    pub fn more(&self) -> bool {
-        self.0.get_bit(30)
+    pub fn dnr(self) -> bool {
+        self.0.get_bit(15)
     }
 
-    pub fn status_code(&self) -> StatusCode {
-        match self.0.get_bits(25..28) {
-            0x0 => StatusCode::Generic(GenericStatus::try_from(self.0.get_bits(17..25)).unwrap()),
-            0x1 => StatusCode::CommandSpecific(self.0.get_bits(17..25) as u8),
-            // TODO implement Media and Data Integrity Errors
-            // TODO implement Path Related Status
-            value => panic!("Invalid status code type value: 0x{:X}", value),
+    pub fn more(self) -> bool {
+        self.0.get_bit(14)
+    }
+
+    pub fn is_success(self) -> bool {
+        self.0.get_bits(1..15) == 0
+    }
+
+    pub fn status_code(self) -> StatusCode {
+        let code = u8::try_from(self.0.get_bits(1..9)).unwrap();
+        let ty = u8::try_from(self.0.get_bits(9..12)).unwrap();
+
+        match ty {
+            0x0 => {
+                GenericStatus::try_from(u32::from(code)).map_or(StatusCode::Unknown { ty, code }, StatusCode::Generic)
+            }
+            0x1 => StatusCode::CommandSpecific(code),
+            0x2 => StatusCode::MediaAndDataIntegrityErrors(code),
+            0x3 => StatusCode::PathRelatedStatus(code),
+            0x7 => StatusCode::VendorSpecific(code),
+            ty => StatusCode::Unknown { ty, code },
         }
     }
 }
@@ -149,31 +192,44 @@ impl fmt::Debug for CompletionStatus {
     }
 }
 
+/// A completion queue entry.
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct CommandResult {
     dw0: u32,
     dw1: u32,
-    dw2: u32,
-    dw3: u32,
+    sq_head: u16,
+    sq_id: u16,
+    command_id: u16,
+    status: CompletionStatus,
 }
 
 impl QueueEntry for CommandResult {}
+
 impl CommandResult {
-    pub fn get_sub_queue_id(&self) -> u16 {
-        self.dw2.get_bits(16..32) as u16
+    /// Command-specific result value.
+    pub const fn get_value(&self) -> u32 {
+        self.dw0
+    }
+
+    pub const fn get_sub_queue_head(&self) -> u16 {
+        self.sq_head
+    }
+
+    pub const fn get_sub_queue_id(&self) -> u16 {
+        self.sq_id
     }
 
-    pub fn get_command_id(&self) -> u16 {
-        self.dw3.get_bits(0..16) as u16
+    pub const fn get_command_id(&self) -> u16 {
+        self.command_id
     }
 
     pub fn get_phase_tag(&self) -> bool {
-        self.dw3.get_bit(16)
+        self.status.phase_tag()
     }
 
-    pub fn get_status(&self) -> CompletionStatus {
-        CompletionStatus(self.dw3)
+    pub const fn get_status(&self) -> CompletionStatus {
+        self.status
     }
 }
 
@@ -182,6 +238,7 @@ impl fmt::Debug for CommandResult {
         formatter
             .debug_struct("NVMe Command Completed")
             .field("Submission Queue ID", &self.get_sub_queue_id())
+            .field("Submission Queue Head", &self.get_sub_queue_head())
             .field("Command ID", &self.get_command_id())
             .field("Phase Tag", &self.get_phase_tag())
             .field("Status", &self.get_status())
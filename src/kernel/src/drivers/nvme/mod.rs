@@ -1,21 +1,71 @@
 pub mod command;
 pub mod queue;
 
-use alloc::{boxed::Box, collections::BTreeMap};
+use crate::{
+    block,
+    mem::{alloc::pmm, dma::DmaBuffer, io::pci, HHDM},
+};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use bit_field::BitField;
-use core::{convert::TryFrom, fmt, marker::PhantomData, mem::MaybeUninit, sync::atomic::AtomicU16};
-use libsys::{
-    io::pci::{standard::StandardRegister, PCIeDevice, Standard},
-    memory::{
-        page_aligned_allocator,
-        volatile::{Volatile, VolatileCell},
-        PageAlignedBox,
-    },
-    sync::{SuccessSource, SuccessToken, ValuedSuccessToken},
-    volatile_bitfield_getter, volatile_bitfield_getter_ro, Address, Physical, ReadOnly, ReadWrite,
+use command::{
+    admin::{self, IdentifyKind},
+    io, Command, CommandResult, CompletionStatus, DataPointer,
+};
+use core::{
+    fmt,
+    num::NonZeroUsize,
+    ops::Range,
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, Ordering},
 };
+use libkernel::{mem::VolatileCell, ReadOnly, ReadWrite};
+use libsys::page_size;
 use num_enum::TryFromPrimitive;
-use spin::{Mutex, MutexGuard};
+use queue::{Completion, Queue, Submission};
+use spin::{Mutex, RwLock};
+
+crate::error_impl! {
+    #[derive(Debug)]
+    pub enum Error {
+        /// Indicates the controller's register BAR is missing, or isn't memory space.
+        InvalidBar => None,
+
+        /// Indicates the controller doesn't support the host's memory page size.
+        UnsupportedPageSize => None,
+
+        /// Indicates the controller reported a fatal status.
+        FatalStatus => None,
+
+        /// Indicates the controller didn't respond within its timeout.
+        Timeout => None,
+
+        /// Indicates the controller was disabled, as it couldn't be recovered after a command timed out.
+        Disabled => None,
+
+        /// Indicates a command completed with an error status.
+        CommandFailed { status: CompletionStatus } => None,
+
+        /// Indicates the controller has no active namespace with the provided ID.
+        UnknownNamespace { id: u32 } => None,
+
+        /// Indicates a transfer would extend past the end of the namespace.
+        OutOfBounds { lba: u64, count: u64 } => None,
+
+        /// Indicates a buffer's length isn't a multiple of the namespace's block size.
+        UnalignedBuffer { len: usize } => None,
+
+        /// Indicates there wasn't enough physical memory for the controller's queues or buffers.
+        OutOfMemory { err: pmm::Error } => None,
+
+        Pci { err: pci::Error } => Some(err)
+    }
+}
+
+impl From<pmm::Error> for Error {
+    fn from(err: pmm::Error) -> Self {
+        Self::OutOfMemory { err }
+    }
+}
 
 #[repr(u64)]
 #[derive(Debug, TryFromPrimitive)]
@@ -28,6 +78,7 @@ pub enum ControllerPowerScope {
 
 bitflags::bitflags! {
     #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct CommandSetsSupported: u8 {
         const NVM = 1 << 0;
         const IO = 1 << 6;
@@ -36,58 +87,98 @@ bitflags::bitflags! {
 }
 
 impl CommandSetsSupported {
+    /// Selects the command set to enable the controller with, preferring every supported I/O command set.
     pub fn into_command_set(self) -> CommandSet {
-        if self.contains(Self::ADMIN) {
-            CommandSet::Admin
-        } else if self.contains(Self::IO) {
+        if self.contains(Self::IO) {
             CommandSet::IO
         } else if self.contains(Self::NVM) {
             CommandSet::NVM
         } else {
-            panic!("Invalid state for CAP.CSS: {:?}", self)
+            CommandSet::Admin
         }
     }
 }
 
 #[repr(transparent)]
-pub struct Capabilities {
-    value: VolatileCell<u64, ReadOnly>,
-}
+pub struct Capabilities(VolatileCell<u64, ReadOnly>);
 
 /// NVME Capabilities Register
 /// An explanation of these values can be found at:
 ///     https://nvmexpress.org/wp-content/uploads/NVMe-NVM-Express-2.0a-2021.07.26-Ratified.pdf
 ///     Figure 36
 impl Capabilities {
-    volatile_bitfield_getter_ro!(value, u64, mqes, 0..16);
-    volatile_bitfield_getter_ro!(value, cqr, 16);
-    volatile_bitfield_getter_ro!(value, u64, ams, 17..19);
+    fn get_field(&self, range: Range<usize>) -> u64 {
+        self.0.read().get_bits(range)
+    }
+
+    pub fn get_mqes(&self) -> u16 {
+        u16::try_from(self.get_field(0..16)).unwrap()
+    }
+
+    pub fn get_cqr(&self) -> bool {
+        self.0.read().get_bit(16)
+    }
+
+    pub fn get_ams(&self) -> u8 {
+        u8::try_from(self.get_field(17..19)).unwrap()
+    }
+
     // 19..24 reserved
-    volatile_bitfield_getter_ro!(value, u64, to, 24..32);
-    volatile_bitfield_getter_ro!(value, u64, dstrd, 32..36);
-    volatile_bitfield_getter_ro!(value, nssrs, 36);
+
+    pub fn get_to(&self) -> u8 {
+        u8::try_from(self.get_field(24..32)).unwrap()
+    }
+
+    pub fn get_dstrd(&self) -> u8 {
+        u8::try_from(self.get_field(32..36)).unwrap()
+    }
+
+    pub fn get_nssrs(&self) -> bool {
+        self.0.read().get_bit(36)
+    }
 
     pub fn get_css(&self) -> CommandSetsSupported {
-        CommandSetsSupported::from_bits_truncate(self.value.read().get_bits(37..45) as u8)
+        CommandSetsSupported::from_bits_truncate(u8::try_from(self.get_field(37..45)).unwrap())
     }
 
-    volatile_bitfield_getter_ro!(value, bps, 45);
+    pub fn get_bps(&self) -> bool {
+        self.0.read().get_bit(45)
+    }
 
     pub fn get_cps(&self) -> ControllerPowerScope {
-        ControllerPowerScope::try_from(self.value.read().get_bits(46..48)).unwrap()
+        ControllerPowerScope::try_from(self.get_field(46..48)).unwrap()
     }
 
-    volatile_bitfield_getter_ro!(value, u64, mpsmin, 48..52);
-    volatile_bitfield_getter_ro!(value, u64, mpsmax, 52..56);
-    volatile_bitfield_getter_ro!(value, pmrs, 56);
-    volatile_bitfield_getter_ro!(value, cmbs, 57);
-    volatile_bitfield_getter_ro!(value, nsss, 58);
-    volatile_bitfield_getter_ro!(value, crwms, 59);
-    volatile_bitfield_getter_ro!(value, crims, 60);
-    // 60..64 reserved
-}
+    pub fn get_mpsmin(&self) -> u8 {
+        u8::try_from(self.get_field(48..52)).unwrap()
+    }
+
+    pub fn get_mpsmax(&self) -> u8 {
+        u8::try_from(self.get_field(52..56)).unwrap()
+    }
+
+    pub fn get_pmrs(&self) -> bool {
+        self.0.read().get_bit(56)
+    }
+
+    pub fn get_cmbs(&self) -> bool {
+        self.0.read().get_bit(57)
+    }
+
+    pub fn get_nsss(&self) -> bool {
+        self.0.read().get_bit(58)
+    }
+
+    pub fn get_crwms(&self) -> bool {
+        self.0.read().get_bit(59)
+    }
+
+    pub fn get_crims(&self) -> bool {
+        self.0.read().get_bit(60)
+    }
 
-impl Volatile for Capabilities {}
+    // 61..64 reserved
+}
 
 impl fmt::Debug for Capabilities {
     fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -117,20 +208,18 @@ pub struct Version(VolatileCell<u32, ReadOnly>);
 
 impl Version {
     pub fn major(&self) -> u16 {
-        self.0.read().get_bits(16..32) as u16
+        u16::try_from(self.0.read().get_bits(16..32)).unwrap()
     }
 
     pub fn minor(&self) -> u8 {
-        self.0.read().get_bits(8..16) as u8
+        u8::try_from(self.0.read().get_bits(8..16)).unwrap()
     }
 
     pub fn tertiary(&self) -> u8 {
-        self.0.read().get_bits(0..8) as u8
+        u8::try_from(self.0.read().get_bits(0..8)).unwrap()
     }
 }
 
-impl Volatile for Version {}
-
 impl fmt::Debug for Version {
     fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
         formatter.debug_tuple("Version").field(&self.major()).field(&self.minor()).field(&self.tertiary()).finish()
@@ -162,63 +251,71 @@ pub enum ShutdownNotification {
 }
 
 #[repr(transparent)]
-pub struct ControllerConfiguration {
-    value: VolatileCell<u32, ReadWrite>,
-}
-
-impl Volatile for ControllerConfiguration {}
+pub struct ControllerConfiguration(VolatileCell<u32, ReadWrite>);
 
 impl ControllerConfiguration {
-    volatile_bitfield_getter!(value, en, 0);
+    fn set_field(&self, range: Range<usize>, value: u32) {
+        let mut register = self.0.read();
+        register.set_bits(range, value);
+        self.0.write(register);
+    }
+
+    pub fn get_en(&self) -> bool {
+        self.0.read().get_bit(0)
+    }
+
+    pub fn set_en(&self, enabled: bool) {
+        self.set_field(0..1, u32::from(enabled));
+    }
 
     pub fn get_css(&self) -> CommandSet {
-        CommandSet::try_from(self.value.read().get_bits(4..7)).expect("CSS is reserved value")
+        CommandSet::try_from(self.0.read().get_bits(4..7)).expect("CSS is reserved value")
     }
 
     pub fn set_css(&self, command_set: CommandSet) {
-        self.value.write(*self.value.read().set_bits(4..7, command_set as u32))
+        self.set_field(4..7, command_set as u32);
     }
 
     pub fn get_mps(&self) -> u32 {
-        self.value.read().get_bits(7..11)
+        self.0.read().get_bits(7..11)
     }
 
     pub fn set_mps(&self, mps: u32) {
         assert!(mps < 0b10000, "Provided memory page size must be no more than 4 bits.");
         assert!(!self.get_en(), "Memory page size may only be set when controller is not enabled.");
-        self.value.write(*self.value.read().set_bits(7..11, mps));
+        self.set_field(7..11, mps);
     }
 
     pub fn get_ams(&self) -> ArbitrationMechanism {
-        ArbitrationMechanism::try_from(self.value.read().get_bits(11..14)).expect("AMS is reserved value")
+        ArbitrationMechanism::try_from(self.0.read().get_bits(11..14)).expect("AMS is reserved value")
     }
 
     pub fn set_ams(&self, ams: ArbitrationMechanism) {
-        self.value.write(*self.value.read().set_bits(11..14, ams as u32))
+        self.set_field(11..14, ams as u32);
     }
 
     pub fn get_shn(&self) -> ShutdownNotification {
-        ShutdownNotification::try_from(self.value.read().get_bits(14..16)).expect("SHN is resrved value")
+        ShutdownNotification::try_from(self.0.read().get_bits(14..16)).expect("SHN is resrved value")
     }
 
     pub fn set_shn(&self, shn: ShutdownNotification) {
-        self.value.write(*self.value.read().set_bits(14..16, shn as u32))
+        self.set_field(14..16, shn as u32);
     }
 
     pub fn get_iosqes(&self) -> u32 {
-        self.value.read().get_bits(16..20)
+        self.0.read().get_bits(16..20)
     }
 
     pub fn set_iosqes(&self, iosqes: u32) {
-        self.value.write(*self.value.read().set_bits(16..20, iosqes))
+        self.set_field(16..20, iosqes);
     }
 
     pub fn get_iocqes(&self) -> u32 {
-        self.value.read().get_bits(20..24)
+        self.0.read().get_bits(20..24)
     }
 
     pub fn set_iocqes(&self, iocqes: u32) {
-        self.value.write(*self.value.read().set_bits(20..24, iocqes))
+        self.set_field(20..24, iocqes);
     }
 
     // TODO CC.CRIME
@@ -248,23 +345,32 @@ pub enum ShutdownStatus {
 }
 
 #[repr(transparent)]
-pub struct ControllerStatus {
-    value: VolatileCell<u32, ReadOnly>,
-}
-
-impl Volatile for ControllerStatus {}
+pub struct ControllerStatus(VolatileCell<u32, ReadOnly>);
 
 impl ControllerStatus {
-    volatile_bitfield_getter_ro!(value, rdy, 0);
-    volatile_bitfield_getter_ro!(value, cfs, 1);
+    pub fn get_rdy(&self) -> bool {
+        self.0.read().get_bit(0)
+    }
+
+    pub fn get_cfs(&self) -> bool {
+        self.0.read().get_bit(1)
+    }
 
     pub fn get_shst(&self) -> ShutdownStatus {
-        ShutdownStatus::try_from(self.value.read().get_bits(2..4)).expect("SHST is reserved value")
+        ShutdownStatus::try_from(self.0.read().get_bits(2..4)).expect("SHST is reserved value")
+    }
+
+    pub fn get_nssro(&self) -> bool {
+        self.0.read().get_bit(4)
+    }
+
+    pub fn get_pp(&self) -> bool {
+        self.0.read().get_bit(5)
     }
 
-    volatile_bitfield_getter_ro!(value, nssro, 4);
-    volatile_bitfield_getter_ro!(value, pp, 5);
-    volatile_bitfield_getter_ro!(value, st, 6);
+    pub fn get_st(&self) -> bool {
+        self.0.read().get_bit(6)
+    }
 }
 
 impl fmt::Debug for ControllerStatus {
@@ -287,282 +393,646 @@ pub struct InterruptMask {
     clear: VolatileCell<u32, ReadWrite>,
 }
 
-impl Volatile for InterruptMask {}
-
 impl InterruptMask {
+    // Both registers are write-1-to-apply, so only the affected vector's bit is written.
+
     pub fn mask_vector(&self, index: usize) {
         assert!(index < 32, "Index must be 0..32.");
-        self.set.write(*self.set.read().set_bit(index, true));
+        self.set.write(1 << index);
     }
+
     pub fn unmask_vector(&self, index: usize) {
         assert!(index < 32, "Index must be 0..32.");
-        self.clear.write(*self.clear.read().set_bit(index, true));
+        self.clear.write(1 << index);
     }
+
     pub fn raw_bits_str(&self) -> alloc::string::String {
         alloc::format!("{:b}", self.set.read())
     }
 }
 
-// TODO proper core::error::Error impl
-#[derive(Debug)]
-pub enum ControllerEnableError {
-    FatalStatus,
-    NoReady,
-}
-
-pub struct Controller<'dev> {
-    device: &'dev PCIeDevice<Standard>,
-    msix: libsys::io::pci::standard::MSIX<'dev>,
-    next_sub_queue_id: AtomicU16,
-    next_com_queue_id: AtomicU16,
-    admin_sub: Mutex<queue::Queue<'dev, queue::Submission>>,
-    admin_com: Mutex<queue::Queue<'dev, queue::Completion>>,
-    pending_cmds: Mutex<BTreeMap<u16, SuccessSource>>,
-}
+/// The controller's memory-mapped registers (the first memory BAR).
+#[derive(Clone, Copy)]
+struct Registers(NonNull<u8>);
 
-impl<'dev> Controller<'dev> {
+impl Registers {
     const CAP: usize = 0x0;
     const VER: usize = 0x8;
     const INTMS: usize = 0xC;
-    const INTMC: usize = 0x10;
     const CC: usize = 0x14;
     const CSTS: usize = 0x1C;
     const AQA: usize = 0x24;
     const ASQ: usize = 0x28;
     const ACQ: usize = 0x30;
 
-    pub fn from_device_and_configure(
-        device: &'dev PCIeDevice<Standard>,
-        sub_entry_count: u16,
-        com_entry_count: u16,
-    ) -> Self {
-        let nvme = {
-            let reg0 = device.get_register(StandardRegister::Register0).unwrap();
-
-            let admin_sub = queue::Queue::<queue::Submission>::new(reg0, 0, sub_entry_count);
-            let admin_com = queue::Queue::<queue::Completion>::new(reg0, 0, com_entry_count);
-            reg0.write(Self::ASQ, admin_sub.get_phys_addr().as_u64());
-            reg0.write(Self::ACQ, admin_com.get_phys_addr().as_u64());
-            reg0.write(Self::AQA, ((com_entry_count as u32) << 16) | (sub_entry_count as u32));
-
-            Self {
-                device,
-                msix: device.find_msix().expect("MSI-X is required for NVMe controller creation."),
-                next_sub_queue_id: AtomicU16::new(1),
-                next_com_queue_id: AtomicU16::new(1),
-                admin_sub: Mutex::new(admin_sub),
-                admin_com: Mutex::new(admin_com),
-                pending_cmds: Mutex::new(BTreeMap::new()),
-            }
-        };
-
-        unsafe {
-            nvme.set_enable_and_wait(false).expect("NVMe controller failed to reset");
-        }
-        debug!("NVMe controller successfully reset.");
-
-        let cc = nvme.config();
-        cc.set_css(nvme.capabilities().get_css().into_command_set());
-        cc.set_ams(ArbitrationMechanism::RoundRobin);
-        cc.set_mps(0); // 4KiB pages
-        cc.set_iosqes(6); // 64 bytes (2^6)
-        cc.set_iocqes(4); // 16 bytes (2^4)
-
-        // Configure MSI-X for admin completion queue.
-        // REMARK:  This needs to be before the enable, as QEMU tracks
-        //          driver message IRQ usage internally, and doesn't
-        //          'use' the first interrupt message if MSI-X isn't
-        //          enabled when the controller starts.
-        //
-        //          I'm unsure what behaviour exists on real hardware.
-        nvme.msix.set_enable(true);
-        nvme.msix.set_function_mask(false);
-        nvme.msix[0].configure(
-            unsafe { crate::cpu::get_id() as u8 },
-            // Specific vector should be dynamically selected
-            // TODO possibly dynamically selected with special attributes per vector?
-            //      i.e. separate interrupts for completions, DMA, etc.
-            //      or a single interrupts per device? <<< this seems limiting
-            crate::interrupts::Vector::Storage0 as u8,
-            libsys::InterruptDeliveryMode::Fixed,
-        );
-        nvme.msix[0].set_masked(false);
-
-        unsafe {
-            nvme.set_enable_and_wait(true).expect("NVMe driver failed to enable");
-        }
-
-        nvme
+    /// ### Safety
+    ///
+    /// Caller must ensure a `T` is the register at `offset`.
+    unsafe fn borrow<T>(&self, offset: usize) -> &T {
+        &*self.0.as_ptr().add(offset).cast::<T>()
     }
 
     pub fn capabilities(&self) -> &Capabilities {
-        unsafe { self.device.get_register(StandardRegister::Register0).unwrap().borrow(Self::CAP) }
+        // Safety: Offset is specified by the NVMe base specification.
+        unsafe { self.borrow(Self::CAP) }
     }
 
     pub fn version(&self) -> &Version {
-        unsafe { self.device.get_register(StandardRegister::Register0).unwrap().borrow(Self::VER) }
+        // Safety: Offset is specified by the NVMe base specification.
+        unsafe { self.borrow(Self::VER) }
     }
 
     pub fn interrupt_mask(&self) -> &InterruptMask {
-        unsafe { self.device.get_register(StandardRegister::Register0).unwrap().borrow(Self::INTMS) }
+        // Safety: Offset is specified by the NVMe base specification.
+        unsafe { self.borrow(Self::INTMS) }
     }
 
     pub fn config(&self) -> &ControllerConfiguration {
-        unsafe { self.device.get_register(StandardRegister::Register0).unwrap().borrow(Self::CC) }
+        // Safety: Offset is specified by the NVMe base specification.
+        unsafe { self.borrow(Self::CC) }
     }
 
     pub fn status(&self) -> &ControllerStatus {
-        unsafe { self.device.get_register(StandardRegister::Register0).unwrap().borrow(Self::CSTS) }
+        // Safety: Offset is specified by the NVMe base specification.
+        unsafe { self.borrow(Self::CSTS) }
+    }
+
+    /// Programs the admin queue attributes and base addresses. The controller must be disabled.
+    fn set_admin_queues(&self, admin: &QueuePair) {
+        assert!(!self.config().get_en(), "Admin queues may only be set when controller is not enabled.");
+
+        let len = u32::from(admin.len - 1);
+
+        // Safety: Offsets are specified by the NVMe base specification.
+        unsafe {
+            self.borrow::<VolatileCell<u32, ReadWrite>>(Self::AQA).write((len << 16) | len);
+            self.borrow::<VolatileCell<u64, ReadWrite>>(Self::ASQ).write(admin.submission.get_phys_addr());
+            self.borrow::<VolatileCell<u64, ReadWrite>>(Self::ACQ).write(admin.completion.get_phys_addr());
+        }
     }
 
-    pub unsafe fn set_enable_and_wait(&self, enabled: bool) -> Result<(), ControllerEnableError> {
+    fn set_enable_and_wait(&self, enabled: bool) -> Result<()> {
+        const POLL_INTERVAL_MS: u32 = 10;
+
         debug!("Resetting controller to enabled state: {enabled}.");
         self.config().set_en(enabled);
         let csts = self.status();
-        let max_wait = self.capabilities().get_to() * 500;
+        // CAP.TO is in units of 500ms.
+        let max_wait = u32::from(self.capabilities().get_to()) * 500;
         let mut msec_waited = 0;
 
         debug!("Waiting up to {}ms for controller to finalize enable state.", max_wait);
         while csts.get_rdy() != enabled && !csts.get_cfs() && msec_waited < max_wait {
-            const SLEEP_INTERVAL: u64 = 100;
-
-            crate::clock::busy_wait_msec(SLEEP_INTERVAL);
-            msec_waited += SLEEP_INTERVAL;
+            crate::time::SYSTEM_CLOCK.spin_wait_us(POLL_INTERVAL_MS * 1000);
+            msec_waited += POLL_INTERVAL_MS;
         }
 
         if csts.get_cfs() {
-            Err(ControllerEnableError::FatalStatus)
+            Err(Error::FatalStatus)
         } else if csts.get_rdy() != enabled {
-            Err(ControllerEnableError::NoReady)
+            Err(Error::Timeout)
         } else {
             Ok(())
         }
     }
+}
+
+/// A submission queue, and the completion queue its commands complete to.
+struct QueuePair {
+    submission: Queue<Submission>,
+    completion: Queue<Completion>,
+    len: u16,
+    next_command_id: u16,
+}
+
+impl QueuePair {
+    const COMMAND_TIMEOUT_US: u32 = 5_000_000;
+    const POLL_INTERVAL_US: u32 = 10;
+
+    /// ### Safety
+    ///
+    /// Caller must ensure `registers` remain valid for the lifetime of the queues.
+    unsafe fn new(registers: Registers, id: u16, len: u16) -> Result<Self> {
+        let doorbell_stride = usize::from(registers.capabilities().get_dstrd());
+
+        Ok(Self {
+            submission: Queue::new(registers.0, doorbell_stride, id, len)?,
+            completion: Queue::new(registers.0, doorbell_stride, id, len)?,
+            len,
+            next_command_id: 0,
+        })
+    }
 
-    fn next_command_id(pending_cmds: &MutexGuard<BTreeMap<u16, SuccessSource>>) -> u16 {
-        // TODO optimize this
-        let mut command_id = u16::MAX;
-        for id in u16::MIN..u16::MAX {
-            if !pending_cmds.contains_key(&id) {
-                command_id = id;
-                break;
+    /// Submits `command`, and polls for its completion.
+    ///
+    /// Completions are polled rather than signalled by interrupts. Commands which time out must be recovered from
+    /// before the next is submitted (see [`Controller::recover`]), so only one command is ever outstanding, and the
+    /// queues can never overflow.
+    fn execute(&mut self, command: Command) -> Result<CommandResult> {
+        let command_id = self.submit(command);
+        self.wait(command_id)
+    }
+
+    /// Submits `command`, returning the ID it was assigned.
+    fn submit(&mut self, mut command: Command) -> u16 {
+        command.command_id = self.next_command_id;
+        self.next_command_id = self.next_command_id.wrapping_add(1);
+        self.submission.submit_command(command);
+
+        command.command_id
+    }
+
+    /// Polls for the completion of the command `command_id`.
+    fn wait(&mut self, command_id: u16) -> Result<CommandResult> {
+        for _ in 0..(Self::COMMAND_TIMEOUT_US / Self::POLL_INTERVAL_US) {
+            match self.completion.next_cmd_result() {
+                Some(result) if result.get_command_id() == command_id => {
+                    let status = result.get_status();
+
+                    return if status.is_success() { Ok(result) } else { Err(Error::CommandFailed { status }) };
+                }
+
+                // Completion of a command which previously timed out.
+                Some(_) => {}
+
+                None => crate::time::SYSTEM_CLOCK.spin_wait_us(Self::POLL_INTERVAL_US),
             }
         }
 
-        if command_id == u16::MAX {
-            panic!("No more command IDs available.");
-        } else {
-            command_id
+        Err(Error::Timeout)
+    }
+
+    /// Returns the queues to their initial state, after the controller has been reset.
+    fn reset(&mut self) {
+        self.submission.reset();
+        self.completion.reset();
+    }
+}
+
+/// The I/O queues, along with the bounce buffer that data is transferred through.
+struct IoQueue {
+    queues: QueuePair,
+    buffer: DmaBuffer,
+    /// PRP list describing every page of `buffer` after the first.
+    prp_list: DmaBuffer,
+}
+
+impl IoQueue {
+    /// Pages in the bounce buffer, which bounds the size of a single transfer.
+    const BUFFER_PAGES: usize = 32;
+
+    /// ### Safety
+    ///
+    /// Caller must ensure `registers` remain valid for the lifetime of the queues.
+    unsafe fn new(registers: Registers, id: u16, len: u16) -> Result<Self> {
+        let buffer = DmaBuffer::new(NonZeroUsize::new(Self::BUFFER_PAGES).unwrap(), None)?;
+        let prp_list = DmaBuffer::new(NonZeroUsize::MIN, None)?;
+
+        let entries = prp_list.as_ptr().as_ptr().cast::<u64>();
+        for page_index in 1..Self::BUFFER_PAGES {
+            entries.add(page_index - 1).write(buffer.physical_address(page_index * page_size()));
         }
+
+        Ok(Self { queues: QueuePair::new(registers, id, len)?, buffer, prp_list })
+    }
+
+    /// Describes the first `len` bytes of the bounce buffer to the controller.
+    fn data_pointer(&self, len: usize) -> DataPointer {
+        let first_page = self.buffer.physical_address(0);
+
+        match libsys::align_up_div(len, libsys::page_shift()) {
+            0 | 1 => DataPointer::new_prp(first_page, None),
+            2 => DataPointer::new_prp(first_page, Some(self.buffer.physical_address(page_size()))),
+            _ => DataPointer::new_prp(first_page, Some(self.prp_list.physical_address(0))),
+        }
+    }
+}
+
+/// An active namespace of a controller, which serves logical block reads and writes.
+#[derive(Debug, Clone, Copy)]
+pub struct Namespace {
+    id: u32,
+    block_size: usize,
+    block_count: u64,
+}
+
+impl Namespace {
+    #[inline]
+    pub const fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Size of the namespace's logical blocks, in bytes.
+    #[inline]
+    pub const fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    #[inline]
+    pub const fn block_count(&self) -> u64 {
+        self.block_count
     }
+}
 
-    pub fn submit_admin_command(&self, command: command::admin::AdminCommand) -> PendingCommand {
-        let mut pending_cmds = self.pending_cmds.lock();
-        let command_id = Self::next_command_id(&pending_cmds);
+pub struct Controller {
+    claim: pci::Claim,
+    registers: Registers,
+    admin: Mutex<QueuePair>,
+    io: Mutex<IoQueue>,
+    /// Whether the controller was disabled, as a timed out command couldn't be stopped. The bounce buffer may still
+    /// be written by the controller, so no further transfers are made through it.
+    disabled: AtomicBool,
+    /// Number of bounce buffer pages a single transfer may use, which may be limited by the controller.
+    max_transfer_pages: usize,
+    namespaces: Vec<Namespace>,
+}
+
+// Safety: Registers are accessed through the global HHDM, and queues are only accessed behind their locks.
+unsafe impl Send for Controller {}
+// Safety: See above.
+unsafe impl Sync for Controller {}
+
+impl Controller {
+    const ADMIN_QUEUE_LEN: u16 = 32;
+    const IO_QUEUE_LEN: u16 = 64;
+    const IO_QUEUE_ID: u16 = 1;
+
+    /// Resets and enables the claimed controller, creates its I/O queues, and identifies its namespaces.
+    pub fn new(claim: pci::Claim) -> Result<Self> {
+        let bar = claim.with_device(|device| {
+            // Completions are polled, so the device's pin interrupt is disabled.
+            let mut command = device.get_command();
+            command.insert(pci::Command::MEMORY_SPACE | pci::Command::BUS_MASTER | pci::Command::INTERRUPT_DISABLE);
+            device.set_command(command);
+
+            device.get_bar(0).ok()
+        });
+
+        let registers_address = match bar {
+            Some(bar @ (pci::Bar::MemorySpace32 { .. } | pci::Bar::MemorySpace64 { .. })) if !bar.is_unused() => {
+                bar.get_address().get() & !0xF
+            }
 
-        use command::{
-            admin::{AdminCommand, Identify},
-            Command, DataPointer, FuseOperation, PSDT,
+            _ => return Err(Error::InvalidBar),
         };
+        // Safety: Like PCI configuration space, the controller's registers are accessed through the HHDM.
+        let registers = Registers(NonNull::new(unsafe { HHDM.ptr().add(registers_address) }).unwrap());
+
+        // Queues and PRPs are laid out in host pages, so the controller must support them.
+        let capabilities = registers.capabilities();
+        if (page_size() >> 12) < (1 << capabilities.get_mpsmin())
+            || (page_size() >> 12) > (1 << capabilities.get_mpsmax())
+        {
+            return Err(Error::UnsupportedPageSize);
+        }
+
+        registers.set_enable_and_wait(false)?;
+        debug!("NVMe controller successfully reset.");
+
+        // Every queue is allocated before the controller is enabled, so none are freed while it may be using them.
+        let max_queue_len = capabilities.get_mqes().saturating_add(1);
+        // Safety: Registers are valid for the lifetime of the controller, which owns the queues.
+        let mut admin = unsafe { QueuePair::new(registers, 0, Self::ADMIN_QUEUE_LEN.min(max_queue_len)) }?;
+        // Safety: See above.
+        let io = unsafe { IoQueue::new(registers, Self::IO_QUEUE_ID, Self::IO_QUEUE_LEN.min(max_queue_len)) }?;
+        let identify_page = DmaBuffer::new(NonZeroUsize::MIN, None)?;
+
+        registers.set_admin_queues(&admin);
+
+        let cc = registers.config();
+        cc.set_css(capabilities.get_css().into_command_set());
+        cc.set_ams(ArbitrationMechanism::RoundRobin);
+        cc.set_mps(libsys::page_shift().get() - 12);
+        cc.set_iosqes(6); // 64 bytes (2^6)
+        cc.set_iocqes(4); // 16 bytes (2^4)
 
-        let opcode = command.get_opcode();
-        match command {
-            AdminCommand::Identify { ctrl_id } => {
-                // Allocate the necessary memory for returning the command value.
-                let memory = PageAlignedBox::<Identify>::new_uninit_in(page_aligned_allocator());
-                let phys_addr = Address::<Physical>::new(
-                    crate::memory::get_kernel_page_manager()
-                        .get_mapped_to(&libsys::memory::Page::from_ptr(memory.as_ptr()))
-                        .unwrap(),
-                );
-
-                // Construct the command with the provided data.
-                let command = Command {
-                    opcode,
-                    fuse_psdt: ((PSDT::PRP as u8) << 6) | (FuseOperation::Normal as u8),
-                    command_id,
-                    ns_id: 0,
-                    cdw2: 0,
-                    cdw3: 0,
-                    mdata_ptr: Address::zero(),
-                    data_ptr: DataPointer::new_prp(phys_addr, None),
-                    cdw10: ((ctrl_id as u32) << 16) | 0b1, // TODO implement CNS
-                    cdw11: 0,                              // Ensure CSI or CNS Specific Identifier are not required,
-                    cdw12: 0,
-                    cdw13: 0,
-                    cdw14: 0, // Ensure no UUID is required, or possibly allow providing one (?)
-                    cdw15: 0,
-                };
-
-                // Create the success synchronization.
-                let (success_source, success_token) = SuccessSource::new_valued(unsafe { memory });
-
-                // Pend command success synchronization, and submit.
-                pending_cmds.insert(command_id, success_source);
-                self.admin_sub.lock().submit_command(command);
-
-                PendingCommand::Identify(success_token)
+        let configured =
+            registers.set_enable_and_wait(true).and_then(|()| Self::configure(&mut admin, &io, &identify_page));
+
+        match configured {
+            Ok((max_transfer_pages, namespaces)) => Ok(Self {
+                claim,
+                registers,
+                admin: Mutex::new(admin),
+                io: Mutex::new(io),
+                disabled: AtomicBool::new(false),
+                max_transfer_pages,
+                namespaces,
+            }),
+
+            Err(err) => {
+                // Ensure the controller stops accessing the queues before they're freed.
+                registers.set_enable_and_wait(false).ok();
+
+                Err(err)
             }
         }
     }
 
-    // TODO submit_command to use lpu::processor_id to index the submission and completion queues
+    /// Identifies the controller and its active namespaces, and creates the I/O queues. Returns the number of pages a
+    /// single transfer may use, and the namespaces.
+    fn configure(admin: &mut QueuePair, io: &IoQueue, identify_page: &DmaBuffer) -> Result<(usize, Vec<Namespace>)> {
+        admin.execute(admin::identify(IdentifyKind::Controller, 0, identify_page.physical_address(0)))?;
+        // Safety: The command has completed, and the page holds the controller's identify structure.
+        let identify = unsafe { &*identify_page.as_ptr().as_ptr().cast::<admin::Identify>() };
+        debug!("{:#?}", identify);
+
+        let max_transfer_pages =
+            identify.max_transfer_shift().map_or(IoQueue::BUFFER_PAGES, |shift| IoQueue::BUFFER_PAGES.min(1 << shift));
+
+        Self::create_io_queues(admin, io)?;
+
+        admin.execute(admin::identify(IdentifyKind::ActiveNamespaces, 0, identify_page.physical_address(0)))?;
+        // Safety: The command has completed, and the page holds the (zero-terminated) list of active namespace IDs.
+        let namespace_ids = unsafe { core::slice::from_raw_parts(identify_page.as_ptr().as_ptr().cast::<u32>(), 1024) }
+            .iter()
+            .copied()
+            .take_while(|id| *id != 0)
+            .collect::<Vec<_>>();
+
+        let mut namespaces = Vec::with_capacity(namespace_ids.len());
+        for id in namespace_ids {
+            admin.execute(admin::identify(IdentifyKind::Namespace, id, identify_page.physical_address(0)))?;
+            // Safety: The command has completed, and the page holds the namespace's identify structure.
+            let identify = unsafe { &*identify_page.as_ptr().as_ptr().cast::<admin::IdentifyNamespace>() };
+            trace!("Namespace {}: {:#?}", id, identify);
+
+            if identify.size() > 0 {
+                namespaces.push(Namespace { id, block_size: identify.block_size(), block_count: identify.size() });
+            }
+        }
+
+        Ok((max_transfer_pages, namespaces))
+    }
 
-    pub fn run(&self) -> ! {
-        loop {
-            let mut admin_com = self.admin_com.lock();
-            if let Some(cmd_result) = admin_com.next_cmd_result() {
-                let mut pending_cmds = self.pending_cmds.lock();
-                let success_source = pending_cmds
-                    .remove(&cmd_result.get_command_id())
-                    .expect("NVMe completion provided unknown command ID");
+    fn create_io_queues(admin: &mut QueuePair, io: &IoQueue) -> Result<()> {
+        let io_len = io.queues.len;
+        admin.execute(admin::create_io_completion_queue(
+            Self::IO_QUEUE_ID,
+            io_len,
+            io.queues.completion.get_phys_addr(),
+            None,
+        ))?;
+        admin.execute(admin::create_io_submission_queue(
+            Self::IO_QUEUE_ID,
+            io_len,
+            io.queues.submission.get_phys_addr(),
+            Self::IO_QUEUE_ID,
+        ))?;
+
+        Ok(())
+    }
 
-                use command::{GenericStatus, StatusCode};
-                match cmd_result.get_status().status_code() {
-                    StatusCode::Generic(GenericStatus::SuccessfulCompletion) => success_source.complete(true),
-                    _ => success_source.complete(false),
-                }
-            }
+    /// Executes `command` on the I/O queues. If it times out, the controller is stopped from accessing the bounce
+    /// buffer on its behalf before the buffer can be reused.
+    fn execute_io(&self, io: &mut IoQueue, command: Command) -> Result<CommandResult> {
+        if self.disabled.load(Ordering::Acquire) {
+            return Err(Error::Disabled);
+        }
+
+        let command_id = io.queues.submit(command);
+        let result = io.queues.wait(command_id);
+        if matches!(result, Err(Error::Timeout)) {
+            self.recover(io, command_id);
+        }
+
+        result
+    }
+
+    /// Ensures the timed out I/O command `command_id` is no longer outstanding. The command is aborted, and if the
+    /// controller doesn't then complete it, the controller is reset (which stops every command it's processing).
+    fn recover(&self, io: &mut IoQueue, command_id: u16) {
+        warn!("NVMe I/O command {} timed out, aborting it.", command_id);
+
+        let mut admin = self.admin.lock();
+        // The command posts its completion once it's aborted, or once it completes if it can't be.
+        let completed = admin.execute(admin::abort(Self::IO_QUEUE_ID, command_id)).is_ok()
+            && !matches!(io.queues.wait(command_id), Err(Error::Timeout));
+        if completed {
+            return;
+        }
+
+        warn!("NVMe I/O command {} couldn't be aborted, resetting the controller.", command_id);
+        let reset = self.registers.set_enable_and_wait(false).and_then(|()| {
+            // Disabling the controller deletes the I/O queues, and returns the admin queues to their initial state.
+            admin.reset();
+            io.queues.reset();
+
+            self.registers.set_admin_queues(&admin);
+            self.registers.set_enable_and_wait(true)?;
+            Self::create_io_queues(&mut admin, io)
+        });
+
+        if let Err(err) = reset {
+            error!("Failed to reset NVMe controller, disabling it: {:?}", err);
+            self.disabled.store(true, Ordering::Release);
         }
     }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        self.registers.capabilities()
+    }
+
+    pub fn version(&self) -> &Version {
+        self.registers.version()
+    }
+
+    pub fn interrupt_mask(&self) -> &InterruptMask {
+        self.registers.interrupt_mask()
+    }
+
+    pub fn config(&self) -> &ControllerConfiguration {
+        self.registers.config()
+    }
+
+    pub fn status(&self) -> &ControllerStatus {
+        self.registers.status()
+    }
+
+    #[inline]
+    pub fn namespaces(&self) -> &[Namespace] {
+        &self.namespaces
+    }
+
+    /// Splits a transfer of `len` bytes, starting at `lba` within the namespace `namespace_id`, into transfers which
+    /// fit the bounce buffer. `func` is called for each with its starting LBA, its block count, and the range of
+    /// bytes it covers.
+    fn for_each_transfer(
+        &self,
+        namespace_id: u32,
+        lba: u64,
+        len: usize,
+        mut func: impl FnMut(&mut IoQueue, u64, u16, Range<usize>) -> Result<()>,
+    ) -> Result<()> {
+        let namespace = self
+            .namespaces
+            .iter()
+            .find(|namespace| namespace.id() == namespace_id)
+            .ok_or(Error::UnknownNamespace { id: namespace_id })?;
+        let block_size = namespace.block_size();
+
+        if len % block_size != 0 {
+            return Err(Error::UnalignedBuffer { len });
+        }
+
+        let count = u64::try_from(len / block_size).unwrap();
+        if lba.checked_add(count).map_or(true, |end_lba| end_lba > namespace.block_count()) {
+            return Err(Error::OutOfBounds { lba, count });
+        }
+
+        let transfer_size = ((self.max_transfer_pages * page_size()) / block_size) * block_size;
+        let mut io = self.io.lock();
+        for start in (0..len).step_by(transfer_size) {
+            let range = start..(start + transfer_size).min(len);
+            let transfer_lba = lba + u64::try_from(start / block_size).unwrap();
+            let block_count = u16::try_from(range.len() / block_size).unwrap();
+
+            func(&mut io, transfer_lba, block_count, range)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the logical blocks starting at `lba` into `buffer`, whose length must be a multiple of the block size.
+    pub fn read(&self, namespace_id: u32, lba: u64, buffer: &mut [u8]) -> Result<()> {
+        self.for_each_transfer(namespace_id, lba, buffer.len(), |io, lba, block_count, range| {
+            let data_ptr = io.data_pointer(range.len());
+            self.execute_io(io, io::read(namespace_id, lba, block_count, data_ptr))?;
+
+            // Safety: The read has completed, so the controller is no longer writing to the buffer.
+            let transferred = unsafe { &io.buffer.as_slice()[..range.len()] };
+            buffer[range].copy_from_slice(transferred);
+
+            Ok(())
+        })
+    }
+
+    /// Writes `buffer`, whose length must be a multiple of the block size, to the logical blocks starting at `lba`.
+    pub fn write(&self, namespace_id: u32, lba: u64, buffer: &[u8]) -> Result<()> {
+        self.for_each_transfer(namespace_id, lba, buffer.len(), |io, lba, block_count, range| {
+            // Safety: No command is outstanding, so the controller isn't accessing the buffer.
+            unsafe { io.buffer.as_mut_slice()[..range.len()].copy_from_slice(&buffer[range.clone()]) };
+
+            let data_ptr = io.data_pointer(range.len());
+            self.execute_io(io, io::write(namespace_id, lba, block_count, data_ptr))?;
+
+            Ok(())
+        })
+    }
+
+    /// Commits any cached writes of the namespace `namespace_id` to non-volatile media.
+    pub fn flush(&self, namespace_id: u32) -> Result<()> {
+        self.execute_io(&mut self.io.lock(), io::flush(namespace_id)).map(|_| ())
+    }
 }
 
-impl fmt::Debug for Controller<'_> {
+impl Drop for Controller {
+    fn drop(&mut self) {
+        // Ensure the controller stops accessing the queues before they're freed.
+        self.registers.set_enable_and_wait(false).ok();
+    }
+}
+
+impl fmt::Debug for Controller {
     fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
         formatter
             .debug_struct("NVMe Device")
             .field("Capabilities", &self.capabilities())
             .field("Version", &self.version())
             .field("Interrupt Mask", &self.interrupt_mask().raw_bits_str())
-            .field("MSIX", &self.msix)
             .field("Controller Configuration", &self.config())
             .field("Controller Status", &self.status())
-            .field("Admin Submission Queue Address", &self.admin_sub)
-            .field("Admin Completion Queue Address", &self.admin_com)
-            .finish()
+            .field("Namespaces", &self.namespaces)
+            .finish_non_exhaustive()
     }
 }
 
-pub enum PendingCommand {
-    Identify(ValuedSuccessToken<PageAlignedBox<MaybeUninit<command::admin::Identify>>>),
-    Generic(SuccessToken),
+/// A namespace of a controller, exposed as a block device.
+pub struct Disk {
+    controller: Arc<Controller>,
+    namespace: Namespace,
+}
+
+impl Disk {
+    pub const fn new(controller: Arc<Controller>, namespace: Namespace) -> Self {
+        Self { controller, namespace }
+    }
 }
 
-pub fn exec_driver() {
-    use libsys::io::pci;
+/// Requests complete synchronously, within their first poll, as the controller polls for completions.
+impl block::BlockDevice for Disk {
+    fn sector_size(&self) -> usize {
+        self.namespace.block_size()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.namespace.block_count()
+    }
+
+    fn read<'a>(&'a self, lba: u64, buffer: &'a mut [u8]) -> block::Request<'a> {
+        Box::pin(async move { Ok(self.controller.read(self.namespace.id(), lba, buffer)?) })
+    }
+
+    fn write<'a>(&'a self, lba: u64, buffer: &'a [u8]) -> block::Request<'a> {
+        Box::pin(async move { Ok(self.controller.write(self.namespace.id(), lba, buffer)?) })
+    }
+
+    fn flush(&self) -> block::Request<'_> {
+        Box::pin(async move { Ok(self.controller.flush(self.namespace.id())?) })
+    }
+}
 
-    let nvme: Controller = crate::PCIE_DEVICES
-        .iter()
-        .find_map(|device_variant| match device_variant {
-            pci::DeviceVariant::Standard(device)
-                if device.class() == pci::DeviceClass::MassStorageController && device.subclass() == 0x08 =>
-            {
-                Some(Controller::from_device_and_configure(&device, 4, 4))
+impl fmt::Debug for Disk {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.debug_struct("NVMe Disk").field("Namespace", &self.namespace).finish_non_exhaustive()
+    }
+}
+
+static CONTROLLERS: RwLock<Vec<Arc<Controller>>> = RwLock::new(Vec::new());
+
+/// Every NVMe controller which has been brought up.
+pub fn controllers() -> Vec<Arc<Controller>> {
+    CONTROLLERS.read().clone()
+}
+
+/// Claims and brings up every NVMe controller on the PCI bus.
+pub fn init() {
+    let indexes = pci::with_devices(|devices| {
+        devices
+            .iter()
+            .enumerate()
+            // Mass storage controller, non-volatile memory controller, NVM Express.
+            .filter(|(_, device)| device.get_class_codes() == (0x01, 0x08, 0x02))
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>()
+    });
+
+    for index in indexes {
+        let controller =
+            pci::claim(index, *crate::init::KERNEL_HANDLE).map_err(|err| Error::Pci { err }).and_then(Controller::new);
+
+        match controller {
+            Ok(controller) => {
+                info!("NVMe controller {} is ready, with namespaces: {:?}", index, controller.namespaces());
+
+                // Reading the first block of each namespace exercises the I/O queues.
+                for namespace in controller.namespaces() {
+                    let mut block = alloc::vec![0u8; namespace.block_size()];
+                    match controller.read(namespace.id(), 0, &mut block) {
+                        Ok(()) => debug!("Namespace {} LBA 0: {:02X?}", namespace.id(), &block[..16]),
+                        Err(err) => error!("Failed to read namespace {}: {:?}", namespace.id(), err),
+                    }
+                }
+
+                let controller = Arc::new(controller);
+                for namespace in controller.namespaces() {
+                    block::register(Arc::new(Disk::new(controller.clone(), *namespace)));
+                }
+
+                CONTROLLERS.write().push(controller);
             }
-            _ => None,
-        })
-        // TODO exit task syscall instead ?
-        .expect("No NVMe device attached.");
 
-    nvme.run()
+            Err(err) => error!("Failed to bring up NVMe controller {}: {:?}", index, err),
+        }
+    }
 }
@@ -1,29 +1,15 @@
 use super::command::{Command, CommandResult, QueueEntry};
-use alloc::boxed::Box;
-use core::{
-    mem::MaybeUninit,
-    sync::atomic::{AtomicU16, Ordering},
-};
-use libsys::{
-    memory::{volatile::VolatileCell, PageAlignedBox},
-    Address, IndexRing, ReadWrite, {Physical, Virtual},
-};
+use crate::mem::{alloc::pmm, dma::DmaBuffer};
+use core::{marker::PhantomData, num::NonZeroUsize};
+use libkernel::{mem::VolatileCell, IndexRing, ReadWrite};
 
 /// Uses the NVMe specified equation to calculate the offset of a particular queue's doorbell.
 ///
 /// REMARK: `ty_mult` is the type multiplier for the queue type. `Submission` is 0, `Completion` is 1.
 const fn calc_doorbell_offset(queue_id: u16, ty_mult: usize, dstrd: usize) -> usize {
-    0x1000 + ((((queue_id as usize) * 2) + (ty_mult as usize)) * (4 << dstrd))
+    0x1000 + ((((queue_id as usize) * 2) + ty_mult) * (4 << dstrd))
 }
 
-const CAP_OFFSET: usize = 0x0;
-const AQA_OFFSET: usize = 0x24;
-const ASQ_OFFSET: usize = 0x28;
-const ACQ_OFFSET: usize = 0x30;
-
-#[derive(Debug)]
-pub struct QueueOverflow;
-
 pub trait QueueType {
     type EntryType: QueueEntry;
     const DOORBELL_OFFSET: usize;
@@ -41,79 +27,88 @@ impl QueueType for Completion {
     const DOORBELL_OFFSET: usize = 1;
 }
 
-pub struct Queue<'q, T: QueueType> {
-    entries: libsys::memory::PageAlignedBox<[MaybeUninit<T::EntryType>]>,
-    doorbell: &'q VolatileCell<u32, ReadWrite>,
+pub struct Queue<T: QueueType> {
+    entries: DmaBuffer,
+    doorbell: &'static VolatileCell<u32, ReadWrite>,
+    entry_count: u16,
     cur_index: IndexRing,
     phase_tag: bool, /* this is unused for submission queues */
+    phantom: PhantomData<T>,
 }
 
-impl<'q, T: QueueType> Queue<'q, T> {
-    /// Creates a new NVMe queue of type `T`.
+// Safety: The doorbell is controller MMIO, which is only written through an exclusive borrow of the queue.
+unsafe impl<T: QueueType> Send for Queue<T> {}
+
+impl<T: QueueType> Queue<T> {
+    /// Creates a new NVMe queue of type `T`, in physically contiguous memory.
     ///
     /// REMARK: The `queue_id` provided is explicitly trusted. For admin queues, it should always
     ///         be 0. For all other queues, if the queue ID is already used for its respective type,
     ///         then an erroring CommandResult is returned by the controller.
-    pub fn new(
-        reg0: &'q libsys::memory::MMIO,
-        queue_id: u16, /* may need a way to dynamically select this? */
+    ///
+    /// ### Safety
+    ///
+    /// Caller must ensure `registers` points to the controller's (mapped) register space, which remains valid for
+    /// the lifetime of the queue.
+    pub unsafe fn new(
+        registers: core::ptr::NonNull<u8>,
+        doorbell_stride: usize,
+        queue_id: u16,
         entry_count: u16,
-    ) -> Self {
-        unsafe {
-            let doorbell_offset = calc_doorbell_offset(
-                queue_id,
-                T::DOORBELL_OFFSET,
-                reg0.borrow::<crate::drivers::nvme::Capabilities>(CAP_OFFSET).get_dstrd() as usize,
-            );
+    ) -> pmm::Result<Self> {
+        let entries_size = usize::from(entry_count) * core::mem::size_of::<T::EntryType>();
+        let page_count = libsys::align_up_div(entries_size, libsys::page_shift());
+        let doorbell_offset = calc_doorbell_offset(queue_id, T::DOORBELL_OFFSET, doorbell_stride);
+
+        Ok(Self {
+            entries: DmaBuffer::new(NonZeroUsize::new(page_count).unwrap(), None)?,
+            doorbell: &*registers.as_ptr().add(doorbell_offset).cast(),
+            entry_count,
+            cur_index: IndexRing::new(usize::from(entry_count)),
+            phase_tag: true,
+            phantom: PhantomData,
+        })
+    }
 
-            Self {
-                entries: Box::new_uninit_slice_in(entry_count as usize, libsys::memory::page_aligned_allocator()),
-                doorbell: reg0.borrow(doorbell_offset),
-                cur_index: IndexRing::new(entry_count as usize),
-                phase_tag: true,
-            }
-        }
+    /// Physical address of the queue's entries.
+    pub fn get_phys_addr(&self) -> u64 {
+        self.entries.physical_address(0)
+    }
+
+    /// Returns the queue to its initial state, as it is after the controller is reset.
+    pub fn reset(&mut self) {
+        // Safety: The controller has been reset, so it isn't accessing the entries.
+        unsafe { self.entries.as_mut_slice().fill(0) };
+        self.cur_index = IndexRing::new(usize::from(self.entry_count));
+        self.phase_tag = true;
     }
 
-    pub fn get_phys_addr(&self) -> Address<Physical> {
-        Address::<Physical>::new(
-            libsys::memory::global_pmgr()
-                .get_mapped_to(&libsys::memory::Page::from_ptr(self.entries.as_ptr()))
-                .unwrap(),
-        )
+    fn entry_ptr(&self, index: usize) -> *mut T::EntryType {
+        // Safety: Index is always within the bounds of the queue's entries.
+        unsafe { self.entries.as_ptr().as_ptr().cast::<T::EntryType>().add(index) }
     }
 }
 
 /* COMPLETION QUEUE */
 
-impl Queue<'_, Completion> {
+impl Queue<Completion> {
     /// Provides the next `CommandResult`, or `None`.
     pub fn next_cmd_result(&mut self) -> Option<CommandResult> {
-        // Assume the current completion is initialized, to test the Phase Tag bit.
-        let cur_completion = unsafe { self.entries[self.cur_index.index()].assume_init() };
-        info!("CHECKING COMPLETION: {:?}", cur_completion);
-        // Test the current completion's phase tag bit against our own.
-        //
-        // Our phase tag bit should always be inverted to newly incoming completions.
-        //
-        // REMARK: It may be possible to fail processing sufficient completions
-        //         before the next interrupt is sent, thus resulting in this approach
-        //         not processing all of the queued completions.
-        if cur_completion.get_phase_tag() != self.phase_tag {
-            self.doorbell.write(self.cur_index.index() as u32);
-            self.cur_index.increment();
+        // Safety: Entry memory is zeroed when allocated, so every entry is initialized.
+        let cur_completion = unsafe { self.entry_ptr(self.cur_index.index()).read_volatile() };
+
+        // The controller inverts the phase tag each time it wraps the queue, so a matching phase tag indicates a new
+        // completion.
+        if cur_completion.get_phase_tag() == self.phase_tag {
+            self.increment_with_phase_inversion();
+            self.doorbell.write(u32::try_from(self.cur_index.index()).unwrap());
+
             Some(cur_completion)
         } else {
             None
         }
     }
 
-    /// Inverts the phase tag match for the queue. This should only be done
-    /// when
-    pub fn invert_phase(&mut self) {
-        self.phase_tag = !self.phase_tag;
-    }
-
     /// Increments the completion queue index. Then, if incrementing rolls over
     /// the index ring, the phase tag for this queue is inverted.
     fn increment_with_phase_inversion(&mut self) {
@@ -125,11 +120,11 @@ impl Queue<'_, Completion> {
     }
 }
 
-impl core::fmt::Debug for Queue<'_, Completion> {
+impl core::fmt::Debug for Queue<Completion> {
     fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         formatter
-            .debug_struct("Admin Completion Queue")
-            .field("Physical Address", &self.get_phys_addr())
+            .debug_struct("Completion Queue")
+            .field("Physical Address", &format_args!("{:#X}", self.get_phys_addr()))
             .field("Index", &self.cur_index)
             .field("Phase Tag", &self.phase_tag)
             .finish()
@@ -138,163 +133,25 @@ impl core::fmt::Debug for Queue<'_, Completion> {
 
 /* SUBMISSION QUEUE */
 
-impl Queue<'_, Submission> {
+impl Queue<Submission> {
+    /// Writes `command` to the tail of the queue, and notifies the controller of it.
+    ///
+    /// REMARK: Queue overflow isn't checked, so callers must not have more commands outstanding than the queue can
+    ///         hold.
     pub fn submit_command(&mut self, command: Command) {
-        self.entries[self.cur_index.index()] = MaybeUninit::new(command);
+        // Safety: Index is within the bounds of the queue's entries.
+        unsafe { self.entry_ptr(self.cur_index.index()).write_volatile(command) };
         self.cur_index.increment();
+        self.doorbell.write(u32::try_from(self.cur_index.index()).unwrap());
     }
-
-    // TODO submit_commands function
 }
 
-impl core::fmt::Debug for Queue<'_, Submission> {
+impl core::fmt::Debug for Queue<Submission> {
     fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         formatter
-            .debug_struct("Admin Submission Queue")
-            .field("Physical Address", &self.get_phys_addr())
+            .debug_struct("Submission Queue")
+            .field("Physical Address", &format_args!("{:#X}", self.get_phys_addr()))
             .field("Index", &self.cur_index)
             .finish()
     }
 }
-
-// /// NVMe completion queue, used for receiving and tracking completed commands.
-// pub(super) struct CompletionQueue<'q> {
-//     entries: Box<QueueEntries<Completion>>,
-//     doorbell: &'q VolatileCell<u32, ReadWrite>,
-//     phase_tag: bool,
-//     cur_index: IndexRing,
-// }
-
-// impl<'q> CompletionQueue<'q> {
-//     pub fn new(
-//         reg0: &'q libsys::memory::MMIO,
-//         queue_id: u16, /* may need a way to dynamically select this? */
-//     ) -> Self {
-//         unsafe {
-//             let doorbell_offset = calc_doorbell_offset(
-//                 queue_id,
-//                 QueueType::Completion,
-//                 reg0.borrow::<crate::drivers::nvme::Capabilities>(CAP_OFFSET)
-//                     .get_dstrd() as usize,
-//             );
-
-//             Self {
-//                 entries: Box::new(QueueEntries::uninit()),
-//                 doorbell: reg0.borrow(doorbell_offset),
-//                 cur_index: IndexRing::new(GLOBAL_QUEUE_ENTRIES_COUNT),
-//                 phase_tag: true,
-//             }
-//         }
-//     }
-
-//     pub fn get_phys_addr(&self) -> Address<Physical> {
-//         Address::<Physical>::new(
-//             libsys::memory::global_pmgr()
-//                 .get_mapped_to(&libsys::memory::Page::from_ptr(self.entries.as_ptr()))
-//                 .unwrap(),
-//         )
-//     }
-
-//     pub fn next_completion(&mut self) -> Option<Completion> {
-//         // Assume the current completion is initialized, to test the Phase Tag bit.
-//         let cur_completion = unsafe { self.entries[self.cur_index.index()].assume_init() };
-
-//         info!("CHECKING COMPLETION: {:?}", cur_completion);
-
-//         // Test the current completion's phase tag bit against our own.
-//         //
-//         // Our phase tag bit should always be inverted to newly incoming completions.
-//         //
-//         // REMARK: It may be possible to fail processing sufficient completions
-//         //         before the next interrupt is sent, thus resulting in this approach
-//         //         not processing all of the queued completions.
-//         if cur_completion.get_phase_tag() != self.phase_tag {
-//             self.doorbell.write(self.cur_index.index() as u32);
-//             self.cur_index.increment();
-
-//             Some(cur_completion)
-//         } else {
-//             None
-//         }
-//     }
-
-//     pub fn invert_phase(&mut self) {
-//         self.phase_tag = !self.phase_tag;
-//     }
-// }
-
-// impl core::fmt::Debug for CompletionQueue<'_> {
-//     fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-//         formatter
-//             .debug_struct("Admin Completion Queue")
-//             .field("Physical Address", &self.get_phys_addr())
-//             .field("Index", &self.cur_index)
-//             .field("Phase Tag", &self.phase_tag)
-//             .finish()
-//     }
-// }
-
-// pub(super) struct SubmissionQueue<'q> {
-//     entries: Box<QueueEntries<Command>>,
-//     cur_index: IndexRing,
-//     pending_count: u16,
-//     doorbell: &'q VolatileCell<u32, ReadWrite>,
-// }
-
-// impl<'q> SubmissionQueue<'q> {
-//     pub fn new(reg0: &'q libsys::memory::MMIO, queue_id: u16) -> Self {
-//         // TODO somehow validate entry size?
-
-//         unsafe {
-//             let doorbell_offset = calc_doorbell_offset(
-//                 queue_id,
-//                 QueueType::Submission,
-//                 reg0.borrow::<crate::drivers::nvme::Capabilities>(CAP_OFFSET)
-//                     .get_dstrd() as usize,
-//             );
-
-//             Self {
-//                 doorbell: reg0.borrow(doorbell_offset),
-//                 entries: Box::new(QueueEntries::uninit()),
-//                 cur_index: IndexRing::new(GLOBAL_QUEUE_ENTRIES_COUNT),
-//                 pending_count: 0,
-//             }
-//         }
-//     }
-
-//     pub fn get_phys_addr(&self) -> Address<Physical> {
-//         Address::<Physical>::new(
-//             libsys::memory::global_pmgr()
-//                 .get_mapped_to(&libsys::memory::Page::from_ptr(self.entries.as_ptr()))
-//                 .unwrap(),
-//         )
-//     }
-
-//     pub fn submit_command(&mut self, command: Command) -> Result<(), QueueOverflow> {
-//         if self.pending_count < self.entry_count {
-//             self.entries[self.cur_index.index()] = MaybeUninit::new(command);
-//             self.cur_index.increment();
-//             self.pending_count += 1;
-
-//             Ok(())
-//         } else {
-//             Err(QueueOverflow)
-//         }
-//     }
-
-//     pub fn flush_commands(&mut self) {
-//         self.doorbell.write(self.cur_index.index() as u32);
-//         self.pending_count = 0;
-//     }
-// }
-
-// impl core::fmt::Debug for SubmissionQueue<'_> {
-//     fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-//         formatter
-//             .debug_struct("Admin Submission Queue")
-//             .field("Physical Address", &self.get_phys_addr())
-//             .field("Index", &self.cur_index)
-//             .field("Pending", &self.pending_count)
-//             .finish()
-//     }
-// }
@@ -0,0 +1,325 @@
+//! Decodes the scancodes of a PS/2 keyboard into key events, which name keys by their USB HID usage ID.
+
+/// Scancode set the keyboard reports keys in. Set 2 is the keyboard's native set, which the controller translates to
+/// set 1 unless translation is disabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    One,
+    Two,
+}
+
+bitflags::bitflags! {
+    /// Modifier keys which are held down, and lock keys which are toggled on.
+    ///
+    /// The held modifiers are laid out as in a USB HID keyboard report, so usage `0xE0 + n` is bit `n`.
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Modifiers: u16 {
+        const LEFT_CTRL = 1 << 0;
+        const LEFT_SHIFT = 1 << 1;
+        const LEFT_ALT = 1 << 2;
+        const LEFT_GUI = 1 << 3;
+        const RIGHT_CTRL = 1 << 4;
+        const RIGHT_SHIFT = 1 << 5;
+        const RIGHT_ALT = 1 << 6;
+        const RIGHT_GUI = 1 << 7;
+        const CAPS_LOCK = 1 << 8;
+        const NUM_LOCK = 1 << 9;
+
+        const CTRL = Self::LEFT_CTRL.bits() | Self::RIGHT_CTRL.bits();
+        const SHIFT = Self::LEFT_SHIFT.bits() | Self::RIGHT_SHIFT.bits();
+        const ALT = Self::LEFT_ALT.bits() | Self::RIGHT_ALT.bits();
+        const GUI = Self::LEFT_GUI.bits() | Self::RIGHT_GUI.bits();
+    }
+}
+
+const USAGE_CAPS_LOCK: u8 = 0x39;
+const USAGE_NUM_LOCK: u8 = 0x53;
+const USAGE_LEFT_CTRL: u8 = 0xE0;
+const USAGE_RIGHT_GUI: u8 = 0xE7;
+
+/// USB HID usage IDs of the scancode set 1 make codes, where zero marks codes with no key.
+#[rustfmt::skip]
+const SET1_USAGES: [u8; 0x59] = [
+    0x00, 0x29, 0x1E, 0x1F, 0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x2D, 0x2E, 0x2A, 0x2B,
+    0x14, 0x1A, 0x08, 0x15, 0x17, 0x1C, 0x18, 0x0C, 0x12, 0x13, 0x2F, 0x30, 0x28, 0xE0, 0x04, 0x16,
+    0x07, 0x09, 0x0A, 0x0B, 0x0D, 0x0E, 0x0F, 0x33, 0x34, 0x35, 0xE1, 0x31, 0x1D, 0x1B, 0x06, 0x19,
+    0x05, 0x11, 0x10, 0x36, 0x37, 0x38, 0xE5, 0x55, 0xE2, 0x2C, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E,
+    0x3F, 0x40, 0x41, 0x42, 0x43, 0x53, 0x47, 0x5F, 0x60, 0x61, 0x56, 0x5C, 0x5D, 0x5E, 0x57, 0x59,
+    0x5A, 0x5B, 0x62, 0x63, 0x00, 0x00, 0x64, 0x44, 0x45,
+];
+
+/// Looks up the USB HID usage ID of a scancode set 1 make code, which may follow an `0xE0` prefix.
+fn set1_usage(code: u8, extended: bool) -> Option<u8> {
+    if extended {
+        match code {
+            0x1C => Some(0x58),
+            0x1D => Some(0xE4),
+            0x35 => Some(0x54),
+            0x37 => Some(0x46),
+            0x38 => Some(0xE6),
+            0x47 => Some(0x4A),
+            0x48 => Some(0x52),
+            0x49 => Some(0x4B),
+            0x4B => Some(0x50),
+            0x4D => Some(0x4F),
+            0x4F => Some(0x4D),
+            0x50 => Some(0x51),
+            0x51 => Some(0x4E),
+            0x52 => Some(0x49),
+            0x53 => Some(0x4C),
+            0x5B => Some(0xE3),
+            0x5C => Some(0xE7),
+            0x5D => Some(0x65),
+            // Includes the fake shifts sent around the navigation keys and Print Screen.
+            _ => None,
+        }
+    } else {
+        SET1_USAGES.get(usize::from(code)).copied().filter(|usage| *usage != 0)
+    }
+}
+
+/// Translates a scancode set 2 make code to its set 1 equivalent, in the same way as the controller.
+const fn set2_to_set1(code: u8) -> Option<u8> {
+    let translated = match code {
+        0x01 => 0x43,
+        0x03 => 0x3F,
+        0x04 => 0x3D,
+        0x05 => 0x3B,
+        0x06 => 0x3C,
+        0x07 => 0x58,
+        0x09 => 0x44,
+        0x0A => 0x42,
+        0x0B => 0x40,
+        0x0C => 0x3E,
+        0x0D => 0x0F,
+        0x0E => 0x29,
+        0x11 => 0x38,
+        0x12 => 0x2A,
+        0x14 => 0x1D,
+        0x15 => 0x10,
+        0x16 => 0x02,
+        0x1A => 0x2C,
+        0x1B => 0x1F,
+        0x1C => 0x1E,
+        0x1D => 0x11,
+        0x1E => 0x03,
+        0x1F => 0x5B,
+        0x21 => 0x2E,
+        0x22 => 0x2D,
+        0x23 => 0x20,
+        0x24 => 0x12,
+        0x25 => 0x05,
+        0x26 => 0x04,
+        0x27 => 0x5C,
+        0x29 => 0x39,
+        0x2A => 0x2F,
+        0x2B => 0x21,
+        0x2C => 0x14,
+        0x2D => 0x13,
+        0x2E => 0x06,
+        0x2F => 0x5D,
+        0x31 => 0x31,
+        0x32 => 0x30,
+        0x33 => 0x23,
+        0x34 => 0x22,
+        0x35 => 0x15,
+        0x36 => 0x07,
+        0x3A => 0x32,
+        0x3B => 0x24,
+        0x3C => 0x16,
+        0x3D => 0x08,
+        0x3E => 0x09,
+        0x41 => 0x33,
+        0x42 => 0x25,
+        0x43 => 0x17,
+        0x44 => 0x18,
+        0x45 => 0x0B,
+        0x46 => 0x0A,
+        0x49 => 0x34,
+        0x4A => 0x35,
+        0x4B => 0x26,
+        0x4C => 0x27,
+        0x4D => 0x19,
+        0x4E => 0x0C,
+        0x52 => 0x28,
+        0x54 => 0x1A,
+        0x55 => 0x0D,
+        0x58 => 0x3A,
+        0x59 => 0x36,
+        0x5A => 0x1C,
+        0x5B => 0x1B,
+        0x5D => 0x2B,
+        0x61 => 0x56,
+        0x66 => 0x0E,
+        0x69 => 0x4F,
+        0x6B => 0x4B,
+        0x6C => 0x47,
+        0x70 => 0x52,
+        0x71 => 0x53,
+        0x72 => 0x50,
+        0x73 => 0x4C,
+        0x74 => 0x4D,
+        0x75 => 0x48,
+        0x76 => 0x01,
+        0x77 => 0x45,
+        0x78 => 0x57,
+        0x79 => 0x4E,
+        0x7A => 0x51,
+        0x7B => 0x4A,
+        0x7C => 0x37,
+        0x7D => 0x49,
+        0x7E => 0x46,
+        0x83 => 0x41,
+        _ => return None,
+    };
+
+    Some(translated)
+}
+
+/// A key being pressed or released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// USB HID usage ID of the key.
+    pub key: u8,
+    /// Character the key produces with a US layout, if it was pressed and produces one.
+    pub character: Option<char>,
+    pub pressed: bool,
+    /// Modifiers as they are after the event.
+    pub modifiers: Modifiers,
+}
+
+pub struct Keyboard {
+    set: ScancodeSet,
+    modifiers: Modifiers,
+    /// Whether the last byte was an `0xE0` prefix.
+    extended: bool,
+    /// Whether the last byte was a set 2 `0xF0` break prefix.
+    released: bool,
+    /// Number of bytes left to skip of the current Pause sequence.
+    skip: u8,
+}
+
+impl Keyboard {
+    pub const fn new(set: ScancodeSet) -> Self {
+        Self { set, modifiers: Modifiers::empty(), extended: false, released: false, skip: 0 }
+    }
+
+    #[inline]
+    pub const fn scancode_set(&self) -> ScancodeSet {
+        self.set
+    }
+
+    /// Changes the scancode set, discarding any partially received scancode.
+    pub fn set_scancode_set(&mut self, set: ScancodeSet) {
+        *self = Self { set, modifiers: self.modifiers, ..Self::new(set) };
+    }
+
+    /// Decodes the next byte from the keyboard, returning an event once a whole scancode has been received.
+    ///
+    /// Pause is ignored, as it's reported without ever being released.
+    pub fn decode(&mut self, byte: u8) -> Option<KeyEvent> {
+        if self.skip > 0 {
+            self.skip -= 1;
+            return None;
+        }
+
+        match (self.set, byte) {
+            (_, 0xE0) => {
+                self.extended = true;
+                return None;
+            }
+
+            // Pause is `E1 1D 45 E1 9D C5` in set 1, and `E1 14 77 E1 F0 14 F0 77` in set 2.
+            (ScancodeSet::One, 0xE1) => {
+                self.skip = 5;
+                return None;
+            }
+            (ScancodeSet::Two, 0xE1) => {
+                self.skip = 7;
+                return None;
+            }
+
+            (ScancodeSet::Two, 0xF0) => {
+                self.released = true;
+                return None;
+            }
+
+            _ => {}
+        }
+
+        let extended = core::mem::take(&mut self.extended);
+        let released = core::mem::take(&mut self.released);
+        let (code, pressed) = match self.set {
+            ScancodeSet::One => (byte & 0x7F, (byte & 0x80) == 0),
+            ScancodeSet::Two => (set2_to_set1(byte)?, !released),
+        };
+        let key = set1_usage(code, extended)?;
+
+        match key {
+            USAGE_LEFT_CTRL..=USAGE_RIGHT_GUI => {
+                self.modifiers.set(Modifiers::from_bits_retain(1 << (key - USAGE_LEFT_CTRL)), pressed);
+            }
+            USAGE_CAPS_LOCK if pressed => self.modifiers.toggle(Modifiers::CAPS_LOCK),
+            USAGE_NUM_LOCK if pressed => self.modifiers.toggle(Modifiers::NUM_LOCK),
+            _ => {}
+        }
+
+        Some(KeyEvent {
+            key,
+            character: if pressed { self.character(key) } else { None },
+            pressed,
+            modifiers: self.modifiers,
+        })
+    }
+
+    /// Character `key` produces with a US layout, given the current modifiers.
+    fn character(&self, key: u8) -> Option<char> {
+        const DIGITS: &[u8; 10] = b"1234567890";
+        const SHIFTED_DIGITS: &[u8; 10] = b"!@#$%^&*()";
+        const SYMBOLS: &[u8; 12] = b"-=[]\\#;'`,./";
+        const SHIFTED_SYMBOLS: &[u8; 12] = b"_+{}|~:\"~<>?";
+
+        let shift = self.modifiers.intersects(Modifiers::SHIFT);
+        let num_lock = self.modifiers.contains(Modifiers::NUM_LOCK);
+
+        let byte = match key {
+            0x04..=0x1D => {
+                let letter = b'a' + (key - 0x04);
+
+                if self.modifiers.intersects(Modifiers::CTRL) {
+                    letter & 0x1F
+                } else if shift != self.modifiers.contains(Modifiers::CAPS_LOCK) {
+                    letter.to_ascii_uppercase()
+                } else {
+                    letter
+                }
+            }
+
+            0x1E..=0x27 if shift => SHIFTED_DIGITS[usize::from(key - 0x1E)],
+            0x1E..=0x27 => DIGITS[usize::from(key - 0x1E)],
+
+            0x28 | 0x58 => b'\n',
+            0x29 => 0x1B,
+            0x2A => 0x08,
+            0x2B => b'\t',
+            0x2C => b' ',
+
+            0x2D..=0x38 if shift => SHIFTED_SYMBOLS[usize::from(key - 0x2D)],
+            0x2D..=0x38 => SYMBOLS[usize::from(key - 0x2D)],
+            0x64 if shift => b'|',
+            0x64 => b'\\',
+
+            0x54 => b'/',
+            0x55 => b'*',
+            0x56 => b'-',
+            0x57 => b'+',
+            0x59..=0x62 if num_lock => DIGITS[usize::from(key - 0x59)],
+            0x63 if num_lock => b'.',
+
+            _ => return None,
+        };
+
+        Some(char::from(byte))
+    }
+}
@@ -0,0 +1,401 @@
+//! Driver for the PS/2 controller (the 8042), and the keyboard and mouse attached to its ports.
+//!
+//! Input events are queued for consumers to take with [`next_event`], and are also delivered to the display server
+//! as they arrive.
+
+pub mod keyboard;
+pub mod mouse;
+
+use crate::{
+    drivers::graphics::display::{self, Input},
+    interrupts::irq::{self, Interrupt},
+};
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc};
+use keyboard::{KeyEvent, Keyboard, ScancodeSet};
+use mouse::{Mouse, MouseButtons, MouseEvent};
+use port::{ReadOnlyPort, ReadWritePort, WriteOnlyPort};
+use spin::Mutex;
+
+crate::error_impl! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Error {
+        /// Indicates the controller or a device didn't respond in time.
+        Timeout => None,
+
+        /// Indicates the controller failed its self-test, or doesn't exist.
+        SelfTestFailed { response: u8 } => None,
+
+        /// Indicates neither port passed its interface test.
+        NoPorts => None,
+
+        /// Indicates a device didn't acknowledge a command.
+        NotAcknowledged { response: u8 } => None,
+
+        /// Indicates a device failed its self-test after being reset.
+        DeviceSelfTestFailed { response: u8 } => None,
+
+        Interrupt { err: irq::Error } => Some(err)
+    }
+}
+
+/// Times the status register is polled before a command is considered to have timed out. Devices can take a large
+/// fraction of a second to reset, so this is generous.
+const POLL_ATTEMPTS: usize = 1_000_000;
+/// Times a command is resent after a device asks for it to be.
+const COMMAND_RETRIES: usize = 3;
+/// Most events which are queued. Past this, the oldest events are dropped.
+const MAX_PENDING_EVENTS: usize = 256;
+
+const KEYBOARD_ISA_IRQ: u8 = 1;
+const MOUSE_ISA_IRQ: u8 = 12;
+
+bitflags::bitflags! {
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Status: u8 {
+        const OUTPUT_FULL = 1 << 0;
+        const INPUT_FULL = 1 << 1;
+        const SYSTEM = 1 << 2;
+        const COMMAND = 1 << 3;
+        const AUX_OUTPUT = 1 << 5;
+        const TIMEOUT = 1 << 6;
+        const PARITY_ERROR = 1 << 7;
+    }
+}
+
+bitflags::bitflags! {
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Config: u8 {
+        const FIRST_INTERRUPT = 1 << 0;
+        const SECOND_INTERRUPT = 1 << 1;
+        const SYSTEM = 1 << 2;
+        const FIRST_CLOCK_DISABLED = 1 << 4;
+        const SECOND_CLOCK_DISABLED = 1 << 5;
+        const TRANSLATION = 1 << 6;
+    }
+}
+
+mod command {
+    pub const READ_CONFIG: u8 = 0x20;
+    pub const WRITE_CONFIG: u8 = 0x60;
+    pub const DISABLE_SECOND_PORT: u8 = 0xA7;
+    pub const ENABLE_SECOND_PORT: u8 = 0xA8;
+    pub const TEST_SECOND_PORT: u8 = 0xA9;
+    pub const SELF_TEST: u8 = 0xAA;
+    pub const TEST_FIRST_PORT: u8 = 0xAB;
+    pub const DISABLE_FIRST_PORT: u8 = 0xAD;
+    pub const ENABLE_FIRST_PORT: u8 = 0xAE;
+    pub const WRITE_SECOND_PORT: u8 = 0xD4;
+}
+
+mod device_command {
+    pub const SET_DEFAULTS: u8 = 0xF6;
+    pub const ENABLE_REPORTING: u8 = 0xF4;
+    pub const RESET: u8 = 0xFF;
+}
+
+mod response {
+    pub const SELF_TEST_PASSED: u8 = 0x55;
+    pub const PORT_TEST_PASSED: u8 = 0x00;
+    pub const DEVICE_SELF_TEST_PASSED: u8 = 0xAA;
+    pub const ACKNOWLEDGE: u8 = 0xFA;
+    pub const RESEND: u8 = 0xFE;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Port {
+    First,
+    Second,
+}
+
+struct Controller {
+    data: ReadWritePort<u8>,
+    status: ReadOnlyPort<u8>,
+    command: WriteOnlyPort<u8>,
+}
+
+impl Controller {
+    const fn new() -> Self {
+        // Safety: These are the 8042's fixed ports, which nothing else uses.
+        unsafe {
+            Self { data: ReadWritePort::new(0x60), status: ReadOnlyPort::new(0x64), command: WriteOnlyPort::new(0x64) }
+        }
+    }
+
+    fn status(&self) -> Status {
+        Status::from_bits_retain(self.status.read())
+    }
+
+    fn poll(&self, until: impl Fn(Status) -> bool) -> Result<()> {
+        for _ in 0..POLL_ATTEMPTS {
+            if until(self.status()) {
+                return Ok(());
+            }
+
+            core::hint::spin_loop();
+        }
+
+        Err(Error::Timeout)
+    }
+
+    fn send_command(&mut self, command: u8) -> Result<()> {
+        self.poll(|status| !status.contains(Status::INPUT_FULL))?;
+        self.command.write(command);
+
+        Ok(())
+    }
+
+    fn read_data(&mut self) -> Result<u8> {
+        self.poll(|status| status.contains(Status::OUTPUT_FULL))?;
+
+        Ok(self.data.read())
+    }
+
+    fn write_data(&mut self, value: u8) -> Result<()> {
+        self.poll(|status| !status.contains(Status::INPUT_FULL))?;
+        self.data.write(value);
+
+        Ok(())
+    }
+
+    /// Discards any bytes waiting to be read.
+    fn flush_output(&mut self) {
+        while self.status().contains(Status::OUTPUT_FULL) {
+            self.data.read();
+        }
+    }
+
+    fn read_config(&mut self) -> Result<Config> {
+        self.send_command(command::READ_CONFIG)?;
+        self.read_data().map(Config::from_bits_retain)
+    }
+
+    fn write_config(&mut self, config: Config) -> Result<()> {
+        self.send_command(command::WRITE_CONFIG)?;
+        self.write_data(config.bits())
+    }
+
+    /// Runs the interface test of `port`, returning whether it passed.
+    fn test_port(&mut self, port: Port) -> Result<bool> {
+        self.send_command(match port {
+            Port::First => command::TEST_FIRST_PORT,
+            Port::Second => command::TEST_SECOND_PORT,
+        })?;
+
+        Ok(self.read_data()? == response::PORT_TEST_PASSED)
+    }
+
+    /// Sends `command` to the device on `port`, waiting for it to be acknowledged.
+    fn send_device_command(&mut self, port: Port, command: u8) -> Result<()> {
+        for _ in 0..COMMAND_RETRIES {
+            if port == Port::Second {
+                self.send_command(command::WRITE_SECOND_PORT)?;
+            }
+            self.write_data(command)?;
+
+            match self.read_data()? {
+                response::ACKNOWLEDGE => return Ok(()),
+                response::RESEND => continue,
+                response => return Err(Error::NotAcknowledged { response }),
+            }
+        }
+
+        Err(Error::NotAcknowledged { response: response::RESEND })
+    }
+
+    /// Resets the device on `port`, waiting for it to pass its self-test.
+    fn reset_device(&mut self, port: Port) -> Result<()> {
+        self.send_device_command(port, device_command::RESET)?;
+
+        match self.read_data()? {
+            response::DEVICE_SELF_TEST_PASSED => {
+                // Mice follow with their device ID, which isn't needed.
+                if port == Port::Second {
+                    self.read_data()?;
+                }
+
+                Ok(())
+            }
+
+            response => Err(Error::DeviceSelfTestFailed { response }),
+        }
+    }
+}
+
+static CONTROLLER: Mutex<Controller> = Mutex::new(Controller::new());
+static KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard::new(ScancodeSet::One));
+static MOUSE: Mutex<Mouse> = Mutex::new(Mouse::new());
+static KEYBOARD_INTERRUPT: spin::Once<Arc<Interrupt>> = spin::Once::new();
+static MOUSE_INTERRUPT: spin::Once<Arc<Interrupt>> = spin::Once::new();
+
+/// Brings up the controller, and whichever of the keyboard and mouse are connected.
+pub fn init() {
+    match crate::interrupts::without(init_controller) {
+        Ok((has_keyboard, has_mouse)) => {
+            info!("PS/2 controller is ready (keyboard: {}, mouse: {}).", has_keyboard, has_mouse);
+        }
+
+        Err(err) => info!("No PS/2 controller is usable: {:?}", err),
+    }
+}
+
+/// Returns whether the keyboard and mouse, respectively, were brought up.
+fn init_controller() -> Result<(bool, bool)> {
+    let mut controller = CONTROLLER.lock();
+
+    controller.send_command(command::DISABLE_FIRST_PORT)?;
+    controller.send_command(command::DISABLE_SECOND_PORT)?;
+    controller.flush_output();
+
+    let mut config = controller.read_config()?;
+    config.remove(Config::FIRST_INTERRUPT | Config::SECOND_INTERRUPT);
+    controller.write_config(config)?;
+
+    controller.send_command(command::SELF_TEST)?;
+    match controller.read_data()? {
+        response::SELF_TEST_PASSED => {}
+        response => return Err(Error::SelfTestFailed { response }),
+    }
+    // Some controllers are reset by their self-test.
+    controller.write_config(config)?;
+
+    // Only dual-port controllers are able to enable the second port's clock.
+    let has_second_port = config.contains(Config::SECOND_CLOCK_DISABLED) && {
+        controller.send_command(command::ENABLE_SECOND_PORT)?;
+        let enabled = !controller.read_config()?.contains(Config::SECOND_CLOCK_DISABLED);
+        controller.send_command(command::DISABLE_SECOND_PORT)?;
+
+        enabled
+    };
+
+    let first_port = controller.test_port(Port::First)?;
+    let second_port = has_second_port && controller.test_port(Port::Second)?;
+    if !first_port && !second_port {
+        return Err(Error::NoPorts);
+    }
+
+    let has_keyboard = first_port && {
+        controller.send_command(command::ENABLE_FIRST_PORT)?;
+
+        match controller.reset_device(Port::First) {
+            Ok(()) => {
+                request_interrupt(KEYBOARD_ISA_IRQ, &KEYBOARD_INTERRUPT, handle_keyboard_interrupt)?;
+                config.insert(Config::FIRST_INTERRUPT);
+
+                // The controller translates the keyboard's scancodes to set 1, unless translation was disabled.
+                let set = if config.contains(Config::TRANSLATION) { ScancodeSet::One } else { ScancodeSet::Two };
+                KEYBOARD.lock().set_scancode_set(set);
+
+                true
+            }
+
+            Err(err) => {
+                warn!("PS/2 keyboard failed to reset: {:?}", err);
+                controller.send_command(command::DISABLE_FIRST_PORT)?;
+
+                false
+            }
+        }
+    };
+
+    let has_mouse = second_port && {
+        controller.send_command(command::ENABLE_SECOND_PORT)?;
+
+        let enabled = controller
+            .reset_device(Port::Second)
+            .and_then(|()| controller.send_device_command(Port::Second, device_command::SET_DEFAULTS))
+            .and_then(|()| controller.send_device_command(Port::Second, device_command::ENABLE_REPORTING));
+
+        match enabled {
+            Ok(()) => {
+                request_interrupt(MOUSE_ISA_IRQ, &MOUSE_INTERRUPT, handle_mouse_interrupt)?;
+                config.insert(Config::SECOND_INTERRUPT);
+
+                true
+            }
+
+            Err(err) => {
+                warn!("PS/2 mouse failed to reset: {:?}", err);
+                controller.send_command(command::DISABLE_SECOND_PORT)?;
+
+                false
+            }
+        }
+    };
+
+    controller.flush_output();
+    config = controller.read_config()? | (config & (Config::FIRST_INTERRUPT | Config::SECOND_INTERRUPT));
+    controller.write_config(config)?;
+
+    Ok((has_keyboard, has_mouse))
+}
+
+fn request_interrupt(isa_irq: u8, interrupt: &spin::Once<Arc<Interrupt>>, handler: fn()) -> Result<()> {
+    let requested = irq::request_isa_irq(isa_irq, Box::new(handler)).map_err(|err| Error::Interrupt { err })?;
+    debug!("PS/2 IRQ {} is routed through GSI {:?} to vector {:#X}.", isa_irq, requested.gsi(), requested.vector());
+    interrupt.call_once(|| requested);
+
+    Ok(())
+}
+
+/// An input event from the keyboard or mouse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Key(KeyEvent),
+    Mouse(MouseEvent),
+}
+
+static EVENTS: Mutex<VecDeque<Event>> = Mutex::new(VecDeque::new());
+
+/// Takes the oldest queued input event.
+pub fn next_event() -> Option<Event> {
+    crate::interrupts::without(|| EVENTS.lock().pop_front())
+}
+
+fn push_event(event: Event) {
+    let mut events = EVENTS.lock();
+    if events.len() >= MAX_PENDING_EVENTS {
+        events.pop_front();
+    }
+    events.push_back(event);
+    drop(events);
+
+    match event {
+        Event::Key(KeyEvent { key, character, pressed, .. }) => {
+            display::push_input(Input::Key { key: usize::from(key), character, pressed });
+        }
+
+        Event::Mouse(MouseEvent { dx, dy, buttons, changed }) => {
+            if dx != 0 || dy != 0 {
+                display::push_input(Input::PointerMotion { dx, dy });
+            }
+
+            let changed_buttons = [MouseButtons::LEFT, MouseButtons::RIGHT, MouseButtons::MIDDLE]
+                .into_iter()
+                .enumerate()
+                .filter(|(_, button)| changed.contains(*button));
+            for (index, button) in changed_buttons {
+                display::push_input(Input::PointerButton { button: index, pressed: buttons.contains(button) });
+            }
+        }
+    }
+}
+
+/// Handles the keyboard's interrupt, which is raised once for each byte it sends.
+fn handle_keyboard_interrupt() {
+    let byte = CONTROLLER.lock().data.read();
+
+    if let Some(event) = KEYBOARD.lock().decode(byte) {
+        push_event(Event::Key(event));
+    }
+}
+
+/// Handles the mouse's interrupt, which is raised once for each byte it sends.
+fn handle_mouse_interrupt() {
+    let byte = CONTROLLER.lock().data.read();
+
+    if let Some(event) = MOUSE.lock().decode(byte) {
+        push_event(Event::Mouse(event));
+    }
+}
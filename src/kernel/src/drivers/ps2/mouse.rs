@@ -0,0 +1,72 @@
+//! Decodes the movement packets of a standard PS/2 mouse.
+
+bitflags::bitflags! {
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct MouseButtons: u8 {
+        const LEFT = 1 << 0;
+        const RIGHT = 1 << 1;
+        const MIDDLE = 1 << 2;
+    }
+}
+
+/// Bit which is always set in the first byte of a packet.
+const PACKET_SYNC: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+/// Movement of the mouse, and the state of its buttons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// Distance moved rightward.
+    pub dx: isize,
+    /// Distance moved downward, following screen coordinates (the mouse reports movement upward).
+    pub dy: isize,
+    /// Buttons which are held down.
+    pub buttons: MouseButtons,
+    /// Buttons which were pressed or released since the last event.
+    pub changed: MouseButtons,
+}
+
+pub struct Mouse {
+    packet: [u8; 3],
+    index: usize,
+    buttons: MouseButtons,
+}
+
+impl Mouse {
+    pub const fn new() -> Self {
+        Self { packet: [0; 3], index: 0, buttons: MouseButtons::empty() }
+    }
+
+    /// Decodes the next byte from the mouse, returning an event once a whole packet has been received.
+    pub fn decode(&mut self, byte: u8) -> Option<MouseEvent> {
+        // If a byte is ever lost, bytes are discarded until one looks like the start of a packet again.
+        if self.index == 0 && (byte & PACKET_SYNC) == 0 {
+            return None;
+        }
+
+        self.packet[self.index] = byte;
+        self.index += 1;
+        if self.index < self.packet.len() {
+            return None;
+        }
+        self.index = 0;
+
+        let [flags, x, y] = self.packet;
+        // Movement which overflowed is meaningless, so the whole packet is discarded.
+        if (flags & (X_OVERFLOW | Y_OVERFLOW)) != 0 {
+            return None;
+        }
+
+        let dx = isize::from(x) - if (flags & X_SIGN) == 0 { 0 } else { 0x100 };
+        let dy = isize::from(y) - if (flags & Y_SIGN) == 0 { 0 } else { 0x100 };
+        let buttons = MouseButtons::from_bits_truncate(flags);
+        let changed = buttons ^ self.buttons;
+        self.buttons = buttons;
+
+        Some(MouseEvent { dx, dy: -dy, buttons, changed })
+    }
+}
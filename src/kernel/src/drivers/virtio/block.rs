@@ -0,0 +1,402 @@
+use super::{
+    pci::Transport,
+    queue::{Buffer, VirtQueue},
+    Error, Result,
+};
+use crate::mem::{dma::DmaBuffer, io::pci};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{
+    fmt,
+    num::NonZeroUsize,
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use libsys::page_size;
+use spin::{Mutex, RwLock};
+
+bitflags::bitflags! {
+    /// Features of a virtio block device which the driver negotiates.
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Features: u64 {
+        const SIZE_MAX = 1 << 1;
+        const SEG_MAX = 1 << 2;
+        const RO = 1 << 5;
+        const BLK_SIZE = 1 << 6;
+        const FLUSH = 1 << 9;
+    }
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RequestType {
+    In = 0,
+    Out = 1,
+    Flush = 4,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct RequestHeader {
+    ty: RequestType,
+    reserved: u32,
+    /// Offset of the request, in 512-byte sectors regardless of the device's block size.
+    sector: u64,
+}
+
+/// The request queue, along with the memory requests are built in.
+struct Requests {
+    queue: VirtQueue,
+    /// Request header, followed by the status byte the device writes.
+    header: DmaBuffer,
+    /// Bounce buffer that data is transferred through.
+    buffer: DmaBuffer,
+}
+
+impl Requests {
+    const REQUEST_TIMEOUT_US: u32 = 5_000_000;
+    const POLL_INTERVAL_US: u32 = 10;
+    const STATUS_OFFSET: usize = core::mem::size_of::<RequestHeader>();
+    const STATUS_OK: u8 = 0;
+
+    /// Submits a request which transfers the first `len` bytes of the bounce buffer, and polls for its completion.
+    ///
+    /// Requests which time out must be recovered from before the next is submitted (see [`Device::recover`]), so only
+    /// one request is ever outstanding, and the queue can never overflow.
+    fn execute(&mut self, ty: RequestType, sector: u64, len: usize) -> Result<()> {
+        // Safety: No request is outstanding, so the device isn't accessing the header or status.
+        unsafe {
+            let header_ptr = self.header.as_ptr().as_ptr();
+            header_ptr.cast::<RequestHeader>().write_volatile(RequestHeader { ty, reserved: 0, sector });
+            header_ptr.add(Self::STATUS_OFFSET).write_volatile(u8::MAX);
+        }
+
+        let header = Buffer {
+            address: self.header.physical_address(0),
+            len: u32::try_from(core::mem::size_of::<RequestHeader>()).unwrap(),
+            writable: false,
+        };
+        let status = Buffer { address: self.header.physical_address(Self::STATUS_OFFSET), len: 1, writable: true };
+
+        let id = if len > 0 {
+            let data = Buffer {
+                address: self.buffer.physical_address(0),
+                len: u32::try_from(len).unwrap(),
+                writable: ty == RequestType::In,
+            };
+
+            self.queue.submit(&[header, data, status])
+        } else {
+            self.queue.submit(&[header, status])
+        }
+        .ok_or(Error::QueueFull)?;
+
+        for _ in 0..(Self::REQUEST_TIMEOUT_US / Self::POLL_INTERVAL_US) {
+            if let Some((used_id, _)) = self.queue.pop_used() {
+                debug_assert_eq!(used_id, id, "device used a request which isn't outstanding");
+
+                // Safety: The request has completed, so the device is no longer writing the status.
+                let status = unsafe { self.header.as_ptr().as_ptr().add(Self::STATUS_OFFSET).read_volatile() };
+
+                return if status == Self::STATUS_OK { Ok(()) } else { Err(Error::RequestFailed { status }) };
+            }
+
+            crate::time::SYSTEM_CLOCK.spin_wait_us(Self::POLL_INTERVAL_US);
+        }
+
+        Err(Error::Timeout)
+    }
+}
+
+/// A virtio block device, which serves logical block reads and writes.
+pub struct Device {
+    // The transport is dropped first, resetting the device before the request queue is freed.
+    transport: Transport,
+    requests: Mutex<Requests>,
+    /// Whether the device was marked failed, as it couldn't be reset after a request timed out.
+    failed: AtomicBool,
+    features: Features,
+    block_size: usize,
+    block_count: u64,
+    /// Number of bytes a single request may transfer.
+    max_transfer: usize,
+}
+
+impl Device {
+    const SECTOR_SIZE: usize = 512;
+    const BUFFER_PAGES: usize = 32;
+    const QUEUE_SIZE: u16 = 16;
+
+    /// Offsets into the device configuration structure.
+    const CONFIG_CAPACITY: usize = 0x0;
+    const CONFIG_SIZE_MAX: usize = 0x8;
+    const CONFIG_BLK_SIZE: usize = 0x14;
+
+    /// Negotiates features with the claimed device, and creates its request queue.
+    pub fn new(claim: pci::Claim) -> Result<Self> {
+        let transport = Transport::new(claim)?;
+        let features = Features::from_bits_truncate(transport.negotiate_features(Features::all().bits())?);
+
+        let requests = match Self::create_requests(&transport) {
+            Ok(requests) => requests,
+            Err(err) => {
+                transport.fail();
+                return Err(err);
+            }
+        };
+
+        let (capacity, size_max, blk_size) = transport.read_device_config(|config| {
+            (
+                config.read_u64(Self::CONFIG_CAPACITY),
+                config.read_u32(Self::CONFIG_SIZE_MAX),
+                config.read_u32(Self::CONFIG_BLK_SIZE),
+            )
+        });
+
+        // Block size is only a hint of the optimal request granularity, so it's only used if it's a whole number of
+        // sectors.
+        let block_size = Some(usize::try_from(blk_size).unwrap())
+            .filter(|block_size| {
+                features.contains(Features::BLK_SIZE) && *block_size > 0 && (*block_size % Self::SECTOR_SIZE) == 0
+            })
+            .unwrap_or(Self::SECTOR_SIZE);
+        let block_count = (capacity * u64::try_from(Self::SECTOR_SIZE).unwrap()) / u64::try_from(block_size).unwrap();
+
+        let buffer_len = Self::BUFFER_PAGES * page_size();
+        let max_transfer = if features.contains(Features::SIZE_MAX) && size_max > 0 {
+            buffer_len.min(usize::try_from(size_max).unwrap())
+        } else {
+            buffer_len
+        };
+        let max_transfer = (max_transfer / block_size) * block_size;
+
+        transport.set_driver_ok();
+
+        Ok(Self {
+            transport,
+            requests: Mutex::new(requests),
+            failed: AtomicBool::new(false),
+            features,
+            block_size,
+            block_count,
+            max_transfer,
+        })
+    }
+
+    fn create_requests(transport: &Transport) -> Result<Requests> {
+        Ok(Requests {
+            queue: transport.setup_queue(0, Self::QUEUE_SIZE)?,
+            header: DmaBuffer::new(NonZeroUsize::MIN, None)?,
+            buffer: DmaBuffer::new(NonZeroUsize::new(Self::BUFFER_PAGES).unwrap(), None)?,
+        })
+    }
+
+    /// Executes a request on the request queue. If it times out, the device is reset before the request's buffers
+    /// can be reused.
+    fn execute(&self, requests: &mut Requests, ty: RequestType, sector: u64, len: usize) -> Result<()> {
+        if self.failed.load(Ordering::Acquire) {
+            return Err(Error::Failed);
+        }
+
+        let result = requests.execute(ty, sector, len);
+        if matches!(result, Err(Error::Timeout)) {
+            self.recover(requests);
+        }
+
+        result
+    }
+
+    /// Resets the device after a request timed out, so it stops accessing the request's buffers (which the driver
+    /// still owns), and replaces the request queue. If the device can't be brought back up, it's marked failed.
+    fn recover(&self, requests: &mut Requests) {
+        warn!("virtio block request timed out, resetting the device.");
+
+        // Negotiating features resets the device first, which returns every virtqueue to the driver.
+        let queue = self
+            .transport
+            .negotiate_features(self.features.bits())
+            .and_then(|_| self.transport.setup_queue(0, Self::QUEUE_SIZE));
+
+        match queue {
+            Ok(queue) => {
+                requests.queue = queue;
+                self.transport.set_driver_ok();
+            }
+
+            Err(err) => {
+                error!("Failed to reset virtio block device, marking it failed: {:?}", err);
+                self.transport.fail();
+                self.failed.store(true, Ordering::Release);
+            }
+        }
+    }
+
+    /// Size of the device's logical blocks, in bytes.
+    #[inline]
+    pub const fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    #[inline]
+    pub const fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    #[inline]
+    pub const fn is_read_only(&self) -> bool {
+        self.features.contains(Features::RO)
+    }
+
+    /// Splits a transfer of `len` bytes starting at `lba` into requests which fit the bounce buffer. `func` is called
+    /// for each with its starting sector, and the range of bytes it covers.
+    fn for_each_transfer(
+        &self,
+        lba: u64,
+        len: usize,
+        mut func: impl FnMut(&mut Requests, u64, Range<usize>) -> Result<()>,
+    ) -> Result<()> {
+        if len % self.block_size != 0 {
+            return Err(Error::UnalignedBuffer { len });
+        }
+
+        let count = u64::try_from(len / self.block_size).unwrap();
+        if lba.checked_add(count).map_or(true, |end_lba| end_lba > self.block_count) {
+            return Err(Error::OutOfBounds { lba, count });
+        }
+
+        let sectors_per_block = u64::try_from(self.block_size / Self::SECTOR_SIZE).unwrap();
+        let mut requests = self.requests.lock();
+        for start in (0..len).step_by(self.max_transfer) {
+            let range = start..(start + self.max_transfer).min(len);
+            let sector = (lba * sectors_per_block) + u64::try_from(start / Self::SECTOR_SIZE).unwrap();
+
+            func(&mut requests, sector, range)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the logical blocks starting at `lba` into `buffer`, whose length must be a multiple of the block size.
+    pub fn read(&self, lba: u64, buffer: &mut [u8]) -> Result<()> {
+        self.for_each_transfer(lba, buffer.len(), |requests, sector, range| {
+            self.execute(requests, RequestType::In, sector, range.len())?;
+
+            // Safety: The request has completed, so the device is no longer writing to the buffer.
+            let transferred = unsafe { &requests.buffer.as_slice()[..range.len()] };
+            buffer[range].copy_from_slice(transferred);
+
+            Ok(())
+        })
+    }
+
+    /// Writes `buffer`, whose length must be a multiple of the block size, to the logical blocks starting at `lba`.
+    pub fn write(&self, lba: u64, buffer: &[u8]) -> Result<()> {
+        if self.is_read_only() {
+            return Err(Error::ReadOnly);
+        }
+
+        self.for_each_transfer(lba, buffer.len(), |requests, sector, range| {
+            // Safety: No request is outstanding, so the device isn't accessing the buffer.
+            unsafe { requests.buffer.as_mut_slice()[..range.len()].copy_from_slice(&buffer[range.clone()]) };
+
+            self.execute(requests, RequestType::Out, sector, range.len())
+        })
+    }
+
+    /// Commits any cached writes to non-volatile media.
+    pub fn flush(&self) -> Result<()> {
+        // Devices which can't flush don't cache writes.
+        if !self.features.contains(Features::FLUSH) {
+            return Ok(());
+        }
+
+        self.execute(&mut self.requests.lock(), RequestType::Flush, 0, 0)
+    }
+}
+
+impl fmt::Debug for Device {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("virtio Block Device")
+            .field("Features", &self.features)
+            .field("Block Size", &self.block_size)
+            .field("Block Count", &self.block_count)
+            .field("Max Transfer", &self.max_transfer)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Requests complete synchronously, within their first poll, as the device polls for completions.
+impl crate::block::BlockDevice for Device {
+    fn sector_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn is_read_only(&self) -> bool {
+        Device::is_read_only(self)
+    }
+
+    fn read<'a>(&'a self, lba: u64, buffer: &'a mut [u8]) -> crate::block::Request<'a> {
+        Box::pin(async move { Ok(Device::read(self, lba, buffer)?) })
+    }
+
+    fn write<'a>(&'a self, lba: u64, buffer: &'a [u8]) -> crate::block::Request<'a> {
+        Box::pin(async move { Ok(Device::write(self, lba, buffer)?) })
+    }
+
+    fn flush(&self) -> crate::block::Request<'_> {
+        Box::pin(async move { Ok(Device::flush(self)?) })
+    }
+}
+
+static DEVICES: RwLock<Vec<Arc<Device>>> = RwLock::new(Vec::new());
+
+/// Every virtio block device which has been brought up.
+pub fn devices() -> Vec<Arc<Device>> {
+    DEVICES.read().clone()
+}
+
+/// Claims and brings up every virtio block device on the PCI bus.
+pub fn init() {
+    // Modern and transitional device IDs, respectively.
+    const DEVICE_IDS: [u16; 2] = [0x1042, 0x1001];
+
+    let indexes = pci::with_devices(|devices| {
+        devices
+            .iter()
+            .enumerate()
+            .filter(|(_, device)| {
+                device.get_vendor_id() == super::PCI_VENDOR_ID && DEVICE_IDS.contains(&device.get_device_id())
+            })
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>()
+    });
+
+    for index in indexes {
+        let device =
+            pci::claim(index, *crate::init::KERNEL_HANDLE).map_err(|err| Error::Pci { err }).and_then(Device::new);
+
+        match device {
+            Ok(device) => {
+                info!("virtio block device {} is ready: {:?}", index, device);
+
+                // Reading the first block exercises the request queue.
+                let mut block = alloc::vec![0u8; device.block_size()];
+                match device.read(0, &mut block) {
+                    Ok(()) => debug!("virtio block device {} LBA 0: {:02X?}", index, &block[..16]),
+                    Err(err) => error!("Failed to read virtio block device {}: {:?}", index, err),
+                }
+
+                let device = Arc::new(device);
+                crate::block::register(device.clone());
+                DEVICES.write().push(device);
+            }
+
+            Err(err) => error!("Failed to bring up virtio block device {}: {:?}", index, err),
+        }
+    }
+}
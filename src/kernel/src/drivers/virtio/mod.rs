@@ -0,0 +1,57 @@
+pub mod block;
+pub mod pci;
+pub mod queue;
+
+use crate::mem::{alloc::pmm, io::pci as bus};
+
+crate::error_impl! {
+    #[derive(Debug)]
+    pub enum Error {
+        /// Indicates the device lacks a configuration structure required by the modern PCI transport.
+        MissingCapabilities => None,
+
+        /// Indicates the device only supports the legacy (pre-1.0) interface.
+        LegacyOnly => None,
+
+        /// Indicates the device didn't accept the negotiated features.
+        FeaturesRejected { features: u64 } => None,
+
+        /// Indicates the device has no virtqueue with the provided index.
+        UnknownQueue { index: u16 } => None,
+
+        /// Indicates a virtqueue had too few free descriptors for a request.
+        QueueFull => None,
+
+        /// Indicates the device didn't complete a request within its timeout.
+        Timeout => None,
+
+        /// Indicates a request completed with an error status.
+        RequestFailed { status: u8 } => None,
+
+        /// Indicates the device was marked failed, as it couldn't be reset after a request timed out.
+        Failed => None,
+
+        /// Indicates a write was attempted to a read-only device.
+        ReadOnly => None,
+
+        /// Indicates a transfer would extend past the end of the device.
+        OutOfBounds { lba: u64, count: u64 } => None,
+
+        /// Indicates a buffer's length isn't a multiple of the device's block size.
+        UnalignedBuffer { len: usize } => None,
+
+        /// Indicates there wasn't enough physical memory for a virtqueue or buffer.
+        OutOfMemory { err: pmm::Error } => None,
+
+        Pci { err: bus::Error } => Some(err)
+    }
+}
+
+impl From<pmm::Error> for Error {
+    fn from(err: pmm::Error) -> Self {
+        Self::OutOfMemory { err }
+    }
+}
+
+/// PCI vendor ID of every virtio device.
+pub const PCI_VENDOR_ID: u16 = 0x1AF4;
@@ -0,0 +1,263 @@
+use super::{queue::VirtQueue, Error, Result};
+use crate::mem::{io::pci, HHDM};
+use bit_field::BitField;
+use core::ptr::NonNull;
+use libkernel::{mem::VolatileCell, ReadOnly, ReadWrite};
+
+bitflags::bitflags! {
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct DeviceStatus: u8 {
+        const ACKNOWLEDGE = 1 << 0;
+        const DRIVER = 1 << 1;
+        const DRIVER_OK = 1 << 2;
+        const FEATURES_OK = 1 << 3;
+        const DEVICE_NEEDS_RESET = 1 << 6;
+        const FAILED = 1 << 7;
+    }
+}
+
+/// Common configuration structure of the virtio PCI transport.
+/// An explanation of these values can be found at:
+///     https://docs.oasis-open.org/virtio/virtio/v1.2/virtio-v1.2.html
+///     Section 4.1.4.3
+#[repr(C)]
+pub struct CommonConfig {
+    device_feature_select: VolatileCell<u32, ReadWrite>,
+    device_feature: VolatileCell<u32, ReadOnly>,
+    driver_feature_select: VolatileCell<u32, ReadWrite>,
+    driver_feature: VolatileCell<u32, ReadWrite>,
+    config_msix_vector: VolatileCell<u16, ReadWrite>,
+    num_queues: VolatileCell<u16, ReadOnly>,
+    device_status: VolatileCell<u8, ReadWrite>,
+    config_generation: VolatileCell<u8, ReadOnly>,
+    queue_select: VolatileCell<u16, ReadWrite>,
+    queue_size: VolatileCell<u16, ReadWrite>,
+    queue_msix_vector: VolatileCell<u16, ReadWrite>,
+    queue_enable: VolatileCell<u16, ReadWrite>,
+    queue_notify_off: VolatileCell<u16, ReadOnly>,
+    // 64-bit fields are accessed as two dwords, which every device must support.
+    queue_desc: [VolatileCell<u32, ReadWrite>; 2],
+    queue_driver: [VolatileCell<u32, ReadWrite>; 2],
+    queue_device: [VolatileCell<u32, ReadWrite>; 2],
+}
+
+const _: () = assert!(core::mem::size_of::<CommonConfig>() == 0x38);
+
+/// Writes `value` to a 64-bit field accessed as two dwords.
+fn write_split(field: &[VolatileCell<u32, ReadWrite>; 2], value: u64) {
+    field[0].write(u32::try_from(value.get_bits(0..32)).unwrap());
+    field[1].write(u32::try_from(value.get_bits(32..64)).unwrap());
+}
+
+/// Type of the configuration structure a virtio vendor capability describes.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfigType {
+    Common = 1,
+    Notify = 2,
+    Isr = 3,
+    Device = 4,
+}
+
+/// A claimed virtio device, accessed through the modern (virtio 1.0+) PCI transport.
+pub struct Transport {
+    claim: pci::Claim,
+    common: &'static CommonConfig,
+    notify: NonNull<u8>,
+    notify_off_multiplier: u32,
+    device_config: NonNull<u8>,
+}
+
+// Safety: Registers are accessed through the global HHDM.
+unsafe impl Send for Transport {}
+// Safety: See above.
+unsafe impl Sync for Transport {}
+
+impl Transport {
+    /// Feature bit indicating compliance with virtio 1.0+, which the modern transport requires.
+    pub const FEATURE_VERSION_1: u64 = 1 << 32;
+    /// Vector value which disables MSI-X delivery of an event.
+    const NO_VECTOR: u16 = 0xFFFF;
+
+    /// Locates the configuration structures of the claimed device from its vendor capabilities.
+    pub fn new(claim: pci::Claim) -> Result<Self> {
+        let (common, notify, device_config) = claim.with_device(|device| {
+            // Requests are polled, so the device's pin interrupt is disabled.
+            let mut command = device.get_command();
+            command.insert(pci::Command::MEMORY_SPACE | pci::Command::BUS_MASTER | pci::Command::INTERRUPT_DISABLE);
+            device.set_command(command);
+
+            let bars = device.get_bars();
+            let mut common = None;
+            let mut notify = None;
+            let mut device_config = None;
+
+            for capability in device.vendor_capabilities() {
+                // A capability may point to a BAR which doesn't exist, which is ignored like an unknown type.
+                let Some(bar) = bars.get(usize::from(capability.read_u8(4))).copied().flatten() else { continue };
+                let address = (bar.get_address().get() & !0xF) + usize::try_from(capability.read_u32(8)).unwrap();
+                // Safety: Like PCI configuration space, device BARs are accessed through the HHDM.
+                let ptr = NonNull::new(unsafe { HHDM.ptr().add(address) }).unwrap();
+
+                // The first capability of each type is preferred, as specified.
+                match capability.read_u8(3) {
+                    ty if ty == ConfigType::Common as u8 => common = common.or(Some(ptr)),
+                    ty if ty == ConfigType::Notify as u8 => notify = notify.or(Some((ptr, capability.read_u32(16)))),
+                    ty if ty == ConfigType::Device as u8 => device_config = device_config.or(Some(ptr)),
+                    _ => {}
+                }
+            }
+
+            (common, notify, device_config)
+        });
+
+        match (common, notify, device_config) {
+            (Some(common), Some((notify, notify_off_multiplier)), Some(device_config)) => Ok(Self {
+                claim,
+                // Safety: Capability describes the common configuration structure.
+                common: unsafe { common.cast::<CommonConfig>().as_ref() },
+                notify,
+                notify_off_multiplier,
+                device_config,
+            }),
+
+            _ => Err(Error::MissingCapabilities),
+        }
+    }
+
+    pub fn status(&self) -> DeviceStatus {
+        DeviceStatus::from_bits_retain(self.common.device_status.read())
+    }
+
+    fn add_status(&self, status: DeviceStatus) {
+        self.common.device_status.write((self.status() | status).bits());
+    }
+
+    /// Resets the device, waiting for the reset to complete.
+    pub fn reset(&self) {
+        self.common.device_status.write(0);
+
+        while self.common.device_status.read() != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Resets the device, and negotiates the features in `supported` which the device offers. Returns the
+    /// negotiated features.
+    pub fn negotiate_features(&self, supported: u64) -> Result<u64> {
+        self.reset();
+        self.add_status(DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER);
+
+        self.common.device_feature_select.write(0);
+        let mut offered = u64::from(self.common.device_feature.read());
+        self.common.device_feature_select.write(1);
+        offered |= u64::from(self.common.device_feature.read()) << 32;
+
+        let features = offered & (supported | Self::FEATURE_VERSION_1);
+        if (features & Self::FEATURE_VERSION_1) == 0 {
+            self.fail();
+            return Err(Error::LegacyOnly);
+        }
+
+        self.common.driver_feature_select.write(0);
+        self.common.driver_feature.write(u32::try_from(features.get_bits(0..32)).unwrap());
+        self.common.driver_feature_select.write(1);
+        self.common.driver_feature.write(u32::try_from(features.get_bits(32..64)).unwrap());
+
+        self.add_status(DeviceStatus::FEATURES_OK);
+        // The device clears the bit if it doesn't accept the features.
+        if self.status().contains(DeviceStatus::FEATURES_OK) {
+            Ok(features)
+        } else {
+            self.fail();
+            Err(Error::FeaturesRejected { features })
+        }
+    }
+
+    /// Creates and enables the virtqueue `index`, with at most `max_size` entries. Features must have been
+    /// negotiated, and the device must not yet be live.
+    pub fn setup_queue(&self, index: u16, max_size: u16) -> Result<VirtQueue> {
+        if index >= self.common.num_queues.read() {
+            return Err(Error::UnknownQueue { index });
+        }
+
+        self.common.queue_select.write(index);
+
+        // The device's maximum queue size is a power of two (or zero if the queue is unavailable), as is `max_size`.
+        let size = match self.common.queue_size.read() {
+            0 => return Err(Error::UnknownQueue { index }),
+            size => size.min(max_size.next_power_of_two()),
+        };
+        let notify_offset =
+            usize::from(self.common.queue_notify_off.read()) * usize::try_from(self.notify_off_multiplier).unwrap();
+
+        // Safety: The notify address is within the notification structure described by the device's capability.
+        let queue = unsafe { VirtQueue::new(index, size, self.notify.as_ptr().add(notify_offset).cast()) }?;
+
+        self.common.queue_size.write(size);
+        self.common.queue_msix_vector.write(Self::NO_VECTOR);
+        write_split(&self.common.queue_desc, queue.descriptors_address());
+        write_split(&self.common.queue_driver, queue.available_address());
+        write_split(&self.common.queue_device, queue.used_address());
+        self.common.queue_enable.write(1);
+
+        Ok(queue)
+    }
+
+    /// Marks the driver as ready, making the device live.
+    pub fn set_driver_ok(&self) {
+        self.common.config_msix_vector.write(Self::NO_VECTOR);
+        self.add_status(DeviceStatus::DRIVER_OK);
+    }
+
+    /// Marks the device as failed, so it stops processing requests.
+    pub fn fail(&self) {
+        self.add_status(DeviceStatus::FAILED);
+    }
+
+    /// Reads a consistent snapshot of the device-specific configuration, by calling `func` until the configuration
+    /// doesn't change while it's read.
+    pub fn read_device_config<T>(&self, mut func: impl FnMut(&DeviceConfig) -> T) -> T {
+        loop {
+            let generation = self.common.config_generation.read();
+            let value = func(&DeviceConfig(self.device_config));
+
+            if generation == self.common.config_generation.read() {
+                return value;
+            }
+        }
+    }
+
+    #[inline]
+    pub const fn claim(&self) -> &pci::Claim {
+        &self.claim
+    }
+}
+
+impl Drop for Transport {
+    fn drop(&mut self) {
+        // Ensure the device stops accessing any virtqueues before they're freed.
+        self.reset();
+    }
+}
+
+/// The device-specific configuration structure, whose layout depends on the device type.
+pub struct DeviceConfig(NonNull<u8>);
+
+impl DeviceConfig {
+    /// Reads the dword at `offset` into the structure.
+    ///
+    /// REMARK: The offset is trusted to be within the structure for the device's type.
+    pub fn read_u32(&self, offset: usize) -> u32 {
+        assert_eq!(offset % 4, 0, "offset must be dword-aligned");
+
+        // Safety: Caller trusts the offset is within the structure, which is mapped through the HHDM.
+        unsafe { self.0.as_ptr().add(offset).cast::<u32>().read_volatile() }
+    }
+
+    /// Reads the qword at `offset` into the structure, as two dwords.
+    pub fn read_u64(&self, offset: usize) -> u64 {
+        u64::from(self.read_u32(offset)) | (u64::from(self.read_u32(offset + 4)) << 32)
+    }
+}
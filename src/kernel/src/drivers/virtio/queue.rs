@@ -0,0 +1,271 @@
+use super::Result;
+use crate::mem::dma::DmaBuffer;
+use core::{
+    num::NonZeroUsize,
+    sync::atomic::{fence, Ordering},
+};
+use libkernel::{mem::VolatileCell, ReadWrite};
+
+/// Virtqueue descriptor, which describes a buffer the device reads from or writes to.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Descriptor {
+    address: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+impl Descriptor {
+    /// The descriptor continues via the `next` field.
+    const F_NEXT: u16 = 1 << 0;
+    /// The buffer is written by the device (otherwise it's read).
+    const F_WRITE: u16 = 1 << 1;
+}
+
+/// Element of the used ring, which returns a descriptor chain to the driver.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct UsedElement {
+    id: u32,
+    len: u32,
+}
+
+/// A buffer in physical memory, which makes up part of a request.
+#[derive(Debug, Clone, Copy)]
+pub struct Buffer {
+    pub address: u64,
+    pub len: u32,
+    /// Whether the device writes to the buffer, rather than reading it.
+    pub writable: bool,
+}
+
+/// A split virtqueue, laid out in a single physically contiguous allocation.
+/// An explanation of the layout can be found at:
+///     https://docs.oasis-open.org/virtio/virtio/v1.2/virtio-v1.2.html
+///     Section 2.7
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    memory: DmaBuffer,
+    notify: &'static VolatileCell<u16, ReadWrite>,
+    /// Head of the chain of free descriptors, linked through their `next` fields.
+    free_head: u16,
+    free_count: u16,
+    /// Index of the next available ring entry (which is only advanced by the driver).
+    next_available: u16,
+    /// Index of the next used ring entry to be returned to the driver.
+    next_used: u16,
+}
+
+// Safety: The notify register is device MMIO, which is only written through an exclusive borrow of the queue.
+unsafe impl Send for VirtQueue {}
+
+impl VirtQueue {
+    const AVAILABLE_F_NO_INTERRUPT: u16 = 1 << 0;
+
+    /// ### Safety
+    ///
+    /// Caller must ensure `notify` is the queue's notification address, which remains valid for the lifetime of the
+    /// queue.
+    pub unsafe fn new(index: u16, size: u16, notify: *const VolatileCell<u16, ReadWrite>) -> Result<Self> {
+        let len = Self::used_offset(size) + 6 + (usize::from(size) * core::mem::size_of::<UsedElement>());
+        let page_count = NonZeroUsize::new(libsys::align_up_div(len, libsys::page_shift())).unwrap();
+        let memory = DmaBuffer::new(page_count, None)?;
+
+        let queue = Self {
+            index,
+            size,
+            memory,
+            notify: &*notify,
+            free_head: 0,
+            free_count: size,
+            next_available: 0,
+            next_used: 0,
+        };
+
+        // Link every descriptor into the free chain.
+        for index in 0..size {
+            queue.descriptor(index).write_volatile(Descriptor {
+                address: 0,
+                len: 0,
+                flags: 0,
+                next: index.wrapping_add(1),
+            });
+        }
+
+        // Requests are polled, so the device needn't interrupt when it uses them.
+        queue.available_ptr(0).write_volatile(Self::AVAILABLE_F_NO_INTERRUPT);
+
+        Ok(queue)
+    }
+
+    const fn available_offset(size: u16) -> usize {
+        (size as usize) * core::mem::size_of::<Descriptor>()
+    }
+
+    const fn used_offset(size: u16) -> usize {
+        let available_end = Self::available_offset(size) + 6 + ((size as usize) * core::mem::size_of::<u16>());
+
+        // The used ring must be 4-byte aligned.
+        (available_end + 3) & !3
+    }
+
+    #[inline]
+    pub const fn index(&self) -> u16 {
+        self.index
+    }
+
+    #[inline]
+    pub const fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn descriptors_address(&self) -> u64 {
+        self.memory.physical_address(0)
+    }
+
+    pub fn available_address(&self) -> u64 {
+        self.memory.physical_address(Self::available_offset(self.size))
+    }
+
+    pub fn used_address(&self) -> u64 {
+        self.memory.physical_address(Self::used_offset(self.size))
+    }
+
+    fn descriptor(&self, index: u16) -> *mut Descriptor {
+        assert!(index < self.size, "descriptor index is out of bounds of the queue");
+
+        // Safety: Index is within the descriptor table, which begins the queue's memory.
+        unsafe { self.memory.as_ptr().as_ptr().cast::<Descriptor>().add(usize::from(index)) }
+    }
+
+    /// Pointer to the `u16` at `index` into the available ring structure (flags, index, then the ring itself).
+    fn available_ptr(&self, index: usize) -> *mut u16 {
+        // Safety: Offset is within the available ring, which is within the queue's memory.
+        unsafe { self.memory.as_ptr().as_ptr().add(Self::available_offset(self.size)).cast::<u16>().add(index) }
+    }
+
+    /// Pointer to the `u16` index field of the used ring.
+    fn used_index_ptr(&self) -> *const u16 {
+        // Safety: Offset is within the used ring, which is within the queue's memory.
+        unsafe { self.memory.as_ptr().as_ptr().add(Self::used_offset(self.size) + 2).cast::<u16>() }
+    }
+
+    fn used_element(&self, index: u16) -> UsedElement {
+        let ring_index = usize::from(index % self.size);
+
+        // Safety: Ring index is within the used ring, which is within the queue's memory.
+        unsafe {
+            self.memory
+                .as_ptr()
+                .as_ptr()
+                .add(Self::used_offset(self.size) + 4)
+                .cast::<UsedElement>()
+                .add(ring_index)
+                .read_volatile()
+        }
+    }
+
+    /// Places `buffers` on the queue as a single descriptor chain, and notifies the device. Returns the ID of the
+    /// chain, or `None` if there aren't enough free descriptors.
+    pub fn submit(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        let count = u16::try_from(buffers.len()).ok()?;
+        if count == 0 || count > self.free_count {
+            return None;
+        }
+
+        let head = self.free_head;
+        let mut index = head;
+        for (buffer_index, buffer) in buffers.iter().enumerate() {
+            let descriptor = self.descriptor(index);
+            // Safety: Descriptor is free, so the device isn't accessing it.
+            let next = unsafe { descriptor.read_volatile() }.next;
+
+            let mut flags = 0;
+            if buffer.writable {
+                flags |= Descriptor::F_WRITE;
+            }
+            if (buffer_index + 1) < buffers.len() {
+                flags |= Descriptor::F_NEXT;
+            }
+
+            // Safety: See above.
+            unsafe { descriptor.write_volatile(Descriptor { address: buffer.address, len: buffer.len, flags, next }) };
+
+            if (flags & Descriptor::F_NEXT) > 0 {
+                index = next;
+            } else {
+                self.free_head = next;
+            }
+        }
+        self.free_count -= count;
+
+        let ring_index = usize::from(self.next_available % self.size);
+        // Safety: Available ring entries beyond the index aren't read by the device.
+        unsafe { self.available_ptr(2 + ring_index).write_volatile(head) };
+        self.next_available = self.next_available.wrapping_add(1);
+
+        // Ensure the descriptors and ring entry are visible before the index, and the index before the notification.
+        fence(Ordering::SeqCst);
+        // Safety: The available index is only written by the driver.
+        unsafe { self.available_ptr(1).write_volatile(self.next_available) };
+        fence(Ordering::SeqCst);
+        self.notify.write(self.index);
+
+        Some(head)
+    }
+
+    /// Takes the next chain the device has finished with, returning its ID and the number of bytes the device
+    /// wrote to it. Its descriptors are returned to the free chain.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        // Safety: The used index is written atomically by the device.
+        let used_index = unsafe { self.used_index_ptr().read_volatile() };
+        if used_index == self.next_used {
+            return None;
+        }
+
+        // Ensure the element is read only after the index which exposed it.
+        fence(Ordering::SeqCst);
+        let element = self.used_element(self.next_used);
+        self.next_used = self.next_used.wrapping_add(1);
+
+        let head = u16::try_from(element.id).unwrap();
+        let mut tail = head;
+        let mut count = 1;
+        loop {
+            // Safety: The device has returned the chain, so it's no longer accessing its descriptors.
+            let descriptor = unsafe { self.descriptor(tail).read_volatile() };
+            if (descriptor.flags & Descriptor::F_NEXT) == 0 {
+                break;
+            }
+
+            tail = descriptor.next;
+            count += 1;
+        }
+
+        // Prepend the chain to the free chain.
+        // Safety: See above.
+        unsafe {
+            let descriptor = self.descriptor(tail);
+            descriptor.write_volatile(Descriptor { next: self.free_head, ..descriptor.read_volatile() });
+        }
+        self.free_head = head;
+        self.free_count += count;
+
+        Some((head, element.len))
+    }
+}
+
+impl core::fmt::Debug for VirtQueue {
+    fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        formatter
+            .debug_struct("VirtQueue")
+            .field("Index", &self.index)
+            .field("Size", &self.size)
+            .field("Free", &self.free_count)
+            .field("Next Available", &self.next_available)
+            .field("Next Used", &self.next_used)
+            .finish()
+    }
+}
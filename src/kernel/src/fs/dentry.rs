@@ -0,0 +1,250 @@
+use super::{Error, Inode, Metadata, Result};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::fmt;
+use libsys::syscall::fs::{FileKind, MAX_NAME_LEN};
+use spin::RwLock;
+
+/// Number of symbolic links a single path lookup may follow before it's considered a loop.
+const MAX_LINK_DEPTH: usize = 40;
+
+/// A named entry in the VFS tree, which caches the inode it refers to and its looked-up children.
+///
+/// Dentries are never evicted, so a mount remains attached to its mountpoint's dentry.
+pub struct Dentry {
+    name: String,
+    inode: Arc<dyn Inode>,
+    /// `None` for the root of the VFS tree, which is its own parent.
+    parent: Option<Weak<Dentry>>,
+    children: RwLock<BTreeMap<String, Arc<Dentry>>>,
+    /// Root of the filesystem mounted over this dentry, if any.
+    mounted: RwLock<Option<Arc<Dentry>>>,
+}
+
+impl Dentry {
+    pub(super) fn new_root(inode: Arc<dyn Inode>) -> Arc<Self> {
+        Arc::new(Self {
+            name: String::new(),
+            inode,
+            parent: None,
+            children: RwLock::new(BTreeMap::new()),
+            mounted: RwLock::new(None),
+        })
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    #[inline]
+    pub fn metadata(&self) -> Result<Metadata> {
+        self.inode.metadata()
+    }
+
+    pub fn parent(self: &Arc<Self>) -> Arc<Self> {
+        self.parent.as_ref().and_then(Weak::upgrade).unwrap_or_else(|| self.clone())
+    }
+
+    /// Absolute path of this dentry within the VFS tree.
+    pub fn path(self: &Arc<Self>) -> String {
+        let mut names = Vec::new();
+        let mut current = self.clone();
+        while let Some(parent) = current.parent.as_ref().and_then(Weak::upgrade) {
+            names.push(current.name.clone());
+            current = parent;
+        }
+
+        if names.is_empty() {
+            "/".to_string()
+        } else {
+            names.iter().rev().fold(String::new(), |path, name| path + "/" + name)
+        }
+    }
+
+    /// Follows any filesystems mounted over this dentry, returning the root of the topmost one.
+    pub(super) fn follow_mounts(self: Arc<Self>) -> Arc<Self> {
+        let mut current = self;
+        while let Some(mounted) = current.mounted.read().clone() {
+            current = mounted;
+        }
+
+        current
+    }
+
+    /// Mounts the filesystem rooted at `root` over this dentry.
+    pub(super) fn mount(self: &Arc<Self>, root: Arc<dyn Inode>) {
+        let mounted = Arc::new(Self {
+            name: self.name.clone(),
+            inode: root,
+            parent: self.parent.clone(),
+            children: RwLock::new(BTreeMap::new()),
+            mounted: RwLock::new(None),
+        });
+
+        *self.mounted.write() = Some(mounted);
+    }
+
+    /// Attaches the filesystem rooted at `root` as the entry `name` of this directory, hiding any entry of that name,
+    /// without creating the entry in this directory's filesystem.
+    pub(super) fn attach(self: &Arc<Self>, name: &str, root: Arc<dyn Inode>) -> Arc<Self> {
+        let child = Arc::new(Self {
+            name: name.to_string(),
+            inode: root,
+            parent: Some(Arc::downgrade(self)),
+            children: RwLock::new(BTreeMap::new()),
+            mounted: RwLock::new(None),
+        });
+
+        self.children.write().insert(name.to_string(), child.clone());
+
+        child
+    }
+
+    fn insert_child(self: &Arc<Self>, name: &str, inode: Arc<dyn Inode>) -> Arc<Self> {
+        let child = Arc::new(Self {
+            name: name.to_string(),
+            inode,
+            parent: Some(Arc::downgrade(self)),
+            children: RwLock::new(BTreeMap::new()),
+            mounted: RwLock::new(None),
+        });
+
+        self.children.write().entry(name.to_string()).or_insert(child).clone()
+    }
+
+    /// Gets the entry `name` of this directory, following any filesystems mounted over it.
+    pub fn child(self: &Arc<Self>, name: &str) -> Result<Arc<Self>> {
+        let cached = self.children.read().get(name).cloned();
+        let child = match cached {
+            Some(child) => child,
+            None => self.insert_child(name, self.inode.lookup(name)?),
+        };
+
+        Ok(child.follow_mounts())
+    }
+
+    /// Creates the entry `name` of this directory, as an empty file of `kind`.
+    pub fn create(self: &Arc<Self>, name: &str, kind: FileKind) -> Result<Arc<Self>> {
+        check_name(name)?;
+        let inode = self.inode.create(name, kind)?;
+
+        Ok(self.insert_child(name, inode))
+    }
+
+    /// Creates the entry `name` of this directory, as a symbolic link to `target`.
+    pub fn symlink(self: &Arc<Self>, name: &str, target: &str) -> Result<Arc<Self>> {
+        check_name(name)?;
+        let inode = self.inode.symlink(name, target)?;
+
+        Ok(self.insert_child(name, inode))
+    }
+}
+
+impl fmt::Debug for Dentry {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter
+            .debug_struct("Dentry")
+            .field("Name", &self.name)
+            .field("Mounted", &self.mounted.read().is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.len() > MAX_NAME_LEN {
+        Err(Error::InvalidPath)
+    } else {
+        Ok(())
+    }
+}
+
+fn check_absolute(path: &str) -> Result<()> {
+    if path.starts_with('/') {
+        Ok(())
+    } else {
+        Err(Error::InvalidPath)
+    }
+}
+
+/// Splits the absolute path `path` into its components, skipping empty and `.` components.
+pub(super) fn components(path: &str) -> Result<impl Iterator<Item = &str>> {
+    check_absolute(path)?;
+
+    Ok(path.split('/').filter(|component| !component.is_empty() && *component != "."))
+}
+
+/// Walks `path` from `start` (or from the root, if it's absolute), following symbolic links in every component
+/// except the last, which is only followed if `follow_last` is set.
+fn walk(start: Arc<Dentry>, path: &str, follow_last: bool, depth: &mut usize) -> Result<Arc<Dentry>> {
+    let mut current = if path.starts_with('/') { super::root() } else { start };
+    let mut components = path.split('/').filter(|component| !component.is_empty() && *component != ".").peekable();
+
+    while let Some(component) = components.next() {
+        let next = if component == ".." {
+            if current.metadata()?.kind != FileKind::Directory {
+                return Err(Error::NotDirectory);
+            }
+
+            current.parent()
+        } else {
+            current.child(component)?
+        };
+
+        let follow = follow_last || components.peek().is_some();
+        current = resolve_from(&current, next, follow, depth)?;
+    }
+
+    Ok(current)
+}
+
+/// Resolves `dentry` (an entry of `parent`) to the target of the symbolic link it names, if `follow` is set.
+/// `depth` counts the links followed by the lookup so far.
+pub(super) fn resolve_from(
+    parent: &Arc<Dentry>,
+    dentry: Arc<Dentry>,
+    follow: bool,
+    depth: &mut usize,
+) -> Result<Arc<Dentry>> {
+    if !follow || dentry.metadata()?.kind != FileKind::Symlink {
+        return Ok(dentry);
+    }
+
+    *depth += 1;
+    if *depth > MAX_LINK_DEPTH {
+        return Err(Error::TooManyLinks);
+    }
+
+    let target = dentry.inode().read_link()?;
+    walk(parent.clone(), &target, true, depth)
+}
+
+/// Finds the dentry at the absolute path `path`, following symbolic links.
+pub fn lookup(path: &str) -> Result<Arc<Dentry>> {
+    check_absolute(path)?;
+
+    walk(super::root(), path, true, &mut 0)
+}
+
+/// Finds the directory containing the absolute path `path`, returning it along with the path's final component.
+pub fn lookup_parent(path: &str) -> Result<(Arc<Dentry>, &str)> {
+    let path = path.trim_end_matches('/');
+    let (parent_path, name) = path.rsplit_once('/').ok_or(Error::InvalidPath)?;
+    check_name(name)?;
+
+    let parent = lookup(if parent_path.is_empty() { "/" } else { parent_path })?;
+    if parent.metadata()?.kind == FileKind::Directory {
+        Ok((parent, name))
+    } else {
+        Err(Error::NotDirectory)
+    }
+}
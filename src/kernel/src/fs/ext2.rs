@@ -0,0 +1,223 @@
+use super::{Device, DirEntry, Error, Filesystem, Inode, Metadata, Result};
+use crate::block;
+use alloc::{string::String, sync::Arc, vec::Vec};
+use libsys::syscall::fs::FileKind;
+use spin::Mutex;
+
+impl From<::ext2::Error> for Error {
+    fn from(err: ::ext2::Error) -> Self {
+        use ::ext2::Error as Ext2Error;
+
+        match err {
+            Ext2Error::Io => Self::Io,
+            Ext2Error::InvalidSuperblock { reason } | Ext2Error::Corrupt { reason } => Self::Corrupt { reason },
+            Ext2Error::Unsupported { .. } => Self::Unsupported,
+            Ext2Error::ReadOnly => Self::ReadOnly,
+            Ext2Error::NotFound => Self::NotFound,
+            Ext2Error::AlreadyExists => Self::AlreadyExists,
+            Ext2Error::NotDirectory => Self::NotDirectory,
+            Ext2Error::IsDirectory => Self::IsDirectory,
+            Ext2Error::NotSymlink | Ext2Error::InvalidName => Self::InvalidPath,
+            Ext2Error::DirectoryNotEmpty => Self::NotEmpty,
+            Ext2Error::TooManyLinks => Self::TooManyLinks,
+            Ext2Error::NoSpace | Ext2Error::FileTooLarge => Self::NoSpace,
+        }
+    }
+}
+
+/// State shared by every inode of a mounted filesystem.
+struct Volume {
+    filesystem: Mutex<::ext2::FileSystem<Device>>,
+    /// Whether the device is read-only, or the filesystem uses features which can't be written (such as extents).
+    read_only: bool,
+}
+
+impl Volume {
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            Err(Error::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn sync(&self) -> Result<()> {
+        if self.read_only {
+            Ok(())
+        } else {
+            Ok(self.filesystem.lock().sync()?)
+        }
+    }
+}
+
+fn file_kind(file_type: ::ext2::FileType) -> FileKind {
+    use ::ext2::FileType;
+
+    match file_type {
+        FileType::Directory => FileKind::Directory,
+        FileType::Symlink => FileKind::Symlink,
+        FileType::CharDevice => FileKind::CharDevice,
+        FileType::BlockDevice => FileKind::BlockDevice,
+        // The VFS has no kinds for FIFOs and sockets, which read as empty files.
+        FileType::Regular | FileType::Fifo | FileType::Socket => FileKind::Regular,
+    }
+}
+
+/// A file, directory, or symbolic link of an ext2 filesystem.
+pub struct Ext2Inode {
+    volume: Arc<Volume>,
+    inode: u32,
+}
+
+impl Ext2Inode {
+    fn new_inode(&self, inode: u32) -> Arc<dyn Inode> {
+        Arc::new(Self { volume: self.volume.clone(), inode })
+    }
+}
+
+impl Inode for Ext2Inode {
+    fn metadata(&self) -> Result<Metadata> {
+        let stat = self.volume.filesystem.lock().stat(self.inode)?;
+
+        Ok(Metadata {
+            kind: file_kind(stat.file_type),
+            size: stat.size,
+            inode: u64::from(stat.inode),
+            permissions: stat.permissions,
+            uid: stat.uid,
+            gid: stat.gid,
+            link_count: u32::from(stat.link_count),
+        })
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        let filesystem = self.volume.filesystem.lock();
+        if filesystem.stat(self.inode)?.file_type == ::ext2::FileType::Directory {
+            return Err(Error::IsDirectory);
+        }
+
+        Ok(filesystem.read(self.inode, offset, buffer)?)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize> {
+        self.volume.check_writable()?;
+
+        Ok(self.volume.filesystem.lock().write(self.inode, offset, buffer)?)
+    }
+
+    fn truncate(&self, len: u64) -> Result<()> {
+        self.volume.check_writable()?;
+
+        Ok(self.volume.filesystem.lock().truncate(self.inode, len)?)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let entry = self.volume.filesystem.lock().lookup(self.inode, name)?;
+
+        Ok(self.new_inode(entry.inode()))
+    }
+
+    fn create(&self, name: &str, kind: FileKind) -> Result<Arc<dyn Inode>> {
+        self.volume.check_writable()?;
+
+        let inode = {
+            let mut filesystem = self.volume.filesystem.lock();
+
+            match kind {
+                FileKind::Regular => filesystem.create_file(self.inode, name, 0o644)?,
+                FileKind::Directory => filesystem.create_dir(self.inode, name, 0o755)?,
+                _ => return Err(Error::Unsupported),
+            }
+        };
+
+        Ok(self.new_inode(inode))
+    }
+
+    fn read_dir(&self, index: usize, count: usize) -> Result<Vec<DirEntry>> {
+        let filesystem = self.volume.filesystem.lock();
+
+        Ok(filesystem
+            .read_dir_range(self.inode, index, count)?
+            .into_iter()
+            .map(|entry| DirEntry {
+                name: String::from_utf8_lossy(entry.name()).into_owned(),
+                inode: u64::from(entry.inode()),
+                kind: file_kind(entry.file_type()),
+            })
+            .collect())
+    }
+
+    fn read_link(&self) -> Result<String> {
+        let target = self.volume.filesystem.lock().read_link(self.inode)?;
+
+        String::from_utf8(target).map_err(|_| Error::InvalidPath)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>> {
+        self.volume.check_writable()?;
+
+        let inode = self.volume.filesystem.lock().symlink(self.inode, name, target.as_bytes())?;
+
+        Ok(self.new_inode(inode))
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.volume.sync()
+    }
+}
+
+/// An ext2 filesystem (or ext4 filesystem, read-only) on a block device.
+pub struct Ext2Fs {
+    volume: Arc<Volume>,
+}
+
+impl Ext2Fs {
+    pub fn mount(device: Arc<dyn block::BlockDevice>) -> Result<Self> {
+        let device_read_only = device.is_read_only();
+        let filesystem = ::ext2::FileSystem::mount(Device(device))?;
+        let read_only = device_read_only || !filesystem.is_writable();
+
+        Ok(Self { volume: Arc::new(Volume { filesystem: Mutex::new(filesystem), read_only }) })
+    }
+}
+
+impl Filesystem for Ext2Fs {
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        Arc::new(Ext2Inode { volume: self.volume.clone(), inode: ::ext2::FileSystem::<Device>::ROOT })
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.volume.sync()
+    }
+}
+
+/// Mounts the first registered block device which holds an ext2 filesystem as the root of the VFS tree.
+///
+/// This must happen before anything else is mounted, as the root mount hides every existing entry of `/`. Later
+/// mountpoints are created in memory (see [`super::mount_boot_dirs`]), so the root filesystem needn't have them.
+pub fn mount_root() {
+    for (name, device) in block::devices() {
+        let filesystem = match Ext2Fs::mount(device) {
+            Ok(filesystem) => filesystem,
+            Err(err) => {
+                trace!("Block device {} doesn't hold an ext2 filesystem: {:?}", name, err);
+                continue;
+            }
+        };
+
+        if filesystem.volume.read_only {
+            info!("Root filesystem of {} is read-only.", name);
+        }
+
+        match super::mount("/", Arc::new(filesystem)) {
+            Ok(()) => return,
+            Err(err) => warn!("Failed to mount ext2 filesystem of {} as root: {:?}", name, err),
+        }
+    }
+
+    warn!("No ext2 root filesystem was found, so the root remains in memory.");
+}
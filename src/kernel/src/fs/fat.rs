@@ -0,0 +1,238 @@
+use super::{Device, DirEntry, Error, Filesystem, Inode, Metadata, Result};
+use crate::block;
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::ToString,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use libsys::syscall::fs::FileKind;
+use spin::Mutex;
+
+impl From<::fat::Error> for Error {
+    fn from(err: ::fat::Error) -> Self {
+        use ::fat::Error as FatError;
+
+        match err {
+            FatError::Io => Self::Io,
+            FatError::InvalidBootSector { reason } | FatError::Corrupt { reason } => Self::Corrupt { reason },
+            FatError::Unsupported { .. } => Self::Unsupported,
+            FatError::NotFound => Self::NotFound,
+            FatError::AlreadyExists => Self::AlreadyExists,
+            FatError::NotDirectory => Self::NotDirectory,
+            FatError::IsDirectory => Self::IsDirectory,
+            FatError::DirectoryNotEmpty => Self::NotEmpty,
+            FatError::InvalidName => Self::InvalidPath,
+            FatError::DirectoryFull | FatError::NoSpace | FatError::FileTooLarge => Self::NoSpace,
+        }
+    }
+}
+
+/// State shared by every inode of a mounted volume.
+struct Volume {
+    filesystem: Mutex<::fat::FileSystem<Device>>,
+    read_only: bool,
+    /// Inodes which are in use, so that each node of the volume is only ever represented by one inode (FAT names are
+    /// case-insensitive, so several dentries may name the same node).
+    inodes: Mutex<BTreeMap<u64, Weak<FatInode>>>,
+}
+
+impl Volume {
+    fn inode(self: &Arc<Self>, node: ::fat::Node) -> Arc<FatInode> {
+        let mut inodes = self.inodes.lock();
+        if let Some(inode) = inodes.get(&node.id()).and_then(Weak::upgrade) {
+            return inode;
+        }
+
+        inodes.retain(|_, inode| inode.strong_count() > 0);
+
+        let inode = Arc::new(FatInode { volume: self.clone(), node: Mutex::new(node) });
+        inodes.insert(node.id(), Arc::downgrade(&inode));
+
+        inode
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            Err(Error::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A file or directory of a FAT volume.
+///
+/// The volume is always locked before the node, as writes update both.
+pub struct FatInode {
+    volume: Arc<Volume>,
+    node: Mutex<::fat::Node>,
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> Result<Metadata> {
+        let node = *self.node.lock();
+        // FAT has no permissions, so every file is owned by root and writable unless the device isn't.
+        let (kind, permissions) =
+            if node.is_directory() { (FileKind::Directory, 0o755) } else { (FileKind::Regular, 0o644) };
+
+        Ok(Metadata {
+            kind,
+            size: u64::from(node.size()),
+            inode: node.id(),
+            permissions: if self.volume.read_only { permissions & 0o555 } else { permissions },
+            uid: 0,
+            gid: 0,
+            link_count: 1,
+        })
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        let filesystem = self.volume.filesystem.lock();
+        let node = *self.node.lock();
+
+        Ok(filesystem.read(&node, offset, buffer)?)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize> {
+        self.volume.check_writable()?;
+
+        let mut filesystem = self.volume.filesystem.lock();
+        let mut node = self.node.lock();
+
+        Ok(filesystem.write(&mut node, offset, buffer)?)
+    }
+
+    fn truncate(&self, len: u64) -> Result<()> {
+        self.volume.check_writable()?;
+
+        let mut filesystem = self.volume.filesystem.lock();
+        let mut node = self.node.lock();
+
+        Ok(filesystem.truncate(&mut node, len)?)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let entry = {
+            let filesystem = self.volume.filesystem.lock();
+            let node = *self.node.lock();
+
+            filesystem.lookup(&node, name)?
+        };
+
+        Ok(self.volume.inode(entry.node()))
+    }
+
+    fn create(&self, name: &str, kind: FileKind) -> Result<Arc<dyn Inode>> {
+        self.volume.check_writable()?;
+
+        let created = {
+            let mut filesystem = self.volume.filesystem.lock();
+            let node = *self.node.lock();
+
+            match kind {
+                FileKind::Regular => filesystem.create_file(&node, name)?,
+                FileKind::Directory => filesystem.create_dir(&node, name)?,
+                _ => return Err(Error::Unsupported),
+            }
+        };
+
+        Ok(self.volume.inode(created))
+    }
+
+    fn read_dir(&self, index: usize, count: usize) -> Result<Vec<DirEntry>> {
+        let filesystem = self.volume.filesystem.lock();
+        let node = *self.node.lock();
+
+        Ok(filesystem
+            .read_dir(&node)?
+            .into_iter()
+            .skip(index)
+            .take(count)
+            .map(|entry| DirEntry {
+                name: entry.name().to_string(),
+                inode: entry.node().id(),
+                kind: if entry.is_directory() { FileKind::Directory } else { FileKind::Regular },
+            })
+            .collect())
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(self.volume.filesystem.lock().sync()?)
+    }
+}
+
+/// A FAT12, FAT16, or FAT32 volume on a block device.
+pub struct FatFs {
+    volume: Arc<Volume>,
+    root: Arc<FatInode>,
+}
+
+impl FatFs {
+    pub fn mount(device: Arc<dyn block::BlockDevice>) -> Result<Self> {
+        let read_only = device.is_read_only();
+        let filesystem = ::fat::FileSystem::mount(Device(device))?;
+        let root = filesystem.root();
+
+        let volume =
+            Arc::new(Volume { filesystem: Mutex::new(filesystem), read_only, inodes: Mutex::new(BTreeMap::new()) });
+        let root = volume.inode(root);
+
+        Ok(Self { volume, root })
+    }
+}
+
+impl Filesystem for FatFs {
+    fn name(&self) -> &'static str {
+        match self.volume.filesystem.lock().fat_type() {
+            ::fat::FatType::Fat12 => "fat12",
+            ::fat::FatType::Fat16 => "fat16",
+            ::fat::FatType::Fat32 => "fat32",
+        }
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.root.sync()
+    }
+}
+
+/// Mounts every registered block device which holds a FAT volume. The EFI system partition (the volume with an `EFI`
+/// directory in its root) is mounted at `/boot/efi`, and any other volume at `/mnt/<device name>`.
+///
+/// Disks with a partition table are skipped, so that only their partitions are probed.
+pub fn mount_volumes() {
+    let devices = block::devices();
+
+    for (name, device) in &devices {
+        let partition_prefix = format!("{name}p");
+        if devices.iter().any(|(other, _)| other.starts_with(&partition_prefix)) {
+            continue;
+        }
+
+        let filesystem = match FatFs::mount(device.clone()) {
+            Ok(filesystem) => filesystem,
+            Err(err) => {
+                trace!("Block device {} doesn't hold a FAT volume: {:?}", name, err);
+                continue;
+            }
+        };
+
+        let is_esp = filesystem
+            .root
+            .lookup("EFI")
+            .and_then(|efi| efi.metadata())
+            .is_ok_and(|metadata| metadata.kind == FileKind::Directory);
+        let mount_path = if is_esp { "/boot/efi".to_string() } else { format!("/mnt/{name}") };
+
+        if let Err(err) =
+            super::create_dir_all(&mount_path).and_then(|_| super::mount(&mount_path, Arc::new(filesystem)))
+        {
+            warn!("Failed to mount FAT volume of {} at {}: {:?}", name, mount_path, err);
+        }
+    }
+}
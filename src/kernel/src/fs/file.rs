@@ -0,0 +1,163 @@
+use super::{Dentry, DirEntry, Error, Metadata, Result};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use libsys::syscall::fs::{OpenFlags, Whence};
+use spin::Mutex;
+
+/// An open file, which tracks the offset that reads and writes continue from.
+#[derive(Debug)]
+pub struct File {
+    dentry: Arc<Dentry>,
+    flags: OpenFlags,
+    offset: Mutex<u64>,
+}
+
+impl File {
+    pub(super) const fn new(dentry: Arc<Dentry>, flags: OpenFlags) -> Self {
+        Self { dentry, flags, offset: Mutex::new(0) }
+    }
+
+    #[inline]
+    pub const fn dentry(&self) -> &Arc<Dentry> {
+        &self.dentry
+    }
+
+    #[inline]
+    pub const fn flags(&self) -> OpenFlags {
+        self.flags
+    }
+
+    #[inline]
+    pub fn metadata(&self) -> Result<Metadata> {
+        self.dentry.metadata()
+    }
+
+    /// Reads from the file at `offset`, without moving the file offset.
+    pub fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        if !self.flags.contains(OpenFlags::READ) {
+            return Err(Error::AccessDenied);
+        }
+
+        self.dentry.inode().read_at(offset, buffer)
+    }
+
+    /// Reads from the file at `offset` until `buffer` is filled, without moving the file offset.
+    pub fn read_exact_at(&self, offset: u64, buffer: &mut [u8]) -> Result<()> {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match self.read_at(offset + u64::try_from(filled).unwrap(), &mut buffer[filled..])? {
+                0 => return Err(Error::UnexpectedEof),
+                len => filled += len,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the entire contents of the file, without moving the file offset.
+    pub fn read_all(&self) -> Result<Vec<u8>> {
+        let mut data = alloc::vec![0u8; usize::try_from(self.metadata()?.size).unwrap()];
+        self.read_exact_at(0, &mut data)?;
+
+        Ok(data)
+    }
+
+    /// Reads from the file offset, then passes the bytes read to `consume`. The offset is only advanced past them if
+    /// `consume` succeeds, so bytes that couldn't be consumed are read again next time.
+    pub fn read<T, E: From<Error>>(
+        &self,
+        buffer: &mut [u8],
+        consume: impl FnOnce(&[u8]) -> core::result::Result<T, E>,
+    ) -> core::result::Result<T, E> {
+        let mut offset = self.offset.lock();
+        let len = self.read_at(*offset, buffer)?;
+        let output = consume(&buffer[..len])?;
+        *offset += u64::try_from(len).unwrap();
+
+        Ok(output)
+    }
+
+    /// Writes to the file offset (or the end of the file, if opened to append), advancing it by the number of bytes
+    /// written.
+    pub fn write(&self, buffer: &[u8]) -> Result<usize> {
+        if !self.flags.contains(OpenFlags::WRITE) {
+            return Err(Error::AccessDenied);
+        }
+
+        let mut offset = self.offset.lock();
+        if self.flags.contains(OpenFlags::APPEND) {
+            *offset = self.metadata()?.size;
+        }
+
+        let len = self.dentry.inode().write_at(*offset, buffer)?;
+        *offset += u64::try_from(len).unwrap();
+
+        Ok(len)
+    }
+
+    /// Moves the file offset to `offset` bytes from `whence`, returning the new offset.
+    pub fn seek(&self, offset: i64, whence: Whence) -> Result<u64> {
+        let mut current = self.offset.lock();
+        let base = match whence {
+            Whence::Start => 0,
+            Whence::Current => *current,
+            Whence::End => self.metadata()?.size,
+        };
+
+        *current = base.checked_add_signed(offset).ok_or(Error::InvalidOffset)?;
+
+        Ok(*current)
+    }
+
+    /// Commits the file's cached writes (and those of the rest of its filesystem) to the underlying device.
+    pub fn sync(&self) -> Result<()> {
+        self.dentry.inode().sync()
+    }
+
+    /// Reads every remaining entry of the directory, advancing the file offset past them.
+    pub fn read_dir_all(&self) -> Result<Vec<DirEntry>> {
+        self.read_dir_many(usize::MAX, |entries| Ok(entries.to_vec()))
+    }
+
+    /// Reads up to `count` of the directory's next entries, then passes them to `consume`. The file offset is only
+    /// advanced past them if `consume` succeeds, so entries that couldn't be consumed are read again next time.
+    pub fn read_dir_many<T, E: From<Error>>(
+        &self,
+        count: usize,
+        consume: impl FnOnce(&[DirEntry]) -> core::result::Result<T, E>,
+    ) -> core::result::Result<T, E> {
+        let mut offset = self.offset.lock();
+        let entries = self.dentry.inode().read_dir(usize::try_from(*offset).unwrap(), count)?;
+        let output = consume(&entries)?;
+        *offset += u64::try_from(entries.len()).unwrap();
+
+        Ok(output)
+    }
+}
+
+/// Maps the file descriptors of a process to the files they name.
+pub struct FileTable {
+    files: BTreeMap<usize, Arc<File>>,
+}
+
+impl FileTable {
+    pub const fn new() -> Self {
+        Self { files: BTreeMap::new() }
+    }
+
+    /// Inserts `file` at the lowest free file descriptor, which is returned.
+    pub fn insert(&mut self, file: Arc<File>) -> usize {
+        let fd =
+            self.files.keys().zip(0..).find(|(fd, expected)| **fd != *expected).map_or(self.files.len(), |(_, fd)| fd);
+        self.files.insert(fd, file);
+
+        fd
+    }
+
+    pub fn get(&self, fd: usize) -> Option<Arc<File>> {
+        self.files.get(&fd).cloned()
+    }
+
+    pub fn remove(&mut self, fd: usize) -> Option<Arc<File>> {
+        self.files.remove(&fd)
+    }
+}
@@ -0,0 +1,332 @@
+mod dentry;
+pub use dentry::*;
+
+mod file;
+pub use file::*;
+
+pub mod ext2;
+pub mod fat;
+pub mod ramfs;
+pub mod tarfs;
+
+use crate::block;
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use libsys::syscall::fs::{FileKind, OpenFlags};
+use spin::RwLock;
+
+crate::error_impl! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Error {
+        /// Indicates no file exists at a path.
+        NotFound => None,
+
+        /// Indicates a file already exists where one was to be created.
+        AlreadyExists => None,
+
+        /// Indicates a path component which must be a directory is not one.
+        NotDirectory => None,
+
+        /// Indicates a file operation was attempted on a directory.
+        IsDirectory => None,
+
+        /// Indicates a directory can't be removed, as it still has entries.
+        NotEmpty => None,
+
+        /// Indicates the filesystem has no space left for a write.
+        NoSpace => None,
+
+        /// Indicates a write was attempted to a read-only filesystem.
+        ReadOnly => None,
+
+        /// Indicates a file wasn't opened with the access an operation requires.
+        AccessDenied => None,
+
+        /// Indicates a seek would move the file offset before the start of the file.
+        InvalidOffset => None,
+
+        /// Indicates the file ended before a read could be completed.
+        UnexpectedEof => None,
+
+        /// Indicates a path isn't absolute, or a name contains a separator.
+        InvalidPath => None,
+
+        /// Indicates a path resolves through too many symbolic links (likely a loop).
+        TooManyLinks => None,
+
+        /// Indicates the filesystem doesn't support an operation.
+        Unsupported => None,
+
+        /// Indicates the filesystem's on-disk structures are malformed.
+        Corrupt { reason: &'static str } => None,
+
+        /// Indicates the underlying block device failed a transfer.
+        Io => None
+    }
+}
+
+impl From<block::Error> for Error {
+    fn from(err: block::Error) -> Self {
+        // Block errors carry driver state which can't be copied, so they're reported here instead.
+        warn!("Filesystem block transfer failed: {:?}", err);
+
+        Self::Io
+    }
+}
+
+/// Adapts a registered block device to the filesystem crates, completing each request before returning.
+struct Device(Arc<dyn block::BlockDevice>);
+
+impl ::block_device::BlockDevice for Device {
+    fn sector_size(&self) -> usize {
+        self.0.sector_size()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.0.sector_count()
+    }
+
+    fn read(&self, lba: u64, buffer: &mut [u8]) -> ::block_device::Result<()> {
+        block::block_on(self.0.read(lba, buffer)).map_err(|err| io_error(&err))
+    }
+
+    fn write(&self, lba: u64, buffer: &[u8]) -> ::block_device::Result<()> {
+        block::block_on(self.0.write(lba, buffer)).map_err(|err| io_error(&err))
+    }
+
+    fn flush(&self) -> ::block_device::Result<()> {
+        block::block_on(self.0.flush()).map_err(|err| io_error(&err))
+    }
+}
+
+fn io_error(err: &block::Error) -> ::block_device::Error {
+    warn!("Filesystem block transfer failed: {:?}", err);
+
+    ::block_device::Error
+}
+
+/// Describes a file, as recorded by its filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub kind: FileKind,
+    pub size: u64,
+    /// Number of the file's inode, which is unique within its filesystem.
+    pub inode: u64,
+    /// Unix permission bits of the file (e.g. `0o755`).
+    pub permissions: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub link_count: u32,
+}
+
+/// An entry of a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub inode: u64,
+    pub kind: FileKind,
+}
+
+/// A file within a filesystem, which is either a regular file, a directory, or a symbolic link.
+///
+/// Operations which don't apply to the inode's kind fail by default.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> Result<Metadata>;
+
+    /// Reads from the file at `offset` into `buffer`, returning the number of bytes read. Reads past the end of the
+    /// file return zero.
+    fn read_at(&self, _offset: u64, _buffer: &mut [u8]) -> Result<usize> {
+        Err(Error::IsDirectory)
+    }
+
+    /// Writes `buffer` to the file at `offset`, extending it if necessary. Returns the number of bytes written.
+    fn write_at(&self, _offset: u64, _buffer: &[u8]) -> Result<usize> {
+        Err(Error::IsDirectory)
+    }
+
+    /// Sets the length of the file, zero-filling it if it grows.
+    fn truncate(&self, _len: u64) -> Result<()> {
+        Err(Error::IsDirectory)
+    }
+
+    /// Finds the entry `name` of this directory.
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn Inode>> {
+        Err(Error::NotDirectory)
+    }
+
+    /// Creates the entry `name` of this directory, as an empty file of `kind`.
+    fn create(&self, _name: &str, _kind: FileKind) -> Result<Arc<dyn Inode>> {
+        Err(Error::NotDirectory)
+    }
+
+    /// Gets up to `count` entries of this directory, starting from the entry at `index`. Fewer are returned once there
+    /// are no further entries.
+    ///
+    /// Entries are read in batches, as filesystems may have to read through the directory to find the `index`th.
+    fn read_dir(&self, _index: usize, _count: usize) -> Result<Vec<DirEntry>> {
+        Err(Error::NotDirectory)
+    }
+
+    /// Gets the path this symbolic link points to.
+    fn read_link(&self) -> Result<String> {
+        Err(Error::InvalidPath)
+    }
+
+    /// Creates the entry `name` of this directory, as a symbolic link to `target`.
+    fn symlink(&self, _name: &str, _target: &str) -> Result<Arc<dyn Inode>> {
+        Err(Error::Unsupported)
+    }
+
+    /// Commits the cached writes of the file (and the rest of its filesystem) to the underlying device.
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A mountable tree of inodes.
+pub trait Filesystem: Send + Sync {
+    /// Short name of the filesystem's format (e.g. `ramfs`).
+    fn name(&self) -> &'static str;
+
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Commits any cached writes to the underlying device.
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A filesystem mounted into the VFS tree.
+#[derive(Clone)]
+pub struct Mount {
+    path: String,
+    filesystem: Arc<dyn Filesystem>,
+}
+
+impl Mount {
+    #[inline]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    #[inline]
+    pub fn filesystem(&self) -> &Arc<dyn Filesystem> {
+        &self.filesystem
+    }
+}
+
+static ROOT: spin::Once<Arc<Dentry>> = spin::Once::new();
+static MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+
+/// Creates the root of the VFS tree, as an empty in-memory filesystem which other filesystems are mounted onto.
+pub fn init() {
+    ROOT.call_once(|| {
+        let filesystem: Arc<dyn Filesystem> = Arc::new(ramfs::RamFs::new());
+        MOUNTS.write().push(Mount { path: "/".to_string(), filesystem: filesystem.clone() });
+
+        Dentry::new_root(filesystem.root())
+    });
+}
+
+/// The root directory of the VFS tree.
+pub fn root() -> Arc<Dentry> {
+    ROOT.get().expect("VFS has not been initialized").clone().follow_mounts()
+}
+
+/// Mounts `filesystem` onto the directory at `path`, hiding the directory's existing entries.
+pub fn mount(path: &str, filesystem: Arc<dyn Filesystem>) -> Result<()> {
+    let mountpoint = lookup(path)?;
+    if mountpoint.metadata()?.kind != FileKind::Directory {
+        return Err(Error::NotDirectory);
+    }
+
+    let path = mountpoint.path();
+    mountpoint.mount(filesystem.root());
+    info!("Mounted {} filesystem at {}", filesystem.name(), path);
+    MOUNTS.write().push(Mount { path, filesystem });
+
+    Ok(())
+}
+
+/// Directories of the root which hold the mountpoints created during boot (for modules, and FAT volumes).
+const BOOT_MOUNT_DIRS: [&str; 2] = ["boot", "mnt"];
+
+/// Attaches an in-memory filesystem at each directory of the root which holds boot-time mountpoints, hiding any
+/// directory the root filesystem has there. The mountpoints are then created in memory, so booting never writes to
+/// the root's device (which may be read-only).
+pub fn mount_boot_dirs() {
+    let root = root();
+
+    for name in BOOT_MOUNT_DIRS {
+        let filesystem: Arc<dyn Filesystem> = Arc::new(ramfs::RamFs::new());
+        let path = root.attach(name, filesystem.root()).path();
+
+        info!("Mounted {} filesystem at {}", filesystem.name(), path);
+        MOUNTS.write().push(Mount { path, filesystem });
+    }
+}
+
+/// Every mounted filesystem, in the order they were mounted.
+pub fn mounts() -> Vec<Mount> {
+    MOUNTS.read().clone()
+}
+
+/// Commits the cached writes of every mounted filesystem.
+pub fn sync() -> Result<()> {
+    mounts().iter().try_for_each(|mount| mount.filesystem().sync())
+}
+
+/// Creates every missing directory along the absolute path `path`.
+pub fn create_dir_all(path: &str) -> Result<Arc<Dentry>> {
+    let mut current = root();
+
+    for component in components(path)? {
+        current = match current.child(component) {
+            Ok(child) => child,
+            Err(Error::NotFound) => current.create(component, FileKind::Directory)?,
+            Err(err) => return Err(err),
+        };
+    }
+
+    Ok(current)
+}
+
+/// Opens the file at the absolute path `path`.
+pub fn open(path: &str, flags: OpenFlags) -> Result<File> {
+    let dentry = if flags.contains(OpenFlags::CREATE) {
+        let (parent, name) = lookup_parent(path)?;
+
+        match parent.child(name) {
+            Ok(child) => resolve_from(&parent, child, true, &mut 0)?,
+            Err(Error::NotFound) => parent.create(name, FileKind::Regular)?,
+            Err(err) => return Err(err),
+        }
+    } else {
+        lookup(path)?
+    };
+
+    let kind = dentry.metadata()?.kind;
+    if flags.contains(OpenFlags::DIRECTORY) && kind != FileKind::Directory {
+        return Err(Error::NotDirectory);
+    }
+
+    if flags.contains(OpenFlags::WRITE) {
+        if kind == FileKind::Directory {
+            return Err(Error::IsDirectory);
+        }
+
+        if flags.contains(OpenFlags::TRUNCATE) {
+            dentry.inode().truncate(0)?;
+        }
+    }
+
+    Ok(File::new(dentry, flags))
+}
+
+/// Reads the entire contents of the file at the absolute path `path`.
+pub fn read(path: &str) -> Result<Vec<u8>> {
+    open(path, OpenFlags::READ)?.read_all()
+}
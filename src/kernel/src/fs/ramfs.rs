@@ -0,0 +1,161 @@
+use super::{DirEntry, Error, Filesystem, Inode, Metadata, Result};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+use libsys::syscall::fs::FileKind;
+use spin::RwLock;
+
+enum Contents {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Arc<RamInode>>),
+    Symlink(String),
+}
+
+/// A file held entirely in memory.
+pub struct RamInode {
+    inode: u64,
+    contents: RwLock<Contents>,
+    /// Shared with every inode of the filesystem, to number newly created inodes.
+    next_inode: Arc<AtomicU64>,
+}
+
+impl RamInode {
+    fn new(next_inode: &Arc<AtomicU64>, contents: Contents) -> Arc<Self> {
+        Arc::new(Self {
+            inode: next_inode.fetch_add(1, Ordering::Relaxed),
+            contents: RwLock::new(contents),
+            next_inode: next_inode.clone(),
+        })
+    }
+
+    fn insert(&self, name: &str, contents: Contents) -> Result<Arc<dyn Inode>> {
+        let mut self_contents = self.contents.write();
+        let Contents::Directory(entries) = &mut *self_contents else { return Err(Error::NotDirectory) };
+        if entries.contains_key(name) {
+            return Err(Error::AlreadyExists);
+        }
+
+        let inode = Self::new(&self.next_inode, contents);
+        entries.insert(name.to_string(), inode.clone());
+
+        Ok(inode)
+    }
+}
+
+impl Inode for RamInode {
+    fn metadata(&self) -> Result<Metadata> {
+        let (kind, size, permissions) = match &*self.contents.read() {
+            Contents::File(data) => (FileKind::Regular, data.len(), 0o644),
+            Contents::Directory(entries) => (FileKind::Directory, entries.len(), 0o755),
+            Contents::Symlink(target) => (FileKind::Symlink, target.len(), 0o777),
+        };
+
+        Ok(Metadata {
+            kind,
+            size: u64::try_from(size).unwrap(),
+            inode: self.inode,
+            permissions,
+            uid: 0,
+            gid: 0,
+            link_count: 1,
+        })
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        let contents = self.contents.read();
+        let Contents::File(data) = &*contents else { return Err(Error::IsDirectory) };
+        let Some(remaining) = usize::try_from(offset).ok().and_then(|offset| data.get(offset..)) else { return Ok(0) };
+
+        let len = buffer.len().min(remaining.len());
+        buffer[..len].copy_from_slice(&remaining[..len]);
+
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: u64, buffer: &[u8]) -> Result<usize> {
+        let mut contents = self.contents.write();
+        let Contents::File(data) = &mut *contents else { return Err(Error::IsDirectory) };
+        let offset = usize::try_from(offset).map_err(|_| Error::InvalidOffset)?;
+
+        let end = offset + buffer.len();
+        if end > data.len() {
+            data.resize(end, 0);
+        }
+        data[offset..end].copy_from_slice(buffer);
+
+        Ok(buffer.len())
+    }
+
+    fn truncate(&self, len: u64) -> Result<()> {
+        let mut contents = self.contents.write();
+        let Contents::File(data) = &mut *contents else { return Err(Error::IsDirectory) };
+        data.resize(usize::try_from(len).map_err(|_| Error::InvalidOffset)?, 0);
+
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let contents = self.contents.read();
+        let Contents::Directory(entries) = &*contents else { return Err(Error::NotDirectory) };
+
+        entries.get(name).map(|inode| -> Arc<dyn Inode> { inode.clone() }).ok_or(Error::NotFound)
+    }
+
+    fn create(&self, name: &str, kind: FileKind) -> Result<Arc<dyn Inode>> {
+        match kind {
+            FileKind::Regular => self.insert(name, Contents::File(Vec::new())),
+            FileKind::Directory => self.insert(name, Contents::Directory(BTreeMap::new())),
+            _ => Err(Error::Unsupported),
+        }
+    }
+
+    fn read_dir(&self, index: usize, count: usize) -> Result<Vec<DirEntry>> {
+        let contents = self.contents.read();
+        let Contents::Directory(entries) = &*contents else { return Err(Error::NotDirectory) };
+
+        entries
+            .iter()
+            .skip(index)
+            .take(count)
+            .map(|(name, inode)| Ok(DirEntry { name: name.clone(), inode: inode.inode, kind: inode.metadata()?.kind }))
+            .collect()
+    }
+
+    fn read_link(&self) -> Result<String> {
+        match &*self.contents.read() {
+            Contents::Symlink(target) => Ok(target.clone()),
+            _ => Err(Error::InvalidPath),
+        }
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn Inode>> {
+        self.insert(name, Contents::Symlink(target.to_string()))
+    }
+}
+
+/// A filesystem held entirely in memory, whose contents are lost on reboot.
+pub struct RamFs {
+    root: Arc<RamInode>,
+}
+
+impl RamFs {
+    pub fn new() -> Self {
+        let next_inode = Arc::new(AtomicU64::new(1));
+
+        Self { root: RamInode::new(&next_inode, Contents::Directory(BTreeMap::new())) }
+    }
+}
+
+impl Filesystem for RamFs {
+    fn name(&self) -> &'static str {
+        "ramfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
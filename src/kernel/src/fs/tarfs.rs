@@ -0,0 +1,160 @@
+use super::{DirEntry, Error, Filesystem, Inode, Metadata, Result};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use libsys::syscall::fs::FileKind;
+
+/// Offset of the magic within a tar header, which both ustar and GNU archives begin with.
+const MAGIC_OFFSET: usize = 257;
+const MAGIC: &[u8; 5] = b"ustar";
+
+/// Checks whether `data` begins with a tar header.
+pub fn is_archive(data: &[u8]) -> bool {
+    data.get(MAGIC_OFFSET..(MAGIC_OFFSET + MAGIC.len())) == Some(MAGIC)
+}
+
+/// An archive entry, before it's numbered as an inode.
+enum Node {
+    File(&'static [u8]),
+    Directory(BTreeMap<String, Node>),
+}
+
+enum Contents {
+    File(&'static [u8]),
+    Directory(BTreeMap<String, Arc<TarInode>>),
+}
+
+/// A file or directory within a tar archive.
+pub struct TarInode {
+    inode: u64,
+    contents: Contents,
+}
+
+impl TarInode {
+    fn from_node(node: Node, next_inode: &mut u64) -> Arc<Self> {
+        let inode = *next_inode;
+        *next_inode += 1;
+
+        let contents = match node {
+            Node::File(data) => Contents::File(data),
+            Node::Directory(entries) => Contents::Directory(
+                entries.into_iter().map(|(name, node)| (name, Self::from_node(node, next_inode))).collect(),
+            ),
+        };
+
+        Arc::new(Self { inode, contents })
+    }
+}
+
+impl Inode for TarInode {
+    fn metadata(&self) -> Result<Metadata> {
+        let (kind, size, permissions) = match &self.contents {
+            Contents::File(data) => (FileKind::Regular, data.len(), 0o444),
+            Contents::Directory(entries) => (FileKind::Directory, entries.len(), 0o555),
+        };
+
+        Ok(Metadata {
+            kind,
+            size: u64::try_from(size).unwrap(),
+            inode: self.inode,
+            permissions,
+            uid: 0,
+            gid: 0,
+            link_count: 1,
+        })
+    }
+
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        let Contents::File(data) = &self.contents else { return Err(Error::IsDirectory) };
+        let Some(remaining) = usize::try_from(offset).ok().and_then(|offset| data.get(offset..)) else { return Ok(0) };
+
+        let len = buffer.len().min(remaining.len());
+        buffer[..len].copy_from_slice(&remaining[..len]);
+
+        Ok(len)
+    }
+
+    fn write_at(&self, _: u64, _: &[u8]) -> Result<usize> {
+        Err(Error::ReadOnly)
+    }
+
+    fn truncate(&self, _: u64) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn Inode>> {
+        let Contents::Directory(entries) = &self.contents else { return Err(Error::NotDirectory) };
+
+        entries.get(name).map(|inode| -> Arc<dyn Inode> { inode.clone() }).ok_or(Error::NotFound)
+    }
+
+    fn create(&self, _: &str, _: FileKind) -> Result<Arc<dyn Inode>> {
+        Err(Error::ReadOnly)
+    }
+
+    fn read_dir(&self, index: usize, count: usize) -> Result<Vec<DirEntry>> {
+        let Contents::Directory(entries) = &self.contents else { return Err(Error::NotDirectory) };
+
+        entries
+            .iter()
+            .skip(index)
+            .take(count)
+            .map(|(name, inode)| Ok(DirEntry { name: name.clone(), inode: inode.inode, kind: inode.metadata()?.kind }))
+            .collect()
+    }
+
+    fn symlink(&self, _: &str, _: &str) -> Result<Arc<dyn Inode>> {
+        Err(Error::ReadOnly)
+    }
+}
+
+/// A read-only filesystem over the entries of a tar archive, whose file data is read in place.
+///
+/// Directories are created for every path leading to an entry, so archives needn't contain directory entries.
+pub struct TarFs {
+    root: Arc<TarInode>,
+}
+
+impl TarFs {
+    pub fn new(archive: &'static [u8]) -> Self {
+        let mut root = Node::Directory(BTreeMap::new());
+
+        for entry in tar_no_std::TarArchiveRef::new(archive).entries() {
+            let path = entry.filename().to_string();
+            let is_directory = path.ends_with('/');
+
+            let mut components =
+                path.split('/').filter(|component| !component.is_empty() && *component != ".").peekable();
+            let mut current = &mut root;
+            while let Some(component) = components.next() {
+                let Node::Directory(entries) = current else {
+                    warn!("Archive entry is within a file, so it will be skipped: {}", path);
+                    break;
+                };
+
+                let node = if is_directory || components.peek().is_some() {
+                    Node::Directory(BTreeMap::new())
+                } else {
+                    Node::File(entry.data())
+                };
+
+                current = entries.entry(component.to_string()).or_insert(node);
+            }
+        }
+
+        Self { root: TarInode::from_node(root, &mut 1) }
+    }
+}
+
+impl Filesystem for TarFs {
+    fn name(&self) -> &'static str {
+        "tarfs"
+    }
+
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
@@ -63,14 +63,25 @@ pub fn cpu_setup() {
     crate::arch::x86_64::structures::load_static_tables();
 
     // Setup system call interface.
-    // // Safety: Parameters are set according to the IA-32 SDM, and so should have no undetermined side-effects.
-    // unsafe {
-    //     // Configure system call environment registers.
-    //     msr::IA32_STAR::set_selectors(gdt::kernel_code_selector().0, gdt::kernel_data_selector().0);
-    //     msr::IA32_LSTAR::set_syscall(syscall::_syscall_entry);
-    //     // We don't want to keep any flags set within the syscall (especially the interrupt flag).
-    //     msr::IA32_FMASK::set_rflags_mask(RFlags::all().bits());
-    //     // Enable `syscall`/`sysret`.
-    //     msr::IA32_EFER::set_sce(true);
-    // }
+    if cpuid::EXT_FUNCTION_INFO.as_ref().map_or(false, cpuid::ExtendedProcessorFeatureIdentifiers::has_syscall_sysret) {
+        use crate::arch::x86_64::{
+            registers::RFlags,
+            structures::{gdt, idt},
+        };
+
+        // Safety: Parameters are set according to the IA-32 SDM, and so should have no undetermined side-effects.
+        unsafe {
+            // Configure system call environment registers.
+            msr::IA32_STAR::set_selectors(gdt::kernel_code_selector().0, gdt::kernel_data_selector().0);
+            msr::IA32_LSTAR::set_syscall(idt::syscall_entry);
+            // We don't want to keep any flags set within the syscall (especially the interrupt flag).
+            msr::IA32_FMASK::set_rflags_mask(u64::try_from(RFlags::all().bits()).unwrap());
+            // Enable `syscall`/`sysret`.
+            msr::IA32_EFER::set_sce(true);
+        }
+    } else {
+        libsys::do_once!({
+            warn!("PC does not support `syscall`/`sysret`; system calls will use the `int 0x80` interface only.");
+        });
+    }
 }
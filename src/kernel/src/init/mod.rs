@@ -6,6 +6,11 @@ pub use params::*;
 
 pub mod boot;
 
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+};
 use libsys::Address;
 
 crate::error_impl! {
@@ -41,10 +46,22 @@ pub unsafe extern "C" fn init() -> ! {
     crate::mem::alloc::pmm::init(boot::get_memory_map().unwrap()).unwrap();
     crate::panic::symbols::parse(kernel_file).unwrap();
     memory::setup(kernel_file).unwrap();
+    crate::drivers::graphics::console::init();
 
     crate::acpi::init_interface().unwrap();
+    crate::interrupts::irq::init();
 
     crate::mem::io::pci::init_devices().unwrap();
+    crate::drivers::nvme::init();
+    crate::drivers::ahci::init();
+    crate::drivers::virtio::block::init();
+    crate::drivers::ps2::init();
+
+    crate::fs::init();
+    crate::fs::ext2::mount_root();
+    crate::fs::mount_boot_dirs();
+    mount_modules();
+    crate::fs::fat::mount_volumes();
 
     load_drivers();
 
@@ -98,12 +115,44 @@ fn print_boot_info() {
     }
 }
 
+#[limine::limine_tag]
+static LIMINE_MODULES: limine::ModuleRequest = limine::ModuleRequest::new(crate::init::boot::LIMINE_REV);
+
+/// Path a module archive is mounted at. This is the module's command line (`MODULE_CMDLINE` in `limine.cfg`) if it
+/// has one, otherwise `/boot/<module name>`.
+fn module_mount_path(module: &limine::File) -> String {
+    match module.cmdline().trim() {
+        "" => format!("/boot/{}", module.path().rsplit('/').next().unwrap_or_default()),
+        mount_path => mount_path.to_string(),
+    }
+}
+
+/// Mounts every module which is a tar archive as a read-only filesystem.
+fn mount_modules() {
+    let Some(modules) = LIMINE_MODULES.get_response()
+    else {
+        return
+    };
+
+    for module in modules.modules() {
+        if !crate::fs::tarfs::is_archive(module.data()) {
+            trace!("Module isn't a tar archive, so it won't be mounted: {}", module.path());
+            continue;
+        }
+
+        let mount_path = module_mount_path(module);
+        let filesystem = Arc::new(crate::fs::tarfs::TarFs::new(module.data()));
+        if let Err(err) = crate::fs::create_dir_all(&mount_path).and_then(|_| crate::fs::mount(&mount_path, filesystem))
+        {
+            error!("Failed to mount module {} at {}: {:?}", module.path(), mount_path, err);
+        }
+    }
+}
+
 fn load_drivers() {
     use crate::task::{AddressSpace, Priority, Task};
     use elf::endian::AnyEndian;
-
-    #[limine::limine_tag]
-    static LIMINE_MODULES: limine::ModuleRequest = limine::ModuleRequest::new(crate::init::boot::LIMINE_REV);
+    use libsys::syscall::fs::{FileKind, OpenFlags};
 
     debug!("Unpacking kernel drivers...");
 
@@ -118,24 +167,49 @@ fn load_drivers() {
 
     let Some(drivers_module) = modules.iter().find(|module| module.path().ends_with("drivers"))
     else {
-        panic!("no drivers module found")
+        error!("No drivers module found; skipping driver loading.");
+        return
     };
 
-    let archive = tar_no_std::TarArchiveRef::new(drivers_module.data());
-    archive
-        .entries()
-        .filter_map(|entry| {
-            debug!("Attempting to parse driver blob: {}", entry.filename());
+    let drivers_path = module_mount_path(drivers_module);
+    let drivers_dir = match crate::fs::open(&drivers_path, OpenFlags::READ | OpenFlags::DIRECTORY) {
+        Ok(drivers_dir) => drivers_dir,
+        Err(err) => {
+            error!("Drivers module isn't mounted at {}; skipping driver loading: {:?}", drivers_path, err);
+            return;
+        }
+    };
 
-            match elf::ElfBytes::<AnyEndian>::minimal_parse(entry.data()) {
-                Ok(elf) => Some((entry, elf)),
+    let entries = match drivers_dir.read_dir_all() {
+        Ok(entries) => entries,
+        Err(err) => {
+            error!("Failed to read drivers directory {}; skipping driver loading: {:?}", drivers_path, err);
+            return;
+        }
+    };
+
+    entries
+        .into_iter()
+        .filter(|entry| entry.kind == FileKind::Regular)
+        .map(|entry| format!("{}/{}", drivers_path.trim_end_matches('/'), entry.name))
+        .filter_map(|path| match crate::fs::open(&path, OpenFlags::READ).and_then(|file| Ok((file.read_all()?, file))) {
+            Ok((data, file)) => Some((path, file, data)),
+            Err(err) => {
+                error!("Failed to read driver blob {}: {:?}", path, err);
+                None
+            }
+        })
+        .for_each(|(path, file, data)| {
+            debug!("Attempting to parse driver blob: {}", path);
+
+            let elf = match elf::ElfBytes::<AnyEndian>::minimal_parse(&data) {
+                Ok(elf) => elf,
                 Err(err) => {
                     error!("Failed to parse driver blob into ELF: {:?}", err);
-                    None
+                    return;
                 }
-            }
-        })
-        .for_each(|(entry, elf)| {
+            };
+
             // Get and copy the ELF segments into a small box.
             let Some(segments_copy) = elf.segments().map(|segments| segments.into_iter().collect())
             else {
@@ -143,12 +217,6 @@ fn load_drivers() {
                 return
             };
 
-            // Safety: In-place transmutation of initialized bytes for the purpose of copying safely.
-            // let (_, archive_data, _) = unsafe { entry.data().align_to::<MaybeUninit<u8>>() };
-            trace!("Allocating ELF data into memory...");
-            let elf_data = alloc::boxed::Box::from(entry.data());
-            trace!("ELF data allocated into memory.");
-
             let Ok((Some(shdrs), Some(_))) = elf.section_headers_with_strtab()
             else {
                 panic!("Error retrieving ELF relocation metadata.")
@@ -178,6 +246,7 @@ fn load_drivers() {
 
             trace!("Finished processing relocations, pushing task.");
 
+            // Segment data is demand-mapped from the open file, so the blob itself needn't be kept.
             let task = Task::new(
                 Priority::Normal,
                 AddressSpace::new_userspace(),
@@ -185,10 +254,12 @@ fn load_drivers() {
                 elf.ehdr,
                 segments_copy,
                 relas,
-                crate::task::ElfData::Memory(elf_data),
+                crate::task::ElfData::File(file),
+                &[],
+                &[],
             );
 
-            crate::task::PROCESSES.lock().push_back(task);
+            crate::task::push_task(task);
         });
 }
 
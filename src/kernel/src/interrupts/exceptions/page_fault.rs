@@ -17,9 +17,11 @@ crate::error_impl! {
 #[doc(hidden)]
 #[inline(never)]
 pub unsafe fn handler(fault_address: Address<Virtual>) -> Result<()> {
-    crate::cpu::state::with_scheduler(|scheduler| {
-        scheduler.task_mut().ok_or(Error::NoTask)?.demand_map(fault_address).map_err(|err| Error::Task { err })
-    })?;
+    let process =
+        crate::cpu::state::with_scheduler(|scheduler| scheduler.task_mut().map(|task| task.process().clone()))
+            .ok_or(Error::NoTask)?;
 
-    Ok(())
+    // The scheduler isn't borrowed while demand mapping, as reading file-backed data may block on I/O (which borrows
+    // the core's state to wait for interrupts).
+    process.demand_map(fault_address).map_err(|err| Error::Task { err })
 }
@@ -0,0 +1,277 @@
+use crate::{
+    arch::x86_64::structures::ioapic,
+    interrupts::{InterruptDeliveryMode, Vector},
+    mem::io::pci,
+    task::{Registers, Scheduler, State, WaitQueue},
+};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Weak},
+};
+use spin::{Mutex, RwLock};
+
+crate::error_impl! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Error {
+        /// Indicates every allocatable vector is in use.
+        NoFreeVectors => None,
+
+        /// Indicates the device supports neither MSI nor MSI-X.
+        Unsupported => None,
+
+        /// Indicates the device has no message with the provided index.
+        InvalidMessage { index: usize } => None,
+
+        /// Indicates no I/O APIC handles the global system interrupt.
+        NoIoApic { gsi: u32 } => None,
+
+        /// Indicates the global system interrupt has already been requested.
+        GsiInUse { gsi: u32 } => None,
+
+        /// Indicates the core can't be targeted by I/O APIC interrupts, as its APIC ID doesn't fit in a redirection.
+        InvalidTarget { apic_id: u32 } => None
+    }
+}
+
+/// Whether `vector` may be allocated for device interrupts.
+fn is_allocatable(vector: u8) -> bool {
+    // The APIC's software reset places its vectors at the top of the range.
+    (0x34..=0x3B).contains(&vector) || ((0x40..0xFD).contains(&vector) && u64::from(vector) != Vector::Syscall as u64)
+}
+
+/// Interrupts registered to allocated vectors.
+static INTERRUPTS: RwLock<BTreeMap<u8, Weak<Interrupt>>> = RwLock::new(BTreeMap::new());
+
+/// Global system interrupts which have been requested.
+static REQUESTED_GSIS: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+/// Finds a vector which may be allocated, and isn't registered to an interrupt.
+fn free_vector(interrupts: &BTreeMap<u8, Weak<Interrupt>>) -> Result<u8> {
+    (0..=u8::MAX).find(|vector| is_allocatable(*vector) && !interrupts.contains_key(vector)).ok_or(Error::NoFreeVectors)
+}
+
+/// What raises an interrupt: the message of a claimed device, or a global system interrupt routed through an I/O
+/// APIC.
+enum Source {
+    Msi { claim: Arc<pci::Claim> },
+    Msix { claim: Arc<pci::Claim>, index: usize },
+    Gsi { gsi: u32 },
+}
+
+/// Services a device from within its interrupt handler.
+pub type Handler = Box<dyn Fn() + Send + Sync>;
+
+/// An interrupt raised by a device, which tasks can wait on, or which a kernel driver handles directly.
+///
+/// When raised, the device's message (or its I/O APIC redirection) is masked until the interrupt is acknowledged, so
+/// the driver can service the device without being interrupted again. Interrupts with a handler are serviced as
+/// they're raised, so they're never masked.
+pub struct Interrupt {
+    vector: u8,
+    source: Source,
+    handler: Option<Handler>,
+    /// Number of times the interrupt has been raised since it was last waited on.
+    pending: Mutex<usize>,
+    waiters: WaitQueue,
+}
+
+impl Interrupt {
+    /// Allocates a vector for the message `index` of the claimed device, and programs the device to raise it on the
+    /// local core. MSI-X is preferred, falling back to MSI (which only has the message `0`).
+    pub fn new(claim: Arc<pci::Claim>, index: usize) -> Result<Arc<Self>> {
+        Self::allocate(claim, index, None)
+    }
+
+    /// Like [`Interrupt::new`], but `handler` is called (with interrupts disabled) each time the interrupt is
+    /// raised, rather than waking tasks.
+    pub fn with_handler(claim: Arc<pci::Claim>, index: usize, handler: Handler) -> Result<Arc<Self>> {
+        Self::allocate(claim, index, Some(handler))
+    }
+
+    fn allocate(claim: Arc<pci::Claim>, index: usize, handler: Option<Handler>) -> Result<Arc<Self>> {
+        let mut interrupts = INTERRUPTS.write();
+        let vector = free_vector(&interrupts)?;
+        // Read from the core directly, as kernel drivers allocate interrupts before core-local state exists.
+        let apic_id = crate::cpu::read_id();
+
+        let source = claim.with_device(|device| {
+            if let Some(mut msix) = device.get_msix() {
+                let message = msix.messages().get(index).ok_or(Error::InvalidMessage { index })?;
+                message.set_masked(true);
+                message.configure(apic_id, vector, InterruptDeliveryMode::Fixed);
+                msix.set_enable(true);
+
+                Ok(Source::Msix { claim: claim.clone(), index })
+            } else if let Some(mut msi) = device.get_msi() {
+                if index > 0 {
+                    return Err(Error::InvalidMessage { index });
+                }
+
+                msi.set_masked(true);
+                msi.configure(apic_id, vector, InterruptDeliveryMode::Fixed);
+                msi.set_enable(true);
+
+                Ok(Source::Msi { claim: claim.clone() })
+            } else {
+                Err(Error::Unsupported)
+            }
+        })?;
+
+        let interrupt = Arc::new(Self { vector, source, handler, pending: Mutex::new(0), waiters: WaitQueue::new() });
+        interrupts.insert(vector, Arc::downgrade(&interrupt));
+        drop(interrupts);
+
+        interrupt.set_masked(false);
+
+        Ok(interrupt)
+    }
+
+    /// Routes the global system interrupt `gsi` to a newly allocated vector on the core with `apic_id`. `handler` is
+    /// called (with interrupts disabled) each time it's raised.
+    ///
+    /// The polarity and trigger mode come from the MADT's interrupt source overrides, or otherwise those of the ISA
+    /// bus for the legacy GSIs, and those of PCI for the rest.
+    pub fn request_on(apic_id: u32, gsi: u32, handler: Handler) -> Result<Arc<Self>> {
+        let destination = u8::try_from(apic_id).map_err(|_| Error::InvalidTarget { apic_id })?;
+        if !ioapic::handles_gsi(gsi) {
+            return Err(Error::NoIoApic { gsi });
+        }
+
+        let mut interrupts = INTERRUPTS.write();
+        let vector = free_vector(&interrupts)?;
+        if !REQUESTED_GSIS.lock().insert(gsi) {
+            return Err(Error::GsiInUse { gsi });
+        }
+
+        let (polarity, trigger_mode) = ioapic::gsi_mode(gsi);
+        ioapic::route_gsi(gsi, vector, destination, polarity, trigger_mode);
+
+        let interrupt = Arc::new(Self {
+            vector,
+            source: Source::Gsi { gsi },
+            handler: Some(handler),
+            pending: Mutex::new(0),
+            waiters: WaitQueue::new(),
+        });
+        interrupts.insert(vector, Arc::downgrade(&interrupt));
+        drop(interrupts);
+
+        interrupt.set_masked(false);
+
+        Ok(interrupt)
+    }
+
+    fn set_masked(&self, masked: bool) {
+        match &self.source {
+            Source::Msi { claim } => claim.with_device(|device| {
+                if let Some(mut msi) = device.get_msi() {
+                    msi.set_masked(masked);
+                }
+            }),
+
+            Source::Msix { claim, index } => claim.with_device(|device| {
+                if let Some(message) = device.get_msix().and_then(|msix| msix.messages().get(*index)) {
+                    message.set_masked(masked);
+                }
+            }),
+
+            Source::Gsi { gsi } => {
+                ioapic::set_gsi_masked(*gsi, masked);
+            }
+        }
+    }
+
+    #[inline]
+    pub const fn vector(&self) -> u8 {
+        self.vector
+    }
+
+    /// Global system interrupt the interrupt is routed from, if it comes through an I/O APIC.
+    pub const fn gsi(&self) -> Option<u32> {
+        match self.source {
+            Source::Gsi { gsi } => Some(gsi),
+            Source::Msi { .. } | Source::Msix { .. } => None,
+        }
+    }
+
+    /// Records that the interrupt was raised, masking it and waking any waiting tasks. If the interrupt has a
+    /// handler, it's called instead.
+    fn raise(&self) {
+        if let Some(handler) = &self.handler {
+            handler();
+            return;
+        }
+
+        self.set_masked(true);
+        *self.pending.lock() += 1;
+        self.waiters.wake_all();
+    }
+
+    /// Takes the number of times the interrupt has been raised, or, if it hasn't been raised, blocks the scheduler's
+    /// current task until it is.
+    pub fn wait(&self, scheduler: &mut Scheduler, state: &mut State, regs: &mut Registers) -> Option<usize> {
+        let mut pending = self.pending.lock();
+
+        if *pending > 0 {
+            Some(core::mem::take(&mut *pending))
+        } else {
+            // The interrupt is raised while holding the lock, so blocking here can't miss the wake.
+            self.waiters.wait(scheduler, state, regs);
+
+            None
+        }
+    }
+
+    /// Acknowledges the interrupt, unmasking it so it can be raised again.
+    pub fn acknowledge(&self) {
+        self.set_masked(false);
+    }
+}
+
+impl Drop for Interrupt {
+    fn drop(&mut self) {
+        self.set_masked(true);
+        INTERRUPTS.write().remove(&self.vector);
+
+        if let Source::Gsi { gsi } = self.source {
+            REQUESTED_GSIS.lock().remove(&gsi);
+        }
+    }
+}
+
+/// Raises the interrupt registered to `vector`. Returns whether one was registered.
+pub fn dispatch(vector: u8) -> bool {
+    let Some(interrupt) = INTERRUPTS.read().get(&vector).and_then(Weak::upgrade) else { return false };
+    interrupt.raise();
+
+    true
+}
+
+/// Masks the legacy PICs, and every I/O APIC redirection, so only interrupts which have been requested are delivered.
+pub fn init() {
+    // Safety: The PICs are moved onto the vectors reserved for them, with every line masked, so they raise nothing.
+    unsafe { pic_8259::ChainedPic::new(Vector::Clock as u8).init(pic_8259::InterruptLines::disabled()) };
+
+    for io_apic in ioapic::get_io_apics() {
+        for gsi in io_apic.handled_irqs() {
+            let mut redirection = io_apic.get_redirection(gsi);
+            redirection.set_masked(true);
+            io_apic.set_redirection(gsi, &redirection);
+        }
+    }
+
+    debug!("Masked the legacy PICs, and {} I/O APIC(s).", ioapic::get_io_apics().len());
+}
+
+/// Routes the global system interrupt `gsi` to the current core, calling `handler` each time it's raised.
+pub fn request_irq(gsi: u32, handler: Handler) -> Result<Arc<Interrupt>> {
+    // Read from the core directly, as kernel drivers request interrupts before core-local state exists.
+    Interrupt::request_on(crate::cpu::read_id(), gsi, handler)
+}
+
+/// Routes the ISA interrupt `isa_irq` (following any interrupt source override) to the current core, calling
+/// `handler` each time it's raised.
+pub fn request_isa_irq(isa_irq: u8, handler: Handler) -> Result<Arc<Interrupt>> {
+    request_irq(ioapic::isa_irq_gsi(isa_irq), handler)
+}
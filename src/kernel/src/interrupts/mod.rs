@@ -1,4 +1,5 @@
 pub mod exceptions;
+pub mod irq;
 pub mod traps;
 
 mod instructions;
@@ -49,7 +50,7 @@ pub enum Vector {
     Timer = 0x30,
     Thermal = 0x32,
     Performance = 0x33,
-    /* 0x34..=0x3B free for use */
+    /* 0x34..=0x3B allocated for device interrupts, see `irq` */
     Error = 0x3C,
     LINT0 = 0x3D,
     LINT1 = 0x3E,
@@ -13,10 +13,20 @@ use crate::{
 #[inline(never)]
 pub unsafe fn handle_trap(irq_vector: u64, state: &mut State, regs: &mut Registers) {
     match Vector::try_from(irq_vector) {
+        // The kernel is halted mid-operation, so the interrupted context isn't a task's, and can't be switched out.
+        // The timer is re-armed, so the scheduler runs again soon after the kernel returns to the task.
+        Ok(Vector::Timer) if crate::cpu::state::is_waiting_for_interrupt() => {
+            // Safety: No task switch occurred, so there's no other preemption wait to supercede.
+            unsafe {
+                crate::cpu::state::set_preemption_wait(core::num::NonZeroU16::MIN).unwrap();
+            }
+        }
+
         Ok(Vector::Timer) => crate::cpu::state::with_scheduler(|scheduler| scheduler.interrupt_task(state, regs)),
 
         Ok(Vector::Syscall) => handle_syscall(state, regs),
 
+        Err(_) if crate::interrupts::irq::dispatch(u8::try_from(irq_vector).unwrap()) => {}
         Err(err) => panic!("Invalid interrupt vector: {:X?}", err),
         vector_result => unimplemented!("Unhandled interrupt: {:?}", vector_result),
     }
@@ -24,8 +34,9 @@ pub unsafe fn handle_trap(irq_vector: u64, state: &mut State, regs: &mut Registe
     crate::cpu::state::end_of_interrupt().unwrap();
 }
 
+/// Dispatches a system call from the given task context. This is shared by the `int 0x80` and `syscall` entry paths.
 #[allow(clippy::similar_names)]
-fn handle_syscall(state: &mut State, regs: &mut Registers) {
+pub fn handle_syscall(state: &mut State, regs: &mut Registers) {
     let vector = regs.rax;
     let arg0 = regs.rdi;
     let arg1 = regs.rsi;
@@ -34,8 +45,5 @@ fn handle_syscall(state: &mut State, regs: &mut Registers) {
     let arg4 = regs.r8;
     let arg5 = regs.r9;
 
-    let result = syscall::process(vector, arg0, arg1, arg2, arg3, arg4, arg5, state, regs);
-    let (rdi, rsi) = <libsys::syscall::Result as libsys::syscall::ResultConverter>::into_registers(result);
-    regs.rdi = rdi;
-    regs.rsi = rsi;
+    syscall::process(vector, arg0, arg1, arg2, arg3, arg4, arg5, state, regs);
 }
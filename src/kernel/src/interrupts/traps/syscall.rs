@@ -1,6 +1,57 @@
-use crate::task::{Registers, State};
-use libsys::syscall::{Error, Result, Success, Vector};
+use crate::task::{
+    device::DeviceClaim,
+    handle::{HandleEntry, Object},
+    ipc::{self, Channel, Message},
+    shm::SharedMemory,
+    AddressSpaceError, Cacheability, MmapPermissions, Process, Registers, Scheduler, State, Task,
+};
+use alloc::{sync::Arc, vec::Vec};
+use core::num::NonZeroUsize;
+use libsys::{
+    syscall::{handle::Rights, Error, Result, ResultConverter, Success, Vector},
+    Address, Frame, Page,
+};
 
+/// Writes the result of a system call into the calling task's registers.
+fn write_result(result: Result, regs: &mut Registers) {
+    trace!("Syscall: {:X?}", result);
+
+    let (rdi, rsi) = result.into_registers();
+    regs.rdi = rdi;
+    regs.rsi = rsi;
+}
+
+/// Completes the system call successfully, then switches tasks with `switch_fn`. The result has to be written
+/// beforehand, as switching replaces the register state with that of the next task.
+fn complete_and_switch(
+    state: &mut State,
+    regs: &mut Registers,
+    switch_fn: impl FnOnce(&mut Scheduler, &mut State, &mut Registers),
+) {
+    write_result(Ok(Success::Ok), regs);
+    crate::cpu::state::with_scheduler(|scheduler| switch_fn(scheduler, state, regs));
+}
+
+/// Views `values` as bytes, to be copied to userspace.
+///
+/// ### Safety
+///
+/// `T` must have no padding bytes.
+unsafe fn struct_bytes<T: Copy>(values: &[T]) -> &[u8] {
+    core::slice::from_raw_parts(values.as_ptr().cast::<u8>(), core::mem::size_of_val(values))
+}
+
+/// Completes a system call which may have blocked the calling task. Blocking system calls write their retry result
+/// before blocking, and return `None`, in which case the registers now belong to the next task.
+fn complete_blocking(result: core::result::Result<Option<Success>, Error>, regs: &mut Registers) {
+    match result {
+        Ok(Some(success)) => write_result(Ok(success), regs),
+        Ok(None) => {}
+        Err(err) => write_result(Err(err), regs),
+    }
+}
+
+/// Processes a system call, writing its result into the calling task's registers.
 #[allow(clippy::too_many_arguments)]
 pub(super) fn process(
     vector: usize,
@@ -12,7 +63,7 @@ pub(super) fn process(
     arg5: usize,
     state: &mut State,
     regs: &mut Registers,
-) -> Result {
+) {
     trace!(
         "Syscall Args: Vector:{:X?}   0:{:X?}  1:{:X?}  2:{:X?}  3:{:X?}  4:{:X?}  5:{:X?}",
         vector,
@@ -36,20 +87,97 @@ pub(super) fn process(
         Ok(Vector::KlogTrace) => process_klog(log::Level::Trace, arg0, arg1),
 
         Ok(Vector::TaskExit) => {
-            crate::cpu::state::with_scheduler(|scheduler| scheduler.kill_task(state, regs));
-
-            Ok(Success::Ok)
+            return complete_and_switch(state, regs, |scheduler, state, regs| scheduler.kill_task(arg0, state, regs));
         }
         Ok(Vector::TaskYield) => {
-            crate::cpu::state::with_scheduler(|scheduler| scheduler.yield_task(state, regs));
+            return complete_and_switch(state, regs, Scheduler::yield_task);
+        }
+        Ok(Vector::TaskSleep) => {
+            let ticks = u64::try_from(arg0)
+                .map_err(|_| Error::InvalidArgument)
+                .and_then(|milliseconds| crate::cpu::state::ms_to_ticks(milliseconds).map_err(|_| Error::NoActiveTask));
 
-            Ok(Success::Ok)
+            match ticks {
+                Ok(ticks) => {
+                    return complete_and_switch(state, regs, |scheduler, state, regs| {
+                        scheduler.sleep_task(ticks, state, regs);
+                    });
+                }
+                Err(err) => Err(err),
+            }
         }
-    };
+        Ok(Vector::TaskFutexWait) => return process_futex_wait(arg0, arg1, state, regs),
+        Ok(Vector::TaskFutexWake) => process_futex_wake(arg0, arg1),
+        Ok(Vector::TaskSpawnThread) => process_spawn_thread(arg0, arg1, arg2, arg3),
+        Ok(Vector::TaskJoinThread) => return process_join_thread(arg0, arg1, state, regs),
+        Ok(Vector::TaskWait) => return process_task_wait(arg0, state, regs),
 
-    trace!("Syscall: {:X?}", result);
+        Ok(Vector::MemMap) => process_mem_map(arg0, arg1, arg2, arg3),
+        Ok(Vector::MemUnmap) => process_mem_unmap(arg0, arg1, arg2),
+        Ok(Vector::MemProtect) => process_mem_protect(arg0, arg1, arg2, arg3),
+        Ok(Vector::MemMapDma) => process_mem_map_dma(arg0, arg1, arg2, arg3, arg4),
+        Ok(Vector::MemCreate) => process_mem_create(arg0),
+        Ok(Vector::MemMapObject) => process_mem_map_object(arg0, arg1, arg2),
+
+        Ok(Vector::IpcCreate) => process_ipc_create(arg0),
+        Ok(Vector::IpcSend) => {
+            let build_fn = |task: &Task, _: &Channel| build_message(task, (arg1, arg2), (arg3, arg4));
+            let result = process_ipc_send(arg0, false, build_fn, state, regs);
+            return complete_blocking(result, regs);
+        }
+        Ok(Vector::IpcReceive) => {
+            let result = process_ipc_receive(arg0, (arg1, arg2), arg3, state, regs);
+            return complete_blocking(result, regs);
+        }
+        Ok(Vector::IpcCall) => {
+            let build_fn = |task: &Task, _: &Channel| build_message(task, (arg1, arg2), (arg3, arg4));
+            let result = process_ipc_send(arg0, true, build_fn, state, regs);
+            return complete_blocking(result, regs);
+        }
+        Ok(Vector::IpcAwaitReply) => {
+            let result = process_ipc_await_reply(arg0, (arg1, arg2), arg3, state, regs);
+            return complete_blocking(result, regs);
+        }
+        Ok(Vector::IpcReply) => process_ipc_reply(arg0, (arg1, arg2), (arg3, arg4)),
+
+        Ok(Vector::HandleDuplicate) => process_handle_duplicate(arg0, arg1),
+        Ok(Vector::HandleClose) => process_handle_close(arg0),
+        Ok(Vector::HandleTransfer) => {
+            let result =
+                process_ipc_send(arg1, false, |task, channel| transfer_message(task, arg0, channel), state, regs);
+            return complete_blocking(result, regs);
+        }
+
+        Ok(Vector::PciEnumerate) => process_pci_enumerate((arg0, arg1, arg2), (arg3, arg4)),
+        Ok(Vector::PciClaim) => process_pci_claim(arg0, arg1),
 
-    result
+        Ok(Vector::IrqCreate) => process_irq_create(arg0, arg1),
+        Ok(Vector::IrqWait) => {
+            let result = process_irq_wait(arg0, state, regs);
+            return complete_blocking(result, regs);
+        }
+        Ok(Vector::IrqAcknowledge) => process_irq_acknowledge(arg0),
+
+        Ok(Vector::FsOpen) => process_fs_open((arg0, arg1), arg2),
+        Ok(Vector::FsRead) => process_fs_read(arg0, (arg1, arg2)),
+        Ok(Vector::FsWrite) => process_fs_write(arg0, (arg1, arg2)),
+        Ok(Vector::FsSeek) => process_fs_seek(arg0, arg1, arg2),
+        Ok(Vector::FsClose) => process_fs_close(arg0),
+        Ok(Vector::FsStat) => process_fs_stat(arg0, arg1),
+        Ok(Vector::FsReadDir) => process_fs_read_dir(arg0, (arg1, arg2)),
+        Ok(Vector::FsSync) => process_fs_sync(arg0),
+
+        Ok(Vector::DisplayCreateWindow) => process_display_create_window((arg0, arg1, arg2, arg3), arg4),
+        Ok(Vector::DisplayResizeWindow) => process_display_resize_window(arg0, (arg1, arg2), arg3),
+        Ok(Vector::DisplayCommit) => process_display_commit(arg0, arg1),
+        Ok(Vector::DisplayDestroyWindow) => process_display_destroy_window(arg0),
+        Ok(Vector::DisplayWaitEvent) => {
+            let result = process_display_wait_event(arg0, arg1, state, regs);
+            return complete_blocking(result, regs);
+        }
+    };
+
+    write_result(result, regs);
 }
 
 fn process_klog(level: log::Level, str_ptr_arg: usize, str_len: usize) -> Result {
@@ -86,3 +214,1034 @@ fn process_klog(level: log::Level, str_ptr_arg: usize, str_len: usize) -> Result
 
     Ok(Success::Ok)
 }
+
+/// Gets the process named by the task handle `handle`, ensuring the handle has `rights`.
+fn get_process(task: &Task, handle: usize, rights: Rights) -> Result<Arc<Process>> {
+    Ok(task.handles().get_task(handle, rights)?)
+}
+
+/// Gets the process named by the task handle `handle`, which must be the calling task's own.
+///
+/// A process's threads all run on one core, and unmappings are only flushed from the local core's TLB, so threads
+/// can't be spawned into, nor memory unmapped from, a process which may be running elsewhere.
+fn get_own_process(task: &Task, handle: usize, rights: Rights) -> Result<Arc<Process>> {
+    let process = get_process(task, handle, rights)?;
+
+    if Arc::ptr_eq(&process, task.process()) {
+        Ok(process)
+    } else {
+        Err(Error::AccessDenied)
+    }
+}
+
+fn mem_args(address: usize, page_count: usize) -> Result<(Address<Page>, NonZeroUsize)> {
+    let address = Address::<Page>::new(address).ok_or(Error::InvalidAddress)?;
+    let page_count = NonZeroUsize::new(page_count).ok_or(Error::InvalidArgument)?;
+
+    Ok((address, page_count))
+}
+
+fn mem_permissions(permissions: usize) -> Result<MmapPermissions> {
+    libsys::syscall::mem::Permissions::try_from(permissions)
+        .map(MmapPermissions::from)
+        .map_err(|_| Error::InvalidArgument)
+}
+
+impl From<AddressSpaceError> for Error {
+    fn from(err: AddressSpaceError) -> Self {
+        match err {
+            AddressSpaceError::AllocError => Self::OutOfMemory,
+            _ => Self::InvalidAddress,
+        }
+    }
+}
+
+fn process_mem_map(task_handle: usize, address: usize, page_count: usize, permissions: usize) -> Result {
+    let page_count = NonZeroUsize::new(page_count).ok_or(Error::InvalidArgument)?;
+    let permissions = mem_permissions(permissions)?;
+
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let process = get_process(task, task_handle, Rights::MANAGE)?;
+
+        let mapping = if address == 0 {
+            let floor = process.mmap_floor();
+            process.address_space().map_any(floor, page_count, permissions)
+        } else {
+            let address = Address::<Page>::new(address).ok_or(Error::InvalidAddress)?;
+            process.address_space().mmap(Some(address), page_count, permissions)
+        }?;
+
+        Ok(Success::NonNullPtr(mapping.as_non_null_ptr().cast()))
+    })
+}
+
+fn process_mem_unmap(task_handle: usize, address: usize, page_count: usize) -> Result {
+    let (address, page_count) = mem_args(address, page_count)?;
+
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let process = get_own_process(task, task_handle, Rights::MANAGE)?;
+
+        // Safety: Userspace memory is never referenced by the kernel outside of a syscall's context.
+        unsafe { process.address_space().munmap(address, page_count) }?;
+        process.release_shared(address, page_count);
+
+        Ok(Success::Ok)
+    })
+}
+
+fn process_mem_protect(task_handle: usize, address: usize, page_count: usize, permissions: usize) -> Result {
+    let (address, page_count) = mem_args(address, page_count)?;
+    let permissions = mem_permissions(permissions)?;
+
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let process = get_own_process(task, task_handle, Rights::MANAGE)?;
+
+        // Safety: Userspace memory is never referenced by the kernel outside of a syscall's context.
+        unsafe { process.address_space().mprotect(address, page_count, permissions) }?;
+
+        Ok(Success::Ok)
+    })
+}
+
+fn process_mem_map_dma(
+    task_handle: usize,
+    page_count: usize,
+    below_4gib: usize,
+    cacheability: usize,
+    physical_address_ptr: usize,
+) -> Result {
+    let page_count = NonZeroUsize::new(page_count).ok_or(Error::InvalidArgument)?;
+    let limit = match below_4gib {
+        0 => None,
+        1 => Some(Address::<Frame>::new(1 << 32).unwrap()),
+        _ => return Err(Error::InvalidArgument),
+    };
+    let cacheability = libsys::syscall::mem::Cacheability::try_from(cacheability)
+        .map(Cacheability::from)
+        .map_err(|_| Error::InvalidArgument)?;
+    let physical_address_ptr = Address::new(physical_address_ptr).ok_or(Error::InvalidAddress)?;
+
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let process = get_process(task, task_handle, Rights::MANAGE)?;
+
+        let floor = process.mmap_floor();
+        let (address, frame) = process.address_space().map_dma(floor, page_count, limit, cacheability)?;
+
+        if let Err(err) = task.copy_to_user(physical_address_ptr, &frame.get().get().to_ne_bytes()) {
+            // Safety: Memory was only just mapped, and userspace hasn't been told about it.
+            unsafe { process.address_space().munmap(address, page_count) }.ok();
+
+            return Err(Error::from(err));
+        }
+
+        Ok(Success::NonNullPtr(core::ptr::NonNull::new(address.as_ptr()).unwrap().cast()))
+    })
+}
+
+fn process_mem_create(page_count: usize) -> Result {
+    let page_count = NonZeroUsize::new(page_count).ok_or(Error::InvalidArgument)?;
+    let memory = SharedMemory::new(page_count).map_err(|_| Error::OutOfMemory)?;
+
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let rights = Rights::MAP | Rights::DUPLICATE | Rights::TRANSFER;
+
+        Ok(Success::Value(task.handles().insert(HandleEntry::new(Object::Memory(memory), rights)).get()))
+    })
+}
+
+fn process_mem_map_object(task_handle: usize, memory_handle: usize, permissions: usize) -> Result {
+    let permissions = mem_permissions(permissions)?;
+
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let process = get_process(task, task_handle, Rights::MANAGE)?;
+        let memory = task.handles().get_memory(memory_handle, Rights::MAP)?;
+
+        let address = process.map_shared(memory, permissions)?;
+
+        Ok(Success::NonNullPtr(core::ptr::NonNull::new(address.as_ptr()).unwrap().cast()))
+    })
+}
+
+impl From<crate::task::futex::Error> for Error {
+    fn from(err: crate::task::futex::Error) -> Self {
+        use crate::task::futex::Error as FutexError;
+
+        match err {
+            FutexError::Misaligned { .. } | FutexError::NotMapped { .. } => Self::InvalidAddress,
+            FutexError::WouldBlock => Self::WouldBlock,
+        }
+    }
+}
+
+fn process_futex_wait(address: usize, expected: usize, state: &mut State, regs: &mut Registers) {
+    let result = Address::new(address).ok_or(Error::InvalidAddress).and_then(|address| {
+        let expected = u32::try_from(expected).map_err(|_| Error::InvalidArgument)?;
+
+        crate::cpu::state::with_scheduler(|scheduler| {
+            scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+
+            // The task will only observe this result if it blocks, since a failure overwrites it below.
+            write_result(Ok(Success::Ok), regs);
+
+            crate::task::futex::wait(address, expected, scheduler, state, regs).map_err(Error::from)
+        })
+    });
+
+    if let Err(err) = result {
+        // No task switch occurs on failure, so the registers still belong to the calling task.
+        write_result(Err(err), regs);
+    }
+}
+
+fn process_futex_wake(address: usize, count: usize) -> Result {
+    let address = Address::new(address).ok_or(Error::InvalidAddress)?;
+
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let woken = crate::task::futex::wake(task, address, count)?;
+
+        Ok(Success::Value(woken))
+    })
+}
+
+fn process_spawn_thread(task_handle: usize, entry: usize, arg: usize, tls_base: usize) -> Result {
+    let entry = Address::new(entry).ok_or(Error::InvalidAddress)?;
+
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        get_own_process(task, task_handle, Rights::MANAGE)?;
+        let thread = task.spawn_thread(entry, arg, tls_base).map_err(|_| Error::OutOfMemory)?;
+
+        let thread_id = thread.thread_id();
+        scheduler.push_thread(thread);
+
+        Ok(Success::Value(thread_id))
+    })
+}
+
+fn process_join_thread(task_handle: usize, thread_id: usize, state: &mut State, regs: &mut Registers) {
+    let result = crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let process = get_process(task, task_handle, Rights::READ)?;
+
+        // If the thread hasn't exited, the task blocks and observes this result when woken.
+        write_result(Ok(Success::Ok), regs);
+
+        process.join_thread(thread_id, scheduler, state, regs).map_err(|_| Error::InvalidArgument)
+    });
+
+    match result {
+        Ok(Some(exit_status)) => write_result(Ok(Success::Value(exit_status)), regs),
+        // Task was blocked, so the registers belong to the next task.
+        Ok(None) => {}
+        Err(err) => write_result(Err(err), regs),
+    }
+}
+
+fn process_task_wait(task_handle: usize, state: &mut State, regs: &mut Registers) {
+    let result: core::result::Result<_, Error> = crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let exit_status = task.handles().get_exit_status(task_handle, Rights::READ)?;
+
+        // If the task hasn't exited, the waiting task blocks and observes this result when woken.
+        write_result(Ok(Success::Ok), regs);
+
+        Ok(exit_status.wait(scheduler, state, regs))
+    });
+
+    match result {
+        Ok(Some(exit_status)) => write_result(Ok(Success::Value(exit_status)), regs),
+        // Task was blocked, so the registers belong to the next task.
+        Ok(None) => {}
+        Err(err) => write_result(Err(err), regs),
+    }
+}
+
+impl From<crate::task::Error> for Error {
+    fn from(err: crate::task::Error) -> Self {
+        match err {
+            crate::task::Error::AddressSpace { err } => Self::from(err),
+            _ => Self::InvalidAddress,
+        }
+    }
+}
+
+impl From<ipc::Error> for Error {
+    fn from(_: ipc::Error) -> Self {
+        Self::InvalidArgument
+    }
+}
+
+/// Builds a message from the calling task's memory, unmapping any granted pages from its address space.
+fn build_message(
+    task: &Task,
+    (data_ptr, data_len): (usize, usize),
+    (grant_ptr, grant_pages): (usize, usize),
+) -> Result<Message> {
+    if data_len > libsys::syscall::ipc::MAX_MESSAGE_SIZE {
+        return Err(Error::InvalidArgument);
+    }
+
+    let mut data = alloc::vec![0u8; data_len].into_boxed_slice();
+    task.copy_from_user(Address::new(data_ptr).ok_or(Error::InvalidAddress)?, &mut data)?;
+
+    let grant = match NonZeroUsize::new(grant_pages) {
+        Some(page_count) => {
+            let address = Address::<Page>::new(grant_ptr).ok_or(Error::InvalidAddress)?;
+
+            // Safety: Userspace memory is never referenced by the kernel outside of a syscall's context.
+            unsafe { task.address_space().take_frames(address, page_count) }?
+        }
+
+        None => Vec::new(),
+    };
+
+    Ok(Message::new(data, grant))
+}
+
+/// Builds a message which carries only the handle `handle`, moving it out of the calling task's handle table.
+fn transfer_message(task: &Task, handle: usize, channel: &Channel) -> Result<Message> {
+    let mut handles = task.handles();
+
+    // A channel holding a handle to itself could never be destroyed.
+    let entry = handles.get(handle, Rights::TRANSFER)?;
+    if matches!(entry.object(), Object::Channel(object) if core::ptr::eq(Arc::as_ptr(object), channel)) {
+        return Err(Error::InvalidArgument);
+    }
+
+    Ok(Message::with_handle(handles.remove(handle, Rights::TRANSFER)?))
+}
+
+/// Copies a message into the calling task's memory, mapping any granted pages into its address space, and adding any
+/// transferred handle (and the reply handle of a call) to its handle table.
+///
+/// If delivery fails, it's undone, and the message is left intact so it can be returned to where it was received from.
+fn deliver_message(
+    task: &Task,
+    message: &mut Message,
+    (buffer_ptr, buffer_len): (usize, usize),
+    info_ptr: usize,
+) -> Result<()> {
+    let info_address = Address::new(info_ptr).ok_or(Error::InvalidAddress)?;
+    let len = message.data().len();
+    task.copy_to_user(Address::new(buffer_ptr).ok_or(Error::InvalidAddress)?, &message.data()[..len.min(buffer_len)])?;
+
+    let grant = match NonZeroUsize::new(message.grant().len()) {
+        Some(page_count) => {
+            let floor = task.mmap_floor();
+            let address = task.address_space().map_frames(floor, message.grant(), MmapPermissions::ReadWrite)?;
+
+            Some((address, page_count))
+        }
+
+        None => None,
+    };
+
+    let mut handles = task.handles();
+    let handle = message.handle().map(|entry| handles.insert(entry.clone()));
+    let reply_handle = message
+        .reply()
+        .map(|reply| handles.insert(HandleEntry::new(Object::Reply(reply.clone()), Rights::WRITE | Rights::TRANSFER)));
+    drop(handles);
+
+    let info = libsys::syscall::ipc::MessageInfo {
+        len,
+        reply_handle: reply_handle.map_or(0, NonZeroUsize::get),
+        grant_address: grant.map_or(0, |(address, _)| address.get().get()),
+        grant_page_count: grant.map_or(0, |(_, page_count)| page_count.get()),
+        handle: handle.map_or(0, NonZeroUsize::get),
+    };
+    // Safety: `MessageInfo` is `repr(C)`, and contains only integers.
+    if let Err(err) = task.copy_to_user(info_address, unsafe { struct_bytes(core::slice::from_ref(&info)) }) {
+        let mut handles = task.handles();
+        for handle in [handle, reply_handle].into_iter().flatten() {
+            handles.remove(handle.get(), Rights::empty()).ok();
+        }
+        drop(handles);
+
+        if let Some((address, page_count)) = grant {
+            // Safety: Pages were only just mapped, and the task hasn't returned to userspace since, so nothing
+            //         references them. The message still owns their frames.
+            unsafe { task.address_space().take_frames(address, page_count) }.ok();
+        }
+
+        return Err(err);
+    }
+
+    // Everything the message carried is now owned by the task.
+    message.take_grant();
+    message.take_handle();
+    message.take_reply();
+
+    Ok(())
+}
+
+fn process_ipc_create(capacity: usize) -> Result {
+    let capacity = NonZeroUsize::new(capacity).ok_or(Error::InvalidArgument)?;
+
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let channel = Object::Channel(Arc::new(Channel::new(capacity)));
+        let rights = Rights::READ | Rights::WRITE | Rights::DUPLICATE | Rights::TRANSFER;
+
+        Ok(Success::Value(task.handles().insert(HandleEntry::new(channel, rights)).get()))
+    })
+}
+
+fn process_ipc_send(
+    channel_handle: usize,
+    is_call: bool,
+    build_fn: impl FnOnce(&Task, &Channel) -> Result<Message>,
+    state: &mut State,
+    regs: &mut Registers,
+) -> core::result::Result<Option<Success>, Error> {
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let channel = task.handles().get_channel(channel_handle, Rights::WRITE)?;
+
+        // If the channel is full, the task blocks and retries when woken.
+        write_result(Err(Error::WouldBlock), regs);
+
+        let mut reply = None;
+        let sent = channel.send(scheduler, state, regs, |scheduler| {
+            let mut message = build_fn(scheduler.task_mut().unwrap(), &channel)?;
+            if is_call {
+                reply = Some(ipc::make_call(&mut message));
+            }
+
+            Ok(message)
+        })?;
+
+        if !sent {
+            return Ok(None);
+        }
+
+        // The caller's handle to the reply can only await it.
+        Ok(Some(reply.map_or(Success::Ok, |reply| {
+            let entry = HandleEntry::new(Object::Reply(reply), Rights::READ);
+            Success::Value(scheduler.task_mut().unwrap().handles().insert(entry).get())
+        })))
+    })
+}
+
+fn process_ipc_receive(
+    channel_handle: usize,
+    buffer: (usize, usize),
+    info_ptr: usize,
+    state: &mut State,
+    regs: &mut Registers,
+) -> core::result::Result<Option<Success>, Error> {
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let channel = task.handles().get_channel(channel_handle, Rights::READ)?;
+
+        // If the channel is empty, the task blocks and retries when woken.
+        write_result(Err(Error::WouldBlock), regs);
+
+        match channel.receive(scheduler, state, regs) {
+            Some(mut message) => {
+                if let Err(err) = deliver_message(scheduler.task_mut().unwrap(), &mut message, buffer, info_ptr) {
+                    // The message isn't lost, so it can be received again once the task fixes its arguments.
+                    channel.restore(message);
+                    return Err(err);
+                }
+
+                Ok(Some(Success::Ok))
+            }
+
+            None => Ok(None),
+        }
+    })
+}
+
+fn process_ipc_await_reply(
+    reply_handle: usize,
+    buffer: (usize, usize),
+    info_ptr: usize,
+    state: &mut State,
+    regs: &mut Registers,
+) -> core::result::Result<Option<Success>, Error> {
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let reply = task.handles().get_reply(reply_handle, Rights::READ)?;
+
+        // If no reply has been sent, the task blocks and retries when woken.
+        write_result(Err(Error::WouldBlock), regs);
+
+        match reply.take(scheduler, state, regs)? {
+            Some(mut message) => {
+                let task = scheduler.task_mut().unwrap();
+                if let Err(err) = deliver_message(task, &mut message, buffer, info_ptr) {
+                    reply.restore(message);
+                    return Err(err);
+                }
+
+                // The reply has been taken, so the handle is spent.
+                task.handles().remove(reply_handle, Rights::READ).ok();
+
+                Ok(Some(Success::Ok))
+            }
+
+            None => Ok(None),
+        }
+    })
+}
+
+fn process_ipc_reply(reply_handle: usize, data: (usize, usize), grant: (usize, usize)) -> Result {
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let reply = task.handles().get_reply(reply_handle, Rights::WRITE)?;
+        // Claim the reply before building it, as building takes the grant out of the task's address space.
+        reply.claim()?;
+        let message = match build_message(task, data, grant) {
+            Ok(message) => message,
+            Err(err) => {
+                reply.release();
+                return Err(err);
+            }
+        };
+
+        reply.send(message);
+        // A call can only be replied to once, so the handle is spent.
+        task.handles().remove(reply_handle, Rights::WRITE).ok();
+
+        Ok(Success::Ok)
+    })
+}
+
+impl From<crate::task::handle::Error> for Error {
+    fn from(err: crate::task::handle::Error) -> Self {
+        match err {
+            crate::task::handle::Error::InvalidHandle { .. } => Self::InvalidHandle,
+            crate::task::handle::Error::AccessDenied { .. } => Self::AccessDenied,
+        }
+    }
+}
+
+fn process_handle_duplicate(handle: usize, rights: usize) -> Result {
+    let rights = Rights::from_bits(rights).ok_or(Error::InvalidArgument)?;
+
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let handle = task.handles().duplicate(handle, rights)?;
+
+        Ok(Success::Value(handle.get()))
+    })
+}
+
+fn process_handle_close(handle: usize) -> Result {
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        task.handles().remove(handle, Rights::empty())?;
+
+        Ok(Success::Ok)
+    })
+}
+
+impl From<crate::mem::io::pci::Error> for Error {
+    fn from(err: crate::mem::io::pci::Error) -> Self {
+        match err {
+            crate::mem::io::pci::Error::AlreadyClaimed { .. } => Self::AccessDenied,
+            _ => Self::InvalidArgument,
+        }
+    }
+}
+
+fn pci_filter_arg<T: TryFrom<usize>>(value: usize) -> Result<Option<T>> {
+    match value {
+        libsys::syscall::pci::FILTER_ANY => Ok(None),
+        value => T::try_from(value).map(Some).map_err(|_| Error::InvalidArgument),
+    }
+}
+
+fn process_pci_enumerate(
+    (class, subclass, vendor_id): (usize, usize, usize),
+    (buffer_ptr, buffer_len): (usize, usize),
+) -> Result {
+    use libsys::syscall::pci::DeviceInfo;
+
+    let class = pci_filter_arg::<u8>(class)?;
+    let subclass = pci_filter_arg::<u8>(subclass)?;
+    let vendor_id = pci_filter_arg::<u16>(vendor_id)?;
+
+    let devices = crate::mem::io::pci::with_devices(|devices| {
+        devices
+            .iter()
+            .enumerate()
+            .map(|(index, device)| {
+                let (class, subclass, prog_if) = device.get_class_codes();
+
+                DeviceInfo {
+                    index,
+                    vendor_id: device.get_vendor_id(),
+                    device_id: device.get_device_id(),
+                    class,
+                    subclass,
+                    prog_if,
+                    revision_id: device.get_revision_id(),
+                }
+            })
+            .filter(|info| class.map_or(true, |class| info.class == class))
+            .filter(|info| subclass.map_or(true, |subclass| info.subclass == subclass))
+            .filter(|info| vendor_id.map_or(true, |vendor_id| info.vendor_id == vendor_id))
+            .collect::<Vec<_>>()
+    });
+
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let buffer_address = Address::new(buffer_ptr).ok_or(Error::InvalidAddress)?;
+        let written = &devices[..devices.len().min(buffer_len)];
+        // Safety: `DeviceInfo` is `repr(C)`, and its fields are ordered so it has no padding.
+        task.copy_to_user(buffer_address, unsafe { struct_bytes(written) })?;
+
+        Ok(Success::Value(devices.len()))
+    })
+}
+
+/// Maps a claimed device's configuration space and memory BARs into `task`'s address space, and writes the
+/// resulting `ClaimInfo` to `info_ptr`. Every mapping is pushed to `mappings`, so they can be undone on failure.
+fn map_pci_device(
+    task: &Task,
+    claim: &crate::mem::io::pci::Claim,
+    info_ptr: usize,
+    mappings: &mut Vec<(Address<Page>, NonZeroUsize)>,
+) -> Result<()> {
+    use crate::mem::io::pci::Bar as PciBar;
+    use libsys::syscall::pci::{Bar, BarKind, ClaimInfo};
+
+    let info_address = Address::new(info_ptr).ok_or(Error::InvalidAddress)?;
+    let (config_frame, bars, msix_table) =
+        claim.with_device(|device| (device.config_frame(), device.get_bars(), device.get_msix_table()));
+
+    let floor = task.mmap_floor();
+    let mut map_device = |frame: Address<Frame>, page_count: NonZeroUsize| -> Result<Address<Page>> {
+        // Safety: Frames belong to a device, which has been claimed by the task.
+        let address = unsafe { task.address_space().map_device(floor, frame, page_count) }?;
+        mappings.push((address, page_count));
+
+        Ok(address)
+    };
+
+    let config_address = map_device(config_frame, NonZeroUsize::MIN)?;
+    let mut info = ClaimInfo { config_address: config_address.get().get(), ..ClaimInfo::default() };
+
+    for (index, (bar, bar_info)) in bars.iter().zip(info.bars.iter_mut()).enumerate() {
+        *bar_info = match bar {
+            Some(bar @ (PciBar::MemorySpace32 { .. } | PciBar::MemorySpace64 { .. })) => {
+                let bar_address = bar.get_address().get();
+                let page_offset = bar_address & libsys::page_mask();
+                let page_count = libsys::align_up_div(page_offset + bar.get_size(), libsys::page_shift());
+                let page_count = NonZeroUsize::new(page_count).ok_or(Error::InvalidArgument)?;
+                let address = map_device(Address::new_truncate(bar_address), page_count)?;
+
+                // The MSI-X table is programmed by the kernel, so userspace mustn't be able to redirect its messages.
+                if let Some((_, table_range)) = msix_table.clone().filter(|(table_bar, _)| *table_bar == index) {
+                    let first_index = address.index() + ((page_offset + table_range.start) / libsys::page_size());
+                    let last_index =
+                        address.index() + libsys::align_up_div(page_offset + table_range.end, libsys::page_shift());
+
+                    for page in (first_index..last_index).filter_map(Address::<Page>::from_index) {
+                        // Safety: Page was only just mapped, and userspace hasn't been told about it.
+                        unsafe { task.address_space().munmap(page, NonZeroUsize::MIN) }?;
+                    }
+                }
+
+                Bar { kind: BarKind::Memory, address: address.get().get() + page_offset, size: bar.get_size() }
+            }
+
+            Some(bar @ PciBar::IOSpace { .. }) => {
+                Bar { kind: BarKind::Io, address: bar.get_address().get(), size: bar.get_size() }
+            }
+
+            None => Bar::default(),
+        };
+    }
+
+    // Safety: `ClaimInfo` is `repr(C)`, and contains only word-sized fields.
+    task.copy_to_user(info_address, unsafe { struct_bytes(core::slice::from_ref(&info)) })?;
+
+    Ok(())
+}
+
+fn process_pci_claim(index: usize, info_ptr: usize) -> Result {
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let claim = crate::mem::io::pci::claim(index, task.process().id())?;
+
+        let mut mappings = Vec::new();
+        let result = map_pci_device(task, &claim, info_ptr, &mut mappings);
+        let device = Arc::new(DeviceClaim::new(claim, task.process(), mappings));
+        // On failure, dropping the device undoes its mappings before releasing the claim.
+        result?;
+
+        // The device's mappings are bound to this task's address space, so the handle can't be transferred.
+        let rights = Rights::MANAGE | Rights::DUPLICATE;
+        let handle = task.handles().insert(HandleEntry::new(Object::PciDevice(device), rights));
+
+        Ok(Success::Value(handle.get()))
+    })
+}
+
+impl From<crate::interrupts::irq::Error> for Error {
+    fn from(err: crate::interrupts::irq::Error) -> Self {
+        match err {
+            crate::interrupts::irq::Error::NoFreeVectors => Self::OutOfMemory,
+            _ => Self::InvalidArgument,
+        }
+    }
+}
+
+fn process_irq_create(device_handle: usize, index: usize) -> Result {
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let claim = task.handles().get_pci_device(device_handle, Rights::MANAGE)?;
+        let interrupt = crate::interrupts::irq::Interrupt::new(claim, index)?;
+
+        let rights = Rights::READ | Rights::MANAGE | Rights::DUPLICATE | Rights::TRANSFER;
+        let handle = task.handles().insert(HandleEntry::new(Object::Interrupt(interrupt), rights));
+
+        Ok(Success::Value(handle.get()))
+    })
+}
+
+fn process_irq_wait(
+    interrupt_handle: usize,
+    state: &mut State,
+    regs: &mut Registers,
+) -> core::result::Result<Option<Success>, Error> {
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let interrupt = task.handles().get_interrupt(interrupt_handle, Rights::READ)?;
+
+        // If the interrupt hasn't been raised, the task blocks and retries when woken.
+        write_result(Err(Error::WouldBlock), regs);
+
+        Ok(interrupt.wait(scheduler, state, regs).map(Success::Value))
+    })
+}
+
+fn process_irq_acknowledge(interrupt_handle: usize) -> Result {
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        task.handles().get_interrupt(interrupt_handle, Rights::MANAGE)?.acknowledge();
+
+        Ok(Success::Ok)
+    })
+}
+
+impl From<crate::fs::Error> for Error {
+    fn from(err: crate::fs::Error) -> Self {
+        use crate::fs::Error as FsError;
+
+        match err {
+            FsError::NotFound => Self::NotFound,
+            FsError::AlreadyExists => Self::AlreadyExists,
+            FsError::NotDirectory => Self::NotDirectory,
+            FsError::IsDirectory => Self::IsDirectory,
+            FsError::ReadOnly => Self::ReadOnly,
+            FsError::NoSpace => Self::NoSpace,
+            FsError::AccessDenied => Self::AccessDenied,
+            FsError::Corrupt { .. } | FsError::UnexpectedEof | FsError::Io => Self::Io,
+            _ => Self::InvalidArgument,
+        }
+    }
+}
+
+/// Longest path which can be passed to a filesystem system call.
+const MAX_PATH_LEN: usize = 0x1000;
+/// Most bytes transferred by a single read or write. Larger transfers complete partially.
+const MAX_FS_TRANSFER: usize = 0x10_0000;
+
+/// Gets the open file named by `fd` in the calling task's file table.
+fn get_file(fd: usize) -> Result<Arc<crate::fs::File>> {
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+
+        task.files().get(fd).ok_or(Error::InvalidHandle)
+    })
+}
+
+fn process_fs_open((path_ptr, path_len): (usize, usize), flags: usize) -> Result {
+    let flags = libsys::syscall::fs::OpenFlags::from_bits(flags).ok_or(Error::InvalidArgument)?;
+    if path_len > MAX_PATH_LEN {
+        return Err(Error::InvalidArgument);
+    }
+
+    let mut path = alloc::vec![0u8; path_len];
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+
+        task.copy_from_user(Address::new(path_ptr).ok_or(Error::InvalidAddress)?, &mut path).map_err(Error::from)
+    })?;
+
+    let path = core::str::from_utf8(&path)?;
+    let file = Arc::new(crate::fs::open(path, flags)?);
+
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+
+        Ok(Success::Value(task.files().insert(file)))
+    })
+}
+
+fn process_fs_read(fd: usize, (buffer_ptr, buffer_len): (usize, usize)) -> Result {
+    let buffer_address = Address::new(buffer_ptr).ok_or(Error::InvalidAddress)?;
+    let file = get_file(fd)?;
+
+    let mut data = alloc::vec![0u8; buffer_len.min(MAX_FS_TRANSFER)];
+
+    // The file offset is only advanced once the data has been copied, so an invalid buffer doesn't lose it.
+    file.read(&mut data, |data| {
+        crate::cpu::state::with_scheduler(|scheduler| {
+            let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+            task.copy_to_user(buffer_address, data)?;
+
+            Ok(Success::Value(data.len()))
+        })
+    })
+}
+
+fn process_fs_write(fd: usize, (buffer_ptr, buffer_len): (usize, usize)) -> Result {
+    let buffer_address = Address::new(buffer_ptr).ok_or(Error::InvalidAddress)?;
+    let file = get_file(fd)?;
+
+    let mut data = alloc::vec![0u8; buffer_len.min(MAX_FS_TRANSFER)];
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+
+        task.copy_from_user(buffer_address, &mut data).map_err(Error::from)
+    })?;
+
+    Ok(Success::Value(file.write(&data)?))
+}
+
+fn process_fs_seek(fd: usize, offset: usize, whence: usize) -> Result {
+    let whence = libsys::syscall::fs::Whence::try_from(whence).map_err(|_| Error::InvalidArgument)?;
+    // The offset is passed as its two's complement bit pattern.
+    #[allow(clippy::cast_possible_wrap)]
+    let offset = offset as isize as i64;
+
+    let offset = get_file(fd)?.seek(offset, whence)?;
+
+    Ok(Success::Value(usize::try_from(offset).map_err(|_| Error::InvalidArgument)?))
+}
+
+fn process_fs_close(fd: usize) -> Result {
+    let file = crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+
+        task.files().remove(fd).ok_or(Error::InvalidHandle)
+    })?;
+
+    // Writes are cached until synced, so closing a file commits them, as nothing else may.
+    if file.flags().contains(libsys::syscall::fs::OpenFlags::WRITE) {
+        file.sync()?;
+    }
+
+    Ok(Success::Ok)
+}
+
+fn process_fs_stat(fd: usize, stat_ptr: usize) -> Result {
+    let stat_address = Address::new(stat_ptr).ok_or(Error::InvalidAddress)?;
+    let metadata = get_file(fd)?.metadata()?;
+
+    let stat = libsys::syscall::fs::Stat {
+        kind: metadata.kind,
+        size: usize::try_from(metadata.size).unwrap(),
+        inode: usize::try_from(metadata.inode).unwrap(),
+        permissions: usize::from(metadata.permissions),
+        uid: usize::try_from(metadata.uid).unwrap(),
+        gid: usize::try_from(metadata.gid).unwrap(),
+        link_count: usize::try_from(metadata.link_count).unwrap(),
+    };
+
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        // Safety: `Stat` is `repr(C)`, and contains only word-sized fields.
+        task.copy_to_user(stat_address, unsafe { struct_bytes(core::slice::from_ref(&stat)) })?;
+
+        Ok(Success::Ok)
+    })
+}
+
+fn process_fs_read_dir(fd: usize, (buffer_ptr, buffer_len): (usize, usize)) -> Result {
+    use libsys::syscall::fs::{DirEntry, MAX_NAME_LEN};
+
+    let buffer_address = Address::new(buffer_ptr).ok_or(Error::InvalidAddress)?;
+    let file = get_file(fd)?;
+
+    // The file offset is only advanced once the entries have been copied, so an invalid buffer doesn't lose them.
+    file.read_dir_many(buffer_len, |entries| {
+        let entries = entries
+            .iter()
+            .map(|entry| {
+                let mut dir_entry = DirEntry {
+                    kind: entry.kind,
+                    inode: usize::try_from(entry.inode).unwrap(),
+                    name_len: entry.name.len().min(MAX_NAME_LEN),
+                    ..DirEntry::default()
+                };
+                dir_entry.name[..dir_entry.name_len].copy_from_slice(&entry.name.as_bytes()[..dir_entry.name_len]);
+
+                dir_entry
+            })
+            .collect::<Vec<_>>();
+
+        crate::cpu::state::with_scheduler(|scheduler| {
+            let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+            // Safety: `DirEntry` is `repr(C)`, and its name pads it to a multiple of the word size.
+            task.copy_to_user(buffer_address, unsafe { struct_bytes(&entries) })?;
+
+            Ok(Success::Value(entries.len()))
+        })
+    })
+}
+
+fn process_fs_sync(fd: usize) -> Result {
+    get_file(fd)?.sync()?;
+
+    Ok(Success::Ok)
+}
+
+impl From<crate::drivers::graphics::display::Error> for Error {
+    fn from(err: crate::drivers::graphics::display::Error) -> Self {
+        use crate::drivers::graphics::display::Error as DisplayError;
+
+        match err {
+            DisplayError::NoDisplay => Self::NotFound,
+            DisplayError::Destroyed => Self::InvalidHandle,
+            DisplayError::SharedMemory { .. } => Self::OutOfMemory,
+            _ => Self::InvalidArgument,
+        }
+    }
+}
+
+/// Maps the window's surface into the task's address space, and describes it at `surface_ptr`. The surface the
+/// window had mapped before is unmapped.
+fn map_window_surface(
+    task: &Task,
+    window: &crate::drivers::graphics::display::Window,
+    surface_ptr: usize,
+) -> Result<()> {
+    let surface_address = Address::new(surface_ptr).ok_or(Error::InvalidAddress)?;
+    let (memory, width, height) = window.buffer()?;
+    let size = memory.size();
+
+    let address = task.process().map_shared(memory, MmapPermissions::ReadWrite)?;
+    let surface =
+        libsys::syscall::display::SurfaceInfo { address: address.get().get(), size, width, height, stride: width };
+
+    // Safety: `SurfaceInfo` is `repr(C)`, and contains only word-sized fields.
+    if let Err(err) = task.copy_to_user(surface_address, unsafe { struct_bytes(core::slice::from_ref(&surface)) }) {
+        // Safety: Memory was only just mapped, and userspace hasn't been told about it.
+        unsafe { task.process().unmap_shared(address) }.ok();
+
+        return Err(Error::from(err));
+    }
+
+    if let Some(old_address) = window.set_mapped_at(address) {
+        // Safety: Caller is required to ensure no references remain to the old surface.
+        unsafe { task.process().unmap_shared(old_address) }.ok();
+    }
+
+    Ok(())
+}
+
+fn process_display_create_window((x, y, width, height): (usize, usize, usize, usize), surface_ptr: usize) -> Result {
+    let window = crate::drivers::graphics::display::Window::create(crate::drivers::graphics::rect::Rect::new(
+        x, y, width, height,
+    ))?;
+
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        map_window_surface(task, &window, surface_ptr)?;
+
+        // The window's surface is bound to this task's address space, so the handle can't be transferred.
+        let rights = Rights::READ | Rights::WRITE | Rights::MANAGE | Rights::DUPLICATE;
+        let handle = task.handles().insert(HandleEntry::new(Object::Window(window), rights));
+
+        Ok(Success::Value(handle.get()))
+    })
+}
+
+fn process_display_resize_window(window_handle: usize, (width, height): (usize, usize), surface_ptr: usize) -> Result {
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let window = task.handles().get_window(window_handle, Rights::MANAGE)?;
+
+        window.resize(width, height)?;
+        map_window_surface(task, &window, surface_ptr)?;
+
+        Ok(Success::Ok)
+    })
+}
+
+fn process_display_commit(window_handle: usize, damage_ptr: usize) -> Result {
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let window = task.handles().get_window(window_handle, Rights::WRITE)?;
+
+        let damage = match damage_ptr {
+            0 => None,
+            damage_ptr => {
+                let mut bytes = [0u8; core::mem::size_of::<libsys::syscall::display::Rect>()];
+                task.copy_from_user(Address::new(damage_ptr).ok_or(Error::InvalidAddress)?, &mut bytes)?;
+
+                let mut fields = bytes
+                    .chunks_exact(core::mem::size_of::<usize>())
+                    .map(|field| usize::from_ne_bytes(field.try_into().unwrap()));
+                let mut field = || fields.next().unwrap();
+
+                Some(crate::drivers::graphics::rect::Rect::new(field(), field(), field(), field()))
+            }
+        };
+
+        Ok(Success::Value(window.commit(damage)?))
+    })
+}
+
+fn process_display_destroy_window(window_handle: usize) -> Result {
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let window = task.handles().get_window(window_handle, Rights::MANAGE)?;
+        task.handles().remove(window_handle, Rights::MANAGE)?;
+
+        if let Some(address) = window.destroy() {
+            // Safety: Caller is required to ensure no references remain to the surface.
+            unsafe { task.process().unmap_shared(address) }?;
+        }
+
+        Ok(Success::Ok)
+    })
+}
+
+fn process_display_wait_event(
+    window_handle: usize,
+    event_ptr: usize,
+    state: &mut State,
+    regs: &mut Registers,
+) -> core::result::Result<Option<Success>, Error> {
+    let event_address = Address::new(event_ptr).ok_or(Error::InvalidAddress)?;
+
+    crate::cpu::state::with_scheduler(|scheduler| {
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        let window = task.handles().get_window(window_handle, Rights::READ)?;
+
+        // If no events are queued, the task blocks and retries when woken.
+        write_result(Err(Error::WouldBlock), regs);
+
+        let Some(event) = window.wait_event(scheduler, state, regs) else { return Ok(None) };
+
+        let task = scheduler.task_mut().ok_or(Error::NoActiveTask)?;
+        // Safety: `Event` is `repr(C)`, and contains only word-sized fields.
+        task.copy_to_user(event_address, unsafe { struct_bytes(core::slice::from_ref(&event)) })?;
+
+        Ok(Some(Success::Ok))
+    })
+}
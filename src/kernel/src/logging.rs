@@ -42,6 +42,65 @@ impl log::Log for Serial {
     fn flush(&self) {}
 }
 
+/// Writes records to the framebuffer console, which only shows the more important of them.
+struct Console;
+
+impl Console {
+    const MAX_LEVEL: log::Level = log::Level::Info;
+
+    const fn level_color(level: log::Level) -> &'static str {
+        match level {
+            log::Level::Error => "\x1B[1;31m",
+            log::Level::Warn => "\x1B[1;33m",
+            log::Level::Info => "\x1B[32m",
+            log::Level::Debug => "\x1B[36m",
+            log::Level::Trace => "\x1B[90m",
+        }
+    }
+}
+
+impl log::Log for Console {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= Self::MAX_LEVEL && crate::drivers::graphics::console::is_available()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            // The console is written without allocating, as every allocation is itself logged.
+            crate::drivers::graphics::console::print(format_args!(
+                "[{color}{level:5}\x1B[0m] {args}\n",
+                color = Self::level_color(record.level()),
+                level = record.level(),
+                args = record.args(),
+            ));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Forwards records to every log sink: the serial port, and the framebuffer console once it exists.
+struct Logger {
+    serial: &'static Serial,
+    console: Console,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.serial.enabled(metadata) || self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.serial.log(record);
+        self.console.log(record);
+    }
+
+    fn flush(&self) {
+        self.serial.flush();
+        self.console.flush();
+    }
+}
+
 crate::error_impl! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub enum Error {
@@ -75,8 +134,11 @@ pub fn init() -> Result<()> {
         })
     });
 
-    let uart = SERIAL_UART.as_ref().ok_or(Error::NoLogger)?;
-    log::set_logger(uart).map_err(|_| Error::SetLogger)?;
+    static LOGGER: spin::Once<Logger> = spin::Once::new();
+
+    let serial = SERIAL_UART.as_ref().ok_or(Error::NoLogger)?;
+    let logger = LOGGER.call_once(|| Logger { serial, console: Console });
+    log::set_logger(logger).map_err(|_| Error::SetLogger)?;
 
     Ok(())
 }
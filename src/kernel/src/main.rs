@@ -38,6 +38,7 @@
     const_option,
     const_option_ext,
     const_trait_impl,
+    offset_of,
 )]
 #![forbid(clippy::inline_asm_x86_att_syntax)]
 #![deny(clippy::semicolon_if_nothing_returned, clippy::debug_assert_with_mut_call, clippy::float_arithmetic)]
@@ -69,8 +70,11 @@ extern crate log;
 
 mod acpi;
 mod arch;
+mod block;
 mod cpu;
+mod drivers;
 mod error;
+mod fs;
 mod init;
 mod interrupts;
 mod logging;
@@ -215,11 +215,22 @@ impl FrameAllocator<'_> {
     }
 
     pub fn next_frames(&self, count: NonZeroUsize, align_bits: Option<NonZeroU32>) -> Result<Address<Frame>> {
+        self.next_frames_below(count, align_bits, None)
+    }
+
+    /// Allocates `count` contiguous frames, all of which lie below `limit` (if provided).
+    pub fn next_frames_below(
+        &self,
+        count: NonZeroUsize,
+        align_bits: Option<NonZeroU32>,
+        limit: Option<Address<Frame>>,
+    ) -> Result<Address<Frame>> {
         let align_bits = align_bits.unwrap_or(NonZeroU32::MIN).get();
         let align_index_skip = u32::max(1, align_bits >> page_shift().get());
         self.table.with(|table| {
             let mut table = table.write();
-            let index = table
+            let end_index = limit.map_or(table.len(), |limit| limit.index().min(table.len()));
+            let index = table[..end_index]
                 .windows(count.get())
                 .enumerate()
                 .step_by(align_index_skip.try_into().unwrap())
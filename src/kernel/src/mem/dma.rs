@@ -0,0 +1,88 @@
+use crate::mem::{alloc::pmm, HHDM};
+use core::{num::NonZeroUsize, ptr::NonNull};
+use libsys::{page_size, Address, Frame};
+
+/// Physically contiguous, zeroed memory owned by the kernel, which devices can access directly.
+///
+/// The memory is accessed through the HHDM, and its frames are freed when this is dropped.
+pub struct DmaBuffer {
+    frame: Address<Frame>,
+    page_count: NonZeroUsize,
+}
+
+// Safety: The buffer is only ever accessed through the global HHDM.
+unsafe impl Send for DmaBuffer {}
+// Safety: See above.
+unsafe impl Sync for DmaBuffer {}
+
+impl DmaBuffer {
+    /// Allocates `page_count` contiguous frames. If `limit` is provided, every frame lies below it.
+    pub fn new(page_count: NonZeroUsize, limit: Option<Address<Frame>>) -> pmm::Result<Self> {
+        let frame = pmm::get().next_frames_below(page_count, None, limit)?;
+        let buffer = Self { frame, page_count };
+
+        // Safety: Frames were only just allocated, and are mapped within the HHDM.
+        unsafe { core::ptr::write_bytes(buffer.as_ptr().as_ptr(), 0, buffer.len()) };
+
+        Ok(buffer)
+    }
+
+    /// The first frame of the buffer.
+    #[inline]
+    pub const fn frame(&self) -> Address<Frame> {
+        self.frame
+    }
+
+    /// Physical address of the byte at `offset` into the buffer.
+    pub fn physical_address(&self, offset: usize) -> u64 {
+        assert!(offset < self.len(), "offset is out of bounds of the buffer");
+
+        u64::try_from(self.frame.get().get() + offset).unwrap()
+    }
+
+    #[inline]
+    pub const fn page_count(&self) -> NonZeroUsize {
+        self.page_count
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.page_count.get() * page_size()
+    }
+
+    pub fn as_ptr(&self) -> NonNull<u8> {
+        NonNull::new(HHDM.offset(self.frame).unwrap().as_ptr()).unwrap()
+    }
+
+    /// ### Safety
+    ///
+    /// Caller must ensure no device is writing to the buffer while the returned slice exists.
+    pub unsafe fn as_slice(&self) -> &[u8] {
+        core::slice::from_raw_parts(self.as_ptr().as_ptr(), self.len())
+    }
+
+    /// ### Safety
+    ///
+    /// Caller must ensure no device is accessing the buffer while the returned slice exists.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn as_mut_slice(&self) -> &mut [u8] {
+        core::slice::from_raw_parts_mut(self.as_ptr().as_ptr(), self.len())
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        let frames =
+            (self.frame.index()..(self.frame.index() + self.page_count.get())).filter_map(Address::<Frame>::from_index);
+
+        for frame in frames {
+            pmm::get().free_frame(frame).ok();
+        }
+    }
+}
+
+impl core::fmt::Debug for DmaBuffer {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("DmaBuffer").field("Frame", &self.frame).field("Pages", &self.page_count).finish()
+    }
+}
@@ -6,7 +6,7 @@ pub mod standard;
 use bit_field::BitField;
 use core::{fmt, marker::PhantomData, ptr::NonNull};
 use libkernel::{LittleEndian, LittleEndianU16, LittleEndianU32, LittleEndianU8};
-use libsys::{Address, Physical};
+use libsys::{Address, Frame, Physical};
 
 crate::error_impl! {
     #[derive(Debug)]
@@ -18,45 +18,26 @@ crate::error_impl! {
     }
 }
 
-#[repr(transparent)]
-#[derive(Debug, Clone, Copy)]
-pub struct Command(u16);
-
-// TODO impl command bits
-// impl CommandRegister {
-//     volatile_bitfield_getter_ro!(reg, io_space, 0);
-//     volatile_bitfield_getter_ro!(reg, memory_space, 1);
-//     volatile_bitfield_getter!(reg, bus_master, 2);
-//     volatile_bitfield_getter_ro!(reg, special_cycle, 3);
-//     volatile_bitfield_getter_ro!(reg, memory_w_and_i, 4);
-//     volatile_bitfield_getter_ro!(reg, vga_palette_snoop, 5);
-//     volatile_bitfield_getter!(reg, parity_error, 6);
-//     volatile_bitfield_getter_ro!(reg, idsel_stepwait_cycle_ctrl, 7);
-//     volatile_bitfield_getter!(reg, serr_num, 8);
-//     volatile_bitfield_getter_ro!(reg, fast_b2b_transactions, 9);
-//     volatile_bitfield_getter!(reg, interrupt_disable, 10);
-// }
-
-// impl Volatile for CommandRegister {}
-
-// impl fmt::Debug for CommandRegister {
-//     fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
-//         formatter
-//             .debug_struct("Command Register")
-//             .field("IO Space", &self.get_io_space())
-//             .field("Memory Space", &self.get_memory_space())
-//             .field("Bus Master", &self.get_bus_master())
-//             .field("Special Cycle", &self.get_special_cycle())
-//             .field("Memory Write & Invalidate", &self.get_memory_w_and_i())
-//             .field("VGA Palette Snoop", &self.get_vga_palette_snoop())
-//             .field("Parity Error", &self.get_parity_error())
-//             .field("IDSEL Stepping/Wait Cycle Control", &self.get_idsel_stepwait_cycle_ctrl())
-//             .field("SERR#", &self.get_serr_num())
-//             .field("Fast Back-to-Back Transactions", &self.get_fast_b2b_transactions())
-//             .field("Interrupt Disable", &self.get_interrupt_disable())
-//             .finish()
-//     }
-// }
+bitflags::bitflags! {
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Command : u16 {
+        const IO_SPACE = 1 << 0;
+        const MEMORY_SPACE = 1 << 1;
+        const BUS_MASTER = 1 << 2;
+        /// * Not applicable to PCIe.
+        const SPECIAL_CYCLES = 1 << 3;
+        /// * Not applicable to PCIe.
+        const MEMORY_WRITE_AND_INVALIDATE = 1 << 4;
+        /// * Not applicable to PCIe.
+        const VGA_PALETTE_SNOOP = 1 << 5;
+        const PARITY_ERROR_RESPONSE = 1 << 6;
+        const SERR = 1 << 8;
+        /// * Not applicable to PCIe.
+        const FAST_BACK2BACK = 1 << 9;
+        const INTERRUPT_DISABLE = 1 << 10;
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DevselTiming {
@@ -145,6 +126,13 @@ pub unsafe fn new(ptr: NonNull<u8>) -> Result<Devices> {
 impl<T: Kind> Device<T> {
     const ROW_SIZE: usize = core::mem::size_of::<LittleEndianU32>();
 
+    /// Physical frame of the device's configuration space.
+    pub fn config_frame(&self) -> Address<Frame> {
+        let hhdm_offset = self.0.addr().get() - crate::mem::HHDM.address().get();
+
+        Address::new(hhdm_offset).unwrap()
+    }
+
     unsafe fn read_offset<U: LittleEndian>(&self, offset: usize) -> U::NativeType {
         self.0.as_ptr().add(offset).cast::<U>().read_volatile().get()
     }
@@ -162,11 +150,11 @@ impl<T: Kind> Device<T> {
     }
 
     pub fn get_command(&self) -> Command {
-        Command(unsafe { self.read_offset::<LittleEndianU16>(Self::ROW_SIZE) })
+        Command::from_bits_retain(unsafe { self.read_offset::<LittleEndianU16>(Self::ROW_SIZE) })
     }
 
     pub fn set_command(&mut self, command: Command) {
-        unsafe { self.write_offset::<LittleEndianU16>(Self::ROW_SIZE, command.0) }
+        unsafe { self.write_offset::<LittleEndianU16>(Self::ROW_SIZE, command.bits()) }
     }
 
     pub fn get_status(&self) -> Status {
@@ -177,7 +165,8 @@ impl<T: Kind> Device<T> {
         unsafe { self.read_offset::<LittleEndianU8>(2 * Self::ROW_SIZE) }
     }
 
-    pub fn get_class(&self) -> Class {
+    /// Reads the raw class codes, as `(class, subclass, prog_if)`.
+    pub fn get_class_codes(&self) -> (u8, u8, u8) {
         // Match format is:
         //  0x  00      | 00        | 00
         //      Class   | Subclass  | Program interface
@@ -187,6 +176,12 @@ impl<T: Kind> Device<T> {
         let subclass = unsafe { self.read_offset::<LittleEndianU8>(row_offset + 2) };
         let prog_if = unsafe { self.read_offset::<LittleEndianU8>(row_offset + 1) };
 
+        (class, subclass, prog_if)
+    }
+
+    pub fn get_class(&self) -> Class {
+        let (class, subclass, prog_if) = self.get_class_codes();
+
         Class::parse(class, subclass, prog_if)
     }
 
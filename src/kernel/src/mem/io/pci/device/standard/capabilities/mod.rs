@@ -1,46 +1,72 @@
-// mod msix;
-// pub use msix::*;
+mod msi;
+pub use msi::*;
 
-use crate::mem::io::pci::Device;
+mod msix;
+pub use msix::*;
 
-use super::Kind;
-use libkernel::{LittleEndianU32, LittleEndianU8};
+mod vendor;
+pub use vendor::*;
 
-pub trait Capability {
-    const TYPE_CODE: u8;
-    const BARS_USED: [bool; super::Standard::REGISTER_COUNT];
+use crate::{
+    interrupts::InterruptDeliveryMode,
+    mem::io::pci::{Device, Standard, Status},
+};
+use bit_field::BitField;
+use libkernel::LittleEndianU8;
 
-    unsafe fn from_base_ptr(capability_base_ptr: *mut LittleEndianU32, bars: [Option<super::Bar>; 6]) -> Self;
+/// Iterates the capabilities list of a device, yielding the ID and configuration space offset of each capability.
+pub struct Capabilities<'a> {
+    device: &'a Device<Standard>,
+    next_offset: u8,
 }
 
-pub(super) struct CapablitiesIterator<'a, K: Kind> {
-    device: &'a Device<K>,
-    next_offset: u8,
+impl Iterator for Capabilities<'_> {
+    type Item = (u8, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The bottom two bits of a capability pointer are reserved.
+        let offset = usize::from(self.next_offset & !0b11);
+        if offset == 0 {
+            return None;
+        }
+
+        // Safety: Offset was read from the capabilities list, so it's within the configuration space.
+        let id = unsafe { self.device.read_offset::<LittleEndianU8>(offset) };
+        // Safety: See above.
+        self.next_offset = unsafe { self.device.read_offset::<LittleEndianU8>(offset + 1) };
+
+        Some((id, offset))
+    }
 }
 
-impl<'a, K: Kind> CapablitiesIterator<'a, K> {
-    pub(super) fn new(device: &'a Device<K>) -> Self {
-        Self { device, next_offset: unsafe { device.read_offset::<LittleEndianU8>(Device::<K>::ROW_SIZE * 0xD) } }
+impl Device<Standard> {
+    pub fn capabilities(&self) -> Capabilities {
+        let next_offset = if self.get_status().contains(Status::CAPABILITIES) {
+            // Safety: Status indicates the capabilities pointer is valid.
+            unsafe { self.read_offset::<LittleEndianU8>(Self::ROW_SIZE * 0xD) }
+        } else {
+            0
+        };
+
+        Capabilities { device: self, next_offset }
+    }
+
+    fn find_capability(&self, id: u8) -> Option<usize> {
+        self.capabilities().find(|(capability_id, _)| *capability_id == id).map(|(_, offset)| offset)
     }
 }
 
-impl Iterator for CapablitiesIterator {
-    type Item = (u8, *mut LittleEndianU32);
+/// Composes the message address and data which deliver `vector` to the local APIC with ID `apic_id`, as an
+/// edge-triggered interrupt.
+#[cfg(target_arch = "x86_64")]
+fn compose_message(apic_id: u32, vector: u8, delivery_mode: InterruptDeliveryMode) -> (u64, u32) {
+    assert!(vector >= 0x20);
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.next_offset > 0 {
-            unsafe {
-                use bit_field::BitField;
+    let address = u64::try_from(apic::xAPIC_BASE_ADDR).unwrap() | (u64::from(apic_id) << 12);
 
-                let capability_base_ptr =
-                    (self.base_config_address + (self.next_offset as usize)) as *mut LittleEndianU32;
-                let capability_reg0 = capability_base_ptr.read_volatile().get();
-                self.next_offset = capability_reg0.get_bits(8..16) as u8;
+    let mut data = 0u32;
+    data.set_bits(0..8, u32::from(vector));
+    data.set_bits(8..11, delivery_mode as u32);
 
-                Some((capability_reg0.get_bits(0..8) as u8, capability_base_ptr))
-            }
-        } else {
-            None
-        }
-    }
+    (address, data)
 }
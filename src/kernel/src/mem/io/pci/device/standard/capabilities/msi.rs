@@ -1,47 +1,90 @@
-#[repr(u32)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
-#[allow(non_camel_case_types)]
-pub enum MultipleMessage {
-    x1 = 0b000,
-    x2 = 0b001,
-    x4 = 0b010,
-    x8 = 0b011,
-    x16 = 0b100,
-    x32 = 0b101,
+use crate::{
+    interrupts::InterruptDeliveryMode,
+    mem::io::pci::{Device, Standard},
+};
+use bit_field::BitField;
+use libkernel::{LittleEndianU16, LittleEndianU32};
+
+/// The MSI capability of a device, configured to use a single message.
+pub struct Msi<'a> {
+    device: &'a mut Device<Standard>,
+    offset: usize,
+}
+
+impl Device<Standard> {
+    pub fn get_msi(&mut self) -> Option<Msi> {
+        let offset = self.find_capability(Msi::ID)?;
+
+        Some(Msi { device: self, offset })
+    }
 }
 
-#[repr(C)]
-pub struct MessageControl(VolatileCell<u32, ReadWrite>);
+impl Msi<'_> {
+    const ID: u8 = 0x05;
+
+    fn get_control(&self) -> u16 {
+        // Safety: Offset is that of the MSI capability, so the message control register follows it.
+        unsafe { self.device.read_offset::<LittleEndianU16>(self.offset + 2) }
+    }
 
-impl MessageControl {
-    pub fn get_msi_enable(&self) -> bool {
-        self.0.read().get_bit(0)
+    fn set_control(&mut self, control: u16) {
+        // Safety: See `Self::get_control()`.
+        unsafe { self.device.write_offset::<LittleEndianU16>(self.offset + 2, control) };
     }
 
-    pub fn set_msi_enable(&self, enable: bool) {
-        self.0.write(self.0.read().set_bit(1, enable));
+    fn is_64bit(&self) -> bool {
+        self.get_control().get_bit(7)
     }
 
-    pub fn get_multi_msg_capable(&self) -> MultipleMessage {
-        MultipleMessage::try_from_primitive(self.0.read().get_bits(1..4)).unwrap()
+    fn data_offset(&self) -> usize {
+        self.offset + if self.is_64bit() { 0xC } else { 0x8 }
     }
 
-    pub fn try_set_multi_msg_enable(&self, mme: MultipleMessage) -> Result<(), ()> {
-        self.0.write(self.0.read().set_bits(4..7, mme as u32));
+    pub fn get_enable(&self) -> bool {
+        self.get_control().get_bit(0)
     }
 
-    pub fn get_long_mode_capable(&self) -> bool {
-        self.0.read().get_bit(7)
+    pub fn set_enable(&mut self, enable: bool) {
+        let mut control = self.get_control();
+        control.set_bit(0, enable);
+        self.set_control(control);
     }
 
-    pub fn get_per_vector_masking(&self) -> bool {
-        self.0.read().get_bit(8)
+    pub fn has_per_vector_masking(&self) -> bool {
+        self.get_control().get_bit(8)
     }
 
-    pub fn get_table_len(&self) -> usize {
-        self.0.read().get_bits(16..27) as usize
+    /// Masks or unmasks the message. This has no effect if the device doesn't support per-vector masking.
+    pub fn set_masked(&mut self, masked: bool) {
+        if self.has_per_vector_masking() {
+            let mask_offset = self.data_offset() + 4;
+            // Safety: Device supports per-vector masking, so the mask register follows the data register.
+            unsafe {
+                let mut mask = self.device.read_offset::<LittleEndianU32>(mask_offset);
+                mask.set_bit(0, masked);
+                self.device.write_offset::<LittleEndianU32>(mask_offset, mask);
+            }
+        }
     }
 
-    volatile_bitfield_getter!(0, force_mask, 30);
-    volatile_bitfield_getter!(0, enable, 31);
-}
\ No newline at end of file
+    #[cfg(target_arch = "x86_64")]
+    pub fn configure(&mut self, apic_id: u32, vector: u8, delivery_mode: InterruptDeliveryMode) {
+        let (address, data) = super::compose_message(apic_id, vector, delivery_mode);
+
+        // Only a single message is used, so clear the multiple message enable field.
+        let mut control = self.get_control();
+        control.set_bits(4..7, 0);
+        self.set_control(control);
+
+        // Safety: Offsets are those of the message address & data registers.
+        unsafe {
+            self.device.write_offset::<LittleEndianU32>(self.offset + 4, address.get_bits(0..32).try_into().unwrap());
+            if self.is_64bit() {
+                self.device
+                    .write_offset::<LittleEndianU32>(self.offset + 8, address.get_bits(32..64).try_into().unwrap());
+            }
+
+            self.device.write_offset::<LittleEndianU16>(self.data_offset(), data.get_bits(0..16).try_into().unwrap());
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use crate::mem::io::pci::{Device, Standard};
+use libkernel::{LittleEndianU32, LittleEndianU8};
+
+/// A vendor-specific capability of a device, whose layout (after its header) is defined by the device's vendor or
+/// specification.
+pub struct VendorCapability<'a> {
+    device: &'a Device<Standard>,
+    offset: usize,
+}
+
+impl Device<Standard> {
+    /// Iterates the vendor-specific capabilities of the device.
+    pub fn vendor_capabilities(&self) -> impl Iterator<Item = VendorCapability<'_>> {
+        self.capabilities()
+            .filter(|(id, _)| *id == VendorCapability::ID)
+            .map(move |(_, offset)| VendorCapability { device: self, offset })
+    }
+}
+
+impl VendorCapability<'_> {
+    const ID: u8 = 0x09;
+
+    /// Length of the capability in bytes, including its header.
+    pub fn len(&self) -> usize {
+        // Safety: Offset is that of the capability, so its length follows the ID and next pointer.
+        usize::from(unsafe { self.device.read_offset::<LittleEndianU8>(self.offset + 2) })
+    }
+
+    /// Reads the byte at `offset` into the capability.
+    pub fn read_u8(&self, offset: usize) -> u8 {
+        assert!(offset < self.len(), "offset is out of bounds of the capability");
+
+        // Safety: Offset is within the capability.
+        unsafe { self.device.read_offset::<LittleEndianU8>(self.offset + offset) }
+    }
+
+    /// Reads the dword at `offset` into the capability.
+    pub fn read_u32(&self, offset: usize) -> u32 {
+        assert!((offset + 4) <= self.len(), "offset is out of bounds of the capability");
+
+        // Safety: Offset is within the capability.
+        unsafe { self.device.read_offset::<LittleEndianU32>(self.offset + offset) }
+    }
+}
@@ -1,10 +1,28 @@
-// mod capabilities;
-// pub use capabilities::*;
+mod capabilities;
+pub use capabilities::*;
 
-use crate::mem::io::pci::{Device, Standard};
+use crate::mem::io::pci::{Bar, Device, Kind, Standard};
 use libkernel::{LittleEndianU16, LittleEndianU32, LittleEndianU8};
 
 impl Device<Standard> {
+    /// Reads every base address register. The upper half of a 64-bit BAR, and unused BARs, are `None`.
+    pub fn get_bars(&mut self) -> [Option<Bar>; Standard::REGISTER_COUNT] {
+        let mut bars = [None; Standard::REGISTER_COUNT];
+
+        let mut index = 0;
+        while index < Standard::REGISTER_COUNT {
+            let bar = self.get_bar(index).ok();
+            bars[index] = bar.filter(|bar| !bar.is_unused());
+
+            index += match bar {
+                Some(Bar::MemorySpace64 { .. }) => 2,
+                _ => 1,
+            };
+        }
+
+        bars
+    }
+
     pub fn cardbus_cis_ptr(&self) -> Option<usize> {
         match unsafe { self.read_offset::<LittleEndianU32>(Self::ROW_SIZE * 0xA) } {
             0x0 => None,
@@ -27,35 +45,6 @@ impl Device<Standard> {
         }
     }
 
-    // pub(self) fn capabilities(&self) -> CapablitiesIterator {
-    //     CapablitiesIterator::new(&self.mmio, unsafe { (self.mmio.read::<u8>(0x34).assume_init() & !0b11) as usize })
-    // }
-
-    // pub fn get_capability<T: capabilities::Capability>(&self) -> Option<T> {
-    //     let initial_capability_offset = unsafe { self.read_offset::<LittleEndianU8>(Self::ROW_SIZE * 0xD) };
-    //     let capabilities_iterator = CapablitiesIterator::new(self);
-
-    //     for (capability_type, capability_base_ptr) in capabilities_iterator {
-    //         if capability_type == T::TYPE_CODE {
-    //             return Some(unsafe {
-    //                 T::from_base_ptr(
-    //                     capability_base_ptr,
-    //                     [
-    //                         self.get_bar(0),
-    //                         self.get_bar(1),
-    //                         self.get_bar(2),
-    //                         self.get_bar(3),
-    //                         self.get_bar(4),
-    //                         self.get_bar(5),
-    //                     ],
-    //                 )
-    //             });
-    //         }
-    //     }
-
-    //     None
-    // }
-
     pub fn interrupt_line(&self) -> Option<u8> {
         match unsafe { self.read_offset::<LittleEndianU8>(Self::ROW_SIZE * 0xF) } {
             0xFF => None,
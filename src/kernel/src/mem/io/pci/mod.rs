@@ -14,12 +14,71 @@ crate::error_impl! {
     pub enum Error {
         NoninitTables => None,
         AcpiError { err: acpi::AcpiError } => None,
-        Paging { err: paging::Error } => Some(err)
+        Paging { err: paging::Error } => Some(err),
+
+        /// Indicates no device exists with the provided index.
+        UnknownDevice { index: usize } => None,
+
+        /// Indicates the device has already been claimed by a process.
+        AlreadyClaimed { index: usize, owner: Uuid } => None
     }
 }
 
 static PCI_DEVICES: Mutex<Vec<Device<Standard>>> = Mutex::new(Vec::new());
-static OWNED_DEVICES: Mutex<BTreeMap<Uuid, Device<Standard>>> = Mutex::new(BTreeMap::new());
+/// Indexes into `PCI_DEVICES` of claimed devices, mapped to the ID of the process that claimed them.
+static OWNED_DEVICES: Mutex<BTreeMap<usize, Uuid>> = Mutex::new(BTreeMap::new());
+
+/// Calls `func` with every known PCI device. A device's index in the slice identifies it for [`claim`].
+pub fn with_devices<T>(func: impl FnOnce(&[Device<Standard>]) -> T) -> T {
+    func(&PCI_DEVICES.lock())
+}
+
+/// Exclusive ownership of a PCI device by a process. The claim is released when this is dropped.
+#[derive(Debug)]
+pub struct Claim {
+    index: usize,
+    owner: Uuid,
+}
+
+impl Claim {
+    #[inline]
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+
+    #[inline]
+    pub const fn owner(&self) -> Uuid {
+        self.owner
+    }
+
+    pub fn with_device<T>(&self, func: impl FnOnce(&mut Device<Standard>) -> T) -> T {
+        func(&mut PCI_DEVICES.lock()[self.index])
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        trace!("Releasing PCI device claim: {:?}", self);
+
+        OWNED_DEVICES.lock().remove(&self.index);
+    }
+}
+
+/// Claims the device at `index` for the process `owner`.
+pub fn claim(index: usize, owner: Uuid) -> Result<Claim> {
+    if index >= PCI_DEVICES.lock().len() {
+        return Err(Error::UnknownDevice { index });
+    }
+
+    let mut owned_devices = OWNED_DEVICES.lock();
+    if let Some(owner) = owned_devices.get(&index).copied() {
+        return Err(Error::AlreadyClaimed { index, owner });
+    }
+
+    owned_devices.insert(index, owner);
+
+    Ok(Claim { index, owner })
+}
 
 pub fn get_device_base_address(base: usize, bus_index: u8, device_index: u8) -> Address<Frame> {
     let bus_index = usize::from(bus_index);
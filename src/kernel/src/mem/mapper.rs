@@ -104,10 +104,11 @@ impl Mapper {
             unsafe { entry.set_attributes(paging::TableEntryFlags::PRESENT, paging::FlagsModify::Remove) };
 
             let frame = entry.get_frame();
+            let is_device = entry.get_attributes().contains(paging::TableEntryFlags::DEVICE);
             // Safety: See above.
             unsafe { entry.set_frame(Address::new_truncate(0)) };
 
-            if free_frame {
+            if free_frame && !is_device {
                 pmm::get().free_frame(frame).unwrap();
             }
 
@@ -117,9 +118,15 @@ impl Mapper {
         })
     }
 
+    /// Maps the specified page to a newly allocated (and zeroed) frame.
     pub fn auto_map(&mut self, page: Address<Page>, flags: paging::TableEntryFlags) -> Result<()> {
         match pmm::get().next_frame() {
-            Ok(frame) => self.map(page, TableDepth::min(), frame, false, flags),
+            Ok(frame) => {
+                // Safety: Frame was just allocated, so nothing else refers to it, and it is within the HHDM.
+                unsafe { core::ptr::write_bytes(HHDM.offset(frame).unwrap().as_ptr(), 0x0, libsys::page_size()) };
+
+                self.map(page, TableDepth::min(), frame, false, flags)
+            }
             Err(err) => {
                 trace!("Auto alloc pmm::get() error: {:?}", err);
                 Err(Error::AllocError)
@@ -127,10 +134,52 @@ impl Mapper {
         }
     }
 
+    /// Unmaps everything reachable through the root table entries in `root_indexes`, returning the mapped frames
+    /// and the intermediate page table frames to the physical memory manager.
+    ///
+    /// ### Safety
+    ///
+    /// - Caller must ensure nothing refers to memory mapped through the provided entries.
+    /// - The page tables referenced by the provided entries must be owned by this mapper, and not shared.
+    pub unsafe fn free_root_entries(&mut self, root_indexes: core::ops::Range<usize>) {
+        let entry_depth = self.depth.next();
+        let mut root_table = self.root_table_mut();
+        for entry in root_table.entries_mut()[root_indexes].iter_mut().filter(|entry| entry.is_present()) {
+            Self::free_entry(entry, entry_depth);
+        }
+    }
+
+    /// ### Safety
+    ///
+    /// `entry` must be a present entry at `depth`, and must own the frame(s) it points to.
+    unsafe fn free_entry(entry: &mut paging::PageTableEntry, depth: TableDepth) {
+        let frame = entry.get_frame();
+
+        if depth.is_min() || entry.is_huge() {
+            // Entry maps memory directly, so free every frame it spans. Device memory isn't owned by the page tables.
+            if !entry.get_attributes().contains(paging::TableEntryFlags::DEVICE) {
+                for offset in (0..depth.align()).step_by(libsys::page_size()) {
+                    pmm::get().free_frame(Address::new_truncate(frame.get().get() + offset)).ok();
+                }
+            }
+        } else {
+            {
+                let mut table = paging::PageTable::<Mut>::new(depth, entry);
+                for sub_entry in table.entries_mut().iter_mut().filter(|sub_entry| sub_entry.is_present()) {
+                    Self::free_entry(sub_entry, depth.next());
+                }
+            }
+
+            pmm::get().free_frame(frame).ok();
+        }
+
+        *entry = paging::PageTableEntry::empty();
+    }
+
     /* STATE QUERYING */
 
     pub fn is_mapped(&self, page: Address<Page>, depth: Option<TableDepth>) -> bool {
-        self.root_table().with_entry(page, depth, |_| ()).is_ok()
+        self.root_table().with_entry(page, depth, |entry| entry.is_present()).unwrap_or(false)
     }
 
     pub fn is_mapped_to(&self, page: Address<Page>, frame: Address<Frame>) -> bool {
@@ -138,7 +187,7 @@ impl Mapper {
     }
 
     pub fn get_mapped_to(&self, page: Address<Page>) -> Option<Address<Frame>> {
-        self.root_table().with_entry(page, None, |entry| entry.get_frame()).ok()
+        self.root_table().with_entry(page, None, |entry| entry.is_present().then(|| entry.get_frame())).ok().flatten()
     }
 
     /* STATE CHANGING */
@@ -2,6 +2,7 @@ mod hhdm;
 pub use hhdm::*;
 
 pub mod alloc;
+pub mod dma;
 pub mod io;
 pub mod mapper;
 pub mod paging;
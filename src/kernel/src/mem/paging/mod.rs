@@ -136,6 +136,13 @@ bitflags::bitflags! {
         const HUGE = 1 << 7;
         const GLOBAL = 1 << 8;
         const DEMAND = 1 << 9;
+        /// Marks memory which isn't owned by the page tables (e.g. device MMIO), so its frames are never freed.
+        const DEVICE = 1 << 10;
+        /// Marks memory which must stay at its physical address (e.g. DMA buffers), so it can't change owners.
+        const PINNED = 1 << 11;
+        /// Marks a page which is kept unmapped (e.g. below a thread's stack), so accessing it faults, and it's never
+        /// handed out as free memory.
+        const GUARD = 1 << 52;
         const NO_EXECUTE = 1 << 63;
 
         const RO = Self::PRESENT.bits() | Self::NO_EXECUTE.bits();
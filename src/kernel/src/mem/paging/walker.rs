@@ -17,42 +17,51 @@ impl<'a> Walker<'a> {
         (depth >= target_depth).then_some(Self { root_table: table, root_depth: depth, target_depth })
     }
 
-    pub fn walk<E>(&self, mut func: impl FnMut(Option<&PageTableEntry>) -> ControlFlow<E>) -> ControlFlow<E> {
+    /// Walks the entries at the target depth, in order, beginning with the one at `start_index`.
+    ///
+    /// `func` is passed the index of the first target-depth entry it covers, the entry itself, and the number of
+    /// target-depth entries it covers. Tables which aren't present are passed once as `None`, and huge pages once as
+    /// their own entry, each covering every target-depth entry within them, so they can be skipped in bulk.
+    pub fn walk<E>(
+        &self,
+        start_index: usize,
+        mut func: impl FnMut(usize, Option<&PageTableEntry>, usize) -> ControlFlow<E>,
+    ) -> ControlFlow<E> {
         debug_assert!(self.root_depth > self.target_depth);
 
-        Self::walk_impl(self.root_table, self.root_depth, self.target_depth, &mut func)
+        Self::walk_impl(self.root_table, self.root_depth, self.target_depth, 0, start_index, &mut func)
     }
 
     fn walk_impl<E>(
         table: &[PageTableEntry],
         cur_depth: TableDepth,
         target_depth: TableDepth,
-        func: &mut impl FnMut(Option<&PageTableEntry>) -> ControlFlow<E>,
+        base_index: usize,
+        start_index: usize,
+        func: &mut impl FnMut(usize, Option<&PageTableEntry>, usize) -> ControlFlow<E>,
     ) -> ControlFlow<E> {
-        use core::cmp::Ordering;
-
-        match cur_depth.cmp(&target_depth) {
-            Ordering::Equal => table.iter().try_for_each(|entry| func(Some(entry)))?,
-
-            Ordering::Greater => {
-                for entry in table {
-                    if entry.is_present() {
-                        let table_ptr = crate::mem::HHDM.offset(entry.get_frame()).unwrap().as_ptr().cast();
-                        let table_size = libsys::table_index_size();
-                        let table = unsafe { core::slice::from_raw_parts(table_ptr, table_size) };
-
-                        Self::walk_impl(table, cur_depth.next(), target_depth, func)?;
-                    } else {
-                        let steps = core::iter::Step::steps_between(&cur_depth, &target_depth).unwrap();
-                        let iterations = table_index_size().pow(steps.try_into().unwrap());
-                        (0..iterations).try_for_each(|_| func(None))?;
-                    }
-                }
-            }
+        // Number of target-depth entries covered by each entry of this table.
+        let entry_span = table_index_size().pow(cur_depth.get() - target_depth.get());
+        let first_entry = start_index.saturating_sub(base_index) / entry_span;
 
-            Ordering::Less => unreachable!(),
+        for (entry_index, entry) in table.iter().enumerate().skip(first_entry) {
+            let entry_base = base_index + (entry_index * entry_span);
+            let first_index = entry_base.max(start_index);
+            let span = (entry_base + entry_span) - first_index;
+
+            if cur_depth == target_depth || (entry.is_present() && entry.is_huge()) {
+                func(first_index, Some(entry), span)?;
+            } else if entry.is_present() {
+                let table_ptr = crate::mem::HHDM.offset(entry.get_frame()).unwrap().as_ptr().cast();
+                // Safety: Present non-huge entries above the target depth point to valid page tables.
+                let table = unsafe { core::slice::from_raw_parts(table_ptr, table_index_size()) };
+
+                Self::walk_impl(table, cur_depth.next(), target_depth, entry_base, first_index, func)?;
+            } else {
+                func(first_index, None, span)?;
+            }
         }
 
-        core::ops::ControlFlow::Continue(())
+        ControlFlow::Continue(())
     }
 }
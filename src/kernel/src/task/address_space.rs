@@ -3,8 +3,9 @@ use crate::mem::{
     paging,
     paging::{TableDepth, TableEntryFlags},
 };
+use alloc::vec::Vec;
 use core::{num::NonZeroUsize, ptr::NonNull};
-use libsys::{page_size, Address, Page, Virtual};
+use libsys::{page_size, Address, Frame, Page, Virtual};
 
 crate::error_impl! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -27,6 +28,12 @@ crate::error_impl! {
 
         NotMapped { addr: Address<Virtual> } => None,
 
+        /// Indicates the operation is not supported on device memory.
+        DeviceMemory { addr: Address<Virtual> } => None,
+
+        /// Indicates the operation is not supported on pinned memory.
+        PinnedMemory { addr: Address<Virtual> } => None,
+
         /// Provides the error that occured within the internal `Mapper`.
         Paging { err: paging::Error } => Some(err)
     }
@@ -60,6 +67,48 @@ impl From<MmapPermissions> for TableEntryFlags {
     }
 }
 
+impl From<libsys::syscall::mem::Permissions> for MmapPermissions {
+    fn from(permissions: libsys::syscall::mem::Permissions) -> Self {
+        use libsys::syscall::mem::Permissions;
+
+        match permissions {
+            Permissions::ReadExecute => MmapPermissions::ReadExecute,
+            Permissions::ReadWrite => MmapPermissions::ReadWrite,
+            Permissions::ReadOnly => MmapPermissions::ReadOnly,
+        }
+    }
+}
+
+/// Caching behaviour of a memory mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cacheability {
+    WriteBack,
+    WriteThrough,
+    Uncacheable,
+}
+
+impl From<Cacheability> for TableEntryFlags {
+    fn from(cacheability: Cacheability) -> Self {
+        match cacheability {
+            Cacheability::WriteBack => TableEntryFlags::empty(),
+            Cacheability::WriteThrough => TableEntryFlags::WRITE_THROUGH,
+            Cacheability::Uncacheable => TableEntryFlags::UNCACHEABLE,
+        }
+    }
+}
+
+impl From<libsys::syscall::mem::Cacheability> for Cacheability {
+    fn from(cacheability: libsys::syscall::mem::Cacheability) -> Self {
+        use libsys::syscall::mem::Cacheability as SysCacheability;
+
+        match cacheability {
+            SysCacheability::WriteBack => Cacheability::WriteBack,
+            SysCacheability::WriteThrough => Cacheability::WriteThrough,
+            SysCacheability::Uncacheable => Cacheability::Uncacheable,
+        }
+    }
+}
+
 pub const DEFAULT_USERSPACE_SIZE: NonZeroUsize = NonZeroUsize::new(1 << 47).unwrap();
 
 pub struct AddressSpace(Mapper);
@@ -92,45 +141,263 @@ impl AddressSpace {
         if let Some(address) = address {
             self.map_exact(address, page_count, permissions)
         } else {
-            self.map_any(page_count, permissions)
+            // Never hand out the null page.
+            self.map_any(Address::new_truncate(page_size()), page_count, permissions)
         }
     }
 
+    /// Maps the first free run of `page_count` pages which begins at or above `floor`.
     #[cfg_attr(debug_assertions, inline(never))]
-    fn map_any(&mut self, page_count: NonZeroUsize, permissions: MmapPermissions) -> Result<NonNull<[u8]>> {
-        let walker = unsafe {
-            paging::walker::Walker::new(self.0.view_page_table(), TableDepth::max(), TableDepth::min()).unwrap()
-        };
+    pub fn map_any(
+        &mut self,
+        floor: Address<Page>,
+        page_count: NonZeroUsize,
+        permissions: MmapPermissions,
+    ) -> Result<NonNull<[u8]>> {
+        let address = self.find_free(floor, page_count)?;
+        let flags = TableEntryFlags::PRESENT | TableEntryFlags::USER | TableEntryFlags::from(permissions);
 
-        let mut index = 0;
-        let mut run = 0;
-        walker.walk(|entry| {
-            use core::ops::ControlFlow;
+        // Safety: Page range is known to be free userspace memory.
+        unsafe { self.invoke_mapper(address, page_count, flags) }
+    }
+
+    /// Maps `page_count` read-write pages for a thread's stack to the first free run of pages which begins at or above
+    /// `floor`, leaving a guard page below them. Returns the stack's memory.
+    pub fn map_stack(&mut self, floor: Address<Page>, page_count: NonZeroUsize) -> Result<NonNull<[u8]>> {
+        let guard_page = self.find_free(floor, page_count.checked_add(1).unwrap())?;
+        let address = Address::<Page>::from_index(guard_page.index() + 1).unwrap();
+        let flags =
+            TableEntryFlags::PRESENT | TableEntryFlags::USER | TableEntryFlags::from(MmapPermissions::ReadWrite);
+
+        self.map_guard(guard_page)?;
+        // Safety: Page range is known to be free userspace memory.
+        unsafe { self.invoke_mapper(address, page_count, flags) }
+    }
 
-            if entry.is_none() {
-                run += 1;
+    /// Reserves `page` as a guard page, which stays unmapped (so accessing it faults), but is never handed out as
+    /// free memory.
+    pub fn map_guard(&mut self, page: Address<Page>) -> Result<()> {
+        Self::check_userspace_range(page, NonZeroUsize::MIN)?;
+
+        self.0
+            .map(page, TableDepth::min(), Address::new_truncate(0), false, TableEntryFlags::GUARD)
+            .map_err(Error::from)
+    }
 
-                if run == page_count.get() {
-                    return ControlFlow::Break(());
+    /// Unmaps a stack mapped by [`Self::map_stack`] (or below which [`Self::map_guard`] reserved a guard page),
+    /// returning its frames to the physical memory manager, and releases its guard page.
+    ///
+    /// ### Safety
+    ///
+    /// Caller must ensure no references remain to the stack.
+    pub unsafe fn unmap_stack(&mut self, address: Address<Page>, page_count: NonZeroUsize) -> Result<()> {
+        // Safety: Caller is required to ensure unmapping the stack is valid.
+        unsafe { self.munmap(address, page_count) }?;
+
+        let guard_page = Address::<Page>::from_index(address.index() - 1).unwrap();
+        // Safety: Guard pages aren't mapped, so clearing one invalidates no references.
+        unsafe { self.0.set_page_attributes(guard_page, None, TableEntryFlags::empty(), paging::FlagsModify::Set) }
+            .map_err(Error::from)
+    }
+
+    /// Maps the provided frames to the first free run of pages which begins at or above `floor`, taking ownership of
+    /// the frames. Returns the address of the first page.
+    pub fn map_frames(
+        &mut self,
+        floor: Address<Page>,
+        frames: &[Address<Frame>],
+        permissions: MmapPermissions,
+    ) -> Result<Address<Page>> {
+        let page_count = NonZeroUsize::new(frames.len()).ok_or(Error::InvalidAddress)?;
+        let address = self.find_free(floor, page_count)?;
+        let flags = TableEntryFlags::PRESENT | TableEntryFlags::USER | TableEntryFlags::from(permissions);
+
+        for (mapped_count, (page, frame)) in Self::pages(address, page_count).zip(frames).enumerate() {
+            if let Err(err) = self.0.map(page, TableDepth::min(), *frame, false, flags) {
+                // The frames are still owned by the caller, so they're unmapped without being freed.
+                for page in Self::pages(address, page_count).take(mapped_count) {
+                    // Safety: Pages were only just mapped, so nothing references them.
+                    unsafe { self.0.unmap(page, None, false) }.ok();
                 }
-            } else {
-                run = 0;
+
+                return Err(Error::from(err));
             }
+        }
 
-            index += 1;
+        Ok(address)
+    }
 
-            ControlFlow::Continue(())
-        });
+    /// Maps `page_count` frames of device memory, starting at `frame`, to the first free run of pages which begins at
+    /// or above `floor`. Returns the address of the first page.
+    ///
+    /// Device memory is mapped uncacheable, and its frames are never returned to the physical memory manager.
+    ///
+    /// ### Safety
+    ///
+    /// Caller must ensure the frames are device memory, and that userspace is permitted to access them.
+    pub unsafe fn map_device(
+        &mut self,
+        floor: Address<Page>,
+        frame: Address<Frame>,
+        page_count: NonZeroUsize,
+    ) -> Result<Address<Page>> {
+        let address = self.find_free(floor, page_count)?;
+        let flags = TableEntryFlags::MMIO | TableEntryFlags::USER | TableEntryFlags::DEVICE;
+
+        let frames = (frame.index()..(frame.index() + page_count.get())).filter_map(Address::<Frame>::from_index);
+        for (page, frame) in Self::pages(address, page_count).zip(frames) {
+            self.0.map(page, TableDepth::min(), frame, false, flags)?;
+        }
+
+        Ok(address)
+    }
+
+    /// Maps `memory` to the first free run of pages which begins at or above `floor`. Returns the address of the first
+    /// page.
+    ///
+    /// The address space doesn't own shared memory, so its frames are never returned to the physical memory manager.
+    /// Caller must keep `memory` alive for as long as it's mapped.
+    pub fn map_shared(
+        &mut self,
+        floor: Address<Page>,
+        memory: &super::shm::SharedMemory,
+        permissions: MmapPermissions,
+    ) -> Result<Address<Page>> {
+        let page_count = memory.page_count();
+        let address = self.find_free(floor, page_count)?;
+        // Shared memory is marked as device memory, as neither is owned by the page tables, and neither may change
+        // owners or permissions.
+        let flags = TableEntryFlags::PRESENT
+            | TableEntryFlags::USER
+            | TableEntryFlags::DEVICE
+            | TableEntryFlags::PINNED
+            | TableEntryFlags::from(permissions);
+
+        for (mapped_count, (page, frame)) in Self::pages(address, page_count).zip(memory.frames()).enumerate() {
+            if let Err(err) = self.0.map(page, TableDepth::min(), frame, false, flags) {
+                for page in Self::pages(address, page_count).take(mapped_count) {
+                    // Safety: Pages were only just mapped, so nothing references them.
+                    unsafe { self.0.unmap(page, None, false) }.ok();
+                }
+
+                return Err(Error::from(err));
+            }
+        }
+
+        Ok(address)
+    }
+
+    /// Allocates `page_count` zeroed, physically contiguous frames and maps them read-write to the first free run of
+    /// pages which begins at or above `floor`. If `limit` is provided, every frame lies below it. Returns the address
+    /// of the first page, and the first frame.
+    ///
+    /// The memory is pinned, so its frames stay put until it's unmapped (which frees them) for devices to access.
+    pub fn map_dma(
+        &mut self,
+        floor: Address<Page>,
+        page_count: NonZeroUsize,
+        limit: Option<Address<Frame>>,
+        cacheability: Cacheability,
+    ) -> Result<(Address<Page>, Address<Frame>)> {
+        let address = self.find_free(floor, page_count)?;
+        let frame =
+            crate::mem::alloc::pmm::get().next_frames_below(page_count, None, limit).map_err(|_| Error::AllocError)?;
+        let frames = (frame.index()..(frame.index() + page_count.get())).filter_map(Address::<Frame>::from_index);
+
+        // Safety: Frames were only just allocated, and are mapped within the HHDM.
+        unsafe {
+            core::ptr::write_bytes(crate::mem::HHDM.offset(frame).unwrap().as_ptr(), 0, page_count.get() * page_size());
+        }
+
+        let flags =
+            TableEntryFlags::RW | TableEntryFlags::USER | TableEntryFlags::PINNED | TableEntryFlags::from(cacheability);
+        for (mapped_count, (page, frame)) in Self::pages(address, page_count).zip(frames.clone()).enumerate() {
+            if let Err(err) = self.0.map(page, TableDepth::min(), frame, false, flags) {
+                // Unmapping the mapped pages frees their frames, so only the remaining frames are freed directly.
+                for page in Self::pages(address, page_count).take(mapped_count) {
+                    // Safety: Pages were only just mapped, so nothing references them.
+                    unsafe { self.0.unmap(page, None, true) }.ok();
+                }
+                for frame in frames.skip(mapped_count) {
+                    crate::mem::alloc::pmm::get().free_frame(frame).ok();
+                }
+
+                return Err(Error::from(err));
+            }
+        }
+
+        Ok((address, frame))
+    }
+
+    /// Unmaps the given page range without freeing the backing frames, returning them to the caller instead.
+    ///
+    /// ### Safety
+    ///
+    /// Caller must ensure no references remain to the memory being unmapped.
+    pub unsafe fn take_frames(
+        &mut self,
+        address: Address<Page>,
+        page_count: NonZeroUsize,
+    ) -> Result<Vec<Address<Frame>>> {
+        Self::check_userspace_range(address, page_count)?;
+
+        // Device memory can't change owners.
+        if let Some(page) = Self::pages(address, page_count).find(|page| self.is_device(*page)) {
+            return Err(Error::DeviceMemory { addr: page.get() });
+        }
+
+        // Pinned memory may still be in use by a device, which the new owner wouldn't know about.
+        if let Some(page) = Self::pages(address, page_count).find(|page| self.is_pinned(*page)) {
+            return Err(Error::PinnedMemory { addr: page.get() });
+        }
 
-        match run.cmp(&page_count.get()) {
-            core::cmp::Ordering::Equal => {
-                let address = Address::<Page>::new(index << libsys::page_shift().get()).unwrap();
-                let flags = TableEntryFlags::PRESENT | TableEntryFlags::USER | TableEntryFlags::from(permissions);
+        let frames = Self::pages(address, page_count)
+            .map(|page| self.get_mapped_to(page).ok_or(Error::NotMapped { addr: page.get() }))
+            .collect::<Result<Vec<_>>>()?;
 
-                unsafe { self.invoke_mapper(address, page_count, flags) }
+        Self::pages(address, page_count).try_for_each(|page| {
+            // Safety: Caller is required to ensure unmapping the page is valid.
+            unsafe { self.0.unmap(page, None, false) }.map_err(Error::from)
+        })?;
+
+        Ok(frames)
+    }
+
+    /// Finds the first free run of `page_count` pages which begins at or above `floor`.
+    fn find_free(&self, floor: Address<Page>, page_count: NonZeroUsize) -> Result<Address<Page>> {
+        // Safety: The root page table of the mapper is always valid.
+        let walker = unsafe {
+            paging::walker::Walker::new(self.0.view_page_table(), TableDepth::max(), TableDepth::new(1).unwrap())
+                .unwrap()
+        };
+
+        let end_index = DEFAULT_USERSPACE_SIZE.get() >> libsys::page_shift().get();
+
+        // First page, and length, of the current run of free pages.
+        let mut run = (floor.index(), 0);
+        walker.walk(floor.index(), |index, entry, span| {
+            use core::ops::ControlFlow;
+
+            // Guard pages aren't present, but they aren't free either.
+            if entry.is_some_and(|entry| entry.is_present() || entry.get_attributes().contains(TableEntryFlags::GUARD))
+            {
+                run = (index + span, 0);
+            } else {
+                run.1 += span;
             }
-            core::cmp::Ordering::Less => Err(Error::AllocError),
-            core::cmp::Ordering::Greater => unreachable!(),
+
+            if run.1 >= page_count.get() || (index + span) >= end_index {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+
+        let (first_index, run_len) = run;
+        if run_len >= page_count.get() && (first_index + page_count.get()) <= end_index {
+            Ok(Address::<Page>::from_index(first_index).unwrap())
+        } else {
+            Err(Error::AllocError)
         }
     }
 
@@ -141,6 +408,13 @@ impl AddressSpace {
         page_count: NonZeroUsize,
         permissions: MmapPermissions,
     ) -> Result<NonNull<[u8]>> {
+        Self::check_userspace_range(address, page_count)?;
+
+        if Self::pages(address, page_count).any(|page| self.is_mmapped(page)) {
+            return Err(Error::OverlappingAddress);
+        }
+
+        // Safety: Page range is checked to be userspace memory, and to not overlap any existing mappings.
         unsafe {
             self.invoke_mapper(
                 address,
@@ -169,6 +443,76 @@ impl AddressSpace {
         Ok(NonNull::slice_from_raw_parts(NonNull::new(address.as_ptr()).unwrap(), mapping_size))
     }
 
+    /// Unmaps the given page range, returning the backing frames to the physical memory manager.
+    ///
+    /// ### Safety
+    ///
+    /// Caller must ensure no references remain to the memory being unmapped.
+    pub unsafe fn munmap(&mut self, address: Address<Page>, page_count: NonZeroUsize) -> Result<()> {
+        Self::check_userspace_range(address, page_count)?;
+
+        if let Some(page) = Self::pages(address, page_count).find(|page| !self.is_mmapped(*page)) {
+            return Err(Error::NotMapped { addr: page.get() });
+        }
+
+        Self::pages(address, page_count).try_for_each(|page| {
+            // Safety: Caller is required to ensure unmapping the page is valid.
+            unsafe { self.0.unmap(page, None, true) }.map_err(Error::from)
+        })
+    }
+
+    /// Changes the access permissions of the given (already mapped) page range.
+    ///
+    /// ### Safety
+    ///
+    /// Caller must ensure no references to the memory are invalidated by the permission change.
+    pub unsafe fn mprotect(
+        &mut self,
+        address: Address<Page>,
+        page_count: NonZeroUsize,
+        permissions: MmapPermissions,
+    ) -> Result<()> {
+        Self::check_userspace_range(address, page_count)?;
+
+        if let Some(page) = Self::pages(address, page_count).find(|page| !self.is_mmapped(*page)) {
+            return Err(Error::NotMapped { addr: page.get() });
+        }
+
+        // Changing the flags of device memory would lose its `DEVICE` flag, so it would be freed when unmapped.
+        if let Some(page) = Self::pages(address, page_count).find(|page| self.is_device(*page)) {
+            return Err(Error::DeviceMemory { addr: page.get() });
+        }
+
+        // Likewise, pinned memory would lose its `PINNED` flag and its cacheability.
+        if let Some(page) = Self::pages(address, page_count).find(|page| self.is_pinned(*page)) {
+            return Err(Error::PinnedMemory { addr: page.get() });
+        }
+
+        self.set_flags(
+            address,
+            page_count,
+            TableEntryFlags::PRESENT | TableEntryFlags::USER | TableEntryFlags::from(permissions),
+        )
+    }
+
+    fn check_userspace_range(address: Address<Page>, page_count: NonZeroUsize) -> Result<()> {
+        let end_address = page_count
+            .get()
+            .checked_mul(page_size())
+            .and_then(|mapping_size| address.get().get().checked_add(mapping_size))
+            .ok_or(Error::InvalidAddress)?;
+
+        if end_address > DEFAULT_USERSPACE_SIZE.get() {
+            Err(Error::AddressOverrun { value: end_address })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn pages(address: Address<Page>, page_count: NonZeroUsize) -> impl Iterator<Item = Address<Page>> {
+        (address.index()..(address.index() + page_count.get())).filter_map(Address::from_index)
+    }
+
     pub unsafe fn set_flags(
         &mut self,
         address: Address<Page>,
@@ -196,6 +540,64 @@ impl AddressSpace {
         self.0.is_mapped(address, None)
     }
 
+    fn is_device(&self, address: Address<Page>) -> bool {
+        self.get_flags(address).is_ok_and(|flags| flags.contains(TableEntryFlags::DEVICE))
+    }
+
+    fn is_pinned(&self, address: Address<Page>) -> bool {
+        self.get_flags(address).is_ok_and(|flags| flags.contains(TableEntryFlags::PINNED))
+    }
+
+    pub fn get_mapped_to(&self, address: Address<Page>) -> Option<Address<Frame>> {
+        self.0.get_mapped_to(address)
+    }
+
+    /// Copies bytes from this address space at `address` into `buffer`, through the HHDM. This allows reading from an
+    /// address space that is not currently active.
+    pub fn read_bytes(&self, address: Address<Virtual>, buffer: &mut [u8]) -> Result<()> {
+        let mut offset = 0;
+        while offset < buffer.len() {
+            let virt = address.get() + offset;
+            let page = Address::<Page>::new_truncate(virt);
+            let frame = self.0.get_mapped_to(page).ok_or(Error::NotMapped { addr: page.get() })?;
+            let page_offset = virt & libsys::page_mask();
+            let copy_len = (page_size() - page_offset).min(buffer.len() - offset);
+
+            // Safety: Frame is mapped within the HHDM, and the copy is bounded by the end of the page.
+            unsafe {
+                let src = crate::mem::HHDM.offset(frame).unwrap().as_ptr().add(page_offset);
+                core::ptr::copy_nonoverlapping(src, buffer.as_mut_ptr().add(offset), copy_len);
+            }
+
+            offset += copy_len;
+        }
+
+        Ok(())
+    }
+
+    /// Copies `bytes` into this address space at `address`, through the HHDM. This allows writing to an address
+    /// space that is not currently active.
+    pub fn write_bytes(&mut self, address: Address<Virtual>, bytes: &[u8]) -> Result<()> {
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let virt = address.get() + offset;
+            let page = Address::<Page>::new_truncate(virt);
+            let frame = self.0.get_mapped_to(page).ok_or(Error::NotMapped { addr: page.get() })?;
+            let page_offset = virt & libsys::page_mask();
+            let copy_len = (page_size() - page_offset).min(bytes.len() - offset);
+
+            // Safety: Frame is mapped within the HHDM, and the copy is bounded by the end of the page.
+            unsafe {
+                let dst = crate::mem::HHDM.offset(frame).unwrap().as_ptr().add(page_offset);
+                core::ptr::copy_nonoverlapping(bytes.as_ptr().add(offset), dst, copy_len);
+            }
+
+            offset += copy_len;
+        }
+
+        Ok(())
+    }
+
     /// ### Safety
     ///
     /// Caller must ensure that switching the currently active address space will not cause undefined behaviour.
@@ -204,6 +606,21 @@ impl AddressSpace {
     }
 }
 
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        debug_assert!(!self.is_current(), "cannot drop the active address space");
+
+        let userspace_end = Address::new(DEFAULT_USERSPACE_SIZE.get() - 1).unwrap();
+        let root_index_end = TableDepth::max().index_of(userspace_end).unwrap() + 1;
+
+        // Safety: The address space is not active, and only the userspace half of the page tables is freed. The
+        //         kernel half is shared with every other address space, so it is left intact.
+        unsafe { self.0.free_root_entries(0..root_index_end) };
+
+        crate::mem::alloc::pmm::get().free_frame(self.0.root_frame()).ok();
+    }
+}
+
 impl core::fmt::Debug for AddressSpace {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_tuple("AddressSpace").field(&self.0.view_page_table().as_ptr()).finish()
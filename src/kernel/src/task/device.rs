@@ -0,0 +1,47 @@
+use crate::{mem::io::pci, task::Process};
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::num::NonZeroUsize;
+use libsys::{Address, Page};
+
+/// A PCI device claimed by a process, along with the mappings of its configuration space and memory BARs into the
+/// process's address space.
+///
+/// The mappings are undone once this is dropped (when the last handle to the device is closed), before the claim can
+/// be released, so a process can never reach a device that has since been claimed by another.
+pub struct DeviceClaim {
+    claim: Arc<pci::Claim>,
+    process: Weak<Process>,
+    mappings: Vec<(Address<Page>, NonZeroUsize)>,
+}
+
+impl DeviceClaim {
+    pub fn new(claim: pci::Claim, process: &Arc<Process>, mappings: Vec<(Address<Page>, NonZeroUsize)>) -> Self {
+        Self { claim: Arc::new(claim), process: Arc::downgrade(process), mappings }
+    }
+
+    #[inline]
+    pub const fn claim(&self) -> &Arc<pci::Claim> {
+        &self.claim
+    }
+}
+
+impl Drop for DeviceClaim {
+    fn drop(&mut self) {
+        // If the process has been dropped, so have its mappings.
+        let Some(process) = self.process.upgrade() else { return };
+        let mut address_space = process.address_space();
+
+        // Pages are unmapped individually, as the MSI-X table may have left holes in the mappings.
+        for (address, page_count) in self.mappings.drain(..) {
+            let pages =
+                (0..page_count.get()).filter_map(|offset| Address::<Page>::from_index(address.index() + offset));
+            for page in pages {
+                // Safety: Device memory is never referenced by the kernel, and the device's frames aren't freed.
+                unsafe { address_space.munmap(page, NonZeroUsize::MIN) }.ok();
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::task::{Registers, Scheduler, State, Task, WaitQueue};
+use alloc::collections::BTreeMap;
+use libsys::{Address, Page, Virtual};
+
+crate::error_impl! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Error {
+        /// Indicates the futex address was not aligned to its size.
+        Misaligned { addr: Address<Virtual> } => None,
+
+        /// Indicates the futex address is not mapped in the task's address space.
+        NotMapped { addr: Address<Virtual> } => None,
+
+        /// Indicates the futex did not hold the expected value, so the task was not blocked.
+        WouldBlock => None
+    }
+}
+
+/// Futex wait queues, keyed by the physical address of the futex word. Keying by physical address allows tasks
+/// sharing memory to synchronize, even if it is mapped at different addresses.
+static FUTEXES: spin::Mutex<BTreeMap<usize, WaitQueue>> = spin::Mutex::new(BTreeMap::new());
+
+/// Resolves the physical address of the futex word at `address` in `task`'s address space, demand mapping it if
+/// required.
+fn physical_address(task: &Task, address: Address<Virtual>) -> Result<usize> {
+    if (address.get() % core::mem::align_of::<u32>()) != 0 {
+        return Err(Error::Misaligned { addr: address });
+    }
+
+    match task.demand_map(address) {
+        Ok(()) | Err(super::Error::AlreadyMapped) => {}
+        Err(_) => return Err(Error::NotMapped { addr: address }),
+    }
+
+    let page = Address::<Page>::new_truncate(address.get());
+    let frame = task.address_space().get_mapped_to(page).ok_or(Error::NotMapped { addr: address })?;
+
+    Ok(frame.get().get() + (address.get() & libsys::page_mask()))
+}
+
+fn read_futex(physical_address: usize) -> u32 {
+    // Safety: Physical address is known to be mapped in the HHDM, and is aligned for a `u32`.
+    unsafe {
+        crate::mem::HHDM
+            .ptr()
+            .add(physical_address)
+            .cast::<core::sync::atomic::AtomicU32>()
+            .as_ref()
+            .unwrap()
+            .load(core::sync::atomic::Ordering::Acquire)
+    }
+}
+
+/// Blocks the scheduler's current task on the futex at `address`, if it holds `expected`.
+///
+/// The check and the block are atomic with respect to [`wake`], so a wake can not be missed between them.
+pub fn wait(
+    address: Address<Virtual>,
+    expected: u32,
+    scheduler: &mut Scheduler,
+    state: &mut State,
+    regs: &mut Registers,
+) -> Result<()> {
+    let task = scheduler.task_mut().expect("cannot wait without task");
+    let physical_address = physical_address(task, address)?;
+
+    let mut futexes = FUTEXES.lock();
+    if read_futex(physical_address) != expected {
+        return Err(Error::WouldBlock);
+    }
+
+    futexes.entry(physical_address).or_default().wait(scheduler, state, regs);
+
+    Ok(())
+}
+
+/// Wakes up to `count` tasks waiting on the futex at `address`, returning the number of tasks woken.
+pub fn wake(task: &Task, address: Address<Virtual>, count: usize) -> Result<usize> {
+    let physical_address = physical_address(task, address)?;
+
+    let mut futexes = FUTEXES.lock();
+    let Some(wait_queue) = futexes.get(&physical_address) else { return Ok(0) };
+
+    let woken = wait_queue.wake(count);
+    if wait_queue.is_empty() {
+        futexes.remove(&physical_address);
+    }
+
+    Ok(woken)
+}
+
+/// Removes the wait queues of futexes which no task is waiting on anymore, such as those whose waiters were reaped
+/// along with their process.
+pub(super) fn prune() {
+    FUTEXES.lock().retain(|_, wait_queue| !wait_queue.is_empty());
+}
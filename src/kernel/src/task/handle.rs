@@ -0,0 +1,185 @@
+use crate::{
+    drivers::graphics::display::Window,
+    interrupts::irq::Interrupt,
+    mem::io::pci,
+    task::{
+        device::DeviceClaim,
+        ipc::{Channel, Reply},
+        shm::SharedMemory,
+        ExitStatus, Process,
+    },
+};
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+};
+use core::num::NonZeroUsize;
+use libsys::syscall::handle::Rights;
+
+crate::error_impl! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Error {
+        /// Indicates the handle table has no entry for the provided handle.
+        InvalidHandle { handle: usize } => None,
+
+        /// Indicates the handle lacks rights required for an operation.
+        AccessDenied { handle: usize, rights: Rights } => None
+    }
+}
+
+/// A kernel object which can be named by a handle.
+#[derive(Clone)]
+pub enum Object {
+    /// A process, and its exit status. Handles only keep the exit status alive, so the process can still be reaped
+    /// once it exits.
+    Task(Weak<Process>, Arc<ExitStatus>),
+    Memory(Arc<SharedMemory>),
+    Channel(Arc<Channel>),
+    /// The reply to a call, which the caller holds to await it, and the receiver holds to send it.
+    Reply(Arc<Reply>),
+    PciDevice(Arc<DeviceClaim>),
+    Interrupt(Arc<Interrupt>),
+    Window(Arc<Window>),
+}
+
+/// An object named by a handle, along with the rights the handle grants to it.
+#[derive(Clone)]
+pub struct HandleEntry {
+    object: Object,
+    rights: Rights,
+}
+
+impl HandleEntry {
+    pub const fn new(object: Object, rights: Rights) -> Self {
+        Self { object, rights }
+    }
+
+    #[inline]
+    pub const fn object(&self) -> &Object {
+        &self.object
+    }
+
+    #[inline]
+    pub const fn rights(&self) -> Rights {
+        self.rights
+    }
+}
+
+/// Maps the handles of a process to the kernel objects they name.
+pub struct HandleTable {
+    next_handle: NonZeroUsize,
+    entries: BTreeMap<NonZeroUsize, HandleEntry>,
+}
+
+impl HandleTable {
+    pub const fn new() -> Self {
+        Self { next_handle: NonZeroUsize::MIN, entries: BTreeMap::new() }
+    }
+
+    /// Inserts `entry`, returning the handle which now names it.
+    pub fn insert(&mut self, entry: HandleEntry) -> NonZeroUsize {
+        let handle = self.next_handle;
+        self.next_handle = handle.checked_add(1).unwrap();
+        self.entries.insert(handle, entry);
+
+        handle
+    }
+
+    fn check_rights(handle: usize, entry: &HandleEntry, rights: Rights) -> Result<()> {
+        if entry.rights().contains(rights) {
+            Ok(())
+        } else {
+            Err(Error::AccessDenied { handle, rights })
+        }
+    }
+
+    /// Gets the entry for `handle`, ensuring the handle has `rights`.
+    pub fn get(&self, handle: usize, rights: Rights) -> Result<&HandleEntry> {
+        let entry = NonZeroUsize::new(handle)
+            .and_then(|handle| self.entries.get(&handle))
+            .ok_or(Error::InvalidHandle { handle })?;
+        Self::check_rights(handle, entry, rights)?;
+
+        Ok(entry)
+    }
+
+    /// Gets the process named by `handle`, ensuring the handle has `rights`.
+    pub fn get_task(&self, handle: usize, rights: Rights) -> Result<Arc<Process>> {
+        match self.get(handle, rights)?.object() {
+            Object::Task(process, _) => process.upgrade().ok_or(Error::InvalidHandle { handle }),
+            _ => Err(Error::InvalidHandle { handle }),
+        }
+    }
+
+    /// Gets the exit status of the process named by `handle`, ensuring the handle has `rights`.
+    pub fn get_exit_status(&self, handle: usize, rights: Rights) -> Result<Arc<ExitStatus>> {
+        match self.get(handle, rights)?.object() {
+            Object::Task(_, exit_status) => Ok(exit_status.clone()),
+            _ => Err(Error::InvalidHandle { handle }),
+        }
+    }
+
+    /// Gets the memory object named by `handle`, ensuring the handle has `rights`.
+    pub fn get_memory(&self, handle: usize, rights: Rights) -> Result<Arc<SharedMemory>> {
+        match self.get(handle, rights)?.object() {
+            Object::Memory(memory) => Ok(memory.clone()),
+            _ => Err(Error::InvalidHandle { handle }),
+        }
+    }
+
+    /// Gets the channel named by `handle`, ensuring the handle has `rights`.
+    pub fn get_channel(&self, handle: usize, rights: Rights) -> Result<Arc<Channel>> {
+        match self.get(handle, rights)?.object() {
+            Object::Channel(channel) => Ok(channel.clone()),
+            _ => Err(Error::InvalidHandle { handle }),
+        }
+    }
+
+    /// Gets the reply slot named by `handle`, ensuring the handle has `rights`.
+    pub fn get_reply(&self, handle: usize, rights: Rights) -> Result<Arc<Reply>> {
+        match self.get(handle, rights)?.object() {
+            Object::Reply(reply) => Ok(reply.clone()),
+            _ => Err(Error::InvalidHandle { handle }),
+        }
+    }
+
+    /// Gets the claimed PCI device named by `handle`, ensuring the handle has `rights`.
+    pub fn get_pci_device(&self, handle: usize, rights: Rights) -> Result<Arc<pci::Claim>> {
+        match self.get(handle, rights)?.object() {
+            Object::PciDevice(device) => Ok(device.claim().clone()),
+            _ => Err(Error::InvalidHandle { handle }),
+        }
+    }
+
+    /// Gets the interrupt named by `handle`, ensuring the handle has `rights`.
+    pub fn get_interrupt(&self, handle: usize, rights: Rights) -> Result<Arc<Interrupt>> {
+        match self.get(handle, rights)?.object() {
+            Object::Interrupt(interrupt) => Ok(interrupt.clone()),
+            _ => Err(Error::InvalidHandle { handle }),
+        }
+    }
+
+    /// Gets the window named by `handle`, ensuring the handle has `rights`.
+    pub fn get_window(&self, handle: usize, rights: Rights) -> Result<Arc<Window>> {
+        match self.get(handle, rights)?.object() {
+            Object::Window(window) => Ok(window.clone()),
+            _ => Err(Error::InvalidHandle { handle }),
+        }
+    }
+
+    /// Removes the entry for `handle`, ensuring the handle has `rights`.
+    pub fn remove(&mut self, handle: usize, rights: Rights) -> Result<HandleEntry> {
+        self.get(handle, rights)?;
+
+        Ok(self.entries.remove(&NonZeroUsize::new(handle).unwrap()).unwrap())
+    }
+
+    /// Creates a new handle to the object named by `handle`, with `rights` (which must be a subset of the
+    /// handle's rights).
+    pub fn duplicate(&mut self, handle: usize, rights: Rights) -> Result<NonZeroUsize> {
+        let entry = self.get(handle, rights | Rights::DUPLICATE)?;
+        let object = entry.object().clone();
+
+        Ok(self.insert(HandleEntry::new(object, rights)))
+    }
+}
@@ -0,0 +1,239 @@
+use crate::task::{handle::HandleEntry, Registers, Scheduler, State, WaitQueue};
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
+use core::num::NonZeroUsize;
+use libsys::{Address, Frame};
+use spin::Mutex;
+
+crate::error_impl! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Error {
+        /// Indicates the call has already been replied to.
+        AlreadyReplied => None,
+
+        /// Indicates the reply to the call has already been taken.
+        ReplyTaken => None
+    }
+}
+
+/// A message in flight between tasks.
+pub struct Message {
+    data: Box<[u8]>,
+    /// Frames granted with the message, which have been unmapped from the sender's address space.
+    grant: Vec<Address<Frame>>,
+    /// Handle transferred with the message, which has been removed from the sender's handle table.
+    handle: Option<HandleEntry>,
+    /// Slot for the reply, if the message was sent as a call.
+    reply: Option<Arc<Reply>>,
+}
+
+impl Message {
+    pub fn new(data: Box<[u8]>, grant: Vec<Address<Frame>>) -> Self {
+        Self { data, grant, handle: None, reply: None }
+    }
+
+    /// Constructs a message which carries only a transferred handle.
+    pub fn with_handle(handle: HandleEntry) -> Self {
+        Self { data: Box::new([]), grant: Vec::new(), handle: Some(handle), reply: None }
+    }
+
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    #[inline]
+    pub fn grant(&self) -> &[Address<Frame>] {
+        &self.grant
+    }
+
+    #[inline]
+    pub const fn handle(&self) -> Option<&HandleEntry> {
+        self.handle.as_ref()
+    }
+
+    #[inline]
+    pub const fn reply(&self) -> Option<&Arc<Reply>> {
+        self.reply.as_ref()
+    }
+
+    /// Takes ownership of the frames granted with this message.
+    #[inline]
+    pub fn take_grant(&mut self) -> Vec<Address<Frame>> {
+        core::mem::take(&mut self.grant)
+    }
+
+    /// Takes ownership of the handle transferred with this message.
+    #[inline]
+    pub fn take_handle(&mut self) -> Option<HandleEntry> {
+        self.handle.take()
+    }
+
+    /// Takes the slot for the reply to this message, if it was sent as a call.
+    #[inline]
+    pub fn take_reply(&mut self) -> Option<Arc<Reply>> {
+        self.reply.take()
+    }
+}
+
+impl Drop for Message {
+    fn drop(&mut self) {
+        // Free the frames of any grant that was never mapped by a receiver.
+        for frame in self.grant.drain(..) {
+            crate::mem::alloc::pmm::get().free_frame(frame).ok();
+        }
+    }
+}
+
+/// A bounded queue of messages, which blocks receivers while empty and senders while full.
+pub struct Channel {
+    capacity: NonZeroUsize,
+    messages: Mutex<VecDeque<Message>>,
+    receivers: WaitQueue,
+    senders: WaitQueue,
+}
+
+impl Channel {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self { capacity, messages: Mutex::new(VecDeque::new()), receivers: WaitQueue::new(), senders: WaitQueue::new() }
+    }
+
+    /// Pushes the message built by `message_fn`, or, if the channel is full, blocks the scheduler's current task until
+    /// there is space. Returns whether the message was sent.
+    ///
+    /// `message_fn` is only invoked once space is known to be available, so a blocked send has no side effects.
+    pub fn send<E>(
+        &self,
+        scheduler: &mut Scheduler,
+        state: &mut State,
+        regs: &mut Registers,
+        message_fn: impl FnOnce(&mut Scheduler) -> core::result::Result<Message, E>,
+    ) -> core::result::Result<bool, E> {
+        let mut messages = self.messages.lock();
+
+        if messages.len() >= self.capacity.get() {
+            // Receivers take the lock to pop, so blocking while holding it can't miss the wake.
+            self.senders.wait(scheduler, state, regs);
+
+            Ok(false)
+        } else {
+            messages.push_back(message_fn(scheduler)?);
+            drop(messages);
+
+            self.receivers.wake_one();
+
+            Ok(true)
+        }
+    }
+
+    /// Pops the next message, or, if the channel is empty, blocks the scheduler's current task until one is sent.
+    pub fn receive(&self, scheduler: &mut Scheduler, state: &mut State, regs: &mut Registers) -> Option<Message> {
+        let mut messages = self.messages.lock();
+
+        if let Some(message) = messages.pop_front() {
+            drop(messages);
+            self.senders.wake_one();
+
+            Some(message)
+        } else {
+            self.receivers.wait(scheduler, state, regs);
+
+            None
+        }
+    }
+
+    /// Returns a received message which couldn't be delivered to the front of the channel, so it's received next.
+    ///
+    /// The channel may briefly hold more messages than its capacity, if a message was sent in the meantime.
+    pub fn restore(&self, message: Message) {
+        self.messages.lock().push_front(message);
+        self.receivers.wake_one();
+    }
+}
+
+enum ReplyState {
+    Pending,
+    /// A reply is being built, which is guaranteed to be sent.
+    Claimed,
+    Sent(Message),
+    Taken,
+}
+
+/// One-shot slot for the reply to a call.
+///
+/// The caller and the receiver of the call each hold a handle to the slot: the caller's to await the reply, and the
+/// receiver's to send it. Replies are only reachable through these handles, so no other task can reply to (or take
+/// the reply of) a call it wasn't party to. The slot, along with any reply that's never taken, is freed once neither
+/// holds a handle to it anymore.
+pub struct Reply {
+    state: Mutex<ReplyState>,
+    waiters: WaitQueue,
+}
+
+impl Reply {
+    /// Claims the slot for a reply, which must then be sent with [`Self::send`], or released with
+    /// [`Self::release`]. Claiming first allows the reply to be validated before building it has side effects.
+    pub fn claim(&self) -> Result<()> {
+        let mut state = self.state.lock();
+
+        match *state {
+            ReplyState::Pending => {
+                *state = ReplyState::Claimed;
+
+                Ok(())
+            }
+
+            ReplyState::Claimed | ReplyState::Sent(_) | ReplyState::Taken => Err(Error::AlreadyReplied),
+        }
+    }
+
+    /// Releases a claim on the slot, without sending a reply.
+    pub fn release(&self) {
+        let mut state = self.state.lock();
+        debug_assert!(matches!(*state, ReplyState::Claimed));
+
+        *state = ReplyState::Pending;
+    }
+
+    /// Sends the reply to a claimed slot, waking its caller.
+    pub fn send(&self, message: Message) {
+        let mut state = self.state.lock();
+        debug_assert!(matches!(*state, ReplyState::Claimed));
+
+        *state = ReplyState::Sent(message);
+        drop(state);
+
+        self.waiters.wake_all();
+    }
+
+    /// Takes the reply, or, if it hasn't been sent yet, blocks the scheduler's current task until it is.
+    pub fn take(&self, scheduler: &mut Scheduler, state: &mut State, regs: &mut Registers) -> Result<Option<Message>> {
+        let mut reply_state = self.state.lock();
+
+        match core::mem::replace(&mut *reply_state, ReplyState::Taken) {
+            ReplyState::Sent(message) => Ok(Some(message)),
+            ReplyState::Taken => Err(Error::ReplyTaken),
+
+            waiting @ (ReplyState::Pending | ReplyState::Claimed) => {
+                *reply_state = waiting;
+
+                // Replies are sent while holding the lock, so blocking here can't miss the wake.
+                self.waiters.wait(scheduler, state, regs);
+
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns a taken reply which couldn't be delivered, so it can be taken again.
+    pub fn restore(&self, message: Message) {
+        *self.state.lock() = ReplyState::Sent(message);
+    }
+}
+
+/// Marks `message` as a call, returning the slot for its reply.
+pub fn make_call(message: &mut Message) -> Arc<Reply> {
+    let reply = Arc::new(Reply { state: Mutex::new(ReplyState::Pending), waiters: WaitQueue::new() });
+    message.reply = Some(reply.clone());
+
+    reply
+}
@@ -5,13 +5,27 @@ mod scheduling;
 pub use scheduling::*;
 
 mod address_space;
-pub use address_space::*;
+pub use address_space::{Error as AddressSpaceError, *};
 
-use alloc::{boxed::Box, string::String, vec::Vec};
+pub mod device;
+pub mod futex;
+pub mod handle;
+pub mod ipc;
+pub mod reaper;
+pub mod shm;
+
+mod process;
+pub use process::*;
+
+mod wait_queue;
+pub use wait_queue::*;
+
+use crate::mem::paging::TableEntryFlags;
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
 use bit_field::BitField;
 use core::num::NonZeroUsize;
 use elf::{endian::AnyEndian, file::FileHeader, segment::ProgramHeader};
-use libsys::{page_size, Address, Virtual};
+use libsys::{page_size, Address, Page, Virtual};
 
 #[allow(clippy::cast_possible_truncation)]
 pub const STACK_SIZE: NonZeroUsize = NonZeroUsize::new((libsys::MIBIBYTE as usize) - page_size()).unwrap();
@@ -36,7 +50,12 @@ crate::error_impl! {
     pub enum Error {
         AlreadyMapped => None,
         AddressUnderrun { addr: Address<Virtual> } => None,
-        UnhandledAddress { addr: Address<Virtual> } => None
+        UnhandledAddress { addr: Address<Virtual> } => None,
+        UnknownThread { id: usize } => None,
+        SelfJoin { id: usize } => None,
+        AccessViolation { addr: Address<Virtual> } => None,
+        AddressSpace { err: AddressSpaceError } => Some(err),
+        Fs { err: crate::fs::Error } => Some(err)
     }
 }
 
@@ -51,6 +70,33 @@ pub enum Priority {
     Critical = 4,
 }
 
+impl Priority {
+    pub const COUNT: usize = 5;
+
+    /// Number of timer ticks a task of this priority runs before being preempted.
+    pub const fn time_slice(self) -> core::num::NonZeroU16 {
+        let ticks = match self {
+            Priority::Idle => 1,
+            Priority::Low => 3,
+            Priority::Normal => 5,
+            Priority::High => 8,
+            Priority::Critical => 12,
+        };
+
+        core::num::NonZeroU16::new(ticks).unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Task is running, or is waiting in a run queue.
+    Runnable,
+    /// Task is waiting in a wait queue to be woken.
+    Blocked,
+    /// Task is sleeping until the local tick count reaches `until`.
+    Sleeping { until: u64 },
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct ElfRela {
     pub address: Address<Virtual>,
@@ -62,24 +108,25 @@ pub type Context = (State, Registers);
 #[derive(Debug)]
 pub enum ElfData {
     Memory(Box<[u8]>),
-    File(String),
+    /// The file the ELF was loaded from, which is kept open to demand map segment data from.
+    File(crate::fs::File),
 }
 
 pub struct Task {
     id: uuid::Uuid,
+    thread_id: usize,
     priority: Priority,
+    status: Status,
 
-    address_space: AddressSpace,
+    process: Arc<Process>,
     context: Context,
-    load_offset: usize,
-
-    elf_header: FileHeader<AnyEndian>,
-    elf_segments: Box<[ProgramHeader]>,
-    elf_relas: Vec<ElfRela>,
-    elf_data: ElfData,
+    stack: (Address<Page>, NonZeroUsize),
+    tls_base: usize,
 }
 
 impl Task {
+    /// Creates a new task from a loaded ELF image, returning its main thread.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         priority: Priority,
         mut address_space: AddressSpace,
@@ -88,204 +135,226 @@ impl Task {
         elf_segments: Box<[ProgramHeader]>,
         elf_relas: Vec<ElfRela>,
         elf_data: ElfData,
+        args: &[&str],
+        env: &[&str],
     ) -> Self {
         trace!("Generating a random ID for new task.");
         let id = uuid::Uuid::new_v4();
 
         trace!("Allocating userspace stack for task: {:?}.", id);
-        let stack = address_space
-            .mmap(Some(Address::new_truncate(STACK_START.get())), STACK_PAGES, MmapPermissions::ReadWrite)
-            .unwrap();
+        let stack_base = Address::new_truncate(STACK_START.get());
+        let stack = address_space.mmap(Some(stack_base), STACK_PAGES, MmapPermissions::ReadWrite).unwrap();
+        address_space.map_guard(Address::from_index(stack_base.index() - 1).unwrap()).unwrap();
+        let stack_top = stack.as_non_null_ptr().addr().get() + stack.len();
+        let stack_ptr = Self::write_initial_stack(&mut address_space, stack_top, args, env);
+
+        let entry = Address::new(load_offset + usize::try_from(elf_header.e_entry).unwrap()).unwrap();
+        let process = Process::new(address_space, load_offset, elf_header, elf_segments, elf_relas, elf_data);
 
         Self {
             id,
+            thread_id: process.next_thread_id(),
             priority,
-            address_space,
-            context: (
-                State::user(
-                    Address::new(load_offset + usize::try_from(elf_header.e_entry).unwrap()).unwrap(),
-                    // Safety: Addition keeps the pointer within the bounds of the allocation, and the unit size is 1.
-                    unsafe { Address::from_ptr(stack.as_non_null_ptr().as_ptr().add(stack.len())) },
-                ),
-                Registers::default(),
-            ),
-            load_offset,
-            elf_header,
-            elf_segments,
-            elf_relas,
-            elf_data,
+            status: Status::Runnable,
+            process,
+            context: (State::user(entry, stack_ptr), Registers::default()),
+            stack: (stack_base, STACK_PAGES),
+            tls_base: 0,
         }
     }
 
+    /// Creates a new thread of this task, which begins at `entry` with `arg` as its first argument.
+    pub fn spawn_thread(&self, entry: Address<Virtual>, arg: usize, tls_base: usize) -> Result<Self> {
+        let id = uuid::Uuid::new_v4();
+
+        trace!("Allocating userspace stack for thread: {:?}.", id);
+        let floor = self.process.mmap_floor();
+        let stack =
+            self.process.address_space().map_stack(floor, STACK_PAGES).map_err(|err| Error::AddressSpace { err })?;
+        let stack_base = Address::new_truncate(stack.as_non_null_ptr().addr().get());
+        // Emulate the `call` into the entry point, so the stack alignment is as SysV expects.
+        let stack_ptr = Address::new(stack_base.get().get() + stack.len() - core::mem::size_of::<usize>()).unwrap();
+
+        Ok(Self {
+            id,
+            thread_id: self.process.next_thread_id(),
+            priority: self.priority,
+            status: Status::Runnable,
+            process: self.process.clone(),
+            context: (State::user(entry, stack_ptr), Registers { rdi: arg, ..Registers::default() }),
+            stack: (stack_base, STACK_PAGES),
+            tls_base,
+        })
+    }
+
+    /// Writes the System V initial process stack (`argc`, then the null-terminated `argv` and `envp` pointer
+    /// arrays, followed by the string data they point to) below `stack_top`, returning the initial stack pointer.
+    fn write_initial_stack(
+        address_space: &mut AddressSpace,
+        stack_top: usize,
+        args: &[&str],
+        env: &[&str],
+    ) -> Address<Virtual> {
+        let strings_len = args.iter().chain(env).map(|str| str.len() + 1).sum::<usize>();
+        let strings_start = (stack_top - strings_len) & !(core::mem::size_of::<usize>() - 1);
+
+        let mut strings = Vec::with_capacity(strings_len);
+        let mut string_ptrs = args.iter().chain(env).map(|str| {
+            let ptr = strings_start + strings.len();
+            strings.extend_from_slice(str.as_bytes());
+            strings.push(b'\0');
+
+            ptr
+        });
+
+        let mut words = Vec::with_capacity(args.len() + env.len() + 3);
+        words.push(args.len());
+        words.extend(string_ptrs.by_ref().take(args.len()));
+        words.push(0);
+        words.extend(string_ptrs);
+        words.push(0);
+
+        // SysV requires the stack pointer to be 16-byte aligned on entry.
+        let stack_ptr = (strings_start - (words.len() * core::mem::size_of::<usize>())) & !0xF;
+
+        address_space.write_bytes(Address::new(strings_start).unwrap(), &strings).unwrap();
+        address_space
+            .write_bytes(
+                Address::new(stack_ptr).unwrap(),
+                &words.iter().flat_map(|word| word.to_ne_bytes()).collect::<Vec<u8>>(),
+            )
+            .unwrap();
+
+        Address::new(stack_ptr).unwrap()
+    }
+
     #[inline]
     pub const fn id(&self) -> uuid::Uuid {
         self.id
     }
 
+    #[inline]
+    pub const fn thread_id(&self) -> usize {
+        self.thread_id
+    }
+
     #[inline]
     pub const fn priority(&self) -> Priority {
         self.priority
     }
 
     #[inline]
-    pub const fn address_space(&self) -> &AddressSpace {
-        &self.address_space
+    pub const fn status(&self) -> Status {
+        self.status
     }
 
     #[inline]
-    pub fn address_space_mut(&mut self) -> &mut AddressSpace {
-        &mut self.address_space
+    pub const fn process(&self) -> &Arc<Process> {
+        &self.process
     }
 
     #[inline]
-    pub const fn load_offset(&self) -> usize {
-        self.load_offset
+    pub fn address_space(&self) -> spin::MutexGuard<AddressSpace> {
+        self.process.address_space()
     }
 
     #[inline]
-    pub const fn elf_header(&self) -> &FileHeader<AnyEndian> {
-        &self.elf_header
+    pub fn handles(&self) -> spin::MutexGuard<handle::HandleTable> {
+        self.process.handles()
     }
 
     #[inline]
-    pub const fn elf_segments(&self) -> &[ProgramHeader] {
-        &self.elf_segments
+    pub fn files(&self) -> spin::MutexGuard<crate::fs::FileTable> {
+        self.process.files()
     }
 
     #[inline]
-    pub const fn elf_data(&self) -> &ElfData {
-        &self.elf_data
+    pub fn mmap_floor(&self) -> Address<Page> {
+        self.process.mmap_floor()
     }
 
     #[inline]
-    pub fn elf_relas(&mut self) -> &mut Vec<ElfRela> {
-        &mut self.elf_relas
+    pub fn demand_map(&self, address: Address<Virtual>) -> Result<()> {
+        self.process.demand_map(address)
     }
 
-    pub fn demand_map(&mut self, address: Address<Virtual>) -> Result<()> {
-        use crate::mem::paging::TableEntryFlags;
-        use core::mem::MaybeUninit;
-        use libsys::Page;
-
-        let fault_page = Address::new_truncate(address.get());
+    /// Demand maps the user memory in `address..(address + len)`, and ensures every page in it has `flags`.
+    fn check_user_range(&self, address: Address<Virtual>, len: usize, flags: TableEntryFlags) -> Result<()> {
+        let Some(last_address) = len.checked_sub(1) else { return Ok(()) };
+        let last_address = address.get().checked_add(last_address).ok_or(Error::AccessViolation { addr: address })?;
+
+        let first_index = Address::<Page>::new_truncate(address.get()).index();
+        let last_index = Address::<Page>::new_truncate(last_address).index();
+        for page in (first_index..=last_index).filter_map(Address::<Page>::from_index) {
+            match self.demand_map(page.get()) {
+                Ok(()) | Err(Error::AlreadyMapped) | Err(Error::UnhandledAddress { .. }) => {}
+                Err(err) => return Err(err),
+            }
 
-        if self.address_space().is_mmapped(fault_page) {
-            return Err(Error::AlreadyMapped);
+            let page_flags = self.address_space().get_flags(page).map_err(|err| Error::AddressSpace { err })?;
+            // Device memory isn't necessarily covered by the HHDM, so it can't be copied through.
+            if !page_flags.contains(flags) || page_flags.contains(TableEntryFlags::DEVICE) {
+                return Err(Error::AccessViolation { addr: page.get() });
+            }
         }
 
-        let fault_unoffset =
-            address.get().checked_sub(self.load_offset()).ok_or(Error::AddressUnderrun { addr: address })?;
-
-        let segment = self
-            .elf_segments()
-            .iter()
-            .filter(|phdr| phdr.p_type == elf::abi::PT_LOAD)
-            .find(|phdr| {
-                (phdr.p_vaddr..(phdr.p_vaddr + phdr.p_memsz)).contains(&u64::try_from(fault_unoffset).unwrap())
-            })
-            .copied()
-            .ok_or(Error::UnhandledAddress { addr: address })?;
-
-        // Small check to help ensure the segment alignments are page-fit.
-        debug_assert_eq!(segment.p_align & (libsys::page_mask() as u64), 0);
+        Ok(())
+    }
 
-        debug!("Demand mapping {:X?} from segment: {:X?}", Address::<Page>::new_truncate(address.get()), segment);
+    /// Copies bytes from this task's memory at `address` into `buffer`.
+    pub fn copy_from_user(&self, address: Address<Virtual>, buffer: &mut [u8]) -> Result<()> {
+        self.check_user_range(address, buffer.len(), TableEntryFlags::PRESENT | TableEntryFlags::USER)?;
+        self.address_space().read_bytes(address, buffer).map_err(|err| Error::AddressSpace { err })
+    }
 
-        let fault_unoffset_page: Address<Page> = Address::new_truncate(fault_unoffset);
-        let fault_unoffset_page_addr = fault_unoffset_page.get().get();
+    /// Copies `bytes` into this task's memory at `address`.
+    pub fn copy_to_user(&self, address: Address<Virtual>, bytes: &[u8]) -> Result<()> {
+        self.check_user_range(
+            address,
+            bytes.len(),
+            TableEntryFlags::PRESENT | TableEntryFlags::USER | TableEntryFlags::WRITABLE,
+        )?;
+        self.address_space().write_bytes(address, bytes).map_err(|err| Error::AddressSpace { err })
+    }
 
-        let fault_unoffset_end_page: Address<Page> = Address::new_truncate(fault_unoffset_page_addr + page_size());
-        let fault_unoffset_end_page_addr = fault_unoffset_end_page.get().get();
+    /// Saves the interrupted context of the thread, including its thread-local storage base.
+    pub(self) fn save_context(&mut self, state: &State, regs: &Registers) {
+        self.context.0 = *state;
+        self.context.1 = *regs;
 
-        let segment_addr = usize::try_from(segment.p_vaddr).unwrap();
-        let segment_size = usize::try_from(segment.p_filesz).unwrap();
-        let segment_end_addr = segment_addr + segment_size;
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.tls_base = usize::try_from(crate::arch::x86_64::registers::msr::IA32_FS_BASE::read()).unwrap();
+        }
+    }
 
-        let fault_offset = fault_unoffset_page_addr.saturating_sub(segment_addr);
-        let fault_end_pad = fault_unoffset_end_page_addr.saturating_sub(segment_end_addr);
-        let fault_front_pad = segment_addr.saturating_sub(fault_unoffset_page_addr);
-        let fault_size = ((fault_unoffset_end_page_addr - fault_unoffset_page_addr) - fault_front_pad) - fault_end_pad;
+    /// Restores the saved context of the thread, including its thread-local storage base and address space.
+    pub(self) fn restore_context(&self, state: &mut State, regs: &mut Registers) {
+        *state = self.context.0;
+        *regs = self.context.1;
 
-        trace!("Mapping the demand page RW so data can be copied.");
-        let mapped_memory = self
-            .address_space_mut()
-            .mmap(Some(fault_page), core::num::NonZeroUsize::MIN, crate::task::MmapPermissions::ReadWrite)
-            .unwrap();
-        // Safety: Address space allocator fulfills all required invariants.
-        let mapped_memory = unsafe { mapped_memory.as_uninit_slice_mut() };
-
-        let (front_pad, remaining) = mapped_memory.split_at_mut(fault_front_pad);
-        let (file_memory, end_pad) = remaining.split_at_mut(fault_size);
-
-        debug_assert_eq!(fault_front_pad, front_pad.len(), "front padding");
-        debug_assert_eq!(fault_end_pad, end_pad.len(), "end padding");
-        debug_assert_eq!(fault_size, file_memory.len(), "file memory");
-
-        trace!(
-            "Copying memory into demand mapping: {:#X}..{:#X}..{:#X}.",
-            front_pad.len(),
-            file_memory.len(),
-            end_pad.len()
-        );
-        front_pad.fill(MaybeUninit::uninit());
-        end_pad.fill(MaybeUninit::uninit());
-
-        if !file_memory.is_empty() {
-            match self.elf_data() {
-                ElfData::Memory(data) => {
-                    let segment_data_offset = usize::try_from(segment.p_offset).unwrap();
-
-                    let offset_segment_range =
-                        (segment_data_offset + fault_offset)..(segment_data_offset + fault_offset + fault_size);
-
-                    // Safety: Same-sized reinterpret for copying.
-                    let (_, copy_data, _) = unsafe { data[offset_segment_range].align_to() };
-
-                    file_memory.copy_from_slice(copy_data);
-                }
-                ElfData::File(_) => unimplemented!(),
-            }
+        #[cfg(target_arch = "x86_64")]
+        // Safety: The FS base only affects userspace thread-local storage.
+        unsafe {
+            crate::arch::x86_64::registers::msr::IA32_FS_BASE::write(u64::try_from(self.tls_base).unwrap());
         }
 
-        // Safety: Slice has been initialized with values.
-        let _mapped_memory = unsafe { MaybeUninit::slice_assume_init_mut(mapped_memory) };
-
-        trace!("Processing demand mapping relocations.");
-        let load_offset = self.load_offset();
-        let fault_page_as_range = fault_unoffset_page_addr..fault_unoffset_end_page_addr;
-
-        self.elf_relas().retain(|rela| {
-            if fault_page_as_range.contains(&rela.address.get()) {
-                trace!("Processing relocation: {:X?}", rela);
-                // Safety: Fault page is checked to contain the relocation's address, and the pointer is guaranteed after
-                // offset to lie within the memory mapped region above.
-                unsafe {
-                    rela.address.as_ptr().add(load_offset).cast::<usize>().write(rela.value);
-                }
-
-                false
-            } else {
-                true
+        let address_space = self.address_space();
+        if !address_space.is_current() {
+            // Safety: New task requires its own address space.
+            unsafe {
+                address_space.swap_into();
             }
-        });
-
-        trace!("Finalizing page's access attributes.");
-        // Safety: Page is already mapped, permissions are being modified according to the segment access type.
-        unsafe {
-            self.address_space_mut()
-                .set_flags(
-                    fault_page,
-                    core::num::NonZeroUsize::new(1).unwrap(),
-                    TableEntryFlags::PRESENT
-                        | TableEntryFlags::USER
-                        | TableEntryFlags::from(crate::task::segment_to_mmap_permissions(segment.p_type)),
-                )
-                .unwrap();
         }
+    }
+}
 
-        trace!("Demand mapping complete.");
+impl Drop for Task {
+    fn drop(&mut self) {
+        let (stack_base, stack_pages) = self.stack;
 
-        Ok(())
+        // Safety: The thread has exited, so nothing refers to its stack anymore.
+        unsafe { self.address_space().unmap_stack(stack_base, stack_pages) }.ok();
     }
 }
 
@@ -293,11 +362,11 @@ impl core::fmt::Debug for Task {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("Task")
             .field("ID", &self.id)
+            .field("Thread ID", &self.thread_id)
             .field("Priority", &self.priority)
-            .field("Address Space", &self.address_space)
             .field("Context", &self.context)
-            .field("ELF Load Offset", &self.load_offset)
-            .field("ELF Header", &self.elf_header)
+            .field("Stack", &self.stack)
+            .field("TLS Base", &self.tls_base)
             .finish_non_exhaustive()
     }
 }
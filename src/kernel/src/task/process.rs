@@ -0,0 +1,455 @@
+use crate::{
+    fs::FileTable,
+    task::{
+        handle::{HandleEntry, HandleTable, Object},
+        shm::SharedMemory,
+        AddressSpace, AddressSpaceError, ElfData, ElfRela, Error, MmapPermissions, Registers, Result, RunQueue,
+        Scheduler, State, WaitQueue,
+    },
+};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use core::{
+    num::NonZeroUsize,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use elf::{endian::AnyEndian, file::FileHeader, segment::ProgramHeader};
+use libsys::{page_size, syscall::handle::Rights, Address, Page, Virtual};
+
+/// ID of the thread a process is created with.
+pub const MAIN_THREAD_ID: usize = 0;
+
+/// Exit status of a process, which is shared with the handles to it, so tasks waiting on the process can observe it
+/// after the process is reaped.
+pub struct ExitStatus {
+    status: spin::Mutex<Option<usize>>,
+    waiters: WaitQueue,
+}
+
+impl ExitStatus {
+    const fn new() -> Self {
+        Self { status: spin::Mutex::new(None), waiters: WaitQueue::new() }
+    }
+
+    /// Records the process's exit status, and wakes any tasks waiting on it.
+    fn set(&self, status: usize) {
+        *self.status.lock() = Some(status);
+        self.waiters.wake_all();
+    }
+
+    /// Gets the exit status, if the process has exited. Otherwise, blocks the scheduler's current task until it exits,
+    /// and returns `None`.
+    pub fn wait(&self, scheduler: &mut Scheduler, state: &mut State, regs: &mut Registers) -> Option<usize> {
+        let status = self.status.lock();
+
+        if status.is_none() {
+            // The status is set while holding the lock, so blocking here can't miss the wake.
+            self.waiters.wait(scheduler, state, regs);
+        }
+
+        *status
+    }
+}
+
+/// State shared by all of a task's threads: the address space, the ELF image it was loaded from, the handle and file
+/// tables, and the exit statuses of its threads and of itself.
+///
+/// A process starts with a handle to itself (known to userspace as `libsys::syscall::task::CURRENT`), through which
+/// its threads act on it.
+///
+/// The process exits along with its main thread, at which point its remaining threads are reaped.
+pub struct Process {
+    /// ID of the process, which is distinct from the IDs of its threads.
+    id: uuid::Uuid,
+    address_space: spin::Mutex<AddressSpace>,
+    load_offset: usize,
+
+    elf_header: FileHeader<AnyEndian>,
+    elf_segments: Box<[ProgramHeader]>,
+    elf_relas: spin::Mutex<Vec<ElfRela>>,
+    elf_data: ElfData,
+
+    handles: spin::Mutex<HandleTable>,
+    files: spin::Mutex<FileTable>,
+    /// Shared memory mapped into the address space, by the index of the first page it's mapped at. This is dropped
+    /// after the address space, so the memory outlives its mappings.
+    shared_mappings: spin::Mutex<BTreeMap<usize, Arc<SharedMemory>>>,
+
+    next_thread_id: AtomicUsize,
+    /// Thread IDs, mapped to their exit status. `None` indicates the thread has not exited.
+    threads: spin::Mutex<BTreeMap<usize, Option<usize>>>,
+    join_queue: WaitQueue,
+    exited: AtomicBool,
+    /// Exit status of the process, which is that of its main thread.
+    exit_status: Arc<ExitStatus>,
+
+    /// Run queue of the core the process's threads are pinned to, once it has spawned a thread.
+    ///
+    /// TLB invalidation is only local to a core, so an address space must never be active on two cores at once, or
+    /// frames freed on one core could still be reached through the TLB of the other. Until unmappings are shot down
+    /// across cores, every thread of a process runs on the core it was spawned from.
+    pinned_queue: spin::Once<Arc<spin::Mutex<RunQueue>>>,
+}
+
+impl Process {
+    pub fn new(
+        address_space: AddressSpace,
+        load_offset: usize,
+        elf_header: FileHeader<AnyEndian>,
+        elf_segments: Box<[ProgramHeader]>,
+        elf_relas: Vec<ElfRela>,
+        elf_data: ElfData,
+    ) -> Arc<Self> {
+        Arc::new_cyclic(|process| {
+            let exit_status = Arc::new(ExitStatus::new());
+
+            let mut handles = HandleTable::new();
+            let rights = Rights::READ | Rights::MANAGE | Rights::DUPLICATE | Rights::TRANSFER;
+            let handle = handles.insert(HandleEntry::new(Object::Task(process.clone(), exit_status.clone()), rights));
+            debug_assert_eq!(handle.get(), libsys::syscall::task::CURRENT.into_raw());
+
+            Self {
+                id: uuid::Uuid::new_v4(),
+                address_space: spin::Mutex::new(address_space),
+                load_offset,
+                elf_header,
+                elf_segments,
+                elf_relas: spin::Mutex::new(elf_relas),
+                elf_data,
+                handles: spin::Mutex::new(handles),
+                files: spin::Mutex::new(FileTable::new()),
+                shared_mappings: spin::Mutex::new(BTreeMap::new()),
+                next_thread_id: AtomicUsize::new(MAIN_THREAD_ID),
+                threads: spin::Mutex::new(BTreeMap::new()),
+                join_queue: WaitQueue::new(),
+                exited: AtomicBool::new(false),
+                exit_status,
+                pinned_queue: spin::Once::new(),
+            }
+        })
+    }
+
+    #[inline]
+    pub const fn id(&self) -> uuid::Uuid {
+        self.id
+    }
+
+    #[inline]
+    pub fn address_space(&self) -> spin::MutexGuard<AddressSpace> {
+        self.address_space.lock()
+    }
+
+    #[inline]
+    pub fn handles(&self) -> spin::MutexGuard<HandleTable> {
+        self.handles.lock()
+    }
+
+    #[inline]
+    pub fn files(&self) -> spin::MutexGuard<FileTable> {
+        self.files.lock()
+    }
+
+    #[inline]
+    pub const fn load_offset(&self) -> usize {
+        self.load_offset
+    }
+
+    #[inline]
+    pub const fn elf_header(&self) -> &FileHeader<AnyEndian> {
+        &self.elf_header
+    }
+
+    #[inline]
+    pub const fn elf_segments(&self) -> &[ProgramHeader] {
+        &self.elf_segments
+    }
+
+    #[inline]
+    pub const fn elf_data(&self) -> &ElfData {
+        &self.elf_data
+    }
+
+    /// The lowest page that anonymous memory mappings may be placed at, so they never collide with the
+    /// (demand-mapped) ELF image.
+    pub fn mmap_floor(&self) -> Address<Page> {
+        let image_end = self
+            .elf_segments()
+            .iter()
+            .filter(|phdr| phdr.p_type == elf::abi::PT_LOAD)
+            .map(|phdr| usize::try_from(phdr.p_vaddr + phdr.p_memsz).unwrap())
+            .max()
+            .unwrap_or(0);
+
+        Address::new_truncate(libsys::align_up(self.load_offset() + image_end, libsys::page_shift()))
+    }
+
+    /// Maps `memory` into the address space, keeping it alive until it's unmapped.
+    pub fn map_shared(&self, memory: Arc<SharedMemory>, permissions: MmapPermissions) -> Result<Address<Page>> {
+        let floor = self.mmap_floor();
+        let address =
+            self.address_space().map_shared(floor, &memory, permissions).map_err(|err| Error::AddressSpace { err })?;
+        self.shared_mappings.lock().insert(address.index(), memory);
+
+        Ok(address)
+    }
+
+    /// Unmaps the shared memory mapped at `address`.
+    ///
+    /// ### Safety
+    ///
+    /// Caller must ensure no references remain to the memory being unmapped.
+    pub unsafe fn unmap_shared(&self, address: Address<Page>) -> Result<()> {
+        let mut shared_mappings = self.shared_mappings.lock();
+        let memory = shared_mappings
+            .get(&address.index())
+            .ok_or(Error::AddressSpace { err: AddressSpaceError::NotMapped { addr: address.get() } })?;
+
+        self.address_space().munmap(address, memory.page_count()).map_err(|err| Error::AddressSpace { err })?;
+        shared_mappings.remove(&address.index());
+
+        Ok(())
+    }
+
+    /// Drops the shared memory mapped within `page_count` pages from `address`, once they've been unmapped.
+    pub fn release_shared(&self, address: Address<Page>, page_count: NonZeroUsize) {
+        let end_index = address.index() + page_count.get();
+
+        self.shared_mappings
+            .lock()
+            .retain(|index, memory| *index < address.index() || (*index + memory.page_count().get()) > end_index);
+    }
+
+    /// Indicates whether the process's main thread has exited, so its remaining threads should be reaped rather than
+    /// run.
+    #[inline]
+    pub fn has_exited(&self) -> bool {
+        self.exited.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub(super) fn set_exited(&self) {
+        self.exited.store(true, Ordering::Release);
+    }
+
+    /// Records the exit status of the process, once its main thread has exited, and wakes any tasks waiting on it.
+    #[inline]
+    pub(super) fn set_exit_status(&self, exit_status: usize) {
+        self.exit_status.set(exit_status);
+    }
+
+    /// Run queue the process's threads are pinned to, if any.
+    #[inline]
+    pub(super) fn pinned_queue(&self) -> Option<&Arc<spin::Mutex<RunQueue>>> {
+        self.pinned_queue.get()
+    }
+
+    /// Pins the process's threads to `run_queue`, unless they're already pinned.
+    pub(super) fn pin(&self, run_queue: &Arc<spin::Mutex<RunQueue>>) {
+        self.pinned_queue.call_once(|| run_queue.clone());
+    }
+
+    /// Allocates an ID for a new thread of this process.
+    pub(super) fn next_thread_id(&self) -> usize {
+        let thread_id = self.next_thread_id.fetch_add(1, Ordering::Relaxed);
+        self.threads.lock().insert(thread_id, None);
+
+        thread_id
+    }
+
+    /// Records the exit status of a thread, and wakes any threads joining on it.
+    pub(super) fn thread_exited(&self, thread_id: usize, exit_status: usize) {
+        self.threads.lock().insert(thread_id, Some(exit_status));
+        self.join_queue.wake_all();
+    }
+
+    /// Takes the exit status of the thread `thread_id`, if it has exited. Otherwise, blocks the scheduler's current
+    /// thread until any thread of this process exits, and returns `None`.
+    ///
+    /// A thread can't join itself, as it would never be woken. Threads reaped along with their exited process can't be
+    /// joined either, as they never exit.
+    pub fn join_thread(
+        &self,
+        thread_id: usize,
+        scheduler: &mut Scheduler,
+        state: &mut State,
+        regs: &mut Registers,
+    ) -> Result<Option<usize>> {
+        if scheduler
+            .process()
+            .is_some_and(|task| core::ptr::eq(Arc::as_ptr(task.process()), self) && task.thread_id() == thread_id)
+        {
+            return Err(Error::SelfJoin { id: thread_id });
+        }
+
+        let mut threads = self.threads.lock();
+
+        match threads.get(&thread_id).copied() {
+            None => Err(Error::UnknownThread { id: thread_id }),
+
+            Some(Some(exit_status)) => {
+                threads.remove(&thread_id);
+
+                Ok(Some(exit_status))
+            }
+
+            // The process is marked as exited before its main thread's exit wakes any joiners, so they can't block
+            // again after being woken.
+            Some(None) if self.has_exited() => Err(Error::UnknownThread { id: thread_id }),
+
+            Some(None) => {
+                // Thread exits are recorded while holding the lock, so blocking here can't miss the wake.
+                self.join_queue.wait(scheduler, state, regs);
+
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn demand_map(&self, address: Address<Virtual>) -> Result<()> {
+        use crate::mem::paging::TableEntryFlags;
+        use core::mem::MaybeUninit;
+
+        let fault_page = Address::new_truncate(address.get());
+
+        if self.address_space().is_mmapped(fault_page) {
+            return Err(Error::AlreadyMapped);
+        }
+
+        let fault_unoffset =
+            address.get().checked_sub(self.load_offset()).ok_or(Error::AddressUnderrun { addr: address })?;
+
+        let segment = self
+            .elf_segments()
+            .iter()
+            .filter(|phdr| phdr.p_type == elf::abi::PT_LOAD)
+            .find(|phdr| {
+                (phdr.p_vaddr..(phdr.p_vaddr + phdr.p_memsz)).contains(&u64::try_from(fault_unoffset).unwrap())
+            })
+            .copied()
+            .ok_or(Error::UnhandledAddress { addr: address })?;
+
+        // Small check to help ensure the segment alignments are page-fit.
+        debug_assert_eq!(segment.p_align & (libsys::page_mask() as u64), 0);
+
+        debug!("Demand mapping {:X?} from segment: {:X?}", Address::<Page>::new_truncate(address.get()), segment);
+
+        let fault_unoffset_page: Address<Page> = Address::new_truncate(fault_unoffset);
+        let fault_unoffset_page_addr = fault_unoffset_page.get().get();
+
+        let fault_unoffset_end_page: Address<Page> = Address::new_truncate(fault_unoffset_page_addr + page_size());
+        let fault_unoffset_end_page_addr = fault_unoffset_end_page.get().get();
+
+        let segment_addr = usize::try_from(segment.p_vaddr).unwrap();
+        let segment_size = usize::try_from(segment.p_filesz).unwrap();
+        let segment_end_addr = segment_addr + segment_size;
+
+        let fault_offset = fault_unoffset_page_addr.saturating_sub(segment_addr);
+        let fault_end_pad = fault_unoffset_end_page_addr.saturating_sub(segment_end_addr);
+        let fault_front_pad = segment_addr.saturating_sub(fault_unoffset_page_addr);
+        let fault_size = ((fault_unoffset_end_page_addr - fault_unoffset_page_addr) - fault_front_pad) - fault_end_pad;
+
+        // File-backed data is read before the page is mapped, so a failed read leaves nothing to undo. It's read
+        // without holding the address space, as the read may block on I/O.
+        let file_data = match self.elf_data() {
+            ElfData::File(file) if fault_size > 0 => {
+                let mut file_data = alloc::vec![0u8; fault_size];
+                let file_offset = segment.p_offset + u64::try_from(fault_offset).unwrap();
+                file.read_exact_at(file_offset, &mut file_data).map_err(|err| Error::Fs { err })?;
+
+                Some(file_data)
+            }
+
+            _ => None,
+        };
+
+        let mut address_space = self.address_space();
+        // Another thread may have faulted on the page while its data was being read.
+        if address_space.is_mmapped(fault_page) {
+            return Err(Error::AlreadyMapped);
+        }
+
+        trace!("Mapping the demand page RW so data can be copied.");
+        let mapped_memory = address_space
+            .mmap(Some(fault_page), core::num::NonZeroUsize::MIN, crate::task::MmapPermissions::ReadWrite)
+            .unwrap();
+        // Safety: Address space allocator fulfills all required invariants.
+        let mapped_memory = unsafe { mapped_memory.as_uninit_slice_mut() };
+
+        let (front_pad, remaining) = mapped_memory.split_at_mut(fault_front_pad);
+        let (file_memory, end_pad) = remaining.split_at_mut(fault_size);
+
+        debug_assert_eq!(fault_front_pad, front_pad.len(), "front padding");
+        debug_assert_eq!(fault_end_pad, end_pad.len(), "end padding");
+        debug_assert_eq!(fault_size, file_memory.len(), "file memory");
+
+        trace!(
+            "Copying memory into demand mapping: {:#X}..{:#X}..{:#X}.",
+            front_pad.len(),
+            file_memory.len(),
+            end_pad.len()
+        );
+        front_pad.fill(MaybeUninit::uninit());
+        end_pad.fill(MaybeUninit::uninit());
+
+        if !file_memory.is_empty() {
+            match self.elf_data() {
+                ElfData::Memory(data) => {
+                    let segment_data_offset = usize::try_from(segment.p_offset).unwrap();
+
+                    let offset_segment_range =
+                        (segment_data_offset + fault_offset)..(segment_data_offset + fault_offset + fault_size);
+
+                    // Safety: Same-sized reinterpret for copying.
+                    let (_, copy_data, _) = unsafe { data[offset_segment_range].align_to() };
+
+                    file_memory.copy_from_slice(copy_data);
+                }
+                ElfData::File(_) => {
+                    // Safety: Same-sized reinterpret for copying.
+                    let (_, copy_data, _) = unsafe { file_data.as_deref().unwrap().align_to() };
+
+                    file_memory.copy_from_slice(copy_data);
+                }
+            }
+        }
+
+        // Safety: Slice has been initialized with values.
+        let _mapped_memory = unsafe { MaybeUninit::slice_assume_init_mut(mapped_memory) };
+
+        trace!("Processing demand mapping relocations.");
+        let load_offset = self.load_offset();
+        let fault_page_as_range = fault_unoffset_page_addr..fault_unoffset_end_page_addr;
+
+        self.elf_relas.lock().retain(|rela| {
+            if fault_page_as_range.contains(&rela.address.get()) {
+                trace!("Processing relocation: {:X?}", rela);
+                // Safety: Fault page is checked to contain the relocation's address, and the pointer is guaranteed after
+                // offset to lie within the memory mapped region above.
+                unsafe {
+                    rela.address.as_ptr().add(load_offset).cast::<usize>().write(rela.value);
+                }
+
+                false
+            } else {
+                true
+            }
+        });
+
+        trace!("Finalizing page's access attributes.");
+        // Safety: Page is already mapped, permissions are being modified according to the segment access type.
+        unsafe {
+            address_space
+                .set_flags(
+                    fault_page,
+                    core::num::NonZeroUsize::new(1).unwrap(),
+                    TableEntryFlags::PRESENT
+                        | TableEntryFlags::USER
+                        | TableEntryFlags::from(crate::task::segment_to_mmap_permissions(segment.p_type)),
+                )
+                .unwrap();
+        }
+
+        trace!("Demand mapping complete.");
+
+        Ok(())
+    }
+}
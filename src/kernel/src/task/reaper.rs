@@ -0,0 +1,35 @@
+use crate::task::{RunQueue, Task};
+use alloc::{collections::VecDeque, sync::Arc};
+
+static REAP_QUEUE: spin::Mutex<VecDeque<Task>> = spin::Mutex::new(VecDeque::new());
+
+/// Queues an exited task to have its resources reclaimed. Its exit status is recorded by its process, for any thread
+/// which joins it (and, for a main thread, any task waiting on the process).
+///
+/// The task must no longer be running on any core.
+pub fn queue(task: Task) {
+    REAP_QUEUE.lock().push_back(task);
+}
+
+/// Reclaims the resources of every task in the reap queue which may be reaped from the core with `run_queue`,
+/// including their address spaces.
+///
+/// Threads of pinned processes are only reaped on their own core, as unmapping their stacks elsewhere would leave
+/// stale TLB entries on the core still running their process.
+pub fn reap(run_queue: &Arc<spin::Mutex<RunQueue>>) {
+    let mut reap_queue = REAP_QUEUE.lock();
+
+    let mut index = 0;
+    while index < reap_queue.len() {
+        let pinned_queue = reap_queue[index].process().pinned_queue();
+        if pinned_queue.is_some_and(|pinned_queue| !Arc::ptr_eq(pinned_queue, run_queue)) {
+            index += 1;
+            continue;
+        }
+
+        let task = reap_queue.remove(index).unwrap();
+        trace!("Reaping task: {:?}", task.id());
+
+        drop(task);
+    }
+}
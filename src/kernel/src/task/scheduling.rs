@@ -1,21 +1,134 @@
 use crate::{
     mem::Stack,
-    task::{Registers, State, Task},
+    task::{Priority, Process, Registers, State, Status, Task, MAIN_THREAD_ID},
 };
-use alloc::collections::VecDeque;
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use libsys::Address;
+use spin::{Mutex, RwLock};
+
+/// Number of pops a priority with waiting tasks can be passed over for higher priorities, before it's served anyway.
+const MAX_PASSED_OVER: u32 = 8;
+
+/// A set of task queues, one for each priority level.
+#[derive(Default)]
+pub struct RunQueue {
+    queues: [VecDeque<Task>; Priority::COUNT],
+    /// Number of consecutive pops each priority has been passed over for, while it had tasks waiting.
+    passed_over: [u32; Priority::COUNT],
+    /// Number of tasks in the queues, which is shared so tasks can be placed without locking every core's queue.
+    len: Arc<AtomicUsize>,
+}
+
+impl RunQueue {
+    pub fn push(&mut self, task: Task) {
+        self.queues[task.priority() as usize].push_back(task);
+        self.len.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Pops the next task of the highest available priority.
+    ///
+    /// Lower priorities are aged: once one has been passed over [`MAX_PASSED_OVER`] times while it had tasks waiting,
+    /// its next task is popped instead, so a steady supply of higher priority tasks can't starve it.
+    pub fn pop(&mut self) -> Option<Task> {
+        let highest = self.queues.iter().rposition(|queue| !queue.is_empty())?;
+        let index = (0..highest)
+            .rev()
+            .find(|index| self.passed_over[*index] >= MAX_PASSED_OVER && !self.queues[*index].is_empty())
+            .unwrap_or(highest);
+
+        for (queue_index, (queue, passed_over)) in self.queues.iter().zip(&mut self.passed_over).enumerate() {
+            *passed_over = if queue_index == index || queue.is_empty() { 0 } else { *passed_over + 1 };
+        }
+
+        let task = self.queues[index].pop_front();
+        self.len.fetch_sub(1, Ordering::Relaxed);
+
+        task
+    }
+
+    /// Takes a task of the highest available priority from the back of its queue. This is the task least likely to
+    /// run soon on the owning core, which makes it the best candidate for migrating to another core.
+    ///
+    /// Tasks of pinned processes are never taken, as they must stay on their core.
+    pub fn steal(&mut self) -> Option<Task> {
+        let task = self.queues.iter_mut().rev().find_map(|queue| {
+            let index = queue.iter().rposition(|task| task.process().pinned_queue().is_none())?;
+            queue.remove(index)
+        })?;
+        self.len.fetch_sub(1, Ordering::Relaxed);
+
+        Some(task)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
 
-pub static PROCESSES: spin::Mutex<VecDeque<Task>> = spin::Mutex::new(VecDeque::new());
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Run queues of every core's scheduler, along with their shared lengths, for task placement and work stealing.
+static RUN_QUEUES: RwLock<Vec<(Arc<Mutex<RunQueue>>, Arc<AtomicUsize>)>> = RwLock::new(Vec::new());
+
+/// Tasks pushed before any core had a scheduler. Idle cores take from this before stealing from each other.
+static UNASSIGNED: spin::Lazy<Mutex<RunQueue>> = spin::Lazy::new(|| Mutex::new(RunQueue::default()));
+
+/// Pushes a new task onto the run queue of the core its process is pinned to, or otherwise the least-loaded core.
+pub fn push_task(task: Task) {
+    if let Some(run_queue) = task.process().pinned_queue().cloned() {
+        run_queue.lock().push(task);
+        return;
+    }
+
+    let run_queues = RUN_QUEUES.read();
+
+    // Only the chosen core's queue is locked. Its length may have changed since it was read, but placement only needs
+    // to be roughly balanced, as idle cores steal any excess.
+    match run_queues.iter().min_by_key(|(_, len)| len.load(Ordering::Relaxed)) {
+        Some((run_queue, _)) => run_queue.lock().push(task),
+        None => UNASSIGNED.lock().push(task),
+    }
+}
+
+/// Makes a blocked or sleeping task runnable again, pushing it onto a run queue.
+pub fn wake_task(mut task: Task) {
+    task.status = Status::Runnable;
+    push_task(task);
+}
+
+/// Attempts to take a task from the unassigned queue, or from another core's run queue. Contended queues are
+/// skipped, as their owning cores are busy with them anyway.
+fn steal_task(local_queue: &Arc<Mutex<RunQueue>>) -> Option<Task> {
+    if let Some(task) = UNASSIGNED.try_lock().and_then(|mut unassigned| unassigned.pop()) {
+        return Some(task);
+    }
+
+    RUN_QUEUES
+        .try_read()?
+        .iter()
+        .filter(|(run_queue, len)| !Arc::ptr_eq(run_queue, local_queue) && len.load(Ordering::Relaxed) > 0)
+        .find_map(|(run_queue, _)| run_queue.try_lock().and_then(|mut run_queue| run_queue.steal()))
+}
 
 pub struct Scheduler {
     enabled: bool,
     idle_stack: Stack<0x1000>,
+    run_queue: Arc<Mutex<RunQueue>>,
+    sleeping: Vec<Task>,
     task: Option<Task>,
 }
 
 impl Scheduler {
-    pub const fn new(enabled: bool) -> Self {
-        Self { enabled, idle_stack: Stack::new(), task: None }
+    pub fn new(enabled: bool) -> Self {
+        let run_queue = RunQueue::default();
+        let len = run_queue.len.clone();
+        let run_queue = Arc::new(Mutex::new(run_queue));
+        RUN_QUEUES.write().push((run_queue.clone(), len));
+
+        Self { enabled, idle_stack: Stack::new(), run_queue, sleeping: Vec::new(), task: None }
     }
 
     /// Enables the scheduler to pop tasks.
@@ -49,65 +162,149 @@ impl Scheduler {
     pub fn interrupt_task(&mut self, state: &mut State, regs: &mut Registers) {
         debug_assert!(!crate::interrupts::are_enabled());
 
-        let mut processes = PROCESSES.lock();
-
         // Move the current task, if any, back into the scheduler queue.
         if let Some(mut process) = self.task.take() {
             trace!("Interrupting task: {:?}", process.id());
 
-            process.context.0 = *state;
-            process.context.1 = *regs;
+            process.save_context(state, regs);
 
-            processes.push_back(process);
+            self.run_queue.lock().push(process);
         }
 
-        self.next_task(&mut processes, state, regs);
+        self.next_task(state, regs);
     }
 
     /// Attempts to schedule the next task in the local task queue.
     pub fn yield_task(&mut self, state: &mut State, regs: &mut Registers) {
         debug_assert!(!crate::interrupts::are_enabled());
 
-        let mut processes = PROCESSES.lock();
-
         let mut process = self.task.take().expect("cannot yield without process");
         trace!("Yielding task: {:?}", process.id());
 
-        process.context.0 = *state;
-        process.context.1 = *regs;
+        process.save_context(state, regs);
+
+        self.run_queue.lock().push(process);
+
+        self.next_task(state, regs);
+    }
+
+    /// Pushes a newly spawned thread of the current task's process, pinning the process to this core so its address
+    /// space is never active on more than one core.
+    pub fn push_thread(&mut self, thread: Task) {
+        thread.process().pin(&self.run_queue);
+        push_task(thread);
+    }
+
+    /// Blocks the current task, passing it to `block` to be stored until it is woken, and schedules the next task.
+    pub fn block_task(&mut self, state: &mut State, regs: &mut Registers, block: impl FnOnce(Task)) {
+        debug_assert!(!crate::interrupts::are_enabled());
+
+        let mut process = self.task.take().expect("cannot block without process");
+        trace!("Blocking task: {:?}", process.id());
+
+        process.save_context(state, regs);
+        process.status = Status::Blocked;
 
-        processes.push_back(process);
+        block(process);
 
-        self.next_task(&mut processes, state, regs);
+        self.next_task(state, regs);
     }
 
-    pub fn kill_task(&mut self, state: &mut State, regs: &mut Registers) {
+    /// Puts the current task to sleep for at least `ticks` timer ticks, and schedules the next task.
+    pub fn sleep_task(&mut self, ticks: u64, state: &mut State, regs: &mut Registers) {
+        debug_assert!(!crate::interrupts::are_enabled());
+
+        let mut process = self.task.take().expect("cannot sleep without process");
+        trace!("Sleeping task: {:?} ({} ticks)", process.id(), ticks);
+
+        process.save_context(state, regs);
+        process.status = Status::Sleeping { until: crate::cpu::state::get_ticks().unwrap().saturating_add(ticks) };
+
+        self.sleeping.push(process);
+
+        self.next_task(state, regs);
+    }
+
+    /// Moves any sleeping tasks whose wake time has passed into the local run queue. Returns the number of ticks
+    /// until the next sleeping task should wake, if any remain.
+    fn wake_sleeping(&mut self) -> Option<u64> {
+        let now = crate::cpu::state::get_ticks().unwrap();
+        let wake_time = |task: &Task| match task.status {
+            Status::Sleeping { until } => until,
+            _ => unreachable!("non-sleeping task in sleep list"),
+        };
+
+        let mut index = 0;
+        while index < self.sleeping.len() {
+            if wake_time(&self.sleeping[index]) <= now {
+                let mut task = self.sleeping.swap_remove(index);
+                task.status = Status::Runnable;
+                self.run_queue.lock().push(task);
+            } else {
+                index += 1;
+            }
+        }
+
+        self.sleeping.iter().map(wake_time).min().map(|until| until - now)
+    }
+
+    /// Queues the sleeping threads of the exited `process` to be reaped.
+    ///
+    /// A process's threads are pinned to the core its main thread runs on, so they can only be sleeping on this core.
+    fn reap_sleeping(&mut self, process: &Arc<Process>) {
+        let mut index = 0;
+        while index < self.sleeping.len() {
+            if Arc::ptr_eq(self.sleeping[index].process(), process) {
+                super::reaper::queue(self.sleeping.swap_remove(index));
+            } else {
+                index += 1;
+            }
+        }
+    }
+
+    pub fn kill_task(&mut self, exit_status: usize, state: &mut State, regs: &mut Registers) {
         debug_assert!(!crate::interrupts::are_enabled());
 
-        // TODO add process to reap queue to reclaim address space memory
         let process = self.task.take().expect("cannot exit without process");
-        trace!("Exiting process: {:?}", process.id());
+        trace!("Exiting process: {:?} ({})", process.id(), exit_status);
+
+        // Exiting the main thread exits the process. Its blocked threads can never be woken by it again, and they
+        // would otherwise keep the process alive from within its own wait queues. Its sleeping threads may never wake
+        // to be reaped, either.
+        if process.thread_id() == MAIN_THREAD_ID {
+            super::wait_queue::exit_process(process.process()).into_iter().for_each(super::reaper::queue);
+            self.reap_sleeping(process.process());
+            super::futex::prune();
+            process.process().set_exit_status(exit_status);
+        }
+
+        process.process().thread_exited(process.thread_id(), exit_status);
 
-        let mut processes = PROCESSES.lock();
-        self.next_task(&mut processes, state, regs);
+        self.next_task(state, regs);
+
+        super::reaper::queue(process);
     }
 
-    fn next_task(&mut self, processes: &mut VecDeque<Task>, state: &mut State, regs: &mut Registers) {
-        // Pop a new task from the task queue, or simply switch in the idle task.
-        if let Some(next_process) = processes.pop_front() {
-            *state = next_process.context.0;
-            *regs = next_process.context.1;
+    fn next_task(&mut self, state: &mut State, regs: &mut Registers) {
+        let next_wake = self.wake_sleeping();
 
-            if !next_process.address_space.is_current() {
-                // Safety: New task requires its own address space.
-                unsafe {
-                    next_process.address_space.swap_into();
-                }
+        // Pop a new task from the local run queue, steal one from another core, or simply switch in the idle task.
+        // Threads of exited processes are reaped rather than run.
+        let next_process = loop {
+            match self.run_queue.lock().pop().or_else(|| steal_task(&self.run_queue)) {
+                Some(task) if task.process().has_exited() => super::reaper::queue(task),
+                next_process => break next_process,
             }
+        };
+        let time_slice = if let Some(next_process) = next_process {
+            next_process.restore_context(state, regs);
 
             trace!("Switched task: {:?}", next_process.id());
+            let time_slice = next_process.priority().time_slice();
             let old_value = self.task.replace(next_process);
             debug_assert!(old_value.is_none());
+
+            time_slice
         } else {
             *state = State::kernel(
                 Address::new(crate::interrupts::wait_loop as usize).unwrap(),
@@ -115,16 +312,34 @@ impl Scheduler {
             );
             *regs = Registers::default();
 
+            // Switch out of any userspace address space, so exited tasks can be reclaimed while this core is idle.
+            crate::mem::with_kmapper(|kmapper| {
+                if kmapper.root_frame() != crate::mem::PagingRegister::read().frame() {
+                    // Safety: The kernel address space maps all kernel memory, so it is always safe to switch into.
+                    unsafe { kmapper.swap_into() };
+                }
+            });
+
             trace!("Switched idle task.");
+
+            // Keep idle time slices short, so idle cores look for work to steal often.
+            Priority::Idle.time_slice()
         };
 
-        // TODO have some kind of queue of preemption waits, to ensure we select the shortest one.
+        // Ensure the scheduler runs again in time to wake the next sleeping task.
+        let preemption_wait = next_wake
+            .and_then(|ticks| u16::try_from(ticks).ok())
+            .and_then(core::num::NonZeroU16::new)
+            .map_or(time_slice, |ticks| ticks.min(time_slice));
+
         // Safety: Just having switched tasks, no preemption wait should supercede this one.
         unsafe {
-            const TIME_SLICE: core::num::NonZeroU16 = core::num::NonZeroU16::new(5).unwrap();
-
-            crate::cpu::state::set_preemption_wait(TIME_SLICE).unwrap();
+            crate::cpu::state::set_preemption_wait(preemption_wait).unwrap();
         }
+
+        // Reclaim any exited tasks now the next address space has been switched in, since theirs are guaranteed not to
+        // be active anymore.
+        super::reaper::reap(&self.run_queue);
     }
 }
 
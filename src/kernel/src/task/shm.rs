@@ -0,0 +1,70 @@
+use crate::mem::alloc::pmm;
+use alloc::sync::Arc;
+use core::{num::NonZeroUsize, ptr::NonNull};
+use libsys::{page_size, Address, Frame};
+
+crate::error_impl! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Error {
+        /// Indicates there are no free runs of physical memory large enough.
+        OutOfMemory { page_count: usize } => None
+    }
+}
+
+/// Physically contiguous memory which is mapped into any number of address spaces (and the kernel's HHDM) at once.
+///
+/// Address spaces map the memory without owning it, so each mapping holds a reference to keep it alive. Its frames are
+/// freed once the last reference is dropped.
+pub struct SharedMemory {
+    frame: Address<Frame>,
+    page_count: NonZeroUsize,
+}
+
+impl SharedMemory {
+    /// Allocates `page_count` zeroed pages of shared memory.
+    pub fn new(page_count: NonZeroUsize) -> Result<Arc<Self>> {
+        let frame = pmm::get()
+            .next_frames(page_count, None)
+            .map_err(|_| Error::OutOfMemory { page_count: page_count.get() })?;
+        let memory = Self { frame, page_count };
+
+        // Safety: Frames were only just allocated, and are mapped within the HHDM.
+        unsafe { core::ptr::write_bytes(memory.as_ptr().as_ptr(), 0, memory.size()) };
+
+        Ok(Arc::new(memory))
+    }
+
+    #[inline]
+    pub const fn frame(&self) -> Address<Frame> {
+        self.frame
+    }
+
+    #[inline]
+    pub const fn page_count(&self) -> NonZeroUsize {
+        self.page_count
+    }
+
+    /// Frames of the memory, in order.
+    pub fn frames(&self) -> impl Iterator<Item = Address<Frame>> + Clone {
+        (self.frame.index()..(self.frame.index() + self.page_count.get())).filter_map(Address::<Frame>::from_index)
+    }
+
+    /// Size of the memory, in bytes.
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.page_count.get() * page_size()
+    }
+
+    /// Pointer to the memory within the HHDM.
+    pub fn as_ptr(&self) -> NonNull<u8> {
+        NonNull::new(crate::mem::HHDM.offset(self.frame).unwrap().as_ptr()).unwrap()
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        for frame in self.frames() {
+            pmm::get().free_frame(frame).ok();
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use crate::task::{Process, Registers, Scheduler, State, Task};
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec::Vec,
+};
+
+/// Tasks blocked in any wait queue, by ID. Queues only refer to their tasks by ID, so the blocked threads of an
+/// exited process can be reclaimed without finding every queue they wait in (some of which the process itself owns).
+static BLOCKED: spin::Mutex<BTreeMap<uuid::Uuid, Task>> = spin::Mutex::new(BTreeMap::new());
+
+/// A queue of tasks blocked until some event occurs.
+pub struct WaitQueue(spin::Mutex<VecDeque<uuid::Uuid>>);
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self(spin::Mutex::new(VecDeque::new()))
+    }
+
+    /// Blocks the scheduler's current task on this queue, and switches to the next task.
+    ///
+    /// If the task's process has exited, the task is reaped instead.
+    pub fn wait(&self, scheduler: &mut Scheduler, state: &mut State, regs: &mut Registers) {
+        scheduler.block_task(state, regs, |task| {
+            let mut ids = self.0.lock();
+            let mut blocked = BLOCKED.lock();
+
+            // The process's exit is checked while holding the blocked tasks, so it can't be missed by `exit_process`.
+            if task.process().has_exited() {
+                drop(blocked);
+                super::reaper::queue(task);
+            } else {
+                // Forget any tasks which were reclaimed while waiting, so the queue doesn't grow without bound.
+                ids.retain(|id| blocked.contains_key(id));
+                ids.push_back(task.id());
+                blocked.insert(task.id(), task);
+            }
+        });
+    }
+
+    /// Wakes up to `count` tasks, in the order they began waiting. Returns the number of tasks woken.
+    pub fn wake(&self, count: usize) -> usize {
+        let mut tasks = Vec::new();
+
+        {
+            let mut ids = self.0.lock();
+            let mut blocked = BLOCKED.lock();
+            while tasks.len() < count {
+                let Some(id) = ids.pop_front() else { break };
+                tasks.extend(blocked.remove(&id));
+            }
+        }
+
+        let woken = tasks.len();
+        tasks.into_iter().for_each(super::wake_task);
+
+        woken
+    }
+
+    #[inline]
+    pub fn wake_one(&self) -> bool {
+        self.wake(1) > 0
+    }
+
+    #[inline]
+    pub fn wake_all(&self) -> usize {
+        self.wake(usize::MAX)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let ids = self.0.lock();
+        let blocked = BLOCKED.lock();
+
+        !ids.iter().any(|id| blocked.contains_key(id))
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Marks `process` as exited, and removes each of its blocked threads from the wait queues, so they can be reaped.
+/// The process is marked while holding the blocked threads, so none of its threads can block after they're taken.
+pub(super) fn exit_process(process: &Arc<Process>) -> Vec<Task> {
+    let mut blocked = BLOCKED.lock();
+    process.set_exited();
+
+    let ids =
+        blocked.iter().filter(|(_, task)| Arc::ptr_eq(task.process(), process)).map(|(id, _)| *id).collect::<Vec<_>>();
+
+    ids.into_iter().filter_map(|id| blocked.remove(&id)).collect()
+}
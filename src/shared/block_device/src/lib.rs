@@ -0,0 +1,117 @@
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+mod memory;
+pub use memory::*;
+
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+
+#[cfg(test)]
+mod tests;
+
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Indicates a block device failed a transfer, or a transfer extended past the end of the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error;
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, formatter)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// A device addressed in fixed-size sectors, which a filesystem is read from.
+///
+/// The device's sectors needn't match the filesystem's blocks, as filesystems access the device in bytes (see
+/// [`read_bytes`] and [`write_bytes`]).
+pub trait BlockDevice {
+    /// Size of the device's sectors, in bytes.
+    fn sector_size(&self) -> usize;
+
+    fn sector_count(&self) -> u64;
+
+    /// Reads the sectors starting at `lba` into `buffer`, whose length is a multiple of the sector size.
+    fn read(&self, lba: u64, buffer: &mut [u8]) -> Result<()>;
+
+    /// Writes `buffer`, whose length is a multiple of the sector size, to the sectors starting at `lba`.
+    fn write(&self, lba: u64, buffer: &[u8]) -> Result<()>;
+
+    /// Commits any cached writes to non-volatile media.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads `buffer.len()` bytes from `device` at the byte offset `offset`.
+pub fn read_bytes(device: &(impl BlockDevice + ?Sized), offset: u64, mut buffer: &mut [u8]) -> Result<()> {
+    let sector_size = device.sector_size();
+    let sector_size_u64 = u64::try_from(sector_size).unwrap();
+    let mut offset = offset;
+    let mut scratch = Vec::new();
+
+    while !buffer.is_empty() {
+        let lba = offset / sector_size_u64;
+        let within = usize::try_from(offset % sector_size_u64).unwrap();
+
+        let len = if within == 0 && buffer.len() >= sector_size {
+            // Whole sectors are read directly into the buffer.
+            let len = buffer.len() - (buffer.len() % sector_size);
+            device.read(lba, &mut buffer[..len])?;
+
+            len
+        } else {
+            scratch.resize(sector_size, 0);
+            device.read(lba, &mut scratch)?;
+
+            let len = buffer.len().min(sector_size - within);
+            buffer[..len].copy_from_slice(&scratch[within..(within + len)]);
+
+            len
+        };
+
+        offset += u64::try_from(len).unwrap();
+        buffer = &mut core::mem::take(&mut buffer)[len..];
+    }
+
+    Ok(())
+}
+
+/// Writes `buffer` to `device` at the byte offset `offset`, reading back any partially written sectors first.
+pub fn write_bytes(device: &(impl BlockDevice + ?Sized), offset: u64, mut buffer: &[u8]) -> Result<()> {
+    let sector_size = device.sector_size();
+    let sector_size_u64 = u64::try_from(sector_size).unwrap();
+    let mut offset = offset;
+    let mut scratch = Vec::new();
+
+    while !buffer.is_empty() {
+        let lba = offset / sector_size_u64;
+        let within = usize::try_from(offset % sector_size_u64).unwrap();
+
+        let len = if within == 0 && buffer.len() >= sector_size {
+            let len = buffer.len() - (buffer.len() % sector_size);
+            device.write(lba, &buffer[..len])?;
+
+            len
+        } else {
+            scratch.resize(sector_size, 0);
+            device.read(lba, &mut scratch)?;
+
+            let len = buffer.len().min(sector_size - within);
+            scratch[within..(within + len)].copy_from_slice(&buffer[..len]);
+            device.write(lba, &scratch)?;
+
+            len
+        };
+
+        offset += u64::try_from(len).unwrap();
+        buffer = &buffer[len..];
+    }
+
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use crate::{BlockDevice, Error, Result};
+use alloc::{vec, vec::Vec};
+use core::cell::{Ref, RefCell};
+
+/// A block device held in memory, such as a disk image (or a stand-in for one, in tests).
+pub struct MemoryDevice {
+    sector_size: usize,
+    data: RefCell<Vec<u8>>,
+}
+
+impl MemoryDevice {
+    /// Creates a zeroed device of `size` bytes.
+    pub fn new(sector_size: usize, size: usize) -> Self {
+        Self::from_image(sector_size, vec![0; size])
+    }
+
+    /// Creates a device holding the contents of `image`.
+    pub fn from_image(sector_size: usize, image: Vec<u8>) -> Self {
+        Self { sector_size, data: RefCell::new(image) }
+    }
+
+    /// Contents of the device.
+    pub fn data(&self) -> Ref<'_, Vec<u8>> {
+        self.data.borrow()
+    }
+}
+
+impl BlockDevice for MemoryDevice {
+    fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    fn sector_count(&self) -> u64 {
+        u64::try_from(self.data.borrow().len() / self.sector_size).unwrap()
+    }
+
+    fn read(&self, lba: u64, buffer: &mut [u8]) -> Result<()> {
+        assert_eq!(buffer.len() % self.sector_size, 0);
+
+        let start = usize::try_from(lba).unwrap() * self.sector_size;
+        let data = self.data.borrow();
+        buffer.copy_from_slice(data.get(start..(start + buffer.len())).ok_or(Error)?);
+
+        Ok(())
+    }
+
+    fn write(&self, lba: u64, buffer: &[u8]) -> Result<()> {
+        assert_eq!(buffer.len() % self.sector_size, 0);
+
+        let start = usize::try_from(lba).unwrap() * self.sector_size;
+        let mut data = self.data.borrow_mut();
+        data.get_mut(start..(start + buffer.len())).ok_or(Error)?.copy_from_slice(buffer);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,29 @@
+//! Helpers shared by the tests of this crate and of the filesystem drivers (through the `test-support` feature).
+
+use crate::MemoryDevice;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Deterministic test data, which doesn't repeat within any sector, block, or cluster.
+pub fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|index| u8::try_from((index * 7 + index / 251) % 256).unwrap()).collect()
+}
+
+/// A filesystem mounted from a [`MemoryDevice`], which tests remount to check what it wrote reads back.
+pub trait Remount: Sized {
+    type Error: fmt::Debug;
+
+    fn mount(device: MemoryDevice) -> Result<Self, Self::Error>;
+
+    fn sync(&mut self) -> Result<(), Self::Error>;
+
+    fn into_device(self) -> MemoryDevice;
+}
+
+/// Syncs `filesystem`, then mounts its device again.
+pub fn remount<F: Remount>(filesystem: F) -> F {
+    let mut filesystem = filesystem;
+    filesystem.sync().unwrap();
+
+    F::mount(filesystem.into_device()).unwrap()
+}
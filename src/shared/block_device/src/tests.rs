@@ -0,0 +1,26 @@
+use crate::{read_bytes, test_support::pattern, write_bytes, BlockDevice, Error, MemoryDevice};
+
+#[test]
+fn unaligned_bytes_round_trip() {
+    let device = MemoryDevice::new(512, 8 * 512);
+    let data = pattern(3 * 512 + 100);
+
+    // The write starts and ends mid-sector, so the sectors around it must be left as they were.
+    write_bytes(&device, 300, &data).unwrap();
+
+    let mut read = vec![0; data.len()];
+    read_bytes(&device, 300, &mut read).unwrap();
+    assert_eq!(read, data);
+
+    assert!(device.data()[..300].iter().all(|byte| *byte == 0));
+    assert!(device.data()[(300 + data.len())..].iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn transfers_past_the_end_fail() {
+    let device = MemoryDevice::new(512, 4 * 512);
+
+    assert_eq!(device.sector_count(), 4);
+    assert_eq!(read_bytes(&device, 4 * 512 - 10, &mut [0; 20]), Err(Error));
+    assert_eq!(write_bytes(&device, 4 * 512, &[0; 1]), Err(Error));
+}
@@ -0,0 +1,503 @@
+use crate::{
+    inode::{RawInode, FAST_SYMLINK_MAX_LEN, FLAG_INDEX},
+    superblock::{read_u16, read_u32, write_u16, write_u32},
+    volume::FileSystem,
+    BlockDevice, Error, Features, FileType, Result,
+};
+use alloc::{vec, vec::Vec};
+
+/// Size of an entry's fields before its name.
+const ENTRY_HEADER_SIZE: usize = 8;
+const MAX_NAME_LEN: usize = 255;
+/// Largest link count of an inode, which also limits the number of subdirectories of a directory.
+const MAX_LINK_COUNT: u16 = 65000;
+/// Recorded as the length of an entry which spans a whole 64 KiB block, as the length itself doesn't fit in 16 bits.
+const MAX_RECORD_LEN: u16 = 65535;
+
+/// Size an entry with a name of `name_len` bytes occupies, as entries are aligned to four bytes.
+const fn entry_len(name_len: usize) -> usize {
+    (ENTRY_HEADER_SIZE + name_len + 3) & !3
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN || name == "." || name == ".." || name.contains(['/', '\0']) {
+        Err(Error::InvalidName)
+    } else {
+        Ok(())
+    }
+}
+
+/// An entry of a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    inode: u32,
+    name: Vec<u8>,
+    file_type: FileType,
+}
+
+impl DirEntry {
+    #[inline]
+    pub const fn inode(&self) -> u32 {
+        self.inode
+    }
+
+    /// Name of the entry, which ext2 doesn't require to be valid UTF-8.
+    #[inline]
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    #[inline]
+    pub const fn file_type(&self) -> FileType {
+        self.file_type
+    }
+}
+
+/// An entry as found in a block of a directory, including unused entries.
+struct RawEntry {
+    /// Byte offset of the entry within its block.
+    offset: usize,
+    /// Inode the entry names, or zero if the entry is unused.
+    inode: u32,
+    /// Number of bytes from the start of the entry to the next, which may leave space after its name.
+    record_len: usize,
+    name: Vec<u8>,
+    entry_type: u8,
+}
+
+impl RawEntry {
+    /// Number of bytes of the entry which are in use.
+    fn used_len(&self) -> usize {
+        if self.inode == 0 {
+            0
+        } else {
+            entry_len(self.name.len())
+        }
+    }
+
+    fn is_dot_entry(&self) -> bool {
+        self.name == b"." || self.name == b".."
+    }
+}
+
+impl<D: BlockDevice> FileSystem<D> {
+    fn has_entry_types(&self) -> bool {
+        self.superblock.features.has_incompatible(Features::INCOMPAT_FILETYPE)
+    }
+
+    fn decode_record_len(&self, record_len: u16) -> usize {
+        if self.superblock.block_size == 65536 && (record_len == MAX_RECORD_LEN || record_len == 0) {
+            65536
+        } else {
+            usize::from(record_len)
+        }
+    }
+
+    fn encode_record_len(record_len: usize) -> u16 {
+        u16::try_from(record_len).unwrap_or(MAX_RECORD_LEN)
+    }
+
+    /// Writes an entry into a directory block at `offset`.
+    fn write_entry(
+        &self,
+        block: &mut [u8],
+        offset: usize,
+        inode: u32,
+        record_len: usize,
+        name: &[u8],
+        file_type: FileType,
+    ) {
+        write_u32(block, offset, inode);
+        write_u16(block, offset + 4, Self::encode_record_len(record_len));
+
+        let name_len = u8::try_from(name.len()).unwrap();
+        if self.has_entry_types() {
+            block[offset + 6] = name_len;
+            block[offset + 7] = file_type.entry_type();
+        } else {
+            write_u16(block, offset + 6, u16::from(name_len));
+        }
+
+        block[(offset + ENTRY_HEADER_SIZE)..(offset + ENTRY_HEADER_SIZE + name.len())].copy_from_slice(name);
+    }
+
+    fn parse_block(&self, block: &[u8]) -> Result<Vec<RawEntry>> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+
+        while offset < block.len() {
+            if offset + ENTRY_HEADER_SIZE > block.len() {
+                return Err(Error::Corrupt { reason: "directory entry overflows its block" });
+            }
+
+            let inode = read_u32(block, offset);
+            let record_len = self.decode_record_len(read_u16(block, offset + 4));
+            let (name_len, entry_type) = if self.has_entry_types() {
+                (usize::from(block[offset + 6]), block[offset + 7])
+            } else {
+                (usize::from(read_u16(block, offset + 6)), 0)
+            };
+
+            if record_len < ENTRY_HEADER_SIZE
+                || !record_len.is_multiple_of(4)
+                || offset + record_len > block.len()
+                || ENTRY_HEADER_SIZE + name_len > record_len
+            {
+                return Err(Error::Corrupt { reason: "invalid directory entry length" });
+            }
+
+            let name_start = offset + ENTRY_HEADER_SIZE;
+            entries.push(RawEntry {
+                offset,
+                inode,
+                record_len,
+                name: block[name_start..(name_start + name_len)].to_vec(),
+                entry_type,
+            });
+
+            offset += record_len;
+        }
+
+        Ok(entries)
+    }
+
+    /// Number of blocks of the directory `raw`, checking that its size is a whole number of them.
+    fn dir_block_count(&self, raw: &RawInode) -> Result<u64> {
+        let block_size = u64::from(self.superblock.block_size);
+        if raw.size().is_multiple_of(block_size) {
+            Ok(raw.size() / block_size)
+        } else {
+            Err(Error::Corrupt { reason: "directory size isn't a whole number of blocks" })
+        }
+    }
+
+    fn read_dir_block(&self, raw: &RawInode, index: u64) -> Result<Vec<u8>> {
+        let block_size = self.superblock.block_size;
+        let mut block = vec![0u8; usize::try_from(block_size).unwrap()];
+        self.read_data(raw, index * u64::from(block_size), &mut block)?;
+
+        Ok(block)
+    }
+
+    fn read_directory_inode(&self, dir: u32) -> Result<RawInode> {
+        let raw = self.read_inode(dir)?;
+        if Self::file_type_of(&raw)? == FileType::Directory {
+            Ok(raw)
+        } else {
+            Err(Error::NotDirectory)
+        }
+    }
+
+    fn find_entry(&self, raw: &RawInode, name: &[u8]) -> Result<Option<RawEntry>> {
+        for index in 0..self.dir_block_count(raw)? {
+            let block = self.read_dir_block(raw, index)?;
+            if let Some(entry) =
+                self.parse_block(&block)?.into_iter().find(|entry| entry.inode != 0 && entry.name == name)
+            {
+                return Ok(Some(entry));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn to_dir_entry(&self, entry: RawEntry) -> Result<DirEntry> {
+        let file_type = match FileType::from_entry_type(entry.entry_type) {
+            Some(file_type) => file_type,
+            None => Self::file_type_of(&self.read_inode(entry.inode)?)?,
+        };
+
+        Ok(DirEntry { inode: entry.inode, name: entry.name, file_type })
+    }
+
+    /// Lists the entries of the directory `dir`, other than `.` and `..`.
+    pub fn read_dir(&self, dir: u32) -> Result<Vec<DirEntry>> {
+        self.read_dir_range(dir, 0, usize::MAX)
+    }
+
+    /// Lists up to `count` entries of the directory `dir` (other than `.` and `..`), starting from the `index`th.
+    ///
+    /// Only the entries listed are converted (which may read their inodes, to find their types), and no blocks are
+    /// read past the last of them.
+    pub fn read_dir_range(&self, dir: u32, index: usize, count: usize) -> Result<Vec<DirEntry>> {
+        let raw = self.read_directory_inode(dir)?;
+        let mut skipped = 0;
+        let mut entries = Vec::new();
+
+        for block_index in 0..self.dir_block_count(&raw)? {
+            if entries.len() >= count {
+                break;
+            }
+
+            let block = self.read_dir_block(&raw, block_index)?;
+            for entry in self.parse_block(&block)?.into_iter().filter(|entry| entry.inode != 0 && !entry.is_dot_entry())
+            {
+                if skipped < index {
+                    skipped += 1;
+                } else if entries.len() < count {
+                    entries.push(self.to_dir_entry(entry)?);
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Finds the entry named `name` in the directory `dir`.
+    pub fn lookup(&self, dir: u32, name: &str) -> Result<DirEntry> {
+        let raw = self.read_directory_inode(dir)?;
+        let entry = self.find_entry(&raw, name.as_bytes())?.ok_or(Error::NotFound)?;
+
+        self.to_dir_entry(entry)
+    }
+
+    /// Adds an entry for `inode` to the directory `dir`, in the first space large enough for it or else in a new block.
+    fn add_entry(&mut self, dir: u32, raw: &mut RawInode, name: &str, inode: u32, file_type: FileType) -> Result<()> {
+        let block_size = u64::from(self.superblock.block_size);
+        let needed = entry_len(name.len());
+
+        // Entries are added without updating any hashed index, so the index is dropped and rebuilt by fsck.
+        raw.set_flags(raw.flags() & !FLAG_INDEX);
+
+        for index in 0..self.dir_block_count(raw)? {
+            let mut block = self.read_dir_block(raw, index)?;
+            let Some(entry) =
+                self.parse_block(&block)?.into_iter().find(|entry| entry.record_len - entry.used_len() >= needed)
+            else {
+                continue;
+            };
+
+            if entry.inode == 0 {
+                self.write_entry(&mut block, entry.offset, inode, entry.record_len, name.as_bytes(), file_type);
+            } else {
+                let used_len = entry.used_len();
+                write_u16(&mut block, entry.offset + 4, Self::encode_record_len(used_len));
+                self.write_entry(
+                    &mut block,
+                    entry.offset + used_len,
+                    inode,
+                    entry.record_len - used_len,
+                    name.as_bytes(),
+                    file_type,
+                );
+            }
+
+            self.write_data(dir, raw, index * block_size, &block)?;
+
+            return Ok(());
+        }
+
+        let mut block = vec![0u8; usize::try_from(block_size).unwrap()];
+        let record_len = block.len();
+        self.write_entry(&mut block, 0, inode, record_len, name.as_bytes(), file_type);
+        let end = raw.size();
+        self.write_data(dir, raw, end, &block)?;
+
+        Ok(())
+    }
+
+    /// Removes the entry named `name` from the directory `dir`, returning the inode it named.
+    fn remove_entry(&mut self, dir: u32, raw: &mut RawInode, name: &str) -> Result<u32> {
+        let block_size = u64::from(self.superblock.block_size);
+        raw.set_flags(raw.flags() & !FLAG_INDEX);
+
+        for index in 0..self.dir_block_count(raw)? {
+            let mut block = self.read_dir_block(raw, index)?;
+            let entries = self.parse_block(&block)?;
+            let Some(position) = entries.iter().position(|entry| entry.inode != 0 && entry.name == name.as_bytes())
+            else {
+                continue;
+            };
+
+            let entry = &entries[position];
+            if let Some(previous) = position.checked_sub(1).map(|previous| &entries[previous]) {
+                // The previous entry absorbs the removed entry's space.
+                let record_len = previous.record_len + entry.record_len;
+                write_u16(&mut block, previous.offset + 4, Self::encode_record_len(record_len));
+            } else {
+                write_u32(&mut block, entry.offset, 0);
+            }
+
+            let inode = entry.inode;
+            self.write_data(dir, raw, index * block_size, &block)?;
+
+            return Ok(inode);
+        }
+
+        Err(Error::NotFound)
+    }
+
+    /// Allocates and initializes an inode for a new entry of the directory `dir`, which the caller finishes and links.
+    fn new_inode(
+        &mut self,
+        dir: u32,
+        name: &str,
+        file_type: FileType,
+        permissions: u16,
+    ) -> Result<(RawInode, u32, RawInode)> {
+        self.check_writable()?;
+        check_name(name)?;
+
+        let dir_raw = self.read_directory_inode(dir)?;
+        if self.find_entry(&dir_raw, name.as_bytes())?.is_some() {
+            return Err(Error::AlreadyExists);
+        }
+
+        let is_directory = file_type == FileType::Directory;
+        if is_directory && dir_raw.link_count() >= MAX_LINK_COUNT {
+            return Err(Error::TooManyLinks);
+        }
+
+        let inode = self.allocate_inode(self.group_of_inode(dir), is_directory)?;
+
+        let inode_size = self.superblock.inode_size;
+        let mut raw = RawInode::new(inode_size);
+        raw.set_mode(file_type, permissions);
+        raw.set_link_count(if is_directory { 2 } else { 1 });
+        raw.set_extra_size((inode_size - 128).min(32));
+
+        Ok((dir_raw, inode, raw))
+    }
+
+    /// Adds an entry for the new inode `inode` to the directory `dir`, releasing the inode if that fails.
+    fn link_new_inode(
+        &mut self,
+        dir: u32,
+        dir_raw: &mut RawInode,
+        name: &str,
+        inode: u32,
+        raw: &mut RawInode,
+    ) -> Result<()> {
+        let file_type = Self::file_type_of(raw)?;
+        self.write_inode(inode, raw)?;
+
+        if let Err(err) = self.add_entry(dir, dir_raw, name, inode, file_type) {
+            self.release_inode(inode, raw)?;
+
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    /// Frees the data and number of an inode which is no longer linked.
+    fn release_inode(&mut self, inode: u32, raw: &mut RawInode) -> Result<()> {
+        if !self.is_fast_symlink(raw) {
+            self.truncate_data(inode, raw, 0)?;
+        }
+
+        raw.set_link_count(0);
+        // No clock is available, so inodes are dated by when the filesystem was last written.
+        raw.set_deletion_time(self.superblock.write_time.max(1));
+        self.write_inode(inode, raw)?;
+
+        self.free_inode(inode, Self::file_type_of(raw)? == FileType::Directory)
+    }
+
+    /// Creates an empty regular file named `name` in the directory `dir`, returning its inode.
+    pub fn create_file(&mut self, dir: u32, name: &str, permissions: u16) -> Result<u32> {
+        let (mut dir_raw, inode, mut raw) = self.new_inode(dir, name, FileType::Regular, permissions)?;
+        self.link_new_inode(dir, &mut dir_raw, name, inode, &mut raw)?;
+
+        Ok(inode)
+    }
+
+    /// Creates an empty directory named `name` in the directory `dir`, returning its inode.
+    pub fn create_dir(&mut self, dir: u32, name: &str, permissions: u16) -> Result<u32> {
+        let (mut dir_raw, inode, mut raw) = self.new_inode(dir, name, FileType::Directory, permissions)?;
+
+        let mut block = vec![0u8; usize::try_from(self.superblock.block_size).unwrap()];
+        let dot_len = entry_len(1);
+        let dot_dot_len = block.len() - dot_len;
+        self.write_entry(&mut block, 0, inode, dot_len, b".", FileType::Directory);
+        self.write_entry(&mut block, dot_len, dir, dot_dot_len, b"..", FileType::Directory);
+
+        if let Err(err) = self.write_data(inode, &mut raw, 0, &block) {
+            self.release_inode(inode, &mut raw)?;
+
+            return Err(err);
+        }
+
+        self.link_new_inode(dir, &mut dir_raw, name, inode, &mut raw)?;
+
+        // The new directory's `..` entry links its parent.
+        dir_raw.set_link_count(dir_raw.link_count() + 1);
+        self.write_inode(dir, &dir_raw)?;
+
+        Ok(inode)
+    }
+
+    /// Creates a symbolic link named `name` in the directory `dir`, which points at `target`.
+    pub fn symlink(&mut self, dir: u32, name: &str, target: &[u8]) -> Result<u32> {
+        if target.is_empty() || target.len() >= usize::try_from(self.superblock.block_size).unwrap() {
+            return Err(Error::InvalidName);
+        }
+
+        let (mut dir_raw, inode, mut raw) = self.new_inode(dir, name, FileType::Symlink, 0o777)?;
+
+        if target.len() < FAST_SYMLINK_MAX_LEN {
+            raw.block_bytes_mut()[..target.len()].copy_from_slice(target);
+            raw.set_size(u64::try_from(target.len()).unwrap());
+        } else if let Err(err) = self.write_data(inode, &mut raw, 0, target) {
+            self.release_inode(inode, &mut raw)?;
+
+            return Err(err);
+        }
+
+        self.link_new_inode(dir, &mut dir_raw, name, inode, &mut raw)?;
+
+        Ok(inode)
+    }
+
+    /// Reads the target of the symbolic link `inode`.
+    pub fn read_link(&self, inode: u32) -> Result<Vec<u8>> {
+        let raw = self.read_inode(inode)?;
+        if Self::file_type_of(&raw)? != FileType::Symlink {
+            return Err(Error::NotSymlink);
+        }
+
+        if raw.size() >= u64::from(self.superblock.block_size) {
+            return Err(Error::Corrupt { reason: "symbolic link target is longer than a block" });
+        }
+
+        let mut target = vec![0u8; usize::try_from(raw.size()).unwrap()];
+        let len = self.read_data(&raw, 0, &mut target)?;
+        target.truncate(len);
+
+        Ok(target)
+    }
+
+    /// Removes the entry named `name` from the directory `dir`, freeing its inode once it has no other links.
+    /// Directories must be empty to be removed.
+    pub fn remove(&mut self, dir: u32, name: &str) -> Result<()> {
+        self.check_writable()?;
+        check_name(name)?;
+
+        let mut dir_raw = self.read_directory_inode(dir)?;
+        let entry = self.find_entry(&dir_raw, name.as_bytes())?.ok_or(Error::NotFound)?;
+        let inode = entry.inode;
+        let mut raw = self.read_inode(inode)?;
+        let is_directory = Self::file_type_of(&raw)? == FileType::Directory;
+
+        if is_directory && !self.read_dir_range(inode, 0, 1)?.is_empty() {
+            return Err(Error::DirectoryNotEmpty);
+        }
+
+        self.remove_entry(dir, &mut dir_raw, name)?;
+
+        if is_directory {
+            // The directory's `..` entry no longer links its parent.
+            dir_raw.set_link_count(dir_raw.link_count().saturating_sub(1));
+            self.write_inode(dir, &dir_raw)?;
+            raw.set_link_count(0);
+        } else {
+            raw.set_link_count(raw.link_count().saturating_sub(1));
+        }
+
+        if raw.link_count() == 0 {
+            self.release_inode(inode, &mut raw)
+        } else {
+            self.write_inode(inode, &raw)
+        }
+    }
+}
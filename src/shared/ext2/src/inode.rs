@@ -0,0 +1,226 @@
+use crate::superblock::{join_u64, read_u16, read_u32, write_u16, write_u32};
+use alloc::{vec, vec::Vec};
+
+/// Number of block pointers (or bytes of extent tree) stored in the inode itself.
+pub(crate) const INODE_BLOCK_SLOTS: usize = 15;
+/// Number of the slots which point directly at data blocks, rather than at blocks of pointers.
+pub(crate) const DIRECT_BLOCKS: u64 = 12;
+/// Symbolic links with targets shorter than this are stored within the inode's block pointers.
+pub(crate) const FAST_SYMLINK_MAX_LEN: usize = INODE_BLOCK_SLOTS * 4;
+
+const MODE_TYPE_MASK: u16 = 0xF000;
+const MODE_PERMISSIONS_MASK: u16 = 0o7777;
+
+/// The inode's data is mapped by an extent tree, rather than by block pointers.
+pub(crate) const FLAG_EXTENTS: u32 = 0x8_0000;
+/// The directory has a hashed index, which must be dropped when its entries change.
+pub(crate) const FLAG_INDEX: u32 = 0x1000;
+/// The inode's data is stored within the inode itself.
+pub(crate) const FLAG_INLINE_DATA: u32 = 0x1000_0000;
+
+/// Kind of file an inode (or directory entry) describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+}
+
+impl FileType {
+    pub(crate) fn from_mode(mode: u16) -> Option<Self> {
+        match mode & MODE_TYPE_MASK {
+            0x1000 => Some(Self::Fifo),
+            0x2000 => Some(Self::CharDevice),
+            0x4000 => Some(Self::Directory),
+            0x6000 => Some(Self::BlockDevice),
+            0x8000 => Some(Self::Regular),
+            0xA000 => Some(Self::Symlink),
+            0xC000 => Some(Self::Socket),
+            _ => None,
+        }
+    }
+
+    pub(crate) const fn mode_bits(self) -> u16 {
+        match self {
+            Self::Fifo => 0x1000,
+            Self::CharDevice => 0x2000,
+            Self::Directory => 0x4000,
+            Self::BlockDevice => 0x6000,
+            Self::Regular => 0x8000,
+            Self::Symlink => 0xA000,
+            Self::Socket => 0xC000,
+        }
+    }
+
+    /// Decodes the file type recorded in a directory entry, if the filesystem records them.
+    pub(crate) fn from_entry_type(entry_type: u8) -> Option<Self> {
+        match entry_type {
+            1 => Some(Self::Regular),
+            2 => Some(Self::Directory),
+            3 => Some(Self::CharDevice),
+            4 => Some(Self::BlockDevice),
+            5 => Some(Self::Fifo),
+            6 => Some(Self::Socket),
+            7 => Some(Self::Symlink),
+            _ => None,
+        }
+    }
+
+    pub(crate) const fn entry_type(self) -> u8 {
+        match self {
+            Self::Regular => 1,
+            Self::Directory => 2,
+            Self::CharDevice => 3,
+            Self::BlockDevice => 4,
+            Self::Fifo => 5,
+            Self::Socket => 6,
+            Self::Symlink => 7,
+        }
+    }
+}
+
+/// Attributes of an inode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stat {
+    pub inode: u32,
+    pub file_type: FileType,
+    /// Permission bits of the mode, including the set-ID and sticky bits.
+    pub permissions: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub link_count: u16,
+    /// Times of last access, modification and attribute change, in seconds since the Unix epoch.
+    pub access_time: u32,
+    pub modify_time: u32,
+    pub change_time: u32,
+}
+
+/// An inode as stored in its group's inode table.
+#[derive(Debug, Clone)]
+pub(crate) struct RawInode {
+    bytes: Vec<u8>,
+}
+
+impl RawInode {
+    pub fn new(inode_size: u16) -> Self {
+        Self { bytes: vec![0; usize::from(inode_size)] }
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn mode(&self) -> u16 {
+        read_u16(&self.bytes, 0)
+    }
+
+    pub fn set_mode(&mut self, file_type: FileType, permissions: u16) {
+        write_u16(&mut self.bytes, 0, file_type.mode_bits() | (permissions & MODE_PERMISSIONS_MASK));
+    }
+
+    pub fn file_type(&self) -> Option<FileType> {
+        FileType::from_mode(self.mode())
+    }
+
+    pub fn uid(&self) -> u32 {
+        u32::from(read_u16(&self.bytes, 2)) | (u32::from(read_u16(&self.bytes, 120)) << 16)
+    }
+
+    pub fn gid(&self) -> u32 {
+        u32::from(read_u16(&self.bytes, 24)) | (u32::from(read_u16(&self.bytes, 122)) << 16)
+    }
+
+    pub fn size(&self) -> u64 {
+        join_u64(read_u32(&self.bytes, 4), read_u32(&self.bytes, 108))
+    }
+
+    pub fn set_size(&mut self, size: u64) {
+        write_u32(&mut self.bytes, 4, u32::try_from(size & 0xFFFF_FFFF).unwrap());
+        write_u32(&mut self.bytes, 108, u32::try_from(size >> 32).unwrap());
+    }
+
+    pub fn link_count(&self) -> u16 {
+        read_u16(&self.bytes, 26)
+    }
+
+    pub fn set_link_count(&mut self, link_count: u16) {
+        write_u16(&mut self.bytes, 26, link_count);
+    }
+
+    /// Time the inode was deleted at, which marks it as no longer in use.
+    pub fn set_deletion_time(&mut self, time: u32) {
+        write_u32(&mut self.bytes, 20, time);
+    }
+
+    /// Number of 512-byte sectors allocated to the inode, including blocks of pointers and extended attributes.
+    pub fn sectors(&self) -> u32 {
+        read_u32(&self.bytes, 28)
+    }
+
+    pub fn set_sectors(&mut self, sectors: u32) {
+        write_u32(&mut self.bytes, 28, sectors);
+    }
+
+    pub fn flags(&self) -> u32 {
+        read_u32(&self.bytes, 32)
+    }
+
+    pub fn set_flags(&mut self, flags: u32) {
+        write_u32(&mut self.bytes, 32, flags);
+    }
+
+    /// Block of extended attributes, if the inode has one.
+    pub fn attribute_block(&self) -> u64 {
+        join_u64(read_u32(&self.bytes, 104), u32::from(read_u16(&self.bytes, 118)))
+    }
+
+    /// Block pointer (or four bytes of extent tree) in the slot `index`.
+    pub fn block(&self, index: usize) -> u32 {
+        read_u32(&self.bytes, 40 + (index * 4))
+    }
+
+    pub fn set_block(&mut self, index: usize, block: u32) {
+        write_u32(&mut self.bytes, 40 + (index * 4), block);
+    }
+
+    /// The block pointer slots as bytes, which hold the root of an extent tree or the target of a fast symlink.
+    pub fn block_bytes(&self) -> &[u8] {
+        &self.bytes[40..(40 + FAST_SYMLINK_MAX_LEN)]
+    }
+
+    pub fn block_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes[40..(40 + FAST_SYMLINK_MAX_LEN)]
+    }
+
+    /// Sets the size of the inode's fields beyond those of revision 0, if the inode has room for them.
+    pub fn set_extra_size(&mut self, extra_size: u16) {
+        if self.bytes.len() >= 132 {
+            write_u16(&mut self.bytes, 128, extra_size);
+        }
+    }
+
+    pub fn stat(&self, inode: u32, file_type: FileType) -> Stat {
+        Stat {
+            inode,
+            file_type,
+            permissions: self.mode() & MODE_PERMISSIONS_MASK,
+            uid: self.uid(),
+            gid: self.gid(),
+            size: self.size(),
+            link_count: self.link_count(),
+            access_time: read_u32(&self.bytes, 8),
+            change_time: read_u32(&self.bytes, 12),
+            modify_time: read_u32(&self.bytes, 16),
+        }
+    }
+}
@@ -0,0 +1,84 @@
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+mod superblock;
+pub use superblock::Features;
+
+mod inode;
+pub use inode::{FileType, Stat};
+
+mod dir;
+pub use dir::DirEntry;
+
+mod volume;
+pub use volume::*;
+
+#[cfg(test)]
+mod tests;
+
+pub use block_device::BlockDevice;
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Indicates the underlying block device failed a transfer.
+    Io,
+
+    /// Indicates the device's superblock doesn't describe an ext2 filesystem.
+    InvalidSuperblock { reason: &'static str },
+
+    /// Indicates the filesystem's on-disk structures are malformed.
+    Corrupt { reason: &'static str },
+
+    /// Indicates the filesystem uses a feature this driver doesn't implement.
+    Unsupported { reason: &'static str },
+
+    /// Indicates a write was attempted to a filesystem (or inode) which can only be read.
+    ReadOnly,
+
+    /// Indicates no entry with the provided name exists in a directory.
+    NotFound,
+
+    /// Indicates an entry with the provided name already exists in a directory.
+    AlreadyExists,
+
+    /// Indicates a directory operation was attempted on another kind of inode.
+    NotDirectory,
+
+    /// Indicates a file operation was attempted on a directory.
+    IsDirectory,
+
+    /// Indicates a symbolic link operation was attempted on another kind of inode.
+    NotSymlink,
+
+    /// Indicates a directory can't be removed, as it still has entries.
+    DirectoryNotEmpty,
+
+    /// Indicates an inode has the most links it can hold.
+    TooManyLinks,
+
+    /// Indicates every block (or inode) of the filesystem is allocated.
+    NoSpace,
+
+    /// Indicates a name can't be stored in a directory entry.
+    InvalidName,
+
+    /// Indicates a file would exceed the largest size the filesystem can address.
+    FileTooLarge,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, formatter)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl From<block_device::Error> for Error {
+    fn from(_: block_device::Error) -> Self {
+        Self::Io
+    }
+}
@@ -0,0 +1,300 @@
+use crate::{Error, Result};
+
+/// Byte offset of the superblock, which is the same whatever the block size.
+pub const SUPERBLOCK_OFFSET: u64 = 1024;
+pub const SUPERBLOCK_SIZE: usize = 1024;
+const MAGIC: u16 = 0xEF53;
+/// Inode size of revision 0 filesystems, and the smallest of any filesystem.
+pub const GOOD_OLD_INODE_SIZE: u16 = 128;
+/// First non-reserved inode of revision 0 filesystems.
+const GOOD_OLD_FIRST_INODE: u32 = 11;
+
+pub(crate) fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+pub(crate) fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..(offset + 2)].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..(offset + 4)].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Combines the low and high halves of a value which is split across two fields.
+pub(crate) fn join_u64(low: u32, high: u32) -> u64 {
+    (u64::from(high) << 32) | u64::from(low)
+}
+
+/// Feature flags of a filesystem, which describe the on-disk structures it uses.
+///
+/// A driver must not mount a filesystem with incompatible features it doesn't know, nor write to one with read-only
+/// compatible features it doesn't know.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features {
+    pub compatible: u32,
+    pub incompatible: u32,
+    pub read_only_compatible: u32,
+}
+
+impl Features {
+    pub const COMPAT_HAS_JOURNAL: u32 = 0x4;
+    pub const COMPAT_DIR_INDEX: u32 = 0x20;
+
+    pub const INCOMPAT_FILETYPE: u32 = 0x2;
+    pub const INCOMPAT_RECOVER: u32 = 0x4;
+    pub const INCOMPAT_META_BG: u32 = 0x10;
+    pub const INCOMPAT_EXTENTS: u32 = 0x40;
+    pub const INCOMPAT_64BIT: u32 = 0x80;
+    pub const INCOMPAT_FLEX_BG: u32 = 0x200;
+    pub const INCOMPAT_CSUM_SEED: u32 = 0x2000;
+    pub const INCOMPAT_LARGEDIR: u32 = 0x4000;
+
+    pub const RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
+    pub const RO_COMPAT_LARGE_FILE: u32 = 0x2;
+    pub const RO_COMPAT_HUGE_FILE: u32 = 0x8;
+    pub const RO_COMPAT_DIR_NLINK: u32 = 0x20;
+    pub const RO_COMPAT_EXTRA_ISIZE: u32 = 0x40;
+
+    /// Incompatible features which can be read.
+    const READABLE_INCOMPAT: u32 = Self::INCOMPAT_FILETYPE
+        | Self::INCOMPAT_EXTENTS
+        | Self::INCOMPAT_64BIT
+        | Self::INCOMPAT_FLEX_BG
+        | Self::INCOMPAT_CSUM_SEED
+        | Self::INCOMPAT_LARGEDIR;
+
+    /// Incompatible features which can be written. Files are only ever written through block maps, so volumes which
+    /// may hold extent-mapped files (or 64-bit block numbers) are only read.
+    const WRITABLE_INCOMPAT: u32 = Self::INCOMPAT_FILETYPE;
+
+    /// Read-only compatible features which can be written. Notably, this excludes checksummed metadata.
+    const WRITABLE_RO_COMPAT: u32 = Self::RO_COMPAT_SPARSE_SUPER
+        | Self::RO_COMPAT_LARGE_FILE
+        | Self::RO_COMPAT_DIR_NLINK
+        | Self::RO_COMPAT_EXTRA_ISIZE;
+
+    #[inline]
+    pub const fn has_incompatible(&self, feature: u32) -> bool {
+        self.incompatible & feature != 0
+    }
+
+    #[inline]
+    pub const fn has_read_only_compatible(&self, feature: u32) -> bool {
+        self.read_only_compatible & feature != 0
+    }
+
+    /// Checks whether the filesystem can be read.
+    pub fn check_readable(&self) -> Result<()> {
+        if self.has_incompatible(Self::INCOMPAT_RECOVER) {
+            Err(Error::Unsupported { reason: "journal needs recovery" })
+        } else if self.incompatible & !Self::READABLE_INCOMPAT != 0 {
+            Err(Error::Unsupported { reason: "incompatible features" })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether the filesystem can be written, as well as read.
+    pub const fn is_writable(&self) -> bool {
+        self.incompatible & !Self::WRITABLE_INCOMPAT == 0 && self.read_only_compatible & !Self::WRITABLE_RO_COMPAT == 0
+    }
+}
+
+/// The parts of the superblock which are used, along with the layout derived from them.
+#[derive(Debug, Clone)]
+pub struct Superblock {
+    pub inodes_count: u32,
+    pub blocks_count: u64,
+    pub free_blocks_count: u64,
+    pub free_inodes_count: u32,
+    pub first_data_block: u32,
+    /// Time the filesystem was last written, in seconds since the Unix epoch.
+    pub write_time: u32,
+    pub block_size: u32,
+    pub blocks_per_group: u32,
+    pub inodes_per_group: u32,
+    /// First inode which isn't reserved for the filesystem's own use.
+    pub first_inode: u32,
+    pub inode_size: u16,
+    pub features: Features,
+    pub uuid: [u8; 16],
+    pub volume_name: [u8; 16],
+    /// Size of a block group descriptor, which grows on 64-bit filesystems.
+    pub descriptor_size: u16,
+    pub group_count: u32,
+}
+
+impl Superblock {
+    /// Parses and validates the superblock in `bytes`.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < SUPERBLOCK_SIZE || read_u16(bytes, 56) != MAGIC {
+            return Err(Error::InvalidSuperblock { reason: "missing magic" });
+        }
+
+        let log_block_size = read_u32(bytes, 24);
+        // Blocks range from 1 KiB to 64 KiB.
+        if log_block_size > 6 {
+            return Err(Error::InvalidSuperblock { reason: "invalid block size" });
+        }
+        let block_size = 1024 << log_block_size;
+
+        let revision = read_u32(bytes, 76);
+        let (first_inode, inode_size, features) = if revision == 0 {
+            (
+                GOOD_OLD_FIRST_INODE,
+                GOOD_OLD_INODE_SIZE,
+                Features { compatible: 0, incompatible: 0, read_only_compatible: 0 },
+            )
+        } else {
+            (
+                read_u32(bytes, 84),
+                read_u16(bytes, 88),
+                Features {
+                    compatible: read_u32(bytes, 92),
+                    incompatible: read_u32(bytes, 96),
+                    read_only_compatible: read_u32(bytes, 100),
+                },
+            )
+        };
+
+        if inode_size < GOOD_OLD_INODE_SIZE || !inode_size.is_power_of_two() || u32::from(inode_size) > block_size {
+            return Err(Error::InvalidSuperblock { reason: "invalid inode size" });
+        }
+
+        let is_64bit = features.has_incompatible(Features::INCOMPAT_64BIT);
+        let descriptor_size = if is_64bit { read_u16(bytes, 254) } else { 32 };
+        if descriptor_size < 32 || (is_64bit && descriptor_size < 64) || !descriptor_size.is_power_of_two() {
+            return Err(Error::InvalidSuperblock { reason: "invalid group descriptor size" });
+        }
+
+        let high = |offset| if is_64bit { read_u32(bytes, offset) } else { 0 };
+        let inodes_count = read_u32(bytes, 0);
+        let blocks_count = join_u64(read_u32(bytes, 4), high(0x150));
+        let free_blocks_count = join_u64(read_u32(bytes, 12), high(0x158));
+        let free_inodes_count = read_u32(bytes, 16);
+        let first_data_block = read_u32(bytes, 20);
+        let write_time = read_u32(bytes, 48);
+        let blocks_per_group = read_u32(bytes, 32);
+        let inodes_per_group = read_u32(bytes, 40);
+
+        if blocks_per_group == 0 || blocks_per_group > block_size * 8 {
+            return Err(Error::InvalidSuperblock { reason: "invalid blocks per group" });
+        }
+
+        if inodes_per_group == 0 || inodes_per_group > block_size * 8 {
+            return Err(Error::InvalidSuperblock { reason: "invalid inodes per group" });
+        }
+
+        if blocks_count <= u64::from(first_data_block) {
+            return Err(Error::InvalidSuperblock { reason: "no blocks beyond the superblock" });
+        }
+
+        let group_count =
+            u32::try_from((blocks_count - u64::from(first_data_block)).div_ceil(u64::from(blocks_per_group)))
+                .map_err(|_| Error::InvalidSuperblock { reason: "too many block groups" })?;
+        if u64::from(group_count) * u64::from(inodes_per_group) < u64::from(inodes_count) {
+            return Err(Error::InvalidSuperblock { reason: "block groups don't hold every inode" });
+        }
+
+        if first_inode <= 2 || first_inode > inodes_count {
+            return Err(Error::InvalidSuperblock { reason: "invalid first inode" });
+        }
+
+        let mut uuid = [0u8; 16];
+        uuid.copy_from_slice(&bytes[104..120]);
+        let mut volume_name = [0u8; 16];
+        volume_name.copy_from_slice(&bytes[120..136]);
+
+        Ok(Self {
+            inodes_count,
+            blocks_count,
+            free_blocks_count,
+            free_inodes_count,
+            first_data_block,
+            write_time,
+            block_size,
+            blocks_per_group,
+            inodes_per_group,
+            first_inode,
+            inode_size,
+            features,
+            uuid,
+            volume_name,
+            descriptor_size,
+            group_count,
+        })
+    }
+
+    /// Records the free block and inode counts in the raw superblock `bytes`.
+    pub fn write_counts(&self, bytes: &mut [u8]) {
+        write_u32(bytes, 12, u32::try_from(self.free_blocks_count & 0xFFFF_FFFF).unwrap());
+        write_u32(bytes, 16, self.free_inodes_count);
+        if self.features.has_incompatible(Features::INCOMPAT_64BIT) {
+            write_u32(bytes, 0x158, u32::try_from(self.free_blocks_count >> 32).unwrap());
+        }
+    }
+
+    /// Byte offset of `block`.
+    pub fn block_offset(&self, block: u64) -> u64 {
+        block * u64::from(self.block_size)
+    }
+
+    /// Byte offset of the block group descriptor table, which follows the superblock's block.
+    pub fn descriptor_table_offset(&self) -> u64 {
+        self.block_offset(u64::from(self.first_data_block) + 1)
+    }
+
+    /// Number of blocks in `group`, as the last group may be cut short.
+    pub fn blocks_in_group(&self, group: u32) -> u32 {
+        let group_start = u64::from(self.first_data_block) + (u64::from(group) * u64::from(self.blocks_per_group));
+        u32::try_from((self.blocks_count - group_start).min(u64::from(self.blocks_per_group))).unwrap()
+    }
+}
+
+/// Describes the location of a block group's bitmaps and inode table, and how much of it is free.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupDescriptor {
+    pub block_bitmap: u64,
+    pub inode_bitmap: u64,
+    pub inode_table: u64,
+    pub free_blocks_count: u32,
+    pub free_inodes_count: u32,
+    pub used_dirs_count: u32,
+}
+
+impl GroupDescriptor {
+    pub fn parse(bytes: &[u8]) -> Self {
+        // The high halves of each field follow the 32-byte descriptor of 32-bit filesystems.
+        let high_u32 = |offset| if bytes.len() >= 64 { read_u32(bytes, offset) } else { 0 };
+        let high_u16 = |offset| if bytes.len() >= 64 { u32::from(read_u16(bytes, offset)) } else { 0 };
+
+        Self {
+            block_bitmap: join_u64(read_u32(bytes, 0), high_u32(32)),
+            inode_bitmap: join_u64(read_u32(bytes, 4), high_u32(36)),
+            inode_table: join_u64(read_u32(bytes, 8), high_u32(40)),
+            free_blocks_count: u32::from(read_u16(bytes, 12)) | (high_u16(44) << 16),
+            free_inodes_count: u32::from(read_u16(bytes, 14)) | (high_u16(46) << 16),
+            used_dirs_count: u32::from(read_u16(bytes, 16)) | (high_u16(48) << 16),
+        }
+    }
+
+    /// Records the counts of the descriptor in the raw descriptor `bytes`.
+    pub fn write_counts(&self, bytes: &mut [u8]) {
+        let low = |count: u32| u16::try_from(count & 0xFFFF).unwrap();
+        let high = |count: u32| u16::try_from(count >> 16).unwrap();
+
+        write_u16(bytes, 12, low(self.free_blocks_count));
+        write_u16(bytes, 14, low(self.free_inodes_count));
+        write_u16(bytes, 16, low(self.used_dirs_count));
+        if bytes.len() >= 64 {
+            write_u16(bytes, 44, high(self.free_blocks_count));
+            write_u16(bytes, 46, high(self.free_inodes_count));
+            write_u16(bytes, 48, high(self.used_dirs_count));
+        }
+    }
+}
@@ -0,0 +1,377 @@
+//! These tests build their images with `mke2fs` and check the images they write with `e2fsck`, so they fail where
+//! e2fsprogs isn't installed.
+
+use crate::{Error, FileSystem, FileType};
+use block_device::{
+    test_support::{pattern, remount, Remount},
+    MemoryDevice,
+};
+use std::{
+    format, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    string::String,
+    sync::atomic::{AtomicUsize, Ordering},
+    vec,
+    vec::Vec,
+};
+
+/// A scratch directory holding the tree an image is populated from, and the image itself.
+struct Scratch {
+    path: PathBuf,
+}
+
+impl Scratch {
+    fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "ext2-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(path.join("root")).unwrap();
+
+        Self { path }
+    }
+
+    fn root(&self) -> PathBuf {
+        self.path.join("root")
+    }
+
+    fn image(&self) -> PathBuf {
+        self.path.join("disk.img")
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Formats an image of `size` with `mke2fs` (passing `options`), populated with the files `populate` writes to the
+/// directory it's given.
+fn image(options: &[&str], size: &str, populate: impl FnOnce(&Path)) -> FileSystem<MemoryDevice> {
+    let scratch = Scratch::new();
+    populate(&scratch.root());
+
+    let output = Command::new("mke2fs")
+        .args(["-F", "-q"])
+        .args(options)
+        .arg("-d")
+        .arg(scratch.root())
+        .arg(scratch.image())
+        .arg(size)
+        .output()
+        .expect("mke2fs (from e2fsprogs) is required to build test images");
+    assert!(output.status.success(), "mke2fs failed:\n{}", String::from_utf8_lossy(&output.stderr));
+
+    let device = MemoryDevice::from_image(512, fs::read(scratch.image()).unwrap());
+
+    FileSystem::mount(device).unwrap()
+}
+
+impl Remount for FileSystem<MemoryDevice> {
+    type Error = Error;
+
+    fn mount(device: MemoryDevice) -> Result<Self, Error> {
+        Self::mount(device)
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.sync()
+    }
+
+    fn into_device(self) -> MemoryDevice {
+        self.device
+    }
+}
+
+/// Checks the filesystem's image with `e2fsck`, which must find nothing to repair.
+fn check(filesystem: &mut FileSystem<MemoryDevice>) {
+    filesystem.sync().unwrap();
+
+    let scratch = Scratch::new();
+    fs::write(scratch.image(), &*filesystem.device().data()).unwrap();
+
+    let output = Command::new("e2fsck")
+        .args(["-f", "-n"])
+        .arg(scratch.image())
+        .output()
+        .expect("e2fsck (from e2fsprogs) is required to check test images");
+    assert!(
+        output.status.success(),
+        "e2fsck found errors:\n{}",
+        String::from_utf8_lossy(&output.stdout) + String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn read_all(filesystem: &FileSystem<MemoryDevice>, inode: u32) -> Vec<u8> {
+    let mut data = vec![0; usize::try_from(filesystem.stat(inode).unwrap().size).unwrap()];
+    assert_eq!(filesystem.read(inode, 0, &mut data).unwrap(), data.len());
+
+    data
+}
+
+fn lookup_path(filesystem: &FileSystem<MemoryDevice>, path: &str) -> u32 {
+    path.split('/').fold(FileSystem::<MemoryDevice>::ROOT, |dir, name| filesystem.lookup(dir, name).unwrap().inode())
+}
+
+const ROOT: u32 = FileSystem::<MemoryDevice>::ROOT;
+
+#[test]
+fn mount_rejects_unformatted_device() {
+    let device = MemoryDevice::new(512, 1024 * 1024);
+
+    assert!(matches!(FileSystem::mount(device), Err(Error::InvalidSuperblock { .. })));
+}
+
+#[test]
+fn reads_populated_image() {
+    // Small blocks make the large file reach through the double indirect block.
+    let large = pattern(400 * 1024);
+    let long_target = "a/".repeat(50) + "target";
+
+    let filesystem = image(&["-t", "ext2", "-b", "1024", "-L", "linuiz"], "8M", |root| {
+        fs::create_dir_all(root.join("boot/limine")).unwrap();
+        fs::write(root.join("boot/limine/limine.conf"), b"timeout: 0\n").unwrap();
+        fs::write(root.join("large"), &large).unwrap();
+        std::os::unix::fs::symlink("boot/limine/limine.conf", root.join("config")).unwrap();
+        std::os::unix::fs::symlink(&long_target, root.join("long")).unwrap();
+    });
+
+    assert_eq!(filesystem.volume_name(), Some("linuiz"));
+    assert_eq!(filesystem.block_size(), 1024);
+    assert!(filesystem.is_writable());
+
+    let mut names = filesystem
+        .read_dir(ROOT)
+        .unwrap()
+        .into_iter()
+        .map(|entry| (String::from_utf8(entry.name().to_vec()).unwrap(), entry.file_type()))
+        .collect::<Vec<_>>();
+    names.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        names,
+        [
+            ("boot".into(), FileType::Directory),
+            ("config".into(), FileType::Symlink),
+            ("large".into(), FileType::Regular),
+            ("long".into(), FileType::Symlink),
+            ("lost+found".into(), FileType::Directory),
+        ]
+    );
+
+    assert_eq!(read_all(&filesystem, lookup_path(&filesystem, "boot/limine/limine.conf")), b"timeout: 0\n");
+    assert_eq!(read_all(&filesystem, lookup_path(&filesystem, "large")), large);
+
+    // Reads may start anywhere, and stop at the end of the file.
+    let mut tail = [0; 64];
+    assert_eq!(filesystem.read(lookup_path(&filesystem, "large"), 400 * 1024 - 10, &mut tail).unwrap(), 10);
+    assert_eq!(tail[..10], large[(large.len() - 10)..]);
+
+    assert_eq!(filesystem.read_link(lookup_path(&filesystem, "config")).unwrap(), b"boot/limine/limine.conf");
+    assert_eq!(filesystem.read_link(lookup_path(&filesystem, "long")).unwrap(), long_target.as_bytes());
+    assert_eq!(filesystem.read_link(lookup_path(&filesystem, "large")), Err(Error::NotSymlink));
+
+    let boot = filesystem.stat(lookup_path(&filesystem, "boot")).unwrap();
+    assert_eq!(boot.file_type, FileType::Directory);
+    assert_eq!(boot.link_count, 3);
+    assert_eq!(filesystem.lookup(ROOT, "missing"), Err(Error::NotFound));
+    assert_eq!(filesystem.lookup(lookup_path(&filesystem, "large"), "x"), Err(Error::NotDirectory));
+}
+
+#[test]
+fn written_files_round_trip() {
+    let mut filesystem = image(&["-t", "ext2", "-b", "1024"], "8M", |_| {});
+
+    let dir = filesystem.create_dir(ROOT, "data", 0o755).unwrap();
+    let file = filesystem.create_file(dir, "large", 0o640).unwrap();
+    let data = pattern(300 * 1024);
+    assert_eq!(filesystem.write(file, 0, &data).unwrap(), data.len());
+    // Overwriting within the file keeps its size.
+    filesystem.write(file, 1000, b"overwritten").unwrap();
+    filesystem.symlink(dir, "short", b"large").unwrap();
+    let long_target = "b/".repeat(40);
+    filesystem.symlink(dir, "long", long_target.as_bytes()).unwrap();
+
+    check(&mut filesystem);
+    let filesystem = remount(filesystem);
+
+    let file = lookup_path(&filesystem, "data/large");
+    let stat = filesystem.stat(file).unwrap();
+    assert_eq!(
+        (stat.file_type, stat.permissions, stat.size, stat.link_count),
+        (FileType::Regular, 0o640, 300 * 1024, 1)
+    );
+
+    let mut expected = data;
+    expected[1000..1011].copy_from_slice(b"overwritten");
+    assert_eq!(read_all(&filesystem, file), expected);
+
+    assert_eq!(filesystem.read_link(lookup_path(&filesystem, "data/short")).unwrap(), b"large");
+    assert_eq!(filesystem.read_link(lookup_path(&filesystem, "data/long")).unwrap(), long_target.as_bytes());
+    assert_eq!(filesystem.stat(ROOT).unwrap().link_count, 4);
+}
+
+#[test]
+fn synced_writes_persist_across_remount() {
+    let mut filesystem = image(&["-t", "ext2", "-b", "1024"], "8M", |_| {});
+
+    let file = filesystem.create_file(ROOT, "file", 0o644).unwrap();
+    let data = pattern(64 * 1024);
+    filesystem.write(file, 0, &data).unwrap();
+    let free_counts = (filesystem.free_blocks(), filesystem.free_inodes());
+    filesystem.sync().unwrap();
+
+    // Mounting a copy of the device as the sync left it shows what would be found after a reboot.
+    let device = MemoryDevice::from_image(512, filesystem.device().data().clone());
+    let remounted = FileSystem::mount(device).unwrap();
+
+    assert_eq!((remounted.free_blocks(), remounted.free_inodes()), free_counts);
+    assert_eq!(read_all(&remounted, lookup_path(&remounted, "file")), data);
+}
+
+#[test]
+fn create_rejects_invalid_names() {
+    let mut filesystem = image(&["-t", "ext2"], "4M", |root| fs::write(root.join("file"), b"").unwrap());
+
+    assert_eq!(filesystem.create_file(ROOT, "file", 0o644), Err(Error::AlreadyExists));
+    assert_eq!(filesystem.create_file(ROOT, "a/b", 0o644), Err(Error::InvalidName));
+    assert_eq!(filesystem.create_file(ROOT, "..", 0o644), Err(Error::InvalidName));
+    assert_eq!(filesystem.create_file(ROOT, &"x".repeat(256), 0o644), Err(Error::InvalidName));
+    assert_eq!(filesystem.create_dir(lookup_path(&filesystem, "file"), "x", 0o755), Err(Error::NotDirectory));
+    assert_eq!(filesystem.write(ROOT, 0, b"x"), Err(Error::IsDirectory));
+
+    check(&mut filesystem);
+}
+
+#[test]
+fn sparse_writes_leave_holes() {
+    let mut filesystem = image(&["-t", "ext2", "-b", "1024"], "8M", |_| {});
+    let free = filesystem.free_blocks();
+
+    let file = filesystem.create_file(ROOT, "sparse", 0o644).unwrap();
+    filesystem.write(file, 2 * 1024 * 1024, b"end").unwrap();
+
+    // Only the last block, and the two blocks of pointers leading to it, are allocated.
+    assert_eq!(filesystem.free_blocks(), free - 3);
+    check(&mut filesystem);
+
+    let data = read_all(&filesystem, file);
+    assert_eq!(data.len(), 2 * 1024 * 1024 + 3);
+    assert!(data[..(2 * 1024 * 1024)].iter().all(|byte| *byte == 0));
+    assert_eq!(&data[(2 * 1024 * 1024)..], b"end");
+}
+
+#[test]
+fn truncate_frees_blocks() {
+    let mut filesystem = image(&["-t", "ext2", "-b", "1024"], "8M", |_| {});
+    let free = filesystem.free_blocks();
+
+    let file = filesystem.create_file(ROOT, "file", 0o644).unwrap();
+    let data = pattern(500 * 1024);
+    filesystem.write(file, 0, &data).unwrap();
+
+    filesystem.truncate(file, 5000).unwrap();
+    assert_eq!(filesystem.free_blocks(), free - 5);
+    check(&mut filesystem);
+
+    // The end of the last block was zeroed, so extending the file again reads zeroes.
+    filesystem.truncate(file, 6000).unwrap();
+    let read = read_all(&filesystem, file);
+    assert_eq!(read[..5000], data[..5000]);
+    assert!(read[5000..].iter().all(|byte| *byte == 0));
+
+    filesystem.truncate(file, 0).unwrap();
+    assert_eq!(filesystem.free_blocks(), free);
+    check(&mut filesystem);
+}
+
+#[test]
+fn remove_frees_inodes_and_blocks() {
+    let mut filesystem = image(&["-t", "ext2"], "8M", |_| {});
+    let (free_blocks, free_inodes) = (filesystem.free_blocks(), filesystem.free_inodes());
+
+    let dir = filesystem.create_dir(ROOT, "dir", 0o755).unwrap();
+    let file = filesystem.create_file(dir, "file", 0o644).unwrap();
+    filesystem.write(file, 0, &pattern(100 * 1024)).unwrap();
+    filesystem.symlink(dir, "link", "c/".repeat(100).as_bytes()).unwrap();
+
+    assert_eq!(filesystem.remove(ROOT, "dir"), Err(Error::DirectoryNotEmpty));
+    filesystem.remove(dir, "file").unwrap();
+    filesystem.remove(dir, "link").unwrap();
+    assert_eq!(filesystem.remove(dir, "file"), Err(Error::NotFound));
+    filesystem.remove(ROOT, "dir").unwrap();
+
+    assert_eq!((filesystem.free_blocks(), filesystem.free_inodes()), (free_blocks, free_inodes));
+    assert_eq!(filesystem.stat(ROOT).unwrap().link_count, 3);
+    check(&mut filesystem);
+
+    let filesystem = remount(filesystem);
+    assert_eq!(filesystem.lookup(ROOT, "dir"), Err(Error::NotFound));
+}
+
+#[test]
+fn directories_grow() {
+    let mut filesystem = image(&["-t", "ext2", "-b", "1024"], "8M", |_| {});
+    let dir = filesystem.create_dir(ROOT, "many", 0o755).unwrap();
+
+    // Each entry takes about 40 bytes, so the directory spans several blocks.
+    for index in 0..150 {
+        filesystem.create_file(dir, &format!("a long file name numbered {index}"), 0o644).unwrap();
+    }
+
+    // Removed entries leave space which is reused.
+    for index in (0..150).step_by(3) {
+        filesystem.remove(dir, &format!("a long file name numbered {index}")).unwrap();
+    }
+    let size = filesystem.stat(dir).unwrap().size;
+    filesystem.create_file(dir, "reused", 0o644).unwrap();
+    assert_eq!(filesystem.stat(dir).unwrap().size, size);
+
+    check(&mut filesystem);
+    let filesystem = remount(filesystem);
+    assert!(size > 4 * 1024);
+    assert_eq!(filesystem.read_dir(dir).unwrap().len(), 101);
+    let batches =
+        (0..101).step_by(16).flat_map(|index| filesystem.read_dir_range(dir, index, 16).unwrap()).collect::<Vec<_>>();
+    assert_eq!(batches, filesystem.read_dir(dir).unwrap());
+    assert!(filesystem.lookup(dir, "a long file name numbered 149").is_ok());
+    assert_eq!(filesystem.lookup(dir, "a long file name numbered 147"), Err(Error::NotFound));
+}
+
+#[test]
+fn filling_the_volume_fails_cleanly() {
+    let mut filesystem = image(&["-t", "ext2"], "2M", |_| {});
+
+    let file = filesystem.create_file(ROOT, "fill", 0o644).unwrap();
+    assert_eq!(filesystem.write(file, 0, &pattern(4 * 1024 * 1024)), Err(Error::NoSpace));
+    assert_eq!(filesystem.free_blocks(), 0);
+    check(&mut filesystem);
+
+    filesystem.remove(ROOT, "fill").unwrap();
+    assert!(filesystem.free_blocks() > 0);
+    check(&mut filesystem);
+}
+
+#[test]
+fn reads_ext4_extents() {
+    let large = pattern(3 * 1024 * 1024 + 123);
+
+    let mut filesystem = image(&["-t", "ext4"], "32M", |root| {
+        fs::create_dir(root.join("dir")).unwrap();
+        fs::write(root.join("dir/large"), &large).unwrap();
+        std::os::unix::fs::symlink("dir/large", root.join("link")).unwrap();
+    });
+
+    assert!(!filesystem.is_writable());
+    let file = lookup_path(&filesystem, "dir/large");
+    assert_eq!(read_all(&filesystem, file), large);
+    assert_eq!(filesystem.read_link(lookup_path(&filesystem, "link")).unwrap(), b"dir/large");
+
+    assert_eq!(filesystem.write(file, 0, b"x"), Err(Error::ReadOnly));
+    assert_eq!(filesystem.create_file(ROOT, "new", 0o644), Err(Error::ReadOnly));
+    assert_eq!(filesystem.remove(ROOT, "link"), Err(Error::ReadOnly));
+}
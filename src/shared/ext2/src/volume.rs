@@ -0,0 +1,787 @@
+use crate::{
+    inode::{RawInode, DIRECT_BLOCKS, FAST_SYMLINK_MAX_LEN, FLAG_EXTENTS, FLAG_INLINE_DATA},
+    superblock::{read_u16, read_u32, write_u32, GroupDescriptor, Superblock, SUPERBLOCK_OFFSET, SUPERBLOCK_SIZE},
+    BlockDevice, Error, Features, FileType, Result, Stat,
+};
+use alloc::{vec, vec::Vec};
+use block_device::{read_bytes, write_bytes};
+
+const EXTENT_MAGIC: u16 = 0xF30A;
+/// Extents longer than this are uninitialized, and read as zeroes.
+const EXTENT_MAX_INITIALIZED_LEN: u16 = 32768;
+/// Extent trees are far shallower than this, so deeper trees are taken to be cyclic.
+const EXTENT_MAX_DEPTH: usize = 8;
+
+/// Files are limited to this size without the large file feature.
+const SMALL_FILE_MAX_SIZE: u64 = 0x7FFF_FFFF;
+
+/// Location of a file block's pointer within the inode's block map.
+struct BlockPath {
+    /// Slot of the inode which holds the pointer, or the root of the pointer blocks leading to it.
+    slot: usize,
+    /// Number of pointer blocks between the slot and the data block.
+    depth: u32,
+    /// Index of the data block within the blocks reachable from the slot.
+    index: u64,
+}
+
+/// A mounted ext2 filesystem.
+///
+/// Files are identified by their inode numbers. Filesystems which use ext4's extents (or other features whose
+/// structures aren't written by this driver) can still be read, but every write to them fails with
+/// [`Error::ReadOnly`].
+pub struct FileSystem<D: BlockDevice> {
+    pub(crate) device: D,
+    pub(crate) superblock: Superblock,
+    /// The superblock as it was read, so that fields which aren't parsed are preserved when it's rewritten.
+    raw_superblock: Vec<u8>,
+    groups: Vec<GroupDescriptor>,
+    writable: bool,
+    /// Whether the superblock's free counts need to be rewritten on sync.
+    superblock_dirty: bool,
+}
+
+impl<D: BlockDevice> FileSystem<D> {
+    /// Inode number of the root directory.
+    pub const ROOT: u32 = 2;
+
+    /// Mounts the filesystem which begins at the first sector of `device`.
+    pub fn mount(device: D) -> Result<Self> {
+        let mut raw_superblock = vec![0u8; SUPERBLOCK_SIZE];
+        read_bytes(&device, SUPERBLOCK_OFFSET, &mut raw_superblock)?;
+        let superblock = Superblock::parse(&raw_superblock)?;
+        superblock.features.check_readable()?;
+
+        let device_size = u64::try_from(device.sector_size()).unwrap() * device.sector_count();
+        if superblock.block_offset(superblock.blocks_count) > device_size {
+            return Err(Error::InvalidSuperblock { reason: "filesystem is larger than the device" });
+        }
+
+        let descriptor_size = usize::from(superblock.descriptor_size);
+        let mut raw_descriptors = vec![0u8; usize::try_from(superblock.group_count).unwrap() * descriptor_size];
+        read_bytes(&device, superblock.descriptor_table_offset(), &mut raw_descriptors)?;
+        let groups = raw_descriptors.chunks_exact(descriptor_size).map(GroupDescriptor::parse).collect::<Vec<_>>();
+
+        let blocks_count = superblock.blocks_count;
+        if groups.iter().any(|group| {
+            [group.block_bitmap, group.inode_bitmap, group.inode_table].iter().any(|block| *block >= blocks_count)
+        }) {
+            return Err(Error::Corrupt { reason: "group descriptor points beyond the filesystem" });
+        }
+
+        let writable = superblock.features.is_writable();
+
+        Ok(Self { device, superblock, raw_superblock, groups, writable, superblock_dirty: false })
+    }
+
+    #[inline]
+    pub const fn device(&self) -> &D {
+        &self.device
+    }
+
+    /// Size of a block, in bytes.
+    #[inline]
+    pub const fn block_size(&self) -> u32 {
+        self.superblock.block_size
+    }
+
+    #[inline]
+    pub const fn features(&self) -> Features {
+        self.superblock.features
+    }
+
+    /// Whether the filesystem's features allow it to be written by this driver.
+    #[inline]
+    pub const fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    #[inline]
+    pub const fn free_blocks(&self) -> u64 {
+        self.superblock.free_blocks_count
+    }
+
+    #[inline]
+    pub const fn free_inodes(&self) -> u32 {
+        self.superblock.free_inodes_count
+    }
+
+    #[inline]
+    pub const fn uuid(&self) -> [u8; 16] {
+        self.superblock.uuid
+    }
+
+    /// Name of the volume, if it has one.
+    pub fn volume_name(&self) -> Option<&str> {
+        let name = &self.superblock.volume_name;
+        let len = name.iter().position(|byte| *byte == 0).unwrap_or(name.len());
+
+        core::str::from_utf8(&name[..len]).ok().filter(|name| !name.is_empty())
+    }
+
+    /// Writes the superblock's free counts (if they've changed) and flushes the device.
+    pub fn sync(&mut self) -> Result<()> {
+        if self.superblock_dirty {
+            self.superblock.write_counts(&mut self.raw_superblock);
+            write_bytes(&self.device, SUPERBLOCK_OFFSET, &self.raw_superblock)?;
+            self.superblock_dirty = false;
+        }
+
+        Ok(self.device.flush()?)
+    }
+
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        if self.writable {
+            Ok(())
+        } else {
+            Err(Error::ReadOnly)
+        }
+    }
+
+    pub(crate) fn read_block(&self, block: u64, buffer: &mut [u8]) -> Result<()> {
+        Ok(read_bytes(&self.device, self.superblock.block_offset(block), buffer)?)
+    }
+
+    pub(crate) fn write_block(&self, block: u64, buffer: &[u8]) -> Result<()> {
+        Ok(write_bytes(&self.device, self.superblock.block_offset(block), buffer)?)
+    }
+
+    fn block_size_usize(&self) -> usize {
+        usize::try_from(self.superblock.block_size).unwrap()
+    }
+
+    /// Number of block pointers which fit in a block.
+    fn pointers_per_block(&self) -> u64 {
+        u64::from(self.superblock.block_size / 4)
+    }
+
+    /// Number of 512-byte sectors in a block, which is the unit an inode's allocated blocks are counted in.
+    fn sectors_per_block(&self) -> u32 {
+        self.superblock.block_size / 512
+    }
+
+    /* INODES */
+
+    pub(crate) fn group_of_inode(&self, inode: u32) -> u32 {
+        (inode - 1) / self.superblock.inodes_per_group
+    }
+
+    fn inode_offset(&self, inode: u32) -> Result<u64> {
+        if inode == 0 || inode > self.superblock.inodes_count {
+            return Err(Error::Corrupt { reason: "inode number out of range" });
+        }
+
+        let group = &self.groups[usize::try_from(self.group_of_inode(inode)).unwrap()];
+        let index = (inode - 1) % self.superblock.inodes_per_group;
+
+        Ok(self.superblock.block_offset(group.inode_table) + (u64::from(index) * u64::from(self.superblock.inode_size)))
+    }
+
+    pub(crate) fn read_inode(&self, inode: u32) -> Result<RawInode> {
+        let mut bytes = vec![0u8; usize::from(self.superblock.inode_size)];
+        read_bytes(&self.device, self.inode_offset(inode)?, &mut bytes)?;
+
+        Ok(RawInode::from_bytes(bytes))
+    }
+
+    pub(crate) fn write_inode(&self, inode: u32, raw: &RawInode) -> Result<()> {
+        Ok(write_bytes(&self.device, self.inode_offset(inode)?, raw.as_bytes())?)
+    }
+
+    pub(crate) fn file_type_of(raw: &RawInode) -> Result<FileType> {
+        raw.file_type().ok_or(Error::Corrupt { reason: "inode has an invalid mode" })
+    }
+
+    /// Returns the attributes of `inode`.
+    pub fn stat(&self, inode: u32) -> Result<Stat> {
+        let raw = self.read_inode(inode)?;
+
+        Ok(raw.stat(inode, Self::file_type_of(&raw)?))
+    }
+
+    /// Whether the symbolic link `raw` stores its target within its block pointers.
+    pub(crate) fn is_fast_symlink(&self, raw: &RawInode) -> bool {
+        let attribute_sectors = if raw.attribute_block() == 0 { 0 } else { self.sectors_per_block() };
+
+        raw.file_type() == Some(FileType::Symlink)
+            && raw.flags() & (FLAG_EXTENTS | FLAG_INLINE_DATA) == 0
+            && raw.sectors() == attribute_sectors
+            && raw.size() < u64::try_from(FAST_SYMLINK_MAX_LEN).unwrap()
+    }
+
+    /* ALLOCATION */
+
+    fn write_group(&self, group: u32) -> Result<()> {
+        let descriptor_size = usize::from(self.superblock.descriptor_size);
+        let offset =
+            self.superblock.descriptor_table_offset() + (u64::from(group) * u64::try_from(descriptor_size).unwrap());
+
+        let mut bytes = vec![0u8; descriptor_size];
+        read_bytes(&self.device, offset, &mut bytes)?;
+        self.groups[usize::try_from(group).unwrap()].write_counts(&mut bytes);
+        Ok(write_bytes(&self.device, offset, &bytes)?)
+    }
+
+    /// Finds the first clear bit among the first `count` bits of `bitmap_block`, sets it, and returns its index.
+    fn allocate_bit(&self, bitmap_block: u64, count: u32) -> Result<Option<u32>> {
+        let mut bitmap = vec![0u8; self.block_size_usize()];
+        self.read_block(bitmap_block, &mut bitmap)?;
+
+        let Some(bit) = (0..count).find(|bit| bitmap[usize::try_from(bit / 8).unwrap()] & (1 << (bit % 8)) == 0) else {
+            return Ok(None);
+        };
+
+        let byte_index = usize::try_from(bit / 8).unwrap();
+        bitmap[byte_index] |= 1 << (bit % 8);
+        write_bytes(
+            &self.device,
+            self.superblock.block_offset(bitmap_block) + u64::try_from(byte_index).unwrap(),
+            &bitmap[byte_index..=byte_index],
+        )?;
+
+        Ok(Some(bit))
+    }
+
+    /// Clears the bit `bit` of `bitmap_block`, returning whether it was set.
+    fn free_bit(&self, bitmap_block: u64, bit: u32) -> Result<bool> {
+        let offset = self.superblock.block_offset(bitmap_block) + u64::from(bit / 8);
+        let mut byte = [0u8];
+        read_bytes(&self.device, offset, &mut byte)?;
+
+        if byte[0] & (1 << (bit % 8)) == 0 {
+            return Ok(false);
+        }
+
+        byte[0] &= !(1 << (bit % 8));
+        write_bytes(&self.device, offset, &byte)?;
+
+        Ok(true)
+    }
+
+    /// Groups in the order they're searched for free space, starting from `goal`.
+    fn groups_from(&self, goal: u32) -> impl Iterator<Item = u32> {
+        let group_count = self.superblock.group_count;
+
+        (0..group_count).map(move |offset| (goal + offset) % group_count)
+    }
+
+    /// Allocates a zeroed block, preferring the group `goal`.
+    fn allocate_block(&mut self, goal: u32) -> Result<u64> {
+        for group in self.groups_from(goal) {
+            let descriptor = self.groups[usize::try_from(group).unwrap()];
+            if descriptor.free_blocks_count == 0 {
+                continue;
+            }
+
+            let Some(bit) = self.allocate_bit(descriptor.block_bitmap, self.superblock.blocks_in_group(group))? else {
+                continue;
+            };
+
+            let block = u64::from(self.superblock.first_data_block)
+                + (u64::from(group) * u64::from(self.superblock.blocks_per_group))
+                + u64::from(bit);
+
+            self.groups[usize::try_from(group).unwrap()].free_blocks_count -= 1;
+            self.write_group(group)?;
+            self.superblock.free_blocks_count = self.superblock.free_blocks_count.saturating_sub(1);
+            self.superblock_dirty = true;
+
+            self.write_block(block, &vec![0u8; self.block_size_usize()])?;
+
+            return Ok(block);
+        }
+
+        Err(Error::NoSpace)
+    }
+
+    fn free_block(&mut self, block: u64) -> Result<()> {
+        let first_data_block = u64::from(self.superblock.first_data_block);
+        if block < first_data_block || block >= self.superblock.blocks_count {
+            return Err(Error::Corrupt { reason: "block number out of range" });
+        }
+
+        let blocks_per_group = u64::from(self.superblock.blocks_per_group);
+        let group = u32::try_from((block - first_data_block) / blocks_per_group).unwrap();
+        let bit = u32::try_from((block - first_data_block) % blocks_per_group).unwrap();
+
+        let descriptor = self.groups[usize::try_from(group).unwrap()];
+        if !self.free_bit(descriptor.block_bitmap, bit)? {
+            return Err(Error::Corrupt { reason: "freed block was already free" });
+        }
+
+        let descriptor = &mut self.groups[usize::try_from(group).unwrap()];
+        descriptor.free_blocks_count += 1;
+        self.write_group(group)?;
+        self.superblock.free_blocks_count += 1;
+        self.superblock_dirty = true;
+
+        Ok(())
+    }
+
+    /// Allocates an inode, preferring the group `goal`. The inode's contents are left as they were.
+    pub(crate) fn allocate_inode(&mut self, goal: u32, is_directory: bool) -> Result<u32> {
+        for group in self.groups_from(goal) {
+            let descriptor = self.groups[usize::try_from(group).unwrap()];
+            if descriptor.free_inodes_count == 0 {
+                continue;
+            }
+
+            let Some(bit) = self.allocate_bit(descriptor.inode_bitmap, self.superblock.inodes_per_group)? else {
+                continue;
+            };
+
+            let inode = (group * self.superblock.inodes_per_group) + bit + 1;
+            if inode < self.superblock.first_inode || inode > self.superblock.inodes_count {
+                return Err(Error::Corrupt { reason: "reserved inode is marked free" });
+            }
+
+            let descriptor = &mut self.groups[usize::try_from(group).unwrap()];
+            descriptor.free_inodes_count -= 1;
+            if is_directory {
+                descriptor.used_dirs_count += 1;
+            }
+            self.write_group(group)?;
+            self.superblock.free_inodes_count = self.superblock.free_inodes_count.saturating_sub(1);
+            self.superblock_dirty = true;
+
+            return Ok(inode);
+        }
+
+        Err(Error::NoSpace)
+    }
+
+    pub(crate) fn free_inode(&mut self, inode: u32, is_directory: bool) -> Result<()> {
+        let group = self.group_of_inode(inode);
+        let bit = (inode - 1) % self.superblock.inodes_per_group;
+
+        let descriptor = self.groups[usize::try_from(group).unwrap()];
+        if !self.free_bit(descriptor.inode_bitmap, bit)? {
+            return Err(Error::Corrupt { reason: "freed inode was already free" });
+        }
+
+        let descriptor = &mut self.groups[usize::try_from(group).unwrap()];
+        descriptor.free_inodes_count += 1;
+        if is_directory {
+            descriptor.used_dirs_count = descriptor.used_dirs_count.saturating_sub(1);
+        }
+        self.write_group(group)?;
+        self.superblock.free_inodes_count += 1;
+        self.superblock_dirty = true;
+
+        Ok(())
+    }
+
+    /// Allocates a block for the data (or block map) of `raw`, counting it among the inode's blocks.
+    fn allocate_inode_block(&mut self, inode: u32, raw: &mut RawInode) -> Result<u64> {
+        let block = self.allocate_block(self.group_of_inode(inode))?;
+        raw.set_sectors(raw.sectors() + self.sectors_per_block());
+
+        Ok(block)
+    }
+
+    fn free_inode_block(&mut self, raw: &mut RawInode, block: u64) -> Result<()> {
+        self.free_block(block)?;
+        raw.set_sectors(raw.sectors().saturating_sub(self.sectors_per_block()));
+
+        Ok(())
+    }
+
+    /* BLOCK MAPPING */
+
+    /// Locates the pointer to the file block `block` within a block map.
+    fn block_path(&self, block: u64) -> Result<BlockPath> {
+        let pointers = self.pointers_per_block();
+
+        if block < DIRECT_BLOCKS {
+            return Ok(BlockPath { slot: usize::try_from(block).unwrap(), depth: 0, index: 0 });
+        }
+
+        let mut index = block - DIRECT_BLOCKS;
+        for depth in 1..=3 {
+            let span = pointers.pow(depth);
+            if index < span {
+                return Ok(BlockPath {
+                    slot: usize::try_from(DIRECT_BLOCKS).unwrap() + usize::try_from(depth - 1).unwrap(),
+                    depth,
+                    index,
+                });
+            }
+
+            index -= span;
+        }
+
+        Err(Error::FileTooLarge)
+    }
+
+    /// Largest size of a file whose blocks are mapped by pointers.
+    fn max_file_size(&self) -> u64 {
+        let pointers = self.pointers_per_block();
+        let blocks = DIRECT_BLOCKS + pointers + pointers.pow(2) + pointers.pow(3);
+        let max_size = blocks.saturating_mul(u64::from(self.superblock.block_size));
+
+        if self.superblock.features.has_read_only_compatible(Features::RO_COMPAT_LARGE_FILE) {
+            // The count of allocated sectors is 32 bits wide, which limits files to 2 TiB.
+            max_size.min(u64::from(u32::MAX) * 512)
+        } else {
+            max_size.min(SMALL_FILE_MAX_SIZE)
+        }
+    }
+
+    fn read_pointer(&self, block: u64, index: u64) -> Result<u32> {
+        let mut pointer = [0u8; 4];
+        read_bytes(&self.device, self.superblock.block_offset(block) + (index * 4), &mut pointer)?;
+
+        Ok(u32::from_le_bytes(pointer))
+    }
+
+    fn write_pointer(&self, block: u64, index: u64, pointer: u32) -> Result<()> {
+        Ok(write_bytes(&self.device, self.superblock.block_offset(block) + (index * 4), &pointer.to_le_bytes())?)
+    }
+
+    fn check_block_number(&self, block: u64) -> Result<u64> {
+        if block < self.superblock.blocks_count {
+            Ok(block)
+        } else {
+            Err(Error::Corrupt { reason: "block pointer beyond the filesystem" })
+        }
+    }
+
+    /// Maps the file block `block` of `raw` to a block of the filesystem, or `None` if it's a hole.
+    pub(crate) fn map_block(&self, raw: &RawInode, block: u64) -> Result<Option<u64>> {
+        if raw.flags() & FLAG_INLINE_DATA != 0 {
+            return Err(Error::Unsupported { reason: "inline data" });
+        }
+
+        if raw.flags() & FLAG_EXTENTS != 0 {
+            return self.map_extent(raw, block);
+        }
+
+        let path = self.block_path(block)?;
+        let mut current = u64::from(raw.block(path.slot));
+        let pointers = self.pointers_per_block();
+
+        for level in (0..path.depth).rev() {
+            if current == 0 {
+                return Ok(None);
+            }
+
+            let span = pointers.pow(level);
+            current = u64::from(self.read_pointer(self.check_block_number(current)?, (path.index / span) % pointers)?);
+        }
+
+        if current == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(self.check_block_number(current)?))
+        }
+    }
+
+    /// Maps the file block `block` of `raw`, allocating it (and any blocks of pointers leading to it) if it's a hole.
+    ///
+    /// Changes to the inode's block pointers and allocated blocks are made to `raw`, which the caller writes back.
+    fn map_block_for_write(&mut self, inode: u32, raw: &mut RawInode, block: u64) -> Result<u64> {
+        let path = self.block_path(block)?;
+        let pointers = self.pointers_per_block();
+
+        let mut current = u64::from(raw.block(path.slot));
+        if current == 0 {
+            current = self.allocate_inode_block(inode, raw)?;
+            raw.set_block(path.slot, u32::try_from(current).map_err(|_| Error::FileTooLarge)?);
+        }
+
+        for level in (0..path.depth).rev() {
+            let index = (path.index / pointers.pow(level)) % pointers;
+            let parent = self.check_block_number(current)?;
+
+            current = u64::from(self.read_pointer(parent, index)?);
+            if current == 0 {
+                current = self.allocate_inode_block(inode, raw)?;
+                self.write_pointer(parent, index, u32::try_from(current).map_err(|_| Error::FileTooLarge)?)?;
+            }
+        }
+
+        self.check_block_number(current)
+    }
+
+    /// Maps the file block `block` through the extent tree rooted in `raw`'s block pointers.
+    fn map_extent(&self, raw: &RawInode, block: u64) -> Result<Option<u64>> {
+        let Ok(block) = u32::try_from(block) else {
+            return Ok(None);
+        };
+
+        let mut node = raw.block_bytes().to_vec();
+
+        for _ in 0..EXTENT_MAX_DEPTH {
+            if read_u16(&node, 0) != EXTENT_MAGIC {
+                return Err(Error::Corrupt { reason: "invalid extent header" });
+            }
+
+            let entries = usize::from(read_u16(&node, 2));
+            let depth = read_u16(&node, 6);
+            if 12 + (entries * 12) > node.len() {
+                return Err(Error::Corrupt { reason: "extent node overflows its block" });
+            }
+
+            // Entries are sorted by their first block, so the last which starts before `block` is the one to follow.
+            let Some(entry) =
+                (0..entries).map(|index| 12 + (index * 12)).take_while(|entry| read_u32(&node, *entry) <= block).last()
+            else {
+                return Ok(None);
+            };
+
+            let first_block = read_u32(&node, entry);
+
+            if depth == 0 {
+                let len = read_u16(&node, entry + 4);
+                let start = (u64::from(read_u16(&node, entry + 6)) << 32) | u64::from(read_u32(&node, entry + 8));
+                let (len, initialized) = if len > EXTENT_MAX_INITIALIZED_LEN {
+                    (len - EXTENT_MAX_INITIALIZED_LEN, false)
+                } else {
+                    (len, true)
+                };
+
+                let within = block - first_block;
+                return if within >= u32::from(len) || !initialized {
+                    Ok(None)
+                } else {
+                    Ok(Some(self.check_block_number(start + u64::from(within))?))
+                };
+            }
+
+            let child = u64::from(read_u32(&node, entry + 4)) | (u64::from(read_u16(&node, entry + 8)) << 32);
+            node.resize(self.block_size_usize(), 0);
+            self.read_block(self.check_block_number(child)?, &mut node)?;
+        }
+
+        Err(Error::Corrupt { reason: "extent tree is too deep" })
+    }
+
+    /* DATA */
+
+    /// Reads from the data of `inode` at the byte offset `offset`, returning the number of bytes read (which is zero at
+    /// or beyond the end of the file).
+    pub fn read(&self, inode: u32, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        let raw = self.read_inode(inode)?;
+
+        self.read_data(&raw, offset, buffer)
+    }
+
+    pub(crate) fn read_data(&self, raw: &RawInode, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        let size = raw.size();
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let len = usize::try_from((size - offset).min(u64::try_from(buffer.len()).unwrap())).unwrap();
+        let buffer = &mut buffer[..len];
+
+        if self.is_fast_symlink(raw) {
+            let start = usize::try_from(offset).unwrap();
+            buffer.copy_from_slice(&raw.block_bytes()[start..(start + len)]);
+
+            return Ok(len);
+        }
+
+        let block_size = u64::from(self.superblock.block_size);
+        let mut position = offset;
+        let mut done = 0;
+
+        while done < len {
+            let within = position % block_size;
+            let chunk = usize::try_from((block_size - within).min(u64::try_from(len - done).unwrap())).unwrap();
+            let target = &mut buffer[done..(done + chunk)];
+
+            match self.map_block(raw, position / block_size)? {
+                Some(block) => read_bytes(&self.device, self.superblock.block_offset(block) + within, target)?,
+                None => target.fill(0),
+            }
+
+            done += chunk;
+            position += u64::try_from(chunk).unwrap();
+        }
+
+        Ok(len)
+    }
+
+    /// Writes `buffer` to the regular file `inode` at the byte offset `offset`, extending the file as needed. Any gap
+    /// between the end of the file and `offset` is left as a hole.
+    pub fn write(&mut self, inode: u32, offset: u64, buffer: &[u8]) -> Result<usize> {
+        self.check_writable()?;
+
+        let mut raw = self.read_inode(inode)?;
+        match Self::file_type_of(&raw)? {
+            FileType::Regular => {}
+            FileType::Directory => return Err(Error::IsDirectory),
+            _ => return Err(Error::Unsupported { reason: "writing to special files" }),
+        }
+
+        self.write_data(inode, &mut raw, offset, buffer)
+    }
+
+    /// Writes `buffer` to the data of `raw`, and writes back the inode.
+    pub(crate) fn write_data(&mut self, inode: u32, raw: &mut RawInode, offset: u64, buffer: &[u8]) -> Result<usize> {
+        if raw.flags() & (FLAG_EXTENTS | FLAG_INLINE_DATA) != 0 {
+            return Err(Error::ReadOnly);
+        }
+
+        let end = offset.checked_add(u64::try_from(buffer.len()).unwrap()).ok_or(Error::FileTooLarge)?;
+        if end > self.max_file_size() {
+            return Err(Error::FileTooLarge);
+        }
+
+        let block_size = u64::from(self.superblock.block_size);
+        let mut position = offset;
+        let mut done = 0;
+
+        while done < buffer.len() {
+            let within = position % block_size;
+            let chunk =
+                usize::try_from((block_size - within).min(u64::try_from(buffer.len() - done).unwrap())).unwrap();
+
+            let result = self.map_block_for_write(inode, raw, position / block_size).and_then(|block| {
+                write_bytes(&self.device, self.superblock.block_offset(block) + within, &buffer[done..(done + chunk)])
+                    .map_err(Error::from)
+            });
+
+            if let Err(err) = result {
+                // Blocks allocated before the failure still belong to the inode, so it's written back regardless.
+                if position > raw.size() {
+                    raw.set_size(position);
+                }
+                self.write_inode(inode, raw)?;
+
+                return Err(err);
+            }
+
+            done += chunk;
+            position += u64::try_from(chunk).unwrap();
+        }
+
+        if end > raw.size() {
+            raw.set_size(end);
+        }
+        self.write_inode(inode, raw)?;
+
+        Ok(buffer.len())
+    }
+
+    /// Sets the size of the regular file `inode` to `len`, freeing any blocks beyond it. Extending a file leaves a
+    /// hole.
+    pub fn truncate(&mut self, inode: u32, len: u64) -> Result<()> {
+        self.check_writable()?;
+
+        let mut raw = self.read_inode(inode)?;
+        match Self::file_type_of(&raw)? {
+            FileType::Regular => {}
+            FileType::Directory => return Err(Error::IsDirectory),
+            _ => return Err(Error::Unsupported { reason: "truncating special files" }),
+        }
+
+        self.truncate_data(inode, &mut raw, len)
+    }
+
+    /// Sets the size of `raw` to `len`, frees its blocks beyond it, and writes back the inode.
+    pub(crate) fn truncate_data(&mut self, inode: u32, raw: &mut RawInode, len: u64) -> Result<()> {
+        if raw.flags() & (FLAG_EXTENTS | FLAG_INLINE_DATA) != 0 {
+            return Err(Error::ReadOnly);
+        }
+
+        if len > self.max_file_size() {
+            return Err(Error::FileTooLarge);
+        }
+
+        if len < raw.size() {
+            let block_size = u64::from(self.superblock.block_size);
+            let kept_blocks = len.div_ceil(block_size);
+
+            for slot in kept_blocks..DIRECT_BLOCKS {
+                let slot = usize::try_from(slot).unwrap();
+                let block = u64::from(raw.block(slot));
+                if block != 0 {
+                    self.free_inode_block(raw, block)?;
+                    raw.set_block(slot, 0);
+                }
+            }
+
+            let pointers = self.pointers_per_block();
+            let mut first_block = DIRECT_BLOCKS;
+            for depth in 1..=3 {
+                let slot = usize::try_from(DIRECT_BLOCKS).unwrap() + usize::try_from(depth - 1).unwrap();
+                let span = pointers.pow(depth);
+                let block = u64::from(raw.block(slot));
+
+                if block != 0
+                    && first_block + span > kept_blocks
+                    && self.truncate_pointers(raw, self.check_block_number(block)?, depth, first_block, kept_blocks)?
+                {
+                    self.free_inode_block(raw, block)?;
+                    raw.set_block(slot, 0);
+                }
+
+                first_block += span;
+            }
+
+            // The rest of the last block is zeroed, so that it reads as zeroes if the file is extended again.
+            let within = len % block_size;
+            if within != 0 {
+                if let Some(block) = self.map_block(raw, len / block_size)? {
+                    let zeroes = vec![0u8; usize::try_from(block_size - within).unwrap()];
+                    write_bytes(&self.device, self.superblock.block_offset(block) + within, &zeroes)?;
+                }
+            }
+        }
+
+        raw.set_size(len);
+        self.write_inode(inode, raw)
+    }
+
+    /// Frees the file blocks from `kept_blocks` onwards which are reachable from the block of pointers `block`, whose
+    /// first file block is `first_block`. Returns whether every pointer of the block is now clear, in which case the
+    /// caller frees it.
+    fn truncate_pointers(
+        &mut self,
+        raw: &mut RawInode,
+        block: u64,
+        depth: u32,
+        first_block: u64,
+        kept_blocks: u64,
+    ) -> Result<bool> {
+        let mut pointers = vec![0u8; self.block_size_usize()];
+        self.read_block(block, &mut pointers)?;
+
+        let span = self.pointers_per_block().pow(depth - 1);
+        let mut changed = false;
+        let mut is_empty = true;
+
+        for index in 0..self.pointers_per_block() {
+            let offset = usize::try_from(index * 4).unwrap();
+            let child = u64::from(read_u32(&pointers, offset));
+            if child == 0 {
+                continue;
+            }
+
+            let child_first_block = first_block + (index * span);
+            let free = if child_first_block + span <= kept_blocks {
+                false
+            } else if depth == 1 {
+                true
+            } else {
+                self.truncate_pointers(raw, self.check_block_number(child)?, depth - 1, child_first_block, kept_blocks)?
+            };
+
+            if free {
+                self.free_inode_block(raw, child)?;
+                write_u32(&mut pointers, offset, 0);
+                changed = true;
+            } else {
+                is_empty = false;
+            }
+        }
+
+        if changed && !is_empty {
+            self.write_block(block, &pointers)?;
+        }
+
+        Ok(is_empty)
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{Error, Result};
+
+/// Largest cluster count of a FAT12 volume, plus one.
+pub const FAT12_MAX_CLUSTERS: u32 = 4085;
+/// Largest cluster count of a FAT16 volume, plus one.
+pub const FAT16_MAX_CLUSTERS: u32 = 65525;
+/// Largest cluster count of a FAT32 volume, as cluster numbers are only 28 bits wide.
+pub const FAT32_MAX_CLUSTERS: u32 = 0x0FFF_FFF5;
+
+/// Width of the entries of the file allocation table.
+///
+/// This is determined solely by the volume's cluster count, as the specification requires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    pub const fn from_cluster_count(cluster_count: u32) -> Self {
+        if cluster_count < FAT12_MAX_CLUSTERS {
+            Self::Fat12
+        } else if cluster_count < FAT16_MAX_CLUSTERS {
+            Self::Fat16
+        } else {
+            Self::Fat32
+        }
+    }
+
+    /// Value at or above which an entry marks the end of a cluster chain.
+    pub(crate) const fn end_of_chain(self) -> u32 {
+        match self {
+            Self::Fat12 => 0xFF8,
+            Self::Fat16 => 0xFFF8,
+            Self::Fat32 => 0x0FFF_FFF8,
+        }
+    }
+
+    /// Value which marks a cluster as bad.
+    pub(crate) const fn bad_cluster(self) -> u32 {
+        self.end_of_chain() - 1
+    }
+
+    /// File system type string recorded in the boot sector.
+    pub(crate) const fn label(self) -> &'static [u8; 8] {
+        match self {
+            Self::Fat12 => b"FAT12   ",
+            Self::Fat16 => b"FAT16   ",
+            Self::Fat32 => b"FAT32   ",
+        }
+    }
+}
+
+pub(crate) const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+/// Marks the extended boot record fields (volume ID, label, and type string) as present.
+pub(crate) const EXTENDED_BOOT_SIGNATURE: u8 = 0x29;
+
+pub(crate) fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+pub(crate) fn write_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..(offset + 2)].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..(offset + 4)].copy_from_slice(&value.to_le_bytes());
+}
+
+/// The BIOS parameter block of a volume, along with the layout derived from it.
+#[derive(Debug, Clone)]
+pub struct BootSector {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub fat_count: u8,
+    pub root_entry_count: u16,
+    pub total_sectors: u32,
+    pub fat_size: u32,
+    /// FAT32 only: the FAT which is in use, if the FATs aren't mirrored.
+    pub active_fat: Option<u8>,
+    /// FAT32 only: first cluster of the root directory.
+    pub root_cluster: u32,
+    /// FAT32 only: sector of the FSInfo structure.
+    pub fs_info_sector: Option<u16>,
+    pub volume_id: u32,
+    pub volume_label: [u8; 11],
+
+    pub fat_type: FatType,
+    pub cluster_count: u32,
+}
+
+impl BootSector {
+    /// Parses and validates the boot sector at the start of `bytes`.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 512 || bytes[510..512] != BOOT_SIGNATURE {
+            return Err(Error::InvalidBootSector { reason: "missing boot signature" });
+        }
+
+        if bytes[0] != 0xEB && bytes[0] != 0xE9 {
+            return Err(Error::InvalidBootSector { reason: "missing jump instruction" });
+        }
+
+        let bytes_per_sector = read_u16(bytes, 11);
+        if !(512..=4096).contains(&bytes_per_sector) || !bytes_per_sector.is_power_of_two() {
+            return Err(Error::InvalidBootSector { reason: "invalid bytes per sector" });
+        }
+
+        let sectors_per_cluster = bytes[13];
+        if sectors_per_cluster == 0 || !sectors_per_cluster.is_power_of_two() {
+            return Err(Error::InvalidBootSector { reason: "invalid sectors per cluster" });
+        }
+
+        let reserved_sectors = read_u16(bytes, 14);
+        if reserved_sectors == 0 {
+            return Err(Error::InvalidBootSector { reason: "no reserved sectors" });
+        }
+
+        let fat_count = bytes[16];
+        if fat_count == 0 {
+            return Err(Error::InvalidBootSector { reason: "no FATs" });
+        }
+
+        let root_entry_count = read_u16(bytes, 17);
+        let total_sectors = match read_u16(bytes, 19) {
+            0 => read_u32(bytes, 32),
+            total_sectors => u32::from(total_sectors),
+        };
+        let fat_size = match read_u16(bytes, 22) {
+            0 => read_u32(bytes, 36),
+            fat_size => u32::from(fat_size),
+        };
+        if fat_size == 0 {
+            return Err(Error::InvalidBootSector { reason: "FATs are empty" });
+        }
+
+        let root_dir_sectors = (u32::from(root_entry_count) * 32).div_ceil(u32::from(bytes_per_sector));
+        let metadata_sectors = u32::from(reserved_sectors)
+            .checked_add(u32::from(fat_count).saturating_mul(fat_size))
+            .and_then(|sectors| sectors.checked_add(root_dir_sectors))
+            .filter(|sectors| *sectors < total_sectors)
+            .ok_or(Error::InvalidBootSector { reason: "no sectors remain for data" })?;
+        let cluster_count = (total_sectors - metadata_sectors) / u32::from(sectors_per_cluster);
+        let fat_type = FatType::from_cluster_count(cluster_count);
+
+        // Every cluster (and the two reserved entries) must have an entry in the FAT.
+        let fat_entries = match fat_type {
+            FatType::Fat12 => u64::from(fat_size) * u64::from(bytes_per_sector) * 2 / 3,
+            FatType::Fat16 => u64::from(fat_size) * u64::from(bytes_per_sector) / 2,
+            FatType::Fat32 => u64::from(fat_size) * u64::from(bytes_per_sector) / 4,
+        };
+        if fat_entries < u64::from(cluster_count) + 2 {
+            return Err(Error::InvalidBootSector { reason: "FATs are too small for the volume" });
+        }
+
+        let (active_fat, root_cluster, fs_info_sector, extended_offset) = if fat_type == FatType::Fat32 {
+            if root_entry_count != 0 {
+                return Err(Error::InvalidBootSector { reason: "FAT32 volume has a fixed root directory" });
+            }
+
+            if read_u16(bytes, 42) != 0 {
+                return Err(Error::Unsupported { reason: "FAT32 version" });
+            }
+
+            let ext_flags = read_u16(bytes, 40);
+            // Bit 7 disables mirroring, in which case only the FAT numbered in the low nibble is in use.
+            let active_fat = if ext_flags & (1 << 7) != 0 {
+                let active_fat = u8::try_from(ext_flags & 0xF).unwrap();
+                if active_fat >= fat_count {
+                    return Err(Error::InvalidBootSector { reason: "active FAT doesn't exist" });
+                }
+
+                Some(active_fat)
+            } else {
+                None
+            };
+
+            let root_cluster = read_u32(bytes, 44);
+            if !(2..(cluster_count + 2)).contains(&root_cluster) {
+                return Err(Error::InvalidBootSector { reason: "root cluster is outside the volume" });
+            }
+
+            let fs_info_sector = match read_u16(bytes, 48) {
+                0 | 0xFFFF => None,
+                sector => Some(sector).filter(|sector| *sector < reserved_sectors),
+            };
+
+            (active_fat, root_cluster, fs_info_sector, 64)
+        } else {
+            if root_entry_count == 0 {
+                return Err(Error::InvalidBootSector { reason: "FAT12/16 volume has no root directory" });
+            }
+
+            (None, 0, None, 36)
+        };
+
+        let (volume_id, volume_label) = if bytes[extended_offset + 2] == EXTENDED_BOOT_SIGNATURE {
+            let mut volume_label = [0u8; 11];
+            volume_label.copy_from_slice(&bytes[(extended_offset + 7)..(extended_offset + 18)]);
+
+            (read_u32(bytes, extended_offset + 3), volume_label)
+        } else {
+            (0, *b"NO NAME    ")
+        };
+
+        Ok(Self {
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            fat_count,
+            root_entry_count,
+            total_sectors,
+            fat_size,
+            active_fat,
+            root_cluster,
+            fs_info_sector,
+            volume_id,
+            volume_label,
+            fat_type,
+            cluster_count,
+        })
+    }
+
+    /// Size of a cluster, in bytes.
+    pub fn cluster_size(&self) -> u32 {
+        u32::from(self.sectors_per_cluster) * u32::from(self.bytes_per_sector)
+    }
+
+    fn sector_offset(&self, sector: u32) -> u64 {
+        u64::from(sector) * u64::from(self.bytes_per_sector)
+    }
+
+    /// Byte offset of the FAT numbered `index`.
+    pub fn fat_offset(&self, index: u8) -> u64 {
+        self.sector_offset(u32::from(self.reserved_sectors) + (u32::from(index) * self.fat_size))
+    }
+
+    /// Byte offset of the FAT12/16 root directory.
+    pub fn root_dir_offset(&self) -> u64 {
+        self.fat_offset(self.fat_count)
+    }
+
+    /// Size of the FAT12/16 root directory, in bytes.
+    pub fn root_dir_size(&self) -> u64 {
+        u64::from(self.root_entry_count) * 32
+    }
+
+    /// Byte offset of the first cluster of the data region.
+    pub fn data_offset(&self) -> u64 {
+        let root_dir_sectors = self.root_dir_size().div_ceil(u64::from(self.bytes_per_sector));
+
+        self.root_dir_offset() + (root_dir_sectors * u64::from(self.bytes_per_sector))
+    }
+
+    /// Byte offset of `cluster`, which must be a data cluster (i.e. at least 2).
+    pub fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_offset() + (u64::from(cluster - 2) * u64::from(self.cluster_size()))
+    }
+}
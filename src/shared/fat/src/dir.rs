@@ -0,0 +1,531 @@
+use crate::{
+    boot_sector::{read_u16, read_u32, write_u16, write_u32},
+    volume::{FileSystem, Node},
+    BlockDevice, Error, FatType, Result,
+};
+use alloc::{format, string::String, vec::Vec};
+
+pub(crate) const ENTRY_SIZE: usize = 32;
+/// First byte of an entry which has been deleted.
+const DELETED: u8 = 0xE5;
+/// First byte of the entry which ends a directory, after which every entry is free.
+const END: u8 = 0x00;
+/// Stands in for a leading `0xE5` of a short name, which would otherwise mark the entry deleted.
+const ESCAPED_DELETED: u8 = 0x05;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_HIDDEN: u8 = 0x02;
+const ATTR_SYSTEM: u8 = 0x04;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+/// Attributes of a long name entry, which no short entry can have.
+const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+/// Set on the ordinal of the last long name entry of a name, which is stored first.
+const LAST_LONG_ENTRY: u8 = 0x40;
+/// Number of UTF-16 code units stored in each long name entry.
+const LONG_NAME_UNITS: usize = 13;
+/// Byte offsets of the code units within a long name entry.
+const LONG_NAME_OFFSETS: [usize; LONG_NAME_UNITS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const MAX_LONG_NAME_LEN: usize = 255;
+
+/// Set by Windows NT when the base of a short name should be displayed in lowercase.
+const NT_LOWERCASE_BASE: u8 = 0x08;
+/// Set by Windows NT when the extension of a short name should be displayed in lowercase.
+const NT_LOWERCASE_EXTENSION: u8 = 0x10;
+
+/// 1980-01-01, the FAT epoch, which new entries are dated with as no clock is available.
+const EPOCH_DATE: u16 = (1 << 5) | 1;
+
+/// Directories are limited to 65536 entries, so that entry indices fit in 16 bits.
+const MAX_DIRECTORY_SIZE: u64 = 65536 * 32;
+
+/// Checksum of a short name, which its long name entries record to detect that they've been orphaned.
+pub(crate) fn checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, byte| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(*byte))
+}
+
+fn is_short_name_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'()-@^_`{}~".contains(&byte)
+}
+
+/// Formats a short name for display (e.g. `README.TXT`), lowercasing its parts as the NT flags direct.
+fn display_short_name(short_name: &[u8; 11], nt_flags: u8) -> String {
+    let convert = |bytes: &[u8], lowercase: bool| -> String {
+        bytes
+            .iter()
+            .enumerate()
+            .map(|(index, byte)| if index == 0 && *byte == ESCAPED_DELETED { DELETED } else { *byte })
+            .map(|byte| if lowercase { byte.to_ascii_lowercase() } else { byte })
+            // Short names are in an OEM code page, whose lower half is ASCII.
+            .map(|byte| if byte.is_ascii() { char::from(byte) } else { char::REPLACEMENT_CHARACTER })
+            .collect::<String>()
+            .trim_end_matches(' ')
+            .into()
+    };
+
+    let base = convert(&short_name[..8], nt_flags & NT_LOWERCASE_BASE != 0);
+    let extension = convert(&short_name[8..], nt_flags & NT_LOWERCASE_EXTENSION != 0);
+
+    if extension.is_empty() {
+        base
+    } else {
+        format!("{base}.{extension}")
+    }
+}
+
+/// Compares names as FAT does, ignoring case.
+fn names_match(a: &str, b: &str) -> bool {
+    a.chars().flat_map(char::to_uppercase).eq(b.chars().flat_map(char::to_uppercase))
+}
+
+fn check_name(name: &str) -> Result<()> {
+    let is_invalid = name.is_empty()
+        || name == "."
+        || name == ".."
+        // Windows strips trailing dots and spaces, so such names couldn't be opened there.
+        || name.ends_with(['.', ' '])
+        || name.encode_utf16().count() > MAX_LONG_NAME_LEN
+        || name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c));
+
+    if is_invalid {
+        Err(Error::InvalidName)
+    } else {
+        Ok(())
+    }
+}
+
+/// Converts `name` directly to a short name, if it's a valid 8.3 name whose parts are each of a single case. The
+/// NT flags needed to display the short name in the original case are returned with it.
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+        return None;
+    }
+
+    let mut short_name = [b' '; 11];
+    let mut nt_flags = 0;
+    for (part, start, lowercase_flag) in [(base, 0, NT_LOWERCASE_BASE), (extension, 8, NT_LOWERCASE_EXTENSION)] {
+        if !part.bytes().all(is_short_name_byte) {
+            return None;
+        }
+
+        let has_lowercase = part.bytes().any(|byte| byte.is_ascii_lowercase());
+        if has_lowercase && part.bytes().any(|byte| byte.is_ascii_uppercase()) {
+            return None;
+        } else if has_lowercase {
+            nt_flags |= lowercase_flag;
+        }
+
+        short_name[start..(start + part.len())].copy_from_slice(part.to_ascii_uppercase().as_bytes());
+    }
+
+    Some((short_name, nt_flags))
+}
+
+/// Generates a short name for `name` with a numeric tail (e.g. `LONGFI~1.TXT`), which is unique among `entries`.
+fn generate_short_name(name: &str, entries: &[DirEntry]) -> Result<[u8; 11]> {
+    let name = name.trim_start_matches('.');
+    let (base, extension) = name.rsplit_once('.').unwrap_or((name, ""));
+
+    let basis = |part: &str, len: usize| -> Vec<u8> {
+        part.chars()
+            .filter(|c| *c != ' ' && *c != '.')
+            .map(|c| match u8::try_from(c.to_ascii_uppercase()) {
+                Ok(byte) if is_short_name_byte(byte) => byte,
+                _ => b'_',
+            })
+            .take(len)
+            .collect()
+    };
+
+    let base = basis(base, 8);
+    let extension = basis(extension, 3);
+
+    for number in 1..=999_999 {
+        let tail = format!("~{number}");
+        let base_len = base.len().min(8 - tail.len());
+
+        let mut short_name = [b' '; 11];
+        short_name[..base_len].copy_from_slice(&base[..base_len]);
+        short_name[base_len..(base_len + tail.len())].copy_from_slice(tail.as_bytes());
+        short_name[8..(8 + extension.len())].copy_from_slice(&extension);
+
+        if !entries.iter().any(|entry| entry.short_name == short_name) {
+            return Ok(short_name);
+        }
+    }
+
+    Err(Error::DirectoryFull)
+}
+
+fn short_entry(short_name: &[u8; 11], nt_flags: u8, attributes: u8, first_cluster: u32) -> [u8; ENTRY_SIZE] {
+    let mut entry = [0u8; ENTRY_SIZE];
+    entry[..11].copy_from_slice(short_name);
+    entry[11] = attributes;
+    entry[12] = nt_flags;
+    // Creation, last access, and last write dates.
+    write_u16(&mut entry, 16, EPOCH_DATE);
+    write_u16(&mut entry, 18, EPOCH_DATE);
+    write_u16(&mut entry, 24, EPOCH_DATE);
+    write_u16(&mut entry, 20, u16::try_from(first_cluster >> 16).unwrap());
+    write_u16(&mut entry, 26, u16::try_from(first_cluster & 0xFFFF).unwrap());
+    write_u32(&mut entry, 28, 0);
+
+    entry
+}
+
+/// Builds the long name entry holding the code units of `units` numbered `ordinal` (counting from 1).
+fn long_entry(units: &[u16], ordinal: usize, is_last: bool, checksum: u8) -> [u8; ENTRY_SIZE] {
+    let mut entry = [0u8; ENTRY_SIZE];
+    entry[0] = u8::try_from(ordinal).unwrap() | if is_last { LAST_LONG_ENTRY } else { 0 };
+    entry[11] = ATTR_LONG_NAME;
+    entry[13] = checksum;
+
+    let start = (ordinal - 1) * LONG_NAME_UNITS;
+    for (index, offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+        // Names which don't fill their last entry are null-terminated, then padded.
+        let unit = match units.get(start + index) {
+            Some(unit) => *unit,
+            None if start + index == units.len() => 0x0000,
+            None => 0xFFFF,
+        };
+
+        write_u16(&mut entry, *offset, unit);
+    }
+
+    entry
+}
+
+/// A long name being assembled from its entries, which precede the short entry they belong to in reverse order.
+struct LongName {
+    checksum: u8,
+    /// Ordinal of the entry expected next.
+    next_ordinal: u8,
+    units: Vec<u16>,
+    offsets: Vec<u64>,
+}
+
+impl LongName {
+    /// Adds the long name entry `entry` (at `offset`) to `current`, or begins a new long name if it's the last entry
+    /// of one. Returns `None` if the entry doesn't continue the name.
+    fn push(current: Option<Self>, offset: u64, entry: &[u8; ENTRY_SIZE]) -> Option<Self> {
+        let ordinal = entry[0] & !LAST_LONG_ENTRY;
+        let checksum = entry[13];
+
+        let mut long_name = if entry[0] & LAST_LONG_ENTRY != 0 {
+            if ordinal == 0 || usize::from(ordinal) * LONG_NAME_UNITS > MAX_LONG_NAME_LEN + LONG_NAME_UNITS {
+                return None;
+            }
+
+            Self {
+                checksum,
+                next_ordinal: ordinal,
+                units: alloc::vec![0xFFFF; usize::from(ordinal) * LONG_NAME_UNITS],
+                offsets: Vec::new(),
+            }
+        } else {
+            current.filter(|long_name| {
+                ordinal != 0 && long_name.next_ordinal == ordinal && long_name.checksum == checksum
+            })?
+        };
+
+        let start = usize::from(ordinal - 1) * LONG_NAME_UNITS;
+        for (index, offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+            long_name.units[start + index] = read_u16(entry, *offset);
+        }
+
+        long_name.offsets.push(offset);
+        long_name.next_ordinal = ordinal - 1;
+
+        Some(long_name)
+    }
+
+    /// Completes the long name, if every entry was found and they belong to the short name with `checksum`.
+    fn finish(self, checksum: u8) -> Option<(String, Vec<u64>)> {
+        if self.next_ordinal != 0 || self.checksum != checksum {
+            return None;
+        }
+
+        let len = self.units.iter().position(|unit| *unit == 0x0000).unwrap_or(self.units.len());
+        let name: String = char::decode_utf16(self.units[..len].iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+
+        if name.is_empty() {
+            None
+        } else {
+            Some((name, self.offsets))
+        }
+    }
+}
+
+/// An entry of a directory, excluding the `.` and `..` entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    name: String,
+    short_name: [u8; 11],
+    node: Node,
+    /// Offsets of the long name entries preceding the short entry.
+    long_name_offsets: Vec<u64>,
+}
+
+impl DirEntry {
+    /// Long name of the entry, or its short name if it has none.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Short (8.3) name of the entry, in uppercase (e.g. `LONGFI~1.TXT`).
+    pub fn short_name(&self) -> String {
+        display_short_name(&self.short_name, 0)
+    }
+
+    #[inline]
+    pub const fn node(&self) -> Node {
+        self.node
+    }
+
+    #[inline]
+    pub const fn is_directory(&self) -> bool {
+        self.node.is_directory
+    }
+
+    #[inline]
+    pub const fn size(&self) -> u32 {
+        self.node.size
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        names_match(&self.name, name) || names_match(&self.short_name(), name)
+    }
+}
+
+impl<D: BlockDevice> FileSystem<D> {
+    /// Whether `dir` is the fixed-size FAT12/16 root directory, which isn't stored in clusters.
+    fn is_fixed_root(&self, dir: &Node) -> bool {
+        dir.entry_offset.is_none() && dir.first_cluster == 0
+    }
+
+    /// Reads every entry slot of `dir`, along with its byte offset on the volume.
+    fn read_slots(&self, dir: &Node) -> Result<Vec<(u64, [u8; ENTRY_SIZE])>> {
+        if !dir.is_directory {
+            return Err(Error::NotDirectory);
+        }
+
+        let regions = if self.is_fixed_root(dir) {
+            alloc::vec![(self.boot_sector.root_dir_offset(), self.boot_sector.root_dir_size())]
+        } else {
+            let cluster_size = u64::from(self.cluster_size());
+            self.chain(dir.first_cluster)?
+                .into_iter()
+                .map(|cluster| (self.boot_sector.cluster_offset(cluster), cluster_size))
+                .collect()
+        };
+
+        let mut slots = Vec::new();
+        for (region_offset, region_size) in regions {
+            let mut region = alloc::vec![0u8; usize::try_from(region_size).unwrap()];
+            self.read_bytes(region_offset, &mut region)?;
+
+            slots.extend(
+                region
+                    .as_chunks::<ENTRY_SIZE>()
+                    .0
+                    .iter()
+                    .zip((region_offset..).step_by(ENTRY_SIZE))
+                    .map(|(slot, offset)| (offset, *slot)),
+            );
+        }
+
+        Ok(slots)
+    }
+
+    /// Lists the entries of `dir`.
+    pub fn read_dir(&self, dir: &Node) -> Result<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+        let mut long_name = None;
+
+        for (offset, slot) in self.read_slots(dir)? {
+            match slot[0] {
+                END => break,
+                DELETED => {
+                    long_name = None;
+                    continue;
+                }
+                _ => {}
+            }
+
+            if slot[11] & 0x3F == ATTR_LONG_NAME {
+                long_name = LongName::push(long_name.take(), offset, &slot);
+                continue;
+            }
+
+            let long_name = long_name.take();
+            if slot[11] & ATTR_VOLUME_ID != 0 || slot[0] == b'.' {
+                continue;
+            }
+
+            let short_name: [u8; 11] = slot[..11].try_into().unwrap();
+            let (name, long_name_offsets) = long_name
+                .and_then(|long_name| long_name.finish(checksum(&short_name)))
+                .unwrap_or_else(|| (display_short_name(&short_name, slot[12]), Vec::new()));
+
+            // The high word of the first cluster is only defined on FAT32.
+            let first_cluster_high = if self.fat_type() == FatType::Fat32 { read_u16(&slot, 20) } else { 0 };
+            let first_cluster = (u32::from(first_cluster_high) << 16) | u32::from(read_u16(&slot, 26));
+            let is_directory = slot[11] & ATTR_DIRECTORY != 0;
+
+            entries.push(DirEntry {
+                name,
+                short_name,
+                node: Node {
+                    entry_offset: Some(offset),
+                    first_cluster,
+                    size: if is_directory { 0 } else { read_u32(&slot, 28) },
+                    is_directory,
+                },
+                long_name_offsets,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Finds the entry of `dir` whose long or short name is `name`, ignoring case.
+    pub fn lookup(&self, dir: &Node, name: &str) -> Result<DirEntry> {
+        self.read_dir(dir)?.into_iter().find(|entry| entry.matches(name)).ok_or(Error::NotFound)
+    }
+
+    /// Label of the volume, as recorded in the root directory (or the boot sector, if the root has no label entry).
+    pub fn volume_label(&self) -> Result<Option<String>> {
+        let label_entry =
+            self.read_slots(&self.root())?.into_iter().take_while(|(_, slot)| slot[0] != END).find(|(_, slot)| {
+                slot[0] != DELETED && slot[11] & 0x3F != ATTR_LONG_NAME && slot[11] & ATTR_VOLUME_ID != 0
+            });
+
+        let label = match label_entry {
+            Some((_, slot)) => slot[..11].try_into().unwrap(),
+            None => self.boot_sector.volume_label,
+        };
+
+        let label: String = label.iter().map(|byte| char::from(*byte)).collect();
+        let label = label.trim_end_matches(' ');
+
+        Ok(Some(label).filter(|label| !label.is_empty() && *label != "NO NAME").map(String::from))
+    }
+
+    /// Finds `count` consecutive free slots in `dir`, growing it if there are none.
+    fn find_free_slots(&mut self, dir: &Node, count: usize) -> Result<Vec<u64>> {
+        let mut run = Vec::new();
+        let mut ended = false;
+        for (offset, slot) in self.read_slots(dir)? {
+            ended |= slot[0] == END;
+            if ended || slot[0] == DELETED {
+                run.push(offset);
+                if run.len() == count {
+                    return Ok(run);
+                }
+            } else {
+                run.clear();
+            }
+        }
+
+        if self.is_fixed_root(dir) {
+            return Err(Error::DirectoryFull);
+        }
+
+        let mut chain = self.chain(dir.first_cluster)?;
+        let cluster_size = u64::from(self.cluster_size());
+        while run.len() < count {
+            let &last = chain.last().ok_or(Error::Corrupt { reason: "directory has no clusters" })?;
+            if u64::try_from(chain.len()).unwrap() * cluster_size >= MAX_DIRECTORY_SIZE {
+                return Err(Error::DirectoryFull);
+            }
+
+            // The new cluster is zeroed, so each of its slots is free.
+            let cluster = self.allocate_cluster(Some(last))?;
+            chain.push(cluster);
+
+            let cluster_offset = self.boot_sector.cluster_offset(cluster);
+            run.extend((cluster_offset..(cluster_offset + cluster_size)).step_by(ENTRY_SIZE).take(count - run.len()));
+        }
+
+        Ok(run)
+    }
+
+    fn create(&mut self, dir: &Node, name: &str, is_directory: bool) -> Result<Node> {
+        check_name(name)?;
+
+        let entries = self.read_dir(dir)?;
+        if entries.iter().any(|entry| entry.matches(name)) {
+            return Err(Error::AlreadyExists);
+        }
+
+        let (short_name, nt_flags, long_name) = match exact_short_name(name) {
+            Some((short_name, nt_flags)) => (short_name, nt_flags, None),
+            None => (generate_short_name(name, &entries)?, 0, Some(name.encode_utf16().collect::<Vec<u16>>())),
+        };
+
+        let long_entry_count = long_name.as_ref().map_or(0, |units| units.len().div_ceil(LONG_NAME_UNITS));
+        let offsets = self.find_free_slots(dir, long_entry_count + 1)?;
+
+        let first_cluster = if is_directory {
+            let cluster = self.allocate_cluster(None)?;
+            // `..` refers to the root directory as cluster 0, even on FAT32.
+            let parent_cluster = if dir.entry_offset.is_none() { 0 } else { dir.first_cluster };
+
+            let cluster_offset = self.boot_sector.cluster_offset(cluster);
+            self.write_bytes(cluster_offset, &short_entry(b".          ", 0, ATTR_DIRECTORY, cluster))?;
+            self.write_bytes(
+                cluster_offset + u64::try_from(ENTRY_SIZE).unwrap(),
+                &short_entry(b"..         ", 0, ATTR_DIRECTORY, parent_cluster),
+            )?;
+
+            cluster
+        } else {
+            0
+        };
+
+        if let Some(units) = long_name {
+            let checksum = checksum(&short_name);
+
+            for (index, offset) in offsets[..long_entry_count].iter().enumerate() {
+                let ordinal = long_entry_count - index;
+                self.write_bytes(*offset, &long_entry(&units, ordinal, index == 0, checksum))?;
+            }
+        }
+
+        let attributes = if is_directory { ATTR_DIRECTORY } else { ATTR_ARCHIVE };
+        let entry_offset = offsets[long_entry_count];
+        self.write_bytes(entry_offset, &short_entry(&short_name, nt_flags, attributes, first_cluster))?;
+
+        Ok(Node { entry_offset: Some(entry_offset), first_cluster, size: 0, is_directory })
+    }
+
+    /// Creates an empty file named `name` in `dir`.
+    pub fn create_file(&mut self, dir: &Node, name: &str) -> Result<Node> {
+        self.create(dir, name, false)
+    }
+
+    /// Creates an empty directory named `name` in `dir`.
+    pub fn create_dir(&mut self, dir: &Node, name: &str) -> Result<Node> {
+        self.create(dir, name, true)
+    }
+
+    /// Removes the entry `name` of `dir`, freeing its clusters. Directories must be empty to be removed.
+    pub fn remove(&mut self, dir: &Node, name: &str) -> Result<()> {
+        let entry = self.lookup(dir, name)?;
+        if entry.is_directory() && !self.read_dir(&entry.node)?.is_empty() {
+            return Err(Error::DirectoryNotEmpty);
+        }
+
+        for offset in entry.long_name_offsets.iter().chain(entry.node.entry_offset.iter()) {
+            self.write_bytes(*offset, &[DELETED])?;
+        }
+
+        self.free_chain(entry.node.first_cluster)
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{
+    boot_sector::{
+        write_u16, write_u32, BOOT_SIGNATURE, EXTENDED_BOOT_SIGNATURE, FAT12_MAX_CLUSTERS, FAT16_MAX_CLUSTERS,
+        FAT32_MAX_CLUSTERS,
+    },
+    BlockDevice, Error, FatType, Result,
+};
+use alloc::vec;
+use block_device::write_bytes;
+
+/// Media descriptor of fixed disks.
+const MEDIA_FIXED: u8 = 0xF8;
+/// Sectors reserved before the FATs of a FAT32 volume, which hold the boot sector, FSInfo, and their backups.
+const FAT32_RESERVED_SECTORS: u16 = 32;
+const FAT32_FS_INFO_SECTOR: u16 = 1;
+const FAT32_BACKUP_BOOT_SECTOR: u16 = 6;
+/// Number of entries in the FAT12/16 root directory.
+const ROOT_ENTRY_COUNT: u16 = 512;
+
+/// Parameters of a volume created by [`format`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// Type of FAT to create, which is chosen from the size of the device if `None`.
+    pub fat_type: Option<FatType>,
+    /// Sectors in each cluster, which is chosen from the size of the device if `None`.
+    pub sectors_per_cluster: Option<u8>,
+    /// Volume label, padded with spaces.
+    pub volume_label: [u8; 11],
+    pub volume_id: u32,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self { fat_type: None, sectors_per_cluster: None, volume_label: *b"NO NAME    ", volume_id: 0 }
+    }
+}
+
+fn default_fat_type(volume_size: u64) -> FatType {
+    const MIB: u64 = 1024 * 1024;
+
+    if volume_size < 16 * MIB {
+        FatType::Fat12
+    } else if volume_size < 512 * MIB {
+        FatType::Fat16
+    } else {
+        FatType::Fat32
+    }
+}
+
+/// Smallest cluster size which keeps the volume's cluster count within the range of `fat_type`.
+fn default_sectors_per_cluster(fat_type: FatType, bytes_per_sector: u16, total_sectors: u32) -> u8 {
+    const MIB: u64 = 1024 * 1024;
+
+    match fat_type {
+        FatType::Fat12 | FatType::Fat16 => {
+            let max_clusters = if fat_type == FatType::Fat12 { FAT12_MAX_CLUSTERS } else { FAT16_MAX_CLUSTERS };
+
+            let mut sectors_per_cluster = 1u8;
+            while sectors_per_cluster < 128 && total_sectors / u32::from(sectors_per_cluster) >= max_clusters - 16 {
+                sectors_per_cluster *= 2;
+            }
+
+            sectors_per_cluster
+        }
+
+        // These match the cluster sizes Microsoft's own formatter uses.
+        FatType::Fat32 => match u64::from(total_sectors) * u64::from(bytes_per_sector) {
+            size if size <= 260 * MIB => 1,
+            size if size <= 8192 * MIB => 8,
+            size if size <= 16384 * MIB => 16,
+            size if size <= 32768 * MIB => 32,
+            _ => 64,
+        },
+    }
+}
+
+/// Creates an empty FAT volume spanning the whole of `device`.
+pub fn format(device: &impl BlockDevice, options: FormatOptions) -> Result<()> {
+    let bytes_per_sector = u16::try_from(device.sector_size())
+        .ok()
+        .filter(|size| (512..=4096).contains(size) && size.is_power_of_two())
+        .ok_or(Error::Unsupported { reason: "device sector size" })?;
+    let total_sectors =
+        u32::try_from(device.sector_count()).map_err(|_| Error::Unsupported { reason: "device is too large" })?;
+
+    let fat_type =
+        options.fat_type.unwrap_or_else(|| default_fat_type(u64::from(total_sectors) * u64::from(bytes_per_sector)));
+    let sectors_per_cluster = options
+        .sectors_per_cluster
+        .unwrap_or_else(|| default_sectors_per_cluster(fat_type, bytes_per_sector, total_sectors));
+    if sectors_per_cluster == 0 || !sectors_per_cluster.is_power_of_two() {
+        return Err(Error::Unsupported { reason: "sectors per cluster must be a power of two" });
+    }
+
+    let fat_count = 2u8;
+    let (reserved_sectors, root_entry_count) = match fat_type {
+        FatType::Fat12 | FatType::Fat16 => (1u16, ROOT_ENTRY_COUNT),
+        FatType::Fat32 => (FAT32_RESERVED_SECTORS, 0),
+    };
+    let root_dir_sectors = (u32::from(root_entry_count) * 32).div_ceil(u32::from(bytes_per_sector));
+
+    // The FATs shrink the data region they describe, so their size is found by iterating until it settles.
+    let mut fat_size = 1u32;
+    let cluster_count = loop {
+        let metadata_sectors = u32::from(reserved_sectors) + (u32::from(fat_count) * fat_size) + root_dir_sectors;
+        let data_sectors =
+            total_sectors.checked_sub(metadata_sectors).ok_or(Error::Unsupported { reason: "device is too small" })?;
+        let cluster_count = data_sectors / u32::from(sectors_per_cluster);
+
+        let fat_bytes = match fat_type {
+            FatType::Fat12 => ((cluster_count + 2) * 3).div_ceil(2),
+            FatType::Fat16 => (cluster_count + 2) * 2,
+            FatType::Fat32 => (cluster_count + 2) * 4,
+        };
+        let needed_fat_size = fat_bytes.div_ceil(u32::from(bytes_per_sector));
+
+        if needed_fat_size <= fat_size {
+            break cluster_count;
+        }
+
+        fat_size = needed_fat_size;
+    };
+
+    if FatType::from_cluster_count(cluster_count) != fat_type || cluster_count > FAT32_MAX_CLUSTERS {
+        return Err(Error::Unsupported { reason: "device size doesn't suit the FAT type" });
+    }
+
+    let sector_size = usize::from(bytes_per_sector);
+    let sector_offset = |sector: u32| u64::from(sector) * u64::from(bytes_per_sector);
+
+    // Clear the reserved sectors, FATs, and FAT12/16 root directory.
+    let metadata_sectors = u32::from(reserved_sectors) + (u32::from(fat_count) * fat_size) + root_dir_sectors;
+    let zeroes = vec![0u8; sector_size * 64];
+    let mut sector = 0;
+    while sector < metadata_sectors {
+        let count = (metadata_sectors - sector).min(64);
+        write_bytes(device, sector_offset(sector), &zeroes[..(usize::try_from(count).unwrap() * sector_size)])?;
+        sector += count;
+    }
+
+    let mut boot_sector = vec![0u8; sector_size];
+    boot_sector[..3].copy_from_slice(&[0xEB, if fat_type == FatType::Fat32 { 0x58 } else { 0x3C }, 0x90]);
+    boot_sector[3..11].copy_from_slice(b"MSWIN4.1");
+    write_u16(&mut boot_sector, 11, bytes_per_sector);
+    boot_sector[13] = sectors_per_cluster;
+    write_u16(&mut boot_sector, 14, reserved_sectors);
+    boot_sector[16] = fat_count;
+    write_u16(&mut boot_sector, 17, root_entry_count);
+    match u16::try_from(total_sectors) {
+        Ok(total_sectors) if fat_type != FatType::Fat32 => write_u16(&mut boot_sector, 19, total_sectors),
+        _ => write_u32(&mut boot_sector, 32, total_sectors),
+    }
+    boot_sector[21] = MEDIA_FIXED;
+    // Geometry is only meaningful to BIOS disk services, so conventional values are recorded.
+    write_u16(&mut boot_sector, 24, 63);
+    write_u16(&mut boot_sector, 26, 255);
+
+    let extended_offset = if fat_type == FatType::Fat32 {
+        write_u32(&mut boot_sector, 36, fat_size);
+        write_u32(&mut boot_sector, 44, 2);
+        write_u16(&mut boot_sector, 48, FAT32_FS_INFO_SECTOR);
+        write_u16(&mut boot_sector, 50, FAT32_BACKUP_BOOT_SECTOR);
+
+        64
+    } else {
+        write_u16(&mut boot_sector, 22, u16::try_from(fat_size).unwrap());
+
+        36
+    };
+
+    boot_sector[extended_offset] = 0x80;
+    boot_sector[extended_offset + 2] = EXTENDED_BOOT_SIGNATURE;
+    write_u32(&mut boot_sector, extended_offset + 3, options.volume_id);
+    boot_sector[(extended_offset + 7)..(extended_offset + 18)].copy_from_slice(&options.volume_label);
+    boot_sector[(extended_offset + 18)..(extended_offset + 26)].copy_from_slice(fat_type.label());
+    boot_sector[510..512].copy_from_slice(&BOOT_SIGNATURE);
+
+    write_bytes(device, 0, &boot_sector)?;
+
+    if fat_type == FatType::Fat32 {
+        let mut fs_info = vec![0u8; sector_size];
+        write_u32(&mut fs_info, 0, 0x4161_5252);
+        write_u32(&mut fs_info, 484, 0x6141_7272);
+        // The root directory occupies the first cluster.
+        write_u32(&mut fs_info, 488, cluster_count - 1);
+        write_u32(&mut fs_info, 492, 3);
+        write_u32(&mut fs_info, 508, 0xAA55_0000);
+
+        write_bytes(device, sector_offset(u32::from(FAT32_FS_INFO_SECTOR)), &fs_info)?;
+        write_bytes(device, sector_offset(u32::from(FAT32_BACKUP_BOOT_SECTOR)), &boot_sector)?;
+        write_bytes(device, sector_offset(u32::from(FAT32_BACKUP_BOOT_SECTOR + 1)), &fs_info)?;
+    }
+
+    // The first two FAT entries hold the media descriptor and an end-of-chain marker. On FAT32, the third ends the
+    // root directory's chain.
+    let reserved_entries: &[u8] = match fat_type {
+        FatType::Fat12 => &[MEDIA_FIXED, 0xFF, 0xFF],
+        FatType::Fat16 => &[MEDIA_FIXED, 0xFF, 0xFF, 0xFF],
+        FatType::Fat32 => &[MEDIA_FIXED, 0xFF, 0xFF, 0x0F, 0xFF, 0xFF, 0xFF, 0x0F, 0xFF, 0xFF, 0xFF, 0x0F],
+    };
+    for fat in 0..u32::from(fat_count) {
+        write_bytes(device, sector_offset(u32::from(reserved_sectors) + (fat * fat_size)), reserved_entries)?;
+    }
+
+    if fat_type == FatType::Fat32 {
+        let cluster_size = usize::from(sectors_per_cluster) * sector_size;
+        write_bytes(device, sector_offset(metadata_sectors), &vec![0u8; cluster_size])?;
+    }
+
+    Ok(device.flush()?)
+}
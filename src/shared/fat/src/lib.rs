@@ -0,0 +1,78 @@
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+mod boot_sector;
+pub use boot_sector::FatType;
+
+mod dir;
+pub use dir::DirEntry;
+
+mod format;
+pub use format::*;
+
+mod volume;
+pub use volume::*;
+
+#[cfg(test)]
+mod tests;
+
+pub use block_device::BlockDevice;
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Indicates the underlying block device failed a transfer.
+    Io,
+
+    /// Indicates the volume's boot sector doesn't describe a FAT filesystem.
+    InvalidBootSector { reason: &'static str },
+
+    /// Indicates the filesystem's on-disk structures are malformed.
+    Corrupt { reason: &'static str },
+
+    /// Indicates the volume uses a feature this driver doesn't implement.
+    Unsupported { reason: &'static str },
+
+    /// Indicates no entry with the provided name exists in a directory.
+    NotFound,
+
+    /// Indicates an entry with the provided name already exists in a directory.
+    AlreadyExists,
+
+    /// Indicates a directory operation was attempted on a file.
+    NotDirectory,
+
+    /// Indicates a file operation was attempted on a directory.
+    IsDirectory,
+
+    /// Indicates a directory can't be removed, as it still has entries.
+    DirectoryNotEmpty,
+
+    /// Indicates a directory has no free entries, and can't be grown (e.g. the FAT12/16 root directory).
+    DirectoryFull,
+
+    /// Indicates every cluster of the volume is allocated.
+    NoSpace,
+
+    /// Indicates a name can't be stored in a directory entry.
+    InvalidName,
+
+    /// Indicates a file would exceed the 4 GiB size limit of FAT.
+    FileTooLarge,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, formatter)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl From<block_device::Error> for Error {
+    fn from(_: block_device::Error) -> Self {
+        Self::Io
+    }
+}
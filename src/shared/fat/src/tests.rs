@@ -0,0 +1,355 @@
+use crate::{format, Error, FatType, FileSystem, FormatOptions};
+use block_device::{
+    test_support::{pattern, remount, Remount},
+    MemoryDevice,
+};
+use std::{vec, vec::Vec};
+
+const MIB: usize = 1024 * 1024;
+
+fn formatted(fat_type: FatType, size: usize) -> FileSystem<MemoryDevice> {
+    let device = MemoryDevice::new(512, size);
+    format(&device, FormatOptions { fat_type: Some(fat_type), volume_label: *b"LINUIZ     ", ..Default::default() })
+        .unwrap();
+
+    FileSystem::mount(device).unwrap()
+}
+
+impl Remount for FileSystem<MemoryDevice> {
+    type Error = Error;
+
+    fn mount(device: MemoryDevice) -> Result<Self, Error> {
+        Self::mount(device)
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.sync()
+    }
+
+    fn into_device(self) -> MemoryDevice {
+        self.device
+    }
+}
+
+#[test]
+fn format_and_mount() {
+    for (fat_type, size) in [(FatType::Fat12, MIB), (FatType::Fat16, 16 * MIB), (FatType::Fat32, 40 * MIB)] {
+        let mut filesystem = formatted(fat_type, size);
+
+        assert_eq!(filesystem.fat_type(), fat_type);
+        assert_eq!(filesystem.volume_label().unwrap().as_deref(), Some("LINUIZ"));
+        assert!(filesystem.read_dir(&filesystem.root()).unwrap().is_empty());
+
+        // Only the FAT32 root directory occupies a cluster.
+        let used = if fat_type == FatType::Fat32 { 1 } else { 0 };
+        assert_eq!(filesystem.free_clusters().unwrap(), filesystem.cluster_count() - used);
+    }
+}
+
+#[test]
+fn format_rejects_unsuitable_sizes() {
+    let device = MemoryDevice::new(512, MIB);
+
+    assert!(matches!(
+        format(&device, FormatOptions { fat_type: Some(FatType::Fat32), ..Default::default() }),
+        Err(Error::Unsupported { .. })
+    ));
+}
+
+#[test]
+fn mount_rejects_unformatted_device() {
+    assert!(matches!(FileSystem::mount(MemoryDevice::new(512, MIB)), Err(Error::InvalidBootSector { .. })));
+}
+
+#[test]
+fn long_names_round_trip() {
+    for (fat_type, size) in [(FatType::Fat12, MIB), (FatType::Fat16, 16 * MIB), (FatType::Fat32, 40 * MIB)] {
+        let mut filesystem = formatted(fat_type, size);
+        let root = filesystem.root();
+        let name = "A file with a rather long name, and ünïcödé.text";
+        let data = pattern(usize::try_from(filesystem.cluster_size()).unwrap() * 3 + 17);
+
+        let mut node = filesystem.create_file(&root, name).unwrap();
+        assert_eq!(filesystem.write(&mut node, 0, &data).unwrap(), data.len());
+
+        let filesystem = remount(filesystem);
+        let entry = filesystem.lookup(&filesystem.root(), &name.to_uppercase()).unwrap();
+        assert_eq!(entry.name(), name);
+        assert_eq!(entry.size(), u32::try_from(data.len()).unwrap());
+
+        let mut read = vec![0; data.len() + 100];
+        assert_eq!(filesystem.read(&entry.node(), 0, &mut read).unwrap(), data.len());
+        assert_eq!(&read[..data.len()], data);
+
+        // The entry can also be found by its short name.
+        assert_eq!(filesystem.lookup(&filesystem.root(), &entry.short_name()).unwrap(), entry);
+    }
+}
+
+#[test]
+fn short_names() {
+    let mut filesystem = formatted(FatType::Fat16, 16 * MIB);
+    let root = filesystem.root();
+
+    filesystem.create_file(&root, "Long File Name 1.txt").unwrap();
+    filesystem.create_file(&root, "Long File Name 2.txt").unwrap();
+    filesystem.create_file(&root, "readme.md").unwrap();
+    filesystem.create_file(&root, "KERNEL").unwrap();
+    filesystem.create_file(&root, ".hidden").unwrap();
+
+    let entries = filesystem.read_dir(&root).unwrap();
+    let names: Vec<_> = entries.iter().map(|entry| (entry.name(), entry.short_name())).collect();
+    assert_eq!(
+        names,
+        [
+            ("Long File Name 1.txt", "LONGFI~1.TXT".into()),
+            ("Long File Name 2.txt", "LONGFI~2.TXT".into()),
+            ("readme.md", "README.MD".into()),
+            ("KERNEL", "KERNEL".into()),
+            (".hidden", "HIDDEN~1".into()),
+        ]
+    );
+
+    assert_eq!(filesystem.create_file(&root, "README.MD"), Err(Error::AlreadyExists));
+    assert_eq!(filesystem.create_file(&root, "longfi~1.txt"), Err(Error::AlreadyExists));
+    assert_eq!(filesystem.create_file(&root, "a:b"), Err(Error::InvalidName));
+    assert_eq!(filesystem.create_file(&root, "trailing."), Err(Error::InvalidName));
+}
+
+#[test]
+fn nested_directories() {
+    let mut filesystem = formatted(FatType::Fat32, 40 * MIB);
+    let free = filesystem.free_clusters().unwrap();
+
+    let root = filesystem.root();
+    let boot = filesystem.create_dir(&root, "boot").unwrap();
+    let efi = filesystem.create_dir(&boot, "EFI").unwrap();
+    let mut config = filesystem.create_file(&efi, "limine.cfg").unwrap();
+    filesystem.write(&mut config, 0, b"TIMEOUT=0\n").unwrap();
+
+    let mut filesystem = remount(filesystem);
+    let root = filesystem.root();
+    let boot = filesystem.lookup(&root, "BOOT").unwrap().node();
+    let efi = filesystem.lookup(&boot, "efi").unwrap().node();
+    let config = filesystem.lookup(&efi, "limine.cfg").unwrap().node();
+    assert!(boot.is_directory() && efi.is_directory() && !config.is_directory());
+
+    let mut read = [0; 10];
+    filesystem.read(&config, 0, &mut read).unwrap();
+    assert_eq!(&read, b"TIMEOUT=0\n");
+
+    assert_eq!(filesystem.remove(&boot, "EFI"), Err(Error::DirectoryNotEmpty));
+    filesystem.remove(&efi, "limine.cfg").unwrap();
+    filesystem.remove(&boot, "EFI").unwrap();
+    filesystem.remove(&root, "boot").unwrap();
+
+    assert!(filesystem.read_dir(&root).unwrap().is_empty());
+    assert_eq!(filesystem.free_clusters().unwrap(), free);
+    assert_eq!(filesystem.lookup(&root, "boot"), Err(Error::NotFound));
+}
+
+#[test]
+fn directories_grow() {
+    let mut filesystem = formatted(FatType::Fat32, 40 * MIB);
+    let root = filesystem.root();
+    let dir = filesystem.create_dir(&root, "many").unwrap();
+
+    // Each of these names takes several entries, so the directory spans many clusters.
+    for index in 0..100 {
+        filesystem.create_file(&dir, &format!("a long file name numbered {index}")).unwrap();
+    }
+
+    let filesystem = remount(filesystem);
+    let dir = filesystem.lookup(&filesystem.root(), "many").unwrap().node();
+    let entries = filesystem.read_dir(&dir).unwrap();
+    assert_eq!(entries.len(), 100);
+    assert!(entries
+        .iter()
+        .enumerate()
+        .all(|(index, entry)| entry.name() == format!("a long file name numbered {index}")));
+}
+
+#[test]
+fn fixed_root_directory_fills() {
+    let mut filesystem = formatted(FatType::Fat16, 16 * MIB);
+    let root = filesystem.root();
+
+    for index in 0..512 {
+        filesystem.create_file(&root, &format!("F{index}")).unwrap();
+    }
+
+    assert_eq!(filesystem.create_file(&root, "F512"), Err(Error::DirectoryFull));
+}
+
+#[test]
+fn writes_at_offsets() {
+    let mut filesystem = formatted(FatType::Fat16, 16 * MIB);
+    let root = filesystem.root();
+    let cluster_size = usize::try_from(filesystem.cluster_size()).unwrap();
+
+    let mut node = filesystem.create_file(&root, "sparse.bin").unwrap();
+    filesystem.write(&mut node, 10, b"head").unwrap();
+    // Write beyond the end, which leaves a gap that must read back as zeroes.
+    let offset = cluster_size * 2 + 5;
+    filesystem.write(&mut node, u64::try_from(offset).unwrap(), b"tail").unwrap();
+    // Overwrite across a cluster boundary.
+    filesystem.write(&mut node, u64::try_from(cluster_size - 2).unwrap(), b"span").unwrap();
+
+    let mut expected = vec![0; offset + 4];
+    expected[10..14].copy_from_slice(b"head");
+    expected[offset..].copy_from_slice(b"tail");
+    expected[(cluster_size - 2)..(cluster_size + 2)].copy_from_slice(b"span");
+
+    let filesystem = remount(filesystem);
+    let node = filesystem.lookup(&filesystem.root(), "sparse.bin").unwrap().node();
+    let mut read = vec![0xAA; expected.len()];
+    assert_eq!(filesystem.read(&node, 0, &mut read).unwrap(), expected.len());
+    assert_eq!(read, expected);
+    assert_eq!(filesystem.read(&node, u64::try_from(expected.len()).unwrap(), &mut read).unwrap(), 0);
+}
+
+#[test]
+fn truncate_shrinks_and_extends() {
+    let mut filesystem = formatted(FatType::Fat32, 40 * MIB);
+    let root = filesystem.root();
+    let cluster_size = usize::try_from(filesystem.cluster_size()).unwrap();
+    let free = filesystem.free_clusters().unwrap();
+
+    let data = pattern(cluster_size * 4);
+    let mut node = filesystem.create_file(&root, "file").unwrap();
+    filesystem.write(&mut node, 0, &data).unwrap();
+    assert_eq!(filesystem.free_clusters().unwrap(), free - 4);
+
+    filesystem.truncate(&mut node, u64::try_from(cluster_size + 1).unwrap()).unwrap();
+    assert_eq!(filesystem.free_clusters().unwrap(), free - 2);
+
+    // Extending must zero the bytes which were cut off, rather than revealing the old data.
+    filesystem.truncate(&mut node, u64::try_from(cluster_size * 3).unwrap()).unwrap();
+    let mut read = vec![0; cluster_size * 3];
+    filesystem.read(&node, 0, &mut read).unwrap();
+    assert_eq!(&read[..=cluster_size], &data[..=cluster_size]);
+    assert!(read[(cluster_size + 1)..].iter().all(|byte| *byte == 0));
+
+    filesystem.truncate(&mut node, 0).unwrap();
+    assert_eq!(node.first_cluster(), 0);
+    assert_eq!(filesystem.free_clusters().unwrap(), free);
+
+    let filesystem = remount(filesystem);
+    assert_eq!(filesystem.lookup(&filesystem.root(), "file").unwrap().size(), 0);
+}
+
+#[test]
+fn fat12_chains() {
+    let mut filesystem = formatted(FatType::Fat12, MIB);
+    let root = filesystem.root();
+    let cluster_size = usize::try_from(filesystem.cluster_size()).unwrap();
+    let free = filesystem.free_clusters().unwrap();
+
+    // Interleave two files, so that their chains alternate between odd and even clusters.
+    let mut a = filesystem.create_file(&root, "a").unwrap();
+    let mut b = filesystem.create_file(&root, "b").unwrap();
+    let data = pattern(cluster_size * 40);
+    for chunk in 0..40 {
+        let range = (chunk * cluster_size)..((chunk + 1) * cluster_size);
+        let offset = u64::try_from(range.start).unwrap();
+        filesystem.write(&mut a, offset, &data[range.clone()]).unwrap();
+        filesystem.write(&mut b, offset, &data[range].iter().map(|byte| !byte).collect::<Vec<_>>()).unwrap();
+    }
+
+    let mut filesystem = remount(filesystem);
+    let root = filesystem.root();
+    let mut read = vec![0; data.len()];
+    filesystem.read(&filesystem.lookup(&root, "a").unwrap().node(), 0, &mut read).unwrap();
+    assert_eq!(read, data);
+    filesystem.read(&filesystem.lookup(&root, "b").unwrap().node(), 0, &mut read).unwrap();
+    assert!(read.iter().zip(&data).all(|(read, data)| *read == !data));
+
+    filesystem.remove(&root, "a").unwrap();
+    filesystem.remove(&root, "b").unwrap();
+    assert_eq!(filesystem.free_clusters().unwrap(), free);
+}
+
+#[test]
+fn volume_fills() {
+    let mut filesystem = formatted(FatType::Fat12, MIB);
+    let root = filesystem.root();
+    let free = filesystem.free_clusters().unwrap();
+    let volume_size = u64::from(free) * u64::from(filesystem.cluster_size());
+
+    let mut node = filesystem.create_file(&root, "big").unwrap();
+    assert_eq!(filesystem.truncate(&mut node, volume_size + 1), Err(Error::NoSpace));
+    // The partially grown chain is released again.
+    assert_eq!(filesystem.free_clusters().unwrap(), free);
+
+    filesystem.truncate(&mut node, volume_size).unwrap();
+    assert_eq!(filesystem.free_clusters().unwrap(), 0);
+    assert_eq!(filesystem.create_dir(&root, "dir"), Err(Error::NoSpace));
+}
+
+#[test]
+fn larger_device_sectors() {
+    // The volume's logical sectors are independent of the device's sectors.
+    let device = MemoryDevice::new(4096, 16 * MIB);
+    format(&device, FormatOptions::default()).unwrap();
+
+    let mut filesystem = FileSystem::mount(device).unwrap();
+    let root = filesystem.root();
+    let mut node = filesystem.create_file(&root, "unaligned").unwrap();
+    filesystem.write(&mut node, 3, b"data").unwrap();
+
+    let filesystem = remount(filesystem);
+    let mut read = [0; 7];
+    filesystem.read(&filesystem.lookup(&filesystem.root(), "UNALIGNED").unwrap().node(), 0, &mut read).unwrap();
+    assert_eq!(&read, b"\0\0\0data");
+}
+
+/// Checks parsing against entries written byte-by-byte, as another implementation would have written them.
+#[test]
+fn reads_handwritten_entries() {
+    let filesystem = formatted(FatType::Fat16, 16 * MIB);
+    let root_offset = filesystem.boot_sector.root_dir_offset();
+
+    let short_name = *b"HELLOW~1TXT";
+    let checksum = short_name.iter().fold(0u8, |sum, byte| sum.rotate_right(1).wrapping_add(*byte));
+    let units: Vec<u16> = "hello world.txt".encode_utf16().collect();
+
+    let mut slots = vec![0u8; 32 * 5];
+    // A deleted entry, which must be skipped.
+    slots[0] = 0xE5;
+    slots[11] = 0x20;
+    // Long name entries, last first. The 15 code units span two entries.
+    for (slot, ordinal) in [(1, 2u8), (2, 1u8)] {
+        let entry = &mut slots[(slot * 32)..((slot + 1) * 32)];
+        entry[0] = ordinal | if ordinal == 2 { 0x40 } else { 0 };
+        entry[11] = 0x0F;
+        entry[13] = checksum;
+
+        let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+        for (index, offset) in offsets.into_iter().enumerate() {
+            let position = (usize::from(ordinal) - 1) * 13 + index;
+            let unit = match position.cmp(&units.len()) {
+                std::cmp::Ordering::Less => units[position],
+                std::cmp::Ordering::Equal => 0,
+                std::cmp::Ordering::Greater => 0xFFFF,
+            };
+            entry[offset..(offset + 2)].copy_from_slice(&unit.to_le_bytes());
+        }
+    }
+    // The short entry, with a size but no clusters.
+    slots[96..107].copy_from_slice(&short_name);
+    slots[107] = 0x20;
+    // A short-only entry with the NT lowercase flags set.
+    slots[128..139].copy_from_slice(b"NOTES   MD ");
+    slots[139] = 0x20;
+    slots[140] = 0x18;
+    filesystem.write_bytes(root_offset, &slots).unwrap();
+
+    let entries = filesystem.read_dir(&filesystem.root()).unwrap();
+    let names: Vec<_> = entries.iter().map(|entry| entry.name()).collect();
+    assert_eq!(names, ["hello world.txt", "notes.md"]);
+    assert_eq!(entries[0].short_name(), "HELLOW~1.TXT");
+
+    // Orphaned long name entries (with a mismatched checksum) fall back to the short name.
+    filesystem.write_bytes(root_offset + 96, b"HELLOW~2").unwrap();
+    assert_eq!(filesystem.read_dir(&filesystem.root()).unwrap()[0].name(), "HELLOW~2.TXT");
+}
@@ -0,0 +1,554 @@
+use crate::{
+    boot_sector::{read_u32, write_u16, write_u32, BootSector},
+    BlockDevice, Error, FatType, Result,
+};
+use alloc::{vec, vec::Vec};
+use block_device::{read_bytes, write_bytes};
+
+const FS_INFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FS_INFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FS_INFO_TRAIL_SIGNATURE: u32 = 0xAA55_0000;
+/// Recorded in the FSInfo structure when a count or hint isn't known.
+const FS_INFO_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// Decoded entry of the file allocation table, which describes the cluster following another in its chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FatEntry {
+    Free,
+    Next(u32),
+    Bad,
+    End,
+}
+
+/// A file or directory of a volume, as recorded by its directory entry.
+///
+/// Nodes are plain values, so a node which is written through must be passed back to later calls to see its new
+/// size and clusters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Node {
+    /// Byte offset of the node's directory entry, or `None` for the root directory.
+    pub(crate) entry_offset: Option<u64>,
+    /// First cluster of the node's data, or 0 if it has none (or is the FAT12/16 root directory).
+    pub(crate) first_cluster: u32,
+    pub(crate) size: u32,
+    pub(crate) is_directory: bool,
+}
+
+impl Node {
+    /// Identifier of the root directory, which has no directory entry.
+    pub const ROOT_ID: u64 = 1;
+
+    /// Number which uniquely identifies the node within its volume.
+    ///
+    /// This is derived from the location of the node's directory entry, as FAT has no inode numbers.
+    pub fn id(&self) -> u64 {
+        self.entry_offset.map_or(Self::ROOT_ID, |entry_offset| entry_offset / 32)
+    }
+
+    #[inline]
+    pub const fn is_directory(&self) -> bool {
+        self.is_directory
+    }
+
+    /// Size of the file, in bytes. Directories are always of size zero.
+    #[inline]
+    pub const fn size(&self) -> u32 {
+        self.size
+    }
+
+    #[inline]
+    pub const fn first_cluster(&self) -> u32 {
+        self.first_cluster
+    }
+}
+
+/// A mounted FAT12, FAT16, or FAT32 volume.
+pub struct FileSystem<D: BlockDevice> {
+    pub(crate) device: D,
+    pub(crate) boot_sector: BootSector,
+    /// Number of free clusters, if it's known.
+    free_count: Option<u32>,
+    /// Cluster the search for a free cluster starts from.
+    next_free: u32,
+    /// Whether the FSInfo structure needs to be rewritten on sync.
+    fs_info_dirty: bool,
+}
+
+impl<D: BlockDevice> FileSystem<D> {
+    /// Mounts the volume which begins at the first sector of `device`.
+    pub fn mount(device: D) -> Result<Self> {
+        let mut sector = [0u8; 512];
+        read_bytes(&device, 0, &mut sector)?;
+        let boot_sector = BootSector::parse(&sector)?;
+
+        let device_size = u64::try_from(device.sector_size()).unwrap() * device.sector_count();
+        if u64::from(boot_sector.total_sectors) * u64::from(boot_sector.bytes_per_sector) > device_size {
+            return Err(Error::InvalidBootSector { reason: "volume is larger than the device" });
+        }
+
+        let mut filesystem = Self { device, boot_sector, free_count: None, next_free: 2, fs_info_dirty: false };
+
+        if let Some(fs_info_offset) = filesystem.fs_info_offset() {
+            let mut fs_info = [0u8; 512];
+            filesystem.read_bytes(fs_info_offset, &mut fs_info)?;
+
+            if read_u32(&fs_info, 0) == FS_INFO_LEAD_SIGNATURE
+                && read_u32(&fs_info, 484) == FS_INFO_STRUCT_SIGNATURE
+                && read_u32(&fs_info, 508) == FS_INFO_TRAIL_SIGNATURE
+            {
+                let cluster_count = filesystem.boot_sector.cluster_count;
+                filesystem.free_count = Some(read_u32(&fs_info, 488)).filter(|free| *free <= cluster_count);
+                filesystem.next_free = Some(read_u32(&fs_info, 492))
+                    .filter(|next_free| filesystem.is_data_cluster(*next_free))
+                    .unwrap_or(2);
+            }
+        }
+
+        Ok(filesystem)
+    }
+
+    #[inline]
+    pub const fn device(&self) -> &D {
+        &self.device
+    }
+
+    #[inline]
+    pub const fn fat_type(&self) -> FatType {
+        self.boot_sector.fat_type
+    }
+
+    /// Number of data clusters in the volume.
+    #[inline]
+    pub const fn cluster_count(&self) -> u32 {
+        self.boot_sector.cluster_count
+    }
+
+    /// Size of a cluster, in bytes.
+    #[inline]
+    pub fn cluster_size(&self) -> u32 {
+        self.boot_sector.cluster_size()
+    }
+
+    /// Serial number of the volume, which is usually generated when it's formatted.
+    #[inline]
+    pub const fn volume_id(&self) -> u32 {
+        self.boot_sector.volume_id
+    }
+
+    pub fn root(&self) -> Node {
+        Node { entry_offset: None, first_cluster: self.boot_sector.root_cluster, size: 0, is_directory: true }
+    }
+
+    pub(crate) fn read_bytes(&self, offset: u64, buffer: &mut [u8]) -> Result<()> {
+        Ok(read_bytes(&self.device, offset, buffer)?)
+    }
+
+    pub(crate) fn write_bytes(&self, offset: u64, buffer: &[u8]) -> Result<()> {
+        Ok(write_bytes(&self.device, offset, buffer)?)
+    }
+
+    fn fs_info_offset(&self) -> Option<u64> {
+        self.boot_sector.fs_info_sector.map(|sector| u64::from(sector) * u64::from(self.boot_sector.bytes_per_sector))
+    }
+
+    pub(crate) fn is_data_cluster(&self, cluster: u32) -> bool {
+        (2..(self.boot_sector.cluster_count + 2)).contains(&cluster)
+    }
+
+    /* FILE ALLOCATION TABLE */
+
+    fn fat_entry_offset(&self, fat: u8, cluster: u32) -> u64 {
+        let offset = match self.fat_type() {
+            FatType::Fat12 => cluster + (cluster / 2),
+            FatType::Fat16 => cluster * 2,
+            FatType::Fat32 => cluster * 4,
+        };
+
+        self.boot_sector.fat_offset(fat) + u64::from(offset)
+    }
+
+    pub(crate) fn fat_entry(&self, cluster: u32) -> Result<FatEntry> {
+        let offset = self.fat_entry_offset(self.boot_sector.active_fat.unwrap_or(0), cluster);
+
+        let value = match self.fat_type() {
+            FatType::Fat12 | FatType::Fat16 => {
+                let mut bytes = [0u8; 2];
+                self.read_bytes(offset, &mut bytes)?;
+                let value = u16::from_le_bytes(bytes);
+
+                // FAT12 entries are packed in pairs across 3 bytes, so odd clusters take the upper 12 bits.
+                match self.fat_type() {
+                    FatType::Fat12 if cluster % 2 == 1 => u32::from(value >> 4),
+                    FatType::Fat12 => u32::from(value & 0xFFF),
+                    _ => u32::from(value),
+                }
+            }
+
+            FatType::Fat32 => {
+                let mut bytes = [0u8; 4];
+                self.read_bytes(offset, &mut bytes)?;
+
+                // The upper 4 bits are reserved.
+                u32::from_le_bytes(bytes) & 0x0FFF_FFFF
+            }
+        };
+
+        match value {
+            0 => Ok(FatEntry::Free),
+            value if value >= self.fat_type().end_of_chain() => Ok(FatEntry::End),
+            value if value == self.fat_type().bad_cluster() => Ok(FatEntry::Bad),
+            value if self.is_data_cluster(value) => Ok(FatEntry::Next(value)),
+            _ => Err(Error::Corrupt { reason: "FAT entry is outside the volume" }),
+        }
+    }
+
+    /// Sets the entry of `cluster` in every FAT which is in use.
+    pub(crate) fn set_fat_entry(&self, cluster: u32, entry: FatEntry) -> Result<()> {
+        let value = match entry {
+            FatEntry::Free => 0,
+            FatEntry::Next(next) => next,
+            FatEntry::Bad => self.fat_type().bad_cluster(),
+            FatEntry::End => self.fat_type().end_of_chain() | 0x7,
+        };
+
+        let fats = match self.boot_sector.active_fat {
+            Some(active_fat) => active_fat..(active_fat + 1),
+            None => 0..self.boot_sector.fat_count,
+        };
+
+        for fat in fats {
+            let offset = self.fat_entry_offset(fat, cluster);
+
+            match self.fat_type() {
+                FatType::Fat12 => {
+                    let mut bytes = [0u8; 2];
+                    self.read_bytes(offset, &mut bytes)?;
+                    let old = u16::from_le_bytes(bytes);
+                    let value = u16::try_from(value).unwrap();
+
+                    let new = if cluster % 2 == 1 { (old & 0x000F) | (value << 4) } else { (old & 0xF000) | value };
+                    self.write_bytes(offset, &new.to_le_bytes())?;
+                }
+
+                FatType::Fat16 => self.write_bytes(offset, &u16::try_from(value).unwrap().to_le_bytes())?,
+
+                FatType::Fat32 => {
+                    let mut bytes = [0u8; 4];
+                    self.read_bytes(offset, &mut bytes)?;
+                    let new = (u32::from_le_bytes(bytes) & 0xF000_0000) | value;
+                    self.write_bytes(offset, &new.to_le_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Follows the cluster chain which begins at `first_cluster`. A chain beginning at cluster 0 is empty.
+    pub(crate) fn chain(&self, first_cluster: u32) -> Result<Vec<u32>> {
+        let mut chain = Vec::new();
+        if first_cluster == 0 {
+            return Ok(chain);
+        }
+
+        if !self.is_data_cluster(first_cluster) {
+            return Err(Error::Corrupt { reason: "first cluster is outside the volume" });
+        }
+
+        let mut cluster = first_cluster;
+        loop {
+            chain.push(cluster);
+            // A chain can't be longer than the volume unless it loops back on itself.
+            if chain.len() > usize::try_from(self.boot_sector.cluster_count).unwrap() {
+                return Err(Error::Corrupt { reason: "cluster chain loops" });
+            }
+
+            match self.fat_entry(cluster)? {
+                FatEntry::Next(next) => cluster = next,
+                FatEntry::End => return Ok(chain),
+                FatEntry::Free | FatEntry::Bad => {
+                    return Err(Error::Corrupt { reason: "cluster chain ends without an end marker" })
+                }
+            }
+        }
+    }
+
+    /// Allocates a zeroed cluster, appending it to the chain ending at `previous` (if any).
+    pub(crate) fn allocate_cluster(&mut self, previous: Option<u32>) -> Result<u32> {
+        if self.free_count == Some(0) {
+            return Err(Error::NoSpace);
+        }
+
+        let cluster_count = self.boot_sector.cluster_count;
+        let start = if self.is_data_cluster(self.next_free) { self.next_free - 2 } else { 0 };
+        let mut found = None;
+        for index in 0..cluster_count {
+            let cluster = ((start + index) % cluster_count) + 2;
+            if self.fat_entry(cluster)? == FatEntry::Free {
+                found = Some(cluster);
+                break;
+            }
+        }
+
+        let Some(cluster) = found else {
+            self.free_count = Some(0);
+            return Err(Error::NoSpace);
+        };
+
+        let cluster_size = usize::try_from(self.cluster_size()).unwrap();
+        self.write_bytes(self.boot_sector.cluster_offset(cluster), &vec![0u8; cluster_size])?;
+        self.set_fat_entry(cluster, FatEntry::End)?;
+        if let Some(previous) = previous {
+            self.set_fat_entry(previous, FatEntry::Next(cluster))?;
+        }
+
+        self.next_free = if cluster + 1 < cluster_count + 2 { cluster + 1 } else { 2 };
+        self.free_count = self.free_count.map(|free_count| free_count - 1);
+        self.fs_info_dirty = true;
+
+        Ok(cluster)
+    }
+
+    /// Frees every cluster of `chain`.
+    fn free_clusters_of(&mut self, chain: &[u32]) -> Result<()> {
+        for &cluster in chain {
+            self.set_fat_entry(cluster, FatEntry::Free)?;
+            self.free_count = self.free_count.map(|free_count| free_count + 1);
+        }
+
+        self.fs_info_dirty = true;
+
+        Ok(())
+    }
+
+    /// Frees the cluster chain which begins at `first_cluster`.
+    pub(crate) fn free_chain(&mut self, first_cluster: u32) -> Result<()> {
+        let chain = self.chain(first_cluster)?;
+        self.free_clusters_of(&chain)
+    }
+
+    /// Number of free clusters in the volume, which is counted (and remembered) if it isn't already known.
+    pub fn free_clusters(&mut self) -> Result<u32> {
+        if let Some(free_count) = self.free_count {
+            return Ok(free_count);
+        }
+
+        let mut free_count = 0;
+        for cluster in 2..(self.boot_sector.cluster_count + 2) {
+            if self.fat_entry(cluster)? == FatEntry::Free {
+                free_count += 1;
+            }
+        }
+
+        self.free_count = Some(free_count);
+        self.fs_info_dirty = true;
+
+        Ok(free_count)
+    }
+
+    /// Commits the free cluster count to the FSInfo structure, and flushes the device.
+    pub fn sync(&mut self) -> Result<()> {
+        if let Some(fs_info_offset) = self.fs_info_offset().filter(|_| self.fs_info_dirty) {
+            let mut fs_info = [0u8; 512];
+            self.read_bytes(fs_info_offset, &mut fs_info)?;
+
+            if read_u32(&fs_info, 0) == FS_INFO_LEAD_SIGNATURE {
+                write_u32(&mut fs_info, 488, self.free_count.unwrap_or(FS_INFO_UNKNOWN));
+                write_u32(&mut fs_info, 492, self.next_free);
+                self.write_bytes(fs_info_offset, &fs_info)?;
+            }
+        }
+
+        self.fs_info_dirty = false;
+
+        Ok(self.device.flush()?)
+    }
+
+    /* FILE DATA */
+
+    /// Splits the `len` bytes at `offset` within the data of `chain` into runs which are contiguous on the volume,
+    /// as their byte offsets and lengths.
+    fn extents(&self, chain: &[u32], offset: u64, len: usize) -> Result<Vec<(u64, usize)>> {
+        let cluster_size = u64::from(self.cluster_size());
+        let mut extents: Vec<(u64, usize)> = Vec::new();
+        let mut position = offset;
+        let mut remaining = len;
+
+        while remaining > 0 {
+            let index = usize::try_from(position / cluster_size).unwrap();
+            let within = position % cluster_size;
+            let cluster =
+                *chain.get(index).ok_or(Error::Corrupt { reason: "file is larger than its cluster chain" })?;
+
+            let volume_offset = self.boot_sector.cluster_offset(cluster) + within;
+            let len = remaining.min(usize::try_from(cluster_size - within).unwrap());
+
+            match extents.last_mut() {
+                Some((last_offset, last_len)) if *last_offset + u64::try_from(*last_len).unwrap() == volume_offset => {
+                    *last_len += len;
+                }
+                _ => extents.push((volume_offset, len)),
+            }
+
+            position += u64::try_from(len).unwrap();
+            remaining -= len;
+        }
+
+        Ok(extents)
+    }
+
+    fn read_chain(&self, chain: &[u32], offset: u64, buffer: &mut [u8]) -> Result<()> {
+        let mut filled = 0;
+        for (volume_offset, len) in self.extents(chain, offset, buffer.len())? {
+            self.read_bytes(volume_offset, &mut buffer[filled..(filled + len)])?;
+            filled += len;
+        }
+
+        Ok(())
+    }
+
+    fn write_chain(&self, chain: &[u32], offset: u64, buffer: &[u8]) -> Result<()> {
+        let mut written = 0;
+        for (volume_offset, len) in self.extents(chain, offset, buffer.len())? {
+            self.write_bytes(volume_offset, &buffer[written..(written + len)])?;
+            written += len;
+        }
+
+        Ok(())
+    }
+
+    /// Grows `chain` (the chain of `node`) until it holds at least `len` bytes. If the volume fills, the clusters
+    /// allocated so far are freed again.
+    fn grow_chain(&mut self, node: &mut Node, chain: &mut Vec<u32>, len: u64) -> Result<()> {
+        let original_len = chain.len();
+        let needed = usize::try_from(len.div_ceil(u64::from(self.cluster_size()))).unwrap();
+
+        while chain.len() < needed {
+            match self.allocate_cluster(chain.last().copied()) {
+                Ok(cluster) => {
+                    if chain.is_empty() {
+                        node.first_cluster = cluster;
+                    }
+
+                    chain.push(cluster);
+                }
+
+                Err(err) => {
+                    self.shrink_chain(node, chain, original_len)?;
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Shrinks `chain` (the chain of `node`) to its first `keep` clusters, freeing the rest.
+    fn shrink_chain(&mut self, node: &mut Node, chain: &mut Vec<u32>, keep: usize) -> Result<()> {
+        if chain.len() <= keep {
+            return Ok(());
+        }
+
+        match keep.checked_sub(1) {
+            Some(last) => self.set_fat_entry(chain[last], FatEntry::End)?,
+            None => node.first_cluster = 0,
+        }
+
+        let freed = chain.split_off(keep);
+        self.free_clusters_of(&freed)
+    }
+
+    /// Zeroes the bytes of `chain` from `from` up to `to`, or the end of the chain if it's shorter.
+    fn zero_chain(&self, chain: &[u32], from: u64, to: u64) -> Result<()> {
+        let chain_len = u64::try_from(chain.len()).unwrap() * u64::from(self.cluster_size());
+        let to = to.min(chain_len);
+        if from >= to {
+            return Ok(());
+        }
+
+        self.write_chain(chain, from, &vec![0u8; usize::try_from(to - from).unwrap()])
+    }
+
+    /// Writes the first cluster and size of `node` back to its directory entry.
+    pub(crate) fn update_entry(&self, node: &Node) -> Result<()> {
+        let Some(entry_offset) = node.entry_offset else { return Ok(()) };
+
+        let mut entry = [0u8; 32];
+        self.read_bytes(entry_offset, &mut entry)?;
+        write_u16(&mut entry, 20, u16::try_from(node.first_cluster >> 16).unwrap());
+        write_u16(&mut entry, 26, u16::try_from(node.first_cluster & 0xFFFF).unwrap());
+        write_u32(&mut entry, 28, if node.is_directory { 0 } else { node.size });
+
+        self.write_bytes(entry_offset, &entry)
+    }
+
+    /// Reads from the file at `offset` into `buffer`, returning the number of bytes read. Reads past the end of the
+    /// file return zero.
+    pub fn read(&self, node: &Node, offset: u64, buffer: &mut [u8]) -> Result<usize> {
+        if node.is_directory {
+            return Err(Error::IsDirectory);
+        }
+
+        let size = u64::from(node.size);
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let len = buffer.len().min(usize::try_from(size - offset).unwrap_or(usize::MAX));
+        let chain = self.chain(node.first_cluster)?;
+        self.read_chain(&chain, offset, &mut buffer[..len])?;
+
+        Ok(len)
+    }
+
+    /// Writes `buffer` to the file at `offset`, extending it (and zero-filling any gap) if necessary. Returns the
+    /// number of bytes written.
+    pub fn write(&mut self, node: &mut Node, offset: u64, buffer: &[u8]) -> Result<usize> {
+        if node.is_directory {
+            return Err(Error::IsDirectory);
+        }
+
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        let end = offset
+            .checked_add(u64::try_from(buffer.len()).unwrap())
+            .filter(|end| *end <= u64::from(u32::MAX))
+            .ok_or(Error::FileTooLarge)?;
+
+        let size = u64::from(node.size);
+        let mut chain = self.chain(node.first_cluster)?;
+        // Newly allocated clusters are zeroed, so only the tail of the existing chain needs zeroing.
+        self.zero_chain(&chain, size, offset)?;
+        self.grow_chain(node, &mut chain, end)?;
+        self.write_chain(&chain, offset, buffer)?;
+
+        node.size = u32::try_from(end.max(size)).unwrap();
+        self.update_entry(node)?;
+
+        Ok(buffer.len())
+    }
+
+    /// Sets the length of the file, zero-filling it if it grows.
+    pub fn truncate(&mut self, node: &mut Node, len: u64) -> Result<()> {
+        if node.is_directory {
+            return Err(Error::IsDirectory);
+        }
+
+        let len_u32 = u32::try_from(len).map_err(|_| Error::FileTooLarge)?;
+        let size = u64::from(node.size);
+        let mut chain = self.chain(node.first_cluster)?;
+
+        if len < size {
+            let keep = usize::try_from(len.div_ceil(u64::from(self.cluster_size()))).unwrap();
+            self.shrink_chain(node, &mut chain, keep)?;
+        } else if len > size {
+            self.zero_chain(&chain, size, len)?;
+            self.grow_chain(node, &mut chain, len)?;
+        }
+
+        node.size = len_u32;
+        self.update_entry(node)
+    }
+}
@@ -0,0 +1,65 @@
+use core::ffi::{c_char, CStr};
+
+struct StartupInfo {
+    argc: usize,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+}
+
+// Safety: Startup info is written once before `main`, and is never mutated afterwards.
+unsafe impl Send for StartupInfo {}
+// Safety: See above.
+unsafe impl Sync for StartupInfo {}
+
+static STARTUP_INFO: spin::Once<StartupInfo> = spin::Once::new();
+
+/// ### Safety
+///
+/// `initial_stack` must point to a System V initial process stack.
+#[cfg(not(test))]
+pub(crate) unsafe fn init(initial_stack: *const usize) {
+    STARTUP_INFO.call_once(|| {
+        let argc = initial_stack.read();
+        let argv = initial_stack.add(1).cast::<*const c_char>();
+        // Skip the `argv` null terminator.
+        let envp = argv.add(argc + 1);
+
+        StartupInfo { argc, argv, envp }
+    });
+}
+
+/// ### Safety
+///
+/// `array` must be a null-terminated array of pointers to null-terminated strings.
+unsafe fn iter_strings(array: *const *const c_char) -> impl Iterator<Item = &'static str> {
+    (0..)
+        .map(move |index| array.add(index).read())
+        .take_while(|ptr| !ptr.is_null())
+        .filter_map(|ptr| CStr::from_ptr(ptr).to_str().ok())
+}
+
+/// Iterates the arguments the program was started with.
+pub fn args() -> impl Iterator<Item = &'static str> {
+    STARTUP_INFO
+        .get()
+        // Safety: Kernel guarantees `argv` is a null-terminated array of strings.
+        .map(|info| unsafe { iter_strings(info.argv) }.take(info.argc))
+        .into_iter()
+        .flatten()
+}
+
+/// Iterates the environment the program was started with, as `(key, value)` pairs.
+pub fn vars() -> impl Iterator<Item = (&'static str, &'static str)> {
+    STARTUP_INFO
+        .get()
+        // Safety: Kernel guarantees `envp` is a null-terminated array of strings.
+        .map(|info| unsafe { iter_strings(info.envp) })
+        .into_iter()
+        .flatten()
+        .map(|var| var.split_once('=').unwrap_or((var, "")))
+}
+
+/// Gets the value of the environment variable `key`, if it is set.
+pub fn var(key: &str) -> Option<&'static str> {
+    vars().find_map(|(var_key, value)| (var_key == key).then_some(value))
+}
@@ -0,0 +1,156 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    num::NonZeroUsize,
+    ptr::NonNull,
+};
+use libsys::{align_up_div, page_shift, page_size};
+use spin::Mutex;
+
+#[cfg(test)]
+mod tests;
+
+/// Block sizes of the small-allocation free lists. Allocations larger than the last class are mapped as whole pages.
+const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Maps `page_count` fresh read-write pages.
+#[cfg(not(test))]
+fn map_pages(page_count: NonZeroUsize) -> Option<NonNull<u8>> {
+    use libsys::syscall::{mem, task::CURRENT};
+
+    mem::map(CURRENT, None, page_count, mem::Permissions::ReadWrite).ok().map(|region| region.as_non_null_ptr())
+}
+
+/// ### Safety
+///
+/// `ptr` must be the first of `page_count` pages returned by [`map_pages`], which are no longer in use.
+#[cfg(not(test))]
+unsafe fn unmap_pages(ptr: NonNull<u8>, page_count: NonZeroUsize) {
+    libsys::syscall::mem::unmap(libsys::syscall::task::CURRENT, ptr, page_count).ok();
+}
+
+/// Host tests take pages from the system allocator instead.
+#[cfg(test)]
+fn map_pages(page_count: NonZeroUsize) -> Option<NonNull<u8>> {
+    let layout = Layout::from_size_align(page_count.get() * page_size(), page_size()).ok()?;
+
+    // Safety: Layout has a non-zero size.
+    NonNull::new(unsafe { std::alloc::alloc_zeroed(layout) })
+}
+
+/// ### Safety
+///
+/// `ptr` must be the first of `page_count` pages returned by [`map_pages`], which are no longer in use.
+#[cfg(test)]
+unsafe fn unmap_pages(ptr: NonNull<u8>, page_count: NonZeroUsize) {
+    std::alloc::dealloc(ptr.as_ptr(), Layout::from_size_align(page_count.get() * page_size(), page_size()).unwrap());
+}
+
+struct FreeBlock {
+    next: Option<NonNull<FreeBlock>>,
+}
+
+struct FreeList(Option<NonNull<FreeBlock>>);
+
+// Safety: Free blocks are exclusively owned by the list they're in.
+unsafe impl Send for FreeList {}
+
+impl FreeList {
+    fn pop(&mut self) -> Option<NonNull<u8>> {
+        let block = self.0?;
+        // Safety: Every block in the list is a valid, unused allocation.
+        self.0 = unsafe { block.as_ref().next };
+
+        Some(block.cast())
+    }
+
+    /// ### Safety
+    ///
+    /// `ptr` must be an unused allocation at least as large as, and aligned to, a `FreeBlock`.
+    unsafe fn push(&mut self, ptr: NonNull<u8>) {
+        let block = ptr.cast::<FreeBlock>();
+        block.as_ptr().write(FreeBlock { next: self.0 });
+        self.0 = Some(block);
+    }
+
+    /// Maps a fresh page and carves it into blocks of `block_size`.
+    fn refill(&mut self, block_size: usize) -> bool {
+        let Some(page) = map_pages(NonZeroUsize::MIN) else { return false };
+
+        for offset in (0..page_size()).step_by(block_size) {
+            // Safety: Block is within the newly mapped page, and block sizes are a multiple of `FreeBlock`'s size.
+            unsafe { self.push(NonNull::new_unchecked(page.as_ptr().add(offset))) };
+        }
+
+        true
+    }
+}
+
+/// Userspace heap allocator.
+///
+/// Small allocations are served from per-size-class free lists, refilled one page at a time. Larger allocations are
+/// mapped and unmapped directly with the memory system calls.
+pub struct Allocator {
+    free_lists: [Mutex<FreeList>; SIZE_CLASSES.len()],
+}
+
+impl Allocator {
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const EMPTY: Mutex<FreeList> = Mutex::new(FreeList(None));
+
+        Self { free_lists: [EMPTY; SIZE_CLASSES.len()] }
+    }
+
+    /// Returns the index of the size class that can serve `layout`, if any.
+    fn size_class(layout: Layout) -> Option<usize> {
+        // Blocks are naturally aligned to their size, so the alignment can be treated as a minimum size.
+        let size = layout.size().max(layout.align());
+        SIZE_CLASSES.iter().position(|class_size| size <= *class_size)
+    }
+
+    fn page_count(layout: Layout) -> Option<NonZeroUsize> {
+        NonZeroUsize::new(align_up_div(layout.size(), page_shift()))
+    }
+}
+
+impl Default for Allocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Safety: Allocation is backed by the kernel's memory mapping, which never hands out overlapping regions.
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if let Some(class_index) = Self::size_class(layout) {
+            let mut free_list = self.free_lists[class_index].lock();
+
+            match free_list.pop() {
+                Some(ptr) => ptr.as_ptr(),
+                None if free_list.refill(SIZE_CLASSES[class_index]) => {
+                    free_list.pop().map_or(core::ptr::null_mut(), NonNull::as_ptr)
+                }
+                None => core::ptr::null_mut(),
+            }
+        } else if layout.align() > page_size() {
+            // Mapped pages are only guaranteed to be page-aligned.
+            core::ptr::null_mut()
+        } else {
+            Self::page_count(layout).and_then(map_pages).map_or(core::ptr::null_mut(), NonNull::as_ptr)
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let Some(ptr) = NonNull::new(ptr) else { return };
+
+        if let Some(class_index) = Self::size_class(layout) {
+            self.free_lists[class_index].lock().push(ptr);
+        } else if let Some(page_count) = Self::page_count(layout) {
+            unmap_pages(ptr, page_count);
+        }
+    }
+}
+
+#[cfg(not(test))]
+#[global_allocator]
+static GLOBAL_ALLOCATOR: Allocator = Allocator::new();
@@ -0,0 +1,139 @@
+use super::{Allocator, SIZE_CLASSES};
+use core::alloc::{GlobalAlloc, Layout};
+use libsys::page_size;
+
+/// Fills the `len` bytes at `ptr` with a pattern derived from `seed`.
+unsafe fn fill(ptr: *mut u8, len: usize, seed: u8) {
+    for index in 0..len {
+        ptr.add(index).write(seed.wrapping_add(index as u8));
+    }
+}
+
+/// Checks the `len` bytes at `ptr` hold the pattern written by [`fill`].
+unsafe fn check(ptr: *const u8, len: usize, seed: u8) {
+    for index in 0..len {
+        assert_eq!(ptr.add(index).read(), seed.wrapping_add(index as u8), "byte {index}");
+    }
+}
+
+#[test]
+fn alloc_every_size_class() {
+    let allocator = Allocator::new();
+
+    for size in SIZE_CLASSES.iter().flat_map(|class_size| [class_size / 2 + 1, *class_size]) {
+        let layout = Layout::from_size_align(size, 1).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null(), "size {size}");
+
+            fill(ptr, size, size as u8);
+            check(ptr, size, size as u8);
+
+            allocator.dealloc(ptr, layout);
+        }
+    }
+}
+
+#[test]
+fn blocks_are_aligned_to_their_class() {
+    let allocator = Allocator::new();
+
+    for align in [8, 16, 64, 256, 2048] {
+        let layout = Layout::from_size_align(1, align).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert_eq!(ptr.addr() % align, 0, "align {align}");
+
+            allocator.dealloc(ptr, layout);
+        }
+    }
+}
+
+#[test]
+fn freed_blocks_are_reused() {
+    let allocator = Allocator::new();
+    let layout = Layout::from_size_align(48, 8).unwrap();
+
+    unsafe {
+        let ptr = allocator.alloc(layout);
+        allocator.dealloc(ptr, layout);
+
+        assert_eq!(allocator.alloc(layout), ptr);
+    }
+}
+
+#[test]
+fn blocks_do_not_overlap_across_refills() {
+    let allocator = Allocator::new();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    // Enough blocks to take several pages from the free list.
+    let count = 3 * (page_size() / 64);
+
+    unsafe {
+        let ptrs = (0..count).map(|_| allocator.alloc(layout)).collect::<Vec<_>>();
+        for (index, ptr) in ptrs.iter().enumerate() {
+            fill(*ptr, 64, index as u8);
+        }
+
+        for (index, ptr) in ptrs.iter().enumerate() {
+            check(*ptr, 64, index as u8);
+        }
+
+        ptrs.into_iter().for_each(|ptr| allocator.dealloc(ptr, layout));
+    }
+}
+
+#[test]
+fn large_allocations_are_whole_pages() {
+    let allocator = Allocator::new();
+
+    for size in [SIZE_CLASSES[SIZE_CLASSES.len() - 1] + 1, page_size(), 3 * page_size() + 1] {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert_eq!(ptr.addr() % page_size(), 0, "size {size}");
+
+            fill(ptr, size, 0xA5);
+            check(ptr, size, 0xA5);
+
+            allocator.dealloc(ptr, layout);
+        }
+    }
+}
+
+#[test]
+fn over_aligned_allocations_fail() {
+    let allocator = Allocator::new();
+    let layout = Layout::from_size_align(page_size() * 2, page_size() * 2).unwrap();
+
+    assert!(unsafe { allocator.alloc(layout) }.is_null());
+}
+
+#[test]
+fn realloc_across_classes() {
+    let allocator = Allocator::new();
+    // Grows from the smallest class through a larger one into whole pages, then shrinks back into a class.
+    let sizes = [16, 200, 2048, 2 * page_size(), 100, 8];
+
+    unsafe {
+        let mut layout = Layout::from_size_align(sizes[0], 8).unwrap();
+        let mut ptr = allocator.alloc(layout);
+        fill(ptr, layout.size(), 0x3C);
+
+        for new_size in &sizes[1..] {
+            let kept = layout.size().min(*new_size);
+
+            ptr = allocator.realloc(ptr, layout, *new_size);
+            assert!(!ptr.is_null(), "size {new_size}");
+            check(ptr, kept, 0x3C);
+
+            layout = Layout::from_size_align(*new_size, 8).unwrap();
+            fill(ptr, layout.size(), 0x3C);
+        }
+
+        allocator.dealloc(ptr, layout);
+    }
+}
@@ -0,0 +1,23 @@
+#![cfg_attr(not(test), no_std)]
+#![feature(
+    naked_functions,            // #32408 <https://github.com/rust-lang/rust/issues/32408>
+    slice_ptr_get,              // #74265 <https://github.com/rust-lang/rust/issues/74265>
+)]
+
+//! Userspace runtime for Linuiz programs.
+//!
+//! Linking this crate provides the program entry point (`_start`), a panic handler, and a global allocator backed by
+//! the memory-mapping system calls. Programs using it should be `#![no_main]`, and define their entry point as:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! fn main() {}
+//! ```
+
+pub mod heap;
+pub mod env;
+
+#[cfg(not(test))]
+mod panic;
+#[cfg(not(test))]
+mod start;
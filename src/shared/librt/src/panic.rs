@@ -0,0 +1,44 @@
+use core::fmt::Write;
+
+/// Fixed-size buffer for formatting panic messages, as there's no guarantee the allocator is usable.
+struct MessageBuffer {
+    buffer: [u8; 512],
+    len: usize,
+}
+
+impl MessageBuffer {
+    const fn new() -> Self {
+        Self { buffer: [0u8; 512], len: 0 }
+    }
+
+    fn as_str(&self) -> &str {
+        // Safety: Only whole `str`s are ever copied into the buffer.
+        unsafe { core::str::from_utf8_unchecked(&self.buffer[..self.len]) }
+    }
+}
+
+impl Write for MessageBuffer {
+    fn write_str(&mut self, str: &str) -> core::fmt::Result {
+        let remaining = self.buffer.len() - self.len;
+        // Truncate at a character boundary, so the buffer is always valid UTF-8.
+        let copy_len = (0..=str.len().min(remaining)).rev().find(|len| str.is_char_boundary(*len)).unwrap_or(0);
+
+        self.buffer[self.len..(self.len + copy_len)].copy_from_slice(&str.as_bytes()[..copy_len]);
+        self.len += copy_len;
+
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    let mut message = MessageBuffer::new();
+    write!(message, "{info}").ok();
+
+    libsys::syscall::klog::error(message.as_str()).ok();
+    libsys::syscall::task::exit_task(1).ok();
+
+    loop {
+        core::hint::spin_loop();
+    }
+}
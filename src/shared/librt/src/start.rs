@@ -0,0 +1,38 @@
+/// Program entry point, jumped to by the kernel.
+///
+/// The stack is laid out according to the System V ABI, with `argc` at the stack pointer, followed by the
+/// null-terminated `argv` and `envp` pointer arrays.
+///
+/// ### Safety
+///
+/// This function should not be called from software.
+#[naked]
+#[no_mangle]
+unsafe extern "C" fn _start() -> ! {
+    core::arch::asm!(
+        "
+        xor rbp, rbp    # terminate the frame pointer chain
+        mov rdi, rsp    # stack pointer points to `argc`
+        and rsp, -16    # align stack for SysV
+        call {}
+        ud2
+        ",
+        sym start,
+        options(noreturn)
+    )
+}
+
+extern "sysv64" fn start(initial_stack: *const usize) -> ! {
+    extern "Rust" {
+        fn main();
+    }
+
+    // Safety: Kernel guarantees the initial stack layout.
+    unsafe { crate::env::init(initial_stack) };
+
+    // Safety: Program is required to define `main`.
+    unsafe { main() };
+
+    libsys::syscall::task::exit_task(0).ok();
+    unreachable!("task failed to exit")
+}
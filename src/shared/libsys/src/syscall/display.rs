@@ -0,0 +1,160 @@
+use super::{handle::Handle, Error, Result, Success, Vector};
+use core::ptr::NonNull;
+use num_enum::TryFromPrimitive;
+
+/// A rectangle of pixels, with its origin at the top-left.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// A pixel of a window's surface. Colors are blended with whatever lies beneath the window by their opacity (`a`),
+/// and aren't premultiplied by it.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Pixel {
+    pub b: u8,
+    pub g: u8,
+    pub r: u8,
+    pub a: u8,
+}
+
+/// Describes the surface of a window, which is shared with the display server. This is written by the kernel when a
+/// window is created or resized.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SurfaceInfo {
+    /// Address the surface is mapped at.
+    pub address: usize,
+    /// Size of the mapping, in bytes.
+    pub size: usize,
+    pub width: usize,
+    pub height: usize,
+    /// Number of pixels between the starts of consecutive rows.
+    pub stride: usize,
+}
+
+impl SurfaceInfo {
+    /// Pixels of the surface, in rows of `stride` pixels. They're valid to access until the surface is unmapped.
+    pub fn pixels(&self) -> Option<NonNull<[Pixel]>> {
+        NonNull::new(self.address as *mut Pixel).map(|ptr| NonNull::slice_from_raw_parts(ptr, self.stride * self.height))
+    }
+}
+
+#[repr(usize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
+pub enum EventKind {
+    #[default]
+    None = 0,
+    /// A commit has been composed to the screen. `serial` is the commit's serial, and `rect` the region of the
+    /// window which was redrawn.
+    Committed = 1,
+    /// A key was pressed or released. `key` is its USB HID usage ID, `character` the Unicode scalar value it
+    /// produces (or zero), and `pressed` whether it was pressed.
+    Key = 2,
+    /// The pointer moved over the window, to `x` and `y` (relative to the window's origin).
+    PointerMotion = 3,
+    /// A pointer button was pressed or released over the window. `buttons` holds the button which changed, and
+    /// `pressed` whether it was pressed.
+    PointerButton = 4,
+    /// The window gained (`pressed` is set) or lost keyboard focus.
+    Focus = 5,
+}
+
+/// An event delivered to a window by the display server.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub serial: usize,
+    pub rect: Rect,
+    pub key: usize,
+    pub character: usize,
+    pub pressed: usize,
+    pub x: usize,
+    pub y: usize,
+    pub buttons: usize,
+}
+
+/// A window created by [`create_window`], along with its surface.
+#[derive(Debug, Clone, Copy)]
+pub struct Window {
+    pub handle: Handle,
+    pub surface: SurfaceInfo,
+}
+
+/// Creates a window covering `rect` of the screen, above every other window, and maps its surface into the current
+/// task's address space. The surface starts out transparent.
+///
+/// The window and its surface are bound to the current task's address space, so the handle can't be transferred.
+pub fn create_window(rect: Rect) -> core::result::Result<Window, Error> {
+    let mut surface = SurfaceInfo::default();
+
+    // Safety: Kernel validates the provided address.
+    match unsafe {
+        super::invoke(
+            Vector::DisplayCreateWindow,
+            [rect.x, rect.y, rect.width, rect.height, core::ptr::addr_of_mut!(surface).addr(), 0],
+        )
+    }? {
+        Success::Value(window) => Ok(Window { handle: Handle::from_raw(window).ok_or(Error::InvalidHandle)?, surface }),
+        _ => unreachable!(),
+    }
+}
+
+/// Resizes the window, replacing its surface with one of the new size. The old surface is unmapped, and the parts of
+/// it the new surface still covers are copied into it.
+///
+/// ### Safety
+///
+/// Caller must ensure no references remain to the old surface's pixels.
+pub unsafe fn resize_window(window: Handle, width: usize, height: usize) -> core::result::Result<SurfaceInfo, Error> {
+    let mut surface = SurfaceInfo::default();
+
+    super::invoke(
+        Vector::DisplayResizeWindow,
+        [window.into_raw(), width, height, core::ptr::addr_of_mut!(surface).addr(), 0, 0],
+    )?;
+
+    Ok(surface)
+}
+
+/// Presents the window's surface, redrawing `damage` (relative to the window's origin), or the whole window if
+/// `None`. Returns the commit's serial, which is acknowledged with a [`EventKind::Committed`] event once composed.
+pub fn commit(window: Handle, damage: Option<Rect>) -> core::result::Result<usize, Error> {
+    let damage_ptr = damage.as_ref().map_or(0, |damage| NonNull::from(damage).addr().get());
+
+    // Safety: Kernel validates the provided address.
+    match unsafe { super::invoke(Vector::DisplayCommit, [window.into_raw(), damage_ptr, 0, 0, 0, 0]) }? {
+        Success::Value(serial) => Ok(serial),
+        _ => unreachable!(),
+    }
+}
+
+/// Destroys the window, removing it from the screen, and unmaps its surface.
+///
+/// ### Safety
+///
+/// Caller must ensure no references remain to the surface's pixels.
+pub unsafe fn destroy_window(window: Handle) -> Result {
+    super::invoke(Vector::DisplayDestroyWindow, [window.into_raw(), 0, 0, 0, 0, 0])
+}
+
+/// Takes the next event delivered to the window, blocking until there is one.
+pub fn wait_event(window: Handle) -> core::result::Result<Event, Error> {
+    let mut event = Event::default();
+
+    // Safety: Kernel validates the provided address.
+    unsafe {
+        super::invoke_blocking(
+            Vector::DisplayWaitEvent,
+            [window.into_raw(), core::ptr::addr_of_mut!(event).addr(), 0, 0, 0, 0],
+        )
+    }?;
+
+    Ok(event)
+}
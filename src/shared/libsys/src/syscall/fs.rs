@@ -0,0 +1,178 @@
+use super::{Error, Result, Success, Vector};
+use num_enum::TryFromPrimitive;
+
+/// Longest file name which can be returned in a [`DirEntry`].
+pub const MAX_NAME_LEN: usize = 256;
+
+bitflags::bitflags! {
+    /// Selects how a file is opened.
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct OpenFlags : usize {
+        /// Permits reading from the file.
+        const READ = 1 << 0;
+        /// Permits writing to the file.
+        const WRITE = 1 << 1;
+        /// Creates the file if it doesn't exist.
+        const CREATE = 1 << 2;
+        /// Truncates the file to zero length when it's opened for writing.
+        const TRUNCATE = 1 << 3;
+        /// Moves the file offset to the end of the file before each write.
+        const APPEND = 1 << 4;
+        /// Fails to open the file unless it's a directory.
+        const DIRECTORY = 1 << 5;
+    }
+}
+
+/// A file descriptor, which names an open file in the current task's file table.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fd(usize);
+
+impl Fd {
+    #[inline]
+    pub const fn from_raw(raw: usize) -> Self {
+        Self(raw)
+    }
+
+    #[inline]
+    pub const fn into_raw(self) -> usize {
+        self.0
+    }
+}
+
+#[repr(usize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
+pub enum FileKind {
+    #[default]
+    Regular = 0,
+    Directory = 1,
+    Symlink = 2,
+    CharDevice = 3,
+    BlockDevice = 4,
+}
+
+/// Position a seek is relative to.
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
+pub enum Whence {
+    Start = 0,
+    Current = 1,
+    End = 2,
+}
+
+/// Describes an open file. This is written by the kernel on [`stat`].
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stat {
+    pub kind: FileKind,
+    pub size: usize,
+    /// Number of the file's inode, which is unique within its filesystem.
+    pub inode: usize,
+    /// Unix permission bits of the file (e.g. `0o755`).
+    pub permissions: usize,
+    pub uid: usize,
+    pub gid: usize,
+    pub link_count: usize,
+}
+
+/// Describes an entry of a directory. This is written by the kernel on [`read_dir`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirEntry {
+    pub kind: FileKind,
+    pub inode: usize,
+    pub name_len: usize,
+    pub name: [u8; MAX_NAME_LEN],
+}
+
+impl DirEntry {
+    /// Name of the entry, or `None` if it isn't valid UTF-8.
+    pub fn name(&self) -> Option<&str> {
+        core::str::from_utf8(&self.name[..self.name_len.min(MAX_NAME_LEN)]).ok()
+    }
+}
+
+impl Default for DirEntry {
+    fn default() -> Self {
+        Self { kind: FileKind::default(), inode: 0, name_len: 0, name: [0; MAX_NAME_LEN] }
+    }
+}
+
+/// Opens the file at the absolute path `path`, returning a file descriptor for it.
+pub fn open(path: &str, flags: OpenFlags) -> core::result::Result<Fd, Error> {
+    // Safety: Kernel validates the provided addresses.
+    match unsafe { super::invoke(Vector::FsOpen, [path.as_ptr().addr(), path.len(), flags.bits(), 0, 0, 0]) }? {
+        Success::Value(fd) => Ok(Fd::from_raw(fd)),
+        _ => unreachable!(),
+    }
+}
+
+/// Reads from the file offset into `buffer`, advancing the offset. Returns the number of bytes read, which is zero
+/// at the end of the file.
+pub fn read(fd: Fd, buffer: &mut [u8]) -> core::result::Result<usize, Error> {
+    // Safety: Kernel validates the provided addresses.
+    match unsafe { super::invoke(Vector::FsRead, [fd.into_raw(), buffer.as_mut_ptr().addr(), buffer.len(), 0, 0, 0]) }?
+    {
+        Success::Value(len) => Ok(len),
+        _ => unreachable!(),
+    }
+}
+
+/// Writes `buffer` at the file offset, advancing the offset. Returns the number of bytes written.
+pub fn write(fd: Fd, buffer: &[u8]) -> core::result::Result<usize, Error> {
+    // Safety: Kernel validates the provided addresses.
+    match unsafe { super::invoke(Vector::FsWrite, [fd.into_raw(), buffer.as_ptr().addr(), buffer.len(), 0, 0, 0]) }? {
+        Success::Value(len) => Ok(len),
+        _ => unreachable!(),
+    }
+}
+
+/// Moves the file offset to `offset` bytes from `whence`, returning the new offset.
+pub fn seek(fd: Fd, offset: isize, whence: Whence) -> core::result::Result<usize, Error> {
+    // The offset is passed as its two's complement bit pattern.
+    #[allow(clippy::cast_sign_loss)]
+    let offset = offset as usize;
+
+    // Safety: Vector takes no pointer arguments.
+    match unsafe { super::invoke(Vector::FsSeek, [fd.into_raw(), offset, whence as usize, 0, 0, 0]) }? {
+        Success::Value(offset) => Ok(offset),
+        _ => unreachable!(),
+    }
+}
+
+/// Closes `fd`, committing the file's writes if it was opened for writing. The file is closed once no file
+/// descriptors to it remain.
+pub fn close(fd: Fd) -> Result {
+    // Safety: Vector takes no pointer arguments.
+    unsafe { super::invoke(Vector::FsClose, [fd.into_raw(), 0, 0, 0, 0, 0]) }
+}
+
+/// Describes the open file `fd`.
+pub fn stat(fd: Fd) -> core::result::Result<Stat, Error> {
+    let mut stat = Stat::default();
+
+    // Safety: Kernel validates the provided address.
+    unsafe { super::invoke(Vector::FsStat, [fd.into_raw(), core::ptr::addr_of_mut!(stat).addr(), 0, 0, 0, 0]) }?;
+
+    Ok(stat)
+}
+
+/// Reads the next entries of the directory `fd` into `buffer`, advancing the file offset past them. Returns the
+/// number of entries read, which is zero once every entry has been read.
+pub fn read_dir(fd: Fd, buffer: &mut [DirEntry]) -> core::result::Result<usize, Error> {
+    // Safety: Kernel validates the provided addresses.
+    match unsafe {
+        super::invoke(Vector::FsReadDir, [fd.into_raw(), buffer.as_mut_ptr().addr(), buffer.len(), 0, 0, 0])
+    }? {
+        Success::Value(count) => Ok(count),
+        _ => unreachable!(),
+    }
+}
+
+/// Commits the writes of `fd` (and of the rest of its filesystem) to the underlying device. Otherwise, writes are
+/// only committed when a writable file descriptor to the file is closed.
+pub fn sync(fd: Fd) -> Result {
+    // Safety: Vector takes no pointer arguments.
+    unsafe { super::invoke(Vector::FsSync, [fd.into_raw(), 0, 0, 0, 0, 0]) }
+}
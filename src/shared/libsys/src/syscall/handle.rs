@@ -0,0 +1,67 @@
+use super::{Error, Result, Success, Vector};
+use core::num::NonZeroUsize;
+
+bitflags::bitflags! {
+    /// Operations a handle permits on the kernel object it names.
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Rights : usize {
+        /// Permits receiving from a channel, or joining a task's threads.
+        const READ = 1 << 0;
+        /// Permits sending on a channel.
+        const WRITE = 1 << 1;
+        /// Permits mapping a memory object into an address space.
+        const MAP = 1 << 2;
+        /// Permits configuring the object (e.g. a device or interrupt line), or, for a task, spawning its threads and
+        /// managing its address space.
+        const MANAGE = 1 << 3;
+        /// Permits duplicating the handle.
+        const DUPLICATE = 1 << 4;
+        /// Permits transferring the handle to another task.
+        const TRANSFER = 1 << 5;
+    }
+}
+
+/// Names a kernel object in the current task's handle table. Handles are never zero.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Handle(NonZeroUsize);
+
+impl Handle {
+    #[inline]
+    pub const fn from_raw(raw: usize) -> Option<Self> {
+        match NonZeroUsize::new(raw) {
+            Some(raw) => Some(Self(raw)),
+            None => None,
+        }
+    }
+
+    #[inline]
+    pub const fn into_raw(self) -> usize {
+        self.0.get()
+    }
+}
+
+/// Duplicates `handle`, with the new handle having only `rights`. These must be a subset of the handle's rights.
+pub fn duplicate(handle: Handle, rights: Rights) -> core::result::Result<Handle, Error> {
+    // Safety: Vector takes no pointer arguments.
+    match unsafe { super::invoke(Vector::HandleDuplicate, [handle.into_raw(), rights.bits(), 0, 0, 0, 0]) }? {
+        Success::Value(handle) => Handle::from_raw(handle).ok_or(Error::InvalidHandle),
+        _ => unreachable!(),
+    }
+}
+
+/// Closes `handle`. The object it names is destroyed once no handles to it remain.
+pub fn close(handle: Handle) -> Result {
+    // Safety: Vector takes no pointer arguments.
+    unsafe { super::invoke(Vector::HandleClose, [handle.into_raw(), 0, 0, 0, 0, 0]) }
+}
+
+/// Moves `handle` out of the current task's handle table, and sends it as a message on `channel`. The receiver finds
+/// the handle in [`super::ipc::MessageInfo::handle`].
+///
+/// This blocks while the channel is full.
+pub fn transfer(handle: Handle, channel: Handle) -> Result {
+    // Safety: Vector takes no pointer arguments.
+    unsafe { super::invoke_blocking(Vector::HandleTransfer, [handle.into_raw(), channel.into_raw(), 0, 0, 0, 0]) }
+}
@@ -0,0 +1,131 @@
+use super::{handle::Handle, Error, Result, Success, Vector};
+use core::{num::NonZeroUsize, ptr::NonNull};
+
+/// Maximum size of the data copied with a message. Larger payloads should be transferred as a page grant.
+pub const MAX_MESSAGE_SIZE: usize = 0x1000;
+
+/// Pages to be transferred out of the sender's address space with a message.
+#[derive(Debug, Clone, Copy)]
+pub struct Grant {
+    pub address: NonNull<u8>,
+    pub page_count: NonZeroUsize,
+}
+
+/// Describes a received message. This is written by the kernel on receipt.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MessageInfo {
+    /// Length of the message's data. If this is larger than the receive buffer, the data was truncated.
+    pub len: usize,
+    /// Handle to reply with, if the message was sent with [`call`]. Otherwise, zero. The handle is closed once the
+    /// reply is sent.
+    pub reply_handle: usize,
+    /// Address the granted pages were mapped at, if the message carried a grant. Otherwise, zero.
+    pub grant_address: usize,
+    /// Number of pages granted with the message.
+    pub grant_page_count: usize,
+    /// Handle transferred with the message, which has been added to the receiver's handle table. Otherwise, zero.
+    pub handle: usize,
+}
+
+fn grant_args(grant: Option<Grant>) -> (usize, usize) {
+    grant.map_or((0, 0), |grant| (grant.address.addr().get(), grant.page_count.get()))
+}
+
+/// Creates a new channel which can hold `capacity` messages, returning a handle to it.
+pub fn create_channel(capacity: NonZeroUsize) -> core::result::Result<Handle, Error> {
+    // Safety: Vector takes no pointer arguments.
+    match unsafe { super::invoke(Vector::IpcCreate, [capacity.get(), 0, 0, 0, 0, 0]) }? {
+        Success::Value(channel) => Handle::from_raw(channel).ok_or(Error::InvalidHandle),
+        _ => unreachable!(),
+    }
+}
+
+/// Sends a message on a channel, blocking while the channel is full.
+///
+/// Any granted pages are unmapped from the current task's address space.
+pub fn send(channel: Handle, data: &[u8], grant: Option<Grant>) -> Result {
+    let (grant_address, grant_page_count) = grant_args(grant);
+
+    // Safety: Kernel validates the provided addresses.
+    unsafe {
+        super::invoke_blocking(
+            Vector::IpcSend,
+            [channel.into_raw(), data.as_ptr().addr(), data.len(), grant_address, grant_page_count, 0],
+        )
+    }
+}
+
+/// Receives a message from a channel into `buffer`, blocking until one is available.
+pub fn receive(channel: Handle, buffer: &mut [u8]) -> core::result::Result<MessageInfo, Error> {
+    let mut info = MessageInfo::default();
+
+    // Safety: Kernel validates the provided addresses.
+    unsafe {
+        super::invoke_blocking(
+            Vector::IpcReceive,
+            [channel.into_raw(), buffer.as_mut_ptr().addr(), buffer.len(), core::ptr::addr_of_mut!(info).addr(), 0, 0],
+        )
+    }?;
+
+    Ok(info)
+}
+
+/// Sends a message on a channel, then blocks until the receiver replies, receiving the reply into `reply_buffer`.
+///
+/// Only the receiver of the message (or whoever it transfers its reply handle to) can reply.
+pub fn call(
+    channel: Handle,
+    data: &[u8],
+    grant: Option<Grant>,
+    reply_buffer: &mut [u8],
+) -> core::result::Result<MessageInfo, Error> {
+    let (grant_address, grant_page_count) = grant_args(grant);
+
+    // Safety: Kernel validates the provided addresses.
+    let reply_handle = match unsafe {
+        super::invoke_blocking(
+            Vector::IpcCall,
+            [channel.into_raw(), data.as_ptr().addr(), data.len(), grant_address, grant_page_count, 0],
+        )
+    }? {
+        Success::Value(reply_handle) => Handle::from_raw(reply_handle).ok_or(Error::InvalidHandle)?,
+        _ => unreachable!(),
+    };
+
+    let mut info = MessageInfo::default();
+    // Safety: Kernel validates the provided addresses.
+    let result = unsafe {
+        super::invoke_blocking(
+            Vector::IpcAwaitReply,
+            [
+                reply_handle.into_raw(),
+                reply_buffer.as_mut_ptr().addr(),
+                reply_buffer.len(),
+                core::ptr::addr_of_mut!(info).addr(),
+                0,
+                0,
+            ],
+        )
+    };
+
+    // The kernel closes the reply handle once the reply is received. Otherwise, the reply is given up on.
+    if result.is_err() {
+        super::handle::close(reply_handle).ok();
+    }
+
+    result.map(|_| info)
+}
+
+/// Replies to a message received with a reply handle, closing the handle. A call can only be replied to once.
+pub fn reply(reply_handle: Handle, data: &[u8], grant: Option<Grant>) -> Result {
+    let (grant_address, grant_page_count) = grant_args(grant);
+
+    // Safety: Kernel validates the provided addresses.
+    unsafe {
+        super::invoke(
+            Vector::IpcReply,
+            [reply_handle.into_raw(), data.as_ptr().addr(), data.len(), grant_address, grant_page_count, 0],
+        )
+    }
+}
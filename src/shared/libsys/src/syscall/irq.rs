@@ -0,0 +1,28 @@
+use super::{handle::Handle, Error, Result, Success, Vector};
+
+/// Creates an interrupt object for the message `index` of a claimed PCI device, programming the device's MSI-X (or
+/// MSI) capability to raise it.
+pub fn create(device: Handle, index: usize) -> core::result::Result<Handle, Error> {
+    // Safety: Vector takes no pointer arguments.
+    match unsafe { super::invoke(Vector::IrqCreate, [device.into_raw(), index, 0, 0, 0, 0]) }? {
+        Success::Value(interrupt) => Handle::from_raw(interrupt).ok_or(Error::InvalidHandle),
+        _ => unreachable!(),
+    }
+}
+
+/// Blocks until the interrupt is raised, returning the number of times it was raised since the last wait.
+///
+/// A raised interrupt stays masked until it is acknowledged with [`acknowledge`].
+pub fn wait(interrupt: Handle) -> core::result::Result<usize, Error> {
+    // Safety: Vector takes no pointer arguments.
+    match unsafe { super::invoke_blocking(Vector::IrqWait, [interrupt.into_raw(), 0, 0, 0, 0, 0]) }? {
+        Success::Value(count) => Ok(count),
+        _ => unreachable!(),
+    }
+}
+
+/// Acknowledges a raised interrupt, unmasking it so it can be raised again.
+pub fn acknowledge(interrupt: Handle) -> Result {
+    // Safety: Vector takes no pointer arguments.
+    unsafe { super::invoke(Vector::IrqAcknowledge, [interrupt.into_raw(), 0, 0, 0, 0, 0]) }
+}
@@ -24,23 +24,8 @@ pub fn trace(str: &str) -> Result {
 }
 
 fn klog(offset: KlogVectorOffset, str: &str) -> Result {
-    let vector = (Vector::KlogInfo as usize) + (offset as usize);
-    let str_ptr = str.as_ptr();
-    let str_len = str.len();
+    let vector = Vector::try_from((Vector::KlogInfo as usize) + (offset as usize)).unwrap();
 
-    // Safety: It isn't.
-    unsafe {
-        let discriminant: usize;
-        let value: usize;
-
-        core::arch::asm!(
-            "int 0x80",
-            in("rax") vector,
-            inout("rdi") str_ptr => discriminant,
-            inout("rsi") str_len => value,
-            options(nostack, nomem, preserves_flags)
-        );
-
-        <Result as super::ResultConverter>::from_registers((discriminant, value))
-    }
+    // Safety: Pointer and length are valid for the lifetime of the call.
+    unsafe { super::invoke(vector, [str.as_ptr().addr(), str.len(), 0, 0, 0, 0]) }
 }
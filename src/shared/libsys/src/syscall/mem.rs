@@ -0,0 +1,140 @@
+use super::{handle::Handle, Error, Result, Success, Vector};
+use crate::page_size;
+use core::{num::NonZeroUsize, ptr::NonNull};
+use num_enum::TryFromPrimitive;
+
+/// Access permissions for a memory mapping.
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
+pub enum Permissions {
+    ReadExecute = 0,
+    ReadWrite = 1,
+    ReadOnly = 2,
+}
+
+/// Caching behaviour of a DMA buffer mapping.
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
+pub enum Cacheability {
+    WriteBack = 0,
+    WriteThrough = 1,
+    Uncacheable = 2,
+}
+
+/// Physically contiguous memory, which devices can access directly.
+#[derive(Debug, Clone, Copy)]
+pub struct DmaBuffer {
+    pub memory: NonNull<[u8]>,
+    pub physical_address: usize,
+}
+
+/// Maps `page_count` pages of zeroed memory into `task`'s address space. `task` must have `Rights::MANAGE`.
+///
+/// If `address` is provided, the mapping will be placed exactly there (and must be page-aligned). Otherwise, the
+/// kernel will choose a free region.
+pub fn map(
+    task: Handle,
+    address: Option<NonNull<u8>>,
+    page_count: NonZeroUsize,
+    permissions: Permissions,
+) -> core::result::Result<NonNull<[u8]>, Error> {
+    let address = address.map_or(0, |address| address.addr().get());
+
+    // Safety: Kernel validates the provided address range.
+    match unsafe {
+        super::invoke(Vector::MemMap, [task.into_raw(), address, page_count.get(), permissions as usize, 0, 0])
+    }? {
+        Success::NonNullPtr(ptr) => Ok(NonNull::slice_from_raw_parts(ptr.cast(), page_count.get() * page_size())),
+        _ => unreachable!(),
+    }
+}
+
+/// Unmaps `page_count` pages starting at `address` from `task`'s address space.
+///
+/// `task` must be a handle to the current task (such as [`super::task::CURRENT`]) with `Rights::MANAGE`, as
+/// unmappings are only flushed from the local core's TLB.
+///
+/// ### Safety
+///
+/// Caller must ensure no references remain to the memory being unmapped.
+pub unsafe fn unmap(task: Handle, address: NonNull<u8>, page_count: NonZeroUsize) -> Result {
+    super::invoke(Vector::MemUnmap, [task.into_raw(), address.addr().get(), page_count.get(), 0, 0, 0])
+}
+
+/// Changes the access permissions of `page_count` pages starting at `address` in `task`'s address space.
+///
+/// As with [`unmap`], `task` must be a handle to the current task with `Rights::MANAGE`.
+///
+/// ### Safety
+///
+/// Caller must ensure no references to the memory are invalidated by the permission change.
+pub unsafe fn protect(
+    task: Handle,
+    address: NonNull<u8>,
+    page_count: NonZeroUsize,
+    permissions: Permissions,
+) -> Result {
+    super::invoke(
+        Vector::MemProtect,
+        [task.into_raw(), address.addr().get(), page_count.get(), permissions as usize, 0, 0],
+    )
+}
+
+/// Maps `page_count` pages of zeroed, physically contiguous memory into `task`'s address space, for use as a DMA
+/// buffer. If `below_4gib` is set, the memory lies entirely below 4 GiB (for devices which can only address 32 bits).
+/// `task` must have `Rights::MANAGE`.
+///
+/// The memory stays at its physical address until it is unmapped, or the task exits.
+pub fn map_dma(
+    task: Handle,
+    page_count: NonZeroUsize,
+    below_4gib: bool,
+    cacheability: Cacheability,
+) -> core::result::Result<DmaBuffer, Error> {
+    let mut physical_address = 0usize;
+
+    // Safety: Kernel validates the provided address.
+    match unsafe {
+        super::invoke(
+            Vector::MemMapDma,
+            [
+                task.into_raw(),
+                page_count.get(),
+                usize::from(below_4gib),
+                cacheability as usize,
+                core::ptr::addr_of_mut!(physical_address).addr(),
+                0,
+            ],
+        )
+    }? {
+        Success::NonNullPtr(ptr) => Ok(DmaBuffer {
+            memory: NonNull::slice_from_raw_parts(ptr.cast(), page_count.get() * page_size()),
+            physical_address,
+        }),
+        _ => unreachable!(),
+    }
+}
+
+/// Creates a memory object of `page_count` zeroed pages, returning a handle to it. The object can be mapped into any
+/// number of address spaces with [`map_object`], and is freed once it's neither mapped nor named by a handle.
+pub fn create(page_count: NonZeroUsize) -> core::result::Result<Handle, Error> {
+    // Safety: Vector takes no pointer arguments.
+    match unsafe { super::invoke(Vector::MemCreate, [page_count.get(), 0, 0, 0, 0, 0]) }? {
+        Success::Value(memory) => Handle::from_raw(memory).ok_or(Error::InvalidHandle),
+        _ => unreachable!(),
+    }
+}
+
+/// Maps the whole of the memory object `memory` into `task`'s address space, returning the address of its first page.
+/// `memory` must have `Rights::MAP`, and `task` must have `Rights::MANAGE`.
+///
+/// The mapping is removed with [`unmap`], which must cover the whole object.
+pub fn map_object(task: Handle, memory: Handle, permissions: Permissions) -> core::result::Result<NonNull<u8>, Error> {
+    // Safety: Vector takes no pointer arguments.
+    match unsafe {
+        super::invoke(Vector::MemMapObject, [task.into_raw(), memory.into_raw(), permissions as usize, 0, 0, 0])
+    }? {
+        Success::NonNullPtr(ptr) => Ok(ptr.cast()),
+        _ => unreachable!(),
+    }
+}
@@ -1,7 +1,17 @@
+pub mod display;
+pub mod fs;
+pub mod handle;
+pub mod ipc;
+pub mod irq;
 pub mod klog;
+pub mod mem;
+pub mod pci;
 pub mod task;
 
-use core::ffi::c_void;
+use core::{
+    ffi::c_void,
+    sync::atomic::{AtomicU8, Ordering},
+};
 use num_enum::TryFromPrimitive;
 
 #[repr(usize)]
@@ -14,6 +24,142 @@ pub enum Vector {
 
     TaskExit = 0x200,
     TaskYield = 0x201,
+    TaskSleep = 0x202,
+    TaskFutexWait = 0x203,
+    TaskFutexWake = 0x204,
+    TaskSpawnThread = 0x205,
+    TaskJoinThread = 0x206,
+    TaskWait = 0x207,
+
+    MemMap = 0x300,
+    MemUnmap = 0x301,
+    MemProtect = 0x302,
+    MemMapDma = 0x303,
+    MemCreate = 0x304,
+    MemMapObject = 0x305,
+
+    IpcCreate = 0x400,
+    IpcSend = 0x401,
+    IpcReceive = 0x402,
+    IpcCall = 0x403,
+    IpcAwaitReply = 0x404,
+    IpcReply = 0x405,
+
+    HandleDuplicate = 0x500,
+    HandleClose = 0x501,
+    HandleTransfer = 0x502,
+
+    PciEnumerate = 0x600,
+    PciClaim = 0x601,
+
+    IrqCreate = 0x700,
+    IrqWait = 0x701,
+    IrqAcknowledge = 0x702,
+
+    FsOpen = 0x800,
+    FsRead = 0x801,
+    FsWrite = 0x802,
+    FsSeek = 0x803,
+    FsClose = 0x804,
+    FsStat = 0x805,
+    FsReadDir = 0x806,
+    FsSync = 0x807,
+
+    DisplayCreateWindow = 0x900,
+    DisplayResizeWindow = 0x901,
+    DisplayCommit = 0x902,
+    DisplayDestroyWindow = 0x903,
+    DisplayWaitEvent = 0x904,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
+enum Method {
+    Unknown = 0,
+    Interrupt = 1,
+    Syscall = 2,
+}
+
+static METHOD: AtomicU8 = AtomicU8::new(Method::Unknown as u8);
+
+/// Determines (once) the system call method supported by the processor.
+fn method() -> Method {
+    match Method::try_from(METHOD.load(Ordering::Relaxed)) {
+        Ok(Method::Unknown) | Err(_) => {
+            let method = if core::arch::x86_64::__cpuid(0x8000_0001).edx & (1 << 11) > 0 {
+                Method::Syscall
+            } else {
+                Method::Interrupt
+            };
+
+            METHOD.store(method as u8, Ordering::Relaxed);
+
+            method
+        }
+
+        Ok(method) => method,
+    }
+}
+
+/// Invokes the kernel with the given system call vector and arguments.
+///
+/// `syscall` is used where the processor supports it, otherwise this falls back to `int 0x80`.
+///
+/// ### Safety
+///
+/// Caller must ensure the arguments are valid for the given vector.
+#[allow(clippy::similar_names)]
+pub(crate) unsafe fn invoke(vector: Vector, args: [usize; 6]) -> Result {
+    let [arg0, arg1, arg2, arg3, arg4, arg5] = args;
+
+    let discriminant: usize;
+    let value: usize;
+
+    match method() {
+        Method::Syscall => core::arch::asm!(
+            "syscall",
+            in("rax") vector as usize,
+            inout("rdi") arg0 => discriminant,
+            inout("rsi") arg1 => value,
+            in("rdx") arg2,
+            // `syscall` clobbers `rcx`, so the fourth argument is passed in `r10`.
+            in("r10") arg3,
+            in("r8") arg4,
+            in("r9") arg5,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack)
+        ),
+
+        Method::Interrupt | Method::Unknown => core::arch::asm!(
+            "int 0x80",
+            in("rax") vector as usize,
+            inout("rdi") arg0 => discriminant,
+            inout("rsi") arg1 => value,
+            in("rdx") arg2,
+            in("rcx") arg3,
+            in("r8") arg4,
+            in("r9") arg5,
+            options(nostack)
+        ),
+    }
+
+    <Result as ResultConverter>::from_registers((discriminant, value))
+}
+
+/// Invokes `vector` until it completes without blocking. Blocking calls report `Error::WouldBlock` when woken, to
+/// indicate they should be retried.
+///
+/// ### Safety
+///
+/// See [`invoke`].
+pub(crate) unsafe fn invoke_blocking(vector: Vector, args: [usize; 6]) -> Result {
+    loop {
+        match invoke(vector, args) {
+            Err(Error::WouldBlock) => {}
+            result => return result,
+        }
+    }
 }
 
 const_assert!({
@@ -21,7 +167,7 @@ const_assert!({
     size_of::<Result>() <= size_of::<(u64, u64)>()
 });
 
-pub type Result = core::result::Result<Success, Error>;
+pub type Result<T = Success> = core::result::Result<T, Error>;
 
 pub trait ResultConverter {
     type Registers;
@@ -41,6 +187,7 @@ impl ResultConverter for Result {
             Err(0x0) => Ok(Success::Ok),
             Err(0x1) => Ok(Success::Ptr(value as *mut c_void)),
             Err(0x2) => Ok(Success::NonNullPtr(core::ptr::NonNull::new(value as *mut c_void).unwrap())),
+            Err(0x3) => Ok(Success::Value(value)),
 
             Err(_) => unimplemented!(),
         }
@@ -51,6 +198,7 @@ impl ResultConverter for Result {
             Ok(success @ Success::Ok) => (success.discriminant() as usize, usize::default()),
             Ok(success @ Success::Ptr(ptr)) => (success.discriminant() as usize, ptr.addr()),
             Ok(success @ Success::NonNullPtr(ptr)) => (success.discriminant() as usize, ptr.addr().get()),
+            Ok(success @ Success::Value(value)) => (success.discriminant() as usize, value),
 
             Err(err) => (err as usize, Default::default()),
         }
@@ -63,6 +211,7 @@ pub enum Success {
     Ok = 0x0,
     Ptr(*mut c_void) = 0x1,
     NonNullPtr(core::ptr::NonNull<c_void>) = 0x2,
+    Value(usize) = 0x3,
 }
 
 impl Success {
@@ -83,6 +232,22 @@ pub enum Error {
     UnmappedMemory = 0x40000,
 
     NoActiveTask = 0x50000,
+
+    InvalidAddress = 0x60000,
+    InvalidArgument = 0x70000,
+    OutOfMemory = 0x80000,
+    WouldBlock = 0x90000,
+
+    InvalidHandle = 0xA0000,
+    AccessDenied = 0xB0000,
+
+    NotFound = 0xC0000,
+    AlreadyExists = 0xD0000,
+    NotDirectory = 0xE0000,
+    IsDirectory = 0xF0000,
+    ReadOnly = 0x100000,
+    Io = 0x110000,
+    NoSpace = 0x120000,
 }
 
 impl From<core::str::Utf8Error> for Error {
@@ -0,0 +1,98 @@
+use super::{handle::Handle, Error, Success, Vector};
+
+/// Number of base address registers of a standard PCI device.
+pub const BAR_COUNT: usize = 6;
+
+/// Selects the devices returned by [`enumerate`]. `None` matches any value.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Filter {
+    pub class: Option<u8>,
+    pub subclass: Option<u8>,
+    pub vendor_id: Option<u16>,
+}
+
+/// Describes a PCI device. This is written by the kernel on enumeration.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceInfo {
+    /// Identifies the device for [`claim`].
+    pub index: usize,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision_id: u8,
+}
+
+#[repr(usize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BarKind {
+    #[default]
+    Unused = 0,
+    /// The BAR's memory has been mapped into the claiming task's address space.
+    Memory = 1,
+    /// The BAR describes a range of I/O ports, which are not mapped.
+    Io = 2,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Bar {
+    pub kind: BarKind,
+    /// Virtual address of the mapped memory, or the first I/O port.
+    pub address: usize,
+    pub size: usize,
+}
+
+/// Describes the mappings of a claimed device. This is written by the kernel on claim.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClaimInfo {
+    /// Virtual address of the device's mapped configuration space.
+    pub config_address: usize,
+    pub bars: [Bar; BAR_COUNT],
+}
+
+/// Value of a filter argument which matches any value.
+pub const FILTER_ANY: usize = usize::MAX;
+
+fn filter_arg(value: Option<impl Into<usize>>) -> usize {
+    value.map_or(FILTER_ANY, Into::into)
+}
+
+/// Enumerates the PCI devices which match `filter`, writing as many as will fit into `buffer`. Returns the total
+/// number of matching devices.
+pub fn enumerate(filter: Filter, buffer: &mut [DeviceInfo]) -> core::result::Result<usize, Error> {
+    // Safety: Kernel validates the provided addresses.
+    match unsafe {
+        super::invoke(
+            Vector::PciEnumerate,
+            [
+                filter_arg(filter.class),
+                filter_arg(filter.subclass),
+                filter_arg(filter.vendor_id),
+                buffer.as_mut_ptr().addr(),
+                buffer.len(),
+                0,
+            ],
+        )
+    }? {
+        Success::Value(count) => Ok(count),
+        _ => unreachable!(),
+    }
+}
+
+/// Claims exclusive ownership of the device at `index`, mapping its configuration space and memory BARs into the
+/// current task's address space.
+///
+/// The claim is released when the returned handle is closed, or the task exits.
+pub fn claim(index: usize) -> core::result::Result<(Handle, ClaimInfo), Error> {
+    let mut info = ClaimInfo::default();
+
+    // Safety: Kernel validates the provided addresses.
+    match unsafe { super::invoke(Vector::PciClaim, [index, core::ptr::addr_of_mut!(info).addr(), 0, 0, 0, 0]) }? {
+        Success::Value(handle) => Ok((Handle::from_raw(handle).ok_or(Error::InvalidHandle)?, info)),
+        _ => unreachable!(),
+    }
+}
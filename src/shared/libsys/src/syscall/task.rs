@@ -1,37 +1,98 @@
-use super::{Result, Vector};
+use super::{handle::Handle, Error, Result, Success, Vector};
+use core::sync::atomic::AtomicU32;
+
+/// Handle to the current task, which every task starts with in its handle table.
+pub const CURRENT: Handle = Handle::from_raw(1).unwrap();
 
 pub fn yield_task() -> Result {
-    // Safety: We're very careful.
-    unsafe {
-        let discriminant: usize;
-        let value: usize;
-
-        core::arch::asm!(
-            "int 0x80",
-            in("rax") Vector::TaskYield as usize,
-            out("rdi") discriminant,
-            out("rsi") value,
-            options(nostack, nomem, preserves_flags)
-        );
-
-        <Result as super::ResultConverter>::from_registers((discriminant, value))
+    // Safety: Vector takes no arguments.
+    unsafe { super::invoke(Vector::TaskYield, [0; 6]) }
+}
+
+/// Exits the current task, making `status` available to any waiters.
+///
+/// Exiting the main thread exits the whole process, and its remaining threads are torn down.
+pub fn exit_task(status: usize) -> Result {
+    // Safety: Vector takes no pointer arguments.
+    unsafe { super::invoke(Vector::TaskExit, [status, 0, 0, 0, 0, 0]) }
+}
+
+/// Blocks the current task for at least `milliseconds`.
+pub fn sleep(milliseconds: usize) -> Result {
+    // Safety: Vector takes no pointer arguments.
+    unsafe { super::invoke(Vector::TaskSleep, [milliseconds, 0, 0, 0, 0, 0]) }
+}
+
+/// Blocks the current task until woken by [`futex_wake`], if `futex` still holds `expected`.
+///
+/// Returns `Error::WouldBlock` if the value of `futex` was not `expected`.
+pub fn futex_wait(futex: &AtomicU32, expected: u32) -> Result {
+    // Safety: Kernel validates the provided address.
+    unsafe { super::invoke(Vector::TaskFutexWait, [futex.as_ptr().addr(), expected as usize, 0, 0, 0, 0]) }
+}
+
+/// Wakes up to `count` tasks waiting on `futex`, returning how many were woken.
+pub fn futex_wake(futex: &AtomicU32, count: usize) -> core::result::Result<usize, Error> {
+    // Safety: Kernel validates the provided address.
+    match unsafe { super::invoke(Vector::TaskFutexWake, [futex.as_ptr().addr(), count, 0, 0, 0, 0]) }? {
+        Success::Value(woken) => Ok(woken),
+        _ => unreachable!(),
     }
 }
 
-pub fn exit_task() -> Result {
-    // Safety: We're very careful.
-    unsafe {
-        let discriminant: usize;
-        let value: usize;
+/// Spawns a new thread in `task`, which begins executing `entry` with `arg`, and with its thread-local storage base set
+/// to `tls_base`. Returns the ID of the new thread.
+///
+/// `task` must be a handle to the current task (such as [`CURRENT`]) with `Rights::MANAGE`, as a task's threads all run
+/// on one core. Threads should exit with [`exit_task`], which only exits the calling thread (unless it's the main
+/// thread).
+///
+/// Threads run concurrently, but never in parallel: once a task spawns a thread, all of its threads are pinned to the
+/// core it was spawned from, because the kernel doesn't yet shoot down TLB entries across cores when memory is
+/// unmapped. Threads suit servicing interrupts alongside a main loop, rather than spreading work over cores.
+///
+/// Each thread's stack has an unmapped guard page below it, so overflowing the stack faults.
+pub fn spawn_thread(
+    task: Handle,
+    entry: extern "C" fn(usize) -> !,
+    arg: usize,
+    tls_base: *mut u8,
+) -> core::result::Result<usize, Error> {
+    // Safety: Kernel validates the provided addresses.
+    match unsafe {
+        super::invoke(Vector::TaskSpawnThread, [task.into_raw(), entry as usize, arg, tls_base.addr(), 0, 0])
+    }? {
+        Success::Value(thread_id) => Ok(thread_id),
+        _ => unreachable!(),
+    }
+}
 
-        core::arch::asm!(
-            "int 0x80",
-            in("rax") Vector::TaskExit as usize,
-            out("rdi") discriminant,
-            out("rsi") value,
-            options(nostack, nomem, preserves_flags)
-        );
+/// Blocks until the thread `thread_id` of `task` exits, returning its exit status. `task` must have `Rights::READ`.
+///
+/// Returns `Error::InvalidArgument` if `thread_id` is unknown, is the calling thread, or was torn down with its task.
+pub fn join_thread(task: Handle, thread_id: usize) -> core::result::Result<usize, Error> {
+    loop {
+        // Safety: Vector takes no pointer arguments.
+        match unsafe { super::invoke(Vector::TaskJoinThread, [task.into_raw(), thread_id, 0, 0, 0, 0]) }? {
+            Success::Value(exit_status) => return Ok(exit_status),
+            // Kernel blocked until a thread exited, so check again.
+            Success::Ok => {}
+            _ => unreachable!(),
+        }
+    }
+}
 
-        <Result as super::ResultConverter>::from_registers((discriminant, value))
+/// Blocks until `task` exits, returning its exit status (that of its main thread). `task` must have `Rights::READ`.
+///
+/// The exit status stays available for as long as a handle to the task remains, even once the task is torn down.
+pub fn wait(task: Handle) -> core::result::Result<usize, Error> {
+    loop {
+        // Safety: Vector takes no pointer arguments.
+        match unsafe { super::invoke(Vector::TaskWait, [task.into_raw(), 0, 0, 0, 0, 0]) }? {
+            Success::Value(exit_status) => return Ok(exit_status),
+            // Kernel blocked until the task exited, so check again.
+            Success::Ok => {}
+            _ => unreachable!(),
+        }
     }
 }
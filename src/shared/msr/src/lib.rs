@@ -103,8 +103,6 @@ impl IA32_APIC_BASE {
 
 pub struct IA32_EFER;
 impl IA32_EFER {
-    /// Leave the IA32_EFER.SCE bit unsupported, as we don't use `syscall`.
-
     /// Gets the IA32_EFER.LMA (long-mode active) bit.
     #[inline]
     pub fn get_lma() -> bool {
@@ -0,0 +1,36 @@
+use anyhow::{Context, Result};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+use xshell::{cmd, Shell};
+
+/// Disk image which is attached to the emulator as its root drive.
+pub static DISK_IMAGE: &str = "build/disk0.img";
+pub static DEFAULT_DISK_SIZE: &str = "256M";
+
+#[derive(clap::Parser)]
+#[group(skip)]
+pub struct Options {
+    /// Directory whose contents are copied into the root of the new filesystem.
+    #[arg(long)]
+    from: Option<PathBuf>,
+
+    /// Size of the disk image, with an optional `K`, `M`, `G` or `T` suffix.
+    #[arg(long, default_value = DEFAULT_DISK_SIZE)]
+    size: String,
+}
+
+/// Formats the root disk image with an ext2 filesystem, replacing its contents.
+pub fn format(sh: &Shell, options: Options) -> Result<()> {
+    create_image(sh, options.from.as_deref(), &options.size)
+}
+
+/// Creates the root disk image with an ext2 filesystem of `size`, populated from the directory `from` if provided.
+pub fn create_image(sh: &Shell, from: Option<&Path>, size: &str) -> Result<()> {
+    let populate_args = from.map(|dir| vec![OsString::from("-d"), dir.as_os_str().to_owned()]).unwrap_or_default();
+
+    cmd!(sh, "mkfs.ext2 -F -q -L linuiz {populate_args...} {DISK_IMAGE} {size}")
+        .run()
+        .with_context(|| "`mkfs.ext2` failed (is e2fsprogs installed?)")
+}
@@ -1,4 +1,5 @@
 mod build;
+mod disk;
 mod run;
 mod target;
 
@@ -25,6 +26,7 @@ PROTOCOL=limine
 RESOLUTION=800x600x16
 KERNEL_PATH=boot:///linuiz/kernel
 MODULE_PATH=boot:///linuiz/drivers
+MODULE_CMDLINE=/boot/drivers
 KASLR=yes
 "#;
 
@@ -53,6 +55,7 @@ enum Arguments {
 
     Build(build::Options),
     Run(run::Options),
+    Disk(disk::Options),
 }
 
 fn main() -> Result<()> {
@@ -79,8 +82,8 @@ fn main() -> Result<()> {
     create_path_if_not_exists(&sh, "build/root/EFI/BOOT/")?;
     create_path_if_not_exists(&sh, "build/root/linuiz/")?;
     // Ensure dev disk image exists.
-    if !sh.path_exists("build/disk0.img") {
-        cmd!(sh, "qemu-img create -f raw build/disk0.img 256M").run()?;
+    if !sh.path_exists(disk::DISK_IMAGE) {
+        disk::create_image(&sh, None, disk::DEFAULT_DISK_SIZE)?;
     }
 
     // Ensure a valid bootloader configuration exists.
@@ -144,6 +147,10 @@ fn main() -> Result<()> {
         Arguments::Run(run_options) => {
             run::run(&sh, run_options)?;
         }
+
+        Arguments::Disk(disk_options) => {
+            disk::format(&sh, disk_options)?;
+        }
     }
 
     Ok(())